@@ -0,0 +1,358 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Command aliases and default flags, expanded before the command line is parsed.
+//!
+//! Both are read from `cli.json` in the application directory:
+//!
+//! ```json
+//! {
+//!   "aliases": { "hist": "history --limit 20 --output table", "bn": "backup-now" },
+//!   "defaults": { "clone-history": "--to /mnt/archive" }
+//! }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+use storage_common::Config;
+
+use crate::output::{Output, OutputFormat};
+
+/// The aliases and default flags configured for the cli
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct AliasConfig {
+    aliases: BTreeMap<String, String>,
+    defaults: BTreeMap<String, String>,
+}
+
+impl AliasConfig {
+    /// Gets the path of the file the aliases are read from
+    pub(crate) fn path(config: &Config) -> PathBuf {
+        config.app_dir_path().join("cli.json")
+    }
+
+    /// Loads the aliases from [`AliasConfig::path`], which is allowed to not exist
+    pub(crate) fn load(path: &Path) -> miette::Result<Self> {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| miette::miette!("invalid cli config '{}' - {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).into_diagnostic(),
+        }
+    }
+
+    /// Same as [`AliasConfig::load`], but a file that cannot be read or parsed only prints a
+    /// warning and expands nothing, so a broken `cli.json` does not lock every command out
+    pub(crate) fn load_or_default(path: &Path) -> Self {
+        Self::load(path).unwrap_or_else(|e| {
+            eprintln!("warning: {e}, ignoring its aliases and default flags");
+            Self::default()
+        })
+    }
+
+    /// Gets the configured aliases, ordered by name
+    pub(crate) fn aliases(&self) -> &BTreeMap<String, String> {
+        &self.aliases
+    }
+
+    /// Gets the default flags configured for each command, ordered by command
+    pub(crate) fn defaults(&self) -> &BTreeMap<String, String> {
+        &self.defaults
+    }
+
+    /// Expands any alias in the command position of `args` (which includes the program name),
+    /// then inserts the default flags of the resulting command. Built-in commands always take
+    /// precedence over aliases of the same name.
+    ///
+    /// Global flags before the command are skipped, `global_flag` tells for the long name of a
+    /// flag whether it is global, and if so whether it takes a value.
+    pub(crate) fn expand(
+        &self,
+        mut args: Vec<OsString>,
+        is_builtin: impl Fn(&str) -> bool,
+        global_flag: impl Fn(&str) -> Option<bool>,
+    ) -> miette::Result<Vec<OsString>> {
+        let Some(position) = command_position(&args, global_flag) else {
+            return Ok(args);
+        };
+        let mut chain: Vec<String> = Vec::new();
+        loop {
+            let Some(name) = args.get(position).and_then(|arg| arg.to_str()) else {
+                return Ok(args);
+            };
+            if name.starts_with('-') {
+                return Ok(args);
+            }
+            if is_builtin(name) {
+                break;
+            }
+            let Some(expansion) = self.aliases.get(name) else {
+                break;
+            };
+            if chain.iter().any(|seen| seen == name) {
+                chain.push(name.to_string());
+                miette::bail!("alias cycle detected: {}", chain.join(" -> "));
+            }
+            chain.push(name.to_string());
+
+            let expanded = split_args(expansion)?;
+            if expanded.is_empty() {
+                miette::bail!("alias '{name}' expands to nothing");
+            }
+            args.splice(
+                position..=position,
+                expanded.into_iter().map(OsString::from),
+            );
+        }
+
+        let command = args[position].to_string_lossy().into_owned();
+        if let Some(defaults) = self.defaults.get(&command) {
+            let defaults = split_args(defaults)?;
+            let after = position + 1;
+            args.splice(after..after, defaults.into_iter().map(OsString::from));
+        }
+        Ok(args)
+    }
+}
+
+/// Finds the command in `args` after the global flags, see [`AliasConfig::expand`]. Returns
+/// `None` if there is a flag that is not global, e.g. `--help`, before any command.
+fn command_position(
+    args: &[OsString],
+    global_flag: impl Fn(&str) -> Option<bool>,
+) -> Option<usize> {
+    let mut position = 1;
+    while let Some(arg) = args.get(position).and_then(|arg| arg.to_str()) {
+        let Some(flag) = arg.strip_prefix("--").filter(|flag| !flag.is_empty()) else {
+            break;
+        };
+        let (flag, inline_value) = match flag.split_once('=') {
+            Some((flag, _)) => (flag, true),
+            None => (flag, false),
+        };
+        let takes_value = global_flag(flag)?;
+        position += if takes_value && !inline_value { 2 } else { 1 };
+    }
+    Some(position)
+}
+
+/// Splits `line` on whitespace, keeping single or double quoted sections together
+fn split_args(line: &str) -> miette::Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        miette::bail!("unterminated quote in '{line}'");
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// The output of `storage-cli alias list`
+#[derive(Debug, Serialize)]
+struct ListOutput {
+    /// The file the aliases are read from
+    file: PathBuf,
+    #[serde(flatten)]
+    aliases: AliasConfig,
+}
+
+impl Output for ListOutput {
+    fn table(&self) {
+        let ListOutput { file, aliases } = self;
+        if aliases.aliases().is_empty() && aliases.defaults().is_empty() {
+            println!("no aliases configured, add them to {}", file.display());
+            return;
+        }
+        for (name, expansion) in aliases.aliases() {
+            println!("{name:<16} = {expansion}");
+        }
+        for (command, defaults) in aliases.defaults() {
+            println!("{command:<16} + {defaults}");
+        }
+    }
+
+    fn plain(&self) {
+        for (name, expansion) in self.aliases.aliases() {
+            println!("=\t{name}\t{expansion}");
+        }
+        for (command, defaults) in self.aliases.defaults() {
+            println!("+\t{command}\t{defaults}");
+        }
+    }
+}
+
+/// Prints the configured aliases and default flags
+pub(crate) fn list(config: &Config, format: OutputFormat) -> miette::Result<()> {
+    let file = AliasConfig::path(config);
+    let aliases = AliasConfig::load(&file)?;
+    format.print(&ListOutput { file, aliases })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(aliases: &[(&str, &str)], defaults: &[(&str, &str)]) -> AliasConfig {
+        let map = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect()
+        };
+        AliasConfig {
+            aliases: map(aliases),
+            defaults: map(defaults),
+        }
+    }
+
+    fn expand(config: &AliasConfig, line: &str) -> miette::Result<Vec<String>> {
+        let args = std::iter::once("storage-cli")
+            .chain(line.split_whitespace())
+            .map(OsString::from)
+            .collect();
+        let expanded = config.expand(
+            args,
+            |name| ["stats", "clone-history"].contains(&name),
+            |flag| match flag {
+                "format" => Some(true),
+                "log-json" => Some(false),
+                _ => None,
+            },
+        )?;
+        Ok(expanded
+            .into_iter()
+            .skip(1)
+            .map(|arg| arg.into_string().unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn expands_aliases_and_defaults() {
+        let config = config(
+            &[
+                ("ch", "clone-history 'my file.txt'"),
+                ("c", "ch"),
+                ("stats", "nope"),
+            ],
+            &[("clone-history", "--to /archive")],
+        );
+        assert_eq!(
+            expand(&config, "c other.txt").unwrap(),
+            [
+                "clone-history",
+                "--to",
+                "/archive",
+                "my file.txt",
+                "other.txt"
+            ]
+        );
+        assert_eq!(expand(&config, "stats").unwrap(), ["stats"]);
+        assert_eq!(expand(&config, "--help").unwrap(), ["--help"]);
+        assert_eq!(expand(&config, "unknown").unwrap(), ["unknown"]);
+    }
+
+    #[test]
+    fn skips_global_flags() {
+        let config = config(
+            &[("ch", "clone-history")],
+            &[("clone-history", "--to /archive")],
+        );
+        assert_eq!(
+            expand(&config, "--format json --log-json ch a.txt").unwrap(),
+            [
+                "--format",
+                "json",
+                "--log-json",
+                "clone-history",
+                "--to",
+                "/archive",
+                "a.txt"
+            ]
+        );
+        assert_eq!(
+            expand(&config, "--format=json ch").unwrap(),
+            ["--format=json", "clone-history", "--to", "/archive"]
+        );
+        // the alias is not expanded past a flag that is not global
+        assert_eq!(
+            expand(&config, "--format json --help ch").unwrap(),
+            ["--format", "json", "--help", "ch"]
+        );
+    }
+
+    #[test]
+    fn serializes_list() {
+        let aliases = config(&[("bn", "backup-now")], &[("history", "--limit 5")]);
+        let output = ListOutput {
+            file: PathBuf::from("/app/cli.json"),
+            aliases,
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({
+                "file": "/app/cli.json",
+                "aliases": { "bn": "backup-now" },
+                "defaults": { "history": "--limit 5" },
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_invalid_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cli.json");
+        assert_eq!(AliasConfig::load_or_default(&path), AliasConfig::default());
+
+        std::fs::write(&path, r#"{ "aliases": { "bn": "backup-now" } }"#).unwrap();
+        assert_eq!(
+            AliasConfig::load_or_default(&path),
+            config(&[("bn", "backup-now")], &[])
+        );
+
+        std::fs::write(&path, r#"{ "aliases": { "bn": "#).unwrap();
+        assert!(AliasConfig::load(&path).is_err());
+        assert_eq!(AliasConfig::load_or_default(&path), AliasConfig::default());
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let config = config(&[("a", "b --flag"), ("b", "a")], &[]);
+        let err = expand(&config, "a").unwrap_err();
+        assert!(err.to_string().contains("a -> b -> a"), "{err}");
+        assert!(split_args("'unterminated").is_err());
+    }
+}
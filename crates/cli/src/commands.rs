@@ -0,0 +1,50 @@
+//! Implementations of the individual cli commands
+
+pub(crate) mod annotations;
+pub(crate) mod backup;
+pub(crate) mod completions;
+pub(crate) mod config;
+pub(crate) mod daemon;
+pub(crate) mod dictionary;
+pub(crate) mod diff;
+pub(crate) mod doctor;
+pub(crate) mod gc;
+pub(crate) mod history;
+pub(crate) mod migrate;
+pub(crate) mod rekey;
+pub(crate) mod restore;
+pub(crate) mod retention;
+pub(crate) mod schedule;
+pub(crate) mod snapshot;
+pub(crate) mod stats;
+#[cfg(feature = "tui")]
+pub(crate) mod tui;
+pub(crate) mod watch;
+
+use storage_common::{Config, ConfigProblem};
+use storage_store::FileVersion;
+
+/// Prints the problems [`Config::validate`] finds in `config` with what to do about them, and
+/// fails if any of them is an error
+pub(crate) fn check_config(config: &Config) -> miette::Result<()> {
+    let problems = config.validate();
+    print_problems(&problems);
+    let errors = problems.iter().filter(|problem| problem.is_error()).count();
+    if errors > 0 {
+        miette::bail!("the configuration has {errors} error(s), see `storage-cli doctor`");
+    }
+    Ok(())
+}
+
+/// Prints configuration problems to stderr
+pub(crate) fn print_problems(problems: &[ConfigProblem]) {
+    for problem in problems {
+        eprintln!("{problem}");
+        eprintln!("  help: {}", problem.help());
+    }
+}
+
+/// Converts a version given on the command line, starting at 1, into a [`FileVersion`]
+pub(crate) fn file_version(version: u32) -> miette::Result<FileVersion> {
+    FileVersion::try_from(version).map_err(|_| miette::miette!("backup versions start at 1"))
+}
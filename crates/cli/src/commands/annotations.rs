@@ -0,0 +1,181 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::Config;
+use storage_store::BackupManager;
+
+use crate::output::{Output, OutputFormat};
+
+/// Arguments of `storage-cli annotate`
+#[derive(Debug, Args)]
+pub(crate) struct AnnotateArgs {
+    /// The backed up file
+    path: PathBuf,
+    /// The version of the backup to annotate, starting at 1
+    version: u32,
+    /// Replace the note of the backup, an empty note removes it
+    #[arg(long)]
+    note: Option<String>,
+    /// Add a tag, can be repeated
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// Remove a tag, can be repeated
+    #[arg(long = "untag")]
+    untags: Vec<String>,
+    /// Pin the backup
+    #[arg(long, conflicts_with = "unpin")]
+    pin: bool,
+    /// Unpin the backup
+    #[arg(long)]
+    unpin: bool,
+}
+
+/// Subcommands of `storage-cli annotations`
+#[derive(Debug, Subcommand)]
+pub(crate) enum AnnotationsCommand {
+    /// Write every annotation into a sidecar JSON file, e.g. to move it to another store
+    Export {
+        /// The sidecar file to create
+        sidecar: PathBuf,
+    },
+    /// Merge the annotations of a sidecar file created by `export` into the store
+    Import {
+        /// The sidecar file to import
+        sidecar: PathBuf,
+    },
+}
+
+/// The output of `storage-cli annotate`
+#[derive(Debug, Serialize)]
+struct AnnotationOutput {
+    path: PathBuf,
+    version: u32,
+    /// Whether the backup has any annotation left
+    annotated: bool,
+    note: Option<String>,
+    tags: Vec<String>,
+    pinned: bool,
+}
+
+impl Output for AnnotationOutput {
+    fn table(&self) {
+        if !self.annotated {
+            println!(
+                "{} (version {}) has no annotations",
+                self.path.display(),
+                self.version
+            );
+            return;
+        }
+        println!("{} (version {})", self.path.display(), self.version);
+        if let Some(note) = &self.note {
+            println!("  note:   {note}");
+        }
+        println!("  tags:   {}", self.tags.join(", "));
+        println!("  pinned: {}", self.pinned);
+    }
+}
+
+/// The output of `storage-cli annotations export`
+#[derive(Debug, Serialize)]
+struct ExportOutput {
+    sidecar: PathBuf,
+    exported: usize,
+}
+
+impl Output for ExportOutput {
+    fn table(&self) {
+        println!(
+            "exported {} annotation(s) to {}",
+            self.exported,
+            self.sidecar.display()
+        );
+    }
+}
+
+/// The output of `storage-cli annotations import`
+#[derive(Debug, Serialize)]
+struct ImportOutput {
+    applied: usize,
+    unmatched: usize,
+}
+
+impl Output for ImportOutput {
+    fn table(&self) {
+        println!(
+            "applied {} annotation(s), {} without a matching backup",
+            self.applied, self.unmatched
+        );
+    }
+}
+
+/// Updates the note, tags and pin of a single backup and prints the result
+pub(crate) fn annotate(
+    config: &Config,
+    args: &AnnotateArgs,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let version = super::file_version(args.version)?;
+
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    manager
+        .annotate(&args.path, version, |annotation| {
+            if let Some(note) = &args.note {
+                annotation.set_note((!note.is_empty()).then(|| note.clone()));
+            }
+            for tag in &args.tags {
+                annotation.add_tag(tag.clone());
+            }
+            for tag in &args.untags {
+                annotation.remove_tag(tag);
+            }
+            if args.pin || args.unpin {
+                annotation.set_pinned(args.pin);
+            }
+        })
+        .into_diagnostic()?;
+
+    let annotation = manager.annotation(&args.path, version);
+    let annotated = annotation.is_some();
+    let annotation = annotation.unwrap_or_default();
+    format.print(&AnnotationOutput {
+        path: args.path.clone(),
+        version: version.get(),
+        annotated,
+        note: annotation.note().map(str::to_string),
+        tags: annotation.tags().map(str::to_string).collect(),
+        pinned: annotation.is_pinned(),
+    })
+}
+
+pub(crate) fn run(
+    config: &Config,
+    command: &AnnotationsCommand,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    match command {
+        AnnotationsCommand::Export { sidecar } => {
+            let exported = manager.export_annotations(sidecar).into_diagnostic()?;
+            format.print(&ExportOutput {
+                sidecar: sidecar.clone(),
+                exported,
+            })
+        }
+        AnnotationsCommand::Import { sidecar } => {
+            let report = manager.import_annotations(sidecar).into_diagnostic()?;
+            format.print(&ImportOutput {
+                applied: report.applied(),
+                unmatched: report.unmatched(),
+            })
+        }
+    }
+}
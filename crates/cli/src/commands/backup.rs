@@ -0,0 +1,588 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{Config, Error, MaybeConfig, Telemetry, Timestamp};
+use storage_store::{
+    BackupManager, DryRun, FileKind, FileMeta, FileVersion, ProgressReport, ProgressSink,
+    RetentionPolicy, StageProgress, StoreRegistry, SyncMode,
+};
+use xstd::display::{format_bytes, format_duration};
+
+use crate::output::{rfc3339, Output, OutputFormat};
+
+/// How often the progress line of `backup-now --progress` is redrawn
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The result of backing up a single file
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum BackedUpFile {
+    BackedUp {
+        path: PathBuf,
+        version: u32,
+        id: String,
+    },
+    Failed {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+impl BackedUpFile {
+    fn backed_up(path: PathBuf, meta: &FileMeta) -> Self {
+        Self::BackedUp {
+            path,
+            version: meta.version().get(),
+            id: meta.id().to_string(),
+        }
+    }
+
+    /// Creates a failed backup, recording the error in `telemetry`
+    fn failed(path: PathBuf, err: &Error, telemetry: &mut Telemetry) -> Self {
+        telemetry.record_error(err);
+        Self::Failed {
+            path,
+            error: err.to_string(),
+        }
+    }
+
+    fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed { .. })
+    }
+
+    /// Prints the result, a failure goes to stderr
+    fn print(&self, plain: bool) {
+        match self {
+            Self::BackedUp { path, version, id } if plain => {
+                println!("{}\t{version}\t{id}", path.display());
+            }
+            Self::BackedUp { path, version, id } => {
+                println!("backed up {} (version {version}, id {id})", path.display());
+            }
+            Self::Failed { path, error } => {
+                eprintln!("failed to back up {} - {error}", path.display());
+            }
+        }
+    }
+}
+
+/// The output of `storage-cli backup-now`
+#[derive(Debug, Serialize)]
+struct BackupOutput {
+    stores: Vec<StoreBackupOutput>,
+}
+
+/// The backups made in a single store by `storage-cli backup-now`
+#[derive(Debug, Serialize)]
+struct StoreBackupOutput {
+    /// The name of the store, `None` for the global store
+    store: Option<String>,
+    unchanged: Vec<PathBuf>,
+    /// The end of the pause or quiet hours the backups were skipped for
+    #[serde(serialize_with = "rfc3339")]
+    paused_until: Option<Timestamp>,
+    skipped: usize,
+    files: Vec<BackedUpFile>,
+    evicted: Vec<EvictedOutput>,
+    retention: Option<RemovedOutput>,
+}
+
+/// A version evicted to stay under the store size cap
+#[derive(Debug, Serialize)]
+struct EvictedOutput {
+    path: PathBuf,
+    version: u32,
+    size: u64,
+}
+
+/// The versions removed by a retention policy
+#[derive(Debug, Serialize)]
+struct RemovedOutput {
+    removed_versions: usize,
+    removed_bytes: u64,
+}
+
+impl Output for BackupOutput {
+    fn table(&self) {
+        for store in &self.stores {
+            if let Some(name) = &store.store {
+                println!("store {name}");
+            }
+            for path in &store.unchanged {
+                println!("unchanged {}", path.display());
+            }
+            if let Some(until) = store.paused_until {
+                println!(
+                    "paused until {until}, skipped {} backup(s), use --force to run anyway",
+                    store.skipped
+                );
+            }
+            for file in &store.files {
+                file.print(false);
+            }
+            for backup in &store.evicted {
+                println!(
+                    "evicted {} (version {}, {}) to stay under the store size cap",
+                    backup.path.display(),
+                    backup.version,
+                    format_bytes(backup.size)
+                );
+            }
+            if let Some(retention) = &store.retention {
+                println!(
+                    "retention removed {} version(s) / {}",
+                    retention.removed_versions,
+                    format_bytes(retention.removed_bytes)
+                );
+            }
+        }
+    }
+
+    fn plain(&self) {
+        for file in self.stores.iter().flat_map(|store| &store.files) {
+            file.print(true);
+        }
+    }
+}
+
+/// Backs up the given files (or every tracked file when `paths` is empty) once, without a
+/// watcher, each into the store it belongs to (see [`StoreRegistry`]). Unchanged files are
+/// skipped and the backups are deferred while paused, unless `force` is set. Afterwards the
+/// retention `policy` (or the one configured for the store) is applied, if there is one, and the
+/// versions evicted to stay under the store size cap are listed.
+///
+/// With `progress` the files are backed up one at a time, showing the progress of every stage
+/// with its throughput and ETA on stderr.
+pub(crate) fn backup_now(
+    config: &Config,
+    telemetry: &mut Telemetry,
+    paths: &[PathBuf],
+    force: bool,
+    progress: bool,
+    policy: Option<&RetentionPolicy>,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let registry = StoreRegistry::new(config).into_diagnostic()?;
+    let paths = if paths.is_empty() {
+        registry.tracked_files().into_diagnostic()?
+    } else {
+        paths.to_vec()
+    };
+
+    let (mut stores, mut queued) = (Vec::new(), 0);
+    for (store, paths) in registry.group(&paths) {
+        let policy = policy.or(store.retention());
+        let (output, store_queued) =
+            backup_store(store.manager(), telemetry, &paths, force, progress, policy)?;
+        stores.push(StoreBackupOutput {
+            store: store.name().map(str::to_string),
+            ..output
+        });
+        queued += store_queued;
+    }
+    let failed = stores
+        .iter()
+        .flat_map(|store| &store.files)
+        .filter(|file| file.is_failed())
+        .count();
+    format.print(&BackupOutput { stores })?;
+    if failed > 0 {
+        miette::bail!("{} of {} backup(s) failed", failed, queued);
+    }
+    Ok(())
+}
+
+/// Backs up `paths` into the store of `manager` like [`backup_now`], returning what was done and
+/// the number of queued backups
+fn backup_store(
+    manager: &BackupManager,
+    telemetry: &mut Telemetry,
+    paths: &[PathBuf],
+    force: bool,
+    progress: bool,
+    policy: Option<&RetentionPolicy>,
+) -> miette::Result<(StoreBackupOutput, usize)> {
+    let mut unchanged = Vec::new();
+    for path in paths {
+        // a missing file is reported by the backup itself
+        if force || manager.needs_backup(path).unwrap_or(true) {
+            manager.queue_backup(path);
+        } else {
+            unchanged.push(path.clone());
+        }
+    }
+
+    let queued = manager.pending().len();
+    let quiet = !force
+        && manager
+            .schedule()
+            .into_diagnostic()?
+            .is_quiet(Timestamp::now());
+    let results = if progress && !quiet {
+        let pending = manager.pending();
+        pending
+            .into_iter()
+            .map(|path| {
+                let result = manager.backup_with_progress(&path, &mut progress_bar());
+                (path, result)
+            })
+            .collect()
+    } else if force {
+        let pending = manager.pending();
+        manager.backup_all(&pending)
+    } else {
+        manager.run_pending().into_diagnostic()?
+    };
+    let paused_until = if results.is_empty() && queued > 0 {
+        manager
+            .schedule()
+            .into_diagnostic()?
+            .quiet_until(Timestamp::now())
+    } else {
+        None
+    };
+    let files = results
+        .into_iter()
+        .map(|(path, result)| match result {
+            Ok(meta) => BackedUpFile::backed_up(path, &meta),
+            Err(err) => BackedUpFile::failed(path, &err, telemetry),
+        })
+        .collect();
+
+    let evicted = manager
+        .take_evictions()
+        .evicted()
+        .iter()
+        .map(|backup| EvictedOutput {
+            path: backup.path().to_path_buf(),
+            version: backup.version().get(),
+            size: backup.size(),
+        })
+        .collect();
+
+    let retention = match policy {
+        Some(policy) => {
+            let report = manager
+                .apply_retention(policy, DryRun::Off)
+                .into_diagnostic()?;
+            Some(RemovedOutput {
+                removed_versions: report.removed_versions(),
+                removed_bytes: report.removed_bytes(),
+            })
+        }
+        None => None,
+    };
+    let output = StoreBackupOutput {
+        store: None,
+        unchanged,
+        paused_until,
+        skipped: if paused_until.is_some() { queued } else { 0 },
+        files,
+        evicted,
+        retention,
+    };
+    Ok((output, queued))
+}
+
+/// The output of `storage-cli sync`
+#[derive(Debug, Serialize)]
+struct SyncOutput {
+    files: Vec<BackedUpFile>,
+    missing: Vec<PathBuf>,
+    skipped: Vec<SkippedFile>,
+    unchanged: usize,
+}
+
+/// A socket, named pipe or device that `storage-cli sync` did not back up
+#[derive(Debug, Serialize)]
+struct SkippedFile {
+    path: PathBuf,
+    kind: FileKind,
+}
+
+impl Output for SyncOutput {
+    fn table(&self) {
+        let (failed, backed_up): (Vec<_>, Vec<_>) =
+            self.files.iter().partition(|file| file.is_failed());
+        for file in &backed_up {
+            file.print(false);
+        }
+        for path in &self.missing {
+            println!("missing {}", path.display());
+        }
+        for file in &self.skipped {
+            println!("skipped {}, it is a {}", file.path.display(), file.kind);
+        }
+        for file in &failed {
+            file.print(false);
+        }
+        println!(
+            "{} backed up, {} unchanged",
+            backed_up.len(),
+            self.unchanged
+        );
+    }
+
+    fn plain(&self) {
+        for file in &self.files {
+            file.print(true);
+        }
+    }
+}
+
+/// Backs up the given files (or every tracked file when `paths` is empty) that changed since their
+/// latest backup, comparing their contents as well with `thorough`. Unlike `backup_now` this
+/// ignores quiet hours, it is meant to catch up with changes made while the daemon was stopped.
+pub(crate) fn sync(
+    config: &Config,
+    telemetry: &mut Telemetry,
+    paths: &[PathBuf],
+    thorough: bool,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let paths = if paths.is_empty() {
+        config
+            .read_tracked_files()
+            .into_diagnostic()?
+            .into_iter()
+            .filter(|path| !path.trim().is_empty())
+            .map(PathBuf::from)
+            .collect()
+    } else {
+        paths.to_vec()
+    };
+    let mode = if thorough {
+        SyncMode::Thorough
+    } else {
+        SyncMode::Quick
+    };
+
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let report = manager.sync(&paths, mode);
+    let mut files = report
+        .backed_up()
+        .iter()
+        .map(|(path, meta)| BackedUpFile::backed_up(path.clone(), meta))
+        .collect::<Vec<_>>();
+    for (path, err) in report.failed() {
+        files.push(BackedUpFile::failed(path.clone(), err, telemetry));
+    }
+    format.print(&SyncOutput {
+        files,
+        missing: report.missing().to_vec(),
+        skipped: report
+            .skipped()
+            .iter()
+            .map(|(path, kind)| SkippedFile {
+                path: path.clone(),
+                kind: *kind,
+            })
+            .collect(),
+        unchanged: report.unchanged().len(),
+    })?;
+
+    if !report.failed().is_empty() {
+        miette::bail!("{} file(s) could not be synced", report.failed().len());
+    }
+    Ok(())
+}
+
+/// The output of `storage-cli clone-history`
+#[derive(Debug, Serialize)]
+struct CloneOutput {
+    copied: usize,
+    copied_bytes: u64,
+    skipped: usize,
+    renumbered: Vec<RenumberedOutput>,
+}
+
+impl Output for CloneOutput {
+    fn table(&self) {
+        for renumbered in &self.renumbered {
+            println!(
+                "copied {} version {} as version {}, the version was already taken",
+                renumbered.path.display(),
+                renumbered.from,
+                renumbered.to
+            );
+        }
+        println!(
+            "copied {} object(s) ({}), {} already present",
+            self.copied,
+            format_bytes(self.copied_bytes),
+            self.skipped
+        );
+    }
+}
+
+/// Copies the version history of `paths` into the store at `to`, transferring only missing objects
+pub(crate) fn clone_history(
+    config: &Config,
+    paths: &[PathBuf],
+    to: &Path,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let source = BackupManager::new(config.clone()).into_diagnostic()?;
+    let to = to
+        .to_str()
+        .ok_or_else(|| miette::miette!("destination '{}' is not valid utf-8", to.display()))?;
+    std::fs::create_dir_all(to).into_diagnostic()?;
+    let destination =
+        BackupManager::new(config.extend_with(&MaybeConfig::default().with_store_dir(to)))
+            .into_diagnostic()?;
+
+    let report = source
+        .clone_history(paths, &destination)
+        .into_diagnostic()?;
+    format.print(&CloneOutput {
+        copied: report.copied(),
+        copied_bytes: report.copied_bytes(),
+        skipped: report.skipped(),
+        renumbered: RenumberedOutput::list(report.renumbered()),
+    })
+}
+
+/// The output of `storage-cli export`
+#[derive(Debug, Serialize)]
+struct ExportOutput {
+    archive: PathBuf,
+    exported: usize,
+}
+
+impl Output for ExportOutput {
+    fn table(&self) {
+        println!(
+            "exported {} backup(s) to {}",
+            self.exported,
+            self.archive.display()
+        );
+    }
+}
+
+/// Exports the whole store into a portable archive at `archive`
+pub(crate) fn export_archive(
+    config: &Config,
+    archive: &Path,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let exported = manager.export_archive(archive).into_diagnostic()?;
+    format.print(&ExportOutput {
+        archive: archive.to_path_buf(),
+        exported,
+    })
+}
+
+/// The output of `storage-cli import`
+#[derive(Debug, Serialize)]
+struct ImportOutput {
+    imported: usize,
+    imported_bytes: u64,
+    skipped: usize,
+    renumbered: Vec<RenumberedOutput>,
+}
+
+/// A backup stored under another version, because its version was already taken
+#[derive(Debug, Serialize)]
+struct RenumberedOutput {
+    path: PathBuf,
+    from: u32,
+    to: u32,
+}
+
+impl RenumberedOutput {
+    fn list(renumbered: &[(PathBuf, FileVersion, FileVersion)]) -> Vec<Self> {
+        renumbered
+            .iter()
+            .map(|(path, from, to)| Self {
+                path: path.clone(),
+                from: from.get(),
+                to: to.get(),
+            })
+            .collect()
+    }
+}
+
+impl Output for ImportOutput {
+    fn table(&self) {
+        for renumbered in &self.renumbered {
+            println!(
+                "stored {} version {} as version {}, the version was already taken",
+                renumbered.path.display(),
+                renumbered.from,
+                renumbered.to
+            );
+        }
+        println!(
+            "imported {} backup(s) ({}), {} already present",
+            self.imported,
+            format_bytes(self.imported_bytes),
+            self.skipped
+        );
+    }
+}
+
+/// Merges a portable archive created by `export` into the store
+pub(crate) fn import_archive(
+    config: &Config,
+    archive: &Path,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let report = manager.import_archive(archive).into_diagnostic()?;
+    format.print(&ImportOutput {
+        imported: report.imported(),
+        imported_bytes: report.imported_bytes(),
+        skipped: report.skipped(),
+        renumbered: RenumberedOutput::list(report.renumbered()),
+    })
+}
+
+/// Creates a [`ProgressSink`] drawing a single progress line per stage on stderr, e.g.
+/// `compress: 1.2 GiB / 20.0 GiB (45.1 MiB/s, ETA 7m 6s)`
+fn progress_bar() -> impl ProgressSink {
+    let mut last_draw: Option<Instant> = None;
+    StageProgress::new(move |report: &ProgressReport<'_>| {
+        let done = report.processed >= report.total;
+        if !done && last_draw.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        last_draw = Some(Instant::now());
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let rate = match (report.throughput(), report.eta().filter(|_| !done)) {
+            (Some(throughput), Some(eta)) => format!(
+                " ({}/s, ETA {})",
+                format_bytes(throughput as u64),
+                format_duration(eta)
+            ),
+            (Some(throughput), None) => format!(" ({}/s)", format_bytes(throughput as u64)),
+            (None, _) => String::new(),
+        };
+        let line = format!(
+            "{}: {} / {}{rate}",
+            report.stage,
+            format_bytes(report.processed),
+            format_bytes(report.total)
+        );
+        let mut stderr = std::io::stderr().lock();
+        // progress output is best-effort, a closed stderr must not fail the backup
+        let _ = write!(stderr, "\r{line:<72}");
+        if done {
+            let _ = writeln!(stderr);
+            last_draw = None;
+        }
+        let _ = stderr.flush();
+    })
+}
@@ -0,0 +1,55 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::Write;
+
+use clap_complete::Shell;
+use miette::IntoDiagnostic;
+
+/// Prints the completion script of `command` for `shell` to stdout
+pub(crate) fn completions(mut command: clap::Command, shell: Shell) -> miette::Result<()> {
+    let name = command.get_name().to_string();
+    // `generate` panics on write errors, so the script is written to stdout separately
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut script);
+    std::io::stdout().write_all(&script).into_diagnostic()
+}
+
+/// Prints the man page of `command` (in `roff`) to stdout
+pub(crate) fn man(command: clap::Command) -> miette::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    clap_mangen::Man::new(command)
+        .render(&mut stdout)
+        .into_diagnostic()?;
+    stdout.flush().into_diagnostic()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::Cli;
+
+    #[test]
+    fn generates_completions_and_man_page() {
+        let mut command = Cli::command();
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut command, "storage-cli", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("backup-now"), "{shell} completions");
+        }
+
+        let mut page = Vec::new();
+        clap_mangen::Man::new(Cli::command())
+            .render(&mut page)
+            .unwrap();
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.starts_with(".ie"));
+        assert!(page.contains("backup\\-now"));
+    }
+}
@@ -0,0 +1,367 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! `storage-cli config`, showing the configuration in effect and changing the config file. The
+//! file is only saved once the [`ConfigBuilder`] accepts it and [`Config::validate`] finds no
+//! error in it, and what changed is printed afterwards.
+
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{Config, ConfigBuilder, ConfigChange, ConfigSource, ENV_PREFIX};
+
+use crate::{
+    commands::print_problems,
+    output::{Output, OutputFormat},
+};
+
+/// Subcommands of `storage-cli config`
+#[derive(Debug, Subcommand)]
+pub(crate) enum ConfigCommand {
+    /// Print the configuration in effect
+    Show {
+        /// Also print where each value came from: default, file, env (`STORAGE_*`) or cli
+        #[arg(long)]
+        origin: bool,
+    },
+    /// Print the value in effect for a single key
+    Get {
+        /// The key, one of those listed by `config show`
+        key: String,
+    },
+    /// Set a key in the config file, e.g. `config set delay 500`
+    Set {
+        /// The key, one of those listed by `config show`
+        key: String,
+        /// The new value, `null` removes the key from the file
+        value: String,
+    },
+    /// Open the config file in `$VISUAL` or `$EDITOR`, it is only saved if it is valid
+    Edit,
+}
+
+/// The output of `storage-cli config show`
+#[derive(Debug, Serialize)]
+struct ShowOutput {
+    file: Option<PathBuf>,
+    file_exists: bool,
+    entries: Vec<Entry>,
+    /// Whether the table prints the origin of each value, JSON always includes it
+    #[serde(skip)]
+    origin: bool,
+}
+
+/// A single config value and the layer it came from
+#[derive(Debug, Serialize)]
+struct Entry {
+    key: &'static str,
+    value: String,
+    source: String,
+}
+
+impl Output for Entry {
+    fn table(&self) {
+        println!("{}", self.value);
+    }
+}
+
+/// The output of `storage-cli config set` and `config edit`
+#[derive(Debug, Serialize)]
+struct ChangesOutput {
+    file: PathBuf,
+    changes: Vec<Change>,
+}
+
+/// A value that differs after saving the config file
+#[derive(Debug, Serialize)]
+struct Change {
+    key: &'static str,
+    before: String,
+    after: String,
+}
+
+impl From<ConfigChange> for Change {
+    fn from(change: ConfigChange) -> Self {
+        Self {
+            key: change.key(),
+            before: change.before().to_string(),
+            after: change.after().to_string(),
+        }
+    }
+}
+
+impl Output for ChangesOutput {
+    fn table(&self) {
+        if self.changes.is_empty() {
+            println!("no value changed in {}", self.file.display());
+            return;
+        }
+        println!("updated {}", self.file.display());
+        for Change { key, before, after } in &self.changes {
+            println!("  {key:<24} {before} -> {after}");
+        }
+    }
+
+    fn plain(&self) {
+        for Change { key, before, after } in &self.changes {
+            println!("{key}\t{before}\t{after}");
+        }
+    }
+}
+
+impl Output for ShowOutput {
+    fn table(&self) {
+        if let Some(file) = &self.file {
+            let status = if self.file_exists { "" } else { " (missing)" };
+            println!("# config file: {}{status}", file.display());
+            println!("# environment: {ENV_PREFIX}<KEY>");
+        }
+        for Entry { key, value, source } in &self.entries {
+            if self.origin {
+                println!("{key:<20} = {value:<40} ({source})");
+            } else {
+                println!("{key:<20} = {value}");
+            }
+        }
+    }
+
+    fn plain(&self) {
+        for Entry { key, value, source } in &self.entries {
+            if self.origin {
+                println!("{key}\t{value}\t{source}");
+            } else {
+                println!("{key}\t{value}");
+            }
+        }
+    }
+}
+
+pub(crate) fn run(
+    builder: &ConfigBuilder,
+    command: &ConfigCommand,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    match command {
+        ConfigCommand::Show { origin } => show(builder, *origin, format),
+        ConfigCommand::Get { key } => get(builder, key, format),
+        ConfigCommand::Set { key, value } => set(builder, key, value, format),
+        ConfigCommand::Edit => edit(builder, format),
+    }
+}
+
+/// Prints every config value, optionally with the layer it came from
+fn show(builder: &ConfigBuilder, origin: bool, format: OutputFormat) -> miette::Result<()> {
+    format.print(&ShowOutput {
+        file: builder.file().map(Path::to_path_buf),
+        file_exists: builder.file().is_some_and(Path::exists),
+        entries: builder
+            .entries()
+            .into_iter()
+            .map(|(key, value, source)| Entry {
+                key,
+                value,
+                source: source.to_string(),
+            })
+            .collect(),
+        origin,
+    })
+}
+
+/// Prints the value in effect for `key`
+fn get(builder: &ConfigBuilder, key: &str, format: OutputFormat) -> miette::Result<()> {
+    let Some((key, value, source)) = builder
+        .entries()
+        .into_iter()
+        .find(|(known, ..)| *known == key)
+    else {
+        miette::bail!(
+            "unknown config key '{}', see `storage-cli config show`",
+            key
+        );
+    };
+    format.print(&Entry {
+        key,
+        value,
+        source: source.to_string(),
+    })
+}
+
+/// Sets `key` to `value` in the config file, keeping its other keys
+fn set(
+    builder: &ConfigBuilder,
+    key: &str,
+    value: &str,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let file = config_file(builder)?;
+    let old = read_file(file)?;
+    let mut values = if old.trim().is_empty() {
+        serde_json::Map::new()
+    } else {
+        serde_json::from_str(&old)
+            .map_err(|e| miette::miette!("invalid config file '{}' - {}", file.display(), e))?
+    };
+    // numbers, booleans, null and the object of the stores keep their JSON type
+    let value = match serde_json::from_str::<serde_json::Value>(value) {
+        Ok(json) if !json.is_string() && !json.is_array() => json,
+        _ => serde_json::Value::String(value.to_string()),
+    };
+    values.insert(key.to_string(), value);
+    let mut new = serde_json::to_string_pretty(&values).into_diagnostic()?;
+    new.push('\n');
+    let output = save(file, &old, &new)?;
+    let source = builder.origin(key);
+    if source > ConfigSource::File {
+        eprintln!("note: '{key}' is also set by the {source}, which overrides the config file");
+    }
+    format.print(&output)
+}
+
+/// Opens a copy of the config file in the editor of the user, and saves it over the config
+/// file once the editor exits
+fn edit(builder: &ConfigBuilder, format: OutputFormat) -> miette::Result<()> {
+    let file = config_file(builder)?;
+    let old = read_file(file)?;
+    let draft = std::env::temp_dir().join(format!("storage-config-{}.json", std::process::id()));
+    let initial = if old.trim().is_empty() {
+        "{\n}\n"
+    } else {
+        &old
+    };
+    std::fs::write(&draft, initial).into_diagnostic()?;
+
+    let editor = editor();
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&draft)
+        .status()
+        .map_err(|e| miette::miette!("unable to run the editor '{}' - {}", editor, e))?;
+    if !status.success() {
+        let _ = std::fs::remove_file(&draft);
+        miette::bail!(
+            "the editor exited with {}, the config file was not changed",
+            status
+        );
+    }
+
+    let new = std::fs::read_to_string(&draft).into_diagnostic()?;
+    let output = save(file, &old, &new).map_err(|e| {
+        e.wrap_err(format!(
+            "the config file was not changed, the edits are kept in {}",
+            draft.display()
+        ))
+    })?;
+    let _ = std::fs::remove_file(&draft);
+    format.print(&output)
+}
+
+/// The editor used when neither `$VISUAL` nor `$EDITOR` is set
+const DEFAULT_EDITOR: &str = if cfg!(windows) { "notepad" } else { "vi" };
+
+/// Gets the editor command of the user, which may include arguments, e.g. `code --wait`
+fn editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string())
+}
+
+/// Gets the path of the config file of `builder`
+fn config_file(builder: &ConfigBuilder) -> miette::Result<&Path> {
+    builder
+        .file()
+        .ok_or_else(|| miette::miette!("there is no config file to change"))
+}
+
+/// Reads the config file at `file`, which is empty if it does not exist yet
+fn read_file(file: &Path) -> miette::Result<String> {
+    match std::fs::read_to_string(file) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).into_diagnostic(),
+    }
+}
+
+/// Checks the `new` contents of the config `file` and writes them, returning the values that
+/// differ from those of the `old` contents. Nothing is written if the contents are invalid.
+fn save(file: &Path, old: &str, new: &str) -> miette::Result<ChangesOutput> {
+    let parse = |contents: &str| -> miette::Result<Config> {
+        if contents.trim().is_empty() {
+            return Ok(Config::new());
+        }
+        let builder = ConfigBuilder::new()
+            .with_file_contents(file, contents)
+            .into_diagnostic()?;
+        Ok(builder.build())
+    };
+    let before = parse(old).unwrap_or_default();
+    let after = parse(new)?;
+    let problems = after.validate();
+    print_problems(&problems);
+    let errors = problems.iter().filter(|problem| problem.is_error()).count();
+    if errors > 0 {
+        miette::bail!("the new configuration has {} error(s)", errors);
+    }
+
+    if new != old {
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent).into_diagnostic()?;
+        }
+        std::fs::write(file, new).into_diagnostic()?;
+    }
+    Ok(ChangesOutput {
+        file: file.to_path_buf(),
+        changes: before
+            .changes_to(&after)
+            .into_iter()
+            .map(Change::from)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.json");
+        std::fs::write(&file, r#"{ "store_dir": "/store", "log_json": true }"#).unwrap();
+        let builder = || ConfigBuilder::new().with_file(&file).unwrap();
+
+        set(&builder(), "delay", "500", OutputFormat::Json).unwrap();
+        set(&builder(), "quiet_hours", "22:00-06:30", OutputFormat::Json).unwrap();
+        let values: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(values["delay"], 500);
+        assert_eq!(values["quiet_hours"], "22:00-06:30");
+        assert_eq!(values["log_json"], true);
+        let config = builder().build();
+        assert_eq!(config.delay(), 500);
+        assert_eq!(config.store_dir(), "/store");
+
+        let output = save(&file, r#"{ "delay": 500 }"#, r#"{ "delay": 250 }"#).unwrap();
+        assert_eq!(output.changes.len(), 1);
+        assert_eq!(output.changes[0].before, "500");
+
+        // invalid values never reach the file
+        let contents = std::fs::read_to_string(&file).unwrap();
+        assert!(set(&builder(), "delay", "soon", OutputFormat::Json).is_err());
+        assert!(set(&builder(), "dealy", "500", OutputFormat::Json).is_err());
+        assert!(save(&file, &contents, "{ not json").is_err());
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            r#"{ "delay": 250 }"#
+        );
+        assert!(get(&builder(), "dealy", OutputFormat::Json).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use miette::IntoDiagnostic;
+use storage_common::{Config, ConfigBuilder, MaybeConfig, Throttle};
+use storage_daemon::{
+    ControlSocket, Daemon, OnPanic, PidFile, WatchHealth, DEFAULT_REPLACE_TIMEOUT,
+};
+use storage_store::{MemoryBackend, StorageBackend};
+
+use crate::logging;
+
+/// Runs the backup daemon in the foreground until `SIGINT` or `SIGTERM` (`Ctrl-C` on Windows)
+/// arrives. A second signal exits immediately, without waiting for running backups. A panic of
+/// any thread is logged and stops the daemon like a signal would, or aborts the process if
+/// `abort_on_panic` is set.
+///
+/// Only one daemon runs per app dir, with `replace` a running one is stopped first.
+///
+/// The config file of `builder` is watched, edits to it are applied without a restart as far as
+/// they can be. The environment and the command line keep overriding it, `store`, `throttle` and
+/// `max_store_bytes` included.
+///
+/// An `ephemeral` daemon keeps its backups in memory, they are gone once it stops. Only the
+/// index, the lock and the format version of its store are written, to a temporary store
+/// directory in the app dir that is removed again.
+pub(crate) fn daemon(
+    builder: &ConfigBuilder,
+    store: Option<&str>,
+    throttle: Option<Throttle>,
+    max_store_bytes: Option<u64>,
+    abort_on_panic: bool,
+    replace: bool,
+    ephemeral: bool,
+) -> miette::Result<()> {
+    let overrides = Overrides {
+        store: store.map(str::to_string),
+        throttle,
+        max_store_bytes,
+        store_dir: ephemeral.then(|| {
+            let name = format!("ephemeral-{}", std::process::id());
+            builder.config().app_dir_path().join(name)
+        }),
+    };
+    let config = overrides.apply(builder).into_diagnostic()?;
+    config.init_app_structure().into_diagnostic()?;
+    let _pid_file = if replace {
+        PidFile::replace(&config, DEFAULT_REPLACE_TIMEOUT)
+    } else {
+        PidFile::acquire(&config)
+    }
+    .into_diagnostic()?;
+    // `storage-cli doctor` tells a hung daemon from a responsive one by pinging it, and asks it
+    // for the health of its watches
+    let control_socket = ControlSocket::bind(&config)
+        .inspect_err(|e| tracing::warn!(error = %e, "unable to open the control socket"))
+        .ok();
+    let health = control_socket.as_ref().map(ControlSocket::watch_health);
+    let Some(store_dir) = overrides.store_dir.clone() else {
+        let daemon = Daemon::new(config).into_diagnostic()?;
+        return run(daemon, builder, overrides, health, abort_on_panic);
+    };
+    let result = Daemon::with_backend(config, MemoryBackend::new())
+        .into_diagnostic()
+        .and_then(|daemon| {
+            eprintln!("ephemeral mode, the backups are discarded once the daemon stops");
+            run(daemon, builder, overrides, health, abort_on_panic)
+        });
+    if let Err(e) = std::fs::remove_dir_all(&store_dir) {
+        tracing::warn!(dir = %store_dir.display(), error = %e, "unable to remove the ephemeral store");
+    }
+    result
+}
+
+/// Runs `daemon` until it is shut down, watching the config file of `builder` and publishing
+/// the health of its watches to `health`
+fn run<B: StorageBackend>(
+    mut daemon: Daemon<B>,
+    builder: &ConfigBuilder,
+    overrides: Overrides,
+    health: Option<&WatchHealth>,
+    abort_on_panic: bool,
+) -> miette::Result<()> {
+    if let Some(health) = health {
+        daemon.set_watch_health(health.clone());
+    }
+    if let Some(file) = builder.file() {
+        let builder = builder.clone();
+        daemon.set_config_file(file, move || overrides.apply(&builder.reload()?));
+    }
+    daemon.set_log_level_hook(|level| {
+        if let Err(e) = logging::set_level(level) {
+            tracing::warn!(error = %e, "unable to change the log level");
+        }
+    });
+    daemon.install_panic_hook(if abort_on_panic {
+        OnPanic::Abort
+    } else {
+        OnPanic::Shutdown
+    });
+    daemon.shutdown().on_signals().into_diagnostic()?;
+    eprintln!("watching for changes, press Ctrl-C to stop");
+    daemon.run().into_diagnostic()?;
+
+    let deferred = daemon.manager().pending().len();
+    if deferred > 0 {
+        println!("stopped, {deferred} queued backup(s) were not run");
+    } else {
+        println!("stopped");
+    }
+    Ok(())
+}
+
+/// What the command line of the daemon overrides in the config file, on every reload as well
+struct Overrides {
+    /// The named store to back up into
+    store: Option<String>,
+    throttle: Option<Throttle>,
+    max_store_bytes: Option<u64>,
+    /// The temporary store directory of an ephemeral daemon
+    store_dir: Option<PathBuf>,
+}
+
+impl Overrides {
+    /// Gets the config the daemon runs with out of `builder`
+    fn apply(&self, builder: &ConfigBuilder) -> storage_common::Result<Config> {
+        let config = match &self.store {
+            Some(name) => builder.config().for_store(name)?,
+            None => builder.config().clone(),
+        };
+        let mut overrides = MaybeConfig::default();
+        if let Some(throttle) = self.throttle {
+            overrides = overrides.with_throttle(throttle);
+        }
+        if let Some(max_store_bytes) = self.max_store_bytes {
+            overrides = overrides.with_max_store_bytes(max_store_bytes);
+        }
+        if let Some(dir) = &self.store_dir {
+            overrides = overrides.with_store_dir(dir.to_string_lossy());
+        }
+        Ok(config.extend_with(&overrides))
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use clap::Args;
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::Config;
+use storage_store::BackupManager;
+use xstd::{cast::CastFrom, display::format_bytes};
+
+use crate::output::{Output, OutputFormat};
+
+/// Arguments of `storage-cli train-dictionary`
+#[derive(Debug, Args)]
+pub(crate) struct TrainArgs {
+    /// The largest size of the dictionary in bytes
+    #[arg(long, default_value_t = 64 * 1024)]
+    max_size: usize,
+}
+
+/// The output of `storage-cli train-dictionary`
+#[derive(Debug, Serialize)]
+struct TrainDictionaryOutput {
+    id: String,
+    size: u64,
+    samples: usize,
+    /// Files smaller than this are compressed with the dictionary
+    below_bytes: u64,
+}
+
+impl Output for TrainDictionaryOutput {
+    fn table(&self) {
+        println!(
+            "trained dictionary {} ({}) on {} backup(s), used for files below {}",
+            self.id,
+            format_bytes(self.size),
+            self.samples,
+            format_bytes(self.below_bytes)
+        );
+    }
+}
+
+pub(crate) fn train(config: &Config, args: &TrainArgs, format: OutputFormat) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let (dictionary, samples) = manager.train_dictionary(args.max_size).into_diagnostic()?;
+    format.print(&TrainDictionaryOutput {
+        id: format!("{:016x}", dictionary.id()),
+        size: u64::cast_from(dictionary.len()),
+        samples,
+        below_bytes: config.compression().dictionary_bytes(),
+    })
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::Config;
+use storage_store::{BackupManager, Changes};
+use xstd::display::format_bytes;
+
+use super::file_version;
+use crate::output::{Output, OutputFormat};
+
+/// The output of `storage-cli diff`
+#[derive(Debug, Serialize)]
+struct DiffOutput {
+    path: PathBuf,
+    from: u32,
+    to: u32,
+    identical: bool,
+    changes: ChangesOutput,
+}
+
+/// What changed between the versions, see [`Changes`]
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ChangesOutput {
+    Text {
+        unified: String,
+        inserted: usize,
+        deleted: usize,
+    },
+    Binary {
+        from_size: u64,
+        to_size: u64,
+        changed_bytes: u64,
+        first_change: Option<u64>,
+    },
+}
+
+impl DiffOutput {
+    fn versions(&self) -> String {
+        format!("versions {} and {}", self.from, self.to)
+    }
+}
+
+impl Output for DiffOutput {
+    fn table(&self) {
+        let versions = self.versions();
+        if self.identical {
+            println!("{versions} of {} are identical", self.path.display());
+            return;
+        }
+        match &self.changes {
+            ChangesOutput::Text {
+                unified,
+                inserted,
+                deleted,
+            } => {
+                print!("{unified}");
+                // the summary goes to stderr, so the diff itself can be piped into `patch`
+                eprintln!("{inserted} line(s) added, {deleted} removed between {versions}");
+            }
+            ChangesOutput::Binary {
+                from_size,
+                to_size,
+                changed_bytes,
+                first_change,
+            } => {
+                println!(
+                    "binary file {} differs between {versions}",
+                    self.path.display()
+                );
+                println!(
+                    "size: {} -> {}",
+                    format_bytes(*from_size),
+                    format_bytes(*to_size)
+                );
+                println!("changed: {changed_bytes} byte(s)");
+                if let Some(offset) = first_change {
+                    println!("first change at offset {offset:#x}");
+                }
+            }
+        }
+    }
+
+    fn plain(&self) {
+        match &self.changes {
+            // nothing but the diff, ready for `patch`
+            ChangesOutput::Text { unified, .. } => print!("{unified}"),
+            ChangesOutput::Binary { .. } => self.table(),
+        }
+    }
+}
+
+/// Prints what changed between two versions of `path`: a unified diff on stdout for text files,
+/// a summary of the changed bytes for binary files. `to` defaults to the latest version and
+/// `from` to the version right before `to`.
+pub(crate) fn diff(
+    config: &Config,
+    path: &Path,
+    from: Option<u32>,
+    to: Option<u32>,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let from = from.map(file_version).transpose()?;
+    let to = to.map(file_version).transpose()?;
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let diff = manager.diff(path, from, to).into_diagnostic()?;
+
+    let changes = match diff.changes() {
+        Changes::Text {
+            unified,
+            inserted,
+            deleted,
+        } => ChangesOutput::Text {
+            unified: unified.clone(),
+            inserted: *inserted,
+            deleted: *deleted,
+        },
+        Changes::Binary {
+            from_size,
+            to_size,
+            changed_bytes,
+            first_change,
+        } => ChangesOutput::Binary {
+            from_size: *from_size,
+            to_size: *to_size,
+            changed_bytes: *changed_bytes,
+            first_change: *first_change,
+        },
+    };
+    format.print(&DiffOutput {
+        path: path.to_path_buf(),
+        from: diff.from().get(),
+        to: diff.to().get(),
+        identical: diff.is_identical(),
+        changes,
+    })
+}
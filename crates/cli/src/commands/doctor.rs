@@ -0,0 +1,761 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Args, Subcommand};
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{Config, ConfigProblem, Schedule, Severity, Telemetry, Timestamp};
+use storage_daemon::{ControlSocket, PidFile};
+use storage_mon::{WatchError, WatchLimit};
+use storage_store::BackupManager;
+use xstd::{
+    cast::CastFrom,
+    display::{format_bytes, format_duration},
+};
+
+use crate::output::{rfc3339, Output, OutputFormat};
+
+/// Below this much free space in the store dir `storage-cli doctor` warns, 1 GiB
+const LOW_FREE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// A clock before 2023-01-01 has not been set
+const EARLIEST_PLAUSIBLE_SECS: u64 = 1_672_531_200;
+
+/// How far the clock may be behind the newest backup, e.g. after an NTP adjustment
+const CLOCK_TOLERANCE: Duration = Duration::from_mins(5);
+
+/// How long the daemon has to answer a ping
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Subcommands of `storage-cli telemetry`
+#[derive(Debug, Subcommand)]
+pub(crate) enum TelemetryCommand {
+    /// Start collecting a local usage summary. It is never sent anywhere.
+    Enable,
+    /// Stop collecting and delete the local usage summary
+    Disable,
+}
+
+/// The output of `storage-cli telemetry`
+#[derive(Debug, Serialize)]
+struct TelemetryOutput {
+    enabled: bool,
+    /// Where the usage summary is collected
+    path: PathBuf,
+}
+
+impl Output for TelemetryOutput {
+    fn table(&self) {
+        if self.enabled {
+            println!(
+                "collecting a local usage summary in {}",
+                self.path.display()
+            );
+            println!("it never leaves this machine, see `storage-cli doctor --summary`");
+        } else {
+            println!("telemetry disabled, the usage summary has been deleted");
+        }
+    }
+}
+
+pub(crate) fn telemetry(
+    config: &Config,
+    command: &TelemetryCommand,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let enabled = match command {
+        TelemetryCommand::Enable => {
+            Telemetry::enable(config).into_diagnostic()?;
+            true
+        }
+        TelemetryCommand::Disable => {
+            Telemetry::disable(config).into_diagnostic()?;
+            false
+        }
+    };
+    format.print(&TelemetryOutput {
+        enabled,
+        path: config.telemetry_path().clone(),
+    })
+}
+
+/// Arguments of `storage-cli doctor`
+#[derive(Debug, Args)]
+pub(crate) struct DoctorArgs {
+    /// Also print the local usage summary, if telemetry is enabled
+    #[arg(long)]
+    summary: bool,
+    /// Print the checks as JSON, same as `--format json`
+    #[arg(long)]
+    json: bool,
+}
+
+/// The output of `storage-cli doctor`
+#[derive(Debug, Serialize)]
+struct DoctorOutput {
+    checks: Vec<Check>,
+    problems: Vec<ProblemOutput>,
+    #[serde(serialize_with = "rfc3339")]
+    paused_until: Option<Timestamp>,
+    telemetry: bool,
+    /// The local usage summary, only with `--summary`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<SummaryOutput>,
+    /// Whether the usage summary was asked for, to tell a missing summary from an unwanted one
+    #[serde(skip)]
+    show_summary: bool,
+}
+
+/// The outcome of a [`Check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+        }
+    }
+}
+
+/// A single check of the environment, with a hint on how to fix it unless it passed
+#[derive(Debug, Serialize)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            hint: Some(hint.into()),
+            ..Self::pass(name, detail)
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            ..Self::warn(name, detail, hint)
+        }
+    }
+}
+
+/// A problem found by [`Config::validate`]
+#[derive(Debug, Serialize)]
+struct ProblemOutput {
+    code: &'static str,
+    error: bool,
+    message: String,
+    help: String,
+    /// The problem itself, printed to stderr like every other command does
+    #[serde(skip)]
+    problem: ConfigProblem,
+}
+
+/// The local usage summary, see [`storage_common::UsageSummary`]
+#[derive(Debug, Serialize)]
+struct SummaryOutput {
+    #[serde(serialize_with = "rfc3339")]
+    since: Option<Timestamp>,
+    operations: Vec<OperationOutput>,
+    errors: BTreeMap<String, u64>,
+}
+
+/// How often an operation ran and how long it took, the percentiles in milliseconds
+#[derive(Debug, Serialize)]
+struct OperationOutput {
+    name: String,
+    count: u64,
+    failures: u64,
+    p50_ms: Option<u64>,
+    p90_ms: Option<u64>,
+    p99_ms: Option<u64>,
+}
+
+impl Output for DoctorOutput {
+    fn table(&self) {
+        for check in &self.checks {
+            println!(
+                "{:<16} {:<8} {}",
+                check.name,
+                check.status.as_str(),
+                check.detail
+            );
+            if let Some(hint) = &check.hint {
+                println!("{:<16} {:<8} hint: {hint}", "", "");
+            }
+        }
+        let problems = self
+            .problems
+            .iter()
+            .map(|problem| problem.problem.clone())
+            .collect::<Vec<_>>();
+        super::print_problems(&problems);
+
+        match self.paused_until {
+            Some(until) => println!("{:<16} until {until}", "paused"),
+            None => println!("{:<16} no", "paused"),
+        }
+        println!(
+            "{:<16} {}",
+            "telemetry",
+            if self.telemetry {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
+
+        if self.show_summary {
+            println!();
+            print_summary(self.summary.as_ref());
+        }
+    }
+
+    fn plain(&self) {
+        for check in &self.checks {
+            println!(
+                "{}\t{}\t{}",
+                check.name,
+                check.status.as_str(),
+                check.detail
+            );
+        }
+    }
+}
+
+/// Checks the environment: the config, the store, the OS limit on watches, the daemon and the
+/// health of its watches, the index of the store and the clock. Optionally prints the local
+/// usage summary as well.
+///
+/// ## Errors
+/// - Returns an error if any check failed, after printing every check
+pub(crate) fn doctor(
+    config: &Config,
+    telemetry: &Telemetry,
+    args: &DoctorArgs,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let problems = config
+        .validate()
+        .into_iter()
+        .map(|problem| ProblemOutput {
+            code: problem.code().as_str(),
+            error: problem.severity() == Severity::Error,
+            message: problem.message().to_string(),
+            help: problem.help().to_string(),
+            problem,
+        })
+        .collect::<Vec<_>>();
+    let mut checks = vec![
+        config_check(&problems),
+        path_check("app dir", config.app_dir_path()),
+        tracking_list_check(config),
+        store_dir_check(config),
+    ];
+    checks.extend(watches_check(config));
+    checks.push(daemon_check(config));
+    checks.extend(watch_health_check(config));
+    let manager = BackupManager::new(config.clone());
+    checks.push(index_check(manager.as_ref()));
+    checks.push(clock_check(manager.as_ref().ok()));
+    let paused_until = Schedule::load(config)
+        .into_diagnostic()?
+        .quiet_until(Timestamp::now());
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    let format = if args.json {
+        OutputFormat::Json
+    } else {
+        format
+    };
+    format.print(&DoctorOutput {
+        checks,
+        problems,
+        paused_until,
+        telemetry: telemetry.is_enabled(),
+        summary: args.summary.then(|| summary_output(telemetry)).flatten(),
+        show_summary: args.summary,
+    })?;
+    if failed > 0 {
+        return Err(miette::miette!("{failed} check(s) failed"));
+    }
+    Ok(())
+}
+
+/// Whether the config has problems, which are listed on their own
+fn config_check(problems: &[ProblemOutput]) -> Check {
+    let errors = problems.iter().filter(|problem| problem.error).count();
+    let detail = format!("{} problem(s)", problems.len());
+    let hint = "see the problems below, and `storage-cli config show` for where values come from";
+    if errors > 0 {
+        Check::fail("config", detail, hint)
+    } else if problems.is_empty() {
+        Check::pass("config", detail)
+    } else {
+        Check::warn("config", detail, hint)
+    }
+}
+
+/// Whether the directory at `path` exists
+fn path_check(name: &'static str, path: &Path) -> Check {
+    if path.is_dir() {
+        Check::pass(name, path.display().to_string())
+    } else {
+        Check::fail(
+            name,
+            format!("{} is missing", path.display()),
+            format!("create it with `mkdir -p {}`", path.display()),
+        )
+    }
+}
+
+/// Whether there is a tracking list, without one nothing is backed up by the daemon
+fn tracking_list_check(config: &Config) -> Check {
+    let path = config.tracking_list_path();
+    match config.read_tracked_files() {
+        Ok(files) if !files.is_empty() => {
+            Check::pass("tracking list", format!("{} path(s) tracked", files.len()))
+        }
+        Ok(_) => Check::warn(
+            "tracking list",
+            "no path is tracked",
+            format!("add the paths to back up to {}", path.display()),
+        ),
+        Err(_) if !path.exists() => Check::warn(
+            "tracking list",
+            format!("{} is missing", path.display()),
+            format!("add the paths to back up to {}", path.display()),
+        ),
+        Err(e) => Check::fail(
+            "tracking list",
+            format!("{} is unreadable - {e}", path.display()),
+            "fix the file, it is a JSON list of paths",
+        ),
+    }
+}
+
+/// Whether the store dir exists, can be written to and has free space left
+fn store_dir_check(config: &Config) -> Check {
+    let dir = config.store_dir_path();
+    if !dir.is_dir() {
+        return path_check("store dir", dir);
+    }
+    let probe = dir.join(format!(".doctor-{}", std::process::id()));
+    if let Err(e) = std::fs::write(&probe, b"").and_then(|()| std::fs::remove_file(&probe)) {
+        return Check::fail(
+            "store dir",
+            format!("{} is not writable - {e}", dir.display()),
+            "make the directory writable by the user running backups",
+        );
+    }
+    let free = match xstd::fs::free_space(dir) {
+        Ok(free) => free,
+        Err(e) => {
+            return Check::warn(
+                "store dir",
+                format!("unable to get the free space of {} - {e}", dir.display()),
+                "check that the volume is mounted",
+            )
+        }
+    };
+    let detail = format!("{}, {} free", dir.display(), format_bytes(free));
+    match config.min_free_bytes() {
+        Some(min) if free < min => Check::fail(
+            "store dir",
+            detail,
+            format!(
+                "free up space, backups keep at least {} free",
+                format_bytes(min)
+            ),
+        ),
+        _ if free < LOW_FREE_BYTES => Check::warn(
+            "store dir",
+            detail,
+            "free up space, or set `max_store_bytes` to cap the store",
+        ),
+        _ => Check::pass("store dir", detail),
+    }
+}
+
+/// Whether there are enough watches left for the tracked paths, if the OS limits them
+fn watches_check(config: &Config) -> Option<Check> {
+    let limit = WatchLimit::current()?;
+    let tracked = config.read_tracked_files().map_or(0, |files| files.len());
+    let Some(used) = limit.used() else {
+        return Some(Check::pass(
+            "watches",
+            format!("limit {}, {tracked} path(s) tracked", limit.limit()),
+        ));
+    };
+    let detail = format!(
+        "{used} of {} in use, {tracked} path(s) tracked",
+        limit.limit()
+    );
+    // a running daemon already takes up the watches of the tracked paths
+    let available = limit.available().unwrap_or(0);
+    Some(if available < u64::cast_from(tracked) {
+        Check::warn(
+            "watches",
+            detail,
+            format!(
+                "paths past the limit are polled, {}",
+                WatchError::WatchLimitReached.guidance()
+            ),
+        )
+    } else {
+        Check::pass("watches", detail)
+    })
+}
+
+/// Whether the daemon answers on its control socket. If it does not, the lock on its pid file
+/// tells a hung daemon from one that is not running.
+fn daemon_check(config: &Config) -> Check {
+    let unreachable = match ControlSocket::ping(config, PING_TIMEOUT) {
+        Ok(elapsed) => {
+            return Check::pass(
+                "daemon",
+                format!("answered in {}", format_duration(elapsed)),
+            )
+        }
+        Err(e) => e,
+    };
+    match PidFile::running(config) {
+        Ok(Some(daemon)) => Check::fail(
+            "daemon",
+            format!(
+                "running as {daemon}, but not answering on {} - {unreachable}",
+                config.daemon_socket_path().display()
+            ),
+            "restart it with `storage-cli daemon --replace`",
+        ),
+        Ok(None) => Check::warn(
+            "daemon",
+            "not running",
+            "start it with `storage-cli daemon`, changes are not backed up without it",
+        ),
+        Err(e) => Check::fail(
+            "daemon",
+            format!("unable to read the pid file - {e}"),
+            format!(
+                "check the permissions of {}",
+                config.daemon_pid_path().display()
+            ),
+        ),
+    }
+}
+
+/// Whether the running daemon still watches every path it should, `None` if no daemon answers
+fn watch_health_check(config: &Config) -> Option<Check> {
+    let statuses = ControlSocket::health(config, PING_TIMEOUT).ok()?;
+    let unhealthy = statuses
+        .iter()
+        .filter(|status| !status.is_healthy())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    Some(if unhealthy.is_empty() {
+        Check::pass(
+            "watch health",
+            format!("{} path(s) watched", statuses.len()),
+        )
+    } else {
+        Check::warn(
+            "watch health",
+            format!(
+                "{} of {} path(s) not watched: {}",
+                unhealthy.len(),
+                statuses.len(),
+                unhealthy.join("; ")
+            ),
+            "recreate the lost paths, or restart the daemon with `storage-cli daemon --replace`",
+        )
+    })
+}
+
+/// Whether the index of the store matches the objects in it
+fn index_check(manager: Result<&BackupManager, &storage_common::Error>) -> Check {
+    let manager = match manager {
+        Ok(manager) => manager,
+        Err(e) => {
+            return Check::fail(
+                "index",
+                format!("unable to open the store - {e}"),
+                "run `storage-cli rebuild-index`, or `storage-cli migrate` after an upgrade",
+            )
+        }
+    };
+    match manager.index_drift() {
+        Ok(0) => Check::pass(
+            "index",
+            format!("{} backup(s)", manager.stats().total_backups()),
+        ),
+        Ok(drift) => Check::warn(
+            "index",
+            format!("{drift} object(s) out of date in the saved index"),
+            "run `storage-cli rebuild-index`, it is updated on the next backup as well",
+        ),
+        Err(e) => Check::fail(
+            "index",
+            format!("unreadable - {e}"),
+            "run `storage-cli rebuild-index`",
+        ),
+    }
+}
+
+/// Whether the clock looks right: set at all, and not behind the newest backup
+fn clock_check(manager: Option<&BackupManager>) -> Check {
+    let now = Timestamp::now();
+    if now < Timestamp::new(EARLIEST_PLAUSIBLE_SECS) {
+        return Check::fail(
+            "clock",
+            format!("the clock is at {now}"),
+            "set the system clock, e.g. by enabling NTP",
+        );
+    }
+    let newest = manager.and_then(|manager| manager.stats().newest());
+    match newest {
+        Some(newest) if newest.as_duration() > now.as_duration() + CLOCK_TOLERANCE => Check::warn(
+            "clock",
+            format!("the clock is at {now}, behind the newest backup from {newest}"),
+            "check the system clock, backups are ordered by when they were made",
+        ),
+        _ => Check::pass("clock", now.to_string()),
+    }
+}
+
+fn summary_output(telemetry: &Telemetry) -> Option<SummaryOutput> {
+    let summary = telemetry.summary()?;
+    #[allow(clippy::cast_possible_truncation)]
+    let millis = |duration: Option<Duration>| duration.map(|d| d.as_millis() as u64);
+    Some(SummaryOutput {
+        since: Some(summary.since()),
+        operations: summary
+            .operations()
+            .iter()
+            .map(|(name, operation)| OperationOutput {
+                name: name.clone(),
+                count: operation.count(),
+                failures: operation.failures(),
+                p50_ms: millis(operation.percentile(50)),
+                p90_ms: millis(operation.percentile(90)),
+                p99_ms: millis(operation.percentile(99)),
+            })
+            .collect(),
+        errors: summary.errors().clone(),
+    })
+}
+
+fn print_summary(summary: Option<&SummaryOutput>) {
+    let Some(summary) = summary else {
+        println!("no usage summary, enable one with `storage-cli telemetry enable`");
+        return;
+    };
+
+    if let Some(since) = summary.since {
+        println!("usage since {since}");
+    }
+    println!(
+        "{:<20} {:>8} {:>8} {:>10} {:>10} {:>10}",
+        "OPERATION", "COUNT", "FAILED", "P50", "P90", "P99"
+    );
+    for operation in &summary.operations {
+        let percentile = |millis: Option<u64>| {
+            millis.map_or_else(
+                || String::from("-"),
+                |millis| format_duration(Duration::from_millis(millis)),
+            )
+        };
+        println!(
+            "{:<20} {:>8} {:>8} {:>10} {:>10} {:>10}",
+            operation.name,
+            operation.count,
+            operation.failures,
+            percentile(operation.p50_ms),
+            percentile(operation.p90_ms),
+            percentile(operation.p99_ms)
+        );
+    }
+
+    if summary.errors.values().sum::<u64>() > 0 {
+        println!();
+        println!("{:<20} {:>8}", "ERROR", "COUNT");
+        for (category, count) in &summary.errors {
+            println!("{category:<20} {count:>8}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use storage_common::MaybeConfig;
+    use storage_mon::FileWatcher;
+
+    use super::*;
+
+    fn config(dir: &Path) -> Config {
+        let store_dir = dir.join("store");
+        Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir.to_str().unwrap())
+                .with_store_dir(store_dir.to_str().unwrap()),
+        )
+    }
+
+    #[test]
+    fn checks_store_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        let check = store_dir_check(&config);
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.detail.contains("is missing"), "{}", check.detail);
+
+        std::fs::create_dir_all(config.store_dir_path()).unwrap();
+        assert_ne!(store_dir_check(&config).status, Status::Fail);
+        let config = config.extend_with(&MaybeConfig::default().with_min_free_bytes(u64::MAX));
+        let check = store_dir_check(&config);
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.hint.unwrap().contains("free up space"));
+    }
+
+    #[test]
+    fn checks_index() {
+        let err = storage_common::Error::from("unreadable store");
+        assert_eq!(index_check(Err(&err)).status, Status::Fail);
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "contents").unwrap();
+        std::fs::create_dir_all(config.store_dir_path()).unwrap();
+        let manager = BackupManager::new(config.clone()).unwrap();
+        assert!(manager.backup_all([&file])[0].1.is_ok());
+        let check = index_check(Ok(&manager));
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.detail, "1 backup(s)");
+
+        std::fs::remove_file(config.store_index_path()).unwrap();
+        let check = index_check(Ok(&manager));
+        assert_eq!(check.status, Status::Warn);
+        assert!(check.detail.starts_with("1 object(s)"), "{}", check.detail);
+    }
+
+    #[test]
+    fn checks_clock() {
+        let check = clock_check(None);
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.hint, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checks_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        assert_eq!(daemon_check(&config).status, Status::Warn);
+
+        // a daemon holding its pid file without answering has hung
+        let pid_file = PidFile::acquire(&config).unwrap();
+        let check = daemon_check(&config);
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.detail.contains("not answering"), "{}", check.detail);
+
+        let socket = ControlSocket::bind(&config).unwrap();
+        assert_eq!(daemon_check(&config).status, Status::Pass);
+        drop((socket, pid_file));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checks_watch_health() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        assert!(watch_health_check(&config).is_none());
+
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "contents").unwrap();
+        let mut watcher = storage_mon::NotifyWatcher::new().unwrap();
+        watcher.watch_path(&file).unwrap();
+        watcher.start().unwrap();
+        let socket = ControlSocket::bind(&config).unwrap();
+        socket.watch_health().set(watcher.health());
+        let check = watch_health_check(&config).unwrap();
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.detail, "1 path(s) watched");
+
+        std::fs::remove_file(&file).unwrap();
+        socket.watch_health().set(watcher.health());
+        let check = watch_health_check(&config).unwrap();
+        assert_eq!(check.status, Status::Warn);
+        assert!(
+            check.detail.starts_with("1 of 1 path(s)"),
+            "{}",
+            check.detail
+        );
+        assert!(check.hint.unwrap().contains("--replace"));
+    }
+
+    #[test]
+    fn serializes_checks() {
+        let output = DoctorOutput {
+            checks: vec![
+                Check::pass("clock", "2023-03-28T10:40:00Z"),
+                Check::warn("daemon", "not running", "start it"),
+            ],
+            problems: vec![],
+            paused_until: None,
+            telemetry: false,
+            summary: None,
+            show_summary: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({
+                "checks": [
+                    { "name": "clock", "status": "pass", "detail": "2023-03-28T10:40:00Z" },
+                    {
+                        "name": "daemon",
+                        "status": "warn",
+                        "detail": "not running",
+                        "hint": "start it",
+                    },
+                ],
+                "problems": [],
+                "paused_until": null,
+                "telemetry": false,
+            })
+        );
+    }
+}
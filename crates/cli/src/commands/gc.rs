@@ -0,0 +1,94 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use clap::Args;
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{parse_duration, Config};
+use storage_store::{BackupManager, DryRun, GcReport};
+use xstd::display::format_bytes;
+
+use crate::output::{Output, OutputFormat};
+
+/// Arguments of `storage-cli gc`
+#[derive(Debug, Args)]
+pub(crate) struct GcArgs {
+    /// Keep unreferenced blobs written less than this long ago (e.g. `30m`), they may belong to
+    /// a backup that is still being written
+    #[arg(long, default_value = "1h")]
+    grace: String,
+    /// Only print which blobs would be removed
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// The output of `storage-cli gc`
+#[derive(Debug, Serialize)]
+struct GcOutput {
+    dry_run: bool,
+    removed: Vec<String>,
+    reclaimed_bytes: u64,
+    /// Unreferenced blobs younger than the grace period or of unknown age
+    kept: usize,
+}
+
+impl GcOutput {
+    fn new(report: &GcReport, dry_run: DryRun) -> Self {
+        Self {
+            dry_run: dry_run.is_on(),
+            removed: report
+                .removed()
+                .iter()
+                .map(|blob| blob.id().to_string())
+                .collect(),
+            reclaimed_bytes: report.reclaimed_bytes(),
+            kept: report.kept().len(),
+        }
+    }
+}
+
+impl Output for GcOutput {
+    fn table(&self) {
+        for blob in &self.removed {
+            if self.dry_run {
+                println!("would remove {blob}");
+            } else {
+                println!("removed {blob}");
+            }
+        }
+        let verb = if self.dry_run {
+            "would be reclaimed"
+        } else {
+            "reclaimed"
+        };
+        println!(
+            "{} unreferenced blob(s), {} {verb}",
+            self.removed.len(),
+            format_bytes(self.reclaimed_bytes)
+        );
+        if self.kept > 0 {
+            println!(
+                "{} unreferenced blob(s) kept within the grace period or of unknown age",
+                self.kept
+            );
+        }
+    }
+
+    fn plain(&self) {
+        for blob in &self.removed {
+            println!("{blob}");
+        }
+    }
+}
+
+/// Removes the content blobs no backup refers to any more
+pub(crate) fn gc(config: &Config, args: &GcArgs, format: OutputFormat) -> miette::Result<()> {
+    let grace = parse_duration(&args.grace).into_diagnostic()?;
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let dry_run = DryRun::from(args.dry_run);
+    let report = manager.gc(grace, dry_run).into_diagnostic()?;
+    format.print(&GcOutput::new(&report, dry_run))
+}
@@ -0,0 +1,135 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::Config;
+use storage_store::BackupManager;
+use xstd::display::format_bytes;
+
+use crate::output::{Output, OutputFormat};
+
+/// Arguments of `storage-cli history`
+#[derive(Debug, Args)]
+pub(crate) struct HistoryArgs {
+    /// The backed up file
+    path: PathBuf,
+    /// Only list this many of the latest versions
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+/// The output of `storage-cli history`
+#[derive(Debug, Serialize)]
+struct HistoryOutput {
+    path: PathBuf,
+    versions: Vec<VersionOutput>,
+}
+
+/// A single version of the file
+#[derive(Debug, Serialize)]
+struct VersionOutput {
+    version: u32,
+    id: String,
+    created: String,
+    size: u64,
+    /// Whether the version records the deletion of the file instead of its contents
+    deleted: bool,
+}
+
+impl Output for HistoryOutput {
+    fn table(&self) {
+        if self.versions.is_empty() {
+            println!("no backups of {}", self.path.display());
+            return;
+        }
+        println!("{:>8}  {:<30}  {:>10}", "VERSION", "CREATED", "SIZE");
+        for version in &self.versions {
+            let size = if version.deleted {
+                String::from("deleted")
+            } else {
+                format_bytes(version.size)
+            };
+            println!(
+                "{:>8}  {:<30}  {size:>10}",
+                version.version, version.created
+            );
+        }
+    }
+
+    fn plain(&self) {
+        for version in &self.versions {
+            let status = if version.deleted { "deleted" } else { "backup" };
+            println!(
+                "{}\t{status}\t{}\t{}\t{}",
+                version.version, version.id, version.created, version.size
+            );
+        }
+    }
+}
+
+/// Prints the versions of a file in the store, oldest first, including the deletions recorded
+/// for it. With `--limit` only that many of the latest versions are printed.
+pub(crate) fn history(
+    config: &Config,
+    args: &HistoryArgs,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let history = manager.history(&args.path);
+    let skip = args
+        .limit
+        .map_or(0, |limit| history.len().saturating_sub(limit));
+    format.print(&HistoryOutput {
+        path: args.path.clone(),
+        versions: history
+            .iter()
+            .skip(skip)
+            .map(|meta| VersionOutput {
+                version: meta.version().get(),
+                id: meta.id().to_string(),
+                created: meta.created().to_rfc3339(),
+                size: meta.fs_meta().size(),
+                deleted: meta.is_deleted(),
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_history() {
+        let output = HistoryOutput {
+            path: PathBuf::from("/home/me/notes.txt"),
+            versions: vec![VersionOutput {
+                version: 2,
+                id: String::from("abc"),
+                created: String::from("2023-03-28T10:40:00Z"),
+                size: 120,
+                deleted: false,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({
+                "path": "/home/me/notes.txt",
+                "versions": [{
+                    "version": 2,
+                    "id": "abc",
+                    "created": "2023-03-28T10:40:00Z",
+                    "size": 120,
+                    "deleted": false,
+                }],
+            })
+        );
+    }
+}
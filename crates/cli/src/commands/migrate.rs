@@ -0,0 +1,101 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::Config;
+use storage_store::{BackupManager, DryRun};
+
+use crate::output::{Output, OutputFormat};
+
+/// The output of `storage-cli migrate`
+#[derive(Debug, Serialize)]
+struct MigrateOutput {
+    dry_run: bool,
+    from: u32,
+    to: u32,
+    migrations: Vec<MigrationOutput>,
+    migrated: usize,
+    resumed: usize,
+}
+
+/// A migration applied to every backup
+#[derive(Debug, Serialize)]
+struct MigrationOutput {
+    to: u32,
+    description: &'static str,
+}
+
+impl Output for MigrateOutput {
+    fn table(&self) {
+        if self.migrations.is_empty() {
+            println!("the store is up to date (format {})", self.from);
+            return;
+        }
+        for migration in &self.migrations {
+            println!("format {:<3} {}", migration.to, migration.description);
+        }
+        if self.resumed > 0 {
+            println!("{} backup(s) were migrated before", self.resumed);
+        }
+        if self.dry_run {
+            println!(
+                "{} backup(s) would be migrated from format {}",
+                self.migrated, self.from
+            );
+        } else {
+            println!(
+                "migrated {} backup(s) from format {} to {}",
+                self.migrated, self.from, self.to
+            );
+        }
+    }
+}
+
+/// The output of `storage-cli migrate --rollback`
+#[derive(Debug, Serialize)]
+struct RollbackOutput {
+    restored: usize,
+}
+
+impl Output for RollbackOutput {
+    fn table(&self) {
+        println!(
+            "rolled back the migration, restored {} backup(s)",
+            self.restored
+        );
+    }
+}
+
+/// Upgrades every backup in the store to the current format, or with `rollback` undoes a
+/// migration that did not finish
+pub(crate) fn migrate(
+    config: &Config,
+    dry_run: bool,
+    rollback: bool,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    if rollback {
+        let restored = manager.rollback_migration().into_diagnostic()?;
+        return format.print(&RollbackOutput { restored });
+    }
+    let report = manager.migrate(DryRun::from(dry_run)).into_diagnostic()?;
+    format.print(&MigrateOutput {
+        dry_run,
+        from: report.from(),
+        to: report.to(),
+        migrations: report
+            .migrations()
+            .map(|migration| MigrationOutput {
+                to: migration.to(),
+                description: migration.description(),
+            })
+            .collect(),
+        migrated: report.migrated(),
+        resumed: report.resumed(),
+    })
+}
@@ -0,0 +1,79 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::{BufRead, IsTerminal};
+
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::Config;
+use storage_daemon::PidFile;
+use storage_store::{BackupManager, StoreKey};
+
+use crate::output::{Output, OutputFormat};
+
+/// The environment variable holding the new passphrase of `storage-cli rekey`, it is asked for
+/// on stdin otherwise
+const NEW_PASSPHRASE_ENV: &str = "STORAGE_NEW_PASSPHRASE";
+
+/// The output of `storage-cli rekey`
+#[derive(Debug, Serialize)]
+struct RekeyOutput {
+    blobs: usize,
+}
+
+impl Output for RekeyOutput {
+    fn table(&self) {
+        println!(
+            "re-encrypted {} blob(s), the store now needs the new passphrase",
+            self.blobs
+        );
+    }
+}
+
+/// Re-encrypts the store with a key derived from a new passphrase, encrypting a store that is not
+/// encrypted yet
+pub(crate) fn rekey(config: &Config, format: OutputFormat) -> miette::Result<()> {
+    if let Some(daemon) = PidFile::running(config).into_diagnostic()? {
+        miette::bail!(
+            "the daemon is running as {}, stop it before changing the passphrase",
+            daemon
+        );
+    }
+    let passphrase = new_passphrase()?;
+    let mut manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let key = StoreKey::new(&passphrase).into_diagnostic()?;
+    let blobs = manager.rekey(key).into_diagnostic()?;
+    format.print(&RekeyOutput { blobs })
+}
+
+/// Gets the new passphrase from [`NEW_PASSPHRASE_ENV`] or stdin, asking for it twice on a
+/// terminal
+fn new_passphrase() -> miette::Result<String> {
+    if let Ok(passphrase) = std::env::var(NEW_PASSPHRASE_ENV) {
+        return non_empty(passphrase);
+    }
+    let stdin = std::io::stdin();
+    let read = |prompt: &str| {
+        if stdin.is_terminal() {
+            eprint!("{prompt}: ");
+        }
+        let mut line = String::new();
+        stdin.lock().read_line(&mut line).into_diagnostic()?;
+        Ok::<_, miette::Report>(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let passphrase = read("new passphrase")?;
+    if stdin.is_terminal() && read("repeat the new passphrase")? != passphrase {
+        miette::bail!("the passphrases do not match");
+    }
+    non_empty(passphrase)
+}
+
+fn non_empty(passphrase: String) -> miette::Result<String> {
+    if passphrase.is_empty() {
+        miette::bail!("the new passphrase must not be empty");
+    }
+    Ok(passphrase)
+}
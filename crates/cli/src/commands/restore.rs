@@ -0,0 +1,253 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{Config, Error};
+use storage_store::{
+    BackupManager, DryRun, RestoreOptions, SnapshotId, UniqueId, DEFAULT_RESTORE_RETRIES,
+};
+
+use crate::output::{Output, OutputFormat};
+
+/// Arguments of `storage-cli restore`
+#[derive(Debug, Args)]
+pub(crate) struct RestoreArgs {
+    /// The files to restore, deleted files are brought back as they were before their deletion
+    #[arg(
+        required_unless_present_any = ["id", "snapshot"],
+        conflicts_with_all = ["id", "snapshot"]
+    )]
+    paths: Vec<PathBuf>,
+    /// Restore this version instead of the latest one, starting at 1 (only with a single path)
+    #[arg(long)]
+    version: Option<u32>,
+    /// Restore the backup with this id, as printed by `backup-now`
+    #[arg(long, conflicts_with = "snapshot")]
+    id: Option<UniqueId>,
+    /// Restore every file of the snapshot with this id, as printed by `snapshot create`
+    #[arg(long, conflicts_with = "version")]
+    snapshot: Option<SnapshotId>,
+    /// Restore into this directory instead of overwriting the original files
+    #[arg(long)]
+    to: Option<PathBuf>,
+    /// Read every restored file back and compare it with the backup
+    #[arg(long)]
+    verify: bool,
+    /// How often a restore that fails verification is written again before giving up
+    #[arg(long, default_value_t = DEFAULT_RESTORE_RETRIES, requires = "verify")]
+    retries: u32,
+    /// Only print which files would be created or overwritten
+    #[arg(long, conflicts_with = "verify")]
+    dry_run: bool,
+    #[command(flatten)]
+    overwrite: OverwriteArgs,
+}
+
+/// How `storage-cli restore` treats the files it overwrites at their original paths
+#[derive(Debug, Args)]
+struct OverwriteArgs {
+    /// Overwrite existing files without backing them up first, the restore cannot be undone
+    #[arg(long)]
+    no_pre_restore_backup: bool,
+    /// Overwrite files that changed since their latest backup, instead of skipping them
+    #[arg(long)]
+    force: bool,
+}
+
+/// The output of `storage-cli restore`
+#[derive(Debug, Serialize)]
+struct RestoreOutput {
+    files: Vec<RestoredFile>,
+}
+
+/// The result of restoring a single file
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum RestoredFile {
+    /// The file would be restored by a dry run
+    Planned {
+        path: PathBuf,
+        change: String,
+    },
+    Restored {
+        path: PathBuf,
+        version: u32,
+        id: String,
+        destination: PathBuf,
+        verified: bool,
+        attempts: u32,
+        /// The version holding the contents the restore overwrote
+        pre_restore_version: Option<u32>,
+    },
+    /// The file changed since its latest backup and was left alone
+    Conflict {
+        path: PathBuf,
+        latest: u32,
+    },
+    Failed {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+impl Output for RestoreOutput {
+    fn table(&self) {
+        for file in &self.files {
+            match file {
+                RestoredFile::Planned { change, .. } => println!("would {change}"),
+                RestoredFile::Restored {
+                    path,
+                    version,
+                    id,
+                    destination,
+                    verified,
+                    attempts,
+                    pre_restore_version,
+                } => {
+                    let verification = match (verified, attempts) {
+                        (false, _) => String::new(),
+                        (true, 1) => ", verified".to_string(),
+                        (true, attempts) => format!(", verified after {attempts} attempts"),
+                    };
+                    println!(
+                        "restored {} (version {version}, id {id}) to {}{verification}",
+                        path.display(),
+                        destination.display()
+                    );
+                    if let Some(previous) = pre_restore_version {
+                        println!("  the overwritten contents were kept as version {previous}");
+                    }
+                }
+                RestoredFile::Conflict { path, latest } => eprintln!(
+                    "not restoring {}, it changed since its latest backup (version {latest}), \
+                     use --force to overwrite it",
+                    path.display()
+                ),
+                RestoredFile::Failed { path, error } => {
+                    eprintln!("failed to restore {} - {error}", path.display());
+                }
+            }
+        }
+    }
+
+    fn plain(&self) {
+        for file in &self.files {
+            match file {
+                RestoredFile::Planned { path, change } => {
+                    println!("planned\t{}\t{change}", path.display());
+                }
+                RestoredFile::Restored {
+                    path,
+                    version,
+                    id,
+                    destination,
+                    ..
+                } => println!(
+                    "restored\t{}\t{version}\t{id}\t{}",
+                    path.display(),
+                    destination.display()
+                ),
+                RestoredFile::Conflict { path, latest } => {
+                    eprintln!("conflict\t{}\t{latest}", path.display());
+                }
+                RestoredFile::Failed { path, error } => {
+                    eprintln!("failed to restore {} - {error}", path.display());
+                }
+            }
+        }
+    }
+}
+
+/// Restores files from the store, printing the verification result of each file
+pub(crate) fn restore(
+    config: &Config,
+    args: &RestoreArgs,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let version = match args.version {
+        Some(_) if args.paths.len() > 1 => miette::bail!("--version needs a single path"),
+        Some(n) => Some(super::file_version(n)?),
+        None => None,
+    };
+    let mut options = RestoreOptions::new()
+        .with_verification(args.verify)
+        .with_retries(args.retries)
+        .with_dry_run(DryRun::from(args.dry_run))
+        .with_pre_restore_backup(!args.overwrite.no_pre_restore_backup)
+        .with_force(args.overwrite.force);
+    if let Some(to) = &args.to {
+        options = options.with_destination(to);
+    }
+
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let results = match (args.snapshot, args.id, version) {
+        (Some(snapshot), _, _) => manager
+            .restore_snapshot(snapshot, &options)
+            .into_diagnostic()?,
+        (None, Some(id), _) => {
+            let result = manager.restore_by_id(id, &options);
+            let path = result.as_ref().map_or_else(
+                |_| PathBuf::from(id.to_string()),
+                |file| file.path().to_path_buf(),
+            );
+            vec![(path, result)]
+        }
+        (None, None, Some(version)) => vec![(
+            args.paths[0].clone(),
+            manager.restore(&args.paths[0], Some(version), &options),
+        )],
+        (None, None, None) => manager.restore_all(&args.paths, &options),
+    };
+    let total = results.len();
+
+    let files = results
+        .into_iter()
+        .map(|(path, result)| match result {
+            Ok(file) if args.dry_run => RestoredFile::Planned {
+                path,
+                change: file.change().to_string(),
+            },
+            Ok(file) => RestoredFile::Restored {
+                path,
+                version: file.version().get(),
+                id: file.id().to_string(),
+                destination: file.destination().to_path_buf(),
+                verified: file.is_verified(),
+                attempts: file.attempts(),
+                pre_restore_version: file.pre_restore_version().map(|version| version.get()),
+            },
+            Err(err) => match err.root_cause() {
+                Error::Conflict { path, latest } => RestoredFile::Conflict {
+                    path: path.clone(),
+                    latest: *latest,
+                },
+                _ => RestoredFile::Failed {
+                    path,
+                    error: err.to_string(),
+                },
+            },
+        })
+        .collect::<Vec<_>>();
+    let failed = files
+        .iter()
+        .filter(|file| {
+            matches!(
+                file,
+                RestoredFile::Conflict { .. } | RestoredFile::Failed { .. }
+            )
+        })
+        .count();
+    format.print(&RestoreOutput { files })?;
+
+    if failed > 0 {
+        miette::bail!("{} of {} restore(s) failed", failed, total);
+    }
+    Ok(())
+}
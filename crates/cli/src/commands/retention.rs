@@ -0,0 +1,214 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{Config, MaybeConfig, Timestamp};
+use storage_store::{BackupManager, DryRun, Plan, RetentionPolicy};
+use xstd::{display::format_bytes, str::truncate_ellipsis};
+
+use crate::output::{rfc3339, Output, OutputFormat};
+
+/// Subcommands of `storage-cli retention`
+#[derive(Debug, Subcommand)]
+pub(crate) enum RetentionCommand {
+    /// Runs a proposed policy against the current backups without deleting anything
+    Simulate {
+        /// The policy to simulate, e.g. `keep=10,max-age=30d`
+        policy: RetentionPolicy,
+    },
+    /// Deletes every backup a policy does not keep
+    Apply {
+        /// The policy to apply, e.g. `keep=10,max-age=30d`
+        policy: RetentionPolicy,
+        /// Only print which backups would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Deletes the oldest versions until the store fits into a size cap, keeping at least one
+    /// version of every file
+    Evict {
+        /// The (compressed) size the store may take up
+        #[arg(long)]
+        max_store_bytes: u64,
+        /// Only print which backups would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// The output of `storage-cli retention simulate`
+#[derive(Debug, Serialize)]
+struct SimulateOutput {
+    groups: Vec<GroupOutput>,
+    removed_versions: usize,
+    removed_bytes: u64,
+    #[serde(serialize_with = "rfc3339")]
+    oldest_kept: Option<Timestamp>,
+}
+
+/// The simulated outcome for a single file
+#[derive(Debug, Serialize)]
+struct GroupOutput {
+    path: PathBuf,
+    kept_versions: usize,
+    removed_versions: usize,
+    removed_bytes: u64,
+    #[serde(serialize_with = "rfc3339")]
+    oldest_kept: Option<Timestamp>,
+}
+
+impl Output for SimulateOutput {
+    fn table(&self) {
+        println!(
+            "{:<40} {:>8} {:>8} {:>14} {:>14}",
+            "PATH", "KEPT", "REMOVED", "REMOVED BYTES", "HISTORY FROM"
+        );
+        for group in &self.groups {
+            println!(
+                "{:<40} {:>8} {:>8} {:>14} {:>14}",
+                truncate_ellipsis(&group.path.display().to_string(), 40),
+                group.kept_versions,
+                group.removed_versions,
+                format_bytes(group.removed_bytes),
+                group
+                    .oldest_kept
+                    .map_or_else(|| String::from("-"), Timestamp::to_rfc3339),
+            );
+        }
+        println!();
+        println!(
+            "{} version(s) / {} would be removed",
+            self.removed_versions,
+            format_bytes(self.removed_bytes)
+        );
+        if let Some(oldest) = self.oldest_kept {
+            println!("history would reach back to {oldest}");
+        }
+    }
+
+    fn plain(&self) {
+        for group in &self.groups {
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                group.path.display(),
+                group.kept_versions,
+                group.removed_versions,
+                group.removed_bytes,
+                group
+                    .oldest_kept
+                    .map_or_else(|| String::from("-"), Timestamp::to_rfc3339),
+            );
+        }
+    }
+}
+
+/// The output of `storage-cli retention apply` and `storage-cli retention evict`
+#[derive(Debug, Serialize)]
+struct PlanOutput {
+    dry_run: bool,
+    changes: Vec<String>,
+    removed_versions: usize,
+    /// The size of a store that is still over its cap with every file down to a single version
+    over_cap_bytes: Option<u64>,
+}
+
+impl PlanOutput {
+    fn new(plan: &Plan, dry_run: DryRun) -> Self {
+        Self {
+            dry_run: dry_run.is_on(),
+            changes: plan.changes().iter().map(ToString::to_string).collect(),
+            removed_versions: plan.deleted_backups(),
+            over_cap_bytes: None,
+        }
+    }
+}
+
+impl Output for PlanOutput {
+    /// Prints the backups deleted by the plan, or the ones that would be with a dry run
+    fn table(&self) {
+        for change in &self.changes {
+            if self.dry_run {
+                println!("would {change}");
+            } else {
+                println!("{change}");
+            }
+        }
+        let verb = if self.dry_run {
+            "would be removed"
+        } else {
+            "removed"
+        };
+        println!("{} version(s) {verb}", self.removed_versions);
+        if let Some(store_bytes) = self.over_cap_bytes {
+            println!(
+                "the store takes up {}, but every file is down to a single version",
+                format_bytes(store_bytes)
+            );
+        }
+    }
+
+    fn plain(&self) {
+        for change in &self.changes {
+            println!("{change}");
+        }
+    }
+}
+
+pub(crate) fn run(
+    config: &Config,
+    command: &RetentionCommand,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    match command {
+        RetentionCommand::Simulate { policy } => simulate(config, policy, format),
+        RetentionCommand::Apply { policy, dry_run } => {
+            let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+            let dry_run = DryRun::from(*dry_run);
+            let report = manager.apply_retention(policy, dry_run).into_diagnostic()?;
+            format.print(&PlanOutput::new(&report.plan(), dry_run))
+        }
+        RetentionCommand::Evict {
+            max_store_bytes,
+            dry_run,
+        } => {
+            let config =
+                config.extend_with(&MaybeConfig::default().with_max_store_bytes(*max_store_bytes));
+            let manager = BackupManager::new(config).into_diagnostic()?;
+            let dry_run = DryRun::from(*dry_run);
+            let report = manager.evict_to_cap(dry_run).into_diagnostic()?;
+            let output = PlanOutput {
+                over_cap_bytes: report.is_over_cap().then(|| report.store_bytes()),
+                ..PlanOutput::new(&report.plan(), dry_run)
+            };
+            format.print(&output)
+        }
+    }
+}
+
+fn simulate(config: &Config, policy: &RetentionPolicy, format: OutputFormat) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let report = manager.simulate_retention(policy);
+    format.print(&SimulateOutput {
+        groups: report
+            .groups()
+            .iter()
+            .map(|group| GroupOutput {
+                path: group.path().to_path_buf(),
+                kept_versions: group.kept_versions(),
+                removed_versions: group.removed_versions(),
+                removed_bytes: group.removed_bytes(),
+                oldest_kept: group.oldest_kept(),
+            })
+            .collect(),
+        removed_versions: report.removed_versions(),
+        removed_bytes: report.removed_bytes(),
+        oldest_kept: report.oldest_kept(),
+    })
+}
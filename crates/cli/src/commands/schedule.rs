@@ -0,0 +1,72 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{parse_duration, Config, Schedule, Timestamp};
+use xstd::display::format_duration;
+
+use crate::output::{rfc3339, Output, OutputFormat};
+
+/// The output of `storage-cli pause` and `storage-cli resume`
+#[derive(Debug, Serialize)]
+struct ScheduleOutput {
+    /// What the command did, e.g. `resumed`
+    #[serde(skip)]
+    action: String,
+    quiet_hours: Option<String>,
+    /// Until when heavy work is deferred, `None` if it is running normally
+    #[serde(serialize_with = "rfc3339")]
+    deferred_until: Option<Timestamp>,
+}
+
+impl Output for ScheduleOutput {
+    fn table(&self) {
+        println!("{}", self.action);
+        if let Some(quiet_hours) = &self.quiet_hours {
+            println!("quiet hours: {quiet_hours} (UTC)");
+        }
+        match self.deferred_until {
+            Some(until) => println!(
+                "heavy work is deferred for another {}",
+                format_duration(
+                    until
+                        .as_duration()
+                        .saturating_sub(Timestamp::now().as_duration())
+                )
+            ),
+            None => println!("heavy work is running normally"),
+        }
+    }
+}
+
+/// Suspends heavy work for `duration` (e.g. `2h`). Queued work drains once the pause ends.
+pub(crate) fn pause(config: &Config, duration: &str, format: OutputFormat) -> miette::Result<()> {
+    let duration = parse_duration(duration).into_diagnostic()?;
+    let until = Timestamp::new(
+        Timestamp::now()
+            .as_secs()
+            .saturating_add(duration.as_secs()),
+    );
+    Schedule::pause(config, until).into_diagnostic()?;
+    report(config, format!("paused until {until}"), format)
+}
+
+/// Ends an ad-hoc pause early
+pub(crate) fn resume(config: &Config, format: OutputFormat) -> miette::Result<()> {
+    Schedule::resume(config).into_diagnostic()?;
+    report(config, "resumed".to_string(), format)
+}
+
+/// Prints whether heavy work is currently deferred, and for how long
+fn report(config: &Config, action: String, format: OutputFormat) -> miette::Result<()> {
+    let schedule = Schedule::load(config).into_diagnostic()?;
+    format.print(&ScheduleOutput {
+        action,
+        quiet_hours: schedule.quiet_hours().map(|hours| hours.to_string()),
+        deferred_until: schedule.quiet_until(Timestamp::now()),
+    })
+}
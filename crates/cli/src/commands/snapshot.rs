@@ -0,0 +1,120 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{Config, Timestamp};
+use storage_store::{BackupManager, Snapshot};
+
+use crate::output::{rfc3339, Output, OutputFormat};
+
+/// Subcommands of `storage-cli snapshot`
+#[derive(Debug, Subcommand)]
+pub(crate) enum SnapshotCommand {
+    /// Back up files together as a single snapshot, either all of them or none. Restore it with
+    /// `restore --snapshot`.
+    Create {
+        /// The files to back up
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// List the snapshots of the store, oldest first
+    List,
+}
+
+/// The output of `storage-cli snapshot create` and `storage-cli snapshot list`
+#[derive(Debug, Serialize)]
+struct SnapshotsOutput {
+    snapshots: Vec<SnapshotOutput>,
+}
+
+/// A single snapshot
+#[derive(Debug, Serialize)]
+struct SnapshotOutput {
+    id: String,
+    #[serde(serialize_with = "rfc3339")]
+    created: Option<Timestamp>,
+    files: Vec<SnapshotFile>,
+}
+
+/// A single backup of a snapshot
+#[derive(Debug, Serialize)]
+struct SnapshotFile {
+    path: PathBuf,
+    version: u32,
+    id: String,
+}
+
+impl From<&Snapshot> for SnapshotOutput {
+    fn from(snapshot: &Snapshot) -> Self {
+        Self {
+            id: snapshot.id().to_string(),
+            created: Some(snapshot.created()),
+            files: snapshot
+                .members()
+                .iter()
+                .map(|member| SnapshotFile {
+                    path: member.path().to_path_buf(),
+                    version: member.version().get(),
+                    id: member.id().to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Output for SnapshotsOutput {
+    fn table(&self) {
+        if self.snapshots.is_empty() {
+            println!("no snapshots");
+        }
+        for snapshot in &self.snapshots {
+            let created = snapshot
+                .created
+                .map_or_else(|| String::from("-"), Timestamp::to_rfc3339);
+            println!(
+                "snapshot {} ({created}, {} file(s))",
+                snapshot.id,
+                snapshot.files.len()
+            );
+            for file in &snapshot.files {
+                println!("  {} (version {})", file.path.display(), file.version);
+            }
+        }
+    }
+
+    fn plain(&self) {
+        for snapshot in &self.snapshots {
+            for file in &snapshot.files {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    snapshot.id,
+                    file.path.display(),
+                    file.version,
+                    file.id
+                );
+            }
+        }
+    }
+}
+
+pub(crate) fn run(
+    config: &Config,
+    command: &SnapshotCommand,
+    format: OutputFormat,
+) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let snapshots = match command {
+        SnapshotCommand::Create { paths } => vec![manager.snapshot(paths).into_diagnostic()?],
+        SnapshotCommand::List => manager.snapshots().into_diagnostic()?,
+    };
+    format.print(&SnapshotsOutput {
+        snapshots: snapshots.iter().map(SnapshotOutput::from).collect(),
+    })
+}
@@ -0,0 +1,233 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{Config, Timestamp};
+use storage_store::BackupManager;
+use xstd::{display::format_bytes, str::truncate_ellipsis};
+
+use crate::output::{csv_record, rfc3339, Output, OutputFormat};
+
+/// The output of `storage-cli stats`
+#[derive(Debug, Serialize)]
+struct StatsOutput {
+    backups: usize,
+    files: usize,
+    compressed_bytes: u64,
+    original_bytes: u64,
+    deduplicated_bytes: u64,
+    compression_ratio: Option<f64>,
+    #[serde(serialize_with = "rfc3339")]
+    oldest: Option<Timestamp>,
+    #[serde(serialize_with = "rfc3339")]
+    newest: Option<Timestamp>,
+    versions: Vec<FileVersions>,
+}
+
+/// The number of versions kept of a single file
+#[derive(Debug, Serialize)]
+struct FileVersions {
+    path: PathBuf,
+    versions: usize,
+}
+
+impl Output for StatsOutput {
+    fn table(&self) {
+        let timestamp =
+            |t: Option<Timestamp>| t.map_or_else(|| String::from("-"), |t| t.to_string());
+
+        println!("backups:           {}", self.backups);
+        println!("files:             {}", self.files);
+        println!("size on disk:      {}", format_bytes(self.compressed_bytes));
+        println!("original size:     {}", format_bytes(self.original_bytes));
+        println!(
+            "deduplicated:      {}",
+            format_bytes(self.deduplicated_bytes)
+        );
+        if let Some(ratio) = self.compression_ratio {
+            println!("compression ratio: {:.1}%", ratio * 100.0);
+        }
+        println!("oldest backup:     {}", timestamp(self.oldest));
+        println!("newest backup:     {}", timestamp(self.newest));
+
+        if !self.versions.is_empty() {
+            println!();
+            println!("{:<60} {:>8}", "PATH", "VERSIONS");
+            for file in &self.versions {
+                let path = file.path.display().to_string();
+                println!("{:<60} {:>8}", truncate_ellipsis(&path, 60), file.versions);
+            }
+        }
+    }
+
+    fn plain(&self) {
+        for file in &self.versions {
+            println!("{}\t{}", file.path.display(), file.versions);
+        }
+    }
+}
+
+/// The output of `storage-cli rebuild-index`
+#[derive(Debug, Serialize)]
+struct RebuildIndexOutput {
+    indexed: usize,
+}
+
+impl Output for RebuildIndexOutput {
+    fn table(&self) {
+        println!("indexed {} backup(s)", self.indexed);
+    }
+}
+
+/// The output of `storage-cli manifest`
+#[derive(Debug, Serialize)]
+struct ManifestOutput {
+    backups: Vec<ManifestRecord>,
+}
+
+/// A single backup of the manifest
+#[derive(Debug, Serialize)]
+struct ManifestRecord {
+    path: PathBuf,
+    version: u32,
+    id: String,
+    created: String,
+    #[serde(serialize_with = "rfc3339")]
+    modified: Option<Timestamp>,
+    /// The FNV-1a hash of the original contents in hex, none for a recorded deletion
+    hash: Option<String>,
+    size: u64,
+    stored_size: u64,
+    compression: String,
+    deleted: bool,
+}
+
+impl ManifestRecord {
+    /// The names of the fields, in the order of [`ManifestRecord::fields`]
+    const HEADER: [&'static str; 10] = [
+        "path",
+        "version",
+        "id",
+        "created",
+        "modified",
+        "hash",
+        "size",
+        "stored_size",
+        "compression",
+        "deleted",
+    ];
+
+    /// Gets the fields of the record as text, unset ones empty
+    fn fields(&self) -> [String; 10] {
+        [
+            self.path.display().to_string(),
+            self.version.to_string(),
+            self.id.clone(),
+            self.created.clone(),
+            self.modified.map(Timestamp::to_rfc3339).unwrap_or_default(),
+            self.hash.clone().unwrap_or_default(),
+            self.size.to_string(),
+            self.stored_size.to_string(),
+            self.compression.clone(),
+            self.deleted.to_string(),
+        ]
+    }
+}
+
+impl Output for ManifestOutput {
+    fn table(&self) {
+        if self.backups.is_empty() {
+            println!("no backups in the store");
+            return;
+        }
+        println!(
+            "{:<50} {:>8} {:>10} {:<16} {:<10}",
+            "PATH", "VERSION", "SIZE", "HASH", "CODEC"
+        );
+        for backup in &self.backups {
+            let path = backup.path.display().to_string();
+            let hash = backup.hash.as_deref().unwrap_or("deleted");
+            println!(
+                "{:<50} {:>8} {:>10} {hash:<16} {:<10}",
+                truncate_ellipsis(&path, 50),
+                backup.version,
+                format_bytes(backup.size),
+                backup.compression
+            );
+        }
+    }
+
+    fn plain(&self) {
+        for backup in &self.backups {
+            println!("{}", backup.fields().join("\t"));
+        }
+    }
+
+    fn csv(&self) -> miette::Result<()> {
+        println!("{}", csv_record(&ManifestRecord::HEADER));
+        for backup in &self.backups {
+            println!("{}", csv_record(&backup.fields()));
+        }
+        Ok(())
+    }
+}
+
+/// Prints aggregate statistics about the backup store
+pub(crate) fn stats(config: &Config, format: OutputFormat) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let stats = manager.stats();
+    format.print(&StatsOutput {
+        backups: stats.total_backups(),
+        files: stats.total_files(),
+        compressed_bytes: stats.compressed_bytes(),
+        original_bytes: stats.original_bytes(),
+        deduplicated_bytes: stats.deduplicated_bytes(),
+        compression_ratio: stats.compression_ratio(),
+        oldest: stats.oldest(),
+        newest: stats.newest(),
+        versions: stats
+            .versions()
+            .map(|(path, versions)| FileVersions {
+                path: path.to_path_buf(),
+                versions,
+            })
+            .collect(),
+    })
+}
+
+/// Prints an inventory of every backup in the store, ordered by path and version
+pub(crate) fn manifest(config: &Config, format: OutputFormat) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let manifest = manager.manifest().into_diagnostic()?;
+    format.print(&ManifestOutput {
+        backups: manifest
+            .entries()
+            .iter()
+            .map(|entry| ManifestRecord {
+                path: entry.path().to_path_buf(),
+                version: entry.version().get(),
+                id: entry.id().to_string(),
+                created: entry.created().to_rfc3339(),
+                modified: entry.modified(),
+                hash: entry.hash().map(|hash| format!("{hash:016x}")),
+                size: entry.size(),
+                stored_size: entry.stored_size(),
+                compression: entry.compression().to_string(),
+                deleted: entry.is_deleted(),
+            })
+            .collect(),
+    })
+}
+
+/// Rebuilds the index of the backup store from the backups in it
+pub(crate) fn rebuild_index(config: &Config, format: OutputFormat) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let indexed = manager.rebuild_index().into_diagnostic()?;
+    format.print(&RebuildIndexOutput { indexed })
+}
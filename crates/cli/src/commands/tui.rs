@@ -0,0 +1,312 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! `storage-cli tui`, an interactive browser of the backup history. The files with backups are
+//! listed on the left, the versions of the selected file on the right. Everything shown comes
+//! from the query methods of [`BackupManager`].
+
+use std::path::PathBuf;
+
+use miette::IntoDiagnostic;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use storage_common::Config;
+use storage_store::{is_text, BackupManager, FileMeta, RestoreOptions};
+use xstd::display::format_bytes;
+
+/// How many bytes of a version are shown in its preview
+const PREVIEW_BYTES: usize = 64 * 1024;
+
+/// The pane that moves with the arrow keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Files,
+    Versions,
+}
+
+/// The contents of a version, shown over the versions pane
+#[derive(Debug)]
+struct Preview {
+    title: String,
+    text: String,
+}
+
+/// The state of the browser
+struct App {
+    manager: BackupManager,
+    files: Vec<PathBuf>,
+    file_state: ListState,
+    history: Vec<FileMeta>,
+    version_state: ListState,
+    focus: Pane,
+    preview: Option<Preview>,
+    status: String,
+}
+
+impl App {
+    fn new(manager: BackupManager) -> Self {
+        let mut app = Self {
+            files: manager.files(),
+            manager,
+            file_state: ListState::default(),
+            history: Vec::new(),
+            version_state: ListState::default(),
+            focus: Pane::Files,
+            preview: None,
+            status: String::new(),
+        };
+        if !app.files.is_empty() {
+            app.file_state.select(Some(0));
+        }
+        app.load_history();
+        app
+    }
+
+    fn selected_file(&self) -> Option<&PathBuf> {
+        self.files.get(self.file_state.selected()?)
+    }
+
+    fn selected_version(&self) -> Option<&FileMeta> {
+        self.history.get(self.version_state.selected()?)
+    }
+
+    /// Reads the history of the selected file and selects its latest version
+    fn load_history(&mut self) {
+        self.history = self
+            .selected_file()
+            .map(|path| self.manager.history(path))
+            .unwrap_or_default();
+        self.version_state.select(self.history.len().checked_sub(1));
+    }
+
+    /// Moves the selection of the focused pane by `offset` entries
+    fn move_selection(&mut self, offset: isize) {
+        let (state, len) = match self.focus {
+            Pane::Files => (&mut self.file_state, self.files.len()),
+            Pane::Versions => (&mut self.version_state, self.history.len()),
+        };
+        let Some(last) = len.checked_sub(1) else {
+            return;
+        };
+        let selected = state.selected().unwrap_or_default();
+        state.select(Some(selected.saturating_add_signed(offset).min(last)));
+        if self.focus == Pane::Files {
+            self.load_history();
+        }
+    }
+
+    /// Shows the contents of the selected version
+    fn preview(&mut self) {
+        let Some(meta) = self.selected_version() else {
+            return;
+        };
+        let title = format!("{} (version {})", meta.path().display(), meta.version());
+        let text = if meta.is_deleted() {
+            format!("the file was deleted at {}", meta.created())
+        } else {
+            match self
+                .manager
+                .read_version_head(meta.path(), *meta.version(), PREVIEW_BYTES)
+            {
+                Ok(bytes) if is_text(&bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Ok(_) => format!("binary file, {}", format_bytes(meta.fs_meta().size())),
+                Err(e) => format!("unable to read the backup - {e}"),
+            }
+        };
+        self.preview = Some(Preview { title, text });
+    }
+
+    /// Restores the selected version over the original file, which is backed up first
+    fn restore(&mut self) {
+        let Some(meta) = self.selected_version() else {
+            return;
+        };
+        let (path, version) = (meta.path().clone(), *meta.version());
+        self.status = match self
+            .manager
+            .restore(&path, Some(version), &RestoreOptions::new())
+        {
+            Ok(restored) => match restored.pre_restore_version() {
+                Some(kept) => format!(
+                    "restored version {version} of {}, the overwritten contents are version {kept}",
+                    path.display()
+                ),
+                None => format!("restored version {version} of {}", path.display()),
+            },
+            Err(e) => format!("failed to restore {} - {e}", path.display()),
+        };
+        // keeping the overwritten contents adds a version
+        let selected = self.version_state.selected();
+        self.load_history();
+        if selected.is_some() {
+            self.version_state.select(selected);
+        }
+    }
+
+    /// Handles a key press, returns whether the browser should close
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        if self.preview.is_some() {
+            if matches!(key, KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q')) {
+                self.preview = None;
+            }
+            return false;
+        }
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Left | KeyCode::Char('h') => self.focus = Pane::Files,
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Tab => self.focus = Pane::Versions,
+            KeyCode::Enter if self.focus == Pane::Files => self.focus = Pane::Versions,
+            KeyCode::Enter => self.preview(),
+            KeyCode::Char('r') if self.focus == Pane::Versions => self.restore(),
+            _ => {}
+        }
+        false
+    }
+
+    fn draw(&mut self, frame: &mut Frame<'_>) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [files, versions] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let items = self
+            .files
+            .iter()
+            .map(|path| ListItem::new(path.display().to_string()));
+        let list = List::new(items)
+            .block(self.block(" Files ", Pane::Files))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, files, &mut self.file_state);
+
+        let items = self.history.iter().map(version_line);
+        let list = List::new(items)
+            .block(self.block(" Versions ", Pane::Versions))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, versions, &mut self.version_state);
+
+        let help = "↑↓ move  ←→ switch pane  enter preview  r restore  q quit";
+        let line = if self.status.is_empty() {
+            Line::from(help).dim()
+        } else {
+            Line::from(self.status.as_str())
+        };
+        frame.render_widget(line, status);
+
+        if let Some(preview) = &self.preview {
+            let area = inset(versions);
+            frame.render_widget(Clear, area);
+            let paragraph = Paragraph::new(preview.text.as_str())
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(format!(" {} ", preview.title)));
+            frame.render_widget(paragraph, area);
+        }
+    }
+
+    /// The border of a pane, highlighted while it has the focus
+    fn block(&self, title: &'static str, pane: Pane) -> Block<'static> {
+        let block = Block::bordered().title(title);
+        if self.focus == pane {
+            block.border_style(Style::new().bold())
+        } else {
+            block.border_style(Style::new().dim())
+        }
+    }
+}
+
+/// Describes a version in the versions pane
+fn version_line(meta: &FileMeta) -> ListItem<'static> {
+    let size = if meta.is_deleted() {
+        String::from("deleted")
+    } else {
+        format_bytes(meta.fs_meta().size())
+    };
+    ListItem::new(format!(
+        "{:>5}  {}  {size:>10}",
+        meta.version().get(),
+        meta.created()
+    ))
+}
+
+/// Shrinks `area` by a cell on every side
+fn inset(area: Rect) -> Rect {
+    Rect {
+        x: area.x.saturating_add(1),
+        y: area.y.saturating_add(1),
+        width: area.width.saturating_sub(2),
+        height: area.height.saturating_sub(2),
+    }
+}
+
+/// Browses the backup history of the store interactively until `q` is pressed
+pub(crate) fn tui(config: &Config) -> miette::Result<()> {
+    let manager = BackupManager::new(config.clone()).into_diagnostic()?;
+    let mut app = App::new(manager);
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app);
+    // the terminal is restored even if drawing failed, so the error can be read
+    ratatui::restore();
+    result.into_diagnostic()
+}
+
+/// Draws `app` and hands it the key presses until it closes
+fn run(terminal: &mut DefaultTerminal, app: &mut App) -> std::io::Result<()> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && app.handle_key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browses_and_restores() {
+        let store = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let config = Config::new().extend_with(
+            &storage_common::MaybeConfig::default().with_store_dir(store.path().to_str().unwrap()),
+        );
+        let manager = BackupManager::new(config).unwrap();
+        let notes = files.path().join("notes.txt");
+        for contents in ["one", "two"] {
+            std::fs::write(&notes, contents).unwrap();
+            manager.backup(&notes).unwrap();
+        }
+
+        let mut app = App::new(manager);
+        assert_eq!(app.files, [notes.clone()]);
+        assert_eq!(app.selected_version().unwrap().version().get(), 2);
+        assert!(!app.handle_key(KeyCode::Enter));
+        assert_eq!(app.focus, Pane::Versions);
+        assert!(!app.handle_key(KeyCode::Up));
+        assert!(!app.handle_key(KeyCode::Enter));
+        assert_eq!(app.preview.as_ref().unwrap().text, "one");
+        assert!(!app.handle_key(KeyCode::Char('q')));
+        assert!(app.preview.is_none());
+
+        assert!(!app.handle_key(KeyCode::Char('r')));
+        assert_eq!(std::fs::read(&notes).unwrap(), b"one");
+        // the file did not change since version 2, so no version was added
+        assert_eq!(app.history.len(), 2);
+        assert!(app.handle_key(KeyCode::Char('q')));
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{io::IsTerminal, path::PathBuf, time::Duration};
+
+use clap::Args;
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use storage_common::{Config, Shutdown, Timestamp};
+use storage_mon::{ConfiguredWatcher, FileWatcher, WatchEvent};
+
+use crate::output::{Output, OutputFormat};
+
+/// How long to wait for an event before checking for a signal again
+const TICK: Duration = Duration::from_millis(200);
+
+/// Arguments of `storage-cli watch`
+#[derive(Debug, Args)]
+pub(crate) struct WatchArgs {
+    /// The files (or directories) to watch, defaults to every tracked path
+    paths: Vec<PathBuf>,
+    /// Print the events of the underlying `notify` watcher as they arrive, including those that
+    /// are filtered out or dropped while paused (only the `notify` watcher)
+    #[arg(long)]
+    raw: bool,
+}
+
+/// A single event printed by `storage-cli watch`
+#[derive(Debug, Serialize)]
+struct WatchRecord {
+    time: String,
+    /// The kind of the event, as in the `events=` option of the tracking list, or the `notify`
+    /// kind for `--raw`
+    kind: String,
+    paths: Vec<PathBuf>,
+    #[serde(skip)]
+    color: bool,
+}
+
+impl WatchRecord {
+    fn from_event(event: &WatchEvent, color: bool) -> Self {
+        Self {
+            time: Timestamp::now().to_rfc3339(),
+            kind: event.kind().to_string(),
+            paths: event.paths().into_iter().map(PathBuf::from).collect(),
+            color,
+        }
+    }
+
+    fn from_raw(event: &storage_mon::RawEvent, color: bool) -> Self {
+        Self {
+            time: Timestamp::now().to_rfc3339(),
+            kind: format!("{:?}", event.kind),
+            paths: event.paths.clone(),
+            color,
+        }
+    }
+
+    /// Gets the ANSI color of the kind of event
+    fn ansi_color(&self) -> &'static str {
+        let kind = self.kind.to_ascii_lowercase();
+        match kind.get(..4).unwrap_or_default() {
+            "crea" => "32",
+            "modi" if kind.contains("metadata") => "34",
+            "modi" if kind.contains("name") => "36",
+            "modi" => "33",
+            "remo" => "31",
+            "rena" => "36",
+            "meta" => "34",
+            "acce" => "2",
+            _ => "0",
+        }
+    }
+}
+
+impl Output for WatchRecord {
+    fn table(&self) {
+        let paths = self
+            .paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        if self.color {
+            let color = self.ansi_color();
+            println!(
+                "\x1b[2m{}\x1b[0m  \x1b[{color}m{:<8}\x1b[0m  {paths}",
+                self.time, self.kind
+            );
+        } else {
+            println!("{}  {:<8}  {paths}", self.time, self.kind);
+        }
+    }
+
+    fn plain(&self) {
+        let mut fields = vec![self.time.clone(), self.kind.clone()];
+        fields.extend(self.paths.iter().map(|path| path.display().to_string()));
+        println!("{}", fields.join("\t"));
+    }
+}
+
+/// Watches `paths`, or every tracked path, with the watcher selected by the config and prints
+/// their events as they arrive until `SIGINT` or `SIGTERM` (`Ctrl-C` on Windows). Nothing is
+/// backed up, so this can run next to the daemon to find out why a change does not trigger a
+/// backup. With `raw` the events of the underlying `notify` watcher are printed instead.
+///
+/// The kinds of events are colored when printing a table to a terminal, unless `NO_COLOR` is
+/// set.
+pub(crate) fn watch(config: &Config, args: &WatchArgs, format: OutputFormat) -> miette::Result<()> {
+    config.init_app_structure().into_diagnostic()?;
+    let mut watcher = storage_mon::create_file_watcher_for(config).into_diagnostic()?;
+    if !args.paths.is_empty() {
+        for path in watcher.currently_watched().into_diagnostic()? {
+            watcher.unwatch_path(path.as_ref()).into_diagnostic()?;
+        }
+        for path in &args.paths {
+            watcher.watch_path(path).into_diagnostic()?;
+        }
+    }
+    let raw = match &watcher {
+        ConfiguredWatcher::Notify(notify) if args.raw => Some(notify.subscribe_raw()),
+        ConfiguredWatcher::Poll(_) if args.raw => {
+            miette::bail!("--raw needs the notify watcher, see the `watcher` config key")
+        }
+        _ => None,
+    };
+    let events = watcher.events();
+    let shutdown = Shutdown::new();
+    shutdown.on_signals().into_diagnostic()?;
+    watcher.start().into_diagnostic()?;
+    let watched = watcher.currently_watched().into_diagnostic()?.len();
+    eprintln!("watching {watched} path(s), press Ctrl-C to stop");
+
+    let color = format == OutputFormat::Table
+        && std::io::stdout().is_terminal()
+        && std::env::var_os("NO_COLOR").is_none();
+    while !shutdown.is_requested() {
+        let next = match &raw {
+            Some(raw) => raw
+                .recv_timeout(TICK)
+                .map(|event| WatchRecord::from_raw(&event, color)),
+            None => events
+                .recv_timeout(TICK)
+                .map(|event| WatchRecord::from_event(&event, color)),
+        };
+        match next {
+            Ok(record) => format.print(&record)?,
+            Err(e) if e.is_disconnected() => break,
+            Err(_) => {}
+        }
+    }
+    watcher.stop().into_diagnostic()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_event_kinds() {
+        let record = |kind: &str| WatchRecord {
+            time: String::new(),
+            kind: kind.to_string(),
+            paths: Vec::new(),
+            color: true,
+        };
+        let event = WatchEvent::Removed(PathBuf::from("/a"));
+        assert_eq!(WatchRecord::from_event(&event, false).kind, "remove");
+        assert_eq!(record("remove").ansi_color(), "31");
+        assert_eq!(record("Modify(Data(Content))").ansi_color(), "33");
+        assert_eq!(record("Modify(Metadata(Any))").ansi_color(), "34");
+        assert_eq!(record("Modify(Name(Both))").ansi_color(), "36");
+        assert_eq!(record("Other").ansi_color(), "0");
+    }
+}
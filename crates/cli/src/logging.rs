@@ -0,0 +1,113 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Installs the `tracing` subscriber described by the logging section of the [`Config`].
+
+use std::sync::{Mutex, OnceLock};
+
+use clap::Args;
+use miette::IntoDiagnostic;
+use storage_common::{ConfigBuilder, ConfigSource, LogConfig, LogLevel};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, reload, EnvFilter};
+
+/// The environment variable that replaces the configured level with a full filter directive,
+/// e.g. `STORAGE_LOG=storage_store=debug,warn`
+const FILTER_ENV: &str = "STORAGE_LOG";
+
+/// Replaces the filter of the subscriber installed by [`init`], see [`set_level`]
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>;
+
+/// The [`Reload`] of the installed subscriber, unset while the filter comes from [`FILTER_ENV`]
+static RELOAD: OnceLock<Reload> = OnceLock::new();
+
+/// Logging options that can be given on the command line, overriding the config
+#[derive(Debug, Args)]
+pub(crate) struct LogArgs {
+    /// The most verbose level that is logged: error, warn, info, debug or trace
+    #[arg(long = "log-level", global = true)]
+    level: Option<LogLevel>,
+    /// Append the log to this file instead of writing it to stderr
+    #[arg(long = "log-file", global = true)]
+    file: Option<String>,
+    /// Write every log event as a single line of JSON
+    #[arg(long = "log-json", global = true)]
+    json: bool,
+}
+
+impl LogArgs {
+    /// Applies these options as the command line layer of `builder`
+    pub(crate) fn apply(&self, builder: &mut ConfigBuilder) -> miette::Result<()> {
+        if let Some(level) = self.level {
+            builder
+                .set("log_level", &level.to_string(), ConfigSource::Cli)
+                .into_diagnostic()?;
+        }
+        if let Some(file) = &self.file {
+            builder
+                .set("log_file", file, ConfigSource::Cli)
+                .into_diagnostic()?;
+        }
+        if self.json {
+            builder
+                .set("log_json", "true", ConfigSource::Cli)
+                .into_diagnostic()?;
+        }
+        Ok(())
+    }
+}
+
+/// Installs the global subscriber for `config`
+pub(crate) fn init(config: &LogConfig) -> miette::Result<()> {
+    let (filter, reloadable) = match EnvFilter::try_from_env(FILTER_ENV) {
+        Ok(filter) => (filter, false),
+        Err(_) => (
+            EnvFilter::try_new(config.level().to_string()).into_diagnostic()?,
+            true,
+        ),
+    };
+    let writer = match config.file() {
+        Some(file) => BoxMakeWriter::new(Mutex::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .into_diagnostic()?,
+        )),
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    let result = if config.json() {
+        let builder = builder.json().with_filter_reloading();
+        if reloadable {
+            keep_reload(builder.reload_handle());
+        }
+        builder.try_init()
+    } else {
+        let builder = builder.with_filter_reloading();
+        if reloadable {
+            keep_reload(builder.reload_handle());
+        }
+        builder.try_init()
+    };
+    result.map_err(|e| miette::miette!("unable to set up logging - {}", e))
+}
+
+fn keep_reload<S: 'static>(handle: reload::Handle<EnvFilter, S>) {
+    let _ = RELOAD.set(Box::new(move |filter| handle.reload(filter)));
+}
+
+/// Changes the most verbose level that is logged by the subscriber installed by [`init`]. Does
+/// nothing if the filter was given by `STORAGE_LOG`, which keeps precedence.
+pub(crate) fn set_level(level: LogLevel) -> miette::Result<()> {
+    let Some(reload) = RELOAD.get() else {
+        return Ok(());
+    };
+    let filter = EnvFilter::try_new(level.to_string()).into_diagnostic()?;
+    reload(filter).into_diagnostic()
+}
@@ -39,6 +39,409 @@
     )
 )]
 
-fn main() {
-    println!("Hello, world!");
+mod alias;
+mod commands;
+mod logging;
+mod output;
+
+use std::time::Instant;
+
+use clap::{CommandFactory, Parser, Subcommand};
+use miette::IntoDiagnostic;
+use storage_common::{ConfigBuilder, MaybeConfig, Telemetry};
+
+/// Command-line interface for the storage app
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    logging: logging::LogArgs,
+    /// Work on the named store instead of the global one (see the `stores` config key)
+    #[arg(long, global = true)]
+    store: Option<String>,
+    /// How results are printed; progress, warnings and errors always go to stderr
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: output::OutputFormat,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Back up files once, without starting a watcher (e.g. from cron)
+    BackupNow {
+        /// The files to back up, defaults to every tracked file
+        paths: Vec<std::path::PathBuf>,
+        /// Back up unchanged files too, and ignore quiet hours or a pause
+        #[arg(long)]
+        force: bool,
+        /// Back up one file at a time, showing the progress, throughput and ETA of each stage
+        #[arg(long)]
+        progress: bool,
+        /// Apply this retention policy afterwards, e.g. `keep=10,max-age=30d`
+        #[arg(long)]
+        retention: Option<storage_store::RetentionPolicy>,
+        /// Evict the oldest versions once the store grows past this many (compressed) bytes
+        #[arg(long)]
+        max_store_bytes: Option<u64>,
+        /// The `brotli` quality (0-11) for files not stored uncompressed or counted as large
+        #[arg(long, value_parser = clap::value_parser!(u32).range(0..=11))]
+        compression_quality: Option<u32>,
+    },
+    /// Watch the tracked files and back them up as they change, until interrupted
+    Daemon {
+        /// Limit how often a changed file is backed up, e.g. `min-interval=10m,settle=30s`.
+        /// Entries of the tracking list can override it after a tab.
+        #[arg(long)]
+        throttle: Option<storage_common::Throttle>,
+        /// Evict the oldest versions once the store grows past this many (compressed) bytes,
+        /// overriding `max_store_bytes` of the config
+        #[arg(long)]
+        max_store_bytes: Option<u64>,
+        /// Abort the process when any thread panics, instead of shutting down gracefully
+        #[arg(long)]
+        abort_on_panic: bool,
+        /// Stop the daemon already running for the app dir and take over
+        #[arg(long)]
+        replace: bool,
+        /// Keep the backups in memory instead of the store, they are discarded once the daemon
+        /// stops, e.g. to try out a config
+        #[arg(long)]
+        ephemeral: bool,
+    },
+    /// Print the events of the tracked files as they happen, without backing anything up, e.g. to
+    /// find out why a change does not trigger a backup
+    Watch(commands::watch::WatchArgs),
+    /// Show what changed between two versions of a file
+    Diff {
+        /// The backed up file
+        path: std::path::PathBuf,
+        /// The older version, defaults to the one before `--to`
+        #[arg(long)]
+        from: Option<u32>,
+        /// The newer version, defaults to the latest one
+        #[arg(long)]
+        to: Option<u32>,
+    },
+    /// List the versions of a file in the store, including when it was deleted
+    History(commands::history::HistoryArgs),
+    /// Browse the backup history interactively, preview and restore versions
+    #[cfg(feature = "tui")]
+    Tui,
+    /// Back up the files that changed since their latest backup, e.g. while the daemon was stopped
+    Sync {
+        /// The files (or directories) to check, defaults to every tracked path
+        paths: Vec<std::path::PathBuf>,
+        /// Compare the contents of files whose size did not change, even if their modification
+        /// time did not change either
+        #[arg(long)]
+        thorough: bool,
+    },
+    /// Restore files from the store, optionally verifying what was written
+    Restore(commands::restore::RestoreArgs),
+    /// Back up groups of files together, so they can be restored together
+    #[command(subcommand)]
+    Snapshot(commands::snapshot::SnapshotCommand),
+    /// Copy the full version history of files into another store
+    CloneHistory {
+        /// The files (or directories) whose history should be copied
+        #[arg(required = true)]
+        paths: Vec<std::path::PathBuf>,
+        /// The store directory to copy into, e.g. a shared archive
+        #[arg(long)]
+        to: std::path::PathBuf,
+    },
+    /// Export every backup into a single portable archive, e.g. to move the store elsewhere
+    Export {
+        /// The archive file to create
+        archive: std::path::PathBuf,
+    },
+    /// Merge the backups of an archive created by `export` into the store
+    Import {
+        /// The archive file to import
+        archive: std::path::PathBuf,
+    },
+    /// Add or change the note, tags and pin of a backup
+    Annotate(commands::annotations::AnnotateArgs),
+    /// Exchange annotations with other stores through a sidecar file
+    #[command(subcommand)]
+    Annotations(commands::annotations::AnnotationsCommand),
+    /// Suspend compression, verification and gc for a while, e.g. `pause 2h`
+    Pause {
+        /// How long to pause for, accepts the suffixes `s`, `m`, `h`, `d` and `w`
+        duration: String,
+    },
+    /// End a pause started with `pause` early
+    Resume,
+    /// Show statistics about the backup store
+    Stats,
+    /// List every backup in the store with its hash, sizes and codec, e.g. `--format csv` to
+    /// audit the store or compare it with another one
+    Manifest,
+    /// Rebuild the index of the backup store by reading every backup in it
+    RebuildIndex,
+    /// Upgrade every backup in the store to the current format. Interrupted migrations resume
+    /// where they stopped.
+    Migrate {
+        /// Only print which migrations would be applied to how many backups
+        #[arg(long)]
+        dry_run: bool,
+        /// Undo a migration that was interrupted or failed, restoring the original backups
+        #[arg(long, conflicts_with = "dry_run")]
+        rollback: bool,
+    },
+    /// Encrypt the store with a key derived from a new passphrase, read from
+    /// `STORAGE_NEW_PASSPHRASE` or stdin. An encrypted store is opened with the passphrase in
+    /// `STORAGE_PASSPHRASE`.
+    Rekey,
+    /// Train a shared compression dictionary on the small backups in the store, new backups of
+    /// files below `compression_dictionary_bytes` are compressed with it
+    TrainDictionary(commands::dictionary::TrainArgs),
+    /// Remove the content blobs no backup refers to any more, e.g. after a crash or after
+    /// backups were deleted by hand
+    Gc(commands::gc::GcArgs),
+    /// Inspect the backup retention policy
+    #[command(subcommand)]
+    Retention(commands::retention::RetentionCommand),
+    /// Inspect the configuration, layered from defaults, `config.json`, `STORAGE_*` variables
+    /// and flags
+    #[command(subcommand)]
+    Config(commands::config::ConfigCommand),
+    /// Check the environment: the config, the store, the watch limit, the daemon, the index and
+    /// the clock, with hints on fixing what fails
+    Doctor(commands::doctor::DoctorArgs),
+    /// Opt in to or out of the local-only usage summary
+    #[command(subcommand)]
+    Telemetry(commands::doctor::TelemetryCommand),
+    /// Manage command aliases and default flags
+    #[command(subcommand)]
+    Alias(AliasCommand),
+    /// Print a shell completion script, e.g. `completions bash > /etc/bash_completion.d/storage-cli`
+    Completions {
+        /// The shell to complete in
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page
+    #[command(hide = true)]
+    Man,
+}
+
+#[derive(Debug, Subcommand)]
+enum AliasCommand {
+    /// List the configured aliases (`=`) and default flags (`+`)
+    List,
+}
+
+impl Command {
+    /// Whether this command reads or writes the store, so it needs a valid configuration
+    fn uses_store(&self) -> bool {
+        !matches!(
+            self,
+            Self::Config(_)
+                | Self::Doctor(_)
+                | Self::Telemetry(_)
+                | Self::Alias(_)
+                | Self::Completions { .. }
+                | Self::Man
+        )
+    }
+
+    /// The name under which this command is recorded in the usage summary
+    fn name(&self) -> &'static str {
+        match self {
+            Self::BackupNow { .. } => "backup-now",
+            Self::Daemon { .. } => "daemon",
+            Self::Watch(_) => "watch",
+            Self::Diff { .. } => "diff",
+            Self::History(_) => "history",
+            #[cfg(feature = "tui")]
+            Self::Tui => "tui",
+            Self::Sync { .. } => "sync",
+            Self::Restore(_) => "restore",
+            Self::Snapshot(_) => "snapshot",
+            Self::CloneHistory { .. } => "clone-history",
+            Self::Export { .. } => "export",
+            Self::Import { .. } => "import",
+            Self::Annotate(_) => "annotate",
+            Self::Annotations(_) => "annotations",
+            Self::Pause { .. } => "pause",
+            Self::Resume => "resume",
+            Self::Stats => "stats",
+            Self::Manifest => "manifest",
+            Self::RebuildIndex => "rebuild-index",
+            Self::Migrate { .. } => "migrate",
+            Self::Rekey => "rekey",
+            Self::TrainDictionary(_) => "train-dictionary",
+            Self::Gc(_) => "gc",
+            Self::Retention(_) => "retention",
+            Self::Config(_) => "config",
+            Self::Doctor(_) => "doctor",
+            Self::Telemetry(_) => "telemetry",
+            Self::Alias(_) => "alias",
+            Self::Completions { .. } => "completions",
+            Self::Man => "man",
+        }
+    }
+}
+
+/// Gets the retention policy configured for the named `store`, if any. The config of the selected
+/// store has no named stores left to carry it.
+fn store_retention(
+    builder: &ConfigBuilder,
+    store: Option<&str>,
+) -> miette::Result<Option<storage_store::RetentionPolicy>> {
+    store
+        .and_then(|name| builder.config().store(name)?.retention())
+        .map(str::parse)
+        .transpose()
+        .into_diagnostic()
+}
+
+/// Gets the config overrides of the `backup-now` flags
+fn backup_now_overrides(
+    config: &storage_common::Config,
+    max_store_bytes: Option<u64>,
+    compression_quality: Option<u32>,
+) -> MaybeConfig {
+    let mut overrides = MaybeConfig::default();
+    if let Some(max_store_bytes) = max_store_bytes {
+        overrides = overrides.with_max_store_bytes(max_store_bytes);
+    }
+    if let Some(quality) = compression_quality {
+        overrides = overrides.with_compression(config.compression().clone().with_quality(quality));
+    }
+    overrides
+}
+
+/// Loads the usage summary, a summary that cannot be read is not recorded to
+fn load_telemetry(config: &storage_common::Config) -> Telemetry {
+    Telemetry::load(config).unwrap_or_else(|err| {
+        eprintln!("ignoring unreadable usage summary - {err}");
+        Telemetry::disabled(config)
+    })
+}
+
+/// Records a run of `command` in the usage summary, failing to save it is only reported
+fn record_usage(telemetry: &mut Telemetry, command: &Command, started: Instant, failed: bool) {
+    telemetry.record(command.name(), started.elapsed(), failed);
+    if let Err(err) = telemetry.save() {
+        eprintln!("unable to update usage summary - {err}");
+    }
+}
+
+/// Gets the command line with the aliases of `cli.json` expanded, see [`alias::AliasConfig`]
+fn expand_aliases(config: &storage_common::Config) -> miette::Result<Vec<std::ffi::OsString>> {
+    let command = Cli::command();
+    alias::AliasConfig::load_or_default(&alias::AliasConfig::path(config)).expand(
+        std::env::args_os().collect(),
+        |name| command.find_subcommand(name).is_some(),
+        |flag| {
+            command
+                .get_arguments()
+                .find(|arg| arg.is_global_set() && arg.get_long() == Some(flag))
+                .map(|arg| arg.get_action().takes_values())
+        },
+    )
+}
+
+fn main() -> miette::Result<()> {
+    let mut builder = ConfigBuilder::new()
+        .with_file(ConfigBuilder::default_file_path())
+        .and_then(ConfigBuilder::with_env)
+        .into_diagnostic()?;
+    let cli = Cli::parse_from(expand_aliases(builder.config())?);
+    cli.logging.apply(&mut builder)?;
+    let config = match &cli.store {
+        Some(name) => builder.config().for_store(name).into_diagnostic()?,
+        None => builder.config().clone(),
+    };
+    logging::init(config.logging())?;
+    let mut telemetry = load_telemetry(&config);
+
+    if cli.command.uses_store() {
+        commands::check_config(&config)?;
+    }
+
+    let format = cli.format;
+    let started = Instant::now();
+    let result = match &cli.command {
+        Command::BackupNow {
+            paths,
+            force,
+            progress,
+            retention,
+            max_store_bytes,
+            compression_quality,
+        } => {
+            let overrides = backup_now_overrides(&config, *max_store_bytes, *compression_quality);
+            let store_retention = store_retention(&builder, cli.store.as_deref())?;
+            commands::backup::backup_now(
+                &config.extend_with(&overrides),
+                &mut telemetry,
+                paths,
+                *force,
+                *progress,
+                retention.as_ref().or(store_retention.as_ref()),
+                format,
+            )
+        }
+        Command::Daemon {
+            throttle,
+            max_store_bytes,
+            abort_on_panic,
+            replace,
+            ephemeral,
+        } => commands::daemon::daemon(
+            &builder,
+            cli.store.as_deref(),
+            *throttle,
+            *max_store_bytes,
+            *abort_on_panic,
+            *replace,
+            *ephemeral,
+        ),
+        Command::Diff { path, from, to } => commands::diff::diff(&config, path, *from, *to, format),
+        Command::History(args) => commands::history::history(&config, args, format),
+        Command::Watch(args) => commands::watch::watch(&config, args, format),
+        #[cfg(feature = "tui")]
+        Command::Tui => commands::tui::tui(&config),
+        Command::Sync { paths, thorough } => {
+            commands::backup::sync(&config, &mut telemetry, paths, *thorough, format)
+        }
+        Command::Restore(args) => commands::restore::restore(&config, args, format),
+        Command::Snapshot(command) => commands::snapshot::run(&config, command, format),
+        Command::CloneHistory { paths, to } => {
+            commands::backup::clone_history(&config, paths, to, format)
+        }
+        Command::Export { archive } => commands::backup::export_archive(&config, archive, format),
+        Command::Import { archive } => commands::backup::import_archive(&config, archive, format),
+        Command::Annotate(args) => commands::annotations::annotate(&config, args, format),
+        Command::Annotations(command) => commands::annotations::run(&config, command, format),
+        Command::Pause { duration } => commands::schedule::pause(&config, duration, format),
+        Command::Resume => commands::schedule::resume(&config, format),
+        Command::Stats => commands::stats::stats(&config, format),
+        Command::Manifest => commands::stats::manifest(&config, format),
+        Command::RebuildIndex => commands::stats::rebuild_index(&config, format),
+        Command::Migrate { dry_run, rollback } => {
+            commands::migrate::migrate(&config, *dry_run, *rollback, format)
+        }
+        Command::Rekey => commands::rekey::rekey(&config, format),
+        Command::TrainDictionary(args) => commands::dictionary::train(&config, args, format),
+        Command::Gc(args) => commands::gc::gc(&config, args, format),
+        Command::Retention(command) => commands::retention::run(&config, command, format),
+        Command::Config(command) => commands::config::run(&builder, command, format),
+        Command::Doctor(args) => commands::doctor::doctor(&config, &telemetry, args, format),
+        Command::Telemetry(command) => {
+            return commands::doctor::telemetry(&config, command, format)
+        }
+        Command::Alias(AliasCommand::List) => alias::list(&config, format),
+        // generated when installing, so they are not part of the usage summary
+        Command::Completions { shell } => {
+            return commands::completions::completions(Cli::command(), *shell)
+        }
+        Command::Man => return commands::completions::man(Cli::command()),
+    };
+
+    record_usage(&mut telemetry, &cli.command, started, result.is_err());
+    result
 }
@@ -0,0 +1,160 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! How the results of commands are printed, chosen with the global `--format` flag.
+//!
+//! Every command collects its result into a serializable [`Output`], so scripts can read it as
+//! JSON instead of scraping the text meant for people. Progress, warnings and errors always go
+//! to stderr and are never part of the output.
+
+use clap::ValueEnum;
+use miette::IntoDiagnostic;
+use serde::{Serialize, Serializer};
+use storage_common::Timestamp;
+
+/// How the result of a command is printed on stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Aligned columns and sentences, for people
+    #[default]
+    Table,
+    /// One tab-separated record per line without headers, for `cut`, `awk` and friends
+    Plain,
+    /// A single JSON document, for scripts
+    Json,
+    /// Comma-separated records with a header line, for spreadsheets (only commands listing
+    /// records)
+    Csv,
+}
+
+impl OutputFormat {
+    /// Prints `output` in this format
+    ///
+    /// ## Errors
+    /// - Returns an error if `output` cannot be serialized
+    pub(crate) fn print<T: Output>(self, output: &T) -> miette::Result<()> {
+        match self {
+            Self::Table => output.table(),
+            Self::Plain => output.plain(),
+            Self::Json => {
+                let json = serde_json::to_string_pretty(output).into_diagnostic()?;
+                println!("{json}");
+            }
+            Self::Csv => output.csv()?,
+        }
+        Ok(())
+    }
+}
+
+/// The result of a command
+pub(crate) trait Output: Serialize {
+    /// Prints the result for people
+    fn table(&self);
+
+    /// Prints the result as tab-separated records. Results that are not a list of records print
+    /// the same as [`Output::table`].
+    fn plain(&self) {
+        self.table();
+    }
+
+    /// Prints the result as comma-separated records with a header line, see [`csv_record`]
+    ///
+    /// ## Errors
+    /// - Returns an error if the result is not a list of records, the default
+    fn csv(&self) -> miette::Result<()> {
+        miette::bail!("this command has no csv output, use --format json or plain")
+    }
+}
+
+/// Joins `fields` into a line of comma-separated values, quoting the fields that contain a
+/// comma, a quote or a line break
+pub(crate) fn csv_record<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Serializes an optional timestamp of an output as RFC 3339, e.g. `2023-03-28T10:40:00Z`, for
+/// `#[serde(serialize_with = "rfc3339")]`
+#[allow(clippy::ref_option)] // serde passes the field by reference
+pub(crate) fn rfc3339<S: Serializer>(
+    timestamp: &Option<Timestamp>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    timestamp.map(Timestamp::to_rfc3339).serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::Cli;
+
+    #[derive(Serialize)]
+    struct Records {
+        names: Vec<&'static str>,
+    }
+
+    impl Output for Records {
+        fn table(&self) {
+            println!("{}", self.names.join(" "));
+        }
+    }
+
+    #[test]
+    fn parses_format() {
+        let format = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("storage-cli").chain(args.iter().copied()))
+                .map(|cli| cli.format)
+        };
+        assert_eq!(format(&["stats"]).unwrap(), OutputFormat::Table);
+        assert_eq!(
+            format(&["--format", "json", "stats"]).unwrap(),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            format(&["stats", "--format", "plain"]).unwrap(),
+            OutputFormat::Plain
+        );
+        assert_eq!(
+            format(&["stats", "--format=csv"]).unwrap(),
+            OutputFormat::Csv
+        );
+        assert!(format(&["stats", "--format", "yaml"]).is_err());
+    }
+
+    #[test]
+    fn prints_formats() {
+        let records = Records {
+            names: vec!["a", "b"],
+        };
+        for format in [OutputFormat::Table, OutputFormat::Plain, OutputFormat::Json] {
+            assert!(format.print(&records).is_ok(), "{format:?}");
+        }
+        // only commands listing records have a csv output
+        let err = OutputFormat::Csv.print(&records).unwrap_err();
+        assert!(err.to_string().contains("no csv output"), "{err}");
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_record(&["a", "b c", ""]), "a,b c,");
+        assert_eq!(
+            csv_record(&["a,b", "say \"hi\"", "two\nlines"]),
+            "\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\""
+        );
+    }
+}
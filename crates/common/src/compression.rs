@@ -0,0 +1,236 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The compression section of the [`Config`](crate::Config): the heuristics picking how hard a
+//! backup is compressed, or whether it is compressed at all, from its extension and size, and
+//! the buffer and window the `brotli` streams are written with, and which files are compressed
+//! with the shared dictionary of the store.
+
+use std::path::Path;
+
+/// The `brotli` quality used for files without a more specific rule
+const DEFAULT_QUALITY: u32 = 11;
+/// The size from which files are compressed with the faster [`CompressionConfig::large_file_quality`]
+const DEFAULT_LARGE_FILE_BYTES: u64 = 64 * 1024 * 1024;
+/// The `brotli` quality used for large files
+const DEFAULT_LARGE_FILE_QUALITY: u32 = 5;
+/// The size of the buffer files are compressed through, measured by the `codec` benchmark of
+/// `storage-format`: smaller buffers slow `brotli` down, larger ones gain nothing
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+/// The smallest buffer size files are compressed through
+const MIN_BUFFER_SIZE: usize = 1024;
+/// The `brotli` window size (log2) files are compressed with
+const DEFAULT_WINDOW: u32 = 22;
+/// The range of `brotli` window sizes (log2) every decoder supports
+const WINDOW_RANGE: std::ops::RangeInclusive<u32> = 10..=24;
+/// The size below which files are compressed with the shared dictionary of the store, once one
+/// has been trained
+const DEFAULT_DICTIONARY_BYTES: u64 = 16 * 1024;
+/// The extensions of formats that are compressed already, compressing them again gains nothing
+const DEFAULT_STORE_EXTENSIONS: &[&str] = &[
+    "7z", "avif", "br", "bz2", "docx", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg", "m4a",
+    "mkv", "mov", "mp3", "mp4", "ogg", "pdf", "png", "pptx", "rar", "tgz", "webm", "webp", "xlsx",
+    "xz", "zip", "zst",
+];
+
+/// How backups are compressed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompressionConfig {
+    quality: u32,
+    large_file_bytes: u64,
+    large_file_quality: u32,
+    store_extensions: Vec<String>,
+    buffer_size: usize,
+    window: u32,
+    dictionary_bytes: u64,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            quality: DEFAULT_QUALITY,
+            large_file_bytes: DEFAULT_LARGE_FILE_BYTES,
+            large_file_quality: DEFAULT_LARGE_FILE_QUALITY,
+            store_extensions: DEFAULT_STORE_EXTENSIONS
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            window: DEFAULT_WINDOW,
+            dictionary_bytes: DEFAULT_DICTIONARY_BYTES,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Creates the default compression configuration: `brotli` quality 11, quality 5 from 64 MiB
+    /// on, and no compression for common already compressed formats, through a 64 KiB buffer
+    /// with a 4 MiB window. Files below 16 KiB use the shared dictionary of the store.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the `brotli` quality (`0..=11`) used for files without a more specific rule
+    #[must_use]
+    pub fn with_quality(self, quality: u32) -> Self {
+        Self {
+            quality: quality.min(DEFAULT_QUALITY),
+            ..self
+        }
+    }
+
+    /// Compresses files of at least `large_file_bytes` with `large_file_quality` instead
+    #[must_use]
+    pub fn with_large_files(self, large_file_bytes: u64, large_file_quality: u32) -> Self {
+        Self {
+            large_file_bytes,
+            large_file_quality: large_file_quality.min(DEFAULT_QUALITY),
+            ..self
+        }
+    }
+
+    /// Replaces the extensions (without the leading `.`) of files that are stored uncompressed
+    #[must_use]
+    pub fn with_store_extensions<S: Into<String>>(
+        self,
+        store_extensions: impl IntoIterator<Item = S>,
+    ) -> Self {
+        Self {
+            store_extensions: store_extensions
+                .into_iter()
+                .map(|ext| ext.into().trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Sets the size of the buffer files are compressed through, at least 1 KiB
+    #[must_use]
+    pub fn with_buffer_size(self, buffer_size: usize) -> Self {
+        Self {
+            buffer_size: buffer_size.max(MIN_BUFFER_SIZE),
+            ..self
+        }
+    }
+
+    /// Sets the `brotli` window size (log2, `10..=24`) files are compressed with. Larger
+    /// windows find repetitions further apart at the cost of memory.
+    #[must_use]
+    pub fn with_window(self, window: u32) -> Self {
+        Self {
+            window: window.clamp(*WINDOW_RANGE.start(), *WINDOW_RANGE.end()),
+            ..self
+        }
+    }
+
+    /// Compresses files smaller than `dictionary_bytes` with the shared dictionary of the store,
+    /// once one has been trained. `0` never uses the dictionary.
+    #[must_use]
+    pub fn with_dictionary_bytes(self, dictionary_bytes: u64) -> Self {
+        Self {
+            dictionary_bytes,
+            ..self
+        }
+    }
+
+    /// Gets the `brotli` quality used for files without a more specific rule
+    #[must_use]
+    pub fn quality(&self) -> u32 {
+        self.quality
+    }
+
+    /// Gets the size from which files are compressed with [`CompressionConfig::large_file_quality`]
+    #[must_use]
+    pub fn large_file_bytes(&self) -> u64 {
+        self.large_file_bytes
+    }
+
+    /// Gets the `brotli` quality used for large files
+    #[must_use]
+    pub fn large_file_quality(&self) -> u32 {
+        self.large_file_quality
+    }
+
+    /// Gets the extensions of files that are stored uncompressed
+    #[must_use]
+    pub fn store_extensions(&self) -> &[String] {
+        &self.store_extensions
+    }
+
+    /// Gets the size of the buffer files are compressed through
+    #[must_use]
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Gets the `brotli` window size (log2) files are compressed with
+    #[must_use]
+    pub fn window(&self) -> u32 {
+        self.window
+    }
+
+    /// Gets the size below which files are compressed with the shared dictionary of the store
+    #[must_use]
+    pub fn dictionary_bytes(&self) -> u64 {
+        self.dictionary_bytes
+    }
+
+    /// Picks the `brotli` quality for the file at `path` of `size` bytes, `None` if it should be
+    /// stored uncompressed
+    #[must_use]
+    pub fn quality_for(&self, path: &Path, size: u64) -> Option<u32> {
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if extension.is_some_and(|ext| {
+            self.store_extensions
+                .iter()
+                .any(|store| store.eq_ignore_ascii_case(ext))
+        }) {
+            None
+        } else if size >= self.large_file_bytes {
+            Some(self.large_file_quality)
+        } else {
+            Some(self.quality)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_quality() {
+        let config = CompressionConfig::new();
+        assert_eq!(config.quality_for(Path::new("notes.txt"), 10), Some(11));
+        assert_eq!(config.quality_for(Path::new("photo.PNG"), 10), None);
+        assert_eq!(config.quality_for(Path::new("dump.sql"), 1 << 30), Some(5));
+
+        let config = config
+            .with_quality(20)
+            .with_large_files(100, 1)
+            .with_store_extensions([".sql"]);
+        assert_eq!(config.quality(), 11);
+        assert_eq!(config.quality_for(Path::new("photo.png"), 10), Some(11));
+        assert_eq!(config.quality_for(Path::new("photo.png"), 100), Some(1));
+        assert_eq!(config.quality_for(Path::new("dump.sql"), 10), None);
+        assert_eq!(config.quality_for(Path::new("Makefile"), 10), Some(11));
+        assert_eq!(config.dictionary_bytes(), 16 * 1024);
+        assert_eq!(config.with_dictionary_bytes(0).dictionary_bytes(), 0);
+    }
+
+    #[test]
+    fn clamps_stream_settings() {
+        let config = CompressionConfig::new();
+        assert_eq!(config.buffer_size(), 64 * 1024);
+        assert_eq!(config.window(), 22);
+
+        let config = config.with_buffer_size(10).with_window(30);
+        assert_eq!(config.buffer_size(), 1024);
+        assert_eq!(config.window(), 24);
+        assert_eq!(config.with_window(0).window(), 10);
+    }
+}
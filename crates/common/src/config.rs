@@ -4,6 +4,125 @@
 //!  This will store the list of monitored files/directories, backup settings,
 //!  and other app configurations.
 
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    events::EVENTS_KEY,
+    layered::{self, ConfigChange, ConfigReload, RELOADABLE_KEYS},
+    policy::{split_options, POLICY_KEYS},
+    priority::PRIORITY_KEY,
+    BackupPriority, BackupSchedule, CompressionConfig, EventKinds, HookConfig, LogConfig, Policy,
+    QuietHours, StoreConfig, Throttle,
+};
+
+/// The name of the directory of the application inside the data and config directories of the
+/// platform
+pub(crate) const APP_DIR_NAME: &str = "storage";
+
+/// The file watcher implementation used to detect changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WatcherKind {
+    /// Native change notifications (`inotify`, `FSEvents`, `ReadDirectoryChangesW`, ...)
+    #[default]
+    Notify,
+    /// Periodically stat and hash the watched paths, for file systems without native notifications
+    Poll,
+}
+
+impl FromStr for WatcherKind {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "notify" => Ok(Self::Notify),
+            "poll" => Ok(Self::Poll),
+            other => Err(format!("unknown watcher '{other}', expected 'notify' or 'poll'").into()),
+        }
+    }
+}
+
+impl fmt::Display for WatcherKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Notify => f.write_str("notify"),
+            Self::Poll => f.write_str("poll"),
+        }
+    }
+}
+
+/// How symbolic links are backed up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SymlinkPolicy {
+    /// Back up the link itself by storing its target path, restoring recreates the link
+    #[default]
+    Preserve,
+    /// Back up the contents of the file the link points to, restoring writes a regular file
+    Follow,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(Self::Preserve),
+            "follow" => Ok(Self::Follow),
+            other => Err(format!(
+                "unknown symlink policy '{other}', expected 'preserve' or 'follow'"
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for SymlinkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Preserve => f.write_str("preserve"),
+            Self::Follow => f.write_str("follow"),
+        }
+    }
+}
+
+/// What a backup does when writing it would leave less than
+/// [`Config::min_free_bytes`] free on the volume of the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LowDiskAction {
+    /// Log a warning and write the backup anyway
+    #[default]
+    Warn,
+    /// Log a warning and fail the backup with [`Error::DiskFull`](crate::Error::DiskFull)
+    Fail,
+}
+
+impl FromStr for LowDiskAction {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "fail" => Ok(Self::Fail),
+            other => {
+                Err(format!("unknown low disk action '{other}', expected 'warn' or 'fail'").into())
+            }
+        }
+    }
+}
+
+impl fmt::Display for LowDiskAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Warn => f.write_str("warn"),
+            Self::Fail => f.write_str("fail"),
+        }
+    }
+}
+
 /// The main configuration used by the application but with optional fields
 #[derive(Debug, Clone, Default)]
 pub struct MaybeConfig {
@@ -11,6 +130,195 @@ pub struct MaybeConfig {
     app_dir: Option<String>,
     store_dir: Option<String>,
     tracking_list: Option<String>,
+    backup_threads: Option<usize>,
+    quiet_hours: Option<QuietHours>,
+    watcher: Option<WatcherKind>,
+    watch_events: Option<EventKinds>,
+    symlinks: Option<SymlinkPolicy>,
+    logging: Option<LogConfig>,
+    max_store_bytes: Option<u64>,
+    min_free_bytes: Option<u64>,
+    on_low_disk: Option<LowDiskAction>,
+    compression: Option<CompressionConfig>,
+    throttle: Option<Throttle>,
+    stores: Option<Vec<StoreConfig>>,
+    schedule: Option<BackupSchedule>,
+    schedule_jitter: Option<Duration>,
+    hooks: Option<Vec<HookConfig>>,
+}
+
+impl MaybeConfig {
+    /// Sets the file watcher delay (in milliseconds)
+    #[must_use]
+    pub fn with_delay(self, delay: u64) -> Self {
+        Self {
+            delay: Some(delay),
+            ..self
+        }
+    }
+
+    /// Sets the path to the main application directory
+    #[must_use]
+    pub fn with_app_dir(self, app_dir: impl Into<String>) -> Self {
+        Self {
+            app_dir: Some(app_dir.into()),
+            ..self
+        }
+    }
+
+    /// Sets the path to the storage directory
+    #[must_use]
+    pub fn with_store_dir(self, store_dir: impl Into<String>) -> Self {
+        Self {
+            store_dir: Some(store_dir.into()),
+            ..self
+        }
+    }
+
+    /// Sets the path to the tracking list file
+    #[must_use]
+    pub fn with_tracking_list(self, tracking_list: impl Into<String>) -> Self {
+        Self {
+            tracking_list: Some(tracking_list.into()),
+            ..self
+        }
+    }
+
+    /// Sets the number of threads used when backing up multiple files at once
+    #[must_use]
+    pub fn with_backup_threads(self, backup_threads: usize) -> Self {
+        Self {
+            backup_threads: Some(backup_threads),
+            ..self
+        }
+    }
+
+    /// Sets the daily window during which heavy work is deferred
+    #[must_use]
+    pub fn with_quiet_hours(self, quiet_hours: QuietHours) -> Self {
+        Self {
+            quiet_hours: Some(quiet_hours),
+            ..self
+        }
+    }
+
+    /// Sets the file watcher implementation
+    #[must_use]
+    pub fn with_watcher(self, watcher: WatcherKind) -> Self {
+        Self {
+            watcher: Some(watcher),
+            ..self
+        }
+    }
+
+    /// Sets the kinds of events the file watcher reports
+    #[must_use]
+    pub fn with_watch_events(self, watch_events: EventKinds) -> Self {
+        Self {
+            watch_events: Some(watch_events),
+            ..self
+        }
+    }
+
+    /// Sets how symbolic links are backed up
+    #[must_use]
+    pub fn with_symlinks(self, symlinks: SymlinkPolicy) -> Self {
+        Self {
+            symlinks: Some(symlinks),
+            ..self
+        }
+    }
+
+    /// Sets how and where log output is written
+    #[must_use]
+    pub fn with_logging(self, logging: LogConfig) -> Self {
+        Self {
+            logging: Some(logging),
+            ..self
+        }
+    }
+
+    /// Sets the (compressed) size the store may grow to before old versions are evicted
+    #[must_use]
+    pub fn with_max_store_bytes(self, max_store_bytes: u64) -> Self {
+        Self {
+            max_store_bytes: Some(max_store_bytes),
+            ..self
+        }
+    }
+
+    /// Sets the free space to keep on the volume of the store, see [`Config::min_free_bytes`]
+    #[must_use]
+    pub fn with_min_free_bytes(self, min_free_bytes: u64) -> Self {
+        Self {
+            min_free_bytes: Some(min_free_bytes),
+            ..self
+        }
+    }
+
+    /// Sets what a backup does when the volume of the store is low on space
+    #[must_use]
+    pub fn with_on_low_disk(self, on_low_disk: LowDiskAction) -> Self {
+        Self {
+            on_low_disk: Some(on_low_disk),
+            ..self
+        }
+    }
+
+    /// Sets how backups are compressed
+    #[must_use]
+    pub fn with_compression(self, compression: CompressionConfig) -> Self {
+        Self {
+            compression: Some(compression),
+            ..self
+        }
+    }
+
+    /// Sets the limits on how often a changed file is backed up
+    #[must_use]
+    pub fn with_throttle(self, throttle: Throttle) -> Self {
+        Self {
+            throttle: Some(throttle),
+            ..self
+        }
+    }
+
+    /// Sets the named stores next to the global one
+    #[must_use]
+    pub fn with_stores(self, stores: Vec<StoreConfig>) -> Self {
+        Self {
+            stores: Some(stores),
+            ..self
+        }
+    }
+
+    /// Sets when the tracked paths without a schedule of their own are backed up regardless of
+    /// changes
+    #[must_use]
+    pub fn with_schedule(self, schedule: BackupSchedule) -> Self {
+        Self {
+            schedule: Some(schedule),
+            ..self
+        }
+    }
+
+    /// Sets the longest random delay added to every scheduled backup
+    #[must_use]
+    pub fn with_schedule_jitter(self, schedule_jitter: Duration) -> Self {
+        Self {
+            schedule_jitter: Some(schedule_jitter),
+            ..self
+        }
+    }
+
+    /// Sets the hooks fired when something happens to the store
+    #[must_use]
+    pub fn with_hooks(self, hooks: Vec<HookConfig>) -> Self {
+        Self {
+            hooks: Some(hooks),
+            ..self
+        }
+    }
 }
 
 /// The main configuration used by the application
@@ -20,15 +328,56 @@ pub struct Config {
     app_dir: String,
     store_dir: String,
     tracking_list: String,
+    backup_threads: usize,
+    quiet_hours: Option<QuietHours>,
+    watcher: WatcherKind,
+    watch_events: EventKinds,
+    symlinks: SymlinkPolicy,
+    logging: LogConfig,
+    max_store_bytes: Option<u64>,
+    min_free_bytes: Option<u64>,
+    on_low_disk: LowDiskAction,
+    compression: CompressionConfig,
+    throttle: Throttle,
+    stores: Vec<StoreConfig>,
+    schedule: Option<BackupSchedule>,
+    schedule_jitter: Duration,
+    hooks: Vec<HookConfig>,
 }
 
 impl Default for Config {
+    /// The directories default to the platform's data and config directories (see
+    /// [`xstd::dirs`]), e.g. `~/.local/share/storage` on Linux and `%APPDATA%\storage` on
+    /// Windows. If those are unknown they are relative to the working directory.
     fn default() -> Self {
+        let app_dir = xstd::dirs::data_dir()
+            .unwrap_or_default()
+            .join(APP_DIR_NAME);
+        let config_dir = xstd::dirs::config_dir()
+            .unwrap_or_default()
+            .join(APP_DIR_NAME);
+        let path = |path: PathBuf| path.to_string_lossy().into_owned();
         Self {
             delay: 1000,
-            app_dir: String::from("~/.storage-app-data"),
-            store_dir: String::from("~/.storage-app-data/.store"),
-            tracking_list: String::from("~/.storage-app-store/tracking_list.json"),
+            store_dir: path(app_dir.join(".store")),
+            app_dir: path(app_dir),
+            tracking_list: path(config_dir.join("tracking_list.json")),
+            backup_threads: std::thread::available_parallelism()
+                .map_or(1, std::num::NonZeroUsize::get),
+            quiet_hours: None,
+            watcher: WatcherKind::default(),
+            watch_events: EventKinds::DEFAULT,
+            symlinks: SymlinkPolicy::default(),
+            logging: LogConfig::default(),
+            max_store_bytes: None,
+            min_free_bytes: None,
+            on_low_disk: LowDiskAction::default(),
+            compression: CompressionConfig::default(),
+            throttle: Throttle::default(),
+            stores: Vec::new(),
+            schedule: None,
+            schedule_jitter: Duration::ZERO,
+            hooks: Vec::new(),
         }
     }
 }
@@ -82,6 +431,228 @@ impl Config {
         std::path::Path::new(self.tracking_list())
     }
 
+    /// Gets the number of threads used when backing up multiple files at once
+    #[must_use]
+    pub fn backup_threads(&self) -> usize {
+        self.backup_threads
+    }
+
+    /// Gets the daily window during which heavy work is deferred, if any
+    #[must_use]
+    pub fn quiet_hours(&self) -> Option<QuietHours> {
+        self.quiet_hours
+    }
+
+    /// Gets the file watcher implementation
+    #[must_use]
+    pub fn watcher(&self) -> WatcherKind {
+        self.watcher
+    }
+
+    /// Gets the kinds of events the file watcher reports for tracked paths without kinds of
+    /// their own, see [`TrackedPath::events`]
+    #[must_use]
+    pub fn watch_events(&self) -> EventKinds {
+        self.watch_events
+    }
+
+    /// Gets how symbolic links are backed up
+    #[must_use]
+    pub fn symlinks(&self) -> SymlinkPolicy {
+        self.symlinks
+    }
+
+    /// Gets how and where log output is written
+    #[must_use]
+    pub fn logging(&self) -> &LogConfig {
+        &self.logging
+    }
+
+    /// Gets the (compressed) size the store may grow to before old versions are evicted, if any
+    #[must_use]
+    pub fn max_store_bytes(&self) -> Option<u64> {
+        self.max_store_bytes
+    }
+
+    /// Gets the free space, in bytes, that writing a backup should leave on the volume of the
+    /// store, if any. Backups that would go below it trigger [`Config::on_low_disk`].
+    #[must_use]
+    pub fn min_free_bytes(&self) -> Option<u64> {
+        self.min_free_bytes
+    }
+
+    /// Gets what a backup does when writing it would go below [`Config::min_free_bytes`]
+    #[must_use]
+    pub fn on_low_disk(&self) -> LowDiskAction {
+        self.on_low_disk
+    }
+
+    /// Gets how backups are compressed
+    #[must_use]
+    pub fn compression(&self) -> &CompressionConfig {
+        &self.compression
+    }
+
+    /// Gets the limits on how often a changed file is backed up, entries of the tracking list can
+    /// override them (see [`TrackedPath`])
+    #[must_use]
+    pub fn throttle(&self) -> Throttle {
+        self.throttle
+    }
+
+    /// Gets when the tracked paths are backed up regardless of changes, if at all. Entries of the
+    /// tracking list can set their own schedule (see [`Policy`]).
+    #[must_use]
+    pub fn schedule(&self) -> Option<BackupSchedule> {
+        self.schedule
+    }
+
+    /// Gets the longest random delay added to every scheduled backup, which keeps entries on
+    /// the same schedule from all running at once
+    #[must_use]
+    pub fn schedule_jitter(&self) -> Duration {
+        self.schedule_jitter
+    }
+
+    /// Gets the hooks fired when something happens to the store, see [`HookConfig`]
+    #[must_use]
+    pub fn hooks(&self) -> &[HookConfig] {
+        &self.hooks
+    }
+
+    /// Gets the named stores next to the global one, see [`StoreConfig`]
+    #[must_use]
+    pub fn stores(&self) -> &[StoreConfig] {
+        &self.stores
+    }
+
+    /// Gets the named store called `name`
+    #[must_use]
+    pub fn store(&self, name: &str) -> Option<&StoreConfig> {
+        self.stores.iter().find(|store| store.name() == name)
+    }
+
+    /// Gets the named store `path` belongs to, the one with the longest root containing it.
+    /// Paths outside of every root belong to the global store.
+    #[must_use]
+    pub fn store_for(&self, path: &Path) -> Option<&StoreConfig> {
+        self.stores
+            .iter()
+            .filter(|store| store.contains(path))
+            .max_by_key(|store| store.root().components().count())
+    }
+
+    /// Gets the config of the named store called `name`: this config with the store directory
+    /// and tracking list of that store, and without any named stores
+    ///
+    /// ## Errors
+    /// - Errors if there is no store called `name`
+    pub fn for_store(&self, name: &str) -> super::Result<Self> {
+        let Some(store) = self.store(name) else {
+            let names = self
+                .stores
+                .iter()
+                .map(StoreConfig::name)
+                .collect::<Vec<_>>();
+            return Err(if names.is_empty() {
+                format!("unknown store '{name}', no stores are configured")
+            } else {
+                format!(
+                    "unknown store '{name}', expected one of {}",
+                    names.join(", ")
+                )
+            }
+            .into());
+        };
+        let path = |path: PathBuf| path.to_string_lossy().into_owned();
+        Ok(Self {
+            store_dir: path(store.store_dir()),
+            tracking_list: path(store.tracking_list()),
+            stores: Vec::new(),
+            ..self.clone()
+        })
+    }
+
+    /// Gets the path to the file storing an ad-hoc pause (see [`Schedule::pause`](crate::Schedule::pause))
+    #[must_use]
+    pub fn pause_file_path(&self) -> std::path::PathBuf {
+        self.app_dir_path().join(".paused_until")
+    }
+
+    /// Gets the path to the index of backup annotations (notes, tags and pins), which is kept in
+    /// the store directory so it moves together with the objects
+    #[must_use]
+    pub fn annotations_path(&self) -> std::path::PathBuf {
+        self.store_dir_path().join("annotations.json")
+    }
+
+    /// Gets the path to the list of snapshots, the groups of backups that were taken together
+    #[must_use]
+    pub fn snapshots_path(&self) -> std::path::PathBuf {
+        self.store_dir_path().join("snapshots.json")
+    }
+
+    /// Gets the path to the index of the objects in the store, which saves reading every object
+    /// when the store is opened
+    #[must_use]
+    pub fn store_index_path(&self) -> std::path::PathBuf {
+        self.store_dir_path().join("index.rmp")
+    }
+
+    /// Gets the path to the file recording the format version of the objects in the store, a
+    /// store without it predates the file
+    #[must_use]
+    pub fn store_format_path(&self) -> std::path::PathBuf {
+        self.store_dir_path().join("format_version")
+    }
+
+    /// Gets the path to the file recording the id of the shared compression dictionary of the
+    /// store, a store without it has none
+    #[must_use]
+    pub fn store_dictionary_path(&self) -> std::path::PathBuf {
+        self.store_dir_path().join("dictionary")
+    }
+
+    /// Gets the path to the file holding what is needed to derive the key of an encrypted store
+    /// from its passphrase, a store without it is not encrypted
+    #[must_use]
+    pub fn store_key_path(&self) -> std::path::PathBuf {
+        self.store_dir_path().join("key.json")
+    }
+
+    /// Gets the path to the directory holding the journal and the original objects of a store
+    /// migration in progress
+    #[must_use]
+    pub fn store_migration_path(&self) -> std::path::PathBuf {
+        self.store_dir_path().join("migration")
+    }
+
+    /// Gets the path to the lock file held while a process writes to the store
+    #[must_use]
+    pub fn store_lock_path(&self) -> std::path::PathBuf {
+        self.store_dir_path().join("store.lock")
+    }
+
+    /// Gets the path to the local usage summary (see [`Telemetry`](crate::Telemetry))
+    #[must_use]
+    pub fn telemetry_path(&self) -> std::path::PathBuf {
+        self.app_dir_path().join("usage-summary.json")
+    }
+
+    /// Gets the path to the pid file of the running daemon, which keeps a second daemon from
+    /// starting for the same app dir
+    #[must_use]
+    pub fn daemon_pid_path(&self) -> std::path::PathBuf {
+        self.app_dir_path().join("daemon.pid")
+    }
+
+    /// Gets the path to the control socket of the running daemon, which tells other processes
+    /// whether it is responsive
+    #[must_use]
+    pub fn daemon_socket_path(&self) -> std::path::PathBuf {
+        self.app_dir_path().join("daemon.sock")
+    }
+
     /// Converts this config into a [`MaybeConfig`]
     #[must_use]
     pub fn into_maybe(self) -> MaybeConfig {
@@ -90,6 +661,21 @@ impl Config {
             app_dir: Some(self.app_dir),
             store_dir: Some(self.store_dir),
             tracking_list: Some(self.tracking_list),
+            backup_threads: Some(self.backup_threads),
+            quiet_hours: self.quiet_hours,
+            watcher: Some(self.watcher),
+            watch_events: Some(self.watch_events),
+            symlinks: Some(self.symlinks),
+            logging: Some(self.logging),
+            max_store_bytes: self.max_store_bytes,
+            min_free_bytes: self.min_free_bytes,
+            on_low_disk: Some(self.on_low_disk),
+            compression: Some(self.compression),
+            throttle: Some(self.throttle),
+            stores: Some(self.stores),
+            schedule: self.schedule,
+            schedule_jitter: Some(self.schedule_jitter),
+            hooks: Some(self.hooks),
         }
     }
 
@@ -109,11 +695,87 @@ impl Config {
         if let Some(tracking_list) = &other.tracking_list {
             new.tracking_list = tracking_list.clone();
         }
+        if let Some(backup_threads) = other.backup_threads {
+            new.backup_threads = backup_threads;
+        }
+        if let Some(quiet_hours) = other.quiet_hours {
+            new.quiet_hours = Some(quiet_hours);
+        }
+        if let Some(watcher) = other.watcher {
+            new.watcher = watcher;
+        }
+        if let Some(watch_events) = other.watch_events {
+            new.watch_events = watch_events;
+        }
+        if let Some(symlinks) = other.symlinks {
+            new.symlinks = symlinks;
+        }
+        if let Some(logging) = &other.logging {
+            new.logging = logging.clone();
+        }
+        if let Some(max_store_bytes) = other.max_store_bytes {
+            new.max_store_bytes = Some(max_store_bytes);
+        }
+        if let Some(min_free_bytes) = other.min_free_bytes {
+            new.min_free_bytes = Some(min_free_bytes);
+        }
+        if let Some(on_low_disk) = other.on_low_disk {
+            new.on_low_disk = on_low_disk;
+        }
+        if let Some(compression) = &other.compression {
+            new.compression = compression.clone();
+        }
+        if let Some(throttle) = other.throttle {
+            new.throttle = throttle;
+        }
+        if let Some(stores) = &other.stores {
+            new.stores.clone_from(stores);
+        }
+        if let Some(schedule) = other.schedule {
+            new.schedule = Some(schedule);
+        }
+        if let Some(schedule_jitter) = other.schedule_jitter {
+            new.schedule_jitter = schedule_jitter;
+        }
+        if let Some(hooks) = &other.hooks {
+            new.hooks.clone_from(hooks);
+        }
         new
     }
 
+    /// Gets every value of [`CONFIG_KEYS`](crate::CONFIG_KEYS) that differs in `other`, in the
+    /// order of the keys
+    #[must_use]
+    pub fn changes_to(&self, other: &Config) -> Vec<ConfigChange> {
+        layered::changes(self, other)
+    }
+
+    /// Takes over the values of `other` that can change while the daemon runs, see
+    /// [`RELOADABLE_KEYS`](crate::RELOADABLE_KEYS). Every other value is kept, the changes to them
+    /// are returned as rejected.
+    #[must_use]
+    pub fn reload_from(&self, other: &Config) -> ConfigReload {
+        let mut config = self.clone();
+        config.delay = other.delay;
+        config.logging = self.logging.clone().with_level(other.logging.level());
+        config.max_store_bytes = other.max_store_bytes;
+        config.compression = other.compression.clone();
+        config.throttle = other.throttle;
+        config.hooks.clone_from(&other.hooks);
+        let (applied, rejected) = self
+            .changes_to(other)
+            .into_iter()
+            .partition(|change| RELOADABLE_KEYS.contains(&change.key()));
+        ConfigReload {
+            config,
+            applied,
+            rejected,
+        }
+    }
+
     // TODO: This should be a serialiized list of files and loaded through serde instead of plaintext
-    /// Reads the tracking list file and returns a list of files/directories to track
+    /// Reads the tracking list file and returns a list of files/directories to track, without
+    /// the options of the entries (see [`Config::read_tracking_list`])
     ///
     /// ## Errors
     /// - Errors if the tracking list file cannot be opened or read
@@ -123,30 +785,352 @@ impl Config {
         let file = std::fs::File::open(self.tracking_list_path())?;
         let reader = std::io::BufReader::new(file);
         for line in reader.lines() {
-            files.push(line?);
+            let line = line?;
+            match line.split_once(TRACKED_OPTIONS_SEPARATOR) {
+                Some((path, _)) => files.push(path.to_string()),
+                None => files.push(line),
+            }
         }
         Ok(files)
     }
 
+    /// Reads the tracking list file with the options of each entry, blank lines are skipped
+    ///
+    /// ## Errors
+    /// - Errors if the tracking list file cannot be opened or read
+    /// - Errors if the options of an entry are invalid
+    pub fn read_tracking_list(&self) -> super::Result<Vec<TrackedPath>> {
+        let contents = std::fs::read_to_string(self.tracking_list_path())?;
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
     /// Initializing the application folder, creating the main directory if it does not exist,
-    /// the storage directory if it does not exist, and the tracking list file if it does not exist
+    /// the storage directory if it does not exist, and an empty tracking list file if it does
+    /// not exist. Missing parent directories are created as well.
     ///
     /// ## Errors
-    /// - Errors if any call to `std::fs::create_dir_all` or `std::fs::File::create` fails
+    /// - Errors if any of the directories or the tracking list file cannot be created
     pub fn init_app_structure(&self) -> super::Result {
-        use std::io::Write;
-        if !self.app_dir_path().exists() {
-            std::fs::create_dir_all(self.app_dir_path())?;
+        xstd::fs::ensure_dir(self.app_dir_path())?;
+        xstd::fs::ensure_dir(self.store_dir_path())?;
+        xstd::fs::touch_all(self.tracking_list_path())?;
+        Ok(())
+    }
+}
+
+/// Separates the path of a tracking list entry from its options
+const TRACKED_OPTIONS_SEPARATOR: char = '\t';
+
+/// An entry of the tracking list: a path, optionally followed by a tab and the options for the
+/// files at that path, its [`Throttle`], [`Policy`], [`EventKinds`] and [`BackupPriority`], e.g.
+/// `/var/log/app.log<TAB>min-interval=10m,settle=30s,on-delete=tombstone,events=modify,priority=low`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TrackedPath {
+    path: PathBuf,
+    throttle: Throttle,
+    policy: Policy,
+    events: Option<EventKinds>,
+    priority: BackupPriority,
+}
+
+impl TrackedPath {
+    /// Creates a new [`TrackedPath`] for `path` without options
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            throttle: Throttle::default(),
+            policy: Policy::default(),
+            events: None,
+            priority: BackupPriority::default(),
         }
-        if !self.store_dir_path().exists() {
-            std::fs::create_dir_all(self.store_dir_path())?;
+    }
+
+    /// Sets the limits on how often the files at this path are backed up
+    #[must_use]
+    pub fn with_throttle(self, throttle: Throttle) -> Self {
+        Self { throttle, ..self }
+    }
+
+    /// Sets what the daemon does about the files at this path
+    #[must_use]
+    pub fn with_policy(self, policy: Policy) -> Self {
+        Self { policy, ..self }
+    }
+
+    /// Sets the kinds of events reported for the files at this path
+    #[must_use]
+    pub fn with_events(self, events: EventKinds) -> Self {
+        Self {
+            events: Some(events),
+            ..self
         }
-        if !self.tracking_list_path().exists() {
-            let mut tracking_file = std::fs::File::create(self.tracking_list_path())?;
-            tracking_file.write_all(b"{}")?;
-            tracking_file.flush()?;
+    }
+
+    /// Sets the priority of the queued backups of the files at this path
+    #[must_use]
+    pub fn with_priority(self, priority: BackupPriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// Gets the tracked file or directory
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the limits of this entry, unset ones fall back to [`Config::throttle`]
+    #[must_use]
+    pub fn throttle(&self) -> Throttle {
+        self.throttle
+    }
+
+    /// Gets what the daemon does about the files at this path
+    #[must_use]
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// Gets the kinds of events reported for the files at this path, if not those of
+    /// [`Config::watch_events`]
+    #[must_use]
+    pub fn events(&self) -> Option<EventKinds> {
+        self.events
+    }
+
+    /// Gets the priority of the queued backups of the files at this path
+    #[must_use]
+    pub fn priority(&self) -> BackupPriority {
+        self.priority
+    }
+}
+
+impl FromStr for TrackedPath {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((path, options)) = s.split_once(TRACKED_OPTIONS_SEPARATOR) else {
+            return Ok(Self::new(s));
+        };
+        let key = |option: &String| {
+            option
+                .split_once('=')
+                .map_or(option.as_str(), |(key, _)| key)
+                .trim()
+                .to_string()
+        };
+        let (events, options): (Vec<_>, Vec<_>) = split_options(options)
+            .into_iter()
+            .partition(|option| key(option) == EVENTS_KEY);
+        let (priorities, options): (Vec<_>, Vec<_>) = options
+            .into_iter()
+            .partition(|option| key(option) == PRIORITY_KEY);
+        let (rules, limits): (Vec<_>, Vec<_>) = options
+            .into_iter()
+            .partition(|option| POLICY_KEYS.contains(&key(option).as_str()));
+        let invalid = |e: crate::Error| format!("invalid options for '{path}' - {e}");
+        let mut tracked = Self::new(path)
+            .with_throttle(limits.join(",").parse().map_err(invalid)?)
+            .with_policy(rules.join(",").parse().map_err(invalid)?);
+        for option in events {
+            let (_, kinds) = option.split_once('=').unwrap_or_default();
+            tracked = tracked.with_events(kinds.parse().map_err(invalid)?);
+        }
+        for option in priorities {
+            let (_, priority) = option.split_once('=').unwrap_or_default();
+            tracked = tracked.with_priority(priority.trim().parse().map_err(invalid)?);
         }
+        Ok(tracked)
+    }
+}
 
+impl fmt::Display for TrackedPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        let mut separator = TRACKED_OPTIONS_SEPARATOR;
+        if !self.throttle.is_empty() {
+            write!(f, "{separator}{}", self.throttle)?;
+            separator = ',';
+        }
+        if !self.policy.is_default() {
+            write!(f, "{separator}{}", self.policy)?;
+            separator = ',';
+        }
+        if let Some(events) = self.events {
+            write!(f, "{separator}{EVENTS_KEY}={events}")?;
+            separator = ',';
+        }
+        if self.priority != BackupPriority::default() {
+            write!(f, "{separator}{PRIORITY_KEY}={}", self.priority)?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_dirs() {
+        let config = Config::default();
+        assert!(config.store_dir_path().starts_with(config.app_dir_path()));
+        assert!(config.app_dir_path().ends_with(APP_DIR_NAME));
+        if let Some(data_dir) = xstd::dirs::data_dir() {
+            assert!(config.app_dir_path().is_absolute());
+            assert!(config.app_dir_path().starts_with(data_dir));
+        }
+        if let Some(config_dir) = xstd::dirs::config_dir() {
+            assert!(config.tracking_list_path().starts_with(config_dir));
+        }
+    }
+
+    #[test]
+    fn routes_to_named_stores() {
+        let config = Config::default().extend_with(&MaybeConfig::default().with_stores(vec![
+            StoreConfig::new("projects", "/home/me/projects"),
+            StoreConfig::new("website", "/home/me/projects/website").with_store_dir("/mnt/website"),
+        ]));
+        let name_for = |path: &str| config.store_for(Path::new(path)).map(StoreConfig::name);
+        assert_eq!(
+            name_for("/home/me/projects/website/index.html"),
+            Some("website")
+        );
+        assert_eq!(name_for("/home/me/projects/notes.md"), Some("projects"));
+        assert_eq!(name_for("/home/me/notes.md"), None);
+
+        let website = config.for_store("website").unwrap();
+        assert_eq!(website.store_dir(), "/mnt/website");
+        assert_eq!(
+            website.tracking_list_path(),
+            Path::new("/mnt/website/tracking_list")
+        );
+        assert!(website.stores().is_empty());
+        let err = config.for_store("blog").unwrap_err();
+        assert!(err.to_string().contains("projects, website"), "{err}");
+    }
+
+    #[test]
+    fn creates_app_structure() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let dir = |name: &str| root.path().join(name).to_string_lossy().into_owned();
+        let config = Config::default().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir("data"))
+                .with_store_dir(dir("data/store"))
+                .with_tracking_list(dir("config/storage/tracking_list.json")),
+        );
+        config.init_app_structure().unwrap();
+        assert!(config.store_dir_path().is_dir());
+        assert!(config.tracking_list_path().is_file());
+        assert!(config.read_tracking_list().unwrap().is_empty());
+
+        // an existing tracking list is left alone
+        std::fs::write(config.tracking_list_path(), "/plain/file\n").unwrap();
+        config.init_app_structure().unwrap();
+        assert_eq!(config.read_tracked_files().unwrap(), ["/plain/file"]);
+    }
+
+    #[test]
+    fn reads_tracking_list_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracking_list = dir.path().join("tracking_list");
+        std::fs::write(
+            &tracking_list,
+            "/plain/file\n\n/var/log/app.log\tmin-interval=10m,settle=30s\n",
+        )
+        .unwrap();
+        let config = Config::default().extend_with(
+            &MaybeConfig::default().with_tracking_list(tracking_list.to_str().unwrap()),
+        );
+
+        let throttle = Throttle::new()
+            .with_min_interval(std::time::Duration::from_secs(600))
+            .with_settle(std::time::Duration::from_secs(30));
+        let entries = config.read_tracking_list().unwrap();
+        assert_eq!(
+            entries,
+            [
+                TrackedPath::new("/plain/file"),
+                TrackedPath::new("/var/log/app.log").with_throttle(throttle),
+            ]
+        );
+        assert_eq!(
+            entries[1].to_string(),
+            "/var/log/app.log\tmin-interval=600s,settle=30s"
+        );
+        // the options are not part of the path
+        assert_eq!(
+            config.read_tracked_files().unwrap(),
+            ["/plain/file", "", "/var/log/app.log"]
+        );
+
+        std::fs::write(&tracking_list, "/var/log/app.log\tsettle=later").unwrap();
+        assert!(config.read_tracking_list().is_err());
+
+        let entry: TrackedPath = "/home/me/notes\ton-delete=tombstone,settle=5s,on-change=ignore"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            entry.policy(),
+            Policy::new()
+                .with_on_change(crate::OnChange::Ignore)
+                .with_on_delete(crate::OnDelete::Tombstone)
+        );
+        assert_eq!(
+            entry.throttle().settle(),
+            Some(std::time::Duration::from_secs(5))
+        );
+        assert_eq!(
+            entry.to_string(),
+            "/home/me/notes\tsettle=5s,on-change=ignore,on-delete=tombstone"
+        );
+        assert!("/home/me/notes\ton-delete=shred"
+            .parse::<TrackedPath>()
+            .is_err());
+
+        // the commas of a cron expression do not start a new option
+        let entry: TrackedPath = "/home/me/notes\ton-schedule=0,30 9-17 * * 1-5,settle=5s"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            entry.policy().on_schedule(),
+            Some("0,30 9-17 * * 1-5".parse().unwrap())
+        );
+        assert_eq!(entry.to_string().parse::<TrackedPath>().unwrap(), entry);
+
+        let entry: TrackedPath = "/home/me/notes\tevents=modify,create,settle=5s"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            entry.events(),
+            Some(EventKinds::CREATE | EventKinds::MODIFY)
+        );
+        assert_eq!(
+            entry.to_string(),
+            "/home/me/notes\tsettle=5s,events=create,modify"
+        );
+        assert_eq!(TrackedPath::new("/home/me/notes").events(), None);
+        assert!("/home/me/notes\tevents=read"
+            .parse::<TrackedPath>()
+            .is_err());
+
+        let entry: TrackedPath = "/etc/app\tpriority=high,events=modify,settle=5s"
+            .parse()
+            .unwrap();
+        assert_eq!(entry.priority(), BackupPriority::High);
+        assert_eq!(
+            entry.to_string(),
+            "/etc/app\tsettle=5s,events=modify,priority=high"
+        );
+        assert_eq!(
+            TrackedPath::new("/etc/app").priority(),
+            BackupPriority::Normal
+        );
+        assert!("/etc/app\tpriority=urgent".parse::<TrackedPath>().is_err());
+    }
+}
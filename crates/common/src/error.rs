@@ -4,7 +4,13 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::backtrace::{Backtrace, BacktraceStatus};
+
 /// Common error type for the `storage` crate/workspace
+///
+/// Errors can be wrapped with what was being done when they happened, see [`Error::context`] and
+/// [`ResultExt`]. The wrapped error is its [`source`](std::error::Error::source), and a backtrace
+/// is captured with the context if `RUST_BACKTRACE` (or `RUST_LIB_BACKTRACE`) is set.
 #[derive(Debug)]
 pub enum Error {
     /// Wrapper around [`io::Error`](std::io::Error)
@@ -15,8 +21,44 @@ pub enum Error {
     Notify(notify::Error),
     /// Wrapper around various errors produced during serialization and deserialization
     Serde(String),
+    /// A store object whose checksum does not match its contents, e.g. because of bit rot
+    Corrupted {
+        /// The path of the object, if known
+        path: Option<std::path::PathBuf>,
+        /// The checksum stored in the object
+        expected: u32,
+        /// The checksum of the contents actually read
+        actual: u32,
+    },
+    /// A restore that would overwrite a file which changed since its latest backup, so its
+    /// current contents are not in the store
+    Conflict {
+        /// The path of the changed file
+        path: std::path::PathBuf,
+        /// The version of its latest backup
+        latest: u32,
+    },
+    /// A write that was refused because it would leave less free space on the volume than
+    /// configured, see [`Config::min_free_bytes`](crate::Config::min_free_bytes)
+    DiskFull {
+        /// The directory being written to
+        path: std::path::PathBuf,
+        /// The bytes available on its volume
+        available: u64,
+        /// The bytes the write needed, including the space to keep free
+        required: u64,
+    },
     /// Other errors
     Other(String),
+    /// An error with what was being done when it happened, see [`Error::context`]
+    Context {
+        /// What was being done, e.g. `unable to read '/etc/hosts'`
+        message: String,
+        /// The error that happened
+        source: Box<Error>,
+        /// Where the context was added, only captured if enabled by the environment
+        backtrace: Box<Backtrace>,
+    },
 }
 
 impl From<notify::Error> for Error {
@@ -62,12 +104,212 @@ impl std::fmt::Display for Error {
             Self::Utf8(err) => write!(f, "utf-8 error - {err}"),
             Self::Notify(err) => write!(f, "notify error - {err}"),
             Self::Serde(err) => write!(f, "serde error - {err}"),
+            Self::Corrupted {
+                path,
+                expected,
+                actual,
+            } => {
+                write!(f, "corrupted object")?;
+                if let Some(path) = path {
+                    write!(f, " '{}'", path.display())?;
+                }
+                write!(f, " - checksum is {actual:08x}, expected {expected:08x}")
+            }
+            Self::Conflict { path, latest } => write!(
+                f,
+                "'{}' changed since its latest backup (version {latest})",
+                path.display()
+            ),
+            Self::DiskFull {
+                path,
+                available,
+                required,
+            } => write!(
+                f,
+                "not enough free space for '{}' - {available} bytes available, {required} needed",
+                path.display()
+            ),
             Self::Other(err) => write!(f, "other error - {err}"),
+            Self::Context {
+                message, source, ..
+            } => write!(f, "{message} - {source}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Utf8(err) => Some(err),
+            Self::Notify(err) => Some(err),
+            Self::Context { source, .. } => Some(source.as_ref()),
+            Self::Serde(_)
+            | Self::Corrupted { .. }
+            | Self::Conflict { .. }
+            | Self::DiskFull { .. }
+            | Self::Other(_) => None,
+        }
+    }
+}
+
+impl Error {
+    /// Gets a short, stable name for the kind of this error, e.g. `io`. Unlike the
+    /// [`Display`](std::fmt::Display) output this never contains paths or other user data.
+    #[must_use]
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "io",
+            Self::Utf8(_) => "utf8",
+            Self::Notify(_) => "notify",
+            Self::Serde(_) => "serde",
+            Self::Corrupted { .. } => "corrupted",
+            Self::Conflict { .. } => "conflict",
+            Self::DiskFull { .. } => "disk_full",
+            Self::Other(_) => "other",
+            Self::Context { source, .. } => source.category(),
+        }
+    }
+
+    /// Wraps this error with `message`, telling what was being done when it happened. A
+    /// backtrace is captured as well if `RUST_BACKTRACE` is set, see [`Error::backtrace`].
+    #[must_use]
+    pub fn context(self, message: impl Into<String>) -> Self {
+        Self::Context {
+            message: message.into(),
+            source: Box::new(self),
+            backtrace: Box::new(Backtrace::capture()),
+        }
+    }
+
+    /// Gets the error at the bottom of the contexts added by [`Error::context`]
+    #[must_use]
+    pub fn root_cause(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.root_cause(),
+            other => other,
+        }
+    }
+
+    /// Gets the [`ErrorKind`](std::io::ErrorKind) of an [`Error::Io`], also below any context,
+    /// e.g. to tell a missing file from other failures
+    #[must_use]
+    pub fn io_kind(&self) -> Option<std::io::ErrorKind> {
+        match self.root_cause() {
+            Self::Io(err) => Some(err.kind()),
+            _ => None,
+        }
+    }
+
+    /// Gets the outermost backtrace captured by [`Error::context`], if capturing was enabled
+    #[must_use]
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        match self {
+            Self::Context {
+                backtrace, source, ..
+            } => match backtrace.status() {
+                BacktraceStatus::Captured => Some(backtrace),
+                _ => source.backtrace(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Names the object a [`Error::Corrupted`] was read from, if it is not named yet. Any other
+    /// error is returned as it is.
+    #[must_use]
+    pub fn with_path(self, object: impl AsRef<std::path::Path>) -> Self {
+        match self {
+            Self::Corrupted {
+                path: None,
+                expected,
+                actual,
+            } => Self::Corrupted {
+                path: Some(object.as_ref().to_path_buf()),
+                expected,
+                actual,
+            },
+            Self::Context {
+                message,
+                source,
+                backtrace,
+            } => Self::Context {
+                message,
+                source: Box::new(source.with_path(object)),
+                backtrace,
+            },
+            other => other,
         }
     }
 }
 
-impl std::error::Error for Error {}
+/// Adds context to the errors of results, see [`Error::context`]
+pub trait ResultExt<T> {
+    /// Wraps the error, if any, with `message`
+    ///
+    /// ## Errors
+    /// - Returns the error of this result with the context added
+    fn context(self, message: impl Into<String>) -> Result<T>;
+
+    /// Wraps the error, if any, with the message returned by `message`, which is only called
+    /// on failure
+    ///
+    /// ## Errors
+    /// - Returns the error of this result with the context added
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+    fn context(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|err| err.into().context(message))
+    }
+
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T> {
+        self.map_err(|err| err.into().context(message()))
+    }
+}
 
 /// Result type used throughout the `storage` workspace
 pub type Result<T = (), E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn chains_context() {
+        let read = std::fs::read("/does/not/exist").context("unable to read '/does/not/exist'");
+        let err = read.unwrap_err().context("unable to back up");
+        assert!(err
+            .to_string()
+            .starts_with("unable to back up - unable to read '/does/not/exist' - io error"));
+        assert_eq!(err.category(), "io");
+        assert_eq!(err.io_kind(), Some(std::io::ErrorKind::NotFound));
+
+        let mut chain = vec![];
+        let mut source: Option<&dyn std::error::Error> = Some(&err);
+        while let Some(err) = source {
+            chain.push(err.to_string());
+            source = err.source();
+        }
+        // the context, the context, the io error and the error of the OS
+        assert_eq!(chain.len(), 4);
+        assert!(matches!(err.root_cause(), Error::Io(_)));
+
+        let corrupted = Error::Corrupted {
+            path: None,
+            expected: 1,
+            actual: 2,
+        };
+        let named = corrupted.context("unable to decode").with_path("a.bak");
+        assert!(named.to_string().contains("'a.bak'"), "{named}");
+        assert_eq!(
+            Ok::<_, Error>(1)
+                .with_context(|| -> String { unreachable!() })
+                .unwrap(),
+            1
+        );
+    }
+}
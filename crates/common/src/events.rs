@@ -0,0 +1,110 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The kinds of changes a file watcher reports, configured globally (see
+//! [`Config::watch_events`](crate::Config::watch_events)) and per entry of the tracking list
+//! (see [`TrackedPath`](crate::TrackedPath)).
+
+use std::{fmt, str::FromStr};
+
+use crate::Error;
+
+/// The key of the tracking list option holding the [`EventKinds`] of an entry
+pub(crate) const EVENTS_KEY: &str = "events";
+
+xstd::bitflags! {
+    /// A set of kinds of changes to watched paths. Watchers drop the events of kinds that are
+    /// not in the set before they are reported, see [`EventKinds::DEFAULT`].
+    pub struct EventKinds: u8 {
+        /// A file was read or opened, without changing it
+        const ACCESS = 1 << 0;
+        /// A file or directory was created
+        const CREATE = 1 << 1;
+        /// The contents of a file were modified
+        const MODIFY = 1 << 2;
+        /// The metadata (permissions, timestamps, ownership, ...) of a path changed
+        const METADATA = 1 << 3;
+        /// A file or directory was renamed (or moved)
+        const RENAME = 1 << 4;
+        /// A file or directory was removed
+        const REMOVE = 1 << 5;
+    }
+}
+
+impl EventKinds {
+    /// Every kind but [`EventKinds::ACCESS`], since reading a file does not require a new backup
+    pub const DEFAULT: Self = Self::from_bits_truncate(!Self::ACCESS.bits());
+
+    /// Gets the kinds in the set by their lower case names, in declaration order
+    fn names(self) -> impl Iterator<Item = String> {
+        Self::FLAGS
+            .iter()
+            .filter(move |(_, kind)| self.contains(*kind))
+            .map(|(name, _)| name.to_lowercase())
+    }
+}
+
+impl FromStr for EventKinds {
+    type Err = Error;
+
+    /// Parses comma separated kinds, e.g. `create,modify,remove`. The kinds are `access`,
+    /// `create`, `modify`, `metadata`, `rename` and `remove`, `all` is every kind and `none`
+    /// no kind at all.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut kinds = Self::empty();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let kind = match part {
+                "all" => Self::all(),
+                "none" => Self::empty(),
+                name => Self::FLAGS
+                    .iter()
+                    .find(|(flag, _)| flag.eq_ignore_ascii_case(name))
+                    .map(|(_, kind)| *kind)
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown event kind '{name}', expected 'access', 'create', \
+                             'modify', 'metadata', 'rename' or 'remove'"
+                        )
+                    })?,
+            };
+            kinds.insert(kind);
+        }
+        Ok(kinds)
+    }
+}
+
+impl fmt::Display for EventKinds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+        f.write_str(&self.names().collect::<Vec<_>>().join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_kinds() {
+        let kinds: EventKinds = "modify, create".parse().unwrap();
+        assert_eq!(kinds, EventKinds::CREATE | EventKinds::MODIFY);
+        assert_eq!(kinds.to_string(), "create,modify");
+        assert_eq!(
+            EventKinds::DEFAULT.to_string(),
+            "create,modify,metadata,rename,remove"
+        );
+        assert!(!EventKinds::DEFAULT.contains(EventKinds::ACCESS));
+        assert_eq!("all".parse::<EventKinds>().unwrap(), EventKinds::all());
+        assert_eq!("none".parse::<EventKinds>().unwrap(), EventKinds::empty());
+        assert_eq!(EventKinds::empty().to_string(), "none");
+        assert!("written".parse::<EventKinds>().is_err());
+        for kinds in [EventKinds::DEFAULT, EventKinds::all(), EventKinds::empty()] {
+            assert_eq!(kinds.to_string().parse::<EventKinds>().unwrap(), kinds);
+        }
+    }
+}
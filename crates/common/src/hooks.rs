@@ -0,0 +1,274 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! User defined hooks fired when something happens to the store, e.g. to send a notification
+//! after every backup. A hook either runs a shell command or posts the event as JSON to a URL.
+//!
+//! The hooks are configured as a JSON object by name, e.g. in the config file
+//!
+//! ```json
+//! {
+//!     "hooks": {
+//!         "notify": { "on": ["backup-created"], "command": "notify-send \"$STORAGE_HOOK_PATH\"" },
+//!         "dashboard": { "on": ["prune-executed"], "url": "http://localhost:8080/storage", "timeout": "5s" }
+//!     }
+//! }
+//! ```
+
+use std::{collections::BTreeMap, fmt, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{parse_duration, Error, Result};
+
+/// How long a hook may run before it is stopped, unless it sets its own timeout
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened to the store which hooks can be fired on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HookEvent {
+    /// A file was backed up into the store
+    BackupCreated,
+    /// A file was restored from one of its backups
+    RestoreCompleted,
+    /// A retention policy deleted backups from the store
+    PruneExecuted,
+}
+
+impl HookEvent {
+    /// Gets the name of the event, as it is written in the config
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::BackupCreated => "backup-created",
+            Self::RestoreCompleted => "restore-completed",
+            Self::PruneExecuted => "prune-executed",
+        }
+    }
+}
+
+impl FromStr for HookEvent {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "backup-created" => Ok(Self::BackupCreated),
+            "restore-completed" => Ok(Self::RestoreCompleted),
+            "prune-executed" => Ok(Self::PruneExecuted),
+            other => Err(format!(
+                "unknown hook event '{other}', expected 'backup-created', 'restore-completed' \
+                 or 'prune-executed'"
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a hook does when it is fired
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HookAction {
+    /// Run the command with the shell of the platform, the event is described by `STORAGE_HOOK_*`
+    /// environment variables
+    Command(String),
+    /// Post the event as JSON to the (`http://`) URL
+    Webhook(String),
+}
+
+/// A named hook fired on some of the [`HookEvent`]s
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HookConfig {
+    name: String,
+    events: Vec<HookEvent>,
+    action: HookAction,
+    timeout: Duration,
+}
+
+impl HookConfig {
+    /// Creates a new [`HookConfig`] named `name` doing `action` on each of `events`
+    #[must_use]
+    pub fn new(name: impl Into<String>, events: Vec<HookEvent>, action: HookAction) -> Self {
+        Self {
+            name: name.into(),
+            events,
+            action,
+            timeout: DEFAULT_HOOK_TIMEOUT,
+        }
+    }
+
+    /// Sets how long the hook may run before it is stopped
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Gets the name of the hook
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the events the hook is fired on
+    #[must_use]
+    pub fn events(&self) -> &[HookEvent] {
+        &self.events
+    }
+
+    /// Gets what the hook does when it is fired
+    #[must_use]
+    pub fn action(&self) -> &HookAction {
+        &self.action
+    }
+
+    /// Gets how long the hook may run before it is stopped, [`DEFAULT_HOOK_TIMEOUT`] by default
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns true if the hook is fired on `event`
+    #[must_use]
+    pub fn fires_on(&self, event: HookEvent) -> bool {
+        self.events.contains(&event)
+    }
+}
+
+/// A [`HookConfig`] as it is written in the JSON object of the hooks, the name is the key
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct HookEntry {
+    on: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout: Option<String>,
+}
+
+/// Parses the JSON object of the hooks, ordered by name
+pub(crate) fn parse_hooks(json: &str) -> Result<Vec<HookConfig>> {
+    let entries: BTreeMap<String, HookEntry> =
+        serde_json::from_str(json).map_err(|e| format!("invalid hooks - {e}"))?;
+    entries
+        .into_iter()
+        .map(|(name, entry)| {
+            let invalid = |e: &dyn fmt::Display| format!("invalid hook '{name}' - {e}");
+            let events = entry
+                .on
+                .iter()
+                .map(|event| event.parse())
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| invalid(&e))?;
+            if events.is_empty() {
+                return Err(invalid(&"it is not fired on any event").into());
+            }
+            let action = match (entry.command, entry.url) {
+                (Some(command), None) => HookAction::Command(command),
+                (None, Some(url)) if url.starts_with("http://") => HookAction::Webhook(url),
+                (None, Some(url)) => {
+                    return Err(invalid(&format_args!(
+                        "unsupported url '{url}', only http:// urls can be posted to, run a \
+                         command like curl for others"
+                    ))
+                    .into())
+                }
+                _ => return Err(invalid(&"expected either a 'command' or a 'url'").into()),
+            };
+            let timeout = match entry.timeout {
+                Some(timeout) => parse_duration(&timeout).map_err(|e| invalid(&e))?,
+                None => DEFAULT_HOOK_TIMEOUT,
+            };
+            Ok(HookConfig {
+                name,
+                events,
+                action,
+                timeout,
+            })
+        })
+        .collect()
+}
+
+/// Formats `hooks` as the JSON object [`parse_hooks`] reads
+pub(crate) fn format_hooks(hooks: &[HookConfig]) -> String {
+    let entries = hooks
+        .iter()
+        .map(|hook| {
+            let (command, url) = match &hook.action {
+                HookAction::Command(command) => (Some(command.clone()), None),
+                HookAction::Webhook(url) => (None, Some(url.clone())),
+            };
+            let entry = HookEntry {
+                on: hook.events.iter().map(ToString::to_string).collect(),
+                command,
+                url,
+                timeout: (hook.timeout != DEFAULT_HOOK_TIMEOUT).then(|| {
+                    if hook.timeout.subsec_nanos() == 0 {
+                        format!("{}s", hook.timeout.as_secs())
+                    } else {
+                        format!("{}ms", hook.timeout.as_millis())
+                    }
+                }),
+            };
+            (hook.name.as_str(), entry)
+        })
+        .collect::<BTreeMap<_, _>>();
+    serde_json::to_string(&entries).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hooks() {
+        let hooks = parse_hooks(
+            r#"{
+                "notify": { "on": ["backup-created", "restore-completed"], "command": "true" },
+                "dashboard": { "on": ["prune-executed"], "url": "http://localhost/x", "timeout": "5s" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            hooks,
+            [
+                HookConfig::new(
+                    "dashboard",
+                    vec![HookEvent::PruneExecuted],
+                    HookAction::Webhook("http://localhost/x".into())
+                )
+                .with_timeout(Duration::from_secs(5)),
+                HookConfig::new(
+                    "notify",
+                    vec![HookEvent::BackupCreated, HookEvent::RestoreCompleted],
+                    HookAction::Command("true".into())
+                ),
+            ]
+        );
+        assert!(hooks[1].fires_on(HookEvent::RestoreCompleted));
+        assert!(!hooks[1].fires_on(HookEvent::PruneExecuted));
+        assert_eq!(hooks[1].timeout(), DEFAULT_HOOK_TIMEOUT);
+        assert_eq!(parse_hooks(&format_hooks(&hooks)).unwrap(), hooks);
+        let quick = [hooks[1].clone().with_timeout(Duration::from_millis(500))];
+        assert_eq!(parse_hooks(&format_hooks(&quick)).unwrap(), quick);
+
+        assert!(parse_hooks(r#"{ "a": { "on": ["backup-created"] } }"#).is_err());
+        assert!(parse_hooks(r#"{ "a": { "on": [], "command": "true" } }"#).is_err());
+        assert!(parse_hooks(r#"{ "a": { "on": ["backed-up"], "command": "true" } }"#).is_err());
+        assert!(
+            parse_hooks(r#"{ "a": { "on": ["backup-created"], "url": "https://x" } }"#).is_err()
+        );
+        assert!(parse_hooks(
+            r#"{ "a": { "on": ["backup-created"], "command": "true", "url": "http://x" } }"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,621 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Building a [`Config`] from layered sources: the defaults, then the config file, then
+//! environment variables, then command line flags. Every source sets values by key, see
+//! [`CONFIG_KEYS`], and the [`ConfigBuilder`] remembers which source each value came from.
+//!
+//! The config file is a flat JSON object, e.g.
+//!
+//! ```json
+//! { "delay": 500, "store_dir": "/mnt/backup/store", "quiet_hours": "22:00-06:30" }
+//! ```
+//!
+//! and the environment variable of a key is its name in upper case prefixed with `STORAGE_`,
+//! e.g. `STORAGE_STORE_DIR`. The only values that are not a string, number or boolean are the
+//! objects of the named `stores` (see [`StoreConfig`](crate::StoreConfig)) and `hooks` (see
+//! [`HookConfig`](crate::HookConfig)), their variables hold the objects as JSON.
+//!
+//! A running daemon can pick up edits to the config file, see [`ConfigBuilder::reload`]. Only the
+//! [`RELOADABLE_KEYS`] take effect that way, the others need a restart.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::APP_DIR_NAME,
+    hooks::{format_hooks, parse_hooks},
+    parse_duration,
+    stores::{format_stores, parse_stores},
+    Config, MaybeConfig, Result,
+};
+
+/// The keys every source can set, in the order they are shown
+pub const CONFIG_KEYS: &[&str] = &[
+    "delay",
+    "app_dir",
+    "store_dir",
+    "tracking_list",
+    "backup_threads",
+    "quiet_hours",
+    "watcher",
+    "watch_events",
+    "symlinks",
+    "log_level",
+    "log_file",
+    "log_json",
+    "max_store_bytes",
+    "min_free_bytes",
+    "on_low_disk",
+    "compression_quality",
+    "compression_buffer_size",
+    "compression_window",
+    "compression_dictionary_bytes",
+    "throttle",
+    "stores",
+    "schedule",
+    "schedule_jitter",
+    "hooks",
+];
+
+/// The keys whose new value a running daemon applies right away, changes to the other keys need a
+/// restart, see [`Config::reload_from`]
+pub const RELOADABLE_KEYS: &[&str] = &[
+    "delay",
+    "log_level",
+    "max_store_bytes",
+    "compression_quality",
+    "compression_buffer_size",
+    "compression_window",
+    "compression_dictionary_bytes",
+    "throttle",
+    "hooks",
+];
+
+/// The prefix of the environment variables setting config values
+pub const ENV_PREFIX: &str = "STORAGE_";
+
+/// The environment variable that replaces the path of the config file
+pub const CONFIG_FILE_ENV: &str = "STORAGE_CONFIG";
+
+/// Where a config value came from, later sources override earlier ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum ConfigSource {
+    /// The built-in default
+    #[default]
+    Default,
+    /// The config file, see [`ConfigBuilder::with_file`]
+    File,
+    /// An environment variable, see [`ConfigBuilder::with_env`]
+    Env,
+    /// A command line flag
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::File => f.write_str("file"),
+            Self::Env => f.write_str("env"),
+            Self::Cli => f.write_str("cli"),
+        }
+    }
+}
+
+/// Builds a [`Config`] from layered sources, recording which one each value came from
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+    origins: BTreeMap<&'static str, ConfigSource>,
+    file: Option<PathBuf>,
+    /// The values set by the sources after the config file, replayed by [`ConfigBuilder::reload`]
+    overrides: Vec<(String, String, ConfigSource)>,
+}
+
+impl ConfigBuilder {
+    /// Creates a new [`ConfigBuilder`] starting from the default config
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the path of the config file: `$STORAGE_CONFIG` if set, otherwise `config.json` in
+    /// the config directory of the platform, next to the default tracking list
+    #[must_use]
+    pub fn default_file_path() -> PathBuf {
+        if let Some(path) = std::env::var_os(CONFIG_FILE_ENV) {
+            return PathBuf::from(path);
+        }
+        xstd::dirs::config_dir()
+            .unwrap_or_default()
+            .join(APP_DIR_NAME)
+            .join("config.json")
+    }
+
+    /// Applies the values of the config file at `path`, which is allowed to not exist
+    ///
+    /// ## Errors
+    /// - Errors if the file cannot be read or is not a JSON object
+    /// - Errors if a key is unknown or a value is invalid
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => self.with_file_contents(path, &contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.file = Some(path);
+                Ok(self)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Applies the values of `contents` as if they were read from the config file at `path`, e.g.
+    /// to check an edited file before saving it
+    ///
+    /// ## Errors
+    /// - Errors if `contents` is not a JSON object
+    /// - Errors if a key is unknown or a value is invalid
+    pub fn with_file_contents(mut self, path: impl Into<PathBuf>, contents: &str) -> Result<Self> {
+        let path = path.into();
+        let values: BTreeMap<String, serde_json::Value> = serde_json::from_str(contents)
+            .map_err(|e| format!("invalid config file '{}' - {e}", path.display()))?;
+        for (key, value) in values {
+            let value = match value {
+                serde_json::Value::Null => continue,
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Bool(_) | serde_json::Value::Number(_) => value.to_string(),
+                serde_json::Value::Object(_) if key == "stores" || key == "hooks" => {
+                    value.to_string()
+                }
+                serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+                    return Err(format!(
+                        "invalid config file '{}' - '{key}' must be a string, number or boolean",
+                        path.display()
+                    )
+                    .into())
+                }
+            };
+            let overrides = self
+                .overrides_for(&key, &value)
+                .map_err(|e| format!("invalid config file '{}' - {e}", path.display()))?;
+            self.apply(&key, &overrides, ConfigSource::File);
+        }
+        self.file = Some(path);
+        Ok(self)
+    }
+
+    /// Applies the values of the `STORAGE_*` environment variables
+    ///
+    /// ## Errors
+    /// - Errors if a value is invalid
+    pub fn with_env(self) -> Result<Self> {
+        self.with_env_from(|name| std::env::var(name).ok())
+    }
+
+    /// Applies the values of the `STORAGE_*` variables returned by `lookup`
+    ///
+    /// ## Errors
+    /// - Errors if a value is invalid
+    pub fn with_env_from(mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        for key in CONFIG_KEYS {
+            let name = format!("{ENV_PREFIX}{}", key.to_uppercase());
+            if let Some(value) = lookup(&name) {
+                let overrides = self
+                    .overrides_for(key, &value)
+                    .map_err(|e| format!("invalid {name} - {e}"))?;
+                self.apply(key, &overrides, ConfigSource::Env);
+                self.overrides
+                    .push(((*key).to_string(), value, ConfigSource::Env));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Sets the value of `key`, parsed from `value`, and records that it came from `source`
+    ///
+    /// ## Errors
+    /// - Errors if `key` is not one of [`CONFIG_KEYS`]
+    /// - Errors if `value` is invalid for `key`
+    pub fn set(&mut self, key: &str, value: &str, source: ConfigSource) -> Result {
+        let overrides = self.overrides_for(key, value)?;
+        self.apply(key, &overrides, source);
+        if source > ConfigSource::File {
+            self.overrides
+                .push((key.to_string(), value.to_string(), source));
+        }
+        Ok(())
+    }
+
+    /// Builds the config again from the current contents of the config file, keeping the values
+    /// set by the environment and the command line on top of it. The file is applied first, even
+    /// if it was applied after other sources here.
+    ///
+    /// ## Errors
+    /// - Errors if the config file cannot be read or holds an invalid value
+    pub fn reload(&self) -> Result<Self> {
+        let mut builder = match &self.file {
+            Some(file) => Self::new().with_file(file)?,
+            None => Self::new(),
+        };
+        for (key, value, source) in &self.overrides {
+            builder.set(key, value, *source)?;
+        }
+        Ok(builder)
+    }
+
+    /// Parses `value` for `key` into the overrides that set it on the config built so far
+    fn overrides_for(&self, key: &str, value: &str) -> std::result::Result<MaybeConfig, String> {
+        let value = value.trim();
+        let invalid = |e: &dyn fmt::Display| format!("invalid value '{value}' for '{key}' - {e}");
+        let number = |value: &str| value.parse::<u64>().map_err(|e| invalid(&e));
+        let overrides = MaybeConfig::default();
+        let overrides = match key {
+            "delay" => overrides.with_delay(number(value)?),
+            "app_dir" => overrides.with_app_dir(value),
+            "store_dir" => overrides.with_store_dir(value),
+            "tracking_list" => overrides.with_tracking_list(value),
+            "backup_threads" => {
+                overrides.with_backup_threads(value.parse().map_err(|e| invalid(&e))?)
+            }
+            "quiet_hours" => overrides.with_quiet_hours(value.parse().map_err(|e| invalid(&e))?),
+            "watcher" => overrides.with_watcher(value.parse().map_err(|e| invalid(&e))?),
+            "watch_events" => overrides.with_watch_events(value.parse().map_err(|e| invalid(&e))?),
+            "symlinks" => overrides.with_symlinks(value.parse().map_err(|e| invalid(&e))?),
+            "log_level" => overrides.with_logging(
+                self.config
+                    .logging()
+                    .clone()
+                    .with_level(value.parse().map_err(|e| invalid(&e))?),
+            ),
+            "log_file" => overrides.with_logging(self.config.logging().clone().with_file(value)),
+            "log_json" => overrides.with_logging(
+                self.config
+                    .logging()
+                    .clone()
+                    .with_json(value.parse().map_err(|e| invalid(&e))?),
+            ),
+            "max_store_bytes" => overrides.with_max_store_bytes(number(value)?),
+            "min_free_bytes" => overrides.with_min_free_bytes(number(value)?),
+            "on_low_disk" => overrides.with_on_low_disk(value.parse().map_err(|e| invalid(&e))?),
+            "compression_quality" => overrides.with_compression(
+                self.config
+                    .compression()
+                    .clone()
+                    .with_quality(value.parse().map_err(|e| invalid(&e))?),
+            ),
+            "compression_buffer_size" => overrides.with_compression(
+                self.config
+                    .compression()
+                    .clone()
+                    .with_buffer_size(value.parse().map_err(|e| invalid(&e))?),
+            ),
+            "compression_window" => overrides.with_compression(
+                self.config
+                    .compression()
+                    .clone()
+                    .with_window(value.parse().map_err(|e| invalid(&e))?),
+            ),
+            "compression_dictionary_bytes" => overrides.with_compression(
+                self.config
+                    .compression()
+                    .clone()
+                    .with_dictionary_bytes(number(value)?),
+            ),
+            "throttle" => overrides.with_throttle(value.parse().map_err(|e| invalid(&e))?),
+            "stores" => overrides.with_stores(parse_stores(value).map_err(|e| e.to_string())?),
+            "schedule" => overrides.with_schedule(value.parse().map_err(|e| invalid(&e))?),
+            "schedule_jitter" => {
+                overrides.with_schedule_jitter(parse_duration(value).map_err(|e| invalid(&e))?)
+            }
+            "hooks" => overrides.with_hooks(parse_hooks(value).map_err(|e| e.to_string())?),
+            other => return Err(format!("unknown config key '{other}'")),
+        };
+        Ok(overrides)
+    }
+
+    fn apply(&mut self, key: &str, overrides: &MaybeConfig, source: ConfigSource) {
+        self.config = self.config.extend_with(overrides);
+        if let Some(key) = CONFIG_KEYS.iter().find(|known| **known == key) {
+            self.origins.insert(key, source);
+        }
+    }
+
+    /// Gets the config built so far
+    #[must_use]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Gets the config file that was applied (or did not exist), if any
+    #[must_use]
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Gets the source the value of `key` came from
+    #[must_use]
+    pub fn origin(&self, key: &str) -> ConfigSource {
+        self.origins.get(key).copied().unwrap_or_default()
+    }
+
+    /// Gets every key with its current value and the source it came from, see [`CONFIG_KEYS`]
+    #[must_use]
+    pub fn entries(&self) -> Vec<(&'static str, String, ConfigSource)> {
+        CONFIG_KEYS
+            .iter()
+            .map(|key| (*key, value_of(&self.config, key), self.origin(key)))
+            .collect()
+    }
+
+    /// Finishes building, returning the config
+    #[must_use]
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+/// A config value that differs between two configs, see [`Config::reload_from`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    key: &'static str,
+    old: String,
+    new: String,
+}
+
+impl ConfigChange {
+    /// Gets the key that changed, one of [`CONFIG_KEYS`]
+    #[must_use]
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+
+    /// Gets the previous value, formatted like a source would set it
+    #[must_use]
+    pub fn before(&self) -> &str {
+        &self.old
+    }
+
+    /// Gets the new value, formatted like a source would set it
+    #[must_use]
+    pub fn after(&self) -> &str {
+        &self.new
+    }
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.key, self.old, self.new)
+    }
+}
+
+/// The outcome of [`Config::reload_from`]: the config to run with from now on, and which of the
+/// changed values it took over
+#[derive(Debug, Clone)]
+pub struct ConfigReload {
+    pub(crate) config: Config,
+    pub(crate) applied: Vec<ConfigChange>,
+    pub(crate) rejected: Vec<ConfigChange>,
+}
+
+impl ConfigReload {
+    /// Gets the config with the changes of [`ConfigReload::applied`]
+    #[must_use]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Gets the changes to [`RELOADABLE_KEYS`], which are part of [`ConfigReload::config`]
+    #[must_use]
+    pub fn applied(&self) -> &[ConfigChange] {
+        &self.applied
+    }
+
+    /// Gets the changes that need a restart, [`ConfigReload::config`] keeps the previous values
+    #[must_use]
+    pub fn rejected(&self) -> &[ConfigChange] {
+        &self.rejected
+    }
+
+    /// Returns true if no value changed
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.rejected.is_empty()
+    }
+
+    /// Checks whether the value of `key` changed and was applied
+    #[must_use]
+    pub fn applied_key(&self, key: &str) -> bool {
+        self.applied.iter().any(|change| change.key == key)
+    }
+
+    /// Finishes the reload, returning the config
+    #[must_use]
+    pub fn into_config(self) -> Config {
+        self.config
+    }
+}
+
+/// Gets the values of [`CONFIG_KEYS`] that differ between `old` and `new`
+pub(crate) fn changes(old: &Config, new: &Config) -> Vec<ConfigChange> {
+    CONFIG_KEYS
+        .iter()
+        .filter_map(|key| {
+            let (old, new) = (value_of(old, key), value_of(new, key));
+            (old != new).then_some(ConfigChange { key, old, new })
+        })
+        .collect()
+}
+
+/// Formats the value of `key` like a source would set it, `-` if it is not set
+fn value_of(config: &Config, key: &str) -> String {
+    let unset = || String::from("-");
+    match key {
+        "delay" => config.delay().to_string(),
+        "app_dir" => config.app_dir().to_string(),
+        "store_dir" => config.store_dir().to_string(),
+        "tracking_list" => config.tracking_list().to_string(),
+        "backup_threads" => config.backup_threads().to_string(),
+        "quiet_hours" => config
+            .quiet_hours()
+            .map_or_else(unset, |hours| hours.to_string()),
+        "watcher" => config.watcher().to_string(),
+        "watch_events" => config.watch_events().to_string(),
+        "symlinks" => config.symlinks().to_string(),
+        "log_level" => config.logging().level().to_string(),
+        "log_file" => config.logging().file().map_or_else(unset, str::to_string),
+        "log_json" => config.logging().json().to_string(),
+        "max_store_bytes" => config
+            .max_store_bytes()
+            .map_or_else(unset, |bytes| bytes.to_string()),
+        "min_free_bytes" => config
+            .min_free_bytes()
+            .map_or_else(unset, |bytes| bytes.to_string()),
+        "on_low_disk" => config.on_low_disk().to_string(),
+        "compression_quality" => config.compression().quality().to_string(),
+        "compression_buffer_size" => config.compression().buffer_size().to_string(),
+        "compression_window" => config.compression().window().to_string(),
+        "compression_dictionary_bytes" => config.compression().dictionary_bytes().to_string(),
+        "throttle" if config.throttle().is_empty() => unset(),
+        "throttle" => config.throttle().to_string(),
+        "stores" if config.stores().is_empty() => unset(),
+        "stores" => format_stores(config.stores()),
+        "schedule" => config
+            .schedule()
+            .map_or_else(unset, |schedule| schedule.to_string()),
+        "schedule_jitter" => format!("{}s", config.schedule_jitter().as_secs()),
+        "hooks" if config.hooks().is_empty() => unset(),
+        "hooks" => format_hooks(config.hooks()),
+        _ => unset(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LowDiskAction;
+
+    #[test]
+    fn layers_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.json");
+        std::fs::write(
+            &file,
+            r#"{
+                "delay": 500, "store_dir": "/from/file", "log_json": true, "quiet_hours": null,
+                "stores": { "site": { "root": "/srv/site" } }, "compression_window": 20,
+                "min_free_bytes": 4096, "on_low_disk": "fail",
+                "hooks": { "notify": { "on": ["backup-created"], "command": "true" } }
+            }"#,
+        )
+        .unwrap();
+
+        let mut builder = ConfigBuilder::new()
+            .with_file(&file)
+            .unwrap()
+            .with_env_from(|name| (name == "STORAGE_DELAY").then(|| "250".to_string()))
+            .unwrap();
+        builder
+            .set("log_level", "debug", ConfigSource::Cli)
+            .unwrap();
+        builder
+            .set("compression_buffer_size", "16384", ConfigSource::Cli)
+            .unwrap();
+        builder
+            .set("compression_dictionary_bytes", "0", ConfigSource::Cli)
+            .unwrap();
+        assert_eq!(builder.file(), Some(file.as_path()));
+        assert_eq!(builder.origin("delay"), ConfigSource::Env);
+        assert_eq!(builder.origin("store_dir"), ConfigSource::File);
+        assert_eq!(builder.origin("log_level"), ConfigSource::Cli);
+        assert_eq!(builder.origin("watcher"), ConfigSource::Default);
+
+        let entries = builder.entries();
+        assert_eq!(entries.len(), CONFIG_KEYS.len());
+        assert!(entries.contains(&("quiet_hours", "-".to_string(), ConfigSource::Default)));
+        let config = builder.build();
+        assert_eq!(config.delay(), 250);
+        assert_eq!(config.store_dir(), "/from/file");
+        assert_eq!(config.stores()[0].name(), "site");
+        assert_eq!(config.hooks()[0].name(), "notify");
+        // setting the level keeps the other logging values of earlier layers
+        assert!(config.logging().json());
+        assert_eq!(config.compression().window(), 20);
+        assert_eq!(config.compression().buffer_size(), 16384);
+        assert_eq!(config.compression().dictionary_bytes(), 0);
+        assert_eq!(config.min_free_bytes(), Some(4096));
+        assert_eq!(config.on_low_disk(), LowDiskAction::Fail);
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.json");
+        assert!(ConfigBuilder::new()
+            .with_file(dir.path().join("missing.json"))
+            .is_ok());
+        std::fs::write(&file, r#"{ "dealy": 500 }"#).unwrap();
+        let err = ConfigBuilder::new().with_file(&file).unwrap_err();
+        assert!(
+            err.to_string().contains("unknown config key 'dealy'"),
+            "{err}"
+        );
+        std::fs::write(&file, r#"{ "delay": [1] }"#).unwrap();
+        assert!(ConfigBuilder::new().with_file(&file).is_err());
+
+        let err = ConfigBuilder::new()
+            .with_env_from(|name| (name == "STORAGE_WATCHER").then(|| "inotify".to_string()))
+            .unwrap_err();
+        assert!(err.to_string().contains("STORAGE_WATCHER"), "{err}");
+        let err = ConfigBuilder::new()
+            .with_env_from(|name| (name == "STORAGE_ON_LOW_DISK").then(|| "panic".to_string()))
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown low disk action"), "{err}");
+    }
+
+    #[test]
+    fn reloads_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.json");
+        std::fs::write(&file, r#"{ "delay": 500, "store_dir": "/store" }"#).unwrap();
+        let mut builder = ConfigBuilder::new()
+            .with_file(&file)
+            .unwrap()
+            .with_env_from(|name| (name == "STORAGE_MAX_STORE_BYTES").then(|| "1000".to_string()))
+            .unwrap();
+        builder.set("log_json", "true", ConfigSource::Cli).unwrap();
+
+        std::fs::write(
+            &file,
+            r#"{ "delay": 100, "store_dir": "/elsewhere", "log_level": "debug", "max_store_bytes": 5 }"#,
+        )
+        .unwrap();
+        let reloaded = builder.reload().unwrap();
+        assert_eq!(reloaded.config().delay(), 100);
+        // the later sources still win over the file
+        assert_eq!(reloaded.config().max_store_bytes(), Some(1000));
+        assert!(reloaded.config().logging().json());
+        assert_eq!(reloaded.origin("max_store_bytes"), ConfigSource::Env);
+
+        let reload = builder.config().reload_from(reloaded.config());
+        let keys =
+            |changes: &[ConfigChange]| changes.iter().map(ConfigChange::key).collect::<Vec<_>>();
+        assert_eq!(keys(reload.applied()), ["delay", "log_level"]);
+        assert_eq!(keys(reload.rejected()), ["store_dir"]);
+        assert_eq!(
+            reload.rejected()[0].to_string(),
+            "store_dir: /store -> /elsewhere"
+        );
+        assert!(reload.applied_key("log_level"));
+        let config = reload.into_config();
+        assert_eq!(config.delay(), 100);
+        assert_eq!(config.store_dir(), "/store");
+        assert!(config.reload_from(&config).is_empty());
+    }
+}
@@ -36,10 +36,41 @@
     )
 )]
 
+mod compression;
 mod config;
 mod error;
+mod events;
+mod hooks;
+mod layered;
+mod logging;
+mod policy;
+mod priority;
+mod progress;
+mod schedule;
+mod shutdown;
+mod stores;
+mod telemetry;
+mod throttle;
 mod time;
+mod validation;
 
-pub use config::{Config, MaybeConfig};
-pub use error::{Error, Result};
-pub use time::{current_timestamp, Timestamp};
+pub use compression::CompressionConfig;
+pub use config::{Config, LowDiskAction, MaybeConfig, SymlinkPolicy, TrackedPath, WatcherKind};
+pub use error::{Error, Result, ResultExt};
+pub use events::EventKinds;
+pub use hooks::{HookAction, HookConfig, HookEvent, DEFAULT_HOOK_TIMEOUT};
+pub use layered::{
+    ConfigBuilder, ConfigChange, ConfigReload, ConfigSource, CONFIG_FILE_ENV, CONFIG_KEYS,
+    ENV_PREFIX, RELOADABLE_KEYS,
+};
+pub use logging::{LogConfig, LogLevel};
+pub use policy::{OnChange, OnDelete, Policy};
+pub use priority::BackupPriority;
+pub use progress::{write_all_with_progress, ProgressReport, ProgressSink, StageProgress};
+pub use schedule::{BackupSchedule, QuietHours, Schedule};
+pub use shutdown::Shutdown;
+pub use stores::StoreConfig;
+pub use telemetry::{OperationSummary, Telemetry, UsageSummary};
+pub use throttle::Throttle;
+pub use time::{current_timestamp, parse_duration, Timestamp};
+pub use validation::{ConfigProblem, ProblemCode, Severity};
@@ -0,0 +1,133 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The logging section of the [`Config`](crate::Config). The crates of the workspace only emit
+//! `tracing` spans and events, installing a subscriber according to this configuration is left
+//! to the binaries.
+
+use std::{fmt, str::FromStr};
+
+/// The most verbose level of events that is logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum LogLevel {
+    /// Only errors
+    Error,
+    /// Errors and warnings
+    #[default]
+    Warn,
+    /// Also informational events, e.g. every backup that was created
+    Info,
+    /// Also events useful when debugging, e.g. every file system event received
+    Debug,
+    /// Everything
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            "trace" => Ok(Self::Trace),
+            _ => Err(format!(
+                "unknown log level '{s}', expected one of 'error', 'warn', 'info', 'debug' or 'trace'"
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => f.write_str("error"),
+            Self::Warn => f.write_str("warn"),
+            Self::Info => f.write_str("info"),
+            Self::Debug => f.write_str("debug"),
+            Self::Trace => f.write_str("trace"),
+        }
+    }
+}
+
+/// How and where log output is written
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct LogConfig {
+    level: LogLevel,
+    file: Option<String>,
+    json: bool,
+}
+
+impl LogConfig {
+    /// Creates the default logging configuration: warnings and errors as text on stderr
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the most verbose level that is logged
+    #[must_use]
+    pub fn with_level(self, level: LogLevel) -> Self {
+        Self { level, ..self }
+    }
+
+    /// Appends the log output to the file at `file` instead of writing it to stderr
+    #[must_use]
+    pub fn with_file(self, file: impl Into<String>) -> Self {
+        Self {
+            file: Some(file.into()),
+            ..self
+        }
+    }
+
+    /// Writes every event as a single line of JSON instead of text
+    #[must_use]
+    pub fn with_json(self, json: bool) -> Self {
+        Self { json, ..self }
+    }
+
+    /// Gets the most verbose level that is logged
+    #[must_use]
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+
+    /// Gets the file the log output is appended to, `None` for stderr
+    #[must_use]
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Gets whether events are written as JSON
+    #[must_use]
+    pub fn json(&self) -> bool {
+        self.json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_levels() {
+        for level in [
+            LogLevel::Error,
+            LogLevel::Warn,
+            LogLevel::Info,
+            LogLevel::Debug,
+            LogLevel::Trace,
+        ] {
+            assert_eq!(level.to_string().parse::<LogLevel>().unwrap(), level);
+        }
+        assert_eq!("DEBUG".parse::<LogLevel>().unwrap(), LogLevel::Debug);
+        assert!("loud".parse::<LogLevel>().is_err());
+        assert!(LogLevel::Trace > LogLevel::default());
+    }
+}
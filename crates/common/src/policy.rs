@@ -0,0 +1,206 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Rules deciding what the daemon does about the files of a tracked path, configured per entry
+//! of the tracking list (see [`TrackedPath`](crate::TrackedPath)). By default every change is
+//! backed up and deletions are ignored.
+
+use std::{fmt, str::FromStr};
+
+use crate::{BackupSchedule, Error};
+
+/// The keys of the tracking list options that belong to a [`Policy`] rather than a
+/// [`Throttle`](crate::Throttle)
+pub(crate) const POLICY_KEYS: &[&str] = &["on-change", "on-delete", "on-schedule"];
+
+/// What happens when a tracked file is created, modified or renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OnChange {
+    /// The file is backed up, once its [`Throttle`](crate::Throttle) allows it
+    #[default]
+    Backup,
+    /// Nothing, e.g. for files that are only backed up on a schedule
+    Ignore,
+}
+
+/// What happens when a tracked file is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OnDelete {
+    /// Nothing, the backups of the file stay as they are
+    #[default]
+    Ignore,
+    /// A version without contents recording the deletion is added to the history of the file,
+    /// restoring the file brings back the version before it
+    Tombstone,
+}
+
+/// What the daemon does about the files of a tracked path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[allow(clippy::struct_field_names)] // named after the rules of the tracking list
+pub struct Policy {
+    on_change: OnChange,
+    on_delete: OnDelete,
+    on_schedule: Option<BackupSchedule>,
+}
+
+impl Policy {
+    /// Creates a new [`Policy`] that backs up every change and ignores deletions
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets what happens when a file changes
+    #[must_use]
+    pub fn with_on_change(self, on_change: OnChange) -> Self {
+        Self { on_change, ..self }
+    }
+
+    /// Sets what happens when a file is removed
+    #[must_use]
+    pub fn with_on_delete(self, on_delete: OnDelete) -> Self {
+        Self { on_delete, ..self }
+    }
+
+    /// Sets when the files that changed since their latest backup are backed up, whether or
+    /// not a change was reported
+    #[must_use]
+    pub fn with_on_schedule(self, schedule: BackupSchedule) -> Self {
+        Self {
+            on_schedule: Some(schedule),
+            ..self
+        }
+    }
+
+    /// Gets what happens when a file changes
+    #[must_use]
+    pub fn on_change(&self) -> OnChange {
+        self.on_change
+    }
+
+    /// Gets what happens when a file is removed
+    #[must_use]
+    pub fn on_delete(&self) -> OnDelete {
+        self.on_delete
+    }
+
+    /// Gets when changed files are backed up regardless of events, if at all
+    #[must_use]
+    pub fn on_schedule(&self) -> Option<BackupSchedule> {
+        self.on_schedule
+    }
+
+    /// Returns true if this is the default policy
+    #[must_use]
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl FromStr for Policy {
+    type Err = Error;
+
+    /// Parses rules of the form `on-change=ignore,on-delete=tombstone,on-schedule=1h`. Every key
+    /// is optional, `on-change` takes `backup` or `ignore`, `on-delete` takes `ignore` or
+    /// `tombstone` and `on-schedule` takes a [`BackupSchedule`], e.g. `0,30 * * * *`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self::new();
+        for part in split_options(s) {
+            let part = part.as_str();
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid rule '{part}', expected 'key=value'"))?;
+            policy = match (key.trim(), value.trim()) {
+                ("on-change", "backup") => policy.with_on_change(OnChange::Backup),
+                ("on-change", "ignore") => policy.with_on_change(OnChange::Ignore),
+                ("on-delete", "ignore") => policy.with_on_delete(OnDelete::Ignore),
+                ("on-delete", "tombstone") => policy.with_on_delete(OnDelete::Tombstone),
+                ("on-schedule", schedule) => policy.with_on_schedule(schedule.parse()?),
+                ("on-change" | "on-delete", value) => {
+                    return Err(format!("invalid action '{value}' for '{key}'").into())
+                }
+                (other, _) => return Err(format!("unknown rule '{other}'").into()),
+            };
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        if self.on_change == OnChange::Ignore {
+            f.write_str("on-change=ignore")?;
+            separator = ",";
+        }
+        if self.on_delete == OnDelete::Tombstone {
+            write!(f, "{separator}on-delete=tombstone")?;
+            separator = ",";
+        }
+        if let Some(schedule) = self.on_schedule {
+            write!(f, "{separator}on-schedule={schedule}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits comma separated options of the form `key=value`. A part without a `=` belongs to the
+/// value before it, so values like the cron expression `0,30 * * * *` can contain commas.
+pub(crate) fn split_options(options: &str) -> Vec<String> {
+    let mut parts: Vec<String> = Vec::new();
+    for part in options.split(',') {
+        match parts.last_mut() {
+            Some(last) if !part.contains('=') => {
+                last.push(',');
+                last.push_str(part);
+            }
+            _ => parts.push(part.to_string()),
+        }
+    }
+    parts
+        .into_iter()
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_rules() {
+        let policy: Policy = "on-change=ignore, on-delete=tombstone,on-schedule=1h"
+            .parse()
+            .unwrap();
+        assert_eq!(policy.on_change(), OnChange::Ignore);
+        assert_eq!(policy.on_delete(), OnDelete::Tombstone);
+        assert_eq!(
+            policy.on_schedule(),
+            Some(BackupSchedule::every(Duration::from_secs(3600)))
+        );
+        assert_eq!(
+            policy.to_string(),
+            "on-change=ignore,on-delete=tombstone,on-schedule=3600s"
+        );
+        assert!("".parse::<Policy>().unwrap().is_default());
+        assert!("on-change=backup".parse::<Policy>().unwrap().is_default());
+        assert!("on-change=later".parse::<Policy>().is_err());
+        assert!("on-rename=ignore".parse::<Policy>().is_err());
+        assert!("on-schedule=soon".parse::<Policy>().is_err());
+
+        let policy: Policy = "on-schedule=0,30 * * * *,on-delete=tombstone"
+            .parse()
+            .unwrap();
+        assert_eq!(policy.on_delete(), OnDelete::Tombstone);
+        assert_eq!(
+            policy.to_string(),
+            "on-delete=tombstone,on-schedule=0,30 * * * *"
+        );
+        assert_eq!(policy.to_string().parse::<Policy>().unwrap(), policy);
+    }
+}
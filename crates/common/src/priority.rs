@@ -0,0 +1,54 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! How urgent the backups of an entry of the tracking list are (see
+//! [`TrackedPath::priority`](crate::TrackedPath::priority)), queued backups of a higher priority
+//! run first.
+
+use std::{fmt, str::FromStr};
+
+use crate::Error;
+
+/// The key of the tracking list option holding the [`BackupPriority`] of an entry
+pub(crate) const PRIORITY_KEY: &str = "priority";
+
+/// The priority of queued backups, e.g. `high` for small config files that should not wait
+/// behind a large disk image that changed at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum BackupPriority {
+    /// Backed up after every other queued file
+    Low,
+    /// The priority of entries without one
+    #[default]
+    Normal,
+    /// Backed up before every other queued file
+    High,
+}
+
+impl FromStr for BackupPriority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Self::Low),
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            other => Err(
+                format!("unknown priority '{other}', expected 'low', 'normal' or 'high'").into(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BackupPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Low => f.write_str("low"),
+            Self::Normal => f.write_str("normal"),
+            Self::High => f.write_str("high"),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use xstd::cast::CastFrom;
+
+/// Receives progress updates from long running operations, such as reading, compressing
+/// or restoring large files.
+pub trait ProgressSink {
+    /// Called whenever more bytes have been processed. `processed` is the running total and
+    /// `total` the number of bytes the operation expects to process overall.
+    fn progress(&mut self, processed: u64, total: u64);
+
+    /// Called once the operation has processed all of its bytes
+    fn finish(&mut self) {}
+
+    /// Called when an operation made up of several stages (e.g. `read`, `compress`, `write`)
+    /// moves on to the stage called `name`. Progress reported afterwards belongs to that stage.
+    fn stage(&mut self, _name: &str) {}
+}
+
+/// The unit type can be used when no progress reporting is wanted
+impl ProgressSink for () {
+    fn progress(&mut self, _processed: u64, _total: u64) {}
+}
+
+impl<F: FnMut(u64, u64)> ProgressSink for F {
+    fn progress(&mut self, processed: u64, total: u64) {
+        self(processed, total);
+    }
+}
+
+/// The progress of the current stage of an operation, see [`StageProgress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressReport<'a> {
+    /// The name of the current stage
+    pub stage: &'a str,
+    /// The number of bytes processed in this stage so far
+    pub processed: u64,
+    /// The number of bytes this stage expects to process
+    pub total: u64,
+    /// The time since this stage started
+    pub elapsed: Duration,
+}
+
+impl ProgressReport<'_> {
+    /// Gets the average throughput of this stage so far, in bytes per second
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn throughput(&self) -> Option<f64> {
+        let secs = self.elapsed.as_secs_f64();
+        (secs > 0.0 && self.processed > 0).then(|| self.processed as f64 / secs)
+    }
+
+    /// Estimates the time until this stage is done, based on its average throughput so far
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.total.saturating_sub(self.processed) as f64;
+        self.throughput()
+            .map(|throughput| Duration::from_secs_f64(remaining / throughput))
+    }
+}
+
+/// A [`ProgressSink`] that keeps track of stages and their timing, turning every update into a
+/// [`ProgressReport`] with throughput and ETA for `callback`, e.g. to render a progress bar.
+pub struct StageProgress<F> {
+    callback: F,
+    stage: String,
+    started: Instant,
+}
+
+impl<F: FnMut(&ProgressReport<'_>)> StageProgress<F> {
+    /// Creates a new [`StageProgress`] calling `callback` for every update
+    pub fn new(callback: F) -> Self {
+        Self {
+            callback,
+            stage: String::new(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl<F> std::fmt::Debug for StageProgress<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StageProgress")
+            .field("stage", &self.stage)
+            .field("started", &self.started)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FnMut(&ProgressReport<'_>)> ProgressSink for StageProgress<F> {
+    fn progress(&mut self, processed: u64, total: u64) {
+        (self.callback)(&ProgressReport {
+            stage: &self.stage,
+            processed,
+            total,
+            elapsed: self.started.elapsed(),
+        });
+    }
+
+    fn stage(&mut self, name: &str) {
+        name.clone_into(&mut self.stage);
+        self.started = Instant::now();
+    }
+}
+
+/// Writes all of `bytes` to `writer` in chunks of `chunk_size`, reporting to `sink` after
+/// every chunk and calling [`ProgressSink::finish`] once done.
+///
+/// ## Errors
+/// - Returns any error produced while writing to `writer`
+pub fn write_all_with_progress(
+    writer: &mut impl Write,
+    bytes: &[u8],
+    chunk_size: usize,
+    sink: &mut dyn ProgressSink,
+) -> std::io::Result<()> {
+    let total = u64::cast_from(bytes.len());
+    let mut processed = 0;
+    sink.progress(processed, total);
+    for chunk in bytes.chunks(chunk_size.max(1)) {
+        writer.write_all(chunk)?;
+        processed += u64::cast_from(chunk.len());
+        sink.progress(processed, total);
+    }
+    sink.finish();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_chunk() {
+        let mut updates = Vec::new();
+        let mut out = Vec::new();
+        write_all_with_progress(&mut out, &[7; 10], 4, &mut |p, t| updates.push((p, t))).unwrap();
+        assert_eq!(out, vec![7; 10]);
+        assert_eq!(updates, vec![(0, 10), (4, 10), (8, 10), (10, 10)]);
+    }
+
+    #[test]
+    fn estimates_remaining_time() {
+        let report = ProgressReport {
+            stage: "compress",
+            processed: 250,
+            total: 1000,
+            elapsed: Duration::from_secs(5),
+        };
+        assert_eq!(report.throughput(), Some(50.0));
+        assert_eq!(report.eta(), Some(Duration::from_secs(15)));
+        assert_eq!(
+            ProgressReport {
+                processed: 0,
+                ..report
+            }
+            .eta(),
+            None
+        );
+
+        let mut stages = Vec::new();
+        let mut sink = StageProgress::new(|r: &ProgressReport<'_>| {
+            stages.push((r.stage.to_string(), r.processed));
+        });
+        sink.stage("read");
+        sink.progress(1, 2);
+        sink.stage("write");
+        sink.progress(2, 2);
+        assert_eq!(stages, [("read".to_string(), 1), ("write".to_string(), 2)]);
+    }
+}
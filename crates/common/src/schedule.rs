@@ -0,0 +1,601 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use crate::{parse_duration, time::civil_from_days, Config, Error, Result, Timestamp};
+
+const SECS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// A daily window during which no CPU or IO heavy work (compression, verification, gc) should
+/// be performed. Times of day are in **UTC**.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QuietHours {
+    start: u64,
+    end: u64,
+}
+
+impl QuietHours {
+    /// Creates new [`QuietHours`] from the given times of day (as offsets from midnight). A window
+    /// where `start` is after `end` wraps around midnight, a window where both are equal is empty.
+    #[must_use]
+    pub fn new(start: Duration, end: Duration) -> Self {
+        Self {
+            start: start.as_secs() % SECS_PER_DAY,
+            end: end.as_secs() % SECS_PER_DAY,
+        }
+    }
+
+    /// Checks whether `time` falls within this window
+    #[must_use]
+    pub fn contains(&self, time: Timestamp) -> bool {
+        let secs = time.as_secs() % SECS_PER_DAY;
+        if self.start <= self.end {
+            self.start <= secs && secs < self.end
+        } else {
+            secs >= self.start || secs < self.end
+        }
+    }
+
+    /// Gets the time at which the window containing `time` ends, or `None` if `time` is
+    /// outside of this window
+    #[must_use]
+    pub fn ends_after(&self, time: Timestamp) -> Option<Timestamp> {
+        if !self.contains(time) {
+            return None;
+        }
+        let secs = time.as_secs() % SECS_PER_DAY;
+        let midnight = time.as_secs() - secs;
+        if secs < self.end {
+            Some(Timestamp::new(midnight + self.end))
+        } else {
+            Some(Timestamp::new(midnight + SECS_PER_DAY + self.end))
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = Error;
+
+    /// Parses a window of the form `22:00-06:30`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("invalid quiet hours '{s}', expected 'HH:MM-HH:MM'"))?;
+        Ok(Self::new(
+            parse_time_of_day(start)?,
+            parse_time_of_day(end)?,
+        ))
+    }
+}
+
+fn parse_time_of_day(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (hours, minutes) = value
+        .split_once(':')
+        .ok_or_else(|| format!("invalid time of day '{value}', expected 'HH:MM'"))?;
+    let hours: u64 = hours
+        .parse()
+        .map_err(|e| format!("invalid hours in '{value}' - {e}"))?;
+    let minutes: u64 = minutes
+        .parse()
+        .map_err(|e| format!("invalid minutes in '{value}' - {e}"))?;
+    if hours >= 24 || minutes >= 60 {
+        return Err(format!("time of day '{value}' is out of range").into());
+    }
+    Ok(Duration::from_secs(hours * 60 * 60 + minutes * 60))
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 3600,
+            self.start % 3600 / 60,
+            self.end / 3600,
+            self.end % 3600 / 60
+        )
+    }
+}
+
+/// Decides when heavy work may run, combining the configured [`QuietHours`] with an ad-hoc
+/// pause (see `storage-cli pause`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Schedule {
+    quiet_hours: Option<QuietHours>,
+    paused_until: Option<Timestamp>,
+}
+
+impl Schedule {
+    /// Creates a new [`Schedule`] with the given quiet hours and no pause
+    #[must_use]
+    pub fn new(quiet_hours: Option<QuietHours>) -> Self {
+        Self {
+            quiet_hours,
+            paused_until: None,
+        }
+    }
+
+    /// Loads the [`Schedule`] described by `config`, including any pause stored in
+    /// [`Config::pause_file_path`]
+    ///
+    /// ## Errors
+    /// - Function returns an error if the pause file exists but cannot be read or parsed
+    pub fn load(config: &Config) -> Result<Self> {
+        let paused_until = match std::fs::read_to_string(config.pause_file_path()) {
+            Ok(contents) => Some(Timestamp::new(contents.trim().parse().map_err(|e| {
+                format!(
+                    "invalid pause file '{}' - {e}",
+                    config.pause_file_path().display()
+                )
+            })?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            quiet_hours: config.quiet_hours(),
+            paused_until,
+        })
+    }
+
+    /// Pauses heavy work until `until` by writing [`Config::pause_file_path`]
+    ///
+    /// ## Errors
+    /// - Function returns an error if the application directory or the pause file cannot be written
+    pub fn pause(config: &Config, until: Timestamp) -> Result {
+        std::fs::create_dir_all(config.app_dir_path())?;
+        std::fs::write(config.pause_file_path(), until.as_secs().to_string())?;
+        Ok(())
+    }
+
+    /// Removes any pause stored in [`Config::pause_file_path`]
+    ///
+    /// ## Errors
+    /// - Function returns an error if the pause file exists but cannot be removed
+    pub fn resume(config: &Config) -> Result {
+        match std::fs::remove_file(config.pause_file_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the time until which heavy work is paused
+    #[must_use]
+    pub fn with_paused_until(self, paused_until: Timestamp) -> Self {
+        Self {
+            paused_until: Some(paused_until),
+            ..self
+        }
+    }
+
+    /// Gets the configured quiet hours
+    #[must_use]
+    pub fn quiet_hours(&self) -> Option<QuietHours> {
+        self.quiet_hours
+    }
+
+    /// Gets the time until which heavy work has been paused, if it has been
+    #[must_use]
+    pub fn paused_until(&self) -> Option<Timestamp> {
+        self.paused_until
+    }
+
+    /// Checks whether heavy work should be deferred at `now`
+    #[must_use]
+    pub fn is_quiet(&self, now: Timestamp) -> bool {
+        self.quiet_until(now).is_some()
+    }
+
+    /// Gets the time at which heavy work may resume, or `None` if it may run at `now`
+    #[must_use]
+    pub fn quiet_until(&self, now: Timestamp) -> Option<Timestamp> {
+        let mut time = now;
+        loop {
+            if let Some(until) = self.paused_until.filter(|until| *until > time) {
+                time = until;
+            } else if let Some(end) = self.quiet_hours.and_then(|q| q.ends_after(time)) {
+                time = end;
+            } else {
+                break;
+            }
+        }
+        (time != now).then_some(time)
+    }
+}
+
+/// When the files of a tracked path are backed up regardless of watch events, either every
+/// interval or at the times matching a cron expression. Times are in **UTC**, like those of
+/// [`QuietHours`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BackupSchedule(ScheduleKind);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ScheduleKind {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl BackupSchedule {
+    /// Creates a new [`BackupSchedule`] that is due every `interval`
+    ///
+    /// ## Panics
+    /// - Panics if `interval` is shorter than a second
+    #[must_use]
+    pub fn every(interval: Duration) -> Self {
+        assert!(
+            interval.as_secs() > 0,
+            "the interval of a schedule must be at least a second"
+        );
+        Self(ScheduleKind::Every(interval))
+    }
+
+    /// Gets the interval of the schedule, if it is not a cron expression
+    #[must_use]
+    pub fn interval(&self) -> Option<Duration> {
+        match self.0 {
+            ScheduleKind::Every(interval) => Some(interval),
+            ScheduleKind::Cron(_) => None,
+        }
+    }
+
+    /// Gets the first time the schedule is due after `time`, or `None` if a cron expression
+    /// never matches (e.g. `0 0 30 2 *`)
+    #[must_use]
+    pub fn next_after(&self, time: Timestamp) -> Option<Timestamp> {
+        match self.0 {
+            ScheduleKind::Every(interval) => Some(Timestamp::from(time.as_duration() + interval)),
+            ScheduleKind::Cron(cron) => cron.next_after(time),
+        }
+    }
+}
+
+impl FromStr for BackupSchedule {
+    type Err = Error;
+
+    /// Parses either an interval such as `30m` or `1d` (see [`parse_duration`]), a cron
+    /// expression with the five fields `minute hour day-of-month month day-of-week` such as
+    /// `30 2 * * 1-5`, or one of `@hourly`, `@daily`, `@weekly` and `@monthly`. The fields take
+    /// `*`, numbers, ranges like `1-5`, steps like `*/15` and lists like `0,30`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let expression = match s {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            _ if !s.contains(char::is_whitespace) => {
+                let interval = parse_duration(s)?;
+                if interval.as_secs() == 0 {
+                    return Err(format!("invalid schedule '{s}', the interval is zero").into());
+                }
+                return Ok(Self::every(interval));
+            }
+            _ => s,
+        };
+        Ok(Self(ScheduleKind::Cron(
+            expression
+                .parse()
+                .map_err(|e| format!("invalid schedule '{s}' - {e}"))?,
+        )))
+    }
+}
+
+/// Formats the schedule like it is parsed, intervals in seconds
+impl fmt::Display for BackupSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ScheduleKind::Every(interval) => write!(f, "{}s", interval.as_secs()),
+            ScheduleKind::Cron(cron) => cron.fmt(f),
+        }
+    }
+}
+
+/// A cron expression, every field is a bit set of the values it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+}
+
+/// The name and range of each field of a [`Cron`] expression
+const CRON_FIELDS: [(&str, u64, u64); 5] = [
+    ("minute", 0, 59),
+    ("hour", 0, 23),
+    ("day of month", 1, 31),
+    ("month", 1, 12),
+    ("day of week", 0, 7),
+];
+
+/// How many days [`Cron::next_after`] looks ahead, enough for the next 29th of February
+const CRON_SEARCH_DAYS: u64 = 366 * 8;
+
+impl Cron {
+    fn next_after(self, time: Timestamp) -> Option<Timestamp> {
+        let start = time.as_secs() / 60 + 1;
+        let first_day = start * 60 / SECS_PER_DAY;
+        for day in first_day..first_day + CRON_SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let first_minute = if day == first_day {
+                start % (SECS_PER_DAY / 60)
+            } else {
+                0
+            };
+            let minute = (first_minute..SECS_PER_DAY / 60).find(|minute| {
+                has_bit(self.hours, minute / 60) && has_bit(self.minutes, minute % 60)
+            });
+            if let Some(minute) = minute {
+                return Some(Timestamp::new(day * SECS_PER_DAY + minute * 60));
+            }
+        }
+        None
+    }
+
+    /// Checks the date fields, a day matches either of the day fields if both are restricted
+    fn matches_day(self, day: u64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        // the epoch was a thursday
+        let weekday = (day + 4) % 7;
+        let any_day = self.days == field_mask(1, 31);
+        let any_weekday = self.weekdays == field_mask(0, 6);
+        let day_matches = match (any_day, any_weekday) {
+            (true, true) => true,
+            (false, true) => has_bit(self.days, day_of_month),
+            (true, false) => has_bit(self.weekdays, weekday),
+            (false, false) => has_bit(self.days, day_of_month) || has_bit(self.weekdays, weekday),
+        };
+        day_matches && has_bit(self.months, month)
+    }
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != CRON_FIELDS.len() {
+            return Err(format!(
+                "expected 5 fields 'minute hour day-of-month month day-of-week', got {}",
+                fields.len()
+            ));
+        }
+        let mut masks = [0; 5];
+        for ((mask, field), (name, min, max)) in masks.iter_mut().zip(fields).zip(CRON_FIELDS) {
+            *mask = parse_cron_field(field, min, max)
+                .map_err(|e| format!("invalid {name} '{field}' - {e}"))?;
+        }
+        // sunday is both 0 and 7
+        let mut weekdays = masks[4];
+        if has_bit(weekdays, 7) {
+            weekdays = (weekdays | 1) & field_mask(0, 6);
+        }
+        Ok(Self {
+            minutes: masks[0],
+            hours: masks[1],
+            days: masks[2],
+            months: masks[3],
+            weekdays,
+        })
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let masks = [
+            self.minutes,
+            self.hours,
+            self.days,
+            self.months,
+            self.weekdays,
+        ];
+        for (i, (mask, (_, min, max))) in masks.into_iter().zip(CRON_FIELDS).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            // sunday is only written as 0
+            let max = if i == 4 { 6 } else { max };
+            write_cron_field(f, mask, min, max)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a single field of a cron expression into the bit set of the values it matches
+fn parse_cron_field(field: &str, min: u64, max: u64) -> std::result::Result<u64, String> {
+    let number = |value: &str| -> std::result::Result<u64, String> {
+        let number = value
+            .parse::<u64>()
+            .map_err(|e| format!("'{value}' - {e}"))?;
+        if (min..=max).contains(&number) {
+            Ok(number)
+        } else {
+            Err(format!("{number} is not within {min}-{max}"))
+        }
+    };
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (item, None),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/15` runs from 5 to the end of the range
+                None if step.is_some() => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("the range {start}-{end} is empty"));
+        }
+        let step = match step {
+            Some(step) => step
+                .parse::<u64>()
+                .ok()
+                .filter(|step| *step > 0)
+                .ok_or_else(|| format!("invalid step '{step}'"))?,
+            None => 1,
+        };
+        for value in (start..=end).step_by(usize::try_from(step).unwrap_or(usize::MAX)) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+/// Writes the bit set of a cron field as `*` or a list of values and ranges
+fn write_cron_field(f: &mut fmt::Formatter<'_>, mask: u64, min: u64, max: u64) -> fmt::Result {
+    if mask == field_mask(min, max) {
+        return f.write_str("*");
+    }
+    let mut separator = "";
+    let mut value = min;
+    while value <= max {
+        if !has_bit(mask, value) {
+            value += 1;
+            continue;
+        }
+        let end = (value..=max)
+            .take_while(|end| has_bit(mask, *end))
+            .last()
+            .unwrap_or(value);
+        if end > value {
+            write!(f, "{separator}{value}-{end}")?;
+        } else {
+            write!(f, "{separator}{value}")?;
+        }
+        separator = ",";
+        value = end + 1;
+    }
+    Ok(())
+}
+
+fn field_mask(min: u64, max: u64) -> u64 {
+    (min..=max).fold(0, |mask, value| mask | 1 << value)
+}
+
+fn has_bit(mask: u64, value: u64) -> bool {
+    mask & (1 << value) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60;
+
+    #[test]
+    fn quiet_hours_wrap_midnight() {
+        let quiet: QuietHours = "22:00-06:30".parse().unwrap();
+        assert_eq!(quiet.to_string(), "22:00-06:30");
+        assert!(quiet.contains(Timestamp::new(23 * HOUR)));
+        assert!(quiet.contains(Timestamp::new(SECS_PER_DAY + 2 * HOUR)));
+        assert!(!quiet.contains(Timestamp::new(12 * HOUR)));
+        assert_eq!(
+            quiet.ends_after(Timestamp::new(23 * HOUR)),
+            Some(Timestamp::new(SECS_PER_DAY + 6 * HOUR + 30 * 60))
+        );
+        assert!("25:00-01:00".parse::<QuietHours>().is_err());
+        assert!("22:00".parse::<QuietHours>().is_err());
+    }
+
+    #[test]
+    fn schedule_combines_pause_and_window() {
+        let schedule = Schedule::new(Some("01:00-03:00".parse().unwrap()));
+        assert_eq!(schedule.quiet_until(Timestamp::new(0)), None);
+        assert_eq!(
+            schedule.quiet_until(Timestamp::new(2 * HOUR)),
+            Some(Timestamp::new(3 * HOUR))
+        );
+
+        // a pause that ends within the window lasts until the end of the window
+        let schedule = schedule.with_paused_until(Timestamp::new(2 * HOUR));
+        assert_eq!(
+            schedule.quiet_until(Timestamp::new(0)),
+            Some(Timestamp::new(3 * HOUR))
+        );
+        assert!(!schedule.is_quiet(Timestamp::new(4 * HOUR)));
+    }
+
+    #[test]
+    fn backup_schedules() {
+        let every: BackupSchedule = "90m".parse().unwrap();
+        assert_eq!(every.interval(), Some(Duration::from_secs(90 * 60)));
+        assert_eq!(every.to_string(), "5400s");
+        assert_eq!(
+            every.next_after(Timestamp::new(HOUR)),
+            Some(Timestamp::new(HOUR + 90 * 60))
+        );
+
+        // 1970-01-01 was a thursday
+        let cron: BackupSchedule = "30 2 * * 1-5".parse().unwrap();
+        assert_eq!(cron.to_string(), "30 2 * * 1-5");
+        assert_eq!(
+            cron.next_after(Timestamp::new(0)),
+            Some(Timestamp::new(2 * HOUR + 30 * 60))
+        );
+        // friday, then the weekend is skipped
+        let friday = Timestamp::new(SECS_PER_DAY + 2 * HOUR + 30 * 60);
+        assert_eq!(cron.next_after(Timestamp::new(SECS_PER_DAY)), Some(friday));
+        assert_eq!(
+            cron.next_after(friday),
+            Some(Timestamp::new(4 * SECS_PER_DAY + 2 * HOUR + 30 * 60))
+        );
+
+        let quarter: BackupSchedule = "*/15 9-17 * * *".parse().unwrap();
+        assert_eq!(quarter.to_string(), "0,15,30,45 9-17 * * *");
+        assert_eq!(
+            quarter.next_after(Timestamp::new(9 * HOUR + 15 * 60)),
+            Some(Timestamp::new(9 * HOUR + 30 * 60))
+        );
+        assert_eq!(
+            "@weekly".parse::<BackupSchedule>().unwrap().to_string(),
+            "0 0 * * 0"
+        );
+        assert_eq!(
+            "0 0 * * 7".parse::<BackupSchedule>().unwrap(),
+            "0 0 * * 0".parse().unwrap()
+        );
+        // the 29th of february 1972
+        let leap: BackupSchedule = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(
+            leap.next_after(Timestamp::new(0)),
+            Some(Timestamp::new(789 * SECS_PER_DAY))
+        );
+        let never: BackupSchedule = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(never.next_after(Timestamp::new(0)), None);
+
+        assert!("0s".parse::<BackupSchedule>().is_err());
+        assert!("soon".parse::<BackupSchedule>().is_err());
+        assert!("60 * * * *".parse::<BackupSchedule>().is_err());
+        assert!("* * * *".parse::<BackupSchedule>().is_err());
+        assert!("*/0 * * * *".parse::<BackupSchedule>().is_err());
+    }
+
+    #[test]
+    fn pause_file_roundtrip() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let config = Config::new()
+            .extend_with(&crate::MaybeConfig::default().with_app_dir(dir.path().to_str().unwrap()));
+
+        assert_eq!(Schedule::load(&config).unwrap().paused_until(), None);
+        Schedule::pause(&config, Timestamp::new(1234)).unwrap();
+        assert_eq!(
+            Schedule::load(&config).unwrap().paused_until(),
+            Some(Timestamp::new(1234))
+        );
+        Schedule::resume(&config).unwrap();
+        Schedule::resume(&config).unwrap();
+        assert_eq!(Schedule::load(&config).unwrap().paused_until(), None);
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::Result;
+
+/// The exit code used when a second signal arrives while shutting down, like a shell reports a
+/// process killed by `SIGINT`
+const FORCED_EXIT_CODE: i32 = 130;
+
+/// A cloneable handle to request a graceful shutdown of long running work, like the backup
+/// daemon. Every clone shares the same state, so any of them can request the shutdown and all of
+/// them observe it. Once requested, a shutdown cannot be withdrawn.
+///
+/// ```
+/// use storage_common::Shutdown;
+///
+/// let shutdown = Shutdown::new();
+/// let handle = shutdown.clone();
+/// std::thread::spawn(move || handle.request());
+/// shutdown.wait();
+/// assert!(shutdown.is_requested());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    /// Creates a new [`Shutdown`] that has not been requested yet
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the shutdown, waking every thread waiting on this handle or one of its clones
+    pub fn request(&self) {
+        *self.requested() = true;
+        self.state.1.notify_all();
+    }
+
+    /// Returns true once the shutdown has been requested
+    #[must_use]
+    pub fn is_requested(&self) -> bool {
+        *self.requested()
+    }
+
+    /// Blocks until the shutdown is requested
+    pub fn wait(&self) {
+        let mut requested = self.requested();
+        while !*requested {
+            requested = self
+                .state
+                .1
+                .wait(requested)
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+    }
+
+    /// Blocks until the shutdown is requested or `timeout` has passed, returning whether the
+    /// shutdown has been requested
+    #[must_use]
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut requested = self.requested();
+        while !*requested {
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                break;
+            };
+            requested = self
+                .state
+                .1
+                .wait_timeout(requested, left)
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .0;
+        }
+        *requested
+    }
+
+    /// Requests the shutdown when the process receives `SIGINT` or `SIGTERM` (`Ctrl-C` or the
+    /// console closing on Windows). A second signal while shutting down exits the process
+    /// immediately, so a stuck shutdown can always be interrupted.
+    ///
+    /// Only one handler can be installed per process.
+    ///
+    /// ## Errors
+    /// - Errors if a signal handler has already been installed
+    /// - Errors if the handler cannot be registered with the operating system
+    pub fn on_signals(&self) -> Result {
+        let shutdown = self.clone();
+        ctrlc::set_handler(move || {
+            if shutdown.is_requested() {
+                std::process::exit(FORCED_EXIT_CODE);
+            }
+            shutdown.request();
+        })
+        .map_err(|e| format!("unable to install the signal handler - {e}").into())
+    }
+
+    // nothing can panic while holding the lock, but a poisoned flag is still valid
+    fn requested(&self) -> MutexGuard<'_, bool> {
+        self.state
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_between_clones() {
+        let shutdown = Shutdown::new();
+        let clone = shutdown.clone();
+        assert!(!clone.is_requested());
+        assert!(!clone.wait_timeout(Duration::from_millis(10)));
+
+        let waiter = std::thread::spawn(move || clone.wait_timeout(Duration::from_secs(30)));
+        shutdown.request();
+        assert!(waiter.join().unwrap());
+        assert!(shutdown.is_requested());
+
+        // a requested shutdown stays requested
+        shutdown.request();
+        shutdown.wait();
+        assert!(!Shutdown::default().wait_timeout(Duration::ZERO));
+    }
+}
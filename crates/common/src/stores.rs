@@ -0,0 +1,203 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Named stores for the files under a directory, e.g. one store per project next to the global
+//! store of the [`Config`](crate::Config). Paths are routed to the store with the longest
+//! matching root, see [`Config::store_for`](crate::Config::store_for).
+//!
+//! The stores are configured as a JSON object by name, e.g. in the config file
+//!
+//! ```json
+//! { "stores": { "website": { "root": "/home/me/website", "retention": "keep=10" } } }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// The directory inside the root of a store holding it, unless its store dir is set
+const STORE_DIR_NAME: &str = ".storage";
+/// The name of the tracking list inside the store dir, unless it is set
+const TRACKING_LIST_NAME: &str = "tracking_list";
+
+/// A named store for the files under its root
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StoreConfig {
+    name: String,
+    root: PathBuf,
+    store_dir: Option<PathBuf>,
+    tracking_list: Option<PathBuf>,
+    retention: Option<String>,
+}
+
+impl StoreConfig {
+    /// Creates a new [`StoreConfig`] named `name` for the files under `root`
+    #[must_use]
+    pub fn new(name: impl Into<String>, root: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            root: root.into(),
+            store_dir: None,
+            tracking_list: None,
+            retention: None,
+        }
+    }
+
+    /// Sets the directory holding the backups
+    #[must_use]
+    pub fn with_store_dir(self, store_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            store_dir: Some(store_dir.into()),
+            ..self
+        }
+    }
+
+    /// Sets the path to the tracking list file
+    #[must_use]
+    pub fn with_tracking_list(self, tracking_list: impl Into<PathBuf>) -> Self {
+        Self {
+            tracking_list: Some(tracking_list.into()),
+            ..self
+        }
+    }
+
+    /// Sets the retention policy applied to the store, e.g. `keep=10,max-age=30d`
+    #[must_use]
+    pub fn with_retention(self, retention: impl Into<String>) -> Self {
+        Self {
+            retention: Some(retention.into()),
+            ..self
+        }
+    }
+
+    /// Gets the name of the store
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the directory whose files belong to this store
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Gets the directory holding the backups, `.storage` inside the root by default
+    #[must_use]
+    pub fn store_dir(&self) -> PathBuf {
+        self.store_dir
+            .clone()
+            .unwrap_or_else(|| self.root.join(STORE_DIR_NAME))
+    }
+
+    /// Gets the path to the tracking list file, `tracking_list` inside the store dir by default
+    #[must_use]
+    pub fn tracking_list(&self) -> PathBuf {
+        self.tracking_list
+            .clone()
+            .unwrap_or_else(|| self.store_dir().join(TRACKING_LIST_NAME))
+    }
+
+    /// Gets the retention policy applied to the store, if any
+    #[must_use]
+    pub fn retention(&self) -> Option<&str> {
+        self.retention.as_deref()
+    }
+
+    /// Returns true if `path` is inside the root of this store
+    #[must_use]
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.root)
+    }
+}
+
+/// A [`StoreConfig`] as it is written in the JSON object of the stores, the name is the key
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct StoreEntry {
+    root: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    store_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tracking_list: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retention: Option<String>,
+}
+
+/// Parses the JSON object of the stores, ordered by name
+pub(crate) fn parse_stores(json: &str) -> Result<Vec<StoreConfig>> {
+    let entries: BTreeMap<String, StoreEntry> =
+        serde_json::from_str(json).map_err(|e| format!("invalid stores - {e}"))?;
+    Ok(entries
+        .into_iter()
+        .map(|(name, entry)| StoreConfig {
+            name,
+            root: entry.root,
+            store_dir: entry.store_dir,
+            tracking_list: entry.tracking_list,
+            retention: entry.retention,
+        })
+        .collect())
+}
+
+/// Formats `stores` as the JSON object [`parse_stores`] reads
+pub(crate) fn format_stores(stores: &[StoreConfig]) -> String {
+    let entries = stores
+        .iter()
+        .map(|store| {
+            let entry = StoreEntry {
+                root: store.root.clone(),
+                store_dir: store.store_dir.clone(),
+                tracking_list: store.tracking_list.clone(),
+                retention: store.retention.clone(),
+            };
+            (store.name.as_str(), entry)
+        })
+        .collect::<BTreeMap<_, _>>();
+    serde_json::to_string(&entries).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stores() {
+        let stores = parse_stores(
+            r#"{
+                "website": { "root": "/home/me/website", "retention": "keep=10" },
+                "notes": { "root": "/home/me/notes", "store_dir": "/mnt/backup/notes" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            stores,
+            [
+                StoreConfig::new("notes", "/home/me/notes").with_store_dir("/mnt/backup/notes"),
+                StoreConfig::new("website", "/home/me/website").with_retention("keep=10"),
+            ]
+        );
+        assert_eq!(
+            stores[0].tracking_list(),
+            Path::new("/mnt/backup/notes/tracking_list")
+        );
+        assert_eq!(
+            stores[1].store_dir(),
+            Path::new("/home/me/website/.storage")
+        );
+        assert!(stores[1].contains(Path::new("/home/me/website/index.html")));
+        assert!(!stores[1].contains(Path::new("/home/me/website2/index.html")));
+        assert_eq!(parse_stores(&format_stores(&stores)).unwrap(), stores);
+
+        assert!(parse_stores(r#"{ "a": { "store_dir": "/x" } }"#).is_err());
+        assert!(parse_stores(r#"{ "a": { "root": "/x", "unknown": 1 } }"#).is_err());
+    }
+}
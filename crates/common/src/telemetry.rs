@@ -0,0 +1,270 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Opt-in, local-only usage telemetry. Nothing in here ever leaves the machine: the summary is
+//! a plain JSON file in the application directory which the user can read, attach to a bug
+//! report, or delete at any time.
+
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, Error, Result, Timestamp};
+
+/// The number of most recent durations kept per operation for computing percentiles
+const MAX_SAMPLES: usize = 512;
+
+/// Aggregated statistics for a single kind of operation
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct OperationSummary {
+    count: u64,
+    failures: u64,
+    durations_ms: Vec<u64>,
+}
+
+impl OperationSummary {
+    /// Gets the number of times the operation was performed
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Gets the number of times the operation failed
+    #[must_use]
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    /// Gets the `percentile` (`0..=100`) of the most recent durations of the operation, or
+    /// `None` if it has not been recorded yet
+    #[must_use]
+    pub fn percentile(&self, percentile: u8) -> Option<Duration> {
+        let mut sorted = self.durations_ms.clone();
+        sorted.sort_unstable();
+        let rank = (usize::from(percentile.min(100)) * sorted.len()).div_ceil(100);
+        let ms = sorted.get(rank.saturating_sub(1)).copied()?;
+        Some(Duration::from_millis(ms))
+    }
+}
+
+/// The usage summary stored in [`Config::telemetry_path`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageSummary {
+    since: Timestamp,
+    operations: BTreeMap<String, OperationSummary>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Default for UsageSummary {
+    fn default() -> Self {
+        Self {
+            since: Timestamp::now(),
+            operations: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        }
+    }
+}
+
+impl UsageSummary {
+    /// Gets the time at which collection started
+    #[must_use]
+    pub fn since(&self) -> Timestamp {
+        self.since
+    }
+
+    /// Gets the summary of every recorded operation, ordered by name
+    #[must_use]
+    pub fn operations(&self) -> &BTreeMap<String, OperationSummary> {
+        &self.operations
+    }
+
+    /// Gets the number of errors recorded for each [error category](Error::category)
+    #[must_use]
+    pub fn errors(&self) -> &BTreeMap<String, u64> {
+        &self.errors
+    }
+
+    /// Records a single run of `operation` that took `duration`
+    pub fn record(&mut self, operation: &str, duration: Duration, failed: bool) {
+        let summary = self.operations.entry(operation.to_string()).or_default();
+        summary.count += 1;
+        if failed {
+            summary.failures += 1;
+        }
+        if summary.durations_ms.len() == MAX_SAMPLES {
+            summary.durations_ms.remove(0);
+        }
+        summary
+            .durations_ms
+            .push(u64::try_from(duration.as_millis()).unwrap_or(u64::MAX));
+    }
+
+    /// Records an error by its [category](Error::category) only
+    pub fn record_error(&mut self, error: &Error) {
+        *self.errors.entry(error.category().to_string()).or_default() += 1;
+    }
+
+    /// Gets the total number of recorded errors
+    #[must_use]
+    pub fn total_errors(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+/// Records usage into the local [`UsageSummary`] if the user has opted in, and does nothing
+/// otherwise. Telemetry is enabled by [`Telemetry::enable`], which creates the summary file.
+#[derive(Debug, Clone)]
+pub struct Telemetry {
+    path: PathBuf,
+    summary: Option<UsageSummary>,
+}
+
+impl Telemetry {
+    /// Loads the telemetry state for `config`
+    ///
+    /// ## Errors
+    /// - Function returns an error if the summary file exists but cannot be read or parsed
+    pub fn load(config: &Config) -> Result<Self> {
+        let path = config.telemetry_path();
+        let summary = match std::fs::read(&path) {
+            Ok(bytes) => Some(
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| Error::Serde(format!("invalid usage summary - {e}")))?,
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, summary })
+    }
+
+    /// Creates a disabled [`Telemetry`] for `config` which records nothing, e.g. as a fallback
+    /// when the summary file is unreadable
+    #[must_use]
+    pub fn disabled(config: &Config) -> Self {
+        Self {
+            path: config.telemetry_path(),
+            summary: None,
+        }
+    }
+
+    /// Opts in to telemetry, starting a new, empty summary if there is none yet
+    ///
+    /// ## Errors
+    /// - Function returns an error if the summary file cannot be written
+    pub fn enable(config: &Config) -> Result<Self> {
+        let mut this = Self::load(config)?;
+        if this.summary.is_none() {
+            this.summary = Some(UsageSummary::default());
+            this.save()?;
+        }
+        Ok(this)
+    }
+
+    /// Opts out of telemetry, deleting everything that has been collected
+    ///
+    /// ## Errors
+    /// - Function returns an error if the summary file exists but cannot be removed
+    pub fn disable(config: &Config) -> Result {
+        match std::fs::remove_file(config.telemetry_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks whether the user has opted in to telemetry
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.summary.is_some()
+    }
+
+    /// Gets the collected summary, if telemetry is enabled
+    #[must_use]
+    pub fn summary(&self) -> Option<&UsageSummary> {
+        self.summary.as_ref()
+    }
+
+    /// Records a single run of `operation`, see [`UsageSummary::record`]
+    pub fn record(&mut self, operation: &str, duration: Duration, failed: bool) {
+        if let Some(summary) = &mut self.summary {
+            summary.record(operation, duration, failed);
+        }
+    }
+
+    /// Records an error by its category, see [`UsageSummary::record_error`]
+    pub fn record_error(&mut self, error: &Error) {
+        if let Some(summary) = &mut self.summary {
+            summary.record_error(error);
+        }
+    }
+
+    /// Writes the summary back to disk. Does nothing if telemetry is disabled.
+    ///
+    /// ## Errors
+    /// - Function returns an error if the application directory or the summary file cannot be written
+    pub fn save(&self) -> Result {
+        let Some(summary) = &self.summary else {
+            return Ok(());
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(summary)
+            .map_err(|e| Error::Serde(format!("unable to serialize usage summary - {e}")))?;
+        std::fs::write(&self.path, json)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_percentiles() {
+        let mut summary = UsageSummary::default();
+        for ms in (1..=100).rev() {
+            summary.record("backup", Duration::from_millis(ms), ms % 10 == 0);
+        }
+        let backup = &summary.operations()["backup"];
+        assert_eq!(backup.count(), 100);
+        assert_eq!(backup.failures(), 10);
+        assert_eq!(backup.percentile(50), Some(Duration::from_millis(50)));
+        assert_eq!(backup.percentile(99), Some(Duration::from_millis(99)));
+        assert_eq!(backup.percentile(0), Some(Duration::from_millis(1)));
+        assert_eq!(OperationSummary::default().percentile(50), None);
+
+        summary.record_error(&Error::Other("secret path".into()));
+        summary.record_error(&Error::Io(std::io::ErrorKind::NotFound.into()));
+        assert_eq!(summary.total_errors(), 2);
+        assert_eq!(summary.errors()["other"], 1);
+    }
+
+    #[test]
+    fn is_opt_in() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let config = Config::new()
+            .extend_with(&crate::MaybeConfig::default().with_app_dir(dir.path().to_str().unwrap()));
+
+        let mut telemetry = Telemetry::load(&config).unwrap();
+        telemetry.record("backup", Duration::from_millis(5), false);
+        telemetry.save().unwrap();
+        assert!(!telemetry.is_enabled());
+        assert!(!config.telemetry_path().exists());
+
+        Telemetry::enable(&config).unwrap();
+        let mut telemetry = Telemetry::load(&config).unwrap();
+        telemetry.record("backup", Duration::from_millis(5), false);
+        telemetry.save().unwrap();
+        let telemetry = Telemetry::load(&config).unwrap();
+        assert_eq!(
+            telemetry.summary().unwrap().operations()["backup"].count(),
+            1
+        );
+
+        Telemetry::disable(&config).unwrap();
+        assert!(!Telemetry::load(&config).unwrap().is_enabled());
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Limits on how often a changed file is backed up, configured globally (see
+//! [`Config::throttle`](crate::Config::throttle)) and per entry of the tracking list (see
+//! [`TrackedPath`](crate::TrackedPath)).
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use crate::{parse_duration, Error};
+
+/// Limits on how often a file that keeps changing is backed up, e.g. a log file appended to
+/// every second. Unset limits fall back to a less specific [`Throttle`], see [`Throttle::or`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Throttle {
+    min_interval: Option<Duration>,
+    settle: Option<Duration>,
+}
+
+impl Throttle {
+    /// Creates a new [`Throttle`] without any limits
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the least time between two backups of the same file, changes made in between are
+    /// backed up together once it passed
+    #[must_use]
+    pub fn with_min_interval(self, min_interval: Duration) -> Self {
+        Self {
+            min_interval: Some(min_interval),
+            ..self
+        }
+    }
+
+    /// Sets how long a file has to stay unchanged before it is backed up
+    #[must_use]
+    pub fn with_settle(self, settle: Duration) -> Self {
+        Self {
+            settle: Some(settle),
+            ..self
+        }
+    }
+
+    /// Gets the least time between two backups of the same file, if limited
+    #[must_use]
+    pub fn min_interval(&self) -> Option<Duration> {
+        self.min_interval
+    }
+
+    /// Gets how long a file has to stay unchanged before it is backed up, if limited
+    #[must_use]
+    pub fn settle(&self) -> Option<Duration> {
+        self.settle
+    }
+
+    /// Returns true if no limit is set
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.min_interval.is_none() && self.settle.is_none()
+    }
+
+    /// Combines the limits set on this throttle with those of `fallback` for the ones that are
+    /// not, e.g. a tracking list entry with the global throttle
+    #[must_use]
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            min_interval: self.min_interval.or(fallback.min_interval),
+            settle: self.settle.or(fallback.settle),
+        }
+    }
+}
+
+impl FromStr for Throttle {
+    type Err = Error;
+
+    /// Parses limits of the form `min-interval=1m,settle=5s`. Both keys are optional, durations
+    /// accept the suffixes `s`, `m`, `h`, `d` and `w` (seconds when omitted).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut throttle = Self::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid throttle '{part}', expected 'key=value'"))?;
+            let value = parse_duration(value.trim())?;
+            match key.trim() {
+                "min-interval" => throttle = throttle.with_min_interval(value),
+                "settle" => throttle = throttle.with_settle(value),
+                other => return Err(format!("unknown throttle '{other}'").into()),
+            }
+        }
+        Ok(throttle)
+    }
+}
+
+impl fmt::Display for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limits = [("min-interval", self.min_interval), ("settle", self.settle)];
+        let mut separator = "";
+        for (key, value) in limits {
+            if let Some(value) = value {
+                write!(f, "{separator}{key}={}s", value.as_secs())?;
+                separator = ",";
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_limits() {
+        let throttle: Throttle = "min-interval=1m, settle=5".parse().unwrap();
+        assert_eq!(throttle.min_interval(), Some(Duration::from_secs(60)));
+        assert_eq!(throttle.settle(), Some(Duration::from_secs(5)));
+        assert_eq!(throttle.to_string(), "min-interval=60s,settle=5s");
+        assert!("".parse::<Throttle>().unwrap().is_empty());
+        assert!("settle".parse::<Throttle>().is_err());
+        assert!("delay=5s".parse::<Throttle>().is_err());
+        assert!("settle=soon".parse::<Throttle>().is_err());
+    }
+
+    #[test]
+    fn falls_back() {
+        let global = Throttle::new()
+            .with_min_interval(Duration::from_secs(60))
+            .with_settle(Duration::from_secs(1));
+        let entry = Throttle::new().with_settle(Duration::from_secs(5));
+        assert_eq!(
+            entry.or(global),
+            Throttle::new()
+                .with_min_interval(Duration::from_secs(60))
+                .with_settle(Duration::from_secs(5))
+        );
+        assert_eq!(Throttle::new().or(global), global);
+    }
+}
@@ -4,13 +4,31 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-/// Simple timestamp type for abstracting away various time concerns
-#[derive(
-    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
-)]
-pub struct Timestamp(u64);
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+const SECS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// Simple timestamp type for abstracting away various time concerns. Timestamps are stored with
+/// nanosecond precision, as an offset from the unix epoch.
+///
+/// Whole-second timestamps serialize as a bare number of seconds (the format used before
+/// sub-second precision was added), all others as a `(secs, nanos)` tuple. Both forms
+/// deserialize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp {
+    secs: u64,
+    nanos: u32,
+}
 
 impl Timestamp {
     /// Creates a new timestamp representing the current time. Convenience function
@@ -23,49 +41,394 @@ impl Timestamp {
     /// Creates a timestamp from the given number of seconds
     #[must_use]
     pub fn new(secs: u64) -> Self {
-        Self(secs)
+        Self { secs, nanos: 0 }
+    }
+
+    /// Creates a timestamp from the given number of seconds and additional nanoseconds
+    ///
+    /// ## Panics
+    /// Function panics if `nanos` carries over into `secs` and overflows it
+    #[must_use]
+    pub fn with_nanos(secs: u64, nanos: u32) -> Self {
+        Self {
+            secs: secs
+                .checked_add(u64::from(nanos / NANOS_PER_SEC))
+                .expect("overflow in Timestamp::with_nanos"),
+            nanos: nanos % NANOS_PER_SEC,
+        }
+    }
+
+    /// Creates a timestamp from the given number of milliseconds
+    #[must_use]
+    pub fn from_millis(millis: u64) -> Self {
+        Self::from(Duration::from_millis(millis))
     }
 
-    /// The number of seconds that this timestamp represents
+    /// The number of (whole) seconds that this timestamp represents
     #[must_use]
     pub fn as_secs(self) -> u64 {
-        self.0
+        self.secs
+    }
+
+    /// The number of (whole) milliseconds that this timestamp represents
+    #[must_use]
+    pub fn as_millis(self) -> u128 {
+        self.as_duration().as_millis()
+    }
+
+    /// The number of nanoseconds that this timestamp represents
+    #[must_use]
+    pub fn as_nanos(self) -> u128 {
+        self.as_duration().as_nanos()
+    }
+
+    /// The fractional part of this timestamp, in nanoseconds
+    #[must_use]
+    pub fn subsec_nanos(self) -> u32 {
+        self.nanos
     }
 
     /// Creates a duration from this timestamp
     #[must_use]
     pub fn as_duration(self) -> Duration {
-        Duration::from_secs(self.0)
+        Duration::new(self.secs, self.nanos)
     }
 
     /// Creates a system time from this timestamp
     #[must_use]
     pub fn as_system_time(self) -> SystemTime {
-        UNIX_EPOCH + Duration::from_secs(self.0)
+        UNIX_EPOCH + self.as_duration()
+    }
+
+    /// Formats this timestamp as an [RFC 3339](https://www.rfc-editor.org/rfc/rfc3339) string in
+    /// UTC, e.g. `2023-03-28T10:40:00.25Z`. The fractional part is omitted for whole seconds.
+    #[must_use]
+    pub fn to_rfc3339(self) -> String {
+        let (year, month, day, hour, minute, second) = self.to_civil();
+        let mut out = format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}");
+        if self.nanos != 0 {
+            let fraction = format!("{:09}", self.nanos);
+            out.push('.');
+            out.push_str(fraction.trim_end_matches('0'));
+        }
+        out.push('Z');
+        out
+    }
+
+    /// Parses an [RFC 3339](https://www.rfc-editor.org/rfc/rfc3339) string such as
+    /// `2023-03-28T10:40:00.25Z` or `2023-03-28T12:40:00+02:00`
+    ///
+    /// ## Errors
+    /// - Function returns an error if `s` is not a valid RFC 3339 date-time
+    /// - Function returns an error if `s` is before the unix epoch
+    pub fn from_rfc3339(s: &str) -> crate::Result<Self> {
+        let invalid = || crate::Error::from(format!("invalid RFC 3339 timestamp '{s}'"));
+        let field = |range: std::ops::Range<usize>| -> crate::Result<u64> {
+            let digits = s.get(range).ok_or_else(invalid)?;
+            if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            digits.parse().map_err(|_| invalid())
+        };
+        let separator = |index: usize, allowed: &[u8]| -> crate::Result {
+            match s.as_bytes().get(index) {
+                Some(b) if allowed.contains(b) => Ok(()),
+                _ => Err(invalid()),
+            }
+        };
+
+        separator(4, b"-")?;
+        separator(7, b"-")?;
+        separator(10, b"Tt ")?;
+        separator(13, b":")?;
+        separator(16, b":")?;
+        let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+        let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+        if !(1..=12).contains(&month)
+            || day == 0
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(invalid());
+        }
+
+        let mut rest = s.get(19..).ok_or_else(invalid)?;
+        let mut nanos = 0u32;
+        if let Some(fraction) = rest.strip_prefix('.') {
+            let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+            if len == 0 {
+                return Err(invalid());
+            }
+            for (i, digit) in fraction.bytes().take(len).enumerate() {
+                if i < 9 {
+                    nanos = nanos * 10 + u32::from(digit - b'0');
+                }
+            }
+            nanos *= 10u32.pow(9u32.saturating_sub(u32::try_from(len).unwrap_or(9)));
+            rest = &fraction[len..];
+        }
+
+        let offset: i64 = match rest.as_bytes() {
+            [b'Z' | b'z'] => 0,
+            [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+                let hours =
+                    i64::try_from(field(s.len() - 5..s.len() - 3)?).map_err(|_| invalid())?;
+                let minutes = i64::try_from(field(s.len() - 2..s.len())?).map_err(|_| invalid())?;
+                if hours > 23 || minutes > 59 {
+                    return Err(invalid());
+                }
+                let offset = hours * 60 * 60 + minutes * 60;
+                if *sign == b'-' {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+            _ => return Err(invalid()),
+        };
+
+        // leap seconds are folded into the following second
+        let days = days_from_civil(year, month, day);
+        let secs = days * 86_400
+            + i64::try_from(hour * 60 * 60 + minute * 60 + second).map_err(|_| invalid())?
+            - offset;
+        let secs = u64::try_from(secs)
+            .map_err(|_| crate::Error::from(format!("timestamp '{s}' is before the unix epoch")))?;
+        Ok(Self { secs, nanos })
+    }
+
+    /// Splits this timestamp into its UTC `(year, month, day, hour, minute, second)`
+    fn to_civil(self) -> (u64, u64, u64, u64, u64, u64) {
+        let days = self.secs / SECS_PER_DAY;
+        let secs_of_day = self.secs % SECS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+        (
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day % 3600 / 60,
+            secs_of_day % 60,
+        )
+    }
+}
+
+fn is_leap_year(year: u64) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Converts a date into the number of days since the unix epoch
+/// (see <http://howardhinnant.github.io/date_algorithms.html>)
+fn days_from_civil(year: u64, month: u64, day: u64) -> i64 {
+    // `year`, `month` and `day` are validated (and small) at this point
+    #[allow(clippy::cast_possible_wrap)]
+    let (year, month, day) = (year as i64, month as i64, day as i64);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Converts a number of days since the unix epoch into a `(year, month, day)` date
+/// (see <http://howardhinnant.github.io/date_algorithms.html>)
+pub(crate) fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Displays the timestamp in UTC, e.g. `2023-03-28 10:40:00 UTC` (or `2023-03-28 10:40:00.250 UTC`
+/// when there is a fractional part).
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day, hour, minute, second) = self.to_civil();
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}"
+        )?;
+        if self.nanos != 0 {
+            write!(f, ".{:03}", self.nanos / 1_000_000)?;
+        }
+        f.write_str(" UTC")
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.nanos == 0 {
+            serializer.serialize_u64(self.secs)
+        } else {
+            let mut tuple = serializer.serialize_tuple(2)?;
+            tuple.serialize_element(&self.secs)?;
+            tuple.serialize_element(&self.nanos)?;
+            tuple.end()
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TimestampVisitor;
+
+        impl<'de> Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a number of seconds or a (secs, nanos) tuple")
+            }
+
+            fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Self::Value, E> {
+                Ok(Timestamp::new(secs))
+            }
+
+            fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Self::Value, E> {
+                u64::try_from(secs)
+                    .map(Timestamp::new)
+                    .map_err(|_| E::custom("timestamp before the unix epoch"))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let secs = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let nanos: u32 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                if nanos >= NANOS_PER_SEC {
+                    return Err(de::Error::custom("timestamp nanos out of range"));
+                }
+                Ok(Timestamp { secs, nanos })
+            }
+        }
+
+        deserializer.deserialize_any(TimestampVisitor)
     }
 }
 
 impl From<Duration> for Timestamp {
     fn from(duration: Duration) -> Self {
-        Self(duration.as_secs())
+        Self {
+            secs: duration.as_secs(),
+            nanos: duration.subsec_nanos(),
+        }
     }
 }
 impl From<SystemTime> for Timestamp {
     fn from(time: SystemTime) -> Self {
-        Self(
-            time.duration_since(UNIX_EPOCH)
-                .expect("system time before unix epoch")
-                .as_secs(),
-        )
+        time.duration_since(UNIX_EPOCH)
+            .expect("system time before unix epoch")
+            .into()
     }
 }
 
+/// Parses a human readable duration such as `500ms`, `90s`, `15m`, `2h`, `30d` or `1w`. A number
+/// without a suffix is interpreted as seconds.
+///
+/// ## Errors
+/// - Function returns an error if the amount is not a valid unsigned integer
+pub fn parse_duration(value: &str) -> crate::Result<Duration> {
+    if let Some(digits) = value.strip_suffix("ms") {
+        let millis: u64 = digits
+            .parse()
+            .map_err(|e| format!("invalid duration '{value}' - {e}"))?;
+        return Ok(Duration::from_millis(millis));
+    }
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'm')) => (&value[..i], 60),
+        Some((i, 'h')) => (&value[..i], 60 * 60),
+        Some((i, 'd')) => (&value[..i], 60 * 60 * 24),
+        Some((i, 'w')) => (&value[..i], 60 * 60 * 24 * 7),
+        _ => (value, 1),
+    };
+    let amount: u64 = digits
+        .parse()
+        .map_err(|e| format!("invalid duration '{value}' - {e}"))?;
+    Ok(Duration::from_secs(amount.saturating_mul(multiplier)))
+}
+
 /// Creates a new timestamp representing the current time
 #[must_use]
 pub fn current_timestamp() -> Timestamp {
-    let secs_since_epoch = SystemTime::now()
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("unable to get time since epoch")
-        .as_secs();
-    Timestamp(secs_since_epoch)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_rfc3339() {
+        let ts = Timestamp::with_nanos(1_680_000_000, 250_000_000);
+        assert_eq!(ts.to_rfc3339(), "2023-03-28T10:40:00.25Z");
+        assert_eq!(Timestamp::new(0).to_rfc3339(), "1970-01-01T00:00:00Z");
+        assert_eq!(ts.to_string(), "2023-03-28 10:40:00.250 UTC");
+        assert_eq!(
+            Timestamp::new(951_782_400).to_string(),
+            "2000-02-29 00:00:00 UTC"
+        );
+    }
+
+    #[test]
+    fn parses_rfc3339() {
+        let ts = Timestamp::with_nanos(1_680_000_000, 250_000_000);
+        assert_eq!(Timestamp::from_rfc3339(&ts.to_rfc3339()).unwrap(), ts);
+        assert_eq!(
+            Timestamp::from_rfc3339("2023-03-28T12:40:00.250+02:00").unwrap(),
+            ts
+        );
+        assert_eq!(
+            Timestamp::from_rfc3339("2023-03-28t10:40:00.0000000001z").unwrap(),
+            Timestamp::new(1_680_000_000)
+        );
+        for invalid in [
+            "2023-03-28",
+            "2023-02-29T00:00:00Z",
+            "2023-03-28T10:40:00",
+            "2023-03-28T10:40:00.Z",
+            "2023-03-28T10:40:00+2:00",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert!(Timestamp::from_rfc3339(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn serde_is_backwards_compatible() {
+        let whole = Timestamp::new(1_680_000_000);
+        let precise = Timestamp::with_nanos(1_680_000_000, 1);
+
+        // whole seconds are still written in the old, seconds only, format
+        let old = rmp_serde::to_vec(&1_680_000_000u64).unwrap();
+        assert_eq!(rmp_serde::to_vec(&whole).unwrap(), old);
+        assert_eq!(rmp_serde::from_slice::<Timestamp>(&old).unwrap(), whole);
+
+        let bytes = rmp_serde::to_vec(&precise).unwrap();
+        assert_eq!(rmp_serde::from_slice::<Timestamp>(&bytes).unwrap(), precise);
+        assert!(whole < precise);
+    }
 }
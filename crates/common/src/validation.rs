@@ -0,0 +1,275 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Checking a [`Config`] up front, see [`Config::validate`], so a bad setting is reported with
+//! what to do about it instead of as an IO error deep inside a backup.
+
+use std::{fmt, path::Path};
+
+use crate::Config;
+
+/// What is wrong with a [`Config`], as a stable machine-readable code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ProblemCode {
+    /// The watcher delay is zero, every single write is backed up
+    ZeroDelay,
+    /// No threads are allowed to run backups
+    ZeroBackupThreads,
+    /// A configured path is relative, so it depends on the working directory
+    RelativePath,
+    /// The store directory is not inside the application directory
+    StoreOutsideAppDir,
+    /// The store directory (or the directory it would be created in) is not writable
+    StoreNotWritable,
+}
+
+impl ProblemCode {
+    /// Gets the code as a string, e.g. `config::zero-delay`
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ZeroDelay => "config::zero-delay",
+            Self::ZeroBackupThreads => "config::zero-backup-threads",
+            Self::RelativePath => "config::relative-path",
+            Self::StoreOutsideAppDir => "config::store-outside-app-dir",
+            Self::StoreNotWritable => "config::store-not-writable",
+        }
+    }
+}
+
+impl fmt::Display for ProblemCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How bad a [`ConfigProblem`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    /// The setting works but is probably not what was meant
+    Warning,
+    /// The setting keeps the application from working
+    Error,
+}
+
+/// A problem with a [`Config`] found by [`Config::validate`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConfigProblem {
+    code: ProblemCode,
+    severity: Severity,
+    message: String,
+    help: String,
+}
+
+impl ConfigProblem {
+    fn new(
+        code: ProblemCode,
+        severity: Severity,
+        message: impl Into<String>,
+        help: impl Into<String>,
+    ) -> Self {
+        Self {
+            code,
+            severity,
+            message: message.into(),
+            help: help.into(),
+        }
+    }
+
+    /// Gets the machine-readable code of the problem
+    #[must_use]
+    pub fn code(&self) -> ProblemCode {
+        self.code
+    }
+
+    /// Gets how bad the problem is
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Returns true if the problem keeps the application from working
+    #[must_use]
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Gets what is wrong
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Gets what can be done about it
+    #[must_use]
+    pub fn help(&self) -> &str {
+        &self.help
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}[{}]: {}", self.code, self.message)
+    }
+}
+
+impl Config {
+    /// Checks this config for settings that cannot work or are likely mistakes. Nothing is
+    /// created, whether the store is writable is checked by creating and removing a file in it
+    /// (or in the closest existing directory it would be created in).
+    #[must_use]
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        if self.delay() == 0 {
+            problems.push(ConfigProblem::new(
+                ProblemCode::ZeroDelay,
+                Severity::Warning,
+                "the watcher delay is 0ms, files are backed up on every single write",
+                "set a delay of a few hundred milliseconds so bursts of writes are combined",
+            ));
+        }
+        if self.backup_threads() == 0 {
+            problems.push(ConfigProblem::new(
+                ProblemCode::ZeroBackupThreads,
+                Severity::Error,
+                "no backup threads are configured, nothing would ever be backed up",
+                "set the number of backup threads to at least 1",
+            ));
+        }
+        let roots = self.stores().iter().map(|store| {
+            (
+                format!("root of the store '{}'", store.name()),
+                store.root(),
+            )
+        });
+        let paths = [
+            ("app dir".to_string(), self.app_dir_path()),
+            ("store dir".to_string(), self.store_dir_path()),
+            ("tracking list".to_string(), self.tracking_list_path()),
+        ];
+        for (name, path) in paths.into_iter().chain(roots) {
+            if path.is_relative() {
+                problems.push(ConfigProblem::new(
+                    ProblemCode::RelativePath,
+                    Severity::Warning,
+                    format!(
+                        "the {name} '{}' is relative, it changes with the working directory",
+                        path.display()
+                    ),
+                    format!("use an absolute path for the {name}"),
+                ));
+            }
+        }
+        if !self.store_dir_path().starts_with(self.app_dir_path()) {
+            problems.push(ConfigProblem::new(
+                ProblemCode::StoreOutsideAppDir,
+                Severity::Warning,
+                format!(
+                    "the store dir '{}' is not inside the app dir '{}'",
+                    self.store_dir_path().display(),
+                    self.app_dir_path().display()
+                ),
+                "this is fine for a store on another disk, otherwise move it into the app dir",
+            ));
+        }
+        if let Err(e) = probe_writable(self.store_dir_path()) {
+            problems.push(ConfigProblem::new(
+                ProblemCode::StoreNotWritable,
+                Severity::Error,
+                format!(
+                    "the store dir '{}' is not writable - {e}",
+                    self.store_dir_path().display()
+                ),
+                "fix the permissions of the store dir or choose another one",
+            ));
+        }
+        problems
+    }
+}
+
+/// Checks that files can be created in `dir`, or in its closest existing ancestor if it does
+/// not exist yet
+fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    let Some(existing) = dir.ancestors().find(|dir| dir.exists()) else {
+        // a relative path without any existing ancestor is created in the working directory
+        return probe_writable(Path::new("."));
+    };
+    let probe = existing.join(format!(".storage-probe-{}", std::process::id()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    std::fs::remove_file(probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MaybeConfig;
+
+    #[test]
+    fn reports_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let config = Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(path("app"))
+                .with_store_dir(path("app/not/yet/created"))
+                .with_tracking_list(path("tracking_list")),
+        );
+        assert_eq!(config.validate(), []);
+
+        let config = config.extend_with(
+            &MaybeConfig::default()
+                .with_delay(0)
+                .with_backup_threads(0)
+                .with_store_dir("relative/store"),
+        );
+        let problems = config.validate();
+        let codes = problems.iter().map(ConfigProblem::code).collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                ProblemCode::ZeroDelay,
+                ProblemCode::ZeroBackupThreads,
+                ProblemCode::RelativePath,
+                ProblemCode::StoreOutsideAppDir,
+            ]
+        );
+        assert!(problems[1].is_error());
+        assert!(problems[0]
+            .to_string()
+            .starts_with("warning[config::zero-delay]: "));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_read_only_store() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("store");
+        std::fs::create_dir(&store).unwrap();
+        std::fs::set_permissions(&store, std::fs::Permissions::from_mode(0o555)).unwrap();
+        // permissions do not apply to root
+        if probe_writable(&store).is_ok() {
+            return;
+        }
+        let config = Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir.path().to_str().unwrap())
+                .with_store_dir(store.to_str().unwrap()),
+        );
+        let problems = config.validate();
+        assert!(problems
+            .iter()
+            .any(|problem| problem.code() == ProblemCode::StoreNotWritable && problem.is_error()));
+    }
+}
@@ -0,0 +1,1158 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::RecvTimeoutError;
+use storage_common::{
+    BackupPriority, BackupSchedule, ConfigProblem, ConfigReload, LogLevel, OnChange, Policy,
+    Shutdown, Throttle, Timestamp, TrackedPath,
+};
+use storage_mon::{
+    create_file_watcher_for, ConfiguredWatcher, FileWatcher, WatchError, WatchEvent, WatchStatus,
+};
+use storage_store::{
+    BackupManager, BackupPipeline, FileKind, LocalBackend, MetadataUpdate, QueuedBackup,
+    StorageBackend, SyncMode, SyncReport,
+};
+
+use crate::{
+    policy::{self, Action},
+    scheduler::Scheduler,
+    throttle::Throttler,
+    BackupStats, Config, Result, WatchHealth,
+};
+
+/// The longest the daemon waits for a watch event before checking whether it should shut down
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often the daemon checks the health of the watched paths, see [`Daemon::set_watch_health`]
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Backs up the tracked files whenever they change, until a [`Shutdown`] is requested.
+///
+/// A changed file is backed up once it did not change for its settle time, which defaults to
+/// [`Config::delay`] milliseconds, so a file that is saved repeatedly is only backed up once. A
+/// minimum interval between two backups of the same file can be set as well. Both limits are
+/// configured by [`Config::throttle`] and can be overridden per entry of the tracking list (see
+/// [`TrackedPath`](storage_common::TrackedPath)).
+///
+/// What happens to the files of an entry is decided by its [`Policy`] (see
+/// [`Daemon::policy_for`]): changes can be ignored, removed files can have their deletion
+/// recorded in their history (see [`BackupManager::record_deletion`]).
+///
+/// On top of their events, the tracked paths can be backed up on a
+/// [`BackupSchedule`](storage_common::BackupSchedule), either their own or [`Config::schedule`]
+/// (see [`Daemon::schedule_for`]). A scheduled run backs up the files that changed since their
+/// latest backup. Runs missed while the machine was asleep are caught up once it wakes, and
+/// [`Config::schedule_jitter`] spreads out entries that share a schedule.
+///
+/// The tracking list is watched as well, edits to it are applied right away (see
+/// [`Daemon::reconcile`]). So is the config file once it is set with
+/// [`Daemon::set_config_file`], see [`Daemon::reload_config`].
+///
+/// A watch the OS dropped goes quiet instead of failing, so the health of the watched paths is
+/// checked every [`HEALTH_INTERVAL`]: a path that is lost or given up on is logged as a warning,
+/// see [`Daemon::set_watch_health`].
+///
+/// The backups are kept by a [`StorageBackend`], by default the store directory, see
+/// [`Daemon::with_backend`].
+#[derive(Debug)]
+pub struct Daemon<B: StorageBackend = LocalBackend> {
+    config: Config,
+    manager: BackupManager<B>,
+    watcher: ConfiguredWatcher,
+    shutdown: Shutdown,
+    /// The tracked paths with their tracking list entry
+    tracked: BTreeMap<PathBuf, TrackedPath>,
+    throttler: Throttler,
+    /// When the entries with a schedule are backed up next
+    scheduler: Scheduler,
+    /// Builds the config again once the config file changed
+    reloader: Option<Reloader>,
+    /// Applies a reloaded log level
+    log_level_hook: Option<LogLevelHook>,
+    stats: BackupStats,
+    /// The health of the watched paths as of the latest check
+    health: WatchHealth,
+    /// When the health of the watched paths is checked next
+    health_due: Instant,
+    /// The watched paths that were unhealthy on the latest check
+    unhealthy: BTreeSet<PathBuf>,
+}
+
+/// The config file watched by a [`Daemon`], with the function building the config from it
+struct Reloader {
+    path: PathBuf,
+    load: Box<dyn FnMut() -> Result<Config> + Send>,
+}
+
+impl std::fmt::Debug for Reloader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reloader")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The function of [`Daemon::set_log_level_hook`]
+struct LogLevelHook(Box<dyn FnMut(LogLevel) + Send>);
+
+impl std::fmt::Debug for LogLevelHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LogLevelHook")
+    }
+}
+
+/// What happens once a panic has been logged by the hook of [`Daemon::install_panic_hook`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OnPanic {
+    /// Requests the [`Shutdown`] of the daemon, so it finishes the backups in flight and stops
+    #[default]
+    Shutdown,
+    /// Aborts the process right away
+    Abort,
+}
+
+/// The changes to the watched paths made by [`Daemon::reconcile`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Reconciliation {
+    added: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+    failed: Vec<PathBuf>,
+}
+
+impl Reconciliation {
+    /// Gets the paths that were added to the tracking list and are watched now
+    #[must_use]
+    pub fn added(&self) -> &[PathBuf] {
+        &self.added
+    }
+
+    /// Gets the paths that were removed from the tracking list and are no longer watched
+    #[must_use]
+    pub fn removed(&self) -> &[PathBuf] {
+        &self.removed
+    }
+
+    /// Gets the paths that were added to the tracking list but could not be watched, e.g.
+    /// because they are not readable. They are retried by the next reconciliation. Paths that
+    /// do not exist yet are watched, and backed up once they are created. Once the OS limit on
+    /// watches is reached the remaining paths are not tried at all, see
+    /// [`WatchError::is_fatal`].
+    #[must_use]
+    pub fn failed(&self) -> &[PathBuf] {
+        &self.failed
+    }
+
+    /// Returns true if the watched paths did not change
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.failed.is_empty()
+    }
+}
+
+impl Daemon {
+    /// Creates a new [`Daemon`] for the store and tracked files of `config`, using the watcher
+    /// selected by [`Config::watcher`]. Nothing is watched until [`Daemon::run`] is called.
+    ///
+    /// ## Errors
+    /// - Errors if [`Config::validate`] finds an error, warnings are logged
+    /// - Errors if the store cannot be read
+    /// - Errors if the file watcher cannot be created or the tracked files cannot be read
+    pub fn new(config: Config) -> Result<Self> {
+        let backend = LocalBackend::for_config(&config)?;
+        Self::with_backend(config, backend)
+    }
+}
+
+impl<B: StorageBackend> Daemon<B> {
+    /// Same as [`Daemon::new`], keeping the backups in `backend` instead of the store directory,
+    /// e.g. a [`MemoryBackend`](storage_store::MemoryBackend) for a daemon whose backups only
+    /// last as long as it runs
+    ///
+    /// ## Errors
+    /// - Errors if [`Config::validate`] finds an error, warnings are logged
+    /// - Errors if the store cannot be read
+    /// - Errors if the file watcher cannot be created or the tracked files cannot be read
+    pub fn with_backend(config: Config, backend: B) -> Result<Self> {
+        validate(&config)?;
+        let shutdown = Shutdown::new();
+        let mut manager = BackupManager::with_backend(config.clone(), backend)?;
+        manager.set_shutdown(shutdown.clone());
+        let watcher = create_file_watcher_for(&config)?;
+        let tracked = tracked_paths(&config)?;
+        let scheduler = Scheduler::new(config.schedule_jitter());
+        let mut daemon = Self {
+            config,
+            manager,
+            watcher,
+            shutdown,
+            tracked,
+            throttler: Throttler::default(),
+            scheduler,
+            reloader: None,
+            log_level_hook: None,
+            stats: BackupStats::default(),
+            health: WatchHealth::default(),
+            health_due: Instant::now(),
+            unhealthy: BTreeSet::new(),
+        };
+        daemon.reschedule();
+        Ok(daemon)
+    }
+
+    /// Gets the [`Shutdown`] handle of this daemon. Requesting it (from any thread) makes
+    /// [`Daemon::run`] finish the backups in flight and return, see also
+    /// [`Shutdown::on_signals`].
+    #[must_use]
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Installs a process wide panic hook that logs every panic with its backtrace, then shuts
+    /// this daemon down or aborts depending on `on_panic`. Without it a panic in a background
+    /// thread, like the consumer of the watcher or a backup worker, only kills that thread and
+    /// the daemon carries on without it.
+    pub fn install_panic_hook(&self, on_panic: OnPanic) {
+        let shutdown = self.shutdown.clone();
+        xstd::panic::install_panic_hook(move |report| {
+            tracing::error!(
+                thread = report.thread().unwrap_or("<unnamed>"),
+                location = %report.location().map_or_else(String::new, ToString::to_string),
+                backtrace = %report.backtrace(),
+                "panicked: {}",
+                report.message()
+            );
+            if on_panic == OnPanic::Shutdown {
+                shutdown.request();
+            }
+        });
+        if on_panic == OnPanic::Abort {
+            xstd::panic::set_abort_on_panic();
+        }
+    }
+
+    /// Watches the config file at `path` while the daemon runs. Whenever it changes, `load` builds
+    /// the config again (e.g. with [`ConfigBuilder::reload`](storage_common::ConfigBuilder::reload))
+    /// and [`Daemon::reload_config`] applies it.
+    pub fn set_config_file(
+        &mut self,
+        path: impl Into<PathBuf>,
+        load: impl FnMut() -> Result<Config> + Send + 'static,
+    ) {
+        self.reloader = Some(Reloader {
+            path: path.into(),
+            load: Box::new(load),
+        });
+    }
+
+    /// Sets the function applying a new log level found by [`Daemon::reload_config`], the log
+    /// level is left as it is without one
+    pub fn set_log_level_hook(&mut self, hook: impl FnMut(LogLevel) + Send + 'static) {
+        self.log_level_hook = Some(LogLevelHook(Box::new(hook)));
+    }
+
+    /// Publishes the health of the watched paths to `health` on every check, e.g. to the
+    /// [`ControlSocket::watch_health`](crate::ControlSocket::watch_health) so `storage-cli doctor`
+    /// can show it
+    pub fn set_watch_health(&mut self, health: WatchHealth) {
+        self.health = health;
+    }
+
+    /// Gets the health of the watched paths as of the latest check, see [`HEALTH_INTERVAL`]
+    #[must_use]
+    pub fn watch_health(&self) -> Vec<WatchStatus> {
+        self.health.statuses()
+    }
+
+    /// Gets the [`Config`] the daemon runs with, including the changes applied by
+    /// [`Daemon::reload_config`]
+    #[must_use]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Gets the [`BackupManager`] of the store the daemon backs up into
+    #[must_use]
+    pub fn manager(&self) -> &BackupManager<B> {
+        &self.manager
+    }
+
+    /// Gets the statistics of the backups the daemon ran so far, they are logged when it stops
+    #[must_use]
+    pub fn backup_stats(&self) -> &BackupStats {
+        &self.stats
+    }
+
+    /// Gets the paths on the tracking list, as of the last [`Daemon::reconcile`]
+    pub fn tracked(&self) -> impl Iterator<Item = &Path> + '_ {
+        self.tracked.keys().map(PathBuf::as_path)
+    }
+
+    /// Gets the limits on how often the file at `path` is backed up: those of the most specific
+    /// tracking list entry containing it, then [`Config::throttle`]. The settle time defaults to
+    /// [`Config::delay`].
+    #[must_use]
+    pub fn throttle_for(&self, path: &Path) -> Throttle {
+        let entry = self.entry_for(path).map(TrackedPath::throttle);
+        entry
+            .unwrap_or_default()
+            .or(self.config.throttle())
+            .or(Throttle::new().with_settle(Duration::from_millis(self.config.delay())))
+    }
+
+    /// Gets what the daemon does about the file at `path`: the policy of the most specific
+    /// tracking list entry containing it, or the default one.
+    #[must_use]
+    pub fn policy_for(&self, path: &Path) -> Policy {
+        self.entry_for(path)
+            .map(TrackedPath::policy)
+            .unwrap_or_default()
+    }
+
+    /// Gets how urgent the backups of the file at `path` are: the priority of the most specific
+    /// tracking list entry containing it, or the normal one.
+    #[must_use]
+    pub fn priority_for(&self, path: &Path) -> BackupPriority {
+        self.entry_for(path)
+            .map(TrackedPath::priority)
+            .unwrap_or_default()
+    }
+
+    /// Gets when the file at `path` is backed up regardless of changes: the schedule of the most
+    /// specific tracking list entry containing it, then [`Config::schedule`]
+    #[must_use]
+    pub fn schedule_for(&self, path: &Path) -> Option<BackupSchedule> {
+        self.entry_for(path)
+            .and_then(|entry| entry.policy().on_schedule())
+            .or(self.config.schedule())
+    }
+
+    /// Gets when the tracking list entry `path` is backed up on its schedule next, if it has one
+    #[must_use]
+    pub fn next_scheduled(&self, path: &Path) -> Option<Timestamp> {
+        self.scheduler.due(path)
+    }
+
+    /// Gets the most specific tracking list entry containing `path`
+    fn entry_for(&self, path: &Path) -> Option<&TrackedPath> {
+        self.tracked
+            .iter()
+            .filter(|(tracked, _)| path.starts_with(tracked))
+            .max_by_key(|(tracked, _)| tracked.components().count())
+            .map(|(_, entry)| entry)
+    }
+
+    /// Re-reads the tracking list and brings the watcher in line with it: newly added paths are
+    /// watched (and queued for a backup if they changed since the last one), removed paths are
+    /// no longer watched. Paths that cannot be watched are left off and retried next time, the
+    /// throttles and policies of the entries are updated as well.
+    ///
+    /// ## Errors
+    /// - Errors if the tracking list cannot be read, the watched paths are left as they are
+    pub fn reconcile(&mut self) -> Result<Reconciliation> {
+        let tracked = tracked_paths(&self.config)?;
+        let mut reconciliation = Reconciliation::default();
+        let removed = self
+            .tracked
+            .keys()
+            .filter(|path| !tracked.contains_key(*path));
+        for path in removed {
+            match self.watcher.unwatch_path(path) {
+                Ok(()) => tracing::info!(path = %path.display(), "no longer tracked"),
+                // the path is dropped from the tracking list either way
+                Err(e) => tracing::warn!(path = %path.display(), error = %e, "unable to unwatch"),
+            }
+            reconciliation.removed.push(path.clone());
+        }
+        let added = tracked
+            .keys()
+            .filter(|path| !self.tracked.contains_key(*path))
+            .collect::<Vec<_>>();
+        for (i, &path) in added.iter().enumerate() {
+            if let Err(e) = self.watcher.watch_path(path) {
+                let err = WatchError::classify(&e);
+                tracing::warn!(
+                    path = %path.display(),
+                    error = %e,
+                    guidance = err.guidance(),
+                    "unable to watch new path"
+                );
+                if err.is_fatal() {
+                    // none of the other paths is going to be watched either
+                    let remaining = added[i..].iter().map(|&path| path.clone());
+                    reconciliation.failed.extend(remaining);
+                    break;
+                }
+                reconciliation.failed.push(path.clone());
+                continue;
+            }
+            tracing::info!(path = %path.display(), "now tracked");
+            let entry = &tracked[path];
+            if entry.policy().on_change() == OnChange::Backup
+                && self.manager.needs_backup(path).unwrap_or(false)
+            {
+                let job = QueuedBackup::new(path)
+                    .with_priority(entry.priority())
+                    .with_group(path);
+                self.manager.queue(job);
+            }
+            reconciliation.added.push(path.clone());
+        }
+        self.tracked = tracked;
+        for path in &reconciliation.failed {
+            self.tracked.remove(path);
+        }
+        self.reschedule();
+
+        if !reconciliation.is_empty() {
+            tracing::info!(
+                added = reconciliation.added.len(),
+                removed = reconciliation.removed.len(),
+                failed = reconciliation.failed.len(),
+                tracked = self.tracked.len(),
+                "reconciled the tracking list"
+            );
+        }
+        Ok(reconciliation)
+    }
+
+    /// Builds the config again with the function of [`Daemon::set_config_file`] and applies the
+    /// values that can change at runtime, see
+    /// [`RELOADABLE_KEYS`](storage_common::RELOADABLE_KEYS): the delay and throttle apply to the
+    /// next changes, the compression to the next backups, the size limit of the store to the next
+    /// eviction and the log level goes to the hook of [`Daemon::set_log_level_hook`]. Changes to
+    /// any other value, like [`Config::store_dir`], are logged and ignored until the next start.
+    ///
+    /// ## Errors
+    /// - Errors if no config file is set
+    /// - Errors if the config cannot be built or [`Config::validate`] finds an error in it, the
+    ///   daemon keeps running with the config it has
+    pub fn reload_config(&mut self) -> Result<ConfigReload> {
+        let Some(reloader) = &mut self.reloader else {
+            return Err("there is no config file to reload".into());
+        };
+        let config = (reloader.load)()?;
+        validate(&config)?;
+        let reload = self.config.reload_from(&config);
+        for change in reload.rejected() {
+            tracing::warn!(
+                key = change.key(),
+                old = change.before(),
+                new = change.after(),
+                "ignoring a config change that needs a restart"
+            );
+        }
+        if reload.applied().is_empty() {
+            return Ok(reload);
+        }
+
+        let config = reload.config();
+        if reload
+            .applied()
+            .iter()
+            .any(|change| change.key().starts_with("compression_"))
+        {
+            self.manager.set_pipeline(BackupPipeline::with_compression(
+                config.compression().clone(),
+            ));
+        }
+        self.manager.update_config(config.clone());
+        if reload.applied_key("log_level") {
+            if let Some(LogLevelHook(hook)) = &mut self.log_level_hook {
+                hook(config.logging().level());
+            }
+        }
+        self.config = config.clone();
+        let applied = reload.applied().iter().map(ToString::to_string);
+        tracing::info!(
+            changes = %applied.collect::<Vec<_>>().join(", "),
+            ignored = reload.rejected().len(),
+            "reloaded the config"
+        );
+        Ok(reload)
+    }
+
+    /// Watches the tracked files and backs them up as they change, until the [`Shutdown`] is
+    /// requested. Files that changed while the daemon was not running are backed up first. Before
+    /// returning the backups that are already running are finished, the watcher is stopped and
+    /// the index of the store is flushed. Changes that were queued but not backed up yet are left
+    /// in [`BackupManager::pending`], changes still held back by their throttle are dropped.
+    ///
+    /// ## Errors
+    /// - Errors if the watcher cannot be started, or stops delivering events
+    /// - Errors if the index of the store cannot be flushed
+    pub fn run(&mut self) -> Result {
+        self.watcher.start()?;
+        let tracking_list = self.config.tracking_list_path();
+        if let Err(e) = self.watcher.watch_path(tracking_list) {
+            tracing::warn!(
+                path = %tracking_list.display(),
+                error = %e,
+                "unable to watch the tracking list, edits need a restart"
+            );
+        }
+        if let Some(reloader) = &self.reloader {
+            if let Err(e) = self.watcher.watch_path(&reloader.path) {
+                tracing::warn!(
+                    path = %reloader.path.display(),
+                    error = %e,
+                    "unable to watch the config file, edits need a restart"
+                );
+            }
+        }
+        tracing::info!(tracked = self.tracked.len(), "daemon started");
+        // changes made while nothing was watching, the watcher already reports any made from now on
+        self.catch_up();
+        let result = self.watch();
+
+        if let Err(e) = self.watcher.stop() {
+            tracing::warn!(error = %e, "unable to stop the watcher");
+        }
+        let deferred = self.manager.pending().len();
+        if deferred > 0 {
+            tracing::info!(deferred, "left queued backups for the next start");
+        }
+        self.manager.flush()?;
+        self.stats.log();
+        tracing::info!("daemon stopped");
+        result
+    }
+
+    /// The event loop of [`Daemon::run`]
+    fn watch(&mut self) -> Result {
+        let events = self.watcher.events();
+        // newly tracked files queued for their first backup by a reconciliation, or the rest of
+        // the queue after a chunk of backups
+        let mut queued = false;
+        while !self.shutdown.is_requested() {
+            let ready = self
+                .throttler
+                .next_ready()
+                .map(|ready| ready.saturating_duration_since(Instant::now()));
+            // on the wall clock, which keeps going while the machine sleeps
+            let due = self.scheduler.next_due().map(|due| {
+                due.as_duration()
+                    .saturating_sub(Timestamp::now().as_duration())
+            });
+            // the events that arrived during a chunk of backups are queued before the next one
+            let timeout = ready
+                .into_iter()
+                .chain(due)
+                .chain(queued.then_some(Duration::ZERO))
+                .fold(POLL_INTERVAL, Duration::min);
+            match events.recv_timeout(timeout) {
+                Ok(event) => queued |= self.handle(event),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("the watcher stopped delivering events".into());
+                }
+            }
+
+            let ready = self.throttler.take_ready(Instant::now());
+            if !ready.is_empty() || queued {
+                for path in ready {
+                    tracing::debug!(path = %path.display(), "queued backup");
+                    self.queue_backup(path);
+                }
+                queued = self.backup_pending();
+            }
+            self.run_scheduled(Timestamp::now());
+            if Instant::now() >= self.health_due {
+                self.check_health();
+                self.health_due = Instant::now() + HEALTH_INTERVAL;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the health of the watched paths and publishes it, logging the paths that turned
+    /// unhealthy since the latest check as a warning and those that recovered
+    fn check_health(&mut self) {
+        let statuses = self.watcher.health();
+        let mut unhealthy = BTreeSet::new();
+        for status in &statuses {
+            let path = status.path();
+            if status.is_healthy() {
+                if self.unhealthy.contains(path) {
+                    tracing::info!(
+                        path = %path.display(),
+                        state = %status.state(),
+                        "watch recovered"
+                    );
+                }
+                continue;
+            }
+            if !self.unhealthy.contains(path) {
+                tracing::warn!(
+                    path = %path.display(),
+                    state = %status.state(),
+                    error = status.last_error(),
+                    "watch lost, changes to the path are not backed up"
+                );
+            }
+            unhealthy.insert(path.to_path_buf());
+        }
+        self.unhealthy = unhealthy;
+        self.health.set(statuses);
+    }
+
+    /// Handles an event of the watcher: edits to the tracking list or the config file are applied,
+    /// changes to tracked files go through their policy. Returns true if a reconciliation queued
+    /// newly tracked files for their first backup.
+    fn handle(&mut self, event: WatchEvent) -> bool {
+        let paths = event.paths();
+        let removed = matches!(event, WatchEvent::Removed(_));
+        if paths.contains(&self.config.tracking_list_path()) {
+            if removed {
+                return false;
+            }
+            tracing::debug!("the tracking list changed");
+            return match self.reconcile() {
+                Ok(reconciliation) => !reconciliation.added().is_empty(),
+                Err(e) => {
+                    tracing::warn!(error = %e, "unable to reconcile the tracking list");
+                    false
+                }
+            };
+        }
+        let config_file = self
+            .reloader
+            .as_ref()
+            .map(|reloader| reloader.path.as_path());
+        if config_file.is_some_and(|config_file| paths.contains(&config_file)) {
+            if !removed {
+                tracing::debug!("the config file changed");
+                if let Err(e) = self.reload_config() {
+                    tracing::warn!(error = %e, "unable to reload the config, keeping the old one");
+                }
+            }
+            return false;
+        }
+        for action in policy::actions(event, |path| self.policy_for(path)) {
+            self.apply(action);
+        }
+        false
+    }
+
+    /// Carries out an action decided by the policy of a changed file
+    fn apply(&mut self, action: Action) {
+        match action {
+            Action::Backup(path) => {
+                // reading a named pipe or device could block the backup forever
+                if let Some(kind) = special_kind(&path) {
+                    tracing::debug!(path = %path.display(), %kind, "not backing up a special file");
+                    return;
+                }
+                tracing::debug!(path = %path.display(), "changed");
+                let throttle = self.throttle_for(&path);
+                self.throttler.record_change(path, throttle, Instant::now());
+            }
+            Action::UpdateMetadata(path) => self.update_metadata(path),
+            Action::Tombstone(path) => self.tombstone(&path),
+        }
+    }
+
+    /// Brings the scheduler in line with the tracked paths, entries whose schedule did not
+    /// change keep their due time
+    fn reschedule(&mut self) {
+        let schedules = self
+            .tracked
+            .keys()
+            .filter_map(|path| Some((path.clone(), self.schedule_for(path)?)))
+            .collect::<Vec<_>>();
+        self.scheduler.update(schedules, Timestamp::now());
+    }
+
+    /// Backs up the files of the scheduled entries that are due at `now` and changed since
+    /// their latest backup, all in a single batch, see [`BackupManager::sync`]
+    fn run_scheduled(&mut self, now: Timestamp) {
+        let due = self.scheduler.take_due(now);
+        if due.is_empty() {
+            return;
+        }
+        tracing::debug!(entries = due.len(), "running scheduled backups");
+        let started = Instant::now();
+        let report = self.manager.sync(&due, SyncMode::Quick);
+        self.record_run(started, &report);
+        for (path, meta) in report.backed_up() {
+            self.throttler.record_backup(path, Instant::now());
+            tracing::info!(
+                path = %path.display(),
+                version = %meta.version(),
+                "backed up on schedule"
+            );
+        }
+        for (path, e) in report.failed() {
+            tracing::warn!(path = %path.display(), error = %e, "scheduled backup failed");
+        }
+        for path in report.missing() {
+            tracing::warn!(path = %path.display(), "scheduled path does not exist");
+        }
+        for (path, kind) in report.skipped() {
+            tracing::debug!(path = %path.display(), %kind, "skipped a special file");
+        }
+    }
+
+    /// Records the deletion of the removed file at `path` in its history, see
+    /// [`BackupManager::record_deletion`]
+    fn tombstone(&self, path: &Path) {
+        match self.manager.record_deletion(path) {
+            Ok(Some(meta)) => tracing::info!(
+                path = %path.display(),
+                version = %meta.version(),
+                "recorded the deletion of a file"
+            ),
+            Ok(None) => tracing::debug!(path = %path.display(), "no deletion to record"),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "unable to record deletion");
+            }
+        }
+    }
+
+    /// Backs up the tracked files that changed since their latest backup, unless their entry
+    /// ignores changes, see [`BackupManager::sync`]
+    fn catch_up(&mut self) {
+        let paths = self
+            .tracked
+            .values()
+            .filter(|entry| entry.policy().on_change() == OnChange::Backup)
+            .map(TrackedPath::path);
+        let started = Instant::now();
+        let report = self.manager.sync(paths, SyncMode::Quick);
+        self.record_run(started, &report);
+        for (path, meta) in report.backed_up() {
+            self.throttler.record_backup(path, Instant::now());
+            tracing::info!(
+                path = %path.display(),
+                version = %meta.version(),
+                "backed up a change made while stopped"
+            );
+        }
+        for (path, e) in report.failed() {
+            tracing::warn!(path = %path.display(), error = %e, "unable to catch up");
+        }
+        for path in report.missing() {
+            tracing::warn!(path = %path.display(), "tracked path does not exist");
+        }
+        for (path, kind) in report.skipped() {
+            tracing::debug!(path = %path.display(), %kind, "skipped a special file");
+        }
+        tracing::info!(
+            backed_up = report.backed_up().len(),
+            unchanged = report.unchanged().len(),
+            "caught up with changes made while stopped"
+        );
+    }
+
+    /// Brings the metadata of the latest backup of `path` up to date after its attributes
+    /// changed, see [`BackupManager::update_metadata`]. If its contents changed as well it is
+    /// held back and backed up like any other change.
+    fn update_metadata(&mut self, path: PathBuf) {
+        match self.manager.update_metadata(&path) {
+            Ok(MetadataUpdate::Updated(meta)) => tracing::info!(
+                path = %path.display(),
+                version = %meta.version(),
+                revision = meta.meta_revision(),
+                "updated metadata"
+            ),
+            Ok(MetadataUpdate::Unchanged) => {}
+            Ok(MetadataUpdate::NeedsBackup) => {
+                tracing::debug!(path = %path.display(), "changed");
+                let throttle = self.throttle_for(&path);
+                self.throttler.record_change(path, throttle, Instant::now());
+            }
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "unable to update metadata");
+            }
+        }
+    }
+
+    /// Queues a backup of the changed file at `path` with its priority, the files of each tracking
+    /// list entry taking turns with those of the others
+    fn queue_backup(&self, path: PathBuf) {
+        let priority = self.priority_for(&path);
+        let group = self
+            .entry_for(&path)
+            .map(|entry| entry.path().to_path_buf());
+        let job = QueuedBackup::new(path).with_priority(priority);
+        self.manager.queue(match group {
+            Some(group) => job.with_group(group),
+            None => job,
+        });
+    }
+
+    /// Backs up the next chunk of queued changes, logging the outcome of each. Returns true if a
+    /// chunk ran, so the rest of the queue follows once new events are queued.
+    fn backup_pending(&mut self) -> bool {
+        let started = Instant::now();
+        let results = match self.manager.run_next_pending() {
+            Ok(results) => results,
+            Err(e) => {
+                tracing::warn!(error = %e, "unable to run queued backups");
+                return false;
+            }
+        };
+        if results.is_empty() {
+            return false;
+        }
+        let elapsed = started.elapsed();
+        for (path, result) in &results {
+            match result {
+                Ok(meta) => {
+                    self.throttler.record_backup(path, Instant::now());
+                    tracing::info!(path = %path.display(), version = %meta.version(), "backed up");
+                }
+                Err(e) => tracing::warn!(path = %path.display(), error = %e, "backup failed"),
+            }
+        }
+        let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+        let backed_up = results
+            .iter()
+            .filter_map(|(_, result)| result.as_ref().ok());
+        self.stats.record_run(elapsed, backed_up, failed);
+        true
+    }
+
+    /// Records the run of backups of `report` that started at `started` in the statistics
+    fn record_run(&mut self, started: Instant, report: &SyncReport) {
+        let backed_up = report.backed_up().iter().map(|(_, meta)| meta);
+        self.stats
+            .record_run(started.elapsed(), backed_up, report.failed().len());
+    }
+}
+
+/// Gets the kind of the file at `path` if it is a socket, named pipe or device
+fn special_kind(path: &Path) -> Option<FileKind> {
+    let kind = FileKind::from(std::fs::symlink_metadata(path).ok()?.file_type());
+    kind.is_special().then_some(kind)
+}
+
+/// Logs the warnings [`Config::validate`] finds in `config`
+///
+/// ## Errors
+/// - Errors if it finds an error
+fn validate(config: &Config) -> Result {
+    let (errors, warnings): (Vec<_>, Vec<_>) = config
+        .validate()
+        .into_iter()
+        .partition(ConfigProblem::is_error);
+    for warning in warnings {
+        tracing::warn!(code = %warning.code(), help = warning.help(), "{}", warning.message());
+    }
+    if !errors.is_empty() {
+        let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+        return Err(format!("invalid configuration - {}", errors.join("; ")).into());
+    }
+    Ok(())
+}
+
+/// Reads the tracking list of `config`, keyed by path
+fn tracked_paths(config: &Config) -> Result<BTreeMap<PathBuf, TrackedPath>> {
+    Ok(config
+        .read_tracking_list()?
+        .into_iter()
+        .map(|entry| (entry.path().to_path_buf(), entry))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage_common::{ConfigBuilder, ConfigChange, MaybeConfig, WatcherKind};
+    use storage_store::MemoryBackend;
+
+    #[test]
+    fn backs_up_until_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "first").unwrap();
+        let tracking_list = dir.path().join("tracking_list");
+        std::fs::write(&tracking_list, file.to_str().unwrap()).unwrap();
+        let config = Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir.path().to_str().unwrap())
+                .with_store_dir(dir.path().join("store").to_str().unwrap())
+                .with_tracking_list(tracking_list.to_str().unwrap())
+                .with_watcher(WatcherKind::Poll)
+                .with_delay(20),
+        );
+        config.init_app_structure().unwrap();
+
+        let mut daemon = Daemon::new(config.clone()).unwrap();
+        let shutdown = daemon.shutdown().clone();
+        let handle = std::thread::spawn(move || {
+            daemon.run().unwrap();
+            daemon
+        });
+
+        let manager = BackupManager::new(config.clone()).unwrap();
+        let started = Instant::now();
+        let mut contents = 0;
+        while manager.rebuild_index().unwrap() == 0 {
+            assert!(started.elapsed() < Duration::from_secs(30), "no backup");
+            contents += 1;
+            std::fs::write(&file, format!("changed {contents}")).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        shutdown.request();
+        let daemon = handle.join().unwrap();
+        assert!(daemon.shutdown().is_requested());
+        assert!(config.store_index_path().exists());
+    }
+
+    #[test]
+    fn backs_up_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "first").unwrap();
+        let tracking_list = dir.path().join("tracking_list");
+        std::fs::write(&tracking_list, file.to_str().unwrap()).unwrap();
+        let store = dir.path().join("ephemeral");
+        let config = Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir.path().to_str().unwrap())
+                .with_store_dir(store.to_str().unwrap())
+                .with_tracking_list(tracking_list.to_str().unwrap())
+                .with_watcher(WatcherKind::Poll)
+                .with_delay(20),
+        );
+        config.init_app_structure().unwrap();
+
+        // what `storage-cli daemon --ephemeral` runs
+        let mut daemon = Daemon::with_backend(config.clone(), MemoryBackend::new()).unwrap();
+        assert!(!config.store_index_path().exists());
+        let shutdown = daemon.shutdown().clone();
+        let handle = std::thread::spawn(move || {
+            daemon.run().unwrap();
+            daemon
+        });
+        let started = Instant::now();
+        let mut contents = 0;
+        while !config.store_index_path().exists() {
+            assert!(started.elapsed() < Duration::from_secs(30), "no backup");
+            contents += 1;
+            std::fs::write(&file, format!("changed {contents}")).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        shutdown.request();
+        let daemon = handle.join().unwrap();
+        assert!(!daemon.manager().history(&file).is_empty());
+        assert!(daemon.manager().backend().size() > 0);
+        // no blob is written to the store dir, only its index
+        assert!(LocalBackend::new(&store).list().unwrap().is_empty());
+        assert!(config.store_index_path().exists());
+    }
+
+    #[test]
+    fn reloads_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.json");
+        let write = |contents: &str| std::fs::write(&file, contents).unwrap();
+        let tracking_list = dir.path().join("tracking_list");
+        write(&format!(
+            r#"{{ "app_dir": {:?}, "store_dir": {:?}, "tracking_list": {tracking_list:?},
+                "watcher": "poll" }}"#,
+            dir.path(),
+            dir.path().join("store")
+        ));
+        let builder = ConfigBuilder::new().with_file(&file).unwrap();
+        let config = builder.config().clone();
+        config.init_app_structure().unwrap();
+
+        let mut daemon = Daemon::new(config).unwrap();
+        assert!(daemon.reload_config().is_err());
+        daemon.set_config_file(&file, move || Ok(builder.reload()?.build()));
+        let levels = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        daemon.set_log_level_hook({
+            let levels = levels.clone();
+            move |level| levels.lock().unwrap().push(level)
+        });
+        assert!(daemon.reload_config().unwrap().is_empty());
+
+        write(&format!(
+            r#"{{ "app_dir": {:?}, "store_dir": "/somewhere/else", "tracking_list": {tracking_list:?},
+                "watcher": "poll", "delay": 50, "log_level": "debug", "compression_quality": 3 }}"#,
+            dir.path()
+        ));
+        let reload = daemon.reload_config().unwrap();
+        let keys = reload.applied().iter().map(ConfigChange::key);
+        assert_eq!(
+            keys.collect::<Vec<_>>(),
+            ["delay", "log_level", "compression_quality"]
+        );
+        assert_eq!(reload.rejected()[0].key(), "store_dir");
+        assert_eq!(
+            daemon.throttle_for(Path::new("/untracked")).settle(),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(*levels.lock().unwrap(), [LogLevel::Debug]);
+        assert_eq!(daemon.config().compression().quality(), 3);
+        assert_eq!(daemon.config().store_dir_path(), dir.path().join("store"));
+
+        // an invalid file leaves the config as it is
+        write("{ \"delay\": \"soon\" }");
+        assert!(daemon.reload_config().is_err());
+        assert_eq!(daemon.config().delay(), 50);
+    }
+
+    #[test]
+    fn reconciles_tracking_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second.txt");
+        std::fs::write(&first, "first").unwrap();
+        std::fs::write(&second, "second").unwrap();
+        let tracking_list = dir.path().join("tracking_list");
+        std::fs::write(&tracking_list, first.to_str().unwrap()).unwrap();
+        let config = Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir.path().to_str().unwrap())
+                .with_store_dir(dir.path().join("store").to_str().unwrap())
+                .with_tracking_list(tracking_list.to_str().unwrap())
+                .with_watcher(WatcherKind::Poll),
+        );
+        config.init_app_structure().unwrap();
+
+        let mut daemon = Daemon::new(config).unwrap();
+        assert!(daemon.reconcile().unwrap().is_empty());
+        assert_eq!(daemon.tracked().collect::<Vec<_>>(), [first.as_path()]);
+
+        std::fs::write(
+            &tracking_list,
+            format!("{}\tsettle=5s\n\n", second.display()),
+        )
+        .unwrap();
+        let reconciliation = daemon.reconcile().unwrap();
+        assert_eq!(reconciliation.added(), [second.clone()]);
+        assert_eq!(reconciliation.removed(), [first.clone()]);
+        assert!(reconciliation.failed().is_empty());
+        assert_eq!(daemon.tracked().collect::<Vec<_>>(), [second.as_path()]);
+        assert_eq!(
+            daemon.throttle_for(&second),
+            Throttle::new().with_settle(Duration::from_secs(5))
+        );
+        // untracked files fall back to the delay of the config
+        assert_eq!(
+            daemon.throttle_for(&first).settle(),
+            Some(Duration::from_millis(1000))
+        );
+        // the new path has never been backed up
+        assert_eq!(daemon.manager().pending(), [second.clone()]);
+        assert_eq!(
+            daemon.watcher.currently_watched().unwrap(),
+            [second.to_str().unwrap()]
+        );
+
+        // an unreadable tracking list leaves everything as it is
+        std::fs::remove_file(&tracking_list).unwrap();
+        assert!(daemon.reconcile().is_err());
+        assert_eq!(daemon.tracked().count(), 1);
+    }
+    #[test]
+    fn follows_entry_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = dir.path().join("logs");
+        std::fs::create_dir(&logs).unwrap();
+        let log = logs.join("app.log");
+        std::fs::write(&log, "started").unwrap();
+        let tracking_list = dir.path().join("tracking_list");
+        std::fs::write(
+            &tracking_list,
+            format!(
+                "{}\n{}\ton-change=ignore,on-delete=tombstone,on-schedule=1h,priority=low\n",
+                dir.path().display(),
+                logs.display()
+            ),
+        )
+        .unwrap();
+        let config = Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir.path().to_str().unwrap())
+                .with_store_dir(dir.path().join("store").to_str().unwrap())
+                .with_tracking_list(tracking_list.to_str().unwrap())
+                .with_watcher(WatcherKind::Poll),
+        );
+        config.init_app_structure().unwrap();
+
+        let mut daemon = Daemon::new(config).unwrap();
+        assert!(daemon.policy_for(&dir.path().join("notes.md")).is_default());
+        let policy = daemon.policy_for(&log);
+        assert_eq!(policy.on_change(), OnChange::Ignore);
+        assert_eq!(
+            policy.on_schedule(),
+            Some(BackupSchedule::every(Duration::from_secs(3600)))
+        );
+        assert_eq!(daemon.schedule_for(&log), policy.on_schedule());
+        assert_eq!(daemon.schedule_for(dir.path()), None);
+        assert_eq!(daemon.priority_for(&log), BackupPriority::Low);
+        assert_eq!(
+            daemon.priority_for(&dir.path().join("notes.md")),
+            BackupPriority::Normal
+        );
+
+        // only the scheduled backup picks up the log
+        daemon.apply(Action::Backup(log.clone()));
+        daemon.run_scheduled(Timestamp::now());
+        assert!(daemon.manager().latest_backup(&log).is_none());
+        let due = daemon.next_scheduled(&logs).unwrap();
+        daemon.run_scheduled(due);
+        let meta = daemon.manager().latest_backup(&log).unwrap();
+
+        std::fs::remove_file(&log).unwrap();
+        daemon.apply(Action::Tombstone(log.clone()));
+        let deletion = daemon.manager().latest_backup(&log).unwrap();
+        assert!(deletion.is_deleted());
+        assert_eq!(deletion.version().get(), meta.version().get() + 1);
+    }
+
+    #[test]
+    fn publishes_watch_health() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "contents").unwrap();
+        let tracking_list = dir.path().join("tracking_list");
+        std::fs::write(&tracking_list, file.to_str().unwrap()).unwrap();
+        let config = Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir.path().to_str().unwrap())
+                .with_store_dir(dir.path().join("store").to_str().unwrap())
+                .with_tracking_list(tracking_list.to_str().unwrap())
+                .with_watcher(WatcherKind::Notify),
+        );
+        config.init_app_structure().unwrap();
+
+        let mut daemon = Daemon::with_backend(config, MemoryBackend::new()).unwrap();
+        let health = WatchHealth::default();
+        daemon.set_watch_health(health.clone());
+        daemon.watcher.start().unwrap();
+        daemon.check_health();
+        let statuses = health.statuses();
+        assert!(statuses
+            .iter()
+            .any(|status| status.path() == file.as_path()));
+        assert!(statuses.iter().all(WatchStatus::is_healthy));
+        assert!(daemon.unhealthy.is_empty());
+
+        std::fs::remove_file(&file).unwrap();
+        daemon.check_health();
+        assert!(daemon.unhealthy.contains(&file));
+        let status = daemon
+            .watch_health()
+            .into_iter()
+            .find(|status| status.path() == file.as_path())
+            .unwrap();
+        assert!(!status.is_healthy());
+        daemon.watcher.stop().unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The control socket of the running daemon, see [`Config::daemon_socket_path`].
+//!
+//! The lock on the [`PidFile`](crate::PidFile) only tells that the process of the daemon exists,
+//! an answer on the socket tells that it is responsive as well. Every request is a single line,
+//! answered with a single line: `ping` is answered with `pong <pid>`, `health` with the
+//! [`WatchStatus`] of every watched path as a JSON array, see [`ControlSocket::health`].
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use storage_mon::WatchStatus;
+
+use crate::{Config, Result};
+
+/// How long either side waits for the other before giving up on a connection
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Requests longer than this are cut off
+const MAX_REQUEST_BYTES: u64 = 256;
+
+/// The name of the thread answering on the control socket
+const THREAD_NAME: &str = "storage-control";
+
+/// The health of the paths watched by the daemon, as last checked by it. Shared between the
+/// [`Daemon`](crate::Daemon), see [`Daemon::set_watch_health`](crate::Daemon::set_watch_health),
+/// and the [`ControlSocket`] answering with it.
+#[derive(Debug, Clone, Default)]
+pub struct WatchHealth(Arc<Mutex<Vec<WatchStatus>>>);
+
+impl WatchHealth {
+    /// Gets the status of every watched path, empty until the daemon checked them
+    #[must_use]
+    pub fn statuses(&self) -> Vec<WatchStatus> {
+        self.lock().clone()
+    }
+
+    /// Replaces the status of every watched path
+    pub fn set(&self, statuses: Vec<WatchStatus>) {
+        *self.lock() = statuses;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<WatchStatus>> {
+        // the statuses are replaced in a single assignment, a panic cannot leave them half updated
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The control socket of the running daemon, answering until it is dropped
+#[derive(Debug)]
+pub struct ControlSocket {
+    path: PathBuf,
+    health: WatchHealth,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlSocket {
+    /// Answers on the control socket of the app dir of `config`, replacing the socket left
+    /// behind by a daemon that crashed. Must only be called while holding the
+    /// [`PidFile`](crate::PidFile).
+    ///
+    /// ## Errors
+    /// - Returns an error if the platform has no unix sockets
+    /// - Returns an error if the socket cannot be bound or its thread cannot be started
+    #[cfg(unix)]
+    pub fn bind(config: &Config) -> Result<Self> {
+        let path = config.daemon_socket_path();
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let listener = std::os::unix::net::UnixListener::bind(&path)?;
+        let health = WatchHealth::default();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name(THREAD_NAME.to_string())
+            .spawn({
+                let health = health.clone();
+                let stopped = Arc::clone(&stopped);
+                move || serve(&listener, &health, &stopped)
+            })?;
+        Ok(Self {
+            path,
+            health,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// Unix sockets are not available on other platforms
+    ///
+    /// ## Errors
+    /// - Always returns an error
+    #[cfg(not(unix))]
+    pub fn bind(_config: &Config) -> Result<Self> {
+        Err("the control socket is only available on unix".into())
+    }
+
+    /// Pings the daemon running for the app dir of `config` on its control socket, returning
+    /// how long it took to answer
+    ///
+    /// ## Errors
+    /// - Returns an error if no daemon answers on the socket within `timeout`
+    pub fn ping(config: &Config, timeout: Duration) -> Result<Duration> {
+        let started = Instant::now();
+        let reply = request(&config.daemon_socket_path(), "ping", timeout)?;
+        if !reply.starts_with("pong") {
+            return Err(format!("unexpected answer '{reply}' to a ping").into());
+        }
+        Ok(started.elapsed())
+    }
+
+    /// Asks the daemon running for the app dir of `config` on its control socket for the health
+    /// of the paths it watches
+    ///
+    /// ## Errors
+    /// - Returns an error if no daemon answers on the socket within `timeout`
+    /// - Returns an error if the answer is not a list of [`WatchStatus`]
+    pub fn health(config: &Config, timeout: Duration) -> Result<Vec<WatchStatus>> {
+        let reply = request(&config.daemon_socket_path(), "health", timeout)?;
+        serde_json::from_str(&reply)
+            .map_err(|e| format!("unexpected answer '{reply}' to a health request - {e}").into())
+    }
+
+    /// Gets the path of the control socket
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the health the socket answers with, to be kept up to date by the daemon
+    #[must_use]
+    pub fn watch_health(&self) -> &WatchHealth {
+        &self.health
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wakes up the thread waiting for the next connection, which is not answered
+        let _ = request(&self.path, "", CONNECTION_TIMEOUT);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            let socket = self.path.display();
+            tracing::warn!(%socket, error = %e, "unable to remove the control socket");
+        }
+    }
+}
+
+/// Answers the connections to `listener` one after the other until `stopped` is set
+#[cfg(unix)]
+fn serve(listener: &std::os::unix::net::UnixListener, health: &WatchHealth, stopped: &AtomicBool) {
+    for stream in listener.incoming() {
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        if let Err(e) = stream.and_then(|stream| answer(&stream, health)) {
+            tracing::debug!(error = %e, "control connection failed");
+        }
+    }
+}
+
+/// Reads the request of a connection and answers it
+#[cfg(unix)]
+fn answer(stream: &std::os::unix::net::UnixStream, health: &WatchHealth) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(stream.take(MAX_REQUEST_BYTES)).read_line(&mut request)?;
+    let reply = match request.trim() {
+        "ping" => format!("pong {}", std::process::id()),
+        "health" => serde_json::to_string(&health.statuses())?,
+        other => format!("error unknown request '{other}'"),
+    };
+    writeln!(&mut &*stream, "{reply}")
+}
+
+/// Sends `request` to the control socket at `path`, returning the answer
+#[cfg(unix)]
+fn request(path: &Path, request: &str, timeout: Duration) -> Result<String> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    writeln!(&mut &stream, "{request}")?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    if reply.is_empty() {
+        return Err("the control socket closed without an answer".into());
+    }
+    Ok(reply.trim_end().to_string())
+}
+
+/// Unix sockets are not available on other platforms
+#[cfg(not(unix))]
+fn request(_path: &Path, _request: &str, _timeout: Duration) -> Result<String> {
+    Err("the control socket is only available on unix".into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use storage_common::MaybeConfig;
+
+    #[test]
+    fn answers_pings() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::new()
+            .extend_with(&MaybeConfig::default().with_app_dir(dir.path().to_str().unwrap()));
+        assert!(ControlSocket::ping(&config, CONNECTION_TIMEOUT).is_err());
+
+        // a socket left behind is replaced
+        std::fs::write(config.daemon_socket_path(), b"").unwrap();
+        let socket = ControlSocket::bind(&config).unwrap();
+        assert!(ControlSocket::ping(&config, CONNECTION_TIMEOUT).is_ok());
+        assert_eq!(
+            request(socket.path(), "status", CONNECTION_TIMEOUT).unwrap(),
+            "error unknown request 'status'"
+        );
+
+        assert!(ControlSocket::health(&config, CONNECTION_TIMEOUT)
+            .unwrap()
+            .is_empty());
+        let mut watcher = storage_mon::PollingWatcher::new(Duration::from_secs(1));
+        watcher.watch_path(dir.path()).unwrap();
+        socket.watch_health().set(watcher.health());
+        assert_eq!(
+            ControlSocket::health(&config, CONNECTION_TIMEOUT).unwrap(),
+            watcher.health()
+        );
+
+        drop(socket);
+        assert!(!config.daemon_socket_path().exists());
+        assert!(ControlSocket::ping(&config, CONNECTION_TIMEOUT).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! Storage-Daemon
+//!
+//!  The long running process of the storage app: it watches the tracked files, backs them up
+//!  once they settle and shuts down gracefully when asked to, either through a signal or a
+//!  [`Shutdown`] handle held by an embedding application.
+#![warn(
+    clippy::all,
+    clippy::pedantic,
+    clippy::perf,
+    missing_copy_implementations,
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::missing_safety_doc,
+    rustdoc::all,
+    rust_2021_compatibility
+)]
+#![allow(clippy::module_name_repetitions, clippy::similar_names)]
+#![cfg_attr(
+    test,
+    allow(
+        unused,
+        dead_code,
+        clippy::all,
+        clippy::pedantic,
+        clippy::perf,
+        missing_copy_implementations,
+        missing_debug_implementations,
+        missing_docs,
+        rust_2018_idioms,
+        unreachable_pub,
+        clippy::missing_errors_doc,
+        clippy::missing_panics_doc,
+        clippy::missing_safety_doc,
+        rustdoc::all,
+        rust_2021_compatibility
+    )
+)]
+
+mod daemon;
+mod ipc;
+mod pid;
+mod policy;
+mod scheduler;
+mod stats;
+mod throttle;
+
+pub use daemon::{Daemon, OnPanic, Reconciliation, HEALTH_INTERVAL, POLL_INTERVAL};
+pub use ipc::{ControlSocket, WatchHealth};
+pub use pid::{PidFile, RunningDaemon, DEFAULT_REPLACE_TIMEOUT};
+pub use stats::BackupStats;
+pub use storage_common::Shutdown;
+
+pub(crate) use storage_common::{Config, Result};
@@ -0,0 +1,267 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The pid file that keeps a second daemon from starting for the same app dir, see
+//! [`Config::daemon_pid_path`].
+//!
+//! The file holds the pid of the running daemon and is locked for as long as it runs. The OS
+//! releases the lock of a daemon that crashed, so unlike the file itself the lock is never stale.
+
+use std::{
+    fmt,
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use storage_common::Timestamp;
+use xstd::fs::{lock_exclusive, try_lock, FileLock, LockMode};
+
+use crate::{Config, Result};
+
+/// How long [`PidFile::replace`] waits for the running daemon to stop by default
+pub const DEFAULT_REPLACE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A daemon holding the pid file of an app dir
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RunningDaemon {
+    pid: Option<u32>,
+    started: Option<Timestamp>,
+}
+
+impl RunningDaemon {
+    /// Gets the pid of the daemon, if the pid file could be read
+    #[must_use]
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// Gets when the daemon started, if the pid file could be read
+    #[must_use]
+    pub fn started(&self) -> Option<Timestamp> {
+        self.started
+    }
+}
+
+/// Formats the daemon for people, e.g. `process 4242 (since 2023-03-28T10:40:00Z)`
+impl fmt::Display for RunningDaemon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "process {pid}")?,
+            None => f.write_str("an unknown process")?,
+        }
+        if let Some(started) = self.started {
+            write!(f, " (since {started})")?;
+        }
+        Ok(())
+    }
+}
+
+/// The locked pid file of the running daemon, which is removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    /// Held until the daemon stops
+    _lock: FileLock,
+}
+
+impl PidFile {
+    /// Takes the pid file of the app dir of `config` for this process
+    ///
+    /// ## Errors
+    /// - Returns an error if another daemon is running for the same app dir
+    /// - Returns an error if the pid file cannot be locked or written
+    pub fn acquire(config: &Config) -> Result<Self> {
+        let path = config.daemon_pid_path();
+        match Self::try_acquire(&path)? {
+            Some(pid_file) => Ok(pid_file),
+            None => Err(format!(
+                "the daemon is already running as {}, stop it or start with `--replace`",
+                running(&path)
+            )
+            .into()),
+        }
+    }
+
+    /// Same as [`PidFile::acquire`], asking a running daemon to stop and waiting up to `timeout`
+    /// for it to finish its backups in flight
+    ///
+    /// ## Errors
+    /// - Returns an error if the running daemon cannot be asked to stop or is still running after
+    ///   `timeout`
+    /// - Returns an error if the pid file cannot be locked or written
+    pub fn replace(config: &Config, timeout: Duration) -> Result<Self> {
+        let path = config.daemon_pid_path();
+        if let Some(pid_file) = Self::try_acquire(&path)? {
+            return Ok(pid_file);
+        }
+        let daemon = running(&path);
+        let Some(pid) = daemon.pid else {
+            return Err(format!(
+                "unable to read the pid of the running daemon from '{}'",
+                path.display()
+            )
+            .into());
+        };
+        if pid == std::process::id() {
+            return Err("the daemon of this process cannot replace itself".into());
+        }
+        tracing::info!(pid, "asking the running daemon to stop");
+        terminate(pid)?;
+        // the lock may be released before the old daemon removed the file, which must not take
+        // the file of the new one along
+        let lock = lock_exclusive(&path, Some(timeout))
+            .map_err(|e| format!("the daemon running as {daemon} did not stop - {e}"))?;
+        drop(lock);
+        Self::try_acquire(&path)?
+            .ok_or_else(|| "another daemon started while replacing the running one".into())
+    }
+
+    /// Gets the daemon running for the app dir of `config`, if any
+    ///
+    /// ## Errors
+    /// - Returns an error if the pid file cannot be opened
+    pub fn running(config: &Config) -> Result<Option<RunningDaemon>> {
+        let path = config.daemon_pid_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(match try_lock(&path, LockMode::Shared)? {
+            Some(_) => None,
+            None => Some(running(&path)),
+        })
+    }
+
+    /// Gets the path of the pid file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn try_acquire(path: &Path) -> Result<Option<Self>> {
+        loop {
+            let Some(mut lock) = try_lock(path, LockMode::Exclusive)? else {
+                return Ok(None);
+            };
+            // the previous daemon removed the file between opening and locking it
+            if !is_current(path, &lock)? {
+                continue;
+            }
+            let file = lock.file_mut();
+            file.set_len(0)?;
+            file.rewind()?;
+            writeln!(file, "{}", std::process::id())?;
+            file.sync_data()?;
+            return Ok(Some(Self {
+                path: path.to_path_buf(),
+                _lock: lock,
+            }));
+        }
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // removed while still locked, so nobody reads a pid file of a stopped daemon
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!(pid_file = %self.path.display(), error = %e, "unable to remove pid file");
+        }
+    }
+}
+
+/// Reads the pid file at `path` of a running daemon. Some platforms do not allow reading a
+/// locked file, the pid is unknown there.
+fn running(path: &Path) -> RunningDaemon {
+    let mut contents = String::new();
+    let read = std::fs::File::open(path).and_then(|mut file| {
+        file.read_to_string(&mut contents)?;
+        file.metadata()?.modified()
+    });
+    match read {
+        Ok(modified) => RunningDaemon {
+            pid: contents.trim().parse().ok(),
+            started: Some(Timestamp::from(modified)),
+        },
+        Err(_) => RunningDaemon {
+            pid: None,
+            started: None,
+        },
+    }
+}
+
+/// Checks that `lock` is on the file at `path` and not on one that was removed in the meantime
+#[cfg(unix)]
+fn is_current(path: &Path, lock: &FileLock) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let locked = lock.file().metadata()?;
+    Ok(match std::fs::metadata(path) {
+        Ok(current) => current.dev() == locked.dev() && current.ino() == locked.ino(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e.into()),
+    })
+}
+
+/// A locked file cannot be removed on other platforms
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+fn is_current(_path: &Path, _lock: &FileLock) -> Result<bool> {
+    Ok(true)
+}
+
+/// Asks the process `pid` to shut down, the way `Ctrl-C` would
+fn terminate(pid: u32) -> Result {
+    let mut command = if cfg!(windows) {
+        let mut command = std::process::Command::new("taskkill");
+        command.args(["/PID", &pid.to_string()]);
+        command
+    } else {
+        let mut command = std::process::Command::new("kill");
+        command.args(["-TERM", &pid.to_string()]);
+        command
+    };
+    let status = command.status()?;
+    if !status.success() {
+        return Err(format!("unable to stop process {pid} - {status}").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage_common::MaybeConfig;
+
+    #[test]
+    fn single_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::new()
+            .extend_with(&MaybeConfig::default().with_app_dir(dir.path().to_str().unwrap()));
+        assert_eq!(PidFile::running(&config).unwrap(), None);
+
+        let pid_file = PidFile::acquire(&config).unwrap();
+        let contents = std::fs::read_to_string(pid_file.path()).unwrap();
+        assert_eq!(contents.trim(), std::process::id().to_string());
+        if cfg!(unix) {
+            let running = PidFile::running(&config).unwrap().unwrap();
+            assert_eq!(running.pid(), Some(std::process::id()));
+            let err = PidFile::acquire(&config).unwrap_err();
+            assert!(
+                err.to_string().contains(&format!(
+                    "already running as process {}",
+                    std::process::id()
+                )),
+                "{err}"
+            );
+            let err = PidFile::replace(&config, Duration::ZERO).unwrap_err();
+            assert!(err.to_string().contains("cannot replace itself"), "{err}");
+        }
+
+        drop(pid_file);
+        assert!(!config.daemon_pid_path().exists());
+        assert_eq!(PidFile::running(&config).unwrap(), None);
+        drop(PidFile::replace(&config, Duration::ZERO).unwrap());
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Turning watch events into what the daemon does about them, following the [`Policy`] of the
+//! tracking list entry of every path (see [`Daemon::policy_for`](crate::Daemon::policy_for)).
+
+use std::path::{Path, PathBuf};
+
+use storage_common::{OnChange, OnDelete, Policy};
+use storage_mon::WatchEvent;
+
+/// What the daemon does about a watch event
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Action {
+    /// Back up the file once its throttle allows it
+    Backup(PathBuf),
+    /// Bring the metadata of the latest backup of the file up to date
+    UpdateMetadata(PathBuf),
+    /// Record the deletion of the removed file in its history
+    Tombstone(PathBuf),
+}
+
+/// Gets the actions for `event`, with `policy_for` looking up the policy of a path. A renamed
+/// file counts as removed from its old path and changed at its new one.
+pub(crate) fn actions(event: WatchEvent, policy_for: impl Fn(&Path) -> Policy) -> Vec<Action> {
+    let changed = |path: PathBuf, action: fn(PathBuf) -> Action| match policy_for(&path).on_change()
+    {
+        OnChange::Backup => Some(action(path)),
+        OnChange::Ignore => None,
+    };
+    let removed = |path: PathBuf| match policy_for(&path).on_delete() {
+        OnDelete::Tombstone => Some(Action::Tombstone(path)),
+        OnDelete::Ignore => None,
+    };
+    match event {
+        WatchEvent::Created(path) | WatchEvent::Modified(path) => {
+            changed(path, Action::Backup).into_iter().collect()
+        }
+        WatchEvent::MetadataChanged(path) => {
+            changed(path, Action::UpdateMetadata).into_iter().collect()
+        }
+        WatchEvent::Removed(path) => removed(path).into_iter().collect(),
+        WatchEvent::Renamed { from, to } => removed(from)
+            .into_iter()
+            .chain(changed(to, Action::Backup))
+            .collect(),
+        // reading a file does not require a new backup
+        WatchEvent::Accessed(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_policies() {
+        let logs = Path::new("/var/log");
+        let policy_for = |path: &Path| {
+            if path.starts_with(logs) {
+                Policy::new()
+                    .with_on_change(OnChange::Ignore)
+                    .with_on_delete(OnDelete::Tombstone)
+            } else {
+                Policy::new()
+            }
+        };
+        let notes = PathBuf::from("/home/me/notes.md");
+        let log = logs.join("app.log");
+
+        assert_eq!(
+            actions(WatchEvent::Modified(notes.clone()), policy_for),
+            [Action::Backup(notes.clone())]
+        );
+        assert_eq!(
+            actions(WatchEvent::MetadataChanged(notes.clone()), policy_for),
+            [Action::UpdateMetadata(notes.clone())]
+        );
+        assert!(actions(WatchEvent::Removed(notes.clone()), policy_for).is_empty());
+        assert!(actions(WatchEvent::Modified(log.clone()), policy_for).is_empty());
+        assert_eq!(
+            actions(WatchEvent::Removed(log.clone()), policy_for),
+            [Action::Tombstone(log.clone())]
+        );
+        assert_eq!(
+            actions(
+                WatchEvent::Renamed {
+                    from: log.clone(),
+                    to: notes.clone()
+                },
+                policy_for
+            ),
+            [Action::Tombstone(log), Action::Backup(notes)]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Keeping track of when the tracked paths with a [`BackupSchedule`] are backed up next.
+//!
+//! Due times are kept on the wall clock rather than as [`Instant`](std::time::Instant)s, which
+//! stand still while the machine sleeps on some platforms. A schedule that was missed while the
+//! machine was asleep or the daemon was busy is due right away, but only once however many of
+//! its times were missed. A random delay of up to [`Config::schedule_jitter`](crate::Config::schedule_jitter)
+//! is added to every due time, so entries on the same schedule do not all run at once.
+
+use std::{
+    collections::BTreeMap,
+    hash::{BuildHasher, RandomState},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use storage_common::{BackupSchedule, Timestamp};
+
+/// How late a schedule has to be before it counts as missed rather than just delayed
+const MISSED_AFTER: Duration = Duration::from_mins(1);
+
+/// The schedule of a single tracked path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    schedule: BackupSchedule,
+    /// The time the schedule is due next, without the jitter
+    slot: Timestamp,
+    /// The time the path is backed up next, with the jitter
+    due: Timestamp,
+}
+
+/// The tracked paths with a schedule and when they are due next
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Scheduler {
+    entries: BTreeMap<PathBuf, Entry>,
+    jitter: Duration,
+    random: RandomState,
+}
+
+impl Scheduler {
+    /// Creates a new [`Scheduler`] without entries, adding a random delay of up to `jitter` to
+    /// every due time
+    pub(crate) fn new(jitter: Duration) -> Self {
+        Self {
+            jitter,
+            ..Self::default()
+        }
+    }
+
+    /// Replaces the scheduled paths with `schedules`. Paths whose schedule did not change keep
+    /// their due time, the others are due at the next time of their schedule after `now`.
+    /// Schedules that never come due are dropped.
+    pub(crate) fn update(
+        &mut self,
+        schedules: impl IntoIterator<Item = (PathBuf, BackupSchedule)>,
+        now: Timestamp,
+    ) {
+        let mut entries = BTreeMap::new();
+        for (path, schedule) in schedules {
+            let entry = match self.entries.get(&path) {
+                Some(entry) if entry.schedule == schedule => Some(*entry),
+                _ => schedule.next_after(now).map(|slot| Entry {
+                    schedule,
+                    slot,
+                    due: jittered(&self.random, self.jitter, &path, slot),
+                }),
+            };
+            if let Some(entry) = entry {
+                entries.insert(path, entry);
+            } else {
+                tracing::warn!(path = %path.display(), %schedule, "the schedule never comes due");
+            }
+        }
+        self.entries = entries;
+    }
+
+    /// Gets the earliest time any path is due, if any is scheduled
+    pub(crate) fn next_due(&self) -> Option<Timestamp> {
+        self.entries.values().map(|entry| entry.due).min()
+    }
+
+    /// Gets when the path is backed up next, if it is scheduled
+    pub(crate) fn due(&self, path: &Path) -> Option<Timestamp> {
+        self.entries.get(path).map(|entry| entry.due)
+    }
+
+    /// Takes the paths that are due at `now` and schedules them again. A path that missed
+    /// several times of its schedule is only returned once.
+    pub(crate) fn take_due(&mut self, now: Timestamp) -> Vec<PathBuf> {
+        let mut due = Vec::new();
+        let mut dropped = Vec::new();
+        for (path, entry) in &mut self.entries {
+            if entry.due > now {
+                continue;
+            }
+            let late = now.as_duration().saturating_sub(entry.due.as_duration());
+            if late > MISSED_AFTER {
+                tracing::info!(
+                    path = %path.display(),
+                    missed = %entry.due,
+                    "catching up with a missed schedule"
+                );
+            }
+            let slot = entry
+                .schedule
+                .next_after(entry.slot)
+                .filter(|slot| *slot > now)
+                .or_else(|| entry.schedule.next_after(now));
+            match slot {
+                Some(slot) => {
+                    entry.slot = slot;
+                    entry.due = jittered(&self.random, self.jitter, path, slot);
+                }
+                None => dropped.push(path.clone()),
+            }
+            due.push(path.clone());
+        }
+        for path in dropped {
+            self.entries.remove(&path);
+        }
+        due
+    }
+}
+
+/// Adds a random delay of up to `jitter` to `slot`, different for every path and slot
+fn jittered(random: &RandomState, jitter: Duration, path: &Path, slot: Timestamp) -> Timestamp {
+    let jitter = u64::try_from(jitter.as_millis()).unwrap_or(u64::MAX);
+    if jitter == 0 {
+        return slot;
+    }
+    let delay = random.hash_one((path, slot)) % jitter;
+    Timestamp::from(slot.as_duration() + Duration::from_millis(delay))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60;
+
+    #[test]
+    fn catches_up_once() {
+        let hourly = BackupSchedule::every(Duration::from_secs(HOUR));
+        let daily: BackupSchedule = "@daily".parse().unwrap();
+        let docs = PathBuf::from("/home/me/docs");
+        let notes = PathBuf::from("/home/me/notes");
+        let mut scheduler = Scheduler::new(Duration::ZERO);
+        scheduler.update(
+            [(docs.clone(), hourly), (notes.clone(), daily)],
+            Timestamp::new(0),
+        );
+        assert_eq!(scheduler.next_due(), Some(Timestamp::new(HOUR)));
+        assert!(scheduler.take_due(Timestamp::new(HOUR - 1)).is_empty());
+        assert_eq!(scheduler.take_due(Timestamp::new(HOUR)), [docs.clone()]);
+        assert_eq!(scheduler.due(&docs), Some(Timestamp::new(2 * HOUR)));
+
+        // asleep for two days, both are due once and keep their cadence afterwards
+        let woke = Timestamp::new(50 * HOUR + 30 * 60);
+        assert_eq!(scheduler.take_due(woke), [docs.clone(), notes.clone()]);
+        assert!(scheduler.take_due(woke).is_empty());
+        assert_eq!(
+            scheduler.due(&docs),
+            Some(Timestamp::new(51 * HOUR + 30 * 60))
+        );
+        assert_eq!(scheduler.due(&notes), Some(Timestamp::new(72 * HOUR)));
+
+        // an unchanged schedule keeps its due time, a changed one starts over
+        scheduler.update(
+            [(docs.clone(), hourly), (notes.clone(), hourly)],
+            Timestamp::new(51 * HOUR),
+        );
+        assert_eq!(
+            scheduler.due(&docs),
+            Some(Timestamp::new(51 * HOUR + 30 * 60))
+        );
+        assert_eq!(scheduler.due(&notes), Some(Timestamp::new(52 * HOUR)));
+        scheduler.update([], Timestamp::new(51 * HOUR));
+        assert_eq!(scheduler.next_due(), None);
+    }
+
+    #[test]
+    fn spreads_with_jitter() {
+        let daily: BackupSchedule = "@daily".parse().unwrap();
+        let jitter = Duration::from_secs(10 * 60);
+        let mut scheduler = Scheduler::new(jitter);
+        scheduler.update(
+            (0..20).map(|i| (PathBuf::from(format!("/data/{i}")), daily)),
+            Timestamp::new(0),
+        );
+        let due = scheduler
+            .entries
+            .values()
+            .map(|entry| entry.due)
+            .collect::<Vec<_>>();
+        let midnight = Timestamp::new(24 * HOUR);
+        assert!(due
+            .iter()
+            .all(|due| *due >= midnight && due.as_duration() < midnight.as_duration() + jitter));
+        assert!(due.iter().any(|time| *time != due[0]));
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Statistics about the backups a [`Daemon`](crate::Daemon) ran, see
+//! [`Daemon::backup_stats`](crate::Daemon::backup_stats).
+
+use std::time::Duration;
+
+use storage_store::FileMeta;
+use xstd::{cast::CastLossy, stats::StreamingStats};
+
+/// How long the runs of backups of a daemon took and how large the backed up files were, since
+/// it started. Only summaries are kept, not the samples.
+#[derive(Debug, Clone, Default)]
+pub struct BackupStats {
+    durations: StreamingStats,
+    file_sizes: StreamingStats,
+    failures: u64,
+}
+
+impl BackupStats {
+    /// Records a run of backups that took `elapsed`, backing up `backed_up` and failing for
+    /// `failed` files. Runs that did not back up anything are not recorded.
+    pub(crate) fn record_run<'a>(
+        &mut self,
+        elapsed: Duration,
+        backed_up: impl IntoIterator<Item = &'a FileMeta>,
+        failed: usize,
+    ) {
+        let before = self.file_sizes.count();
+        for meta in backed_up {
+            self.file_sizes.push(f64::cast_lossy(meta.fs_meta().size()));
+        }
+        self.failures += u64::try_from(failed).unwrap_or(u64::MAX);
+        if self.file_sizes.count() > before || failed > 0 {
+            self.durations.push(elapsed.as_secs_f64());
+        }
+    }
+
+    /// Gets the durations of the runs of backups in seconds. A run backs up all the files that
+    /// were due at once, e.g. the queued changes or the entries of a schedule.
+    #[must_use]
+    pub fn durations(&self) -> &StreamingStats {
+        &self.durations
+    }
+
+    /// Gets the sizes of the backed up files in bytes
+    #[must_use]
+    pub fn file_sizes(&self) -> &StreamingStats {
+        &self.file_sizes
+    }
+
+    /// Gets the number of backups that failed
+    #[must_use]
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    /// Logs a summary of the statistics
+    pub(crate) fn log(&self) {
+        if self.durations.is_empty() {
+            return;
+        }
+        let (durations, sizes) = (&self.durations, &self.file_sizes);
+        tracing::info!(
+            runs = durations.count(),
+            backups = sizes.count(),
+            failures = self.failures,
+            mean_secs = durations.mean(),
+            p95_secs = durations.percentile(95.0),
+            max_secs = durations.max(),
+            mean_bytes = sizes.mean(),
+            p95_bytes = sizes.percentile(95.0),
+            max_bytes = sizes.max(),
+            "backup statistics"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use storage_common::Timestamp;
+    use storage_store::{FileKind, FileVersion, FsMetadata};
+
+    use super::*;
+
+    #[test]
+    fn records_runs() {
+        let meta = |size| {
+            let fs_meta = FsMetadata::new(None, None, None, size, FileKind::File);
+            FileMeta::new(
+                FileVersion::new(),
+                Timestamp::now(),
+                PathBuf::from("a"),
+                fs_meta,
+            )
+        };
+        let mut stats = BackupStats::default();
+        stats.record_run(Duration::from_secs(1), &[meta(10), meta(30)], 0);
+        stats.record_run(Duration::from_secs(5), &[], 0);
+        stats.record_run(Duration::from_secs(3), &[], 1);
+        assert_eq!(stats.durations().count(), 2);
+        assert_eq!(stats.durations().mean(), Some(2.0));
+        assert_eq!(stats.file_sizes().mean(), Some(20.0));
+        assert_eq!(stats.failures(), 1);
+    }
+}
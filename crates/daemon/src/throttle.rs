@@ -0,0 +1,113 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use storage_common::Throttle;
+
+/// A changed file waiting for its [`Throttle`] to allow a backup
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    /// The latest change of the file
+    at: Instant,
+    settle: Duration,
+    min_interval: Duration,
+}
+
+/// Holds back changed files until they settled and their previous backup is long enough ago
+#[derive(Debug, Default)]
+pub(crate) struct Throttler {
+    changed: BTreeMap<PathBuf, Change>,
+    backed_up: HashMap<PathBuf, Instant>,
+}
+
+impl Throttler {
+    /// Records a change of `path` at `now`. `throttle` applies to the file, unset limits allow
+    /// a backup right away.
+    pub(crate) fn record_change(&mut self, path: PathBuf, throttle: Throttle, now: Instant) {
+        self.changed.insert(
+            path,
+            Change {
+                at: now,
+                settle: throttle.settle().unwrap_or_default(),
+                min_interval: throttle.min_interval().unwrap_or_default(),
+            },
+        );
+    }
+
+    /// Records a backup of `path` at `now`, the next one is held back by its minimum interval
+    pub(crate) fn record_backup(&mut self, path: &Path, now: Instant) {
+        self.backed_up.insert(path.to_path_buf(), now);
+    }
+
+    /// Gets the earliest time at which a changed file may be backed up, if any changed
+    pub(crate) fn next_ready(&self) -> Option<Instant> {
+        self.changed
+            .iter()
+            .map(|(path, change)| self.ready_at(path, change))
+            .min()
+    }
+
+    /// Removes and returns the changed files that may be backed up at `now`
+    pub(crate) fn take_ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let ready = self
+            .changed
+            .iter()
+            .filter(|(path, change)| self.ready_at(path, change) <= now)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in &ready {
+            self.changed.remove(path);
+        }
+        ready
+    }
+
+    fn ready_at(&self, path: &Path, change: &Change) -> Instant {
+        let settled = change.at + change.settle;
+        match self.backed_up.get(path) {
+            Some(backed_up) => settled.max(*backed_up + change.min_interval),
+            None => settled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_back_changes() {
+        let secs = Duration::from_secs;
+        let start = Instant::now();
+        let throttle = Throttle::new()
+            .with_min_interval(secs(60))
+            .with_settle(secs(5));
+        let log = PathBuf::from("app.log");
+        let mut throttler = Throttler::default();
+        assert_eq!(throttler.next_ready(), None);
+
+        throttler.record_change(log.clone(), throttle, start);
+        throttler.record_change("other".into(), Throttle::new(), start);
+        assert_eq!(throttler.take_ready(start), [PathBuf::from("other")]);
+        assert_eq!(throttler.next_ready(), Some(start + secs(5)));
+        // every change restarts the settle time
+        throttler.record_change(log.clone(), throttle, start + secs(3));
+        assert!(throttler.take_ready(start + secs(5)).is_empty());
+        assert_eq!(throttler.take_ready(start + secs(8)), [log.clone()]);
+        throttler.record_backup(&log, start + secs(8));
+
+        // the next backup waits for the minimum interval, although the change settled
+        throttler.record_change(log.clone(), throttle, start + secs(10));
+        assert_eq!(throttler.next_ready(), Some(start + secs(68)));
+        assert!(throttler.take_ready(start + secs(30)).is_empty());
+        assert_eq!(throttler.take_ready(start + secs(68)), [log]);
+        assert_eq!(throttler.next_ready(), None);
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Benchmarks of compressing and decompressing content blobs, across file sizes and codecs, and
+//! of the buffer size and window the streams are written with. The defaults of [`BUFFER_SIZE`]
+//! and of the compression section of the config are picked from these, run them with
+//!
+//! ```text
+//! cargo bench -p storage-format --bench codec
+//! ```
+
+use std::io::Read;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use storage_format::{
+    decode_content, encode_content_with_progress, Compression, StreamOptions, BUFFER_SIZE,
+};
+
+const KIB: usize = 1024;
+const MIB: usize = 1024 * KIB;
+
+/// Text-like bytes that compress about as well as source code, the same on every run
+fn sample(size: usize) -> Vec<u8> {
+    const WORDS: &[&str] = &[
+        "fn", "let", "mut", "self", "match", "Some", "None", "Ok", "Err", "return", "impl", "pub",
+        "struct", "use", "crate", "bytes", "path", "meta", "version", "store", "backup", "=>", "{",
+        "}", "(", ")", ";", "\n    ", "\n",
+    ];
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut bytes = Vec::with_capacity(size + 16);
+    while bytes.len() < size {
+        // xorshift, good enough to pick words
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let word = WORDS[usize::try_from(state % WORDS.len() as u64).unwrap()];
+        bytes.extend_from_slice(word.as_bytes());
+        bytes.push(b' ');
+    }
+    bytes.truncate(size);
+    bytes
+}
+
+fn encode(bytes: &[u8], compression: Compression, options: StreamOptions) -> Vec<u8> {
+    encode_content_with_progress(bytes, compression, options, &mut ()).unwrap()
+}
+
+/// Compressing and decompressing with the default stream options, across sizes and codecs
+fn codecs(c: &mut Criterion) {
+    let codecs = [
+        Compression::Store,
+        Compression::Brotli(1),
+        Compression::Brotli(5),
+        Compression::Brotli(11),
+    ];
+    for size in [4 * KIB, 64 * KIB, MIB] {
+        let bytes = sample(size);
+        let mut encode_group = c.benchmark_group(format!("encode/{}KiB", size / KIB));
+        encode_group.throughput(Throughput::Bytes(size as u64));
+        encode_group.sample_size(10);
+        for compression in codecs {
+            encode_group.bench_function(compression.to_string(), |b| {
+                b.iter(|| encode(&bytes, compression, StreamOptions::default()));
+            });
+        }
+        encode_group.finish();
+
+        let mut decode_group = c.benchmark_group(format!("decode/{}KiB", size / KIB));
+        decode_group.throughput(Throughput::Bytes(size as u64));
+        for compression in codecs {
+            let blob = encode(&bytes, compression, StreamOptions::default());
+            decode_group.bench_function(compression.to_string(), |b| {
+                b.iter(|| decode_content(&blob).unwrap());
+            });
+        }
+        decode_group.finish();
+    }
+}
+
+/// The buffer size bytes are compressed and decompressed through
+fn buffer_sizes(c: &mut Criterion) {
+    let bytes = sample(4 * MIB);
+    let mut group = c.benchmark_group("buffer_size");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.sample_size(10);
+    for buffer_size in [4 * KIB, 16 * KIB, 64 * KIB, 256 * KIB] {
+        let options = StreamOptions::new().with_buffer_size(buffer_size);
+        for compression in [Compression::Store, Compression::Brotli(5)] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("encode/{compression}"), buffer_size / KIB),
+                &options,
+                |b, options| b.iter(|| encode(&bytes, compression, *options)),
+            );
+        }
+        let blob = encode(&bytes, Compression::Brotli(5), StreamOptions::default());
+        // the decoder is driven directly to pick its buffer, without the checksum trailer
+        let stream = &blob[..blob.len() - 8];
+        group.bench_with_input(
+            BenchmarkId::new("decode/brotli-5", buffer_size / KIB),
+            &buffer_size,
+            |b, buffer_size| {
+                b.iter(|| {
+                    let mut decoded = Vec::with_capacity(bytes.len());
+                    brotli::Decompressor::new(stream, *buffer_size)
+                        .read_to_end(&mut decoded)
+                        .unwrap();
+                    decoded
+                });
+            },
+        );
+    }
+    group.finish();
+    println!(
+        "the current default buffer size is {} KiB",
+        BUFFER_SIZE / KIB
+    );
+}
+
+/// The `brotli` window, which trades memory for finding repetitions further apart
+fn windows(c: &mut Criterion) {
+    let bytes = sample(4 * MIB);
+    let mut group = c.benchmark_group("window");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.sample_size(10);
+    for window in [16, 20, 22, 24] {
+        let options = StreamOptions::new().with_window(window);
+        for compression in [Compression::Brotli(5), Compression::Brotli(9)] {
+            let size = encode(&bytes, compression, options).len();
+            println!("{compression} with window {window}: {size} bytes");
+            group.bench_with_input(
+                BenchmarkId::new(compression.to_string(), window),
+                &options,
+                |b, options| b.iter(|| encode(&bytes, compression, *options)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, codecs, buffer_sizes, windows);
+criterion_main!(benches);
@@ -0,0 +1,260 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The codecs an object can be compressed with. Every object is a valid `brotli` stream no
+//! matter the codec, [`Compression::Store`] only writes the bytes as uncompressed meta-blocks,
+//! so objects are always decoded the same way.
+
+use std::{fmt, io::Write, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use storage_common::CompressionConfig;
+
+use crate::{
+    Result, BUFFER_SIZE, COMPRESSION_QUALITY, COMPRESSION_WINDOW, MAX_COMPRESSION_WINDOW,
+    MIN_BUFFER_SIZE, MIN_COMPRESSION_WINDOW,
+};
+
+/// The size of the largest meta-block written by [`Compression::Store`]
+const STORED_BLOCK_SIZE: usize = 1 << 16;
+
+/// How the contents of an object are compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Compression {
+    /// `brotli` with the given quality (`0..=11`)
+    Brotli(u32),
+    /// No compression, e.g. for files that are already compressed
+    Store,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::Brotli(COMPRESSION_QUALITY)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = storage_common::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        if s == "store" {
+            return Ok(Self::Store);
+        }
+        s.strip_prefix("brotli-")
+            .and_then(|quality| quality.parse().ok())
+            .filter(|quality| *quality <= COMPRESSION_QUALITY)
+            .map(Self::Brotli)
+            .ok_or_else(|| {
+                format!("unknown compression '{s}', expected 'store' or 'brotli-0' to 'brotli-11'")
+                    .into()
+            })
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Brotli(quality) => write!(f, "brotli-{quality}"),
+            Self::Store => f.write_str("store"),
+        }
+    }
+}
+
+/// How the `brotli` stream of an object is written. Neither option changes how the object is
+/// decoded, they only trade memory for speed and (for the window) compression ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamOptions {
+    buffer_size: usize,
+    window: u32,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: BUFFER_SIZE,
+            window: COMPRESSION_WINDOW,
+        }
+    }
+}
+
+impl StreamOptions {
+    /// Creates the default options, a [`BUFFER_SIZE`] buffer and a [`COMPRESSION_WINDOW`] window
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size of the buffer the bytes are compressed through, at least
+    /// [`MIN_BUFFER_SIZE`]
+    #[must_use]
+    pub fn with_buffer_size(self, buffer_size: usize) -> Self {
+        Self {
+            buffer_size: buffer_size.max(MIN_BUFFER_SIZE),
+            ..self
+        }
+    }
+
+    /// Sets the `brotli` window size (log2), clamped to [`MIN_COMPRESSION_WINDOW`] to
+    /// [`MAX_COMPRESSION_WINDOW`]
+    #[must_use]
+    pub fn with_window(self, window: u32) -> Self {
+        Self {
+            window: window.clamp(MIN_COMPRESSION_WINDOW, MAX_COMPRESSION_WINDOW),
+            ..self
+        }
+    }
+
+    /// Gets the size of the buffer the bytes are compressed through
+    #[must_use]
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Gets the `brotli` window size (log2)
+    #[must_use]
+    pub fn window(&self) -> u32 {
+        self.window
+    }
+}
+
+impl From<&CompressionConfig> for StreamOptions {
+    fn from(config: &CompressionConfig) -> Self {
+        Self::new()
+            .with_buffer_size(config.buffer_size())
+            .with_window(config.window())
+    }
+}
+
+/// Writes a `brotli` stream made of uncompressed meta-blocks, which any `brotli` decoder reads
+/// back without having to compress anything. [`StoredWriter::finish`] must be called to end the
+/// stream.
+pub(crate) struct StoredWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    started: bool,
+}
+
+impl<W: Write> StoredWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buffer: Vec::with_capacity(STORED_BLOCK_SIZE),
+            started: false,
+        }
+    }
+
+    /// Writes the buffered bytes as a single meta-block
+    fn write_block(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        // ISLAST = 0, MNIBBLES = 4 (`00`), MLEN - 1 in 16 bits and ISUNCOMPRESSED = 1, packed
+        // least significant bit first and padded to a whole byte
+        let len = u32::try_from(self.buffer.len() - 1).expect("block size fits in 16 bits");
+        let mut bits = (len << 3) | (1 << 19);
+        let mut count = 20_usize;
+        if !self.started {
+            // the stream starts with WBITS = 16, a single `0` bit
+            bits <<= 1;
+            count += 1;
+            self.started = true;
+        }
+        let header = bits.to_le_bytes();
+        self.inner.write_all(&header[..count.div_ceil(8)])?;
+        self.inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Writes the remaining bytes and the end of the stream
+    pub(crate) fn finish(mut self) -> std::io::Result<W> {
+        self.write_block()?;
+        // ISLAST = 1 and ISLASTEMPTY = 1, after WBITS if nothing has been written yet
+        let last = if self.started { 0b11 } else { 0b110 };
+        self.inner.write_all(&[last])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for StoredWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(STORED_BLOCK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == STORED_BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn roundtrip(bytes: &[u8]) -> Vec<u8> {
+        let mut writer = StoredWriter::new(Vec::new());
+        writer.write_all(bytes).unwrap();
+        let stored = writer.finish().unwrap();
+        assert!(stored.len() <= bytes.len() + 3 * (bytes.len() / STORED_BLOCK_SIZE + 1) + 1);
+
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(stored.as_slice(), 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn clamps_stream_options() {
+        let options = StreamOptions::new().with_buffer_size(1).with_window(30);
+        assert_eq!(options.buffer_size(), MIN_BUFFER_SIZE);
+        assert_eq!(options.window(), MAX_COMPRESSION_WINDOW);
+        let options = StreamOptions::from(
+            &CompressionConfig::new()
+                .with_buffer_size(1 << 20)
+                .with_window(12),
+        );
+        assert_eq!(options.buffer_size(), 1 << 20);
+        assert_eq!(options.window(), 12);
+    }
+
+    #[test]
+    fn stored_streams_decode() {
+        assert_eq!(roundtrip(&[]), Vec::<u8>::new());
+        assert_eq!(roundtrip(b"hello"), b"hello");
+        let large = (0..STORED_BLOCK_SIZE * 2 + 17)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect::<Vec<_>>();
+        assert_eq!(roundtrip(&large), large);
+        let exact = vec![7u8; STORED_BLOCK_SIZE];
+        assert_eq!(roundtrip(&exact), exact);
+    }
+
+    #[test]
+    fn parses_compression() {
+        for compression in [
+            Compression::Store,
+            Compression::Brotli(0),
+            Compression::Brotli(11),
+        ] {
+            assert_eq!(
+                compression.to_string().parse::<Compression>().unwrap(),
+                compression
+            );
+        }
+        assert_eq!(Compression::default(), Compression::Brotli(11));
+        assert!("brotli-12".parse::<Compression>().is_err());
+        assert!("zstd".parse::<Compression>().is_err());
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Shared `brotli` dictionaries for small content blobs. Small files compress poorly on their
+//! own, since `brotli` has little data to find repetitions in. A [`Dictionary`] trained on
+//! similar files (dotfiles, `JSON` configs, ...) gives the compressor that data up front.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::Result;
+
+/// Lines longer than this are split into pieces of this size when training a [`Dictionary`]
+const MAX_CHUNK_SIZE: usize = 64;
+/// Chunks shorter than this are not worth a place in a [`Dictionary`]
+const MIN_CHUNK_SIZE: usize = 4;
+
+/// A shared `brotli` dictionary, identified by the hash of its bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    id: u64,
+    bytes: Vec<u8>,
+}
+
+impl Dictionary {
+    /// Creates a dictionary out of the given `bytes`
+    #[must_use]
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            id: xstd::hash::fnv1a(&bytes),
+            bytes,
+        }
+    }
+
+    /// Creates the dictionary `id` out of the given `bytes`, e.g. as read back from the store
+    ///
+    /// ## Errors
+    /// - Function returns an error if `bytes` do not hash to `id`.
+    pub fn load(id: u64, bytes: Vec<u8>) -> Result<Self> {
+        let dictionary = Self::new(bytes);
+        if dictionary.id == id {
+            Ok(dictionary)
+        } else {
+            Err(format!(
+                "dictionary {id:016x} is corrupted, its bytes hash to {:016x}",
+                dictionary.id
+            )
+            .into())
+        }
+    }
+
+    /// Trains a dictionary of at most `max_size` bytes out of the given `samples`. The dictionary
+    /// holds the lines (or pieces of long lines) found in at least two samples, the most valuable
+    /// ones last, where `brotli` reaches them with the shortest distances.
+    ///
+    /// ## Errors
+    /// - Function returns an error if the samples have nothing in common.
+    pub fn train<'a>(samples: impl IntoIterator<Item = &'a [u8]>, max_size: usize) -> Result<Self> {
+        let mut counts = HashMap::<&[u8], usize>::new();
+        for sample in samples {
+            let chunks = sample
+                .split_inclusive(|byte| *byte == b'\n')
+                .flat_map(|line| line.chunks(MAX_CHUNK_SIZE))
+                .filter(|chunk| chunk.len() >= MIN_CHUNK_SIZE)
+                .collect::<HashSet<_>>();
+            for chunk in chunks {
+                *counts.entry(chunk).or_default() += 1;
+            }
+        }
+
+        let mut chunks = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .collect::<Vec<_>>();
+        chunks.sort_unstable_by(|(a, a_count), (b, b_count)| {
+            (b_count * b.len(), b).cmp(&(a_count * a.len(), a))
+        });
+
+        let mut size = 0;
+        let mut selected = Vec::new();
+        for (chunk, _) in chunks {
+            if size + chunk.len() <= max_size {
+                size += chunk.len();
+                selected.push(chunk);
+            }
+        }
+        if selected.is_empty() {
+            return Err("the samples share no content to train a dictionary from".into());
+        }
+        Ok(Self::new(
+            selected.into_iter().rev().flatten().copied().collect(),
+        ))
+    }
+
+    /// The id of the dictionary, the `fnv1a` hash of its bytes
+    #[must_use]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The bytes of the dictionary
+    #[must_use]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The size of the dictionary in bytes
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether the dictionary is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trains_on_shared_lines() {
+        let samples: [&[u8]; 3] = [
+            b"[user]\n\tname = a\n\temail = a@example.com\n",
+            b"[user]\n\tname = b\n\temail = b@example.com\n",
+            b"[core]\n\teditor = vim\n",
+        ];
+        let dictionary = Dictionary::train(samples, 1024).unwrap();
+        assert_eq!(dictionary.bytes(), b"[user]\n");
+        assert_eq!(dictionary.id(), xstd::hash::fnv1a(b"[user]\n"));
+        assert_eq!(
+            Dictionary::load(dictionary.id(), b"[user]\n".to_vec()).unwrap(),
+            dictionary
+        );
+        assert!(Dictionary::load(dictionary.id(), b"[core]\n".to_vec()).is_err());
+
+        assert!(Dictionary::train(samples, 4).is_err());
+        assert!(Dictionary::train([b"unique".as_slice()], 1024).is_err());
+    }
+}
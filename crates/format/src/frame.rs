@@ -0,0 +1,873 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! An object in the store is a single `brotli` stream containing the [`FileHeader`] (as plain
+//! bytes), followed by the `rmp` encoded [`FileMeta`], followed by the original file bytes. The
+//! stream is compressed with the [`Compression`] recorded in the [`FileMeta`]. Since format `6`
+//! the stream is followed by a checksum trailer, the `CRC32` of the compressed stream, which is
+//! verified whenever an object is read.
+//!
+//! Since format `10` the file bytes of an object may be empty, the contents being kept in a
+//! content blob instead (see [`FileMeta::content`]). A content blob is framed the same way, but its
+//! stream holds nothing but the original file bytes.
+//!
+//! Since format `11` a content blob may be compressed with a shared [`Dictionary`]. Such a blob
+//! starts with a dictionary header, the magic followed by the id of the dictionary, in front of
+//! the stream. The checksum trailer covers the header as well.
+
+use std::io::{BufReader, Read, Write};
+
+use brotli::{
+    enc::{BrotliEncoderParams, StandardAlloc},
+    CompressorWriter, IoReaderWrapper, IoWriterWrapper,
+};
+
+use storage_common::{write_all_with_progress, Error, ProgressSink};
+use xstd::cast::CastFrom;
+
+use crate::{
+    compression::StoredWriter, Compression, Dictionary, FileHeader, FileMeta, Result,
+    StreamOptions, BUFFER_SIZE,
+};
+
+/// Marks the checksum trailer at the end of objects written since format `6`
+const CHECKSUM_MAGIC: &[u8; 4] = b"SCRC";
+/// The size of the checksum trailer, the magic followed by the `CRC32` (little-endian) of the
+/// compressed stream
+pub const CHECKSUM_SIZE: usize = 8;
+/// Marks the dictionary header at the start of content blobs compressed with a [`Dictionary`]
+/// since format `11`. No `brotli` stream read by this crate starts with `0x11`, which encodes
+/// the window size of a large window stream.
+const DICTIONARY_MAGIC: &[u8; 4] = b"\x11DIC";
+/// The size of the dictionary header, the magic followed by the id (little-endian) of the
+/// dictionary
+const DICTIONARY_HEADER_SIZE: usize = 12;
+/// The highest `brotli` quality used with a [`Dictionary`], the encoder ignores custom
+/// dictionaries at the (`zopfli` based) qualities above
+const MAX_DICTIONARY_QUALITY: u32 = 9;
+
+/// Encodes the given [`FileMeta`] into the bytes stored in an object
+///
+/// ## Errors
+/// - Returns an error if the `rmp_serde` serialization fails
+pub fn encode_meta(meta: &FileMeta) -> Result<Vec<u8>> {
+    Ok(rmp_serde::to_vec(meta)?)
+}
+
+/// Frames and compresses the given parts into the bytes of a store object, using the
+/// [`Compression`] of `meta`
+///
+/// ## Errors
+/// - Function returns an error if any IO operations fail.
+/// - Function returns an error if the `rmp_serde` serialization fails.
+/// - Function returns an error if `brotli` compression fails.
+///
+/// ## Panics
+/// Function panics if the sizes in `header` do not match the sizes of `meta` and `file_bytes`.
+pub fn encode(header: &FileHeader, meta: &FileMeta, file_bytes: &[u8]) -> Result<Vec<u8>> {
+    encode_with_progress(header, meta, file_bytes, StreamOptions::default(), &mut ())
+}
+
+/// Same as [`encode`], writing the stream with `options` and reporting the number of
+/// (uncompressed) bytes compressed so far to `progress`
+///
+/// ## Errors
+/// - Function returns an error if any IO operations fail.
+/// - Function returns an error if the `rmp_serde` serialization fails.
+/// - Function returns an error if `brotli` compression fails.
+///
+/// ## Panics
+/// Function panics if the sizes in `header` do not match the sizes of `meta` and `file_bytes`.
+pub fn encode_with_progress(
+    header: &FileHeader,
+    meta: &FileMeta,
+    file_bytes: &[u8],
+    options: StreamOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<Vec<u8>> {
+    // Convert header to bytes using bytemuck
+    let header_bytes = bytemuck::bytes_of(header);
+    assert_eq!(
+        header_bytes.len(),
+        std::mem::size_of::<FileHeader>(),
+        "header_bytes should be the same size as FileHeader"
+    );
+
+    // Convert metadata to bytes using rmp_serde
+    let meta_bytes = encode_meta(meta)?;
+    assert_eq!(
+        meta_bytes.len(),
+        header.meta_size,
+        "meta bytes should be the size indicated by the header"
+    );
+
+    assert_eq!(
+        file_bytes.len(),
+        header.file_size,
+        "file bytes should be the size indicated by the header"
+    );
+
+    let total_size = std::mem::size_of::<FileHeader>() + file_bytes.len() + meta_bytes.len();
+    let mut bytes = Vec::with_capacity(total_size);
+    bytes.extend_from_slice(header_bytes);
+    bytes.extend_from_slice(&meta_bytes);
+    bytes.extend_from_slice(file_bytes);
+    assert_eq!(
+        bytes.len(),
+        total_size,
+        "bytes.len() should be the expected/calculated total size"
+    );
+
+    compress(&bytes, meta.compression(), options, progress)
+}
+
+/// Compresses `file_bytes` with `compression` into the bytes of a content blob
+///
+/// ## Errors
+/// - Function returns an error if `brotli` compression fails.
+pub fn encode_content(file_bytes: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    encode_content_with_progress(file_bytes, compression, StreamOptions::default(), &mut ())
+}
+
+/// Same as [`encode_content`], writing the stream with `options` and reporting the number of
+/// (uncompressed) bytes compressed so far to `progress`
+///
+/// ## Errors
+/// - Function returns an error if `brotli` compression fails.
+pub fn encode_content_with_progress(
+    file_bytes: &[u8],
+    compression: Compression,
+    options: StreamOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<Vec<u8>> {
+    compress(file_bytes, compression, options, progress)
+}
+
+/// Compresses `file_bytes` with `brotli` at `quality` (at most `9`) and the shared `dictionary`
+/// into the bytes of a content blob, which can only be decoded with the same dictionary again,
+/// see [`decode_content_with`]
+///
+/// ## Errors
+/// - Function returns an error if `brotli` compression fails.
+pub fn encode_content_with_dictionary(
+    file_bytes: &[u8],
+    quality: u32,
+    dictionary: &Dictionary,
+    options: StreamOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(DICTIONARY_HEADER_SIZE + file_bytes.len());
+    bytes.extend_from_slice(DICTIONARY_MAGIC);
+    bytes.extend_from_slice(&dictionary.id().to_le_bytes());
+
+    let params = BrotliEncoderParams {
+        quality: i32::try_from(quality.min(MAX_DICTIONARY_QUALITY)).unwrap_or_default(),
+        lgwin: i32::try_from(options.window()).unwrap_or(i32::MAX),
+        ..BrotliEncoderParams::default()
+    };
+    let total = u64::cast_from(file_bytes.len());
+    progress.progress(0, total);
+    brotli::BrotliCompressCustomIoCustomDict(
+        &mut IoReaderWrapper(&mut { file_bytes }),
+        &mut IoWriterWrapper(&mut bytes),
+        &mut vec![0; options.buffer_size()],
+        &mut vec![0; options.buffer_size()],
+        &params,
+        StandardAlloc::default(),
+        &mut |_, _, _, _| (),
+        dictionary.bytes(),
+        std::io::Error::from(std::io::ErrorKind::UnexpectedEof),
+    )?;
+    progress.progress(total, total);
+    progress.finish();
+
+    append_checksum(&mut bytes);
+    Ok(bytes)
+}
+
+/// Gets the id of the [`Dictionary`] the content blob `bytes` was compressed with, `None` if it
+/// was compressed without one
+#[must_use]
+pub fn content_dictionary(bytes: &[u8]) -> Option<u64> {
+    split_dictionary_header(bytes).map(|(id, _)| id)
+}
+
+/// Compresses `bytes` with `compression` and appends the checksum trailer
+fn compress(
+    bytes: &[u8],
+    compression: Compression,
+    options: StreamOptions,
+    progress: &mut dyn ProgressSink,
+) -> Result<Vec<u8>> {
+    let mut compressed_bytes = Vec::with_capacity(bytes.len());
+    let buffer_size = options.buffer_size();
+    match compression {
+        Compression::Brotli(quality) => {
+            let mut compressor = CompressorWriter::new(
+                &mut compressed_bytes,
+                buffer_size,
+                quality,
+                options.window(),
+            );
+            write_all_with_progress(&mut compressor, bytes, buffer_size, progress)?;
+            compressor.flush()?;
+        }
+        Compression::Store => {
+            let mut writer = StoredWriter::new(&mut compressed_bytes);
+            write_all_with_progress(&mut writer, bytes, buffer_size, progress)?;
+            writer.finish()?;
+        }
+    }
+    append_checksum(&mut compressed_bytes);
+    Ok(compressed_bytes)
+}
+
+fn append_checksum(bytes: &mut Vec<u8>) {
+    let checksum = crc32fast::hash(bytes);
+    bytes.extend_from_slice(CHECKSUM_MAGIC);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+}
+
+/// Decompresses and splits the bytes of a store object back into its parts
+///
+/// ## Errors
+/// - Function returns [`Error::Corrupted`] if the checksum of the object does not match.
+/// - Function returns an error if any IO operations fail.
+/// - Function returns an error if the `brotli` decompression fails.
+/// - Function returns an error if the object is truncated or the sizes in the header are invalid.
+/// - Function returns an error if the `rmp_serde` deserialization fails.
+pub fn decode(bytes: &[u8]) -> Result<(FileHeader, FileMeta, Vec<u8>)> {
+    let decompressed_bytes = decompress(bytes)?;
+    let (header, rest) = FileHeader::try_from_bytes(&decompressed_bytes)?;
+    if rest.len() != header.meta_size.saturating_add(header.file_size) {
+        return Err(format!(
+            "object size mismatch - header expects {} meta byte(s) and {} file byte(s), found {} byte(s)",
+            header.meta_size,
+            header.file_size,
+            rest.len()
+        )
+        .into());
+    }
+    let (meta_bytes, file_bytes) = rest.split_at(header.meta_size);
+
+    let meta = rmp_serde::from_slice(meta_bytes)?;
+    Ok((header, meta, file_bytes.into()))
+}
+
+/// Decompresses the bytes of a content blob back into the original file bytes
+///
+/// ## Errors
+/// - Function returns [`Error::Corrupted`] if the checksum of the blob does not match.
+/// - Function returns an error if the blob has no checksum trailer.
+/// - Function returns an error if any IO operations fail.
+/// - Function returns an error if the `brotli` decompression fails.
+/// - Function returns an error if the blob was compressed with a [`Dictionary`], see
+///   [`decode_content_with`].
+pub fn decode_content(bytes: &[u8]) -> Result<Vec<u8>> {
+    decode_content_with(bytes, None)
+}
+
+/// Same as [`decode_content`], decoding blobs compressed with `dictionary` as well
+///
+/// ## Errors
+/// - Function returns [`Error::Corrupted`] if the checksum of the blob does not match.
+/// - Function returns an error if the blob has no checksum trailer.
+/// - Function returns an error if any IO operations fail.
+/// - Function returns an error if the `brotli` decompression fails.
+/// - Function returns an error if the blob was compressed with a [`Dictionary`] other than
+///   `dictionary`, see [`content_dictionary`].
+pub fn decode_content_with(bytes: &[u8], dictionary: Option<&Dictionary>) -> Result<Vec<u8>> {
+    let (stream, dictionary) = content_stream(bytes, dictionary)?;
+    let mut decompressed_bytes = Vec::with_capacity(stream.len());
+    decompressor(stream, dictionary).read_to_end(&mut decompressed_bytes)?;
+    Ok(decompressed_bytes)
+}
+
+/// Decodes the parts of a store object like [`decode`], but only decompresses up to `max_bytes`
+/// of the original file bytes, e.g. to preview a large file. The [`FileHeader`] still tells the
+/// full size.
+///
+/// ## Errors
+/// - Function returns [`Error::Corrupted`] if the checksum of the object does not match.
+/// - Function returns an error if the `brotli` decompression fails.
+/// - Function returns an error if the object is truncated.
+/// - Function returns an error if the `rmp_serde` deserialization fails.
+pub fn decode_head(bytes: &[u8], max_bytes: usize) -> Result<(FileHeader, FileMeta, Vec<u8>)> {
+    let bytes = verify_checksum(bytes, false)?;
+    let mut decompressor = brotli::Decompressor::new(bytes, BUFFER_SIZE);
+    let (header, meta) = read_parts(&mut decompressor)?;
+    let file_bytes = read_head(decompressor, header.file_size.min(max_bytes))?;
+    if file_bytes.len() < header.file_size.min(max_bytes) {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok((header, meta, file_bytes))
+}
+
+/// Decompresses up to `max_bytes` of the original file bytes out of a content blob, see
+/// [`decode_content`]
+///
+/// ## Errors
+/// - Function returns [`Error::Corrupted`] if the checksum of the blob does not match.
+/// - Function returns an error if the blob has no checksum trailer.
+/// - Function returns an error if the `brotli` decompression fails.
+/// - Function returns an error if the blob was compressed with a [`Dictionary`], see
+///   [`decode_content_head_with`].
+pub fn decode_content_head(bytes: &[u8], max_bytes: usize) -> Result<Vec<u8>> {
+    decode_content_head_with(bytes, max_bytes, None)
+}
+
+/// Same as [`decode_content_head`], decoding blobs compressed with `dictionary` as well
+///
+/// ## Errors
+/// - Function returns [`Error::Corrupted`] if the checksum of the blob does not match.
+/// - Function returns an error if the blob has no checksum trailer.
+/// - Function returns an error if the `brotli` decompression fails.
+/// - Function returns an error if the blob was compressed with a [`Dictionary`] other than
+///   `dictionary`.
+pub fn decode_content_head_with(
+    bytes: &[u8],
+    max_bytes: usize,
+    dictionary: Option<&Dictionary>,
+) -> Result<Vec<u8>> {
+    let (stream, dictionary) = content_stream(bytes, dictionary)?;
+    read_head(decompressor(stream, dictionary), max_bytes)
+}
+
+/// Verifies the checksum of a content blob and splits off its dictionary header, making sure
+/// the blob was compressed with `dictionary` if it has one. Content blobs came after the
+/// checksum, so a blob without one is corrupted.
+fn content_stream<'a>(
+    bytes: &'a [u8],
+    dictionary: Option<&'a Dictionary>,
+) -> Result<(&'a [u8], Option<&'a Dictionary>)> {
+    let bytes = verify_checksum(bytes, true)?;
+    let Some((id, stream)) = split_dictionary_header(bytes) else {
+        return Ok((bytes, None));
+    };
+    match dictionary {
+        Some(dictionary) if dictionary.id() == id => Ok((stream, Some(dictionary))),
+        _ => Err(format!("content blob was compressed with dictionary {id:016x}").into()),
+    }
+}
+
+/// Splits the dictionary header off the bytes of a content blob, `None` if it has none
+fn split_dictionary_header(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let (header, stream) = bytes.split_at_checked(DICTIONARY_HEADER_SIZE)?;
+    let (magic, id) = header.split_at(DICTIONARY_MAGIC.len());
+    if magic != DICTIONARY_MAGIC {
+        return None;
+    }
+    Some((u64::from_le_bytes(id.try_into().ok()?), stream))
+}
+
+fn decompressor<R: Read>(reader: R, dictionary: Option<&Dictionary>) -> brotli::Decompressor<R> {
+    match dictionary {
+        Some(dictionary) => brotli::Decompressor::new_with_custom_dict(
+            reader,
+            BUFFER_SIZE,
+            dictionary.bytes().to_vec().into(),
+        ),
+        None => brotli::Decompressor::new(reader, BUFFER_SIZE),
+    }
+}
+
+/// Reads at most `max_bytes` out of `reader`, leaving the rest of it unread
+fn read_head(reader: impl Read, max_bytes: usize) -> Result<Vec<u8>> {
+    let mut head = Vec::new();
+    reader
+        .take(u64::try_from(max_bytes).unwrap_or(u64::MAX))
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Verifies the checksum of `bytes` and decompresses the stream before it
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let bytes = verify_checksum(bytes, false)?;
+    let mut decompressed_bytes = Vec::with_capacity(bytes.len());
+    let mut reader = BufReader::new(bytes);
+
+    let mut decompressor = brotli::Decompressor::new(&mut reader, BUFFER_SIZE);
+    decompressor.read_to_end(&mut decompressed_bytes)?;
+    Ok(decompressed_bytes)
+}
+
+/// Reads only the [`FileHeader`] and the [`FileMeta`] from a store object without decompressing
+/// the original file bytes. The rest of the object is still read to verify its checksum.
+///
+/// ## Errors
+/// - Returns [`Error::Corrupted`] if the checksum of the object does not match.
+/// - Returns an IO error if the decompressor fails to read the specified number of bytes.
+/// - Returns a Serde error if `rmp_serde` fails to deserialize the [`FileMeta`]
+pub fn read_header_and_meta(reader: impl Read) -> Result<(FileHeader, FileMeta)> {
+    let mut reader = ChecksumReader::new(reader);
+    let parts = read_parts(brotli::Decompressor::new(&mut reader, BUFFER_SIZE));
+    // a corrupted object most likely fails to decompress too, the checksum is the better error
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    reader.verify()?;
+    parts
+}
+
+/// Same as [`read_header_and_meta`], but leaves the rest of the object unread, so its checksum is
+/// not verified. Meant for scanning many objects, see [`has_checksum`] for a quick check that an
+/// object was not cut short.
+///
+/// ## Errors
+/// - Returns an IO error if the decompressor fails to read the specified number of bytes.
+/// - Returns a Serde error if `rmp_serde` fails to deserialize the [`FileMeta`]
+pub fn peek_header_and_meta(reader: impl Read) -> Result<(FileHeader, FileMeta)> {
+    read_parts(brotli::Decompressor::new(
+        ChecksumReader::new(reader),
+        BUFFER_SIZE,
+    ))
+}
+
+/// Checks whether `tail`, the end of a store object or content blob, is a checksum trailer. Every
+/// object of a store in format [`CHECKSUM_FORMAT_VERSION`](crate::CHECKSUM_FORMAT_VERSION) or
+/// later ends with one.
+#[must_use]
+pub fn has_checksum(tail: &[u8]) -> bool {
+    tail.len()
+        .checked_sub(CHECKSUM_SIZE)
+        .is_some_and(|split| parse_trailer(&tail[split..]).is_some())
+}
+
+fn read_parts(mut reader: impl Read) -> Result<(FileHeader, FileMeta)> {
+    let mut header_buf = vec![0; std::mem::size_of::<FileHeader>()];
+    reader.read_exact(&mut header_buf)?;
+    let header = FileHeader::try_from_bytes_exact(&header_buf)?;
+
+    // the buffer only grows with the bytes actually read, a corrupted size must not allocate
+    let mut meta_buf = Vec::new();
+    let meta_size = u64::try_from(header.meta_size).unwrap_or(u64::MAX);
+    reader.take(meta_size).read_to_end(&mut meta_buf)?;
+    if meta_buf.len() != header.meta_size {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    let meta: FileMeta = rmp_serde::from_slice(&meta_buf)?;
+    Ok((header, meta))
+}
+
+/// Splits the checksum trailer off the bytes of a store object and verifies it. Objects written
+/// before format `6` have no checksum and are returned as they are, unless it is `required`.
+fn verify_checksum(bytes: &[u8], required: bool) -> Result<&[u8]> {
+    let trailer = bytes
+        .len()
+        .checked_sub(CHECKSUM_SIZE)
+        .and_then(|split| Some((split, parse_trailer(&bytes[split..])?)));
+    match trailer {
+        Some((split, expected)) => {
+            let stream = &bytes[..split];
+            check(expected, crc32fast::hash(stream))?;
+            Ok(stream)
+        }
+        None if required => Err("missing the checksum trailer".into()),
+        None => Ok(bytes),
+    }
+}
+
+/// Gets the checksum stored in `trailer`, `None` if it is not a checksum trailer
+fn parse_trailer(trailer: &[u8]) -> Option<u32> {
+    let (magic, checksum) = trailer.split_at_checked(CHECKSUM_MAGIC.len())?;
+    if magic != CHECKSUM_MAGIC {
+        return None;
+    }
+    Some(u32::from_le_bytes(checksum.try_into().ok()?))
+}
+
+fn check(expected: u32, actual: u32) -> Result {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(Error::Corrupted {
+            path: None,
+            expected,
+            actual,
+        })
+    }
+}
+
+/// Computes the checksum of everything read through it, and holds back the last
+/// [`CHECKSUM_SIZE`] bytes until the end, so a checksum trailer is never handed to the
+/// decompressor (which rejects data after the end of the stream, e.g. of an empty file)
+struct ChecksumReader<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    /// The bytes read from `inner` but not handed out yet
+    pending: Vec<u8>,
+    /// Whether `inner` has reached its end
+    ended: bool,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+            pending: Vec::with_capacity(BUFFER_SIZE + CHECKSUM_SIZE),
+            ended: false,
+        }
+    }
+
+    /// Verifies the checksum once everything has been read
+    fn verify(self) -> Result {
+        match parse_trailer(&self.pending) {
+            Some(expected) => check(expected, self.hasher.finalize()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while !self.ended && self.pending.len() <= CHECKSUM_SIZE {
+            // read straight into `pending`, the buffer is too large for the stack
+            let filled = self.pending.len();
+            self.pending.resize(filled + BUFFER_SIZE, 0);
+            let read = self.inner.read(&mut self.pending[filled..]);
+            self.pending
+                .truncate(filled + read.as_ref().map_or(0, |read| *read));
+            self.ended = read? == 0;
+        }
+        // objects written before format `6` have no trailer, their last bytes are part of the
+        // stream as well
+        let held_back = if !self.ended || parse_trailer(&self.pending).is_some() {
+            CHECKSUM_SIZE
+        } else {
+            0
+        };
+        let available = self.pending.len().saturating_sub(held_back).min(buf.len());
+        buf[..available].copy_from_slice(&self.pending[..available]);
+        self.hasher.update(&self.pending[..available]);
+        self.pending.drain(..available);
+        Ok(available)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContentRef, FileKind, FileVersion, FsMetadata, Timestamp};
+
+    fn fixture_parts() -> (FileHeader, FileMeta, Vec<u8>) {
+        let ts = Timestamp::new(1_680_000_000);
+        let meta = FileMeta::new(
+            FileVersion::new() + 2u32,
+            ts,
+            "/tmp/compat.txt".into(),
+            FsMetadata::new(Some(ts), Some(ts), None, 5, FileKind::File),
+        );
+        let file_bytes = b"hello".to_vec();
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), file_bytes.len());
+        (header, meta, file_bytes)
+    }
+
+    /// The `rmp` encoding of the fixture [`FileMeta`] in format version 1
+    const META_V1: &[u8] = &[
+        0x94, 0x03, 0xce, 0x64, 0x22, 0xc4, 0x00, 0xaf, 0x2f, 0x74, 0x6d, 0x70, 0x2f, 0x63, 0x6f,
+        0x6d, 0x70, 0x61, 0x74, 0x2e, 0x74, 0x78, 0x74, 0x95, 0xce, 0x64, 0x22, 0xc4, 0x00, 0xce,
+        0x64, 0x22, 0xc4, 0x00, 0xc0, 0x05, 0xa4, 0x46, 0x69, 0x6c, 0x65,
+    ];
+
+    /// A complete store object written by format version 1 (on a little-endian 64-bit target)
+    const OBJECT_V1: &[u8] = &[
+        0x8b, 0x1e, 0x80, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x94, 0x03, 0xce, 0x64, 0x22, 0xc4, 0x00, 0xaf, 0x2f, 0x74, 0x6d,
+        0x70, 0x2f, 0x63, 0x6f, 0x6d, 0x70, 0x61, 0x74, 0x2e, 0x74, 0x78, 0x74, 0x95, 0xce, 0x64,
+        0x22, 0xc4, 0x00, 0xce, 0x64, 0x22, 0xc4, 0x00, 0xc0, 0x05, 0xa4, 0x46, 0x69, 0x6c, 0x65,
+        0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x03,
+    ];
+
+    #[test]
+    fn meta_encoding_is_stable() {
+        let (_, meta, _) = fixture_parts();
+        assert_eq!(encode_meta(&meta).unwrap(), META_V1);
+    }
+
+    #[test]
+    #[cfg(all(target_endian = "little", target_pointer_width = "64"))]
+    fn decodes_v1_object() {
+        assert!(crate::is_readable(1));
+        let (expected_header, expected_meta, expected_bytes) = fixture_parts();
+
+        let (header, meta, bytes) = decode(OBJECT_V1).unwrap();
+        assert_eq!(header, expected_header);
+        assert_eq!(meta.version(), expected_meta.version());
+        assert_eq!(meta.created(), expected_meta.created());
+        assert_eq!(meta.path(), expected_meta.path());
+        assert_eq!(meta.fs_meta().size(), 5);
+        assert_eq!(meta.fs_meta().accessed(), None);
+        assert_eq!(meta.compression(), Compression::default());
+        xstd::assert_bytes_eq!(bytes, expected_bytes);
+
+        let (header, meta) = read_header_and_meta(OBJECT_V1).unwrap();
+        assert_eq!(header, expected_header);
+        assert_eq!(meta.path(), expected_meta.path());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let (header, meta, bytes) = fixture_parts();
+        let object = encode(&header, &meta, &bytes).unwrap();
+        let (decoded_header, decoded_meta, decoded_bytes) = decode(&object).unwrap();
+        assert_eq!(decoded_header, header);
+        assert_eq!(decoded_meta.path(), meta.path());
+        xstd::assert_bytes_eq!(decoded_bytes, bytes);
+    }
+
+    #[test]
+    fn roundtrips_stream_options() {
+        let (header, meta, bytes) = fixture_parts();
+        for options in [
+            StreamOptions::new().with_buffer_size(1),
+            StreamOptions::new().with_window(10),
+            StreamOptions::new()
+                .with_buffer_size(1 << 20)
+                .with_window(24),
+        ] {
+            let object = encode_with_progress(&header, &meta, &bytes, options, &mut ()).unwrap();
+            xstd::assert_bytes_eq!(decode(&object).unwrap().2, bytes);
+            let blob =
+                encode_content_with_progress(&bytes, Compression::Store, options, &mut ()).unwrap();
+            xstd::assert_bytes_eq!(decode_content(&blob).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn decodes_head() {
+        let (header, meta, bytes) = fixture_parts();
+        let object = encode(&header, &meta, &bytes).unwrap();
+        let (head_header, head_meta, head) = decode_head(&object, 4).unwrap();
+        assert_eq!(head_header.file_size, bytes.len());
+        assert_eq!(head_meta.path(), meta.path());
+        xstd::assert_bytes_eq!(head, &bytes[..4]);
+        assert_eq!(decode_head(&object, usize::MAX).unwrap().2, bytes);
+
+        let blob = encode_content(&bytes, Compression::default()).unwrap();
+        xstd::assert_bytes_eq!(decode_content_head(&blob, 4).unwrap(), &bytes[..4]);
+        assert_eq!(decode_content_head(&blob, 0).unwrap(), b"");
+
+        let mut corrupted = blob.clone();
+        corrupted[0] ^= 0xff;
+        assert!(decode_content_head(&corrupted, 4).is_err());
+    }
+
+    #[test]
+    fn roundtrips_subsecond_timestamps() {
+        let ts = Timestamp::with_nanos(1_680_000_000, 123_456_789);
+        let meta = FileMeta::new(
+            FileVersion::new(),
+            ts,
+            "/tmp/compat.txt".into(),
+            FsMetadata::new(Some(ts), None, None, 0, FileKind::File),
+        );
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), 0);
+        let (_, decoded, _) = decode(&encode(&header, &meta, &[]).unwrap()).unwrap();
+        assert_eq!(decoded.created(), &ts);
+        assert_eq!(decoded.fs_meta().created(), Some(ts));
+    }
+
+    #[test]
+    fn roundtrips_permissions() {
+        let (_, meta, bytes) = fixture_parts();
+        let permissions = crate::Permissions::new(true).with_unix(0o100_444, 1000, 100);
+        let meta = FileMeta::new(
+            *meta.version(),
+            *meta.created(),
+            meta.path().clone(),
+            meta.fs_meta().clone().with_permissions(permissions),
+        );
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), bytes.len());
+        let (_, decoded, _) = decode(&encode(&header, &meta, &bytes).unwrap()).unwrap();
+        assert_eq!(decoded.fs_meta().permissions(), Some(permissions));
+    }
+
+    #[test]
+    fn roundtrips_xattrs() {
+        let (_, meta, bytes) = fixture_parts();
+        let xattrs = [("user.tag".to_string(), b"red".to_vec())].into();
+        let meta = FileMeta::new(
+            *meta.version(),
+            *meta.created(),
+            meta.path().clone(),
+            FsMetadata::new(None, None, None, 5, FileKind::File).with_xattrs(xattrs),
+        );
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), bytes.len());
+        let (_, decoded, _) = decode(&encode(&header, &meta, &bytes).unwrap()).unwrap();
+        assert_eq!(decoded.fs_meta(), meta.fs_meta());
+        assert_eq!(decoded.fs_meta().xattrs().unwrap()["user.tag"], b"red");
+    }
+
+    #[test]
+    fn roundtrips_stored_objects() {
+        let (_, meta, bytes) = fixture_parts();
+        let meta = meta.with_compression(Compression::Store);
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), bytes.len());
+        let object = encode(&header, &meta, &bytes).unwrap();
+        assert!(object.windows(bytes.len()).any(|window| window == bytes));
+
+        let (_, decoded, decoded_bytes) = decode(&object).unwrap();
+        assert_eq!(decoded.compression(), Compression::Store);
+        assert_eq!(decoded.id(), meta.id());
+        xstd::assert_bytes_eq!(decoded_bytes, bytes);
+        let (_, decoded) = read_header_and_meta(object.as_slice()).unwrap();
+        assert_eq!(decoded.compression(), Compression::Store);
+    }
+
+    #[test]
+    fn roundtrips_meta_revisions() {
+        let (_, mut meta, bytes) = fixture_parts();
+        assert_eq!(meta.meta_revision(), 0);
+        let permissions = crate::Permissions::new(true);
+        meta.revise_fs_meta(meta.fs_meta().clone().with_permissions(permissions));
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), bytes.len());
+        let (_, decoded, _) = decode(&encode(&header, &meta, &bytes).unwrap()).unwrap();
+        assert_eq!(decoded.meta_revision(), 1);
+        assert_eq!(decoded.version(), meta.version());
+        assert_eq!(decoded.id(), meta.id());
+        assert_eq!(decoded.fs_meta().permissions(), Some(permissions));
+    }
+
+    #[test]
+    fn roundtrips_deletions() {
+        let (_, mut meta, _) = fixture_parts();
+        assert!(!meta.is_deleted());
+        meta.mark_deleted();
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), 0);
+        let object = encode(&header, &meta, &[]).unwrap();
+        let (_, decoded, bytes) = decode(&object).unwrap();
+        assert!(decoded.is_deleted());
+        assert_eq!(decoded.id(), meta.id());
+        assert_eq!(decoded.meta_revision(), 0);
+        assert!(bytes.is_empty());
+
+        // without contents the stream ends right before the checksum trailer
+        let (decoded_header, decoded) = read_header_and_meta(object.as_slice()).unwrap();
+        assert_eq!(decoded_header, header);
+        assert!(decoded.is_deleted());
+    }
+
+    #[test]
+    fn roundtrips_content_blobs() {
+        let (_, mut meta, bytes) = fixture_parts();
+        for compression in [Compression::Store, Compression::Brotli(5)] {
+            let blob = encode_content(&bytes, compression).unwrap();
+            xstd::assert_bytes_eq!(decode_content(&blob).unwrap(), bytes);
+        }
+
+        let content = ContentRef::new(xstd::hash::fnv1a(&bytes), bytes.len() as u64, 42);
+        meta.set_content(content);
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), 0);
+        let (_, decoded, contents) = decode(&encode(&header, &meta, &[]).unwrap()).unwrap();
+        assert_eq!(decoded.content(), Some(content));
+        assert_eq!(decoded.id(), meta.id());
+        assert!(!decoded.is_deleted());
+        assert!(contents.is_empty());
+        assert_eq!(content.name(), format!("{:016x}.blob", content.hash()));
+
+        let content = content.with_digest([0xab; 32]);
+        meta.set_content(content);
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), 0);
+        let (_, decoded, _) = decode(&encode(&header, &meta, &[]).unwrap()).unwrap();
+        assert_eq!(decoded.content(), Some(content));
+        assert_eq!(content.name(), format!("{}.blob", "ab".repeat(32)));
+    }
+
+    #[test]
+    fn roundtrips_dictionary_blobs() {
+        let config = |name: &str| {
+            format!("{{\n  \"name\": \"{name}\",\n  \"editor.fontSize\": 14,\n  \"editor.tabSize\": 4,\n  \"files.autoSave\": \"afterDelay\"\n}}\n")
+        };
+        let samples = ["a", "b", "c"].map(config);
+        let dictionary =
+            Dictionary::train(samples.iter().map(String::as_bytes), 16 * 1024).unwrap();
+        let bytes = config("d").into_bytes();
+
+        let plain = encode_content(&bytes, Compression::Brotli(11)).unwrap();
+        let blob = encode_content_with_dictionary(
+            &bytes,
+            11,
+            &dictionary,
+            StreamOptions::default(),
+            &mut (),
+        )
+        .unwrap();
+        assert!(
+            blob.len() < plain.len(),
+            "{} >= {}",
+            blob.len(),
+            plain.len()
+        );
+        assert_eq!(content_dictionary(&blob), Some(dictionary.id()));
+        assert_eq!(content_dictionary(&plain), None);
+
+        xstd::assert_bytes_eq!(
+            decode_content_with(&blob, Some(&dictionary)).unwrap(),
+            bytes
+        );
+        xstd::assert_bytes_eq!(
+            decode_content_head_with(&blob, 4, Some(&dictionary)).unwrap(),
+            &bytes[..4]
+        );
+        xstd::assert_bytes_eq!(
+            decode_content_with(&plain, Some(&dictionary)).unwrap(),
+            bytes
+        );
+        assert!(decode_content(&blob).is_err());
+        let other = Dictionary::new(b"something else".to_vec());
+        assert!(decode_content_with(&blob, Some(&other)).is_err());
+
+        let options = StreamOptions::new().with_window(10).with_buffer_size(1);
+        let large = Dictionary::new(vec![b'x'; 4096]);
+        let blob = encode_content_with_dictionary(&bytes, 5, &large, options, &mut ()).unwrap();
+        xstd::assert_bytes_eq!(decode_content_with(&blob, Some(&large)).unwrap(), bytes);
+
+        let mut corrupted = blob.clone();
+        corrupted[4] ^= 0xff;
+        let err = decode_content_with(&corrupted, Some(&large)).unwrap_err();
+        assert!(matches!(err, Error::Corrupted { .. }), "{err}");
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let (header, meta, bytes) = fixture_parts();
+        let object = encode(&header, &meta, &bytes).unwrap();
+        assert_eq!(&object[object.len() - CHECKSUM_SIZE..][..4], CHECKSUM_MAGIC);
+        assert!(has_checksum(&object));
+        assert!(!has_checksum(&object[..object.len() - 1]));
+        assert!(read_header_and_meta(object.as_slice()).is_ok());
+        assert!(peek_header_and_meta(object.as_slice()).is_ok());
+
+        for position in [0, object.len() / 2, object.len() - 1] {
+            let mut corrupted = object.clone();
+            corrupted[position] ^= 0x10;
+            let err = decode(&corrupted).unwrap_err();
+            assert!(matches!(err, Error::Corrupted { path: None, .. }), "{err}");
+            let err = read_header_and_meta(corrupted.as_slice()).unwrap_err();
+            assert!(matches!(err, Error::Corrupted { .. }), "{err}");
+        }
+
+        // content blobs always have a checksum, objects only since format `6`
+        let blob = encode_content(&bytes, Compression::Brotli(5)).unwrap();
+        assert!(decode_content(&blob[..blob.len() - CHECKSUM_SIZE]).is_err());
+        assert!(decode(&object[..object.len() - CHECKSUM_SIZE]).is_ok());
+    }
+
+    #[test]
+    fn rejects_mismatched_sizes() {
+        let (_, meta, bytes) = fixture_parts();
+        let header = FileHeader::new(encode_meta(&meta).unwrap().len(), bytes.len());
+        let mut frame = bytemuck::bytes_of(&FileHeader::new(header.meta_size, 100)).to_vec();
+        frame.extend_from_slice(&encode_meta(&meta).unwrap());
+        frame.extend_from_slice(&bytes);
+
+        let mut object = Vec::new();
+        {
+            let mut compressor = CompressorWriter::new(&mut object, BUFFER_SIZE, 1, 22);
+            compressor.write_all(&frame).unwrap();
+        }
+        assert!(decode(&object).is_err());
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{fmt::Write, path::Path};
+
+use crate::{FileVersion, CONTENT_EXTENSION, DICTIONARY_EXTENSION, OBJECT_EXTENSION};
+
+/// Computes the stable hash used to identify the original file at `path` in the store
+#[must_use]
+pub fn path_hash(path: &Path) -> u64 {
+    xstd::hash::fnv1a(path.to_string_lossy().as_bytes())
+}
+
+/// The name of the object in the store holding `version` of the file at `path`
+#[must_use]
+pub fn object_name(path: &Path, version: FileVersion) -> String {
+    format!("{:016x}-{version}.{OBJECT_EXTENSION}", path_hash(path))
+}
+
+/// The name of the content blob in the store holding the contents hashing to `hash`, see
+/// [`ContentRef`](crate::ContentRef)
+#[must_use]
+pub fn content_name(hash: u64) -> String {
+    format!("{hash:016x}.{CONTENT_EXTENSION}")
+}
+
+/// The name of the content blob in the store holding the contents with the BLAKE3 `digest`, see
+/// [`ContentRef::digest`](crate::ContentRef::digest)
+#[must_use]
+pub fn content_digest_name(digest: &[u8; 32]) -> String {
+    let mut name = digest.iter().fold(String::new(), |mut name, b| {
+        let _ = write!(name, "{b:02x}");
+        name
+    });
+    let _ = write!(name, ".{CONTENT_EXTENSION}");
+    name
+}
+
+/// The name of the shared compression dictionary `id` in the store, see
+/// [`Dictionary`](crate::Dictionary)
+#[must_use]
+pub fn dictionary_name(id: u64) -> String {
+    format!("{id:016x}.{DICTIONARY_EXTENSION}")
+}
@@ -38,7 +38,7 @@ impl FileHeader {
     ///
     /// ## Errors
     /// - Errors if bytemuck is unable to convert slice to [`FileHeader`] or if the slice
-    /// is not the correct size
+    ///   is not the correct size
     pub fn try_from_bytes_exact(bytes: &[u8]) -> Result<Self> {
         let this: Self = bytemuck::try_pod_read_unaligned(bytes).map_err_to_string()?;
         Ok(this)
@@ -0,0 +1,124 @@
+//! Storage-Format
+//!
+//!  The on-disk format of the backup store: the [`FileHeader`], the [`FileMeta`], the
+//!  framing (and compression) of the two together with the original file bytes, and the
+//!  naming of objects in the store. This crate deliberately does not depend on the watcher,
+//!  so external tools can read and write store objects on their own.
+//!
+//!  Any change to the bytes produced by this crate **must** bump [`FORMAT_VERSION`], and
+//!  [`MIN_READABLE_FORMAT_VERSION`] documents the oldest format that can still be read.
+#![warn(
+    clippy::all,
+    clippy::pedantic,
+    clippy::perf,
+    missing_copy_implementations,
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::missing_safety_doc,
+    rustdoc::all,
+    rust_2021_compatibility
+)]
+#![allow(clippy::module_name_repetitions, clippy::similar_names)]
+#![cfg_attr(
+    test,
+    allow(
+        unused,
+        dead_code,
+        clippy::all,
+        clippy::pedantic,
+        clippy::perf,
+        missing_copy_implementations,
+        missing_debug_implementations,
+        missing_docs,
+        rust_2018_idioms,
+        unreachable_pub,
+        clippy::missing_errors_doc,
+        clippy::missing_panics_doc,
+        clippy::missing_safety_doc,
+        rustdoc::all,
+        rust_2021_compatibility
+    )
+)]
+
+mod compression;
+mod dictionary;
+mod frame;
+mod hash;
+mod header;
+mod meta;
+mod version;
+#[cfg(feature = "xattr")]
+mod xattrs;
+
+pub use compression::{Compression, StreamOptions};
+pub use dictionary::Dictionary;
+pub use frame::{
+    content_dictionary, decode, decode_content, decode_content_head, decode_content_head_with,
+    decode_content_with, decode_head, encode, encode_content, encode_content_with_dictionary,
+    encode_content_with_progress, encode_meta, encode_with_progress, has_checksum,
+    peek_header_and_meta, read_header_and_meta, CHECKSUM_SIZE,
+};
+pub use hash::{content_digest_name, content_name, dictionary_name, object_name, path_hash};
+pub use header::FileHeader;
+pub use meta::{ContentRef, FileKind, FileMeta, FsMetadata, Permissions};
+pub use version::{
+    FileVersion, Saturating, SaturatingFileVersion, VersionStrategy, Wrapping, WrappingFileVersion,
+};
+pub use xstd::id_gen::UniqueId;
+
+pub(crate) use storage_common::{Result, Timestamp};
+
+/// The version of the on-disk format written by this crate
+///
+/// - `1`: timestamps are whole seconds
+/// - `2`: timestamps may carry nanoseconds, whole seconds are still written as in `1`
+/// - `3`: file metadata may carry permissions and ownership
+/// - `4`: backup metadata may carry a [`UniqueId`]
+/// - `5`: backup metadata may record the [`Compression`] used, objects may be stored uncompressed
+/// - `6`: objects end with a checksum of the compressed stream
+/// - `7`: backup metadata may record a metadata revision, see [`FileMeta::revise_fs_meta`]
+/// - `8`: file metadata may carry extended attributes, see [`FsMetadata::xattrs`]
+/// - `9`: backup metadata may mark the deletion of a file, see [`FileMeta::is_deleted`]
+/// - `10`: the contents of a backup may be kept in a shared content blob, see
+///   [`FileMeta::content`]
+/// - `11`: content blobs may be compressed with a shared [`Dictionary`]
+/// - `12`: content blobs are named after the BLAKE3 digest of their contents, see
+///   [`ContentRef::digest`]
+pub const FORMAT_VERSION: u32 = 12;
+/// The oldest version of the on-disk format that this crate is able to read
+pub const MIN_READABLE_FORMAT_VERSION: u32 = 1;
+/// The first version of the on-disk format whose objects end with a checksum, see
+/// [`has_checksum`]
+pub const CHECKSUM_FORMAT_VERSION: u32 = 6;
+/// The file extension of the objects in a store
+pub const OBJECT_EXTENSION: &str = "bak";
+/// The file extension of the content blobs in a store
+pub const CONTENT_EXTENSION: &str = "blob";
+/// The file extension of the shared compression dictionaries in a store
+pub const DICTIONARY_EXTENSION: &str = "dict";
+/// The buffer size used for compression and decompression, unless [`StreamOptions`] say
+/// otherwise. Picked with the `codec` benchmark: `brotli` compresses fastest through a 64 KiB
+/// buffer, decompression does not depend on it much.
+pub const BUFFER_SIZE: usize = 64 * 1024;
+/// The `brotli` quality used when compressing objects, unless their metadata says otherwise
+pub const COMPRESSION_QUALITY: u32 = 11;
+/// The `brotli` window size (log2) used when compressing objects, unless [`StreamOptions`] say
+/// otherwise
+pub const COMPRESSION_WINDOW: u32 = 22;
+/// The smallest `brotli` window size (log2) objects can be compressed with
+pub const MIN_COMPRESSION_WINDOW: u32 = 10;
+/// The largest `brotli` window size (log2) objects can be compressed with, larger windows need
+/// a decoder supporting the large window extension
+pub const MAX_COMPRESSION_WINDOW: u32 = 24;
+/// The smallest buffer size used for compression
+pub const MIN_BUFFER_SIZE: usize = 1024;
+
+/// Checks whether objects written with format `version` can be read by this crate
+#[must_use]
+pub fn is_readable(version: u32) -> bool {
+    (MIN_READABLE_FORMAT_VERSION..=FORMAT_VERSION).contains(&version)
+}
@@ -0,0 +1,738 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{FileType, Metadata},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use xstd::{hash::fnv1a, id_gen::UniqueId};
+
+use crate::{Compression, FileVersion, Result, Timestamp};
+
+/// A serializable version of [`std::fs::Metadata`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FsMetadata {
+    created: Option<Timestamp>,
+    modified: Option<Timestamp>,
+    accessed: Option<Timestamp>,
+    size: u64,
+    file_type: FileKind,
+    /// Missing in objects written before format version `3`, and left out when unknown so such
+    /// metadata is still encoded exactly as before
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    permissions: Option<Permissions>,
+    /// Missing in objects written before format version `8`, and only captured with the `xattr`
+    /// feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    xattrs: Option<BTreeMap<String, Vec<u8>>>,
+}
+
+impl FsMetadata {
+    /// Creates a new [`FsMetadata`] from its individual parts
+    #[must_use]
+    pub fn new(
+        created: Option<Timestamp>,
+        modified: Option<Timestamp>,
+        accessed: Option<Timestamp>,
+        size: u64,
+        file_type: FileKind,
+    ) -> Self {
+        Self {
+            created,
+            modified,
+            accessed,
+            size,
+            file_type,
+            permissions: None,
+            xattrs: None,
+        }
+    }
+
+    /// Sets the [`Permissions`] of the file
+    #[must_use]
+    pub fn with_permissions(self, permissions: Permissions) -> Self {
+        Self {
+            permissions: Some(permissions),
+            ..self
+        }
+    }
+
+    /// Sets the extended attributes of the file. The metadata is encoded positionally, so the
+    /// permissions are stored as well, otherwise the attributes would be read back in their place.
+    #[must_use]
+    pub fn with_xattrs(self, xattrs: BTreeMap<String, Vec<u8>>) -> Self {
+        Self {
+            permissions: Some(self.permissions.unwrap_or_default()),
+            xattrs: Some(xattrs),
+            ..self
+        }
+    }
+
+    /// Captures the extended attributes of the file at `path`, of the link itself if this is the
+    /// metadata of a [`FileKind::Symlink`]. Nothing is captured if the attributes cannot be read,
+    /// e.g. on a file system without them, so a backup never fails because of them.
+    #[cfg(feature = "xattr")]
+    #[must_use]
+    pub fn with_xattrs_of(self, path: impl AsRef<Path>) -> Self {
+        let follow_links = self.file_type != FileKind::Symlink;
+        match crate::xattrs::read(path.as_ref(), follow_links) {
+            Ok(xattrs) => self.with_xattrs(xattrs),
+            Err(_) => self,
+        }
+    }
+
+    /// Creates a new [`FsMetadata`] by retrieving the [`std::fs::Metadata`]. With the `xattr`
+    /// feature the extended attributes are captured as well.
+    ///
+    /// ## Errors
+    /// - This function will return an error if [`std::fs::metadata`] fails.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let meta = std::fs::metadata(path)?;
+        let this = Self::from_metadata(&meta);
+        #[cfg(feature = "xattr")]
+        let this = this.with_xattrs_of(path);
+        Ok(this)
+    }
+
+    /// Converts a [`Metadata`](std::fs::Metadata) object into this [serializable version](FsMetadata)
+    pub fn from_metadata(meta: &Metadata) -> Self {
+        let created = meta.created().map(std::convert::Into::into).ok();
+        let modified = meta.modified().map(std::convert::Into::into).ok();
+        let accessed = meta.accessed().map(std::convert::Into::into).ok();
+        let size = meta.len();
+        let file_type = meta.into();
+        let permissions = Some(Permissions::from_metadata(meta));
+
+        Self {
+            created,
+            modified,
+            accessed,
+            size,
+            file_type,
+            permissions,
+            xattrs: None,
+        }
+    }
+
+    /// Gets the creation time of the file if available
+    #[must_use]
+    pub fn created(&self) -> Option<Timestamp> {
+        self.created
+    }
+
+    /// Gets the last modified time of the file if available
+    #[must_use]
+    pub fn modified(&self) -> Option<Timestamp> {
+        self.modified
+    }
+
+    /// Gets the last access time of the file if available
+    #[must_use]
+    pub fn accessed(&self) -> Option<Timestamp> {
+        self.accessed
+    }
+
+    /// Gets the size of the file this metadata represents
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Gets the [`FileKind`] of the file this metadata represents
+    #[must_use]
+    pub fn file_type(&self) -> FileKind {
+        self.file_type
+    }
+
+    /// Gets the [`Permissions`] of the file, if they were captured
+    #[must_use]
+    pub fn permissions(&self) -> Option<Permissions> {
+        self.permissions
+    }
+
+    /// Gets the extended attributes of the file by name, if they were captured
+    #[must_use]
+    pub fn xattrs(&self) -> Option<&BTreeMap<String, Vec<u8>>> {
+        self.xattrs.as_ref()
+    }
+
+    /// Makes the captured extended attributes the attributes of the file at `path`, removing
+    /// any others. Nothing changes if none were captured. Attributes the user may not change
+    /// and attributes the file system does not support are skipped.
+    ///
+    /// ## Errors
+    /// - This function will return an error if the attributes of the file cannot be listed or
+    ///   changed
+    #[cfg(feature = "xattr")]
+    pub fn apply_xattrs(&self, path: impl AsRef<Path>) -> Result {
+        match &self.xattrs {
+            Some(xattrs) => crate::xattrs::apply(path.as_ref(), xattrs),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The permissions and ownership of a file. Every platform captures what it supports, the other
+/// fields are `None`, so the metadata can still be read on any platform.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Permissions {
+    readonly: bool,
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    attributes: Option<u32>,
+}
+
+impl Permissions {
+    /// Creates new [`Permissions`] that only carry the read-only flag
+    #[must_use]
+    pub fn new(readonly: bool) -> Self {
+        Self {
+            readonly,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the unix mode bits, user id and group id
+    #[must_use]
+    pub fn with_unix(self, mode: u32, uid: u32, gid: u32) -> Self {
+        Self {
+            mode: Some(mode),
+            uid: Some(uid),
+            gid: Some(gid),
+            ..self
+        }
+    }
+
+    /// Sets the windows file attributes
+    #[must_use]
+    pub fn with_attributes(self, attributes: u32) -> Self {
+        Self {
+            attributes: Some(attributes),
+            ..self
+        }
+    }
+
+    /// Captures the permissions and ownership from a [`Metadata`] object
+    #[must_use]
+    pub fn from_metadata(meta: &Metadata) -> Self {
+        let this = Self::new(meta.permissions().readonly());
+        #[cfg(unix)]
+        let this = {
+            use std::os::unix::fs::MetadataExt;
+            this.with_unix(meta.mode(), meta.uid(), meta.gid())
+        };
+        #[cfg(windows)]
+        let this = {
+            use std::os::windows::fs::MetadataExt;
+            this.with_attributes(meta.file_attributes())
+        };
+        this
+    }
+
+    /// Gets whether the file was read-only
+    #[must_use]
+    pub fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Gets the unix mode bits (including the file type bits), if captured
+    #[must_use]
+    pub fn mode(&self) -> Option<u32> {
+        self.mode
+    }
+
+    /// Gets the unix user id of the owner, if captured
+    #[must_use]
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    /// Gets the unix group id of the owner, if captured
+    #[must_use]
+    pub fn gid(&self) -> Option<u32> {
+        self.gid
+    }
+
+    /// Gets the windows file attributes, if captured
+    #[must_use]
+    pub fn attributes(&self) -> Option<u32> {
+        self.attributes
+    }
+
+    /// Reapplies these permissions to the file at `path` as far as the current platform and
+    /// user allow. Unix mode bits are restored when present, otherwise the read-only flag is.
+    /// Ownership is only changed if it differs and silently kept when the user may not change
+    /// it, since only privileged users can give files away.
+    ///
+    /// ## Errors
+    /// - This function will return an error if the permissions of the file cannot be changed
+    pub fn apply(&self, path: impl AsRef<Path>) -> Result {
+        let path = path.as_ref();
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+            let current = std::fs::metadata(path)?;
+            let uid = self.uid.filter(|uid| *uid != current.uid());
+            let gid = self.gid.filter(|gid| *gid != current.gid());
+            if uid.is_some() || gid.is_some() {
+                match std::os::unix::fs::chown(path, uid, gid) {
+                    Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {}
+                    result => result?,
+                }
+            }
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+            return Ok(());
+        }
+
+        let mut permissions = std::fs::metadata(path)?.permissions();
+        if permissions.readonly() != self.readonly {
+            permissions.set_readonly(self.readonly);
+            std::fs::set_permissions(path, permissions)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Metadata> for FsMetadata {
+    fn from(meta: Metadata) -> Self {
+        Self::from_metadata(&meta)
+    }
+}
+impl From<&Metadata> for FsMetadata {
+    fn from(meta: &Metadata) -> Self {
+        Self::from_metadata(meta)
+    }
+}
+
+/// Types of files. Used due to the fact that [`std::fs::FileType`] is not serializable
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum FileKind {
+    /// A File
+    File,
+    /// A Directory
+    Dir,
+    /// A Symbolic Link
+    Symlink,
+    /// Any other type of file
+    Unknown,
+    /// A Unix domain socket (unix only)
+    Socket,
+    /// A named pipe (unix only)
+    Fifo,
+    /// A block device, e.g. a disk (unix only)
+    BlockDevice,
+    /// A character device, e.g. a terminal (unix only)
+    CharDevice,
+}
+
+impl FileKind {
+    /// Returns true for the kinds of files whose contents cannot be backed up: sockets, named
+    /// pipes, devices and unknown kinds. Reading a named pipe or a device can block forever or
+    /// never end.
+    #[must_use]
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self,
+            Self::Socket | Self::Fifo | Self::BlockDevice | Self::CharDevice | Self::Unknown
+        )
+    }
+}
+
+impl From<FileType> for FileKind {
+    fn from(file_type: FileType) -> Self {
+        #[cfg(unix)]
+        use std::os::unix::fs::FileTypeExt;
+
+        if file_type.is_file() {
+            return Self::File;
+        } else if file_type.is_dir() {
+            return Self::Dir;
+        } else if file_type.is_symlink() {
+            return Self::Symlink;
+        }
+        #[cfg(unix)]
+        if file_type.is_socket() {
+            return Self::Socket;
+        } else if file_type.is_fifo() {
+            return Self::Fifo;
+        } else if file_type.is_block_device() {
+            return Self::BlockDevice;
+        } else if file_type.is_char_device() {
+            return Self::CharDevice;
+        }
+        Self::Unknown
+    }
+}
+
+impl From<Metadata> for FileKind {
+    fn from(meta: Metadata) -> Self {
+        Self::from(meta.file_type())
+    }
+}
+impl From<&Metadata> for FileKind {
+    fn from(meta: &Metadata) -> Self {
+        Self::from(meta.file_type())
+    }
+}
+
+/// Describes the kind of file for people, e.g. `named pipe`
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::File => "regular file",
+            Self::Dir => "directory",
+            Self::Symlink => "symbolic link",
+            Self::Unknown => "file of an unknown type",
+            Self::Socket => "socket",
+            Self::Fifo => "named pipe",
+            Self::BlockDevice => "block device",
+            Self::CharDevice => "character device",
+        })
+    }
+}
+
+/// The metadata for a backup file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileMeta {
+    /// The backup version
+    version: FileVersion,
+    /// The timestamp of when the **backup** was created (*not* the original file)
+    backup_created: Timestamp,
+    /// The path to the original file
+    path: PathBuf,
+    /// The filesystem metadata for the original file at time of backup
+    fs_meta: FsMetadata,
+    /// The id of the backup, objects written before format `4` do not have one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<UniqueId>,
+    /// The codec the object was written with, objects written before format `5` do not record it
+    /// and are compressed with the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<Compression>,
+    /// How often `fs_meta` was replaced without a new backup, objects written before format `7`
+    /// do not record it and were never revised
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    meta_revision: Option<u32>,
+    /// Whether this version records the deletion of the file rather than its contents, objects
+    /// written before format `9` do not record it and are never deletions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted: Option<bool>,
+    /// The content blob holding the contents of the backup, objects written before format `10`
+    /// always hold their contents themselves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<ContentRef>,
+}
+
+impl FileMeta {
+    /// Creates a new [`FileMeta`] from its individual parts
+    #[must_use]
+    pub fn new(
+        version: FileVersion,
+        created: Timestamp,
+        path: PathBuf,
+        fs_meta: FsMetadata,
+    ) -> Self {
+        Self {
+            version,
+            backup_created: created,
+            path,
+            fs_meta,
+            id: None,
+            compression: None,
+            meta_revision: None,
+            deleted: None,
+            content: None,
+        }
+    }
+
+    /// Sets the id of the backup
+    #[must_use]
+    pub fn with_id(self, id: UniqueId) -> Self {
+        Self {
+            id: Some(id),
+            ..self
+        }
+    }
+
+    /// Replaces the id of the backup
+    pub fn set_id(&mut self, id: UniqueId) {
+        self.id = Some(id);
+    }
+
+    /// Sets the codec the object is written with
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.set_compression(compression);
+        self
+    }
+
+    /// Replaces the codec the object is written with. The metadata is encoded positionally, so
+    /// the id is stored as well, otherwise the codec would be read back in its place.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.id = Some(self.id());
+        self.compression = Some(compression);
+    }
+
+    /// Creates a new [`FileMeta`] for the file at the given path.
+    ///
+    /// # Errors
+    /// - This function will return an error if the given `path` does not point to a valid file.
+    /// - This function will return an error if the metadata for the file cannot be retrieved.
+    pub fn new_for(path: &std::path::Path, version: FileVersion) -> Result<Self> {
+        if !path.exists() {
+            return Err(format!("file at path '{}' does not exist", path.display()).into());
+        }
+        let fs_meta = FsMetadata::from_path(path)?;
+        let created = Timestamp::now();
+
+        Ok(Self::new(version, created, path.to_path_buf(), fs_meta))
+    }
+
+    /// Creates a new [`FileMeta`] for the file at the given path with the given `created` timestamp.
+    /// Uses the provided `metadata` instead of retrieving it from the filesystem, only the
+    /// extended attributes are read from `path` with the `xattr` feature.
+    ///
+    /// ## Errors
+    /// - This function will return an error if the given `path` does not point to a valid file.
+    pub fn new_from_metadata(
+        path: impl AsRef<Path>,
+        created: Timestamp,
+        metadata: &Metadata,
+        version: FileVersion,
+    ) -> Result<Self> {
+        let fs_meta = FsMetadata::from_metadata(metadata);
+        #[cfg(feature = "xattr")]
+        let fs_meta = fs_meta.with_xattrs_of(path.as_ref());
+
+        let this = Self::new(version, created, path.as_ref().to_path_buf(), fs_meta);
+        Ok(this)
+    }
+
+    /// Overwrites the current metadata with the given `metadata`, and the extended attributes
+    /// with those of the file with the `xattr` feature
+    pub fn update_from_metadata(&mut self, metadata: &Metadata) {
+        let fs_meta = FsMetadata::from_metadata(metadata);
+        #[cfg(feature = "xattr")]
+        let fs_meta = fs_meta.with_xattrs_of(&self.path);
+        self.fs_meta = fs_meta;
+    }
+
+    /// Overwrites the current filesystem metadata with `fs_meta`, e.g. when it was not read
+    /// from [`std::fs::Metadata`]
+    pub fn set_fs_meta(&mut self, fs_meta: FsMetadata) {
+        self.fs_meta = fs_meta;
+    }
+
+    /// Replaces the filesystem metadata of this backup without changing its contents, e.g.
+    /// after only the permissions of the original file changed, and bumps the metadata revision.
+    /// The fields before the revision are stored as well, since the metadata is encoded
+    /// positionally.
+    pub fn revise_fs_meta(&mut self, fs_meta: FsMetadata) {
+        self.id = Some(self.id());
+        self.compression = Some(self.compression());
+        self.meta_revision = Some(self.meta_revision().saturating_add(1));
+        self.fs_meta = fs_meta;
+    }
+
+    /// Marks this version as the record of the file being deleted, see [`FileMeta::is_deleted`].
+    /// The fields before the marker are stored as well, since the metadata is encoded
+    /// positionally.
+    pub fn mark_deleted(&mut self) {
+        self.id = Some(self.id());
+        self.compression = Some(self.compression());
+        self.meta_revision = Some(self.meta_revision());
+        self.deleted = Some(true);
+    }
+
+    /// Records that the contents of the backup are kept in the content blob `content` rather
+    /// than in the object itself, see [`FileMeta::content`]. The fields before it are stored as
+    /// well, since the metadata is encoded positionally.
+    pub fn set_content(&mut self, content: ContentRef) {
+        self.id = Some(self.id());
+        self.compression = Some(self.compression());
+        self.meta_revision = Some(self.meta_revision());
+        self.deleted = Some(self.is_deleted());
+        self.content = Some(content);
+    }
+
+    /// Drops the reference to a content blob, e.g. once the contents were moved back into the
+    /// object. Returns the reference, if there was one.
+    pub fn take_content(&mut self) -> Option<ContentRef> {
+        self.content.take()
+    }
+
+    /// Increments the current file version
+    pub fn bump_version(&mut self) {
+        self.version.increment();
+    }
+
+    /// Replaces the current file version with `version`
+    pub fn set_version(&mut self, version: FileVersion) {
+        self.version = version;
+    }
+
+    /// Sets the `backup_created` field to the current time
+    pub fn set_created_now(&mut self) {
+        self.backup_created = Timestamp::now();
+    }
+
+    /// Gets the [`FileVersion`]
+    #[must_use]
+    pub fn version(&self) -> &FileVersion {
+        &self.version
+    }
+
+    /// Gets the time the backup was created
+    #[must_use]
+    pub fn created(&self) -> &Timestamp {
+        &self.backup_created
+    }
+
+    /// Gets the original path of this file
+    #[must_use]
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Gets the filesystem metadata for this file
+    #[must_use]
+    pub fn fs_meta(&self) -> &FsMetadata {
+        &self.fs_meta
+    }
+
+    /// Gets the id of the backup. Backups written before ids were introduced get one derived
+    /// from their creation time, path and version, which is stable but not stored.
+    #[must_use]
+    pub fn id(&self) -> UniqueId {
+        self.id.unwrap_or_else(|| {
+            let millis = u64::try_from(self.backup_created.as_millis()).unwrap_or(u64::MAX);
+            let path = fnv1a(self.path.to_string_lossy().as_bytes());
+            UniqueId::from_parts(
+                millis,
+                u128::from(path) << 16 | u128::from(self.version.get() & 0xffff),
+            )
+        })
+    }
+
+    /// Gets the codec the object was written with
+    #[must_use]
+    pub fn compression(&self) -> Compression {
+        self.compression.unwrap_or_default()
+    }
+
+    /// Gets how often the filesystem metadata of this backup was revised, see
+    /// [`FileMeta::revise_fs_meta`]. Zero if it is the metadata of the backed up contents.
+    #[must_use]
+    pub fn meta_revision(&self) -> u32 {
+        self.meta_revision.unwrap_or_default()
+    }
+
+    /// Gets whether this version records the deletion of the file, a tombstone without contents
+    /// whose creation time is when the deletion was noticed
+    #[must_use]
+    pub fn is_deleted(&self) -> bool {
+        self.deleted.unwrap_or_default()
+    }
+
+    /// Gets the content blob holding the contents of this backup, `None` if the object holds
+    /// them itself. Backups of identical contents share a single blob.
+    #[must_use]
+    pub fn content(&self) -> Option<ContentRef> {
+        self.content
+    }
+
+    /// Gets whether the id of the backup is stored in it, rather than derived
+    #[must_use]
+    pub fn has_stored_id(&self) -> bool {
+        self.id.is_some()
+    }
+}
+
+/// A reference from a [`FileMeta`] to the content blob holding the contents of the backup. Blobs
+/// are named after the BLAKE3 digest of the contents they hold, see
+/// [`content_digest_name`](crate::content_digest_name), or after their [`fnv1a`] hash if they
+/// were written before format `12`, see [`content_name`](crate::content_name).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ContentRef {
+    hash: u64,
+    size: u64,
+    stored: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest: Option<[u8; 32]>,
+}
+
+impl ContentRef {
+    /// Creates a new [`ContentRef`] to the blob of the contents hashing to `hash`, which are
+    /// `size` bytes large and take up `stored` bytes in the blob
+    #[must_use]
+    pub fn new(hash: u64, size: u64, stored: u64) -> Self {
+        Self {
+            hash,
+            size,
+            stored,
+            digest: None,
+        }
+    }
+
+    /// Sets the BLAKE3 digest of the contents, which the blob is named after
+    #[must_use]
+    pub fn with_digest(self, digest: [u8; 32]) -> Self {
+        Self {
+            digest: Some(digest),
+            ..self
+        }
+    }
+
+    /// Gets the [`fnv1a`] hash of the contents
+    #[must_use]
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Gets the size of the contents
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Gets the size of the blob holding the (compressed) contents
+    #[must_use]
+    pub fn stored(&self) -> u64 {
+        self.stored
+    }
+
+    /// Gets the BLAKE3 digest of the contents, `None` for blobs written before format `12`
+    #[must_use]
+    pub fn digest(&self) -> Option<&[u8; 32]> {
+        self.digest.as_ref()
+    }
+
+    /// Gets the name of the blob holding the contents
+    #[must_use]
+    pub fn name(&self) -> String {
+        self.digest.as_ref().map_or_else(
+            || crate::content_name(self.hash),
+            crate::content_digest_name,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_testing() {
+        assert_eq!(1, 1);
+    }
+}
@@ -0,0 +1,352 @@
+//! `version` module defines types for versioning files and directories
+
+use std::{cmp::Ordering, fmt, hash::Hash, marker::PhantomData};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for super::Wrapping {}
+    impl Sealed for super::Saturating {}
+}
+
+/// How a [`FileVersion`] behaves when adding to it overflows `u32::MAX`. Subtracting always
+/// saturates at 1.
+pub trait VersionStrategy:
+    private::Sealed
+    + fmt::Debug
+    + Clone
+    + Copy
+    + PartialEq
+    + Eq
+    + PartialOrd
+    + Ord
+    + Hash
+    + Default
+    + Send
+    + Sync
+{
+    /// Adds `rhs` to the inner value `version`
+    fn add(version: u32, rhs: u32) -> u32;
+
+    /// Adds the signed `rhs` to the inner value `version`
+    fn add_signed(version: u32, rhs: i32) -> u32;
+}
+
+/// Versions roll over to 1 after `u32::MAX`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Wrapping;
+
+impl VersionStrategy for Wrapping {
+    fn add(version: u32, rhs: u32) -> u32 {
+        version.wrapping_add(rhs)
+    }
+
+    fn add_signed(version: u32, rhs: i32) -> u32 {
+        version.wrapping_add_signed(rhs)
+    }
+}
+
+/// Versions stop at `u32::MAX`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Saturating;
+
+impl VersionStrategy for Saturating {
+    fn add(version: u32, rhs: u32) -> u32 {
+        version.saturating_add(rhs)
+    }
+
+    fn add_signed(version: u32, rhs: i32) -> u32 {
+        version.saturating_add_signed(rhs)
+    }
+}
+
+/// Simple incrementing version counter for files.
+///
+/// **Adding to a [`FileVersion`] wraps or saturates depending on its [`VersionStrategy`],
+/// subtracting saturates at 1.** Arithmetic on an invalid version leaves it unchanged.
+///
+/// [`FileVersion`]s should always have a non-zero value and the default value is 1. The strategy
+/// is not part of the serialized form, so both kinds of versions read each other.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileVersion<S: VersionStrategy = Saturating>(u32, PhantomData<S>);
+
+/// A [`FileVersion`] that rolls over to 1 after `u32::MAX`
+pub type WrappingFileVersion = FileVersion<Wrapping>;
+/// A [`FileVersion`] that stops at `u32::MAX`
+pub type SaturatingFileVersion = FileVersion<Saturating>;
+
+impl<S: VersionStrategy> FileVersion<S> {
+    /// An invalid [`FileVersion`] that has an inner value of zero.
+    ///
+    /// I'm not sure if this non-zero non-sense will be useful at all but I'm keeping it for now.
+    pub const INVALID: Self = Self(0, PhantomData);
+
+    /// Creates a new [`FileVersion`] with a version number of 1
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new **invalid** [`FileVersion`]
+    #[must_use]
+    pub fn new_invalid() -> Self {
+        Self::INVALID
+    }
+
+    /// Creates a new file version
+    ///
+    /// ## Panics
+    /// Panics if `version` is zero
+    #[must_use]
+    pub(crate) fn new_with_version(version: u32) -> Self {
+        // TODO: `assert` or `debug_assert`?
+        assert!(
+            version != 0,
+            "attempting to create FileVersion with value of zero"
+        );
+        Self(version, PhantomData)
+    }
+
+    /// Wraps an inner value that was computed by arithmetic, zero becomes 1
+    fn clamped(value: u32) -> Self {
+        Self::new_with_version(value.max(1))
+    }
+
+    /// Checks if this [`FileVersion`] is valid.
+    ///
+    /// ***A [`FileVersion`] is valid if it is non-zero.***
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.0 != 0
+    }
+
+    /// Gets the version number.
+    ///
+    /// Same as [`FileVersion::value`]
+    #[must_use]
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+
+    /// Gets the version number.
+    ///
+    /// Same as [`FileVersion::get`]
+    #[must_use]
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+
+    /// Increment the version number by one, wrapping or saturating at `u32::MAX` depending on
+    /// the [`VersionStrategy`]
+    ///
+    /// ## Panics
+    /// Panics if called on an invalid [`FileVersion`] (i.e. one with a value of zero)
+    pub fn increment(&mut self) {
+        // TODO: `assert` or `debug_assert`?
+        assert!(self.is_valid(), "cannot increment an invalid version!");
+        *self += 1u32;
+    }
+
+    /// Increment the version number by `n`, wrapping or saturating at `u32::MAX` depending on
+    /// the [`VersionStrategy`]
+    ///
+    /// ## Panics
+    /// Panics if called on an invalid [`FileVersion`] (i.e. one with a value of zero)
+    pub fn increment_n(&mut self, n: u32) {
+        // TODO: `assert` or `debug_assert`?
+        assert!(self.is_valid(), "cannot increment an invalid version!");
+        *self += n;
+    }
+
+    /// Gets the next version, or `None` if this version is invalid or `u32::MAX`, whatever the
+    /// [`VersionStrategy`]
+    #[must_use]
+    pub fn checked_increment(&self) -> Option<Self> {
+        if !self.is_valid() {
+            return None;
+        }
+        self.0.checked_add(1).map(Self::new_with_version)
+    }
+}
+
+impl<S: VersionStrategy> Default for FileVersion<S> {
+    fn default() -> Self {
+        Self(1, PhantomData)
+    }
+}
+
+impl<S: VersionStrategy> fmt::Debug for FileVersion<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FileVersion").field(&self.0).finish()
+    }
+}
+
+impl<S: VersionStrategy> fmt::Display for FileVersion<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The serialized form of every [`FileVersion`], a newtype struct like the versions used to be
+#[derive(Deserialize, Serialize)]
+#[serde(rename = "FileVersion")]
+struct SerializedVersion(u32);
+
+impl<S: VersionStrategy> Serialize for FileVersion<S> {
+    fn serialize<Se: Serializer>(&self, serializer: Se) -> Result<Se::Ok, Se::Error> {
+        SerializedVersion(self.0).serialize(serializer)
+    }
+}
+
+impl<'de, S: VersionStrategy> Deserialize<'de> for FileVersion<S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let SerializedVersion(version) = SerializedVersion::deserialize(deserializer)?;
+        Ok(Self(version, PhantomData))
+    }
+}
+
+impl<S: VersionStrategy> TryFrom<u32> for FileVersion<S> {
+    type Error = storage_common::Error;
+
+    fn try_from(version: u32) -> Result<Self, Self::Error> {
+        if version == 0 {
+            return Err("file versions start at 1".into());
+        }
+        Ok(Self::new_with_version(version))
+    }
+}
+
+impl<S: VersionStrategy> From<FileVersion<S>> for u32 {
+    fn from(version: FileVersion<S>) -> Self {
+        version.0
+    }
+}
+
+impl<S: VersionStrategy> PartialOrd<u32> for FileVersion<S> {
+    fn partial_cmp(&self, other: &u32) -> Option<Ordering> {
+        self.0.partial_cmp(other)
+    }
+}
+impl<S: VersionStrategy> PartialEq<u32> for FileVersion<S> {
+    fn eq(&self, other: &u32) -> bool {
+        self.0 == *other
+    }
+}
+
+impl<S: VersionStrategy> std::ops::Add for FileVersion<S> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        if !rhs.is_valid() {
+            return self;
+        }
+        self + rhs.0
+    }
+}
+impl<S: VersionStrategy> std::ops::Add<u32> for FileVersion<S> {
+    type Output = Self;
+
+    fn add(self, rhs: u32) -> Self::Output {
+        if !self.is_valid() {
+            return self;
+        }
+        Self::clamped(S::add(self.0, rhs))
+    }
+}
+impl<S: VersionStrategy> std::ops::Add<i32> for FileVersion<S> {
+    type Output = Self;
+
+    fn add(self, rhs: i32) -> Self::Output {
+        if !self.is_valid() {
+            return self;
+        }
+        Self::clamped(S::add_signed(self.0, rhs))
+    }
+}
+
+impl<S: VersionStrategy> std::ops::Sub for FileVersion<S> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        if !rhs.is_valid() {
+            return self;
+        }
+        self - rhs.0
+    }
+}
+impl<S: VersionStrategy> std::ops::Sub<u32> for FileVersion<S> {
+    type Output = Self;
+
+    fn sub(self, rhs: u32) -> Self::Output {
+        if !self.is_valid() {
+            return self;
+        }
+        Self::clamped(self.0.saturating_sub(rhs))
+    }
+}
+impl<S: VersionStrategy> std::ops::Sub<i32> for FileVersion<S> {
+    type Output = Self;
+
+    fn sub(self, rhs: i32) -> Self::Output {
+        if !self.is_valid() {
+            return self;
+        }
+        Self::clamped(S::add_signed(self.0, -rhs))
+    }
+}
+
+macro_rules! assign_ops {
+    ($($rhs:ty),*) => {
+        $(
+            impl<S: VersionStrategy> std::ops::AddAssign<$rhs> for FileVersion<S> {
+                fn add_assign(&mut self, rhs: $rhs) {
+                    *self = *self + rhs;
+                }
+            }
+            impl<S: VersionStrategy> std::ops::SubAssign<$rhs> for FileVersion<S> {
+                fn sub_assign(&mut self, rhs: $rhs) {
+                    *self = *self - rhs;
+                }
+            }
+        )*
+    };
+}
+assign_ops!(Self, u32, i32);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategies() {
+        let max = FileVersion::<Saturating>::new_with_version(u32::MAX);
+        assert_eq!(max + 1u32, u32::MAX);
+        assert_eq!(WrappingFileVersion::new_with_version(u32::MAX) + 2u32, 1u32);
+        assert_eq!(WrappingFileVersion::new() - 5u32, 1u32);
+        assert_eq!(SaturatingFileVersion::new() + 4i32 - 2i32, 3u32);
+        assert_eq!(max.checked_increment(), None);
+        assert_eq!(FileVersion::<Saturating>::INVALID.checked_increment(), None);
+        assert_eq!(
+            SaturatingFileVersion::new().checked_increment().unwrap(),
+            2u32
+        );
+
+        let mut invalid = WrappingFileVersion::INVALID;
+        invalid += 3u32;
+        assert!(!invalid.is_valid());
+    }
+
+    #[test]
+    fn conversions() {
+        assert!(SaturatingFileVersion::try_from(0).is_err());
+        let version = WrappingFileVersion::try_from(7).unwrap();
+        assert_eq!(u32::from(version), 7);
+
+        // the strategy does not change the serialized form
+        let bytes = rmp_serde::to_vec(&version).unwrap();
+        assert_eq!(bytes, rmp_serde::to_vec(&7u32).unwrap());
+        let read: SaturatingFileVersion = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(read, 7u32);
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reading and writing the extended attributes of files, see [`FsMetadata::xattrs`]
+//!
+//! [`FsMetadata::xattrs`]: crate::FsMetadata::xattrs
+
+use std::{collections::BTreeMap, io, path::Path};
+
+use crate::Result;
+
+/// Reads every extended attribute of the file at `path`, or of the link itself unless
+/// `follow_links` is set. Attributes with a name that is not valid utf-8 are left out.
+pub(crate) fn read(path: &Path, follow_links: bool) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let names = if follow_links {
+        xattr::list_deref(path)?
+    } else {
+        xattr::list(path)?
+    };
+    let mut xattrs = BTreeMap::new();
+    for name in names {
+        let Some(key) = name.to_str() else {
+            continue;
+        };
+        let value = if follow_links {
+            xattr::get_deref(path, &name)?
+        } else {
+            xattr::get(path, &name)?
+        };
+        // the attribute was removed in the meantime
+        if let Some(value) = value {
+            xattrs.insert(key.to_string(), value);
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Makes `xattrs` the extended attributes of the file at `path`, removing any others. Attributes
+/// the user may not change, e.g. in the `trusted` or `security` namespaces, and attributes the
+/// file system does not support are skipped.
+pub(crate) fn apply(path: &Path, xattrs: &BTreeMap<String, Vec<u8>>) -> Result {
+    for name in xattr::list_deref(path)? {
+        if name.to_str().is_some_and(|name| xattrs.contains_key(name)) {
+            continue;
+        }
+        skip_unchangeable(xattr::remove_deref(path, &name))?;
+    }
+    for (name, value) in xattrs {
+        skip_unchangeable(xattr::set_deref(path, name, value))?;
+    }
+    Ok(())
+}
+
+fn skip_unchangeable(result: io::Result<()>) -> Result {
+    match result {
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::PermissionDenied | io::ErrorKind::Unsupported
+            ) =>
+        {
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! User annotations (notes, tags and pins) on individual backups. They are kept in an index next
+//! to the objects of a store, keyed by object name, and can be exchanged with other stores
+//! through a sidecar JSON file keyed by path, version and content hash.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use xstd::hash::fnv1a;
+
+use crate::{
+    backup::BackupInfo, content, Error, FileVersion, Result, StorageBackend, PRE_RESTORE_TAG,
+};
+
+/// The version of the sidecar layout written by [`export`]
+const SIDECAR_VERSION: u32 = 1;
+
+/// The notes, tags and pin of a single backup
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    tags: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+}
+
+impl Annotation {
+    /// Gets the free-form note, if any
+    #[must_use]
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    /// Replaces the note, `None` removes it
+    pub fn set_note(&mut self, note: Option<String>) {
+        self.note = note;
+    }
+
+    /// Gets the tags, in alphabetical order
+    pub fn tags(&self) -> impl Iterator<Item = &str> + '_ {
+        self.tags.iter().map(String::as_str)
+    }
+
+    /// Adds `tag`, returning whether it was new
+    pub fn add_tag(&mut self, tag: impl Into<String>) -> bool {
+        self.tags.insert(tag.into())
+    }
+
+    /// Removes `tag`, returning whether it was present
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        self.tags.remove(tag)
+    }
+
+    /// Gets whether the backup is pinned
+    #[must_use]
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Pins or unpins the backup
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    /// Gets whether the backup is kept by retention policies and evictions, which is the case if
+    /// it is pinned or tagged with [`PRE_RESTORE_TAG`]
+    #[must_use]
+    pub fn is_protected(&self) -> bool {
+        self.pinned || self.tags.contains(PRE_RESTORE_TAG)
+    }
+
+    /// Gets whether this annotation carries no information
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.note.is_none() && self.tags.is_empty() && !self.pinned
+    }
+
+    /// Merges `other` into this annotation: its note wins if it has one, tags are combined and
+    /// the backup stays pinned if either side pinned it
+    fn merge(&mut self, other: Annotation) {
+        if other.note.is_some() {
+            self.note = other.note;
+        }
+        self.tags.extend(other.tags);
+        self.pinned |= other.pinned;
+    }
+}
+
+/// The outcome of
+/// [`BackupManager::import_annotations`](crate::BackupManager::import_annotations)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct AnnotationReport {
+    applied: usize,
+    unmatched: usize,
+}
+
+impl AnnotationReport {
+    /// Gets the number of annotations that were merged into the store
+    #[must_use]
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    /// Gets the number of annotations without a backup of the same content in the store
+    #[must_use]
+    pub fn unmatched(&self) -> usize {
+        self.unmatched
+    }
+}
+
+/// The annotations of every backup in a store, keyed by object name
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AnnotationIndex {
+    path: PathBuf,
+    entries: BTreeMap<String, Annotation>,
+}
+
+impl AnnotationIndex {
+    /// Loads the index at `path`, which is empty if the file does not exist yet
+    pub(crate) fn load(path: PathBuf) -> Result<Self> {
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| Error::Serde(format!("invalid annotation index - {e}")))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, entries })
+    }
+
+    /// Writes the index to disk, an empty index removes the file instead
+    pub(crate) fn save(&self) -> Result {
+        if self.entries.is_empty() {
+            return match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        let json = serde_json::to_vec_pretty(&self.entries)
+            .map_err(|e| Error::Serde(format!("unable to serialize annotation index - {e}")))?;
+        std::fs::write(&self.path, json)?;
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, info: &BackupInfo) -> Option<&Annotation> {
+        self.entries.get(&object_key(info)?)
+    }
+
+    /// Checks whether the annotation of `info` protects it, see [`Annotation::is_protected`]
+    pub(crate) fn protects(&self, info: &BackupInfo) -> bool {
+        self.get(info).is_some_and(Annotation::is_protected)
+    }
+
+    /// Applies `f` to the annotation of `info`, dropping it again if it ends up empty
+    pub(crate) fn update(&mut self, info: &BackupInfo, f: impl FnOnce(&mut Annotation)) -> Result {
+        let key = object_key(info)
+            .ok_or_else(|| format!("invalid backup path '{}'", info.backup_path.display()))?;
+        let annotation = self.entries.entry(key.clone()).or_default();
+        f(annotation);
+        if annotation.is_empty() {
+            self.entries.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Drops the annotation of `info`, e.g. after it has been deleted
+    pub(crate) fn remove(&mut self, info: &BackupInfo) {
+        if let Some(key) = object_key(info) {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// A single annotation in a sidecar file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SidecarEntry {
+    path: PathBuf,
+    version: FileVersion,
+    /// The hash of the original file contents, which identifies the backup across stores. It is
+    /// written as a hex string since not every JSON reader handles 64-bit integers.
+    hash: String,
+    #[serde(flatten)]
+    annotation: Annotation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sidecar {
+    version: u32,
+    annotations: Vec<SidecarEntry>,
+}
+
+/// Writes every annotation in `index` to the sidecar file at `sidecar`, returning the number of
+/// annotations written. The objects of the annotated backups are read from `backend`.
+pub(crate) fn export(
+    index: &AnnotationIndex,
+    backend: &dyn StorageBackend,
+    infos: &[BackupInfo],
+    sidecar: &Path,
+) -> Result<usize> {
+    let mut annotations = Vec::new();
+    for info in infos {
+        if let Some(annotation) = index.get(info) {
+            annotations.push(SidecarEntry {
+                path: info.meta.path().clone(),
+                version: *info.meta.version(),
+                hash: format!("{:016x}", content_hash(backend, info)?),
+                annotation: annotation.clone(),
+            });
+        }
+    }
+    annotations.sort_by(|a, b| (&a.path, a.version).cmp(&(&b.path, b.version)));
+
+    let count = annotations.len();
+    let json = serde_json::to_vec_pretty(&Sidecar {
+        version: SIDECAR_VERSION,
+        annotations,
+    })
+    .map_err(|e| Error::Serde(format!("unable to serialize annotations - {e}")))?;
+    std::fs::write(sidecar, json)?;
+    Ok(count)
+}
+
+/// Merges the annotations of the sidecar file at `sidecar` into `index`. Each annotation is
+/// applied to the backup of the same path, version and content, or to another version of the
+/// same path with that content if the versions differ between the stores. The objects of the
+/// candidate backups are read from `backend`.
+pub(crate) fn import(
+    index: &mut AnnotationIndex,
+    backend: &dyn StorageBackend,
+    infos: &[BackupInfo],
+    sidecar: &Path,
+) -> Result<AnnotationReport> {
+    let sidecar: Sidecar = serde_json::from_slice(&std::fs::read(sidecar)?)
+        .map_err(|e| Error::Serde(format!("invalid annotation sidecar - {e}")))?;
+    if sidecar.version > SIDECAR_VERSION {
+        return Err(format!(
+            "annotation sidecar version {} is newer than the supported version {SIDECAR_VERSION}",
+            sidecar.version
+        )
+        .into());
+    }
+
+    let mut hashes = BTreeMap::new();
+    let mut report = AnnotationReport::default();
+    for entry in sidecar.annotations {
+        let mut candidates = infos
+            .iter()
+            .filter(|info| info.meta.path() == &entry.path)
+            .collect::<Vec<_>>();
+        // the same version is the most likely match, so it is checked first
+        candidates.sort_by_key(|info| *info.meta.version() != entry.version);
+
+        let mut target = None;
+        for info in candidates {
+            let hash = if let Some(hash) = hashes.get(&info.backup_path) {
+                *hash
+            } else {
+                let hash = content_hash(backend, info)?;
+                hashes.insert(info.backup_path.clone(), hash);
+                hash
+            };
+            if format!("{hash:016x}") == entry.hash {
+                target = Some(info);
+                break;
+            }
+        }
+
+        match target {
+            Some(info) => {
+                index.update(info, |annotation| annotation.merge(entry.annotation))?;
+                report.applied += 1;
+            }
+            None => report.unmatched += 1,
+        }
+    }
+    Ok(report)
+}
+
+fn object_key(info: &BackupInfo) -> Option<String> {
+    Some(info.backup_path.file_name()?.to_str()?.to_string())
+}
+
+/// Hashes the original file contents of a backup, which unlike the object itself do not depend
+/// on the version it was stored as. Contents kept in a content blob are named after their hash,
+/// so those are not read at all.
+pub(crate) fn content_hash(backend: &dyn StorageBackend, info: &BackupInfo) -> Result<u64> {
+    if let Some(content) = info.meta.content() {
+        return Ok(content.hash());
+    }
+    Ok(fnv1a(&content::read(backend, info)?))
+}
@@ -0,0 +1,270 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Portable archives of a backup store. An archive is a plain tar file holding a `manifest.json`
+//! index followed by every compressed object under `objects/`, so it can be inspected with
+//! standard tools and imported into a store on another machine. Archived objects always hold
+//! their contents themselves, contents kept in a content blob are moved back into the object.
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use xstd::{
+    cast::CastFrom,
+    fs::{create_write_truncate, read_only},
+    hash::fnv1a,
+};
+
+use crate::{
+    backup::BackupInfo, clone, content, Compression, Error, FileHeader, FileVersion, Result,
+    StorageBackend, Timestamp,
+};
+
+/// The version of the archive layout written by [`export`]
+const ARCHIVE_VERSION: u32 = 1;
+/// The name of the index entry, which is always the first entry of an archive
+const MANIFEST_NAME: &str = "manifest.json";
+/// The directory holding the objects inside of an archive
+const OBJECTS_DIR: &str = "objects";
+
+/// The index of an archive, describing every object it contains
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    created: Timestamp,
+    entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestEntry {
+    object: String,
+    path: PathBuf,
+    version: FileVersion,
+    size: u64,
+    hash: u64,
+}
+
+/// The outcome of [`BackupManager::import_archive`](crate::BackupManager::import_archive)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ImportReport {
+    imported: usize,
+    imported_bytes: u64,
+    skipped: usize,
+    renumbered: Vec<(PathBuf, FileVersion, FileVersion)>,
+}
+
+impl ImportReport {
+    /// Gets the number of objects that were added to the store, including renumbered ones
+    #[must_use]
+    pub fn imported(&self) -> usize {
+        self.imported
+    }
+
+    /// Gets the number of bytes that were added to the store
+    #[must_use]
+    pub fn imported_bytes(&self) -> u64 {
+        self.imported_bytes
+    }
+
+    /// Gets the number of objects the store already had
+    #[must_use]
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Gets the `(path, archived version, stored version)` of every object whose version was
+    /// already taken by a different backup in the store, and which was stored as the next free
+    /// version of its file instead
+    #[must_use]
+    pub fn renumbered(&self) -> &[(PathBuf, FileVersion, FileVersion)] {
+        &self.renumbered
+    }
+}
+
+/// Writes every object in `infos` into a new archive at `archive`, reading them from `backend`.
+/// Returns the number of objects written.
+///
+/// The manifest comes first but lists the size and hash of every object, so the objects are read
+/// twice: once for the manifest and once more while they are written, keeping a single object in
+/// memory at a time.
+pub(crate) fn export(
+    backend: &dyn StorageBackend,
+    infos: &[BackupInfo],
+    archive: &Path,
+) -> Result<usize> {
+    let mut infos = infos.iter().collect::<Vec<_>>();
+    infos.sort_by(|a, b| (a.meta.path(), a.meta.version()).cmp(&(b.meta.path(), b.meta.version())));
+
+    let mut entries = Vec::with_capacity(infos.len());
+    for info in &infos {
+        let bytes = archived_bytes(backend, info)?;
+        entries.push(ManifestEntry {
+            object: storage_format::object_name(info.meta.path(), *info.meta.version()),
+            path: info.meta.path().clone(),
+            version: *info.meta.version(),
+            size: u64::cast_from(bytes.len()),
+            hash: fnv1a(&bytes),
+        });
+    }
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        created: Timestamp::now(),
+        entries,
+    };
+    let json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| Error::Serde(format!("unable to serialize archive manifest - {e}")))?;
+
+    let mut builder = tar::Builder::new(create_write_truncate().open(archive)?);
+    append(&mut builder, MANIFEST_NAME, &json)?;
+    for (entry, info) in manifest.entries.iter().zip(&infos) {
+        let bytes = archived_bytes(backend, info)?;
+        if fnv1a(&bytes) != entry.hash {
+            return Err(format!("'{}' changed while it was being exported", entry.object).into());
+        }
+        append(
+            &mut builder,
+            &format!("{OBJECTS_DIR}/{}", entry.object),
+            &bytes,
+        )?;
+    }
+    builder.into_inner()?.sync_all()?;
+
+    Ok(manifest.entries.len())
+}
+
+/// Reads the object of `info` out of `backend` the way it is archived, holding its contents
+/// itself
+fn archived_bytes(backend: &dyn StorageBackend, info: &BackupInfo) -> Result<Vec<u8>> {
+    let bytes = backend.get(info.object_id()?)?;
+    if info.meta.content().is_none() {
+        return Ok(bytes);
+    }
+    let (_, meta, contents) = content::decode(backend, &bytes)?;
+    let meta = meta.with_compression(Compression::default());
+    let header = FileHeader::new(storage_format::encode_meta(&meta)?.len(), contents.len());
+    storage_format::encode(&header, &meta, &contents)
+}
+
+/// Merges the objects of the archive at `archive` into `backend`, adding them to the `infos` of
+/// the store directory `store`. Objects already in the store are skipped; an object whose version
+/// is taken by a different backup of the same file is stored as the next free version instead.
+pub(crate) fn import(
+    archive: &Path,
+    backend: &dyn StorageBackend,
+    store: &Path,
+    infos: &mut Vec<BackupInfo>,
+) -> Result<ImportReport> {
+    let mut tar = tar::Archive::new(read_only().open(archive)?);
+    let mut entries = tar.entries()?;
+
+    let mut manifest = Vec::new();
+    match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+            if entry.path()?.as_ref() != Path::new(MANIFEST_NAME) {
+                return Err(
+                    format!("'{}' does not start with a manifest", archive.display()).into(),
+                );
+            }
+            entry.read_to_end(&mut manifest)?;
+        }
+        None => return Err(format!("'{}' is an empty archive", archive.display()).into()),
+    }
+    let mut pending = manifest_entries(&manifest)?;
+
+    let mut report = ImportReport::default();
+    for entry in entries {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        let Some(index) = pending
+            .iter()
+            .position(|e| Path::new(OBJECTS_DIR).join(&e.object) == name)
+        else {
+            return Err(format!("'{}' is not listed in the manifest", name.display()).into());
+        };
+        let expected = pending.swap_remove(index);
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        if u64::cast_from(bytes.len()) != expected.size || fnv1a(&bytes) != expected.hash {
+            return Err(format!("'{}' is corrupt, its hash does not match", name.display()).into());
+        }
+        import_object(bytes, &expected, backend, store, infos, &mut report)?;
+    }
+
+    if let Some(missing) = pending.first() {
+        return Err(format!(
+            "archive is missing {} object(s) listed in its manifest, e.g. '{}'",
+            pending.len(),
+            missing.object
+        )
+        .into());
+    }
+    Ok(report)
+}
+
+fn import_object(
+    bytes: Vec<u8>,
+    expected: &ManifestEntry,
+    backend: &dyn StorageBackend,
+    store: &Path,
+    infos: &mut Vec<BackupInfo>,
+    report: &mut ImportReport,
+) -> Result {
+    let (_, mut meta, data) = storage_format::decode(&bytes)?;
+    if meta.path() != &expected.path
+        || *meta.version() != expected.version
+        || storage_format::object_name(meta.path(), *meta.version()) != expected.object
+    {
+        return Err(format!("'{}' does not match its manifest entry", expected.object).into());
+    }
+
+    let mut target = expected.object.clone();
+    let mut bytes = bytes;
+    if backend.contains(&target)? {
+        if fnv1a(&backend.get(&target)?) == expected.hash
+            || clone::has_content(backend, infos, &meta, &data)?
+        {
+            report.skipped += 1;
+            return Ok(());
+        }
+        (bytes, target) = clone::renumber(&mut meta, &data, infos)?;
+        report
+            .renumbered
+            .push((meta.path().clone(), expected.version, *meta.version()));
+    }
+
+    let info = clone::write_verified(&bytes, backend, &target, store)?;
+    report.imported += 1;
+    report.imported_bytes += info.backup_size;
+    infos.push(info);
+    Ok(())
+}
+
+fn manifest_entries(manifest: &[u8]) -> Result<Vec<ManifestEntry>> {
+    let manifest: Manifest = serde_json::from_slice(manifest)
+        .map_err(|e| Error::Serde(format!("invalid archive manifest - {e}")))?;
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(format!(
+            "archive version {} is newer than the supported version {ARCHIVE_VERSION}",
+            manifest.version
+        )
+        .into());
+    }
+    Ok(manifest.entries)
+}
+
+fn append(builder: &mut tar::Builder<std::fs::File>, name: &str, bytes: &[u8]) -> Result {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(u64::cast_from(bytes.len()));
+    header.set_mode(0o644);
+    header.set_mtime(Timestamp::now().as_secs());
+    header.set_cksum();
+    builder.append_data(&mut header, name, bytes)?;
+    Ok(())
+}
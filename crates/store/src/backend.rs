@@ -0,0 +1,708 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Where the objects of a store are kept. A [`BackupManager`](crate::BackupManager) only ever
+//! hands its [`StorageBackend`] opaque blobs keyed by the object name, so the objects can live in
+//! a local directory ([`LocalBackend`]), in memory ([`MemoryBackend`]) or on a remote service.
+//! The index, the annotations and the lock of a store stay in its local store directory either
+//! way.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use storage_common::{write_all_with_progress, LowDiskAction, ResultExt};
+use xstd::{
+    cast::CastFrom,
+    fs::{create_write_truncate, read_only},
+};
+
+use crate::{
+    crypto, Config, ProgressSink, Result, StoreKey, Timestamp, BUFFER_SIZE, CONTENT_EXTENSION,
+    DICTIONARY_EXTENSION, OBJECT_EXTENSION,
+};
+
+/// A blob held by a [`StorageBackend`], as returned by [`StorageBackend::list`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlobEntry {
+    id: String,
+    size: u64,
+}
+
+impl BlobEntry {
+    /// Creates a new [`BlobEntry`] for the blob `id` holding `size` bytes
+    #[must_use]
+    pub fn new(id: impl Into<String>, size: u64) -> Self {
+        Self {
+            id: id.into(),
+            size,
+        }
+    }
+
+    /// Gets the id of the blob
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Gets the size of the blob in bytes
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Keeps the objects of a store as opaque blobs keyed by id. Ids are the object names of the
+/// store, e.g. `3f2a...-2.bak`, the names of its content blobs, e.g. `9c41....blob`, or the names
+/// of its compression dictionaries, e.g. `d7e0....dict`, and never contain a path separator.
+pub trait StorageBackend: fmt::Debug + Send + Sync + 'static {
+    /// Stores `bytes` as the blob `id`, replacing the blob if it exists. Readers never see a
+    /// partially written blob.
+    ///
+    /// ## Errors
+    /// - Returns an error if the blob cannot be written
+    fn put(&self, id: &str, bytes: &[u8]) -> Result;
+
+    /// Same as [`StorageBackend::put`], reporting the number of bytes written so far to
+    /// `progress`. The default reports the whole blob at once after writing it.
+    ///
+    /// ## Errors
+    /// - Returns an error if the blob cannot be written
+    fn put_with_progress(&self, id: &str, bytes: &[u8], progress: &mut dyn ProgressSink) -> Result {
+        self.put(id, bytes)?;
+        let size = u64::cast_from(bytes.len());
+        progress.progress(size, size);
+        progress.finish();
+        Ok(())
+    }
+
+    /// Reads the blob `id`
+    ///
+    /// ## Errors
+    /// - Returns an error of [`Error::io_kind`](crate::Error::io_kind) [`ErrorKind::NotFound`]
+    ///   if there is no blob `id`
+    /// - Returns an error if the blob cannot be read
+    fn get(&self, id: &str) -> Result<Vec<u8>>;
+
+    /// Opens the blob `id` for reading, e.g. to read only the start of it. The default reads the
+    /// whole blob with [`StorageBackend::get`].
+    ///
+    /// ## Errors
+    /// - Returns an error of [`Error::io_kind`](crate::Error::io_kind) [`ErrorKind::NotFound`]
+    ///   if there is no blob `id`
+    /// - Returns an error if the blob cannot be opened
+    fn open(&self, id: &str) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(Cursor::new(self.get(id)?)))
+    }
+
+    /// Reads the last `len` bytes of the blob `id`, all of it if it is shorter. The default reads
+    /// the whole blob with [`StorageBackend::get`].
+    ///
+    /// ## Errors
+    /// - Returns an error of [`Error::io_kind`](crate::Error::io_kind) [`ErrorKind::NotFound`]
+    ///   if there is no blob `id`
+    /// - Returns an error if the blob cannot be read
+    fn tail(&self, id: &str, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = self.get(id)?;
+        Ok(bytes.split_off(bytes.len().saturating_sub(len)))
+    }
+
+    /// Lists every blob, in no particular order
+    ///
+    /// ## Errors
+    /// - Returns an error if the blobs cannot be listed
+    fn list(&self) -> Result<Vec<BlobEntry>>;
+
+    /// Deletes the blob `id`
+    ///
+    /// ## Errors
+    /// - Returns an error of [`Error::io_kind`](crate::Error::io_kind) [`ErrorKind::NotFound`]
+    ///   if there is no blob `id`
+    /// - Returns an error if the blob cannot be deleted
+    fn delete(&self, id: &str) -> Result;
+
+    /// Replaces the blob `to` with the blob `from`, which no longer exists afterwards. Readers see
+    /// either the old or the new blob `to`. The default copies the blob and deletes `from`.
+    ///
+    /// ## Errors
+    /// - Returns an error of [`Error::io_kind`](crate::Error::io_kind) [`ErrorKind::NotFound`]
+    ///   if there is no blob `from`
+    /// - Returns an error if the blob cannot be moved
+    fn rename(&self, from: &str, to: &str) -> Result {
+        self.put(to, &self.get(from)?)?;
+        self.delete(from)
+    }
+
+    /// Checks whether there is a blob `id`. The default looks for it in [`StorageBackend::list`].
+    ///
+    /// ## Errors
+    /// - Returns an error if the blobs cannot be listed
+    fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self.list()?.iter().any(|blob| blob.id() == id))
+    }
+
+    /// Gets when the blob `id` was last written, if the backend keeps track of it. The default
+    /// does not.
+    ///
+    /// ## Errors
+    /// - Returns an error if the blob cannot be looked up
+    fn modified(&self, id: &str) -> Result<Option<Timestamp>> {
+        let _ = id;
+        Ok(None)
+    }
+}
+
+/// A [`StorageBackend`] keeping every blob as a file in a local directory, the layout stores have
+/// always had. Other files in the directory (the index, the lock, interrupted writes) are not
+/// blobs and are ignored. With a [`StoreKey`] the blobs are encrypted in the directory.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalBackend {
+    dir: PathBuf,
+    min_free_bytes: Option<u64>,
+    on_low_disk: LowDiskAction,
+    key: Option<StoreKey>,
+}
+
+impl LocalBackend {
+    /// Creates a new [`LocalBackend`] keeping its blobs in `dir`, which must exist
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            min_free_bytes: None,
+            on_low_disk: LowDiskAction::default(),
+            key: None,
+        }
+    }
+
+    /// Creates a new [`LocalBackend`] for the store directory of `config`, keeping the free space
+    /// of [`Config::min_free_bytes`] on its volume. An encrypted store is unlocked with the
+    /// passphrase of [`PASSPHRASE_ENV`](crate::PASSPHRASE_ENV), see [`StoreKey::for_config`], and
+    /// a rekey that was interrupted is finished, see
+    /// [`BackupManager::rekey`](crate::BackupManager::rekey).
+    ///
+    /// ## Errors
+    /// - Returns an error if the store is encrypted and cannot be unlocked
+    /// - Returns an error if an interrupted rekey cannot be finished
+    pub fn for_config(config: &Config) -> Result<Self> {
+        let mut backend = Self::new(config.store_dir_path());
+        if let Some(min_free_bytes) = config.min_free_bytes() {
+            backend = backend.with_min_free_bytes(min_free_bytes, config.on_low_disk());
+        }
+        if let Some(key) = StoreKey::for_config(config)? {
+            backend = backend.with_key(key);
+        }
+        crypto::finish_interrupted_rekey(&backend, config)?;
+        Ok(backend)
+    }
+
+    /// Encrypts the blobs written from now on with `key`, and decrypts the blobs read with it
+    #[must_use]
+    pub fn with_key(self, key: StoreKey) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    /// Gets the key the blobs are encrypted with, `None` if they are not
+    #[must_use]
+    pub fn key(&self) -> Option<&StoreKey> {
+        self.key.as_ref()
+    }
+
+    /// Sets the free space to keep on the volume of the directory. Before a blob is written its
+    /// size is checked against the space available: if writing it would leave less than
+    /// `min_free_bytes` a warning is logged, and with [`LowDiskAction::Fail`] the write fails
+    /// with [`Error::DiskFull`](crate::Error::DiskFull).
+    #[must_use]
+    pub fn with_min_free_bytes(self, min_free_bytes: u64, on_low_disk: LowDiskAction) -> Self {
+        Self {
+            min_free_bytes: Some(min_free_bytes),
+            on_low_disk,
+            ..self
+        }
+    }
+
+    /// Gets the directory holding the blobs
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Gets the free space kept on the volume of the directory, if any
+    #[must_use]
+    pub fn min_free_bytes(&self) -> Option<u64> {
+        self.min_free_bytes
+    }
+
+    /// Gets the path of the file holding the blob `id`
+    ///
+    /// ## Errors
+    /// - Returns an error if `id` is not a plain file name, i.e. it is empty, `.` or `..`, or
+    ///   contains a path separator, so no id can reach outside of the directory
+    pub fn path_of(&self, id: &str) -> Result<PathBuf> {
+        let mut components = Path::new(id).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if name == id && !id.contains(['/', '\\']) => {
+                Ok(self.dir.join(id))
+            }
+            _ => Err(format!("'{id}' is not a valid blob id").into()),
+        }
+    }
+
+    /// Checks that writing `size` more bytes leaves [`LocalBackend::min_free_bytes`] free, a
+    /// volume whose free space cannot be queried is assumed to have enough
+    fn check_free_space(&self, size: u64) -> Result {
+        let Some(min_free_bytes) = self.min_free_bytes else {
+            return Ok(());
+        };
+        let available = match xstd::fs::free_space(&self.dir) {
+            Ok(available) => available,
+            Err(e) => {
+                tracing::debug!(dir = %self.dir.display(), error = %e, "unable to query the free space");
+                return Ok(());
+            }
+        };
+        let required = min_free_bytes.saturating_add(size);
+        if available >= required {
+            return Ok(());
+        }
+        tracing::warn!(
+            dir = %self.dir.display(),
+            available,
+            required,
+            action = %self.on_low_disk,
+            "the store is low on disk space"
+        );
+        match self.on_low_disk {
+            LowDiskAction::Warn => Ok(()),
+            LowDiskAction::Fail => Err(self.disk_full(available, required)),
+        }
+    }
+
+    fn disk_full(&self, available: u64, required: u64) -> crate::Error {
+        crate::Error::DiskFull {
+            path: self.dir.clone(),
+            available,
+            required,
+        }
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn put(&self, id: &str, bytes: &[u8]) -> Result {
+        self.put_with_progress(id, bytes, &mut ())
+    }
+
+    fn put_with_progress(&self, id: &str, bytes: &[u8], progress: &mut dyn ProgressSink) -> Result {
+        let encrypted;
+        let bytes = match &self.key {
+            Some(key) => {
+                encrypted = key.encrypt(bytes)?;
+                &encrypted
+            }
+            None => bytes,
+        };
+        let size = u64::cast_from(bytes.len());
+        self.check_free_space(size)?;
+        let path = self.path_of(id)?;
+        let partial = path.with_extension("partial");
+        let result = create_write_truncate()
+            .open(&partial)
+            .and_then(|mut file| write_all_with_progress(&mut file, bytes, BUFFER_SIZE, progress))
+            .and_then(|()| std::fs::rename(&partial, &path));
+        if let Err(e) = result {
+            // don't leave a half written blob behind, the error being returned is the useful one
+            let _ = std::fs::remove_file(&partial);
+            if e.kind() == ErrorKind::StorageFull {
+                let available = xstd::fs::free_space(&self.dir).unwrap_or(0);
+                return Err(self.disk_full(available, size));
+            }
+            return Err(e).context(format!("unable to write '{}'", path.display()));
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Vec<u8>> {
+        let path = self.path_of(id)?;
+        let bytes =
+            std::fs::read(&path).with_context(|| format!("unable to read '{}'", path.display()))?;
+        match &self.key {
+            Some(key) => key
+                .decrypt(&bytes)
+                .map_err(|e| format!("unable to read '{}' - {e}", path.display()).into()),
+            None => Ok(bytes),
+        }
+    }
+
+    fn open(&self, id: &str) -> Result<Box<dyn Read + Send>> {
+        if self.key.is_some() {
+            return Ok(Box::new(Cursor::new(self.get(id)?)));
+        }
+        let path = self.path_of(id)?;
+        let file = read_only()
+            .open(&path)
+            .with_context(|| format!("unable to open '{}'", path.display()))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    fn tail(&self, id: &str, len: usize) -> Result<Vec<u8>> {
+        if self.key.is_some() {
+            let mut bytes = self.get(id)?;
+            return Ok(bytes.split_off(bytes.len().saturating_sub(len)));
+        }
+        let path = self.path_of(id)?;
+        let read_tail = || {
+            let mut file = read_only().open(&path)?;
+            let size = file.metadata()?.len();
+            file.seek(SeekFrom::Start(size.saturating_sub(u64::cast_from(len))))?;
+            let mut tail = Vec::with_capacity(len);
+            file.read_to_end(&mut tail)?;
+            Ok::<_, std::io::Error>(tail)
+        };
+        read_tail().with_context(|| format!("unable to read '{}'", path.display()))
+    }
+
+    fn list(&self) -> Result<Vec<BlobEntry>> {
+        let mut blobs = Vec::new();
+        let context = || format!("unable to list '{}'", self.dir.display());
+        for entry in std::fs::read_dir(&self.dir).with_context(context)? {
+            let entry = entry.with_context(context)?;
+            let path = entry.path();
+            let extension = path.extension();
+            if ![OBJECT_EXTENSION, CONTENT_EXTENSION, DICTIONARY_EXTENSION]
+                .iter()
+                .any(|known| extension == Some(known.as_ref()))
+            {
+                continue;
+            }
+            let Some(id) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            blobs.push(BlobEntry::new(id, entry.metadata()?.len()));
+        }
+        Ok(blobs)
+    }
+
+    fn delete(&self, id: &str) -> Result {
+        let path = self.path_of(id)?;
+        std::fs::remove_file(&path)
+            .with_context(|| format!("unable to delete '{}'", path.display()))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result {
+        let from = self.path_of(from)?;
+        let to = self.path_of(to)?;
+        std::fs::rename(&from, &to).with_context(|| {
+            format!(
+                "unable to rename '{}' to '{}'",
+                from.display(),
+                to.display()
+            )
+        })
+    }
+
+    fn contains(&self, id: &str) -> Result<bool> {
+        match std::fs::metadata(self.path_of(id)?) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn modified(&self, id: &str) -> Result<Option<Timestamp>> {
+        let path = self.path_of(id)?;
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("unable to read '{}'", path.display()))?;
+        Ok(Some(modified.into()))
+    }
+}
+
+/// A [`StorageBackend`] keeping every blob in memory, e.g. for tests or a store that should not
+/// outlive the process. The blobs are gone once the backend is dropped. Every blob keeps the
+/// time it was written, so [`BackupManager::gc`](crate::BackupManager::gc) can tell its age.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    blobs: Mutex<BTreeMap<String, (Vec<u8>, Timestamp)>>,
+}
+
+impl MemoryBackend {
+    /// Creates a new, empty [`MemoryBackend`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the total size of the blobs in bytes
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.blobs()
+            .values()
+            .map(|(bytes, _)| u64::cast_from(bytes.len()))
+            .sum()
+    }
+
+    fn blobs(&self) -> MutexGuard<'_, BTreeMap<String, (Vec<u8>, Timestamp)>> {
+        // every change to the map is a single call, a panic cannot leave it half updated
+        self.blobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The error of a [`MemoryBackend`] without the blob `id`
+fn missing_blob(id: &str) -> crate::Error {
+    std::io::Error::new(ErrorKind::NotFound, format!("no blob '{id}'")).into()
+}
+
+impl StorageBackend for MemoryBackend {
+    fn put(&self, id: &str, bytes: &[u8]) -> Result {
+        self.blobs()
+            .insert(id.to_string(), (bytes.to_vec(), Timestamp::now()));
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Vec<u8>> {
+        self.blobs()
+            .get(id)
+            .map(|(bytes, _)| bytes.clone())
+            .ok_or_else(|| missing_blob(id))
+    }
+
+    fn tail(&self, id: &str, len: usize) -> Result<Vec<u8>> {
+        let blobs = self.blobs();
+        let (bytes, _) = blobs.get(id).ok_or_else(|| missing_blob(id))?;
+        Ok(bytes[bytes.len().saturating_sub(len)..].to_vec())
+    }
+
+    fn list(&self) -> Result<Vec<BlobEntry>> {
+        Ok(self
+            .blobs()
+            .iter()
+            .map(|(id, (bytes, _))| BlobEntry::new(id, u64::cast_from(bytes.len())))
+            .collect())
+    }
+
+    fn delete(&self, id: &str) -> Result {
+        self.blobs()
+            .remove(id)
+            .map(drop)
+            .ok_or_else(|| missing_blob(id))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result {
+        let mut blobs = self.blobs();
+        let blob = blobs.remove(from).ok_or_else(|| missing_blob(from))?;
+        blobs.insert(to.to_string(), blob);
+        Ok(())
+    }
+
+    fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self.blobs().contains_key(id))
+    }
+
+    fn modified(&self, id: &str) -> Result<Option<Timestamp>> {
+        self.blobs()
+            .get(id)
+            .map(|&(_, modified)| Some(modified))
+            .ok_or_else(|| missing_blob(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BackupManager, Config, DryRun, FileVersion, MemoryFs, RestoreOptions, RetentionPolicy,
+        SyncMode,
+    };
+
+    #[test]
+    fn local_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(dir.path());
+        std::fs::write(dir.path().join("index"), "not a blob").unwrap();
+        assert_eq!(backend.list().unwrap(), []);
+        assert!(!backend.contains("a.bak").unwrap());
+
+        let mut updates = Vec::new();
+        backend
+            .put_with_progress("a.bak", b"first", &mut |p, t| updates.push((p, t)))
+            .unwrap();
+        backend.put("a.bak", b"second").unwrap();
+        assert_eq!(updates.last(), Some(&(5, 5)));
+        assert_eq!(backend.get("a.bak").unwrap(), b"second");
+        assert_eq!(backend.list().unwrap(), [BlobEntry::new("a.bak", 6)]);
+        assert!(backend.contains("a.bak").unwrap());
+        assert!(backend.modified("a.bak").unwrap().is_some());
+        assert!(backend.modified("b.bak").is_err());
+
+        backend.delete("a.bak").unwrap();
+        assert!(backend.list().unwrap().is_empty());
+        assert!(backend.get("a.bak").is_err());
+        assert!(backend.delete("a.bak").is_err());
+
+        backend.put("a.bak.new", b"renamed").unwrap();
+        assert_eq!(backend.list().unwrap(), []);
+        backend.rename("a.bak.new", "a.bak").unwrap();
+        assert_eq!(backend.get("a.bak").unwrap(), b"renamed");
+        assert!(backend.rename("a.bak.new", "a.bak").is_err());
+        backend.delete("a.bak").unwrap();
+
+        for id in [
+            "",
+            ".",
+            "..",
+            "../a.bak",
+            "a/b.bak",
+            "a\\b.bak",
+            "/tmp/a.bak",
+        ] {
+            assert!(backend.put(id, b"outside").is_err(), "{id}");
+            assert!(backend.get(id).is_err(), "{id}");
+        }
+        assert!(!dir.path().parent().unwrap().join("a.bak").exists());
+    }
+
+    #[test]
+    fn keeps_free_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let warn = LocalBackend::new(dir.path()).with_min_free_bytes(u64::MAX, LowDiskAction::Warn);
+        warn.put("a.bak", b"written anyway").unwrap();
+        assert_eq!(warn.get("a.bak").unwrap(), b"written anyway");
+
+        let fail = LocalBackend::new(dir.path()).with_min_free_bytes(u64::MAX, LowDiskAction::Fail);
+        let err = fail.put("b.bak", b"refused").unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::DiskFull {
+                required: u64::MAX,
+                ..
+            }
+        ));
+        assert_eq!(err.category(), "disk_full");
+        assert_eq!(fail.list().unwrap(), [BlobEntry::new("a.bak", 14)]);
+
+        let roomy = LocalBackend::new(dir.path()).with_min_free_bytes(0, LowDiskAction::Fail);
+        roomy.put("c.bak", b"fits").unwrap();
+    }
+
+    #[test]
+    fn manages_remote_blobs() {
+        let store = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "v1").unwrap();
+        let config = Config::new().extend_with(
+            &storage_common::MaybeConfig::default().with_store_dir(store.path().to_str().unwrap()),
+        );
+
+        let manager = BackupManager::with_backend(config, MemoryBackend::default()).unwrap();
+        manager.backup(&path).unwrap();
+        std::fs::write(&path, "v2").unwrap();
+        assert_eq!(
+            manager.backup_all([&path])[0]
+                .1
+                .as_ref()
+                .unwrap()
+                .version()
+                .get(),
+            2
+        );
+        // an object and a content blob for each version
+        assert_eq!(manager.backend().list().unwrap().len(), 4);
+        // only the index is kept locally
+        assert!(LocalBackend::new(store.path()).list().unwrap().is_empty());
+
+        assert_eq!(manager.history(&path).len(), 2);
+        assert_eq!(manager.rebuild_index().unwrap(), 2);
+        let diff = manager.diff(&path, None, None).unwrap();
+        assert_eq!(diff.to().get(), 2);
+        let restored = files.path().join("restored");
+        let options = RestoreOptions::new().with_destination(&restored);
+        let file = manager.restore(&path, None, &options).unwrap();
+        assert_eq!(std::fs::read(file.destination()).unwrap(), b"v2");
+        let policy = "keep=1".parse::<RetentionPolicy>().unwrap();
+        manager.apply_retention(&policy, DryRun::Off).unwrap();
+        assert_eq!(manager.backend().list().unwrap().len(), 2);
+        let missing = manager.backend().get("missing.bak").unwrap_err();
+        assert_eq!(missing.io_kind(), Some(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn backs_up_in_memory() {
+        let store = tempfile::tempdir().unwrap();
+        let config = Config::new().extend_with(
+            &storage_common::MaybeConfig::default()
+                .with_store_dir(store.path().to_str().unwrap())
+                .with_tracking_list(store.path().join("tracking.json").to_str().unwrap()),
+        );
+        let path = Path::new("/home/me/notes.txt");
+        let mut manager = BackupManager::with_backend(config, MemoryBackend::default()).unwrap();
+        manager.set_vfs(MemoryFs::new().with_file(path, "v1"));
+
+        manager.backup(path).unwrap();
+        manager.vfs().write(path, b"v2").unwrap();
+        let report = manager.sync([Path::new("/home/me")], SyncMode::Quick);
+        assert_eq!(report.backed_up().len(), 1);
+
+        let options = RestoreOptions::new().with_verification(true);
+        let file = manager
+            .restore(path, Some(FileVersion::new()), &options)
+            .unwrap();
+        assert!(file.is_verified());
+        assert_eq!(manager.vfs().read(path).unwrap(), b"v1");
+        // the contents the restore overwrote were already backed up
+        assert_eq!(file.pre_restore_version().map(|v| v.get()), Some(2));
+        manager.vfs().remove_file(path).unwrap();
+        assert!(manager.record_deletion(path).unwrap().is_some());
+    }
+
+    #[test]
+    fn round_trips_in_memory() {
+        let store = tempfile::tempdir().unwrap();
+        let config = Config::new().extend_with(
+            &storage_common::MaybeConfig::default().with_store_dir(store.path().to_str().unwrap()),
+        );
+        let path = Path::new("/home/me/notes.txt");
+        let mut manager = BackupManager::with_backend(config, MemoryBackend::new()).unwrap();
+        manager.set_vfs(MemoryFs::new().with_file(path, "v1"));
+        for contents in ["v1", "v2", "v3"] {
+            manager.vfs().write(path, contents.as_bytes()).unwrap();
+            manager.backup(path).unwrap();
+        }
+
+        let history = manager.history(path);
+        assert_eq!(history.len(), 3);
+        assert_eq!(
+            manager.read_version(path, *history[0].version()).unwrap(),
+            b"v1"
+        );
+
+        // pruning removes the objects and contents of the older versions
+        let policy = "keep=1".parse::<RetentionPolicy>().unwrap();
+        let report = manager.apply_retention(&policy, DryRun::Off).unwrap();
+        assert_eq!(report.removed_versions(), 2);
+        assert_eq!(manager.history(path).len(), 1);
+        assert_eq!(manager.backend().list().unwrap().len(), 2);
+        let report = manager.gc(std::time::Duration::ZERO, DryRun::Off).unwrap();
+        assert!(report.removed().is_empty());
+
+        // the remaining version is verified against its checksum on restore
+        let restored = Path::new("/home/me/restored");
+        let options = RestoreOptions::new()
+            .with_destination(restored)
+            .with_verification(true);
+        let file = manager.restore(path, None, &options).unwrap();
+        assert!(file.is_verified());
+        assert_eq!(manager.vfs().read(file.destination()).unwrap(), b"v3");
+        assert!(LocalBackend::new(store.path()).list().unwrap().is_empty());
+    }
+}
@@ -5,26 +5,73 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
-    fs::Metadata,
-    io::{BufReader, BufWriter, Read, Write},
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
 };
 
-use brotli::CompressorWriter;
 use serde::{Deserialize, Serialize};
 use xstd::{
     cast::CastFrom,
+    collections::LruCache,
     fs::{create_write_truncate, read_only},
+    hash::fnv1a,
+    thread::{ThreadPool, ThreadPoolBuilder},
 };
 
-use crate::{Config, FileHeader, FileMeta, FileVersion, Result, Timestamp};
+use storage_common::{write_all_with_progress, HookEvent, ProgressSink, ResultExt};
+use storage_format::{CHECKSUM_FORMAT_VERSION, CHECKSUM_SIZE};
 
-/// A file that has been backed up
+use crate::{
+    annotations::{self, AnnotationIndex},
+    archive, clone, content, crypto, diff, eviction, gc,
+    hooks::{HookDispatcher, HookPayload},
+    index,
+    lock::{StoreLock, DEFAULT_LOCK_TIMEOUT},
+    migrate::{self, Journal},
+    pipeline::Source,
+    queue::BackupQueue,
+    restore,
+    snapshot::{Snapshot, SnapshotBuilder, SnapshotId, SnapshotIndex},
+    symlink, sync, vfs, Annotation, AnnotationReport, BackupPipeline, CloneReport, Compression,
+    Config, Dictionary, DryRun, EvictionReport, FileHeader, FileKind, FileMeta, FileVersion,
+    FsMetadata, GcReport, ImportReport, LocalBackend, Manifest, MigrationReport, QueuedBackup,
+    RestoreOptions, RestoredFile, Result, RetentionPolicy, RetentionReport, Schedule, Shutdown,
+    StorageBackend, StoreKey, StoreStats, StreamOptions, SymlinkPolicy, SyncMode, SyncReport,
+    Timestamp, UniqueId, Vfs, PRE_RESTORE_TAG,
+};
+
+use crate::vfs::RealFs;
+
+/// The contents of a [`BackupFile`]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FileData {
+    /// The contents are held in memory
+    Loaded(Vec<u8>),
+    /// The contents are left in the store object at the given path until they are needed
+    OnDisk(PathBuf),
+}
+
+impl FileData {
+    /// Gets whether the contents are held in memory
+    #[must_use]
+    pub fn is_loaded(&self) -> bool {
+        matches!(self, Self::Loaded(_))
+    }
+}
+
+/// A file that has been backed up. Its contents are either held in memory or, for a backup
+/// opened with [`BackupFile::open`], only read from the store when they are needed.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BackupFile {
     header: FileHeader,
     meta: FileMeta,
-    file_bytes: Vec<u8>,
+    data: FileData,
+    /// The store object this backup was read from, which the contents can be unloaded to
+    object: Option<PathBuf>,
 }
 
 impl BackupFile {
@@ -34,23 +81,116 @@ impl BackupFile {
     /// - Function returns an error if any io operations fail.
     /// - Function returns an error if the serialization of [`FileMeta`] fails (this is used to get the size of the metadata for [`FileHeader`]).
     pub fn create_new(path: impl AsRef<Path>) -> Result<Self> {
+        Self::create_versioned(path, FileVersion::new())
+    }
+
+    /// Create a new backup file from the file at the given path with the given `version`
+    ///
+    /// ## Errors
+    /// - Function returns an error if any io operations fail.
+    /// - Function returns an error if the serialization of [`FileMeta`] fails (this is used to get the size of the metadata for [`FileHeader`]).
+    pub fn create_versioned(path: impl AsRef<Path>, version: FileVersion) -> Result<Self> {
+        Self::create_versioned_with_progress(path, version, &mut ())
+    }
+
+    /// Same as [`BackupFile::create_new`], reporting the number of bytes read so far to `progress`
+    ///
+    /// ## Errors
+    /// - Function returns an error if any io operations fail.
+    /// - Function returns an error if the serialization of [`FileMeta`] fails (this is used to get the size of the metadata for [`FileHeader`]).
+    pub fn create_new_with_progress(
+        path: impl AsRef<Path>,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<Self> {
+        Self::create_versioned_with_progress(path, FileVersion::new(), progress)
+    }
+
+    /// Same as [`BackupFile::create_versioned`], reporting the number of bytes read so far to `progress`
+    ///
+    /// ## Errors
+    /// - Function returns an error if any io operations fail.
+    /// - Function returns an error if the serialization of [`FileMeta`] fails (this is used to get the size of the metadata for [`FileHeader`]).
+    pub fn create_versioned_with_progress(
+        path: impl AsRef<Path>,
+        version: FileVersion,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<Self> {
+        Self::create_with_policy(path, version, SymlinkPolicy::default(), progress)
+    }
+
+    /// Same as [`BackupFile::create_versioned_with_progress`], backing up a symbolic link at
+    /// `path` according to `symlinks`. A preserved link is stored as its target path with
+    /// [`FileKind::Symlink`] metadata, a followed one as the contents of the file it points to.
+    ///
+    /// ## Errors
+    /// - Function returns an error if any io operations fail.
+    /// - Function returns an error if `path` is not a regular file or a symbolic link (to one).
+    /// - Function returns an error if the serialization of [`FileMeta`] fails (this is used to get the size of the metadata for [`FileHeader`]).
+    pub fn create_with_policy(
+        path: impl AsRef<Path>,
+        version: FileVersion,
+        symlinks: SymlinkPolicy,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<Self> {
+        Self::create_in(&RealFs, path, version, symlinks, progress)
+    }
+
+    /// Same as [`BackupFile::create_with_policy`], reading the file from `vfs` instead of the
+    /// real file system
+    ///
+    /// ## Errors
+    /// - Function returns an error if the file cannot be read from `vfs`.
+    /// - Function returns an error if `path` is not a regular file or a symbolic link (to one).
+    /// - Function returns an error if the serialization of [`FileMeta`] fails (this is used to get the size of the metadata for [`FileHeader`]).
+    pub fn create_in(
+        vfs: &dyn Vfs,
+        path: impl AsRef<Path>,
+        version: FileVersion,
+        symlinks: SymlinkPolicy,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<Self> {
         let path = path.as_ref();
-        let (raw_meta, file_bytes) = Self::extract_file_info(path)?;
-        let meta =
-            FileMeta::new_from_metadata(path, Timestamp::now(), &raw_meta, FileVersion::new())?;
-        let meta_size = rmp_serde::to_vec(&meta)?.len();
+        let (fs_meta, file_bytes) = Self::extract_file_info(vfs, path, symlinks, progress)?;
+        let meta = FileMeta::new(version, Timestamp::now(), path.to_path_buf(), fs_meta)
+            .with_id(UniqueId::new());
+        let meta_size = storage_format::encode_meta(&meta)?.len();
 
         let header = FileHeader::new(meta_size, file_bytes.len());
 
         let backup_file = Self {
             header,
             meta,
-            file_bytes,
+            data: FileData::Loaded(file_bytes),
+            object: None,
         };
 
         Ok(backup_file)
     }
 
+    /// Opens the store object at `backup_path`, reading only its [`FileHeader`] and
+    /// [`FileMeta`]. The contents stay [on disk](FileData::OnDisk) until they are
+    /// [loaded](BackupFile::load) or needed to restore the file. Contents kept in a content blob
+    /// (see [`FileMeta::content`]) are read from the blob next to the object, the backup looks as
+    /// if the object held them itself.
+    ///
+    /// ## Errors
+    /// - Function returns an error if the object cannot be read (see [`extract_header_and_meta`]).
+    pub fn open(backup_path: impl Into<PathBuf>) -> Result<Self> {
+        let backup_path = backup_path.into();
+        let (mut header, mut meta) = extract_header_and_meta(&backup_path)?;
+        if let Some(content) = meta.content() {
+            let size = usize::try_from(content.size())
+                .map_err(|_| format!("'{}' is too large", backup_path.display()))?;
+            (header, meta) = content::inline(meta, size)?;
+        }
+        Ok(Self {
+            header,
+            meta,
+            data: FileData::OnDisk(backup_path.clone()),
+            object: Some(backup_path),
+        })
+    }
+
     /// Updates this backup file. This should be called when a change is detected in the original file.
     /// It updates the [`FileMeta`] from the current metadata, bumps the version, and updates the file bytes.
     ///
@@ -58,21 +198,134 @@ impl BackupFile {
     /// - Function returns an error if any IO operations fail.
     /// - Function returns an error if the serialization of [`FileMeta`] fails (this is used to get the size of the metadata for [`FileHeader`]).
     pub fn update_backup(&mut self) -> Result<()> {
-        let (raw_meta, file_bytes) = Self::extract_file_info(self.meta.path())?;
-        self.meta.update_from_metadata(&raw_meta);
+        let symlinks = if self.meta.fs_meta().file_type() == FileKind::Symlink {
+            SymlinkPolicy::Preserve
+        } else {
+            SymlinkPolicy::Follow
+        };
+        let (fs_meta, file_bytes) =
+            Self::extract_file_info(&RealFs, self.meta.path(), symlinks, &mut ())?;
+        self.meta.set_fs_meta(fs_meta);
         self.meta.bump_version();
-        let meta_size = rmp_serde::to_vec(&self.meta)?.len();
+        self.meta.set_id(UniqueId::new());
+        let meta_size = storage_format::encode_meta(&self.meta)?.len();
 
         self.header = FileHeader::new(meta_size, file_bytes.len());
-        self.file_bytes = file_bytes;
+        self.data = FileData::Loaded(file_bytes);
+        // the new contents are not in any store object yet
+        self.object = None;
 
         Ok(())
     }
 
+    /// Gets the [`FileHeader`] of this backup
+    #[must_use]
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// Gets the [`FileMeta`] of this backup
+    #[must_use]
+    pub fn meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    /// Gets the contents of the file, which may not be loaded
+    #[must_use]
+    pub fn data(&self) -> &FileData {
+        &self.data
+    }
+
+    /// Gets the backed up contents of the file, `None` if they have not been
+    /// [loaded](BackupFile::load)
+    #[must_use]
+    pub fn file_bytes(&self) -> Option<&[u8]> {
+        match &self.data {
+            FileData::Loaded(bytes) => Some(bytes),
+            FileData::OnDisk(_) => None,
+        }
+    }
+
+    /// Reads the contents of the file from the store object into memory, if they are not loaded
+    /// already, and returns them
+    ///
+    /// ## Errors
+    /// - Function returns an error if the store object cannot be read or decoded.
+    /// - Function returns an error if the store object has been replaced since it was opened.
+    pub fn load(&mut self) -> Result<&[u8]> {
+        if let FileData::OnDisk(path) = &self.data {
+            self.data = FileData::Loaded(self.read_object(path)?);
+        }
+        match &self.data {
+            FileData::Loaded(bytes) => Ok(bytes),
+            FileData::OnDisk(_) => unreachable!("the contents have just been loaded"),
+        }
+    }
+
+    /// Drops the contents of the file from memory, returning whether they were dropped. Only
+    /// the contents of a backup read from a store object can be unloaded, they are read from it
+    /// again when needed.
+    pub fn unload(&mut self) -> bool {
+        match &self.object {
+            Some(object) => {
+                self.data = FileData::OnDisk(object.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gets the contents of the file, reading them from the store object without keeping them
+    /// if they are not loaded
+    fn bytes(&self) -> Result<Cow<'_, [u8]>> {
+        match &self.data {
+            FileData::Loaded(bytes) => Ok(Cow::Borrowed(bytes)),
+            FileData::OnDisk(path) => Ok(Cow::Owned(self.read_object(path)?)),
+        }
+    }
+
+    /// Reads the contents of the file from the store object at `path`, or the content blob next
+    /// to it
+    fn read_object(&self, path: &Path) -> Result<Vec<u8>> {
+        let blobs = LocalBackend::new(path.parent().unwrap_or(Path::new("")));
+        let (header, meta, bytes) =
+            content::decode(&blobs, &std::fs::read(path)?).map_err(|e| e.with_path(path))?;
+        if header != self.header || meta.id() != self.meta.id() {
+            return Err(format!(
+                "backup object '{}' has been replaced since it was opened",
+                path.display()
+            )
+            .into());
+        }
+        Ok(bytes)
+    }
+
+    /// Creates a backup file holding `file_bytes` in memory, e.g. once they have been read out of
+    /// a store object
+    pub(crate) fn from_parts(header: FileHeader, meta: FileMeta, file_bytes: Vec<u8>) -> Self {
+        Self {
+            header,
+            meta,
+            data: FileData::Loaded(file_bytes),
+            object: None,
+        }
+    }
+
+    /// Splits this backup file into its header, metadata and file bytes, loading the bytes if
+    /// necessary
+    pub(crate) fn into_parts(mut self) -> Result<(FileHeader, FileMeta, Vec<u8>)> {
+        self.load()?;
+        match self.data {
+            FileData::Loaded(bytes) => Ok((self.header, self.meta, bytes)),
+            FileData::OnDisk(_) => unreachable!("the contents have just been loaded"),
+        }
+    }
+
     /// Compresses this backup file into a [`CompressedBackupFile`] using `brotli`
     ///
     /// ## Errors
-    /// - Function returns an error if any IO operations fail.
+    /// - Function returns an error if any IO operations fail, e.g. reading contents that are not
+    ///   loaded.
     /// - Function returns an error if the `rmp_serde` serialization fails.
     /// - Function returns an error if `brotli` compression fails.
     ///
@@ -82,66 +335,154 @@ impl BackupFile {
     ///
     /// See also: [`CompressedBackupFile::try_decompress`]
     pub fn try_compress(self) -> Result<CompressedBackupFile> {
-        // Convert header to bytes using bytemuck
-        let header_bytes = bytemuck::bytes_of(&self.header);
-        assert_eq!(
-            header_bytes.len(),
-            std::mem::size_of::<FileHeader>(),
-            "header_bytes should be the same size as FileHeader"
-        );
-
-        // Convert metadata to bytes using rmp_serde
-        let meta_bytes = rmp_serde::to_vec(&self.meta)?;
-        assert_eq!(
-            meta_bytes.len(),
-            self.header.meta_size,
-            "meta bytes should be the size indicated by the header"
-        );
+        self.try_compress_with_progress(&mut ())
+    }
 
-        assert_eq!(
-            self.file_bytes.len(),
-            self.header.file_size,
-            "meta bytes should be the size indicated by the header"
-        );
+    /// Same as [`BackupFile::try_compress`], reporting the number of (uncompressed) bytes
+    /// compressed so far to `progress`
+    ///
+    /// ## Errors
+    /// - Function returns an error if any IO operations fail.
+    /// - Function returns an error if the `rmp_serde` serialization fails.
+    /// - Function returns an error if `brotli` compression fails.
+    ///
+    /// ## Panics
+    /// Function panics if any of the various size assertions fail.
+    pub fn try_compress_with_progress(
+        self,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<CompressedBackupFile> {
+        let bytes = storage_format::encode_with_progress(
+            &self.header,
+            &self.meta,
+            &self.bytes()?,
+            StreamOptions::default(),
+            progress,
+        )?;
+        Ok(CompressedBackupFile::new(bytes))
+    }
 
-        let total_size =
-            std::mem::size_of::<FileHeader>() + self.file_bytes.len() + meta_bytes.len();
-        let mut bytes = Vec::with_capacity(total_size);
-        bytes.extend_from_slice(header_bytes);
-        bytes.extend_from_slice(&meta_bytes);
-        bytes.extend_from_slice(&self.file_bytes);
-        assert_eq!(
-            bytes.len(),
-            total_size,
-            "bytes.len() should be the expected/calculated total size"
-        );
+    /// Restores the backed up bytes to the original path of the file, overwriting it.
+    ///
+    /// ## Errors
+    /// - Function returns an error if the file cannot be created or written to.
+    pub fn restore(&self) -> Result<()> {
+        self.restore_to(self.meta.path())
+    }
 
-        let mut compressed_bytes = Vec::with_capacity(bytes.capacity());
-        {
-            let mut compressor =
-                CompressorWriter::new(&mut compressed_bytes, crate::BUFFER_SIZE, 11, 22);
-            compressor.write_all(&bytes)?;
-            compressor.flush()?;
-        }
+    /// Restores the backed up bytes to the given `path`, overwriting any existing file. The
+    /// captured [`Permissions`](crate::Permissions) of the file are reapplied where possible, as
+    /// are its extended attributes with the `xattr` feature.
+    /// A backup of a symbolic link recreates the link instead, replacing any existing file.
+    ///
+    /// ## Errors
+    /// - Function returns an error if the file cannot be created or written to.
+    /// - Function returns an error if the permissions of the file cannot be changed.
+    pub fn restore_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.restore_to_with_progress(path, &mut ())
+    }
 
-        Ok(CompressedBackupFile::new(compressed_bytes))
+    /// Same as [`BackupFile::restore_to`], reporting the number of bytes written so far to `progress`
+    ///
+    /// ## Errors
+    /// - Function returns an error if the contents are not loaded and cannot be read.
+    /// - Function returns an error if the file cannot be created or written to.
+    /// - Function returns an error if the permissions of the file cannot be changed.
+    pub fn restore_to_with_progress(
+        &self,
+        path: impl AsRef<Path>,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<()> {
+        self.restore_in(&RealFs, path, progress)
     }
 
-    /// Extracts the metadata and reads the bytes from the file at the given path
-    fn extract_file_info(path: impl AsRef<Path>) -> Result<(Metadata, Vec<u8>)> {
+    /// Same as [`BackupFile::restore_to_with_progress`], writing the file to `vfs` instead of
+    /// the real file system
+    ///
+    /// ## Errors
+    /// - Function returns an error if the contents are not loaded and cannot be read.
+    /// - Function returns an error if the file cannot be created or written to in `vfs`.
+    /// - Function returns an error if the permissions of the file cannot be changed.
+    pub fn restore_in(
+        &self,
+        vfs: &dyn Vfs,
+        path: impl AsRef<Path>,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<()> {
         let path = path.as_ref();
-        let raw_metadata = std::fs::metadata(path)?;
-        let file_size = CastFrom::cast_from(raw_metadata.len());
+        let file_bytes = self.bytes()?;
+        if self.meta.fs_meta().file_type() == FileKind::Symlink {
+            let len = u64::cast_from(file_bytes.len());
+            progress.progress(0, len);
+            vfs.symlink(path, &symlink::target_from_bytes(&file_bytes))
+                .with_context(|| format!("unable to restore the link '{}'", path.display()))?;
+            progress.progress(len, len);
+            progress.finish();
+            return Ok(());
+        }
+        let context = || format!("unable to write '{}'", path.display());
+        let mut writer = BufWriter::new(vfs.create(path).with_context(context)?);
+        write_all_with_progress(&mut writer, &file_bytes, crate::BUFFER_SIZE, progress)
+            .with_context(context)?;
+        writer.flush().with_context(context)?;
+        drop(writer);
+        vfs.set_metadata(path, self.meta.fs_meta())
+    }
+
+    /// Extracts the metadata and reads the bytes from the file at the given path. A symbolic
+    /// link is either read as its target path or followed, depending on `symlinks`.
+    fn extract_file_info(
+        vfs: &dyn Vfs,
+        path: &Path,
+        symlinks: SymlinkPolicy,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(FsMetadata, Vec<u8>)> {
+        let context = || format!("unable to read '{}'", path.display());
+        let link_metadata = vfs.symlink_metadata(path).with_context(context)?;
+        let fs_meta = if link_metadata.file_type() == FileKind::Symlink {
+            if symlinks == SymlinkPolicy::Preserve {
+                let target = vfs::read_target(vfs, path).with_context(context)?;
+                let len = u64::cast_from(target.len());
+                progress.progress(0, len);
+                progress.progress(len, len);
+                progress.finish();
+                return Ok((link_metadata, target));
+            }
+            vfs.metadata(path).with_context(context)?
+        } else {
+            link_metadata
+        };
+        // directories cannot be read, and reading a fifo or device could block or never end
+        if fs_meta.file_type() != FileKind::File {
+            return Err(format!(
+                "'{}' is a {}, only regular files and symbolic links can be backed up",
+                path.display(),
+                fs_meta.file_type()
+            )
+            .into());
+        }
+        let file_size = CastFrom::cast_from(fs_meta.size());
         let mut file_bytes = Vec::with_capacity(file_size);
         {
-            let mut reader = BufReader::new(read_only().open(path)?);
-            let bytes_read = reader.read_to_end(&mut file_bytes)?;
+            let mut reader = BufReader::new(vfs.open(path).with_context(context)?);
+            let mut buffer = vec![0; crate::BUFFER_SIZE];
+            progress.progress(0, fs_meta.size());
+            loop {
+                let read = reader.read(&mut buffer).with_context(context)?;
+                if read == 0 {
+                    break;
+                }
+                file_bytes.extend_from_slice(&buffer[..read]);
+                progress.progress(u64::cast_from(file_bytes.len()), fs_meta.size());
+            }
+            progress.finish();
             assert_eq!(
-                bytes_read, file_size,
+                file_bytes.len(),
+                file_size,
                 "bytes_read should be the same as file_size"
             );
         }
-        Ok((raw_metadata, file_bytes))
+        Ok((fs_meta, file_bytes))
     }
 }
 
@@ -159,41 +500,36 @@ impl CompressedBackupFile {
     /// Attempts to decompress this [`CompressedBackupFile`] into a [`BackupFile`]
     ///
     /// ## Errors
+    /// - Function returns an error if the contents are kept in a content blob, see
+    ///   [`FileMeta::content`].
+    /// - Function returns [`Error::Corrupted`](storage_common::Error::Corrupted) if the checksum of the
+    ///   compressed bytes does not match.
     /// - Function returns an error if any IO operations fail.
     /// - Function returns an error if the `brotli` decompression fails.
+    /// - Function returns an error if the sizes in the [`FileHeader`] do not match the decompressed bytes.
     /// - Function returns an error if the `rmp_serde` deserialization fails.
-    ///
-    /// ## Panics
-    /// Function panics if any of the various size assertions fail. These will eventually be changed to `debug_`
-    /// or possibly removed completely once I have verified that the function works as expected.
     pub fn try_decompress(self) -> Result<BackupFile> {
-        let mut decompressed_bytes = Vec::with_capacity(self.0.len());
-        let mut reader = BufReader::new(&self.0[..]);
-
-        let mut decompressor = brotli::Decompressor::new(&mut reader, crate::BUFFER_SIZE);
-        decompressor.read_to_end(&mut decompressed_bytes)?;
-        let (header, rest) = FileHeader::try_from_bytes(&decompressed_bytes)?;
-        let (meta_bytes, file_bytes) = rest.split_at(header.meta_size);
-
-        assert_eq!(
-            meta_bytes.len(),
-            header.meta_size,
-            "meta bytes should be the size indicated by the header"
-        );
-        assert_eq!(
-            file_bytes.len(),
-            header.file_size,
-            "file bytes should be the size indicated by the header"
-        );
+        let (header, meta, file_bytes) = storage_format::decode(&self.0)?;
+        if let Some(content) = meta.content() {
+            return Err(format!(
+                "the contents of the backup are kept in content blob '{}'",
+                content.name()
+            )
+            .into());
+        }
+        Ok(BackupFile::from_parts(header, meta, file_bytes))
+    }
 
-        let bytes: Vec<u8> = file_bytes.into();
+    /// Gets the size of the compressed bytes
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
 
-        let meta = rmp_serde::from_slice(meta_bytes)?;
-        Ok(BackupFile {
-            header,
-            meta,
-            file_bytes: bytes,
-        })
+    /// Checks whether there are no compressed bytes
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
     /// Writes this [`CompressedBackupFile`] to the given path, overwriting any existing file.
@@ -211,92 +547,3378 @@ impl CompressedBackupFile {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-struct BackupInfo {
-    header: FileHeader,
-    meta: FileMeta,
-    backup_path: PathBuf,
+pub(crate) struct BackupInfo {
+    pub(crate) header: FileHeader,
+    pub(crate) meta: FileMeta,
+    pub(crate) backup_path: PathBuf,
+    /// The size of the backup file on disk (i.e. the compressed size)
+    pub(crate) backup_size: u64,
+}
+
+impl BackupInfo {
+    /// Gets the id of the backup, see [`FileMeta::id`]
+    pub(crate) fn id(&self) -> UniqueId {
+        self.meta.id()
+    }
+
+    /// Gets the id of the object in the [`StorageBackend`] of the store, its file name
+    pub(crate) fn object_id(&self) -> Result<&str> {
+        object_id(&self.backup_path)
+    }
+
+    /// Gets the size of the original contents, whether the object holds them or a content blob
+    pub(crate) fn file_size(&self) -> u64 {
+        self.meta
+            .content()
+            .map_or(u64::cast_from(self.header.file_size), |content| {
+                content.size()
+            })
+    }
+}
+
+/// Gets the id of the object recorded at `backup_path` in the [`StorageBackend`] of the store
+fn object_id(backup_path: &Path) -> Result<&str> {
+    backup_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("invalid backup path '{}'", backup_path.display()).into())
+}
+
+/// Loads the shared dictionary of the store of `config` out of `backend`. A dictionary that
+/// cannot be loaded is not used, blobs compressed with it fail to decode on their own.
+fn load_dictionary(config: &Config, backend: &dyn StorageBackend) -> Option<Arc<Dictionary>> {
+    content::current_dictionary(config, backend)
+        .unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "ignoring unreadable compression dictionary");
+            None
+        })
+        .map(Arc::new)
+}
+
+/// What [`BackupManager::update_metadata`] did
+#[derive(Debug, Clone)]
+pub enum MetadataUpdate {
+    /// The filesystem metadata of the latest backup was replaced and its metadata revision
+    /// bumped, the contents and version are unchanged
+    Updated(Box<FileMeta>),
+    /// The latest backup already has the permissions of the file
+    Unchanged,
+    /// The file has never been backed up or its contents changed since, it needs a new backup
+    NeedsBackup,
+}
+
+/// The number of objects whose header and metadata a [`BackupManager`] keeps in memory after
+/// reading them, so objects that drop out of its index and reappear are not decompressed again
+const META_CACHE_CAPACITY: usize = 4096;
+/// The most backups [`BackupManager::train_dictionary`] reads to train a dictionary on
+const MAX_DICTIONARY_SAMPLES: usize = 1000;
+/// The name of the threads backing up batches of files, see [`Config::backup_threads`]
+const BACKUP_THREAD_NAME: &str = "storage-backup";
+/// How many files per backup thread may wait for one, a batch is queued as the threads take files
+const BACKUP_QUEUE_PER_THREAD: usize = 2;
+
+/// How far a [`BackupManager`] got loading the index of its store, which happens on first use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IndexState {
+    /// Nothing was read yet
+    Unloaded,
+    /// Loading the index failed, it is only retried before the store is written to
+    Failed,
+    /// The index was loaded and is kept up to date from now on
+    Loaded,
 }
 
 /// The main interface for backing up and retreiving files
+///
+/// The manager can be shared between threads, e.g. behind an [`Arc`]: its index of backups is
+/// kept behind a lock, and every operation writing to the store holds the store lock file (see
+/// [`BackupManager::set_lock_timeout`]) so other processes using the same store are not affected.
+///
+/// The objects are kept by a [`StorageBackend`], by default a [`LocalBackend`] in the store
+/// directory. The index, the annotations and the lock file always live in the store directory.
+/// The files that are backed up and restored are reached through a [`Vfs`], by default the real
+/// file system (see [`BackupManager::set_vfs`]). Small contents are compressed with the shared
+/// dictionary of the store, once one has been trained (see [`BackupManager::train_dictionary`]).
 #[derive(Debug)]
-pub struct BackupManager {
+pub struct BackupManager<B: StorageBackend = LocalBackend> {
     config: Config,
-    file_info: Vec<BackupInfo>,
+    backend: Arc<B>,
+    vfs: Arc<dyn Vfs>,
+    file_info: RwLock<Vec<BackupInfo>>,
+    index_state: Mutex<IndexState>,
+    pipeline: Arc<BackupPipeline>,
+    pending: Mutex<BackupQueue>,
+    annotations: RwLock<AnnotationIndex>,
+    dictionary: RwLock<Option<Arc<Dictionary>>>,
+    lock_timeout: Duration,
+    lock_generation: Mutex<Option<u64>>,
+    evictions: Mutex<EvictionReport>,
+    hooks: HookDispatcher,
+    shutdown: Shutdown,
+    meta_cache: Mutex<LruCache<(PathBuf, u64), (FileHeader, FileMeta)>>,
 }
 
 impl BackupManager {
-    /// Creates a new [`BackupManager`] with the given [`Config`]. This will scan the backup
-    /// store folder to collect all metadata.
+    /// Creates a new [`BackupManager`] with the given [`Config`], keeping the objects in the
+    /// store directory. The index of the store is loaded on first use, scanning the backup store
+    /// folder and only reading the metadata of backups the saved index does not know.
     ///
     /// ## Errors
-    /// - `std::io::Error` if there is an error reading the backup store folder or any of the individual backup files
+    /// - Returns an error if the annotations of the store cannot be read
+    /// - Returns an error if the store is encrypted and cannot be unlocked, see
+    ///   [`LocalBackend::for_config`]
     pub fn new(config: Config) -> Result<Self> {
-        let mut this = Self {
+        let backend = LocalBackend::for_config(&config)?;
+        Self::with_backend(config, backend)
+    }
+
+    /// Re-encrypts every blob of the store with `key`, which becomes the key of the store, e.g. a
+    /// [`StoreKey::new`] of a new passphrase. A store that is not encrypted yet is encrypted from
+    /// now on. Returns the number of blobs re-encrypted.
+    ///
+    /// Every blob is re-encrypted into a copy next to it before the new key is saved, and the
+    /// copies only replace the blobs afterwards, so the next [`LocalBackend::for_config`] finishes
+    /// (or undoes) an interrupted rekey. Other processes keep the key they opened the store with,
+    /// a running daemon must be stopped first.
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if a blob cannot be read or written, or the key cannot be saved
+    pub fn rekey(&mut self, key: StoreKey) -> Result<usize> {
+        let (rekeyed, count) = {
+            let _lock = self.lock_store()?;
+            crypto::finish_rekey(&self.backend)?;
+            let blobs = self.backend.list()?;
+            let rekeyed = LocalBackend::clone(&self.backend).with_key(key);
+            for blob in &blobs {
+                let bytes = self.backend.get(blob.id())?;
+                rekeyed.put(&crypto::rekeyed_id(blob.id()), &bytes)?;
+            }
+            if let Some(key) = rekeyed.key() {
+                key.save(&self.config.store_key_path())?;
+            }
+            for blob in &blobs {
+                rekeyed.rename(&crypto::rekeyed_id(blob.id()), blob.id())?;
+            }
+            tracing::info!(blobs = blobs.len(), "re-encrypted the store");
+            (rekeyed, blobs.len())
+        };
+        self.backend = Arc::new(rekeyed);
+        Ok(count)
+    }
+}
+
+impl<B: StorageBackend> BackupManager<B> {
+    /// Creates a new [`BackupManager`] with the given [`Config`], keeping the objects in
+    /// `backend`. The index of the store is loaded on first use, listing the objects of the
+    /// backend and only reading the metadata of backups the saved index does not know. If that
+    /// fails, queries find no backups and the error is returned by the next operation writing to
+    /// the store, which loads the index again.
+    ///
+    /// ## Errors
+    /// - Returns an error if the annotations of the store cannot be read
+    /// - Returns an error if the store does not record its format yet and its objects cannot be
+    ///   listed or any of them read to detect it
+    pub fn with_backend(config: Config, backend: B) -> Result<Self> {
+        let pipeline = BackupPipeline::with_compression(config.compression().clone());
+        let dictionary = load_dictionary(&config, &backend);
+        let this = Self {
+            annotations: RwLock::new(AnnotationIndex::load(config.annotations_path())?),
+            dictionary: RwLock::new(dictionary),
             config,
-            file_info: vec![],
+            backend: Arc::new(backend),
+            vfs: Arc::new(RealFs),
+            file_info: RwLock::new(Vec::new()),
+            index_state: Mutex::new(IndexState::Unloaded),
+            pipeline: Arc::new(pipeline),
+            pending: Mutex::new(BackupQueue::default()),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            lock_generation: Mutex::new(None),
+            evictions: Mutex::new(EvictionReport::default()),
+            hooks: HookDispatcher::default(),
+            shutdown: Shutdown::new(),
+            meta_cache: Mutex::new(LruCache::new(META_CACHE_CAPACITY)),
         };
-        this.collect_backup_info()?;
+        migrate::check_format(&this.config, || {
+            this.collect_backup_info()?;
+            migrate::detect_format(&*this.backend, &this.index())
+        })?;
         Ok(this)
     }
 
-    /// Update the [`Config`] used by the [`BackupManager`]
+    /// Gets the [`StorageBackend`] keeping the objects of the store
+    #[must_use]
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Replaces the [`Vfs`] the backed up files are read from and restored to, the real file
+    /// system by default. Paths are looked up in `vfs` as they are given, e.g. by
+    /// [`BackupManager::backup`] and [`BackupManager::sync`].
+    pub fn set_vfs(&mut self, vfs: impl Vfs + 'static) {
+        self.vfs = Arc::new(vfs);
+    }
+
+    /// Gets the [`Vfs`] the backed up files are read from and restored to
+    #[must_use]
+    pub fn vfs(&self) -> &dyn Vfs {
+        &*self.vfs
+    }
+
+    /// Update the [`Config`] used by the [`BackupManager`]. The [`BackupPipeline`] is kept, use
+    /// [`BackupManager::set_pipeline`] to apply a new [`CompressionConfig`](crate::CompressionConfig).
     pub fn update_config(&mut self, config: Config) {
         self.config = config;
     }
 
-    fn store_path(&self) -> &Path {
-        self.config.store_dir_path()
+    /// Replaces the [`BackupPipeline`] every backed up file is passed through
+    pub fn set_pipeline(&mut self, pipeline: BackupPipeline) {
+        self.pipeline = Arc::new(pipeline);
+    }
+
+    /// Gets the [`BackupPipeline`] every backed up file is passed through
+    #[must_use]
+    pub fn pipeline(&self) -> &BackupPipeline {
+        &self.pipeline
     }
 
-    fn collect_backup_info(&mut self) -> Result {
-        let mut infos = vec![];
+    /// Sets how long operations writing to the store wait for another process to release the
+    /// store lock before failing, [`DEFAULT_LOCK_TIMEOUT`] by default
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+    }
 
-        for entry in std::fs::read_dir(self.store_path())? {
-            let entry = entry?;
-            let backup_path = entry.path();
+    /// Gets how long operations writing to the store wait for the store lock
+    #[must_use]
+    pub fn lock_timeout(&self) -> Duration {
+        self.lock_timeout
+    }
 
-            let (header, meta) = extract_header_and_meta(&backup_path)?;
-            infos.push(BackupInfo {
-                header,
-                meta,
-                backup_path,
-            });
+    /// Sets the [`Shutdown`] handle that is checked before each backup of
+    /// [`BackupManager::backup_all`] starts, once it is requested no new backups are started
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    /// Gets the [`Shutdown`] handle checked by [`BackupManager::backup_all`]
+    #[must_use]
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Backs up the file at `path` into the store through the [`BackupPipeline`], using the next
+    /// version for that file. If the backup would grow the store past [`Config::max_store_bytes`]
+    /// the oldest versions are evicted before it is written, see
+    /// [`BackupManager::take_evictions`].
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the file cannot be read, compressed, or written to the store
+    /// - Returns an error if the file is a socket, named pipe or device, it is never opened
+    /// - Returns an error if a stage of the pipeline rejects the file
+    pub fn backup(&self, path: impl AsRef<Path>) -> Result<FileMeta> {
+        self.backup_with_progress(path, &mut ())
+    }
+
+    /// Same as [`BackupManager::backup`], announcing each stage of the [`BackupPipeline`] to
+    /// `progress` and reporting the bytes processed within it, e.g. to show throughput and an
+    /// ETA while a single large file is backed up.
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the file cannot be read, compressed, or written to the store
+    /// - Returns an error if a stage of the pipeline rejects the file
+    pub fn backup_with_progress(
+        &self,
+        path: impl AsRef<Path>,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<FileMeta> {
+        let path = path.as_ref();
+        let lock = self.lock_store()?;
+        let version = self.next_version(path);
+        self.evict_for_backup(&mut self.index_mut(), self.incoming_bytes([path]));
+        let dictionary = self.dictionary();
+        let info = self.pipeline.run_with_progress(
+            &self.dyn_backend(),
+            self.store_path(),
+            path,
+            version,
+            Source::new(&*self.vfs, self.config.symlinks()).with_dictionary(dictionary.as_ref()),
+            progress,
+        )?;
+        let meta = info.meta.clone();
+        let mut file_info = self.index_mut();
+        file_info.push(info);
+        self.save_index(&file_info);
+        self.evict_for_backup(&mut file_info, 0);
+        // hooks may take a while, and may well run the cli against this store
+        drop(file_info);
+        drop(lock);
+        self.fire_backup_created(&meta);
+        Ok(meta)
+    }
+
+    /// Backs up all of the given files into the store in parallel, using a pool of
+    /// [`Config::backup_threads`] threads. A failure for one file does not affect the others.
+    /// The store lock is held for the whole batch, if it cannot be acquired (or the threads cannot
+    /// be spawned) every file fails.
+    ///
+    /// Once the [`Shutdown`] of the manager is requested the backups that are already running are
+    /// finished, but no new ones are started. The paths that were not backed up are queued again
+    /// (see [`BackupManager::queue_backup`]) instead of being reported.
+    ///
+    /// Returns the result for each path that was backed up, in the same order as `paths`.
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while holding the queue
+    pub fn backup_all<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+    ) -> Vec<(PathBuf, Result<FileMeta>)> {
+        let paths = paths
+            .into_iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect::<Vec<_>>();
+        let (lock, pool) = match self
+            .lock_store()
+            .and_then(|lock| Ok((lock, self.backup_pool()?)))
+        {
+            Ok(started) => started,
+            Err(e) => {
+                return paths
+                    .into_iter()
+                    .map(|path| (path, Err(e.to_string().into())))
+                    .collect()
+            }
+        };
+
+        // versions are assigned up front so duplicate paths never collide in the store
+        let mut next_versions: Vec<(PathBuf, FileVersion)> = Vec::new();
+        let mut jobs = Vec::new();
+        for path in paths {
+            let version =
+                if let Some((_, version)) = next_versions.iter_mut().find(|(p, _)| *p == path) {
+                    version.increment();
+                    *version
+                } else {
+                    let version = self.next_version(&path);
+                    next_versions.push((path.clone(), version));
+                    version
+                };
+            jobs.push((path, version));
         }
+        let incoming = self.incoming_bytes(jobs.iter().map(|(path, _)| path.as_path()));
+        self.evict_for_backup(&mut self.index_mut(), incoming);
 
-        self.file_info = infos;
-        Ok(())
+        let backend = self.dyn_backend();
+        let store = self.store_path().to_path_buf();
+        let vfs = Arc::clone(&self.vfs);
+        let pipeline = Arc::clone(&self.pipeline);
+        let symlinks = self.config.symlinks();
+        let dictionary = self.dictionary();
+        let shutdown = self.shutdown.clone();
+        let results = pool.map(jobs, move |(path, version)| {
+            if shutdown.is_requested() {
+                return (path, None);
+            }
+            let source = Source::new(&*vfs, symlinks).with_dictionary(dictionary.as_ref());
+            let result = pipeline.run(&backend, &store, &path, version, source);
+            (path, Some(result))
+        });
+
+        let mut file_info = self.index_mut();
+        let mut deferred = Vec::new();
+        let results: Vec<(PathBuf, Result<FileMeta>)> = results
+            .into_iter()
+            .filter_map(|(path, result)| {
+                let Some(result) = result else {
+                    deferred.push(path);
+                    return None;
+                };
+                let result = result.map(|info| {
+                    let meta = info.meta.clone();
+                    file_info.push(info);
+                    meta
+                });
+                Some((path, result))
+            })
+            .collect();
+        if !deferred.is_empty() {
+            tracing::info!(count = deferred.len(), "shutting down, deferred backups");
+            for path in deferred {
+                self.queue_backup(path);
+            }
+        }
+        self.save_index(&file_info);
+        self.evict_for_backup(&mut file_info, 0);
+        drop(file_info);
+        drop(lock);
+        for (_, result) in &results {
+            if let Ok(meta) = result {
+                self.fire_backup_created(meta);
+            }
+        }
+        results
     }
-}
 
-/// Given a path (to a **backup** file), extract only the [`FileHeader`] and the [`FileMeta`] without
-/// reading the actual file bytes.
-///
-/// ## Errors
-/// - Returns an IO error if the backup file cannot be opened, or the buffered reader fails to read
-/// the specified number of bytes.
-/// - Returns a Serde error if `rmp_serde` fails to deserialize the [`FileMeta`]
-pub fn extract_header_and_meta(backup_path: impl AsRef<Path>) -> Result<(FileHeader, FileMeta)> {
-    let mut reader = BufReader::new(read_only().open(&backup_path)?);
-    let mut header_buf = vec![0; std::mem::size_of::<FileHeader>()];
-    reader.read_exact(&mut header_buf)?;
-    let header = FileHeader::try_from_bytes_exact(&header_buf)?;
-
-    let mut meta_buf = vec![0; header.meta_size];
-    reader.read_exact(&mut meta_buf)?;
-    let meta: FileMeta = rmp_serde::from_slice(&meta_buf)?;
-    Ok((header, meta))
-}
+    /// Opens a snapshot, a group of files that are backed up together and can be restored
+    /// together with [`BackupManager::restore_snapshot`]. Files are added to the returned builder
+    /// and backed up when it is committed, either all of them or none.
+    #[must_use]
+    pub fn open_snapshot(&self) -> SnapshotBuilder<'_, B> {
+        SnapshotBuilder::new(self)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Backs up `paths` as a single snapshot, see [`BackupManager::open_snapshot`]
+    ///
+    /// ## Errors
+    /// - Returns an error if `paths` is empty
+    /// - Returns an error if any of the files cannot be backed up, in which case none of them is
+    /// - Returns an error if the store lock cannot be acquired or the snapshot cannot be recorded
+    pub fn snapshot<P: AsRef<Path>>(&self, paths: impl IntoIterator<Item = P>) -> Result<Snapshot> {
+        let mut snapshot = self.open_snapshot();
+        for path in paths {
+            snapshot.add(path);
+        }
+        snapshot.commit()
+    }
 
-    fn create_temp_file() -> std::fs::File {
-        tempfile::tempfile().expect("failed to create temp file")
+    /// Backs up `paths` on the backup threads and records them as a snapshot. The objects are
+    /// written first and only added to the index once every one of them was, so a failure deletes
+    /// them again without the index or the snapshots ever seeing them.
+    pub(crate) fn commit_snapshot(&self, paths: &[PathBuf]) -> Result<Snapshot> {
+        if paths.is_empty() {
+            return Err("a snapshot needs at least one file".into());
+        }
+        let _lock = self.lock_store()?;
+        let mut snapshots = SnapshotIndex::load(self.config.snapshots_path())?;
+
+        let jobs = paths
+            .iter()
+            .map(|path| (path.clone(), self.next_version(path)))
+            .collect::<Vec<_>>();
+        self.evict_for_backup(
+            &mut self.index_mut(),
+            self.incoming_bytes(paths.iter().map(PathBuf::as_path)),
+        );
+        let backend = self.dyn_backend();
+        let store = self.store_path().to_path_buf();
+        let vfs = Arc::clone(&self.vfs);
+        let pipeline = Arc::clone(&self.pipeline);
+        let symlinks = self.config.symlinks();
+        let dictionary = self.dictionary();
+        let pool = self.backup_pool()?;
+        let results = pool.map(jobs, move |(path, version)| -> Result<BackupInfo> {
+            pipeline
+                .run(
+                    &backend,
+                    &store,
+                    &path,
+                    version,
+                    Source::new(&*vfs, symlinks).with_dictionary(dictionary.as_ref()),
+                )
+                .map_err(|e| format!("unable to back up '{}' - {e}", path.display()).into())
+        });
+
+        let mut infos = Vec::with_capacity(results.len());
+        let mut failure = None;
+        for result in results {
+            match result {
+                Ok(info) => infos.push(info),
+                Err(e) => {
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        let snapshot = Snapshot::new(infos.iter().map(|info| (&info.meta).into()).collect());
+        if failure.is_none() {
+            snapshots.push(snapshot.clone());
+            failure = snapshots.save().err();
+        }
+        if let Some(e) = failure {
+            for info in &infos {
+                let deleted = info.object_id().and_then(|id| self.backend.delete(id));
+                if let Err(e) = deleted {
+                    tracing::warn!(object = %info.backup_path.display(), error = %e, "unable to delete backup of failed snapshot");
+                }
+            }
+            let contents = infos.iter().filter_map(|info| info.meta.content());
+            if let Err(e) = content::remove_unreferenced(&*self.backend, &self.index(), contents) {
+                tracing::warn!(error = %e, "unable to delete content of failed snapshot");
+            }
+            return Err(e);
+        }
+        tracing::info!(snapshot = %snapshot.id(), files = infos.len(), "took snapshot");
+
+        let mut file_info = self.index_mut();
+        file_info.extend(infos);
+        self.save_index(&file_info);
+        self.evict_for_backup(&mut file_info, 0);
+        Ok(snapshot)
     }
 
-    fn create_named_temp_file() -> tempfile::NamedTempFile {
-        tempfile::NamedTempFile::new().expect("failed to create named temp file")
+    /// Gets every snapshot of the store, oldest first. A snapshot does not keep its backups from
+    /// being evicted or pruned, so some of them may be gone.
+    ///
+    /// ## Errors
+    /// - Returns an error if the list of snapshots cannot be read
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>> {
+        Ok(SnapshotIndex::load(self.config.snapshots_path())?
+            .all()
+            .to_vec())
+    }
+
+    /// Gets the snapshot with the given `id`, if it is in the store
+    ///
+    /// ## Errors
+    /// - Returns an error if the list of snapshots cannot be read
+    pub fn snapshot_by_id(&self, id: SnapshotId) -> Result<Option<Snapshot>> {
+        Ok(SnapshotIndex::load(self.config.snapshots_path())?
+            .get(id)
+            .cloned())
+    }
+
+    /// Restores every backup of the snapshot with the given `id` to the path it was taken from,
+    /// see [`BackupManager::restore_by_id`]. A failure for one file does not affect the others.
+    ///
+    /// Returns the result for each file, in the order they were added to the snapshot.
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no snapshot with that id in the store
+    pub fn restore_snapshot(
+        &self,
+        id: SnapshotId,
+        options: &RestoreOptions,
+    ) -> Result<Vec<(PathBuf, Result<RestoredFile>)>> {
+        let snapshot = self
+            .snapshot_by_id(id)?
+            .ok_or_else(|| format!("no snapshot with id {id}"))?;
+        Ok(snapshot
+            .members()
+            .iter()
+            .map(|member| {
+                let result = self.restore_by_id(member.id(), options);
+                (member.path().to_path_buf(), result)
+            })
+            .collect())
+    }
+
+    /// Queues a backup of `path` with the normal priority to be performed by the next call to
+    /// [`BackupManager::run_pending`]. Queueing a path that is already pending has no effect.
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while holding the queue
+    pub fn queue_backup(&self, path: impl Into<PathBuf>) {
+        self.queue(QueuedBackup::new(path));
+    }
+
+    /// Queues `job` to be performed by the next call to [`BackupManager::run_pending`], which
+    /// backs up the queued files by priority, then the smaller ones first, with the groups of
+    /// files taking turns. Queueing a path that is already pending replaces the queued backup,
+    /// which keeps the higher of both priorities.
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while holding the queue
+    pub fn queue(&self, job: QueuedBackup) {
+        // a file that cannot be read fails its backup quickly
+        let size = self
+            .vfs
+            .symlink_metadata(job.path())
+            .map_or(0, |meta| meta.size());
+        self.pending
+            .lock()
+            .expect("backup queue poisoned")
+            .push(job, size);
+    }
+
+    /// Gets the paths that are queued for backup, in the order they are backed up
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while holding the queue
+    #[must_use]
+    pub fn pending(&self) -> Vec<PathBuf> {
+        self.pending.lock().expect("backup queue poisoned").paths()
+    }
+
+    /// Gets the current [`Schedule`], which decides whether queued backups may run
+    ///
+    /// ## Errors
+    /// - Returns an error if the pause file cannot be read
+    pub fn schedule(&self) -> Result<Schedule> {
+        Schedule::load(&self.config)
+    }
+
+    /// Backs up every queued path in the order of the queue (see [`BackupManager::queue`] and
+    /// [`BackupManager::backup_all`]) unless the [`Schedule`] is currently quiet, in which case
+    /// the queue is left untouched and nothing is returned. The queue is worked off in chunks of
+    /// [`BackupManager::run_next_pending`], so files queued meanwhile by other threads take their
+    /// place in the order.
+    ///
+    /// ## Errors
+    /// - Returns an error if the [`Schedule`] cannot be loaded
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while holding the queue
+    pub fn run_pending(&self) -> Result<Vec<(PathBuf, Result<FileMeta>)>> {
+        let mut results = Vec::new();
+        while !self.shutdown.is_requested() {
+            let chunk = self.run_next_pending()?;
+            if chunk.is_empty() {
+                break;
+            }
+            results.extend(chunk);
+        }
+        Ok(results)
+    }
+
+    /// Backs up the next queued paths, as many as there are backup threads, unless the
+    /// [`Schedule`] is currently quiet. The store is only locked for this chunk, so backups
+    /// queued before the next one are ordered among the remaining ones, and a small file of a
+    /// higher priority does not wait for the whole queue.
+    ///
+    /// ## Errors
+    /// - Returns an error if the [`Schedule`] cannot be loaded
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while holding the queue
+    pub fn run_next_pending(&self) -> Result<Vec<(PathBuf, Result<FileMeta>)>> {
+        let chunk = {
+            let mut pending = self.pending.lock().expect("backup queue poisoned");
+            if pending.is_empty() || self.schedule()?.is_quiet(Timestamp::now()) {
+                return Ok(vec![]);
+            }
+            pending.pop(self.config.backup_threads().max(1))
+        };
+        Ok(self.backup_all(&chunk))
+    }
+
+    /// Checks whether `path` has changed since its latest backup, based on its size and
+    /// modification time. Paths that have never been backed up, or whose deletion was recorded
+    /// since, always need a backup.
+    ///
+    /// ## Errors
+    /// - Returns an error if the metadata of `path` cannot be read
+    pub fn needs_backup(&self, path: impl AsRef<Path>) -> Result<bool> {
+        let path = path.as_ref();
+        let Some(backed_up) = self
+            .index()
+            .iter()
+            .filter(|info| info.meta.path() == path)
+            .max_by_key(|info| *info.meta.version())
+            .map(|info| info.meta.clone())
+        else {
+            return Ok(true);
+        };
+        if backed_up.is_deleted() {
+            return Ok(true);
+        }
+        let backed_up = backed_up.fs_meta();
+        let current = self.current_fs_meta(path)?;
+        Ok(current.size() != backed_up.size() || current.modified() != backed_up.modified())
+    }
+
+    /// Compares `paths` with their latest backups and backs up the files that changed since, or
+    /// have never been backed up, e.g. to catch up with changes made while no watcher was
+    /// running. Directories stand for the files directly inside them. See [`SyncMode`] for how
+    /// files are compared, paths that do not exist are skipped. Sockets, named pipes and devices
+    /// are skipped without being opened, they are listed in [`SyncReport::skipped`].
+    pub fn sync<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        mode: SyncMode,
+    ) -> SyncReport {
+        let mut report = SyncReport::default();
+        let paths = paths.into_iter().map(|path| path.as_ref().to_path_buf());
+        let mut changed = Vec::new();
+        for path in sync::expand(&*self.vfs, paths, &mut report) {
+            let latest = self
+                .index()
+                .iter()
+                .filter(|info| info.meta.path() == &path)
+                .max_by_key(|info| *info.meta.version())
+                .cloned();
+            let result = self.current_fs_meta(&path).and_then(|current| {
+                sync::has_changed(
+                    &*self.backend,
+                    &*self.vfs,
+                    &path,
+                    &current,
+                    latest.as_ref(),
+                    mode,
+                )
+            });
+            match result {
+                Ok(true) => changed.push(path),
+                Ok(false) => report.push_unchanged(path),
+                Err(e) => report.push_result(path, Err(e)),
+            }
+        }
+
+        tracing::debug!(changed = changed.len(), "compared files with their backups");
+        if !changed.is_empty() {
+            for (path, result) in self.backup_all(&changed) {
+                report.push_result(path, result);
+            }
+        }
+        report
+    }
+
+    /// Brings the filesystem metadata of the latest backup of `path` up to date after only its
+    /// attributes changed, e.g. its permissions, so a restore reapplies them. The object is
+    /// rewritten with the same contents and version and a bumped
+    /// [metadata revision](FileMeta::meta_revision). Files whose size or modification time
+    /// differ from the latest backup are not touched, they need a new backup instead.
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the metadata of `path` cannot be read
+    /// - Returns an error if the latest backup cannot be read or rewritten
+    pub fn update_metadata(&self, path: impl AsRef<Path>) -> Result<MetadataUpdate> {
+        let path = path.as_ref();
+        let _lock = self.lock_store()?;
+        let current = self.current_fs_meta(path)?;
+        let mut file_info = self.index_mut();
+        let Some(latest) = file_info
+            .iter_mut()
+            .filter(|info| info.meta.path() == path)
+            .max_by_key(|info| *info.meta.version())
+            .filter(|info| !info.meta.is_deleted())
+        else {
+            return Ok(MetadataUpdate::NeedsBackup);
+        };
+        let backed_up = latest.meta.fs_meta();
+        if current.size() != backed_up.size() || current.modified() != backed_up.modified() {
+            return Ok(MetadataUpdate::NeedsBackup);
+        }
+        // without the feature no attributes are captured, that is not a change
+        let same_xattrs = !cfg!(feature = "xattr") || current.xattrs() == backed_up.xattrs();
+        if current.permissions() == backed_up.permissions() && same_xattrs {
+            return Ok(MetadataUpdate::Unchanged);
+        }
+
+        let id = latest.object_id()?.to_string();
+        let (_, mut meta, bytes) = storage_format::decode(&self.backend.get(&id)?)
+            .map_err(|e| e.with_path(&latest.backup_path))?;
+        meta.revise_fs_meta(current);
+        let header = FileHeader::new(storage_format::encode_meta(&meta)?.len(), bytes.len());
+        let object = storage_format::encode(&header, &meta, &bytes)?;
+        *latest = clone::write_verified(&object, &*self.backend, &id, self.store_path())?;
+        self.meta_cache().insert(
+            (latest.backup_path.clone(), latest.backup_size),
+            (latest.header, latest.meta.clone()),
+        );
+        tracing::debug!(
+            path = %path.display(),
+            version = %meta.version(),
+            revision = meta.meta_revision(),
+            "updated the metadata of the latest backup"
+        );
+        self.save_index(&file_info);
+        Ok(MetadataUpdate::Updated(Box::new(meta)))
+    }
+
+    /// Records that the file at `path` was deleted, as a new version without contents (see
+    /// [`FileMeta::is_deleted`]) created now. The deletion shows up in [`BackupManager::history`],
+    /// and [`BackupManager::restore`] without a version brings back the version before it.
+    ///
+    /// Returns the record of the deletion, or `None` if the file was never backed up, its
+    /// deletion is already recorded, or it exists (again).
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the record cannot be written to the store
+    pub fn record_deletion(&self, path: impl AsRef<Path>) -> Result<Option<FileMeta>> {
+        let path = path.as_ref();
+        if self.vfs.symlink_metadata(path).is_ok() {
+            return Ok(None);
+        }
+        let _lock = self.lock_store()?;
+        let mut file_info = self.index_mut();
+        let Some(latest) = file_info
+            .iter()
+            .filter(|info| info.meta.path() == path)
+            .max_by_key(|info| *info.meta.version())
+        else {
+            return Ok(None);
+        };
+        if latest.meta.is_deleted() {
+            return Ok(None);
+        }
+
+        let version = *latest.meta.version() + 1u32;
+        let kind = latest.meta.fs_meta().file_type();
+        let mut meta = FileMeta::new(
+            version,
+            Timestamp::now(),
+            path.to_path_buf(),
+            FsMetadata::new(None, None, None, 0, kind),
+        )
+        .with_id(UniqueId::new())
+        .with_compression(Compression::Store);
+        meta.mark_deleted();
+        let header = FileHeader::new(storage_format::encode_meta(&meta)?.len(), 0);
+        let object = storage_format::encode(&header, &meta, &[])?;
+        let id = storage_format::object_name(path, version);
+        let info = clone::write_verified(&object, &*self.backend, &id, self.store_path())?;
+        tracing::debug!(path = %path.display(), %version, "recorded a deletion");
+        file_info.push(info);
+        self.save_index(&file_info);
+        Ok(Some(meta))
+    }
+
+    /// Gets the path of every file with a backup in the store, sorted
+    #[must_use]
+    pub fn files(&self) -> Vec<PathBuf> {
+        let files = self
+            .index()
+            .iter()
+            .map(|info| info.meta.path().clone())
+            .collect::<BTreeSet<_>>();
+        files.into_iter().collect()
+    }
+
+    /// Reads the backed up contents of `version` of the file at `path`, without restoring it to
+    /// disk. A version recording a deletion has no contents.
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no such backup in the store
+    /// - Returns an error if the backup cannot be read
+    pub fn read_version(&self, path: impl AsRef<Path>, version: FileVersion) -> Result<Vec<u8>> {
+        let info = Self::find(&self.index(), path.as_ref(), version)?.clone();
+        diff::contents(&*self.backend, &info)
+    }
+
+    /// Reads the first `max_bytes` of the backed up contents of `version` of the file at `path`,
+    /// e.g. to preview it. Only that much of the backup is decompressed, however large the file
+    /// is, see [`FsMetadata::size`](crate::FsMetadata::size) for its full size.
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no such backup in the store
+    /// - Returns an error if the backup cannot be read
+    pub fn read_version_head(
+        &self,
+        path: impl AsRef<Path>,
+        version: FileVersion,
+        max_bytes: usize,
+    ) -> Result<Vec<u8>> {
+        let info = Self::find(&self.index(), path.as_ref(), version)?.clone();
+        content::read_head(&*self.backend, &info, max_bytes)
+    }
+
+    /// Gets the metadata of every version of the file at `path` in the store, oldest first.
+    /// Versions recording a deletion of the file are included, see [`FileMeta::is_deleted`].
+    #[must_use]
+    pub fn history(&self, path: impl AsRef<Path>) -> Vec<FileMeta> {
+        let path = path.as_ref();
+        let mut history = self
+            .index()
+            .iter()
+            .filter(|info| info.meta.path() == path)
+            .map(|info| info.meta.clone())
+            .collect::<Vec<_>>();
+        history.sort_by_key(|meta| *meta.version());
+        history
+    }
+
+    /// Upgrades every object in the store to the current format, see [`MIGRATIONS`]. The original
+    /// of each object is kept until every object is migrated, and an interrupted migration resumes
+    /// with the objects it did not migrate yet, or is undone with
+    /// [`BackupManager::rollback_migration`]. With [`DryRun::On`] nothing is written, the report
+    /// tells which migrations would be applied to how many objects.
+    ///
+    /// [`MIGRATIONS`]: crate::MIGRATIONS
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the format of the store cannot be read or written
+    /// - Returns an error if an object cannot be read, upgraded or rewritten, the objects migrated
+    ///   so far stay migrated
+    pub fn migrate(&self, dry_run: DryRun) -> Result<MigrationReport> {
+        let _lock = self.lock_store()?;
+        let from = migrate::store_format(&self.config)?;
+        let mut report = MigrationReport::new(from, dry_run);
+        if report.is_up_to_date() {
+            return Ok(report);
+        }
+
+        let mut journal = Journal::open(&self.config.store_migration_path())?;
+        let mut file_info = self.index_mut();
+        let mut result = Ok(());
+        for info in file_info.iter_mut() {
+            let id = info.object_id()?.to_string();
+            if journal.contains(&id) {
+                report.record_resumed();
+                continue;
+            }
+            if !dry_run.is_on() {
+                result = self.migrate_object(info, &id, from, &mut journal);
+                if result.is_err() {
+                    break;
+                }
+            }
+            report.record_migrated();
+        }
+        if dry_run.is_on() {
+            return Ok(report);
+        }
+        self.save_index(&file_info);
+        result?;
+
+        migrate::set_store_format(&self.config, report.to())?;
+        journal.finish()?;
+        tracing::info!(
+            from = report.from(),
+            to = report.to(),
+            objects = report.migrated(),
+            "migrated the store"
+        );
+        Ok(report)
+    }
+
+    /// Undoes a migration that was interrupted or failed, see [`BackupManager::migrate`]: every
+    /// object it may have rewritten is restored from the original it kept, and the store stays
+    /// in its previous format. Returns the number of objects restored.
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no unfinished migration
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if an original cannot be read or restored, the migration can be rolled
+    ///   back again afterwards
+    pub fn rollback_migration(&self) -> Result<usize> {
+        let _lock = self.lock_store()?;
+        let dir = self.config.store_migration_path();
+        if !dir.exists() {
+            return Err("there is no unfinished migration to roll back".into());
+        }
+        let journal = Journal::open(&dir)?;
+        let originals = journal.originals()?;
+        for (id, path) in &originals {
+            let original = std::fs::read(path)?;
+            self.backend.put(id, &original)?;
+        }
+        // a restored object may be as large as the migrated one was
+        let restored = originals
+            .iter()
+            .map(|(id, _)| self.store_path().join(id))
+            .collect::<HashSet<_>>();
+        self.index_mut()
+            .retain(|info| !restored.contains(&info.backup_path));
+        self.meta_cache().clear();
+        self.collect_backup_info()?;
+        self.save_index(&self.index());
+        journal.finish()?;
+        tracing::info!(objects = originals.len(), "rolled back the migration");
+        Ok(originals.len())
+    }
+
+    /// Rewrites the object `id` backing `info` in the current format, keeping its original in the
+    /// `journal` until the migration is finished
+    fn migrate_object(
+        &self,
+        info: &mut BackupInfo,
+        id: &str,
+        from: u32,
+        journal: &mut Journal,
+    ) -> Result {
+        let original = journal.keep_original(id, self.backend.get(id)?)?;
+        let object =
+            migrate::upgrade(&original, from).map_err(|e| e.with_path(&info.backup_path))?;
+        *info = clone::write_verified(&object, &*self.backend, id, self.store_path())?;
+        self.meta_cache().insert(
+            (info.backup_path.clone(), info.backup_size),
+            (info.header, info.meta.clone()),
+        );
+        journal.record(id)
+    }
+
+    /// Gets the metadata of `path` the way it is backed up under the configured [`SymlinkPolicy`]
+    fn current_fs_meta(&self, path: &Path) -> Result<FsMetadata> {
+        match self.config.symlinks() {
+            SymlinkPolicy::Preserve => self.vfs.symlink_metadata(path),
+            SymlinkPolicy::Follow => self.vfs.metadata(path),
+        }
+    }
+
+    /// Gets the version the next backup of `path` should use
+    fn next_version(&self, path: &Path) -> FileVersion {
+        self.index()
+            .iter()
+            .filter(|info| info.meta.path() == path)
+            .map(|info| *info.meta.version())
+            .max()
+            .map_or_else(FileVersion::new, |version| version + 1u32)
+    }
+
+    fn store_path(&self) -> &Path {
+        self.config.store_dir_path()
+    }
+
+    /// Spawns the named pool of [`Config::backup_threads`] threads a batch of files is backed up on
+    fn backup_pool(&self) -> Result<ThreadPool> {
+        let threads = self.config.backup_threads();
+        Ok(ThreadPoolBuilder::new()
+            .with_size(threads)
+            .with_name(BACKUP_THREAD_NAME)
+            .with_queue_bound(threads.saturating_mul(BACKUP_QUEUE_PER_THREAD))
+            .build()?)
+    }
+
+    /// Gets the backend for the [`BackupPipeline`], which may run on other threads
+    fn dyn_backend(&self) -> Arc<dyn StorageBackend> {
+        Arc::clone(&self.backend) as Arc<dyn StorageBackend>
+    }
+
+    // the index is always locked before the annotations, so the two never deadlock
+    fn index(&self) -> RwLockReadGuard<'_, Vec<BackupInfo>> {
+        self.load_index();
+        self.file_info.read().expect("backup index poisoned")
+    }
+
+    fn index_mut(&self) -> RwLockWriteGuard<'_, Vec<BackupInfo>> {
+        self.load_index();
+        self.file_info.write().expect("backup index poisoned")
+    }
+
+    /// Same as [`BackupManager::index_mut`], without loading the index first
+    fn unloaded_index_mut(&self) -> RwLockWriteGuard<'_, Vec<BackupInfo>> {
+        self.file_info.write().expect("backup index poisoned")
+    }
+
+    fn index_state(&self) -> MutexGuard<'_, IndexState> {
+        self.index_state.lock().expect("index state poisoned")
+    }
+
+    /// Loads the index of the store if this is its first use, an error is only logged
+    fn load_index(&self) {
+        if *self.index_state() != IndexState::Unloaded {
+            return;
+        }
+        if let Err(e) = self.collect_backup_info() {
+            tracing::warn!(error = %e, "unable to load the store index");
+        }
+    }
+
+    fn annotation_index(&self) -> RwLockReadGuard<'_, AnnotationIndex> {
+        self.annotations.read().expect("annotation index poisoned")
+    }
+
+    fn meta_cache(&self) -> MutexGuard<'_, LruCache<(PathBuf, u64), (FileHeader, FileMeta)>> {
+        self.meta_cache.lock().expect("metadata cache poisoned")
+    }
+
+    fn annotation_index_mut(&self) -> RwLockWriteGuard<'_, AnnotationIndex> {
+        self.annotations.write().expect("annotation index poisoned")
+    }
+
+    /// Acquires the store lock and brings the index up to date with changes other processes made
+    /// to the store before it. Nothing is read if the lock was last released by this manager,
+    /// since no one else can have written to the store in between.
+    fn lock_store(&self) -> Result<StoreLock> {
+        let lock = StoreLock::acquire(&self.config.store_lock_path(), self.lock_timeout)?;
+        let mut generation = self.lock_generation.lock().expect("mutex poisoned");
+        let unchanged = *generation == Some(lock.generation());
+        // releasing the lock moves the store to the next generation
+        *generation = Some(lock.generation().wrapping_add(1));
+        if unchanged {
+            return Ok(lock);
+        }
+        drop(generation);
+        if self.collect_backup_info()? {
+            self.save_index(&self.index());
+        }
+        *self.annotation_index_mut() = AnnotationIndex::load(self.config.annotations_path())?;
+        *self.dictionary.write().expect("dictionary poisoned") =
+            load_dictionary(&self.config, &*self.backend);
+        Ok(lock)
+    }
+
+    /// Writes the index of the store, which must only be done while holding the store lock. The
+    /// index is only a cache, so failing to write it is not an error.
+    fn save_index(&self, file_info: &[BackupInfo]) {
+        if let Err(e) = index::save(&self.config.store_index_path(), file_info) {
+            tracing::warn!(error = %e, "unable to write store index");
+        }
+    }
+
+    /// Writes the index of the store to disk, e.g. before the process exits. The index is written
+    /// after every change as well, but unlike there a failure to write it is reported.
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the index cannot be written
+    pub fn flush(&self) -> Result {
+        let _lock = StoreLock::acquire(&self.config.store_lock_path(), self.lock_timeout)?;
+        self.collect_backup_info()?;
+        index::save(&self.config.store_index_path(), &self.index())
+    }
+
+    /// Rebuilds the index of the store by reading the metadata of every backup in the store, e.g.
+    /// if the index is suspected to be out of date. Cached metadata is discarded as well. The
+    /// current index is only replaced once the new one is complete. Returns the number of backups
+    /// in the store.
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the store or any of the backups cannot be read
+    /// - Returns an error if the index cannot be written
+    pub fn rebuild_index(&self) -> Result<usize> {
+        let _lock = StoreLock::acquire(&self.config.store_lock_path(), self.lock_timeout)?;
+        self.meta_cache().clear();
+        let mut file_info = self.unloaded_index_mut();
+        let mut rebuilt = Vec::new();
+        self.refresh_index(&mut rebuilt)?;
+        *file_info = rebuilt;
+        *self.index_state() = IndexState::Loaded;
+        index::save(&self.config.store_index_path(), &file_info)?;
+        Ok(file_info.len())
+    }
+
+    /// Compares the index saved in the store with the objects actually in it, e.g. to tell
+    /// whether it is out of date. Neither is changed. Returns the number of objects the saved
+    /// index misses or has a different size for, plus the number of its entries for objects that
+    /// are gone. Without a saved index every object counts.
+    ///
+    /// ## Errors
+    /// - Returns an error if the saved index cannot be read
+    /// - Returns an error if the objects cannot be listed or any of the unknown ones read
+    pub fn index_drift(&self) -> Result<usize> {
+        self.collect_backup_info()?;
+        let path = self.config.store_index_path();
+        let saved = if path.exists() {
+            index::load(&path, self.store_path())?
+        } else {
+            Vec::new()
+        };
+        let sizes = |index: &[BackupInfo]| {
+            index
+                .iter()
+                .map(|info| (info.backup_path.clone(), info.backup_size))
+                .collect::<HashMap<_, _>>()
+        };
+        let saved = sizes(&saved);
+        let current = sizes(&self.index());
+        // an object of another size is counted once, not as missing and unknown
+        let changed = current
+            .iter()
+            .filter(|&(path, size)| saved.get(path) != Some(size))
+            .count();
+        let gone = saved
+            .keys()
+            .filter(|path| !current.contains_key(*path))
+            .count();
+        Ok(changed + gone)
+    }
+
+    /// Reads the header and metadata of the object recorded at `backup_path`, which is `size`
+    /// bytes large, only reading the start and, if the object must have a checksum
+    /// (`checksummed`), the end of it. Objects are only rewritten by
+    /// [`BackupManager::update_metadata`], which updates the cache itself, so the result is cached
+    /// until the size changes.
+    fn read_header_and_meta(
+        &self,
+        backup_path: &Path,
+        size: u64,
+        checksummed: bool,
+    ) -> Result<(FileHeader, FileMeta)> {
+        self.meta_cache()
+            .get_or_try_insert_with((backup_path.to_path_buf(), size), || {
+                let id = object_id(backup_path)?;
+                if checksummed
+                    && !storage_format::has_checksum(&self.backend.tail(id, CHECKSUM_SIZE)?)
+                {
+                    return Err(format!(
+                        "'{}' has no checksum, although every object of the store should",
+                        backup_path.display()
+                    )
+                    .into());
+                }
+                storage_format::peek_header_and_meta(self.backend.open(id)?)
+                    .map_err(|e| e.with_path(backup_path))
+            })
+            .cloned()
+    }
+
+    /// Brings the index in line with the objects in the store, only reading the metadata of
+    /// objects it does not know yet or whose size changed. The first time, the index saved in the
+    /// store is loaded first. Returns whether the index changed.
+    fn collect_backup_info(&self) -> Result<bool> {
+        let mut file_info = self.unloaded_index_mut();
+        let mut state = self.index_state();
+        if *state == IndexState::Loaded {
+            return self.refresh_index(&mut file_info);
+        }
+        let mut saved = index::load(&self.config.store_index_path(), self.store_path())
+            .unwrap_or_else(|e| {
+                tracing::warn!(error = %e, "ignoring unreadable store index");
+                vec![]
+            });
+        match self.refresh_index(&mut saved) {
+            Ok(changed) => {
+                *file_info = saved;
+                *state = IndexState::Loaded;
+                Ok(changed)
+            }
+            Err(e) => {
+                *state = IndexState::Failed;
+                Err(e)
+            }
+        }
+    }
+
+    /// Brings `file_info` in line with the objects in the store, see
+    /// [`BackupManager::collect_backup_info`]. The metadata of every unknown object is read
+    /// before `file_info` is changed, so it is left as it was if any of it cannot be read.
+    fn refresh_index(&self, file_info: &mut Vec<BackupInfo>) -> Result<bool> {
+        let objects = self
+            .backend
+            .list()?
+            .into_iter()
+            .filter(|blob| !content::is_content(blob.id()) && !content::is_dictionary(blob.id()))
+            .map(|blob| (self.store_path().join(blob.id()), blob.size()));
+        let mut objects = objects.collect::<HashMap<_, _>>();
+
+        let kept = file_info
+            .iter()
+            .map(|info| match objects.get(&info.backup_path) {
+                Some(size) if *size == info.backup_size => {
+                    objects.remove(&info.backup_path).is_some()
+                }
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        // every object of a store in a format with checksums was written or migrated with one
+        let checksummed =
+            !objects.is_empty() && migrate::store_format(&self.config)? >= CHECKSUM_FORMAT_VERSION;
+        let found = objects
+            .into_iter()
+            .map(|(backup_path, backup_size)| {
+                let (header, meta) =
+                    self.read_header_and_meta(&backup_path, backup_size, checksummed)?;
+                tracing::trace!(
+                    path = %meta.path().display(),
+                    version = %meta.version(),
+                    "found backup"
+                );
+                Ok(BackupInfo {
+                    header,
+                    meta,
+                    backup_path,
+                    backup_size,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let known = file_info.len();
+        let mut kept = kept.into_iter();
+        file_info.retain(|_| kept.next().unwrap_or(false));
+        let changed = file_info.len() != known || !found.is_empty();
+        file_info.extend(found);
+        tracing::debug!(
+            store = %self.store_path().display(),
+            backups = file_info.len(),
+            "collected backups"
+        );
+        Ok(changed)
+    }
+
+    /// Copies the full version history of `paths` (files, or directories containing files) into
+    /// the store of `destination`. Only objects the destination is missing are transferred, and
+    /// each copy is verified by hash before it is added.
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock of `destination` cannot be acquired
+    /// - Returns an error if an object cannot be read, written, or fails verification
+    pub fn clone_history<P: AsRef<Path>, D: StorageBackend>(
+        &self,
+        paths: &[P],
+        destination: &BackupManager<D>,
+    ) -> Result<CloneReport> {
+        let selected = self
+            .index()
+            .iter()
+            .filter(|info| {
+                paths
+                    .iter()
+                    .any(|path| info.meta.path().starts_with(path.as_ref()))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let _lock = destination.lock_store()?;
+        let mut report = CloneReport::default();
+        let mut index = destination.index_mut();
+        let copied = selected.iter().try_for_each(|info| {
+            clone::copy_object(
+                info,
+                &*self.backend,
+                &*destination.backend,
+                destination.store_path(),
+                &mut index,
+                &mut report,
+            )
+        });
+        destination.save_index(&index);
+        copied.map(|()| report)
+    }
+
+    /// Exports every backup in the store into a single portable archive at `archive`, a tar file
+    /// holding an index manifest and all compressed objects. Returns the number of backups
+    /// exported.
+    ///
+    /// ## Errors
+    /// - Returns an error if a backup cannot be read or the archive cannot be written
+    pub fn export_archive(&self, archive: impl AsRef<Path>) -> Result<usize> {
+        archive::export(&*self.backend, &self.index(), archive.as_ref())
+    }
+
+    /// Merges the backups of an archive created by [`BackupManager::export_archive`] into the
+    /// store. Backups the store already has are skipped. A backup whose version is already taken
+    /// by a different backup of the same file is stored as the next free version instead, see
+    /// [`ImportReport::renumbered`].
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the archive cannot be read, is corrupt, or does not match its
+    ///   manifest, backups imported before that are kept
+    /// - Returns an error if an imported backup cannot be written to the store
+    pub fn import_archive(&self, archive: impl AsRef<Path>) -> Result<ImportReport> {
+        let _lock = self.lock_store()?;
+        let mut file_info = self.index_mut();
+        let report = archive::import(
+            archive.as_ref(),
+            &*self.backend,
+            self.config.store_dir_path(),
+            &mut file_info,
+        );
+        // backups imported before a failure are kept, so they are indexed either way
+        self.save_index(&file_info);
+        report
+    }
+
+    /// Restores `version` of the file at `path`, or its latest version if `version` is `None`.
+    /// The latest version of a deleted file is the one before its deletion was recorded (see
+    /// [`BackupManager::record_deletion`]), so this resurrects it. With [`RestoreOptions::with_verification`] the restored file is read back and compared
+    /// with the backed up contents, and written again up to [`RestoreOptions::retries`] times if
+    /// they differ. With [`RestoreOptions::with_dry_run`] nothing is written, see
+    /// [`RestoredFile::change`].
+    ///
+    /// The file is only replaced once the restored contents are complete, and an existing file
+    /// is backed up before it is replaced (see [`RestoreOptions::with_pre_restore_backup`]), so
+    /// restoring the wrong version can be undone with [`RestoredFile::pre_restore_version`].
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no such backup in the store
+    /// - Returns an error if `version` records the deletion of the file
+    /// - Returns [`Error::Conflict`](crate::Error::Conflict) if the file changed since its
+    ///   latest backup, unless [`RestoreOptions::with_force`] is set
+    /// - Returns an error if the existing file cannot be backed up before it is replaced
+    /// - Returns an error if the backup cannot be read or the file cannot be written
+    /// - Returns an error if the restored file still differs after all retries, in which case the
+    ///   existing file is left as it was
+    pub fn restore(
+        &self,
+        path: impl AsRef<Path>,
+        version: Option<FileVersion>,
+        options: &RestoreOptions,
+    ) -> Result<RestoredFile> {
+        let path = path.as_ref();
+        let info = {
+            let file_info = self.index();
+            match version {
+                Some(version) => Self::find(&file_info, path, version)?,
+                // a deleted file is brought back as it was before its deletion
+                None => file_info
+                    .iter()
+                    .filter(|info| info.meta.path() == path && !info.meta.is_deleted())
+                    .max_by_key(|info| *info.meta.version())
+                    .ok_or_else(|| format!("no backup of '{}'", path.display()))?,
+            }
+            .clone()
+        };
+        self.restore_info(&info, options)
+    }
+
+    /// Restores the backup described by `info`, first backing up the file it overwrites unless
+    /// [`RestoreOptions::pre_restore_backup`] is off
+    fn restore_info(&self, info: &BackupInfo, options: &RestoreOptions) -> Result<RestoredFile> {
+        if info.meta.is_deleted() {
+            return Err(format!(
+                "version {} of '{}' records its deletion, restore an earlier version",
+                info.meta.version(),
+                info.meta.path().display()
+            )
+            .into());
+        }
+        if options.destination().is_none() && !options.force() {
+            self.check_conflict(info.meta.path())?;
+        }
+        let pre_restore = if options.pre_restore_backup() && !options.dry_run().is_on() {
+            self.preserve(&options.target(info.meta.path()))?
+        } else {
+            None
+        };
+        let restored = restore::restore(&*self.backend, &*self.vfs, info, options)?;
+        if !options.dry_run().is_on() {
+            let payload = HookPayload::new(HookEvent::RestoreCompleted, self.store_path())
+                .with_file(restored.destination(), restored.version());
+            self.hooks.dispatch(self.config.hooks(), payload);
+        }
+        Ok(match pre_restore {
+            Some(version) => restored.with_pre_restore_version(version),
+            None => restored,
+        })
+    }
+
+    /// Fails with [`Error::Conflict`](crate::Error::Conflict) if the file at `path` changed
+    /// since its latest backup, compared like [`SyncMode::Quick`] does. A changed file whose
+    /// contents match an earlier version, like one an earlier restore put back, has nothing to
+    /// lose.
+    fn check_conflict(&self, path: &Path) -> Result {
+        let current = match self.current_fs_meta(path) {
+            // restoring over a directory fails anyway
+            Ok(current) if current.file_type() == FileKind::Dir => return Ok(()),
+            Ok(current) => current,
+            Err(e) if e.io_kind() == Some(std::io::ErrorKind::NotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let versions = self
+            .index()
+            .iter()
+            .filter(|info| info.meta.path() == path && !info.meta.is_deleted())
+            .cloned()
+            .collect::<Vec<_>>();
+        let Some(latest) = versions.iter().max_by_key(|info| *info.meta.version()) else {
+            return Ok(());
+        };
+        let changed = sync::has_changed(
+            &*self.backend,
+            &*self.vfs,
+            path,
+            &current,
+            Some(latest),
+            SyncMode::Quick,
+        )?;
+        if !changed {
+            return Ok(());
+        }
+        if current.file_type() == FileKind::File {
+            let hash = fnv1a(&self.vfs.read(path)?);
+            for info in &versions {
+                let backed_up = info.meta.fs_meta();
+                if backed_up.file_type() == FileKind::File
+                    && backed_up.size() == current.size()
+                    && annotations::content_hash(&*self.backend, info)? == hash
+                {
+                    return Ok(());
+                }
+            }
+        }
+        Err(crate::Error::Conflict {
+            path: path.to_path_buf(),
+            latest: latest.meta.version().get(),
+        })
+    }
+
+    /// Makes sure the current contents of the file at `path` are in a backup tagged with
+    /// [`PRE_RESTORE_TAG`] before a restore overwrites them, returning its version. A file that
+    /// did not change since its latest backup is not backed up again.
+    fn preserve(&self, path: &Path) -> Result<Option<FileVersion>> {
+        match self.vfs.symlink_metadata(path) {
+            // restoring over a directory fails anyway
+            Ok(meta) if meta.file_type() == FileKind::Dir => return Ok(None),
+            Ok(_) => {}
+            Err(e) if e.io_kind() == Some(std::io::ErrorKind::NotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let version = match self.latest_backup(path) {
+            Some(latest) if !self.needs_backup(path)? => *latest.version(),
+            _ => *self
+                .backup(path)
+                .map_err(|e| {
+                    format!(
+                        "unable to back up '{}' before restoring over it - {e}",
+                        path.display()
+                    )
+                })?
+                .version(),
+        };
+        self.annotate(path, version, |annotation| {
+            annotation.add_tag(PRE_RESTORE_TAG);
+        })?;
+        tracing::info!(
+            path = %path.display(),
+            %version,
+            "kept the contents a restore overwrites"
+        );
+        Ok(Some(version))
+    }
+
+    /// Gets the metadata of the backup with the given `id`, if it is in the store
+    #[must_use]
+    pub fn backup_by_id(&self, id: UniqueId) -> Option<FileMeta> {
+        self.index()
+            .iter()
+            .find(|info| info.id() == id)
+            .map(|info| info.meta.clone())
+    }
+
+    /// Gets the metadata of the latest backup of the file at `path`, if it was ever backed up
+    #[must_use]
+    pub fn latest_backup(&self, path: impl AsRef<Path>) -> Option<FileMeta> {
+        let path = path.as_ref();
+        self.index()
+            .iter()
+            .filter(|info| info.meta.path() == path)
+            .max_by_key(|info| *info.meta.version())
+            .map(|info| info.meta.clone())
+    }
+
+    /// Same as [`BackupManager::restore`], restoring the backup with the given `id`
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no backup with that id in the store
+    /// - Returns [`Error::Conflict`](crate::Error::Conflict) if the file changed since its
+    ///   latest backup, unless [`RestoreOptions::with_force`] is set
+    /// - Returns an error if the backup cannot be read or the file cannot be written
+    /// - Returns an error if the restored file still differs after all retries
+    pub fn restore_by_id(&self, id: UniqueId, options: &RestoreOptions) -> Result<RestoredFile> {
+        let info = self
+            .index()
+            .iter()
+            .find(|info| info.id() == id)
+            .cloned()
+            .ok_or_else(|| format!("no backup with id {id}"))?;
+        self.restore_info(&info, options)
+    }
+
+    /// Restores the latest version of each of `paths`, see [`BackupManager::restore`]. A failure
+    /// for one file does not affect the others.
+    ///
+    /// Returns the result for each path, in the same order as `paths`.
+    pub fn restore_all<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        options: &RestoreOptions,
+    ) -> Vec<(PathBuf, Result<RestoredFile>)> {
+        paths
+            .into_iter()
+            .map(|path| {
+                let path = path.as_ref();
+                (path.to_path_buf(), self.restore(path, None, options))
+            })
+            .collect()
+    }
+
+    /// Restores every file below the directory `dir` as it was at `at` (or now if `None`), each
+    /// from its latest version backed up by then. Files whose version at that time records their
+    /// deletion, or that were not backed up yet, are left alone. With a `target` the files are
+    /// restored below it instead, at their paths relative to `dir`, otherwise to their original
+    /// paths. Missing directories are created, and each file is restored like
+    /// [`BackupManager::restore`] does according to `options`. A failure for one file does not
+    /// affect the others.
+    ///
+    /// Returns the result for each restored file, sorted by path.
+    ///
+    /// ## Errors
+    /// - Returns an error if no file below `dir` has a backup from before `at`
+    pub fn restore_tree(
+        &self,
+        dir: impl AsRef<Path>,
+        at: Option<Timestamp>,
+        target: Option<&Path>,
+        options: &RestoreOptions,
+    ) -> Result<Vec<(PathBuf, Result<RestoredFile>)>> {
+        let dir = dir.as_ref();
+        let mut latest = BTreeMap::<PathBuf, BackupInfo>::new();
+        for info in self.index().iter() {
+            let path = info.meta.path();
+            if path == dir
+                || !path.starts_with(dir)
+                || at.is_some_and(|at| *info.meta.created() > at)
+            {
+                continue;
+            }
+            match latest.get(path) {
+                Some(known) if known.meta.version() >= info.meta.version() => {}
+                _ => {
+                    latest.insert(path.clone(), info.clone());
+                }
+            }
+        }
+        if latest.is_empty() {
+            return Err(format!("no backup of a file below '{}'", dir.display()).into());
+        }
+
+        let options = match target {
+            Some(target) => options.clone().with_tree(dir, target),
+            None => options.clone(),
+        };
+        Ok(latest
+            .into_iter()
+            .filter(|(_, info)| !info.meta.is_deleted())
+            .map(|(path, info)| {
+                let restored = self.restore_info(&info, &options);
+                (path, restored)
+            })
+            .collect())
+    }
+
+    /// Gets the annotation (notes, tags and pin) of `version` of the file at `path`, if it has one
+    #[must_use]
+    pub fn annotation(&self, path: impl AsRef<Path>, version: FileVersion) -> Option<Annotation> {
+        let file_info = self.index();
+        let info = Self::find(&file_info, path.as_ref(), version).ok()?;
+        self.annotation_index().get(info).cloned()
+    }
+
+    /// Updates the annotation of `version` of the file at `path` with `f` and saves it
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if there is no such backup in the store
+    /// - Returns an error if the annotation index cannot be written
+    pub fn annotate(
+        &self,
+        path: impl AsRef<Path>,
+        version: FileVersion,
+        f: impl FnOnce(&mut Annotation),
+    ) -> Result {
+        let _lock = self.lock_store()?;
+        let file_info = self.index();
+        let info = Self::find(&file_info, path.as_ref(), version)?;
+        let mut annotations = self.annotation_index_mut();
+        annotations.update(info, f)?;
+        annotations.save()
+    }
+
+    /// Exports every annotation into the sidecar JSON file at `sidecar`, keyed by path, version
+    /// and content hash so it can be imported into other stores holding the same backups.
+    /// Returns the number of annotations exported.
+    ///
+    /// ## Errors
+    /// - Returns an error if an annotated backup cannot be read or the sidecar cannot be written
+    pub fn export_annotations(&self, sidecar: impl AsRef<Path>) -> Result<usize> {
+        let file_info = self.index();
+        annotations::export(
+            &self.annotation_index(),
+            &*self.backend,
+            &file_info,
+            sidecar.as_ref(),
+        )
+    }
+
+    /// Merges the annotations of a sidecar file created by [`BackupManager::export_annotations`]
+    /// into the store. Annotations are matched by content, so they follow backups that were
+    /// stored under a different version, e.g. by [`BackupManager::import_archive`].
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the sidecar cannot be read or parsed
+    /// - Returns an error if a backup cannot be read or the annotation index cannot be written
+    pub fn import_annotations(&self, sidecar: impl AsRef<Path>) -> Result<AnnotationReport> {
+        let _lock = self.lock_store()?;
+        let file_info = self.index();
+        let mut annotations = self.annotation_index_mut();
+        let report = annotations::import(
+            &mut annotations,
+            &*self.backend,
+            &file_info,
+            sidecar.as_ref(),
+        )?;
+        annotations.save()?;
+        Ok(report)
+    }
+
+    /// Compares two versions of the file at `path`. The newer version `to` defaults to the latest
+    /// one, the older version `from` to the one right before `to`. Text files are compared line by
+    /// line, anything else byte by byte (see [`FileDiff`](crate::FileDiff)).
+    ///
+    /// ## Errors
+    /// - Returns an error if either version does not exist, or `path` has a single version only
+    /// - Returns an error if either backup cannot be read
+    pub fn diff(
+        &self,
+        path: impl AsRef<Path>,
+        from: Option<FileVersion>,
+        to: Option<FileVersion>,
+    ) -> Result<crate::FileDiff> {
+        let path = path.as_ref();
+        let (from, to) = {
+            let file_info = self.index();
+            let mut versions = file_info
+                .iter()
+                .filter(|info| info.meta.path() == path)
+                .collect::<Vec<_>>();
+            if versions.is_empty() {
+                return Err(format!("no backup of '{}'", path.display()).into());
+            }
+            versions.sort_by_key(|info| *info.meta.version());
+            let to = match to {
+                Some(version) => Self::find(&file_info, path, version)?,
+                None => versions[versions.len() - 1],
+            };
+            let from = match from {
+                Some(version) => Self::find(&file_info, path, version)?,
+                None => versions
+                    .iter()
+                    .rev()
+                    .find(|info| info.meta.version() < to.meta.version())
+                    .ok_or_else(|| {
+                        format!(
+                            "no version of '{}' before version {}",
+                            path.display(),
+                            to.meta.version()
+                        )
+                    })?,
+            };
+            (from.clone(), to.clone())
+        };
+        diff::diff(&*self.backend, &from, &to)
+    }
+
+    fn find<'a>(
+        file_info: &'a [BackupInfo],
+        path: &Path,
+        version: FileVersion,
+    ) -> Result<&'a BackupInfo> {
+        file_info
+            .iter()
+            .find(|info| info.meta.path() == path && *info.meta.version() == version)
+            .ok_or_else(|| {
+                format!("no backup of '{}' with version {version}", path.display()).into()
+            })
+    }
+
+    /// Gets the shared dictionary small contents are compressed with, if one has been trained
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while replacing the dictionary
+    #[must_use]
+    pub fn dictionary(&self) -> Option<Arc<Dictionary>> {
+        self.dictionary.read().expect("dictionary poisoned").clone()
+    }
+
+    /// Trains a new shared dictionary of at most `max_size` bytes for the store, out of the latest
+    /// backup of every file smaller than [`CompressionConfig::dictionary_bytes`]. Contents that
+    /// small are compressed with it from now on, content blobs written before keep the dictionary
+    /// (if any) they were compressed with. Returns the dictionary along with the number of backups
+    /// it was trained on.
+    ///
+    /// [`CompressionConfig::dictionary_bytes`]: crate::CompressionConfig::dictionary_bytes
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if a backup cannot be read
+    /// - Returns an error if the small files in the store have nothing in common
+    /// - Returns an error if the dictionary cannot be written
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while replacing the dictionary
+    pub fn train_dictionary(&self, max_size: usize) -> Result<(Arc<Dictionary>, usize)> {
+        let _lock = self.lock_store()?;
+        let threshold = self.config.compression().dictionary_bytes();
+        let samples = {
+            let index = self.index();
+            let mut latest = BTreeMap::<&Path, &BackupInfo>::new();
+            for info in index.iter() {
+                let entry = latest.entry(info.meta.path()).or_insert(info);
+                if info.meta.version() > entry.meta.version() {
+                    *entry = info;
+                }
+            }
+            latest
+                .into_values()
+                .filter(|info| {
+                    let size = info.meta.fs_meta().size();
+                    !info.meta.is_deleted() && size > 0 && size < threshold
+                })
+                .take(MAX_DICTIONARY_SAMPLES)
+                .map(|info| content::read(&*self.backend, info))
+                .collect::<Result<Vec<_>>>()?
+        };
+        let dictionary = Arc::new(Dictionary::train(
+            samples.iter().map(Vec::as_slice),
+            max_size,
+        )?);
+        content::set_current_dictionary(&self.config, &*self.backend, &dictionary)?;
+        tracing::info!(
+            id = %format!("{:016x}", dictionary.id()),
+            size = dictionary.len(),
+            samples = samples.len(),
+            "trained compression dictionary"
+        );
+        *self.dictionary.write().expect("dictionary poisoned") = Some(Arc::clone(&dictionary));
+        Ok((dictionary, samples.len()))
+    }
+
+    /// Gets aggregate statistics about the backups currently in the store
+    #[must_use]
+    pub fn stats(&self) -> StoreStats {
+        StoreStats::collect(&self.index())
+    }
+
+    /// Gets an inventory of every backup currently in the store, see [`Manifest`]. The contents
+    /// of backups that are not kept in a content blob are read to hash them.
+    ///
+    /// ## Errors
+    /// - Returns an error if a backup cannot be read
+    pub fn manifest(&self) -> Result<Manifest> {
+        let infos = self.index().clone();
+        Manifest::collect(&*self.backend, &infos)
+    }
+
+    /// Applies the given [`RetentionPolicy`], deleting every backup it does not keep from the store.
+    /// Pinned backups and the ones kept before a restore are never deleted. Returns the same report
+    /// [`BackupManager::simulate_retention`] would have, see [`RetentionReport::plan`] for the
+    /// deletions. With [`DryRun::On`] nothing is deleted, and the hooks of
+    /// [`HookEvent::PruneExecuted`] only fire if something was.
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if a backup cannot be deleted, backups deleted before that are no longer
+    ///   tracked by this manager
+    pub fn apply_retention(
+        &self,
+        policy: &RetentionPolicy,
+        dry_run: DryRun,
+    ) -> Result<RetentionReport> {
+        if dry_run.is_on() {
+            self.collect_backup_info()?;
+            return Ok(self.simulate_retention(policy));
+        }
+        let lock = self.lock_store()?;
+        let mut file_info = self.index_mut();
+        let annotations = self.annotation_index();
+        let report = policy.simulate(
+            &file_info,
+            |info| annotations.protects(info),
+            Timestamp::now(),
+        );
+        drop(annotations);
+        self.remove_backups(&mut file_info, report.removed_paths(), "retention policy")?;
+        drop(file_info);
+        drop(lock);
+        if report.removed_versions() > 0 {
+            let payload = HookPayload::new(HookEvent::PruneExecuted, self.store_path())
+                .with_count(report.removed_versions());
+            self.hooks.dispatch(self.config.hooks(), payload);
+        }
+        Ok(report)
+    }
+
+    /// Evicts the oldest versions until the store is under [`Config::max_store_bytes`], never
+    /// evicting the newest version of a file or its newest version with contents. Backups evict on
+    /// their own, this is only needed after the cap was lowered. With [`DryRun::On`] nothing is
+    /// deleted, see [`EvictionReport::plan`].
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if a backup cannot be deleted, backups deleted before that are no longer
+    ///   tracked by this manager
+    pub fn evict_to_cap(&self, dry_run: DryRun) -> Result<EvictionReport> {
+        if dry_run.is_on() {
+            self.collect_backup_info()?;
+            let file_info = self.index();
+            let annotations = self.annotation_index();
+            return Ok(eviction::plan(
+                &file_info,
+                |info| annotations.protects(info),
+                self.config.max_store_bytes(),
+            ));
+        }
+        let _lock = self.lock_store()?;
+        self.evict(&mut self.index_mut(), 0)
+    }
+
+    /// Gets the size of the files at `paths` as they are now, roughly the room their backups take
+    /// before compression. Files that cannot be read take none, their backups fail anyway.
+    fn incoming_bytes<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> u64 {
+        paths
+            .into_iter()
+            .filter_map(|path| self.vfs.metadata(path).ok())
+            .map(|meta| meta.size())
+            .sum()
+    }
+
+    /// Removes the content blobs no backup refers to any more that were written at least `grace`
+    /// ago, see [`GcReport`]. The grace period keeps blobs another process is just writing a
+    /// backup for, blobs of unknown age are only removed with a zero `grace`. With
+    /// [`DryRun::On`] nothing is deleted, see [`GcReport::removed`].
+    ///
+    /// ## Errors
+    /// - Returns an error if the store lock cannot be acquired
+    /// - Returns an error if the blobs cannot be listed or a blob cannot be deleted
+    pub fn gc(&self, grace: Duration, dry_run: DryRun) -> Result<GcReport> {
+        if dry_run.is_on() {
+            self.collect_backup_info()?;
+            return gc::plan(&*self.backend, &self.index(), grace, Timestamp::now());
+        }
+        let _lock = self.lock_store()?;
+        // objects removed by hand do not change the lock, so the store is always read again
+        self.collect_backup_info()?;
+        let report = gc::plan(&*self.backend, &self.index(), grace, Timestamp::now())?;
+        for blob in report.removed() {
+            tracing::info!(
+                blob = blob.id(),
+                size = blob.size(),
+                "removing orphaned content blob"
+            );
+            match self.backend.delete(blob.id()) {
+                Ok(()) => {}
+                Err(e) if e.io_kind() == Some(ErrorKind::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Takes the report of every eviction done by backups since the last call
+    ///
+    /// ## Panics
+    /// - Panics if another thread panicked while recording an eviction
+    #[must_use]
+    pub fn take_evictions(&self) -> EvictionReport {
+        std::mem::take(&mut *self.evictions.lock().expect("eviction report poisoned"))
+    }
+
+    /// Fires the hooks of [`HookEvent::BackupCreated`] for the backup described by `meta`
+    fn fire_backup_created(&self, meta: &FileMeta) {
+        let payload = HookPayload::new(HookEvent::BackupCreated, self.store_path())
+            .with_file(meta.path(), *meta.version());
+        self.hooks.dispatch(self.config.hooks(), payload);
+    }
+
+    /// Evicts backups to get the store under its size cap with room for `incoming` more bytes,
+    /// which must only be done while holding the store lock
+    fn evict(&self, file_info: &mut Vec<BackupInfo>, incoming: u64) -> Result<EvictionReport> {
+        let annotations = self.annotation_index();
+        let report = eviction::plan_room(
+            file_info,
+            |info| annotations.protects(info),
+            self.config.max_store_bytes(),
+            incoming,
+        );
+        drop(annotations);
+        self.remove_backups(file_info, report.removed_paths(), "store size cap")?;
+        if report.is_over_cap() {
+            tracing::warn!(
+                store_bytes = report.store_bytes(),
+                max_store_bytes = self.config.max_store_bytes(),
+                "store is over its size cap, but every file is down to a single version"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Evicts backups to make room for backups of `incoming` bytes before they are written, or
+    /// with none to catch up once they were, recording the report for
+    /// [`BackupManager::take_evictions`]. The backups do not depend on it, so a failed eviction is
+    /// only logged.
+    fn evict_for_backup(&self, file_info: &mut Vec<BackupInfo>, incoming: u64) {
+        match self.evict(file_info, incoming) {
+            Ok(report) => self
+                .evictions
+                .lock()
+                .expect("eviction report poisoned")
+                .merge(report),
+            Err(e) => tracing::warn!(error = %e, "unable to evict backups over the store size cap"),
+        }
+    }
+
+    /// Deletes the backups at `paths` from the store, together with their annotations and the
+    /// content blobs no other backup refers to
+    fn remove_backups<'a>(
+        &self,
+        file_info: &mut Vec<BackupInfo>,
+        paths: impl IntoIterator<Item = &'a Path>,
+        reason: &str,
+    ) -> Result {
+        let mut paths = paths.into_iter().peekable();
+        if paths.peek().is_none() {
+            return Ok(());
+        }
+        let mut annotations = self.annotation_index_mut();
+        let mut contents = Vec::new();
+        let mut result = Ok(());
+        for path in paths {
+            tracing::info!(object = %path.display(), reason, "removing backup");
+            if let Err(e) = object_id(path).and_then(|id| self.backend.delete(id)) {
+                result = Err(e);
+                break;
+            }
+            if let Some(info) = file_info.iter().find(|info| info.backup_path == path) {
+                annotations.remove(info);
+                contents.extend(info.meta.content());
+            }
+            file_info.retain(|info| info.backup_path != path);
+        }
+        // the blobs of the backups removed before a failure have to go as well
+        let removed = content::remove_unreferenced(&*self.backend, file_info, contents);
+        self.save_index(file_info);
+        result.and(removed).and(annotations.save())
+    }
+
+    /// Runs the given [`RetentionPolicy`] against the currently known backups **without** deleting
+    /// anything, reporting what would be removed and how far back history would reach afterwards.
+    #[must_use]
+    pub fn simulate_retention(&self, policy: &RetentionPolicy) -> RetentionReport {
+        let file_info = self.index();
+        let annotations = self.annotation_index();
+        policy.simulate(
+            &file_info,
+            |info| annotations.protects(info),
+            Timestamp::now(),
+        )
+    }
+}
+
+/// Given a path (to a **backup** file), extract only the [`FileHeader`] and the [`FileMeta`] without
+/// decompressing the actual file bytes. The whole file is still read to verify its checksum.
+///
+/// ## Errors
+/// - Returns [`Error::Corrupted`](storage_common::Error::Corrupted) if the checksum of the
+///   backup file does not match.
+/// - Returns an IO error if the backup file cannot be opened, or the decompressor fails to read
+/// the specified number of bytes.
+/// - Returns a Serde error if `rmp_serde` fails to deserialize the [`FileMeta`]
+pub fn extract_header_and_meta(backup_path: impl AsRef<Path>) -> Result<(FileHeader, FileMeta)> {
+    let reader = BufReader::new(read_only().open(&backup_path)?);
+    storage_format::read_header_and_meta(reader).map_err(|e| e.with_path(backup_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PlannedChange, OBJECT_EXTENSION};
+
+    fn create_temp_file() -> std::fs::File {
+        tempfile::tempfile().expect("failed to create temp file")
+    }
+
+    fn create_named_temp_file() -> tempfile::NamedTempFile {
+        tempfile::NamedTempFile::new().expect("failed to create named temp file")
+    }
+
+    fn test_config(store: &Path) -> Config {
+        Config::new().extend_with(
+            &storage_common::MaybeConfig::default()
+                .with_store_dir(store.to_str().unwrap())
+                .with_backup_threads(2),
+        )
+    }
+
+    #[test]
+    fn backup_all_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let mut paths: Vec<PathBuf> = (0..5)
+            .map(|i| {
+                let path = files.path().join(format!("file{i}.txt"));
+                std::fs::write(&path, format!("file number {i}")).unwrap();
+                path
+            })
+            .collect();
+        paths.push(paths[0].clone());
+        paths.push(files.path().join("missing.txt"));
+
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        let results = manager.backup_all(&paths);
+        assert_eq!(results.len(), paths.len());
+        for (i, (path, result)) in results.iter().enumerate() {
+            assert_eq!(path, &paths[i]);
+            assert_eq!(
+                result.is_ok(),
+                i != 6,
+                "unexpected result for {}",
+                path.display()
+            );
+        }
+        assert_eq!(results[0].1.as_ref().unwrap().version().get(), 1);
+        assert_eq!(results[5].1.as_ref().unwrap().version().get(), 2);
+
+        // a fresh manager picks up everything that was written to the store
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert_eq!(manager.index().len(), 6);
+        assert_eq!(manager.next_version(&paths[0]).get(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_test() {
+        use storage_common::{HookAction, HookConfig};
+
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let log = files.path().join("hooks.log");
+        let hook = HookConfig::new(
+            "log",
+            vec![
+                HookEvent::BackupCreated,
+                HookEvent::RestoreCompleted,
+                HookEvent::PruneExecuted,
+            ],
+            HookAction::Command(format!(
+                "echo \"$STORAGE_HOOK_EVENT $STORAGE_HOOK_COUNT\" >> {}",
+                log.display()
+            )),
+        );
+        let config = test_config(store.path())
+            .extend_with(&storage_common::MaybeConfig::default().with_hooks(vec![hook]));
+        let manager = BackupManager::new(config).unwrap();
+
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "first").unwrap();
+        manager.backup(&path).unwrap();
+        std::fs::write(&path, "second").unwrap();
+        manager.backup_all([&path]);
+        manager
+            .restore(&path, None, &RestoreOptions::new())
+            .unwrap();
+        let policy = "keep=1".parse::<RetentionPolicy>().unwrap();
+        manager.apply_retention(&policy, DryRun::On).unwrap();
+        manager.apply_retention(&policy, DryRun::Off).unwrap();
+        // nothing is left to prune, so no hook fires
+        manager.apply_retention(&policy, DryRun::Off).unwrap();
+        // hooks run in the background, dropping the manager waits for them
+        drop(manager);
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "backup-created 1\nbackup-created 1\nrestore-completed 1\nprune-executed 1\n"
+        );
+    }
+
+    #[test]
+    fn shared_manager_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let path = files.path().join("shared.txt");
+        std::fs::write(&path, "shared").unwrap();
+
+        // two managers on the same store, as if they were in different processes
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        let other = BackupManager::new(test_config(store.path())).unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| manager.backup(&path).unwrap());
+                scope.spawn(|| other.backup(&path).unwrap());
+            }
+        });
+        // no version was taken twice, although each manager only saw some of the backups
+        manager.collect_backup_info().unwrap();
+        let mut versions = manager
+            .index()
+            .iter()
+            .map(|info| info.meta.version().get())
+            .collect::<Vec<_>>();
+        versions.sort_unstable();
+        assert_eq!(versions, (1..=8).collect::<Vec<_>>());
+        assert_eq!(manager.next_version(&path).get(), 9);
+        drop(StoreLock::acquire(&store.path().join("store.lock"), Duration::ZERO).unwrap());
+
+        // a lock held elsewhere makes writes fail once the timeout is up
+        let mut manager = manager;
+        manager.set_lock_timeout(Duration::from_millis(100));
+        let lock = StoreLock::acquire(&store.path().join("store.lock"), Duration::ZERO).unwrap();
+        let err = manager.backup(&path).unwrap_err();
+        assert!(err.to_string().contains("store is locked"), "{err}");
+        drop(lock);
+        assert_eq!(manager.backup(&path).unwrap().version().get(), 9);
+    }
+
+    #[test]
+    fn clone_history_test() {
+        let source_store = tempfile::tempdir().expect("failed to create store dir");
+        let destination_store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let a = files.path().join("a.txt");
+        let b = files.path().join("b.txt");
+        std::fs::write(&a, "first").unwrap();
+        std::fs::write(&b, "other").unwrap();
+
+        let source = BackupManager::new(test_config(source_store.path())).unwrap();
+        source.backup(&a).unwrap();
+        source.backup(&b).unwrap();
+        std::fs::write(&a, "second").unwrap();
+        source.backup(&a).unwrap();
+
+        let destination = BackupManager::new(test_config(destination_store.path())).unwrap();
+        let report = source.clone_history(&[&a], &destination).unwrap();
+        assert_eq!((report.copied(), report.skipped()), (2, 0));
+        assert_eq!(destination.stats().total_backups(), 2);
+
+        // only missing objects are transferred
+        let report = source.clone_history(&[files.path()], &destination).unwrap();
+        assert_eq!((report.copied(), report.skipped()), (1, 2));
+
+        let destination = BackupManager::new(test_config(destination_store.path())).unwrap();
+        assert_eq!(destination.stats().total_backups(), 3);
+        assert_eq!(destination.next_version(&a).get(), 3);
+
+        // a version taken by other contents in the destination is copied as the next free one
+        let other_store = tempfile::tempdir().expect("failed to create store dir");
+        let other = BackupManager::new(test_config(other_store.path())).unwrap();
+        std::fs::write(&a, "diverged").unwrap();
+        other.backup(&a).unwrap();
+        let report = source.clone_history(&[&a], &other).unwrap();
+        assert_eq!((report.copied(), report.skipped()), (2, 0));
+        let renumbered = report
+            .renumbered()
+            .iter()
+            .map(|(_, from, to)| (from.get(), to.get()))
+            .collect::<Vec<_>>();
+        assert_eq!(renumbered, [(1, 2), (2, 3)]);
+        let (_, _, latest) = &report.renumbered()[1];
+        assert_eq!(other.read_version(&a, *latest).unwrap(), b"second");
+
+        // cloning again finds the contents under their new versions
+        let report = source.clone_history(&[&a], &other).unwrap();
+        assert_eq!((report.copied(), report.skipped()), (0, 2));
+        assert!(report.renumbered().is_empty());
+    }
+
+    #[test]
+    fn dictionary_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let other_store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let settings = |name: &str| {
+            format!("{{\n  \"name\": \"{name}\",\n  \"editor.fontSize\": 14,\n  \"editor.tabSize\": 4\n}}\n")
+        };
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert!(manager.dictionary().is_none());
+        assert!(manager.train_dictionary(1024).is_err());
+        for name in ["a", "b", "c"] {
+            let path = files.path().join(format!("{name}.json"));
+            std::fs::write(&path, settings(name)).unwrap();
+            manager.backup(&path).unwrap();
+        }
+
+        let (dictionary, samples) = manager.train_dictionary(1024).unwrap();
+        assert_eq!(samples, 3);
+        assert!(dictionary.len() <= 1024);
+        let path = files.path().join("d.json");
+        std::fs::write(&path, settings("d")).unwrap();
+        let meta = manager.backup(&path).unwrap();
+        let blob = manager
+            .backend()
+            .get(&meta.content().unwrap().name())
+            .unwrap();
+        assert_eq!(
+            storage_format::content_dictionary(&blob),
+            Some(dictionary.id())
+        );
+        assert_eq!(
+            manager.read_version(&path, *meta.version()).unwrap(),
+            settings("d").as_bytes()
+        );
+        assert_eq!(
+            manager
+                .read_version_head(&path, *meta.version(), 1)
+                .unwrap(),
+            b"{"
+        );
+
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert_eq!(manager.dictionary().unwrap().id(), dictionary.id());
+        assert_eq!(manager.rebuild_index().unwrap(), 4);
+
+        let other = BackupManager::new(test_config(other_store.path())).unwrap();
+        manager.clone_history(&[&path], &other).unwrap();
+        assert!(other.dictionary().is_none());
+        assert_eq!(
+            other.read_version(&path, *meta.version()).unwrap(),
+            settings("d").as_bytes()
+        );
+    }
+
+    #[test]
+    fn archive_test() {
+        let source_store = tempfile::tempdir().expect("failed to create store dir");
+        let destination_store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let archive = files.path().join("store.tar");
+        let a = files.path().join("a.txt");
+        let b = files.path().join("b.txt");
+        std::fs::write(&a, "first").unwrap();
+        std::fs::write(&b, "other").unwrap();
+
+        let source = BackupManager::new(test_config(source_store.path())).unwrap();
+        source.backup(&a).unwrap();
+        source.backup(&b).unwrap();
+        std::fs::write(&a, "second").unwrap();
+        source.backup(&a).unwrap();
+        assert_eq!(source.export_archive(&archive).unwrap(), 3);
+
+        // the destination already has a different version 1 of `a`
+        std::fs::write(&a, "local").unwrap();
+        let destination = BackupManager::new(test_config(destination_store.path())).unwrap();
+        destination.backup(&a).unwrap();
+        let report = destination.import_archive(&archive).unwrap();
+        assert_eq!((report.imported(), report.skipped()), (3, 0));
+        let renumbered = report
+            .renumbered()
+            .iter()
+            .map(|(_, from, to)| (from.get(), to.get()))
+            .collect::<Vec<_>>();
+        assert_eq!(renumbered, [(1, 2), (2, 3)]);
+
+        // importing again is a no-op
+        let report = destination.import_archive(&archive).unwrap();
+        assert_eq!((report.imported(), report.skipped()), (0, 3));
+
+        let destination = BackupManager::new(test_config(destination_store.path())).unwrap();
+        assert_eq!(destination.stats().total_backups(), 4);
+        assert_eq!(destination.next_version(&a).get(), 4);
+
+        // an object stored under a name other than its own is rejected, the name is used as the
+        // id it is written to
+        let b_object = storage_format::object_name(&b, FileVersion::new());
+        let tampered = files.path().join("tampered.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&tampered).unwrap());
+        let mut tar = tar::Archive::new(std::fs::File::open(&archive).unwrap());
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry
+                .path()
+                .unwrap()
+                .to_string_lossy()
+                .replace(&b_object, "evil.bak");
+            let mut bytes = Vec::new();
+            entry.read_to_end(&mut bytes).unwrap();
+            if name == "manifest.json" {
+                bytes = String::from_utf8(bytes)
+                    .unwrap()
+                    .replace(&b_object, "evil.bak")
+                    .into_bytes();
+            }
+            let mut header = entry.header().clone();
+            builder.append_data(&mut header, name, &*bytes).unwrap();
+        }
+        builder.finish().unwrap();
+        let other_store = tempfile::tempdir().expect("failed to create store dir");
+        let other = BackupManager::new(test_config(other_store.path())).unwrap();
+        assert!(other.import_archive(&tampered).is_err());
+        assert!(!other_store.path().join("evil.bak").exists());
+    }
+
+    #[test]
+    fn annotations_test() {
+        let source_store = tempfile::tempdir().expect("failed to create store dir");
+        let destination_store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let archive = files.path().join("store.tar");
+        let sidecar = files.path().join("annotations.json");
+        let a = files.path().join("a.txt");
+        std::fs::write(&a, "first").unwrap();
+
+        let source = BackupManager::new(test_config(source_store.path())).unwrap();
+        source.backup(&a).unwrap();
+        source
+            .annotate(&a, FileVersion::new(), |annotation| {
+                annotation.set_note(Some("before the refactor".into()));
+                annotation.add_tag("release");
+                annotation.set_pinned(true);
+            })
+            .unwrap();
+        assert!(source
+            .annotate(&a, FileVersion::new() + 1u32, |_| {})
+            .is_err());
+        source.export_archive(&archive).unwrap();
+        assert_eq!(source.export_annotations(&sidecar).unwrap(), 1);
+
+        // the annotation index survives a reload and is not mistaken for an object
+        let source = BackupManager::new(test_config(source_store.path())).unwrap();
+        assert_eq!(source.stats().total_backups(), 1);
+        let annotation = source.annotation(&a, FileVersion::new()).unwrap();
+        assert_eq!(annotation.note(), Some("before the refactor"));
+        assert!(annotation.is_pinned());
+
+        // the annotation follows its backup to the version it was renumbered to
+        std::fs::write(&a, "local").unwrap();
+        let destination = BackupManager::new(test_config(destination_store.path())).unwrap();
+        destination.backup(&a).unwrap();
+        destination.import_archive(&archive).unwrap();
+        let report = destination.import_annotations(&sidecar).unwrap();
+        assert_eq!((report.applied(), report.unmatched()), (1, 0));
+        assert!(destination.annotation(&a, FileVersion::new()).is_none());
+        let annotation = destination
+            .annotation(&a, FileVersion::new() + 1u32)
+            .unwrap();
+        assert_eq!(annotation.tags().collect::<Vec<_>>(), ["release"]);
+    }
+
+    #[test]
+    fn restore_keeps_overwritten_contents() {
+        let store = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "v1").unwrap();
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        manager.backup(&path).unwrap();
+
+        // unsaved edits are not overwritten unless forced, and then backed up first
+        std::fs::write(&path, "edited").unwrap();
+        let err = manager
+            .restore(&path, None, &RestoreOptions::new())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::Conflict { ref path, latest: 1 } if path.ends_with("file.txt")
+        ));
+        assert_eq!(err.category(), "conflict");
+        assert_eq!(std::fs::read(&path).unwrap(), b"edited");
+        let file = manager
+            .restore(&path, None, &RestoreOptions::new().with_force(true))
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"v1");
+        let pre_restore = file.pre_restore_version().unwrap();
+        assert_eq!(pre_restore.get(), 2);
+        let annotation = manager.annotation(&path, pre_restore).unwrap();
+        assert_eq!(annotation.tags().collect::<Vec<_>>(), [PRE_RESTORE_TAG]);
+
+        // which makes the restore reversible
+        manager
+            .restore(&path, Some(pre_restore), &RestoreOptions::new())
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"edited");
+        assert_eq!(manager.stats().total_backups(), 3);
+
+        // an unchanged file only has its latest backup tagged
+        let latest = manager.backup(&path).unwrap();
+        let file = manager
+            .restore(&path, Some(FileVersion::new()), &RestoreOptions::new())
+            .unwrap();
+        assert_eq!(file.pre_restore_version(), Some(*latest.version()));
+        assert_eq!(manager.stats().total_backups(), 4);
+
+        let options = RestoreOptions::new()
+            .with_pre_restore_backup(false)
+            .with_force(true);
+        std::fs::write(&path, "edited again").unwrap();
+        let file = manager.restore(&path, Some(pre_restore), &options).unwrap();
+        assert_eq!(file.pre_restore_version(), None);
+        assert_eq!(manager.stats().total_backups(), 4);
+
+        // nothing but the file itself is left next to it
+        let entries = std::fs::read_dir(files.path()).unwrap().count();
+        assert_eq!(entries, 1);
+    }
+
+    #[test]
+    fn record_deletion_test() {
+        let store = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let path = files.path().join("file.txt");
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert!(manager.record_deletion(&path).unwrap().is_none());
+        std::fs::write(&path, "v1").unwrap();
+        manager.backup(&path).unwrap();
+        assert!(manager.record_deletion(&path).unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+        let deletion = manager.record_deletion(&path).unwrap().unwrap();
+        assert!(deletion.is_deleted());
+        assert_eq!(deletion.version().get(), 2);
+        assert!(manager.record_deletion(&path).unwrap().is_none());
+        let history = manager.history(&path);
+        assert_eq!(
+            history.iter().map(FileMeta::is_deleted).collect::<Vec<_>>(),
+            [false, true]
+        );
+        assert_eq!(manager.files(), [path.clone()]);
+        assert_eq!(
+            manager.read_version(&path, FileVersion::new()).unwrap(),
+            b"v1"
+        );
+        assert!(manager
+            .read_version(&path, *deletion.version())
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            manager
+                .read_version_head(&path, FileVersion::new(), 1)
+                .unwrap(),
+            b"v"
+        );
+        assert!(manager
+            .read_version_head(&path, *deletion.version(), 1)
+            .unwrap()
+            .is_empty());
+
+        // the record survives reopening the store without its index
+        drop(manager);
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert!(manager.latest_backup(&path).unwrap().is_deleted());
+
+        // the deletion itself cannot be restored, the version before it is
+        let err = manager
+            .restore(&path, Some(*deletion.version()), &RestoreOptions::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("records its deletion"), "{err}");
+        let file = manager
+            .restore(&path, None, &RestoreOptions::new())
+            .unwrap();
+        assert_eq!(file.version().get(), 1);
+        assert_eq!(std::fs::read(&path).unwrap(), b"v1");
+
+        // the resurrected file is backed up again even though it did not change
+        assert!(manager.needs_backup(&path).unwrap());
+        let report = manager.sync([&path], SyncMode::Quick);
+        assert_eq!(report.backed_up().len(), 1);
+        assert_eq!(report.backed_up()[0].1.version().get(), 3);
+    }
+
+    #[test]
+    fn restore_tree_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let restored = tempfile::tempdir().expect("failed to create restore dir");
+        let dir = files.path().join("project");
+        let top = dir.join("top.txt");
+        let nested = dir.join("src").join("nested.txt");
+        let outside = files.path().join("outside.txt");
+        std::fs::create_dir_all(nested.parent().unwrap()).unwrap();
+        std::fs::write(&top, "top v1").unwrap();
+        std::fs::write(&outside, "outside").unwrap();
+
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert!(manager
+            .restore_tree(&dir, None, None, &RestoreOptions::new())
+            .is_err());
+        let at = *manager.backup(&top).unwrap().created();
+        manager.backup(&outside).unwrap();
+        std::fs::write(&top, "top v2").unwrap();
+        manager.backup(&top).unwrap();
+        std::fs::write(&nested, "nested").unwrap();
+        manager.backup(&nested).unwrap();
+
+        // only what existed at `at`, in that version, relative to the target
+        let results = manager
+            .restore_tree(
+                &dir,
+                Some(at),
+                Some(restored.path()),
+                &RestoreOptions::new(),
+            )
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, top);
+        let file = results[0].1.as_ref().unwrap();
+        assert_eq!(file.destination(), restored.path().join("top.txt"));
+        assert_eq!(std::fs::read(file.destination()).unwrap(), b"top v1");
+        assert!(!restored.path().join("outside.txt").exists());
+
+        // the latest versions, back in place
+        std::fs::remove_dir_all(&dir).unwrap();
+        let results = manager
+            .restore_tree(&dir, None, None, &RestoreOptions::new())
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(std::fs::read(&top).unwrap(), b"top v2");
+        assert_eq!(std::fs::read(&nested).unwrap(), b"nested");
+    }
+
+    #[test]
+    fn restore_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let restored = tempfile::tempdir().expect("failed to create restore dir");
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "v1").unwrap();
+
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        let first = manager.backup(&path).unwrap().id();
+        std::fs::write(&path, "v2").unwrap();
+        let second = manager.backup(&path).unwrap().id();
+        assert!(first < second);
+
+        // ids survive a reload and identify a backup on their own
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert_eq!(manager.backup_by_id(first).unwrap().version().get(), 1);
+        let dry_run = RestoreOptions::new()
+            .with_destination(restored.path())
+            .with_dry_run(DryRun::On);
+        let file = manager.restore_by_id(second, &dry_run).unwrap();
+        assert!(matches!(file.change(), PlannedChange::CreateFile { .. }));
+        assert_eq!(file.attempts(), 0);
+        assert!(!file.destination().exists());
+        let file = manager
+            .restore_by_id(
+                second,
+                &RestoreOptions::new().with_destination(restored.path()),
+            )
+            .unwrap();
+        assert_eq!((file.version().get(), file.id()), (2, second));
+        let file = manager.restore_by_id(second, &dry_run).unwrap();
+        assert!(matches!(file.change(), PlannedChange::OverwriteFile { .. }));
+        assert!(manager
+            .restore_by_id(UniqueId::new(), &RestoreOptions::new())
+            .is_err());
+
+        let options = RestoreOptions::new()
+            .with_destination(restored.path())
+            .with_verification(true);
+        let file = manager
+            .restore(&path, Some(FileVersion::new()), &options)
+            .unwrap();
+        assert!(file.is_verified());
+        assert_eq!(file.attempts(), 1);
+        assert!(file.destination().starts_with(restored.path()));
+        assert!(file.destination().ends_with("file.txt"));
+        assert_eq!(std::fs::read(file.destination()).unwrap(), b"v1");
+
+        let missing = files.path().join("missing.txt");
+        let results = manager.restore_all([&path, &missing], &RestoreOptions::new());
+        assert_eq!(results[0].1.as_ref().unwrap().version().get(), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), b"v2");
+        assert!(results[1].1.is_err());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+            manager.backup(&path).unwrap();
+            let file = manager.restore(&path, None, &options).unwrap();
+            let mode = std::fs::metadata(file.destination())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let target = files.path().join("target.txt");
+        let link = files.path().join("link");
+        std::fs::write(&target, "contents").unwrap();
+        std::os::unix::fs::symlink("target.txt", &link).unwrap();
+
+        let mut manager = BackupManager::new(test_config(store.path())).unwrap();
+        let meta = manager.backup(&link).unwrap();
+        assert_eq!(meta.fs_meta().file_type(), FileKind::Symlink);
+        assert!(!manager.needs_backup(&link).unwrap());
+
+        std::fs::remove_file(&link).unwrap();
+        let file = manager
+            .restore(&link, None, &RestoreOptions::new().with_verification(true))
+            .unwrap();
+        assert!(file.is_verified());
+        assert_eq!(std::fs::read_link(&link).unwrap(), Path::new("target.txt"));
+
+        manager.update_config(test_config(store.path()).extend_with(
+            &storage_common::MaybeConfig::default().with_symlinks(SymlinkPolicy::Follow),
+        ));
+        let meta = manager.backup(&link).unwrap();
+        assert_eq!(meta.fs_meta().file_type(), FileKind::File);
+        std::fs::remove_file(&link).unwrap();
+        manager
+            .restore(&link, None, &RestoreOptions::new())
+            .unwrap();
+        assert!(!std::fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(std::fs::read(&link).unwrap(), b"contents");
+
+        assert!(manager.backup(files.path()).is_err());
+    }
+
+    #[test]
+    fn index_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let a = files.path().join("a.txt");
+        let b = files.path().join("b.txt");
+        std::fs::write(&a, "first").unwrap();
+        std::fs::write(&b, "second").unwrap();
+
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        manager.backup_all([&a, &b, &a]);
+        let index_path = store.path().join("index.rmp");
+        assert!(index_path.exists());
+        assert_eq!(manager.index_drift().unwrap(), 0);
+
+        // an object of another size than the saved index says counts once
+        let mut saved = index::load(&index_path, store.path()).unwrap();
+        saved[0].backup_size += 1;
+        index::save(&index_path, &saved).unwrap();
+        assert_eq!(manager.index_drift().unwrap(), 1);
+        index::save(&index_path, &manager.index()).unwrap();
+
+        // objects the index knows are not read again, so a damaged object goes unnoticed until
+        // the index is rebuilt
+        let object = manager
+            .index()
+            .iter()
+            .find(|info| info.meta.path() == &b)
+            .unwrap()
+            .backup_path
+            .clone();
+        let bytes = std::fs::read(&object).unwrap();
+        std::fs::write(&object, vec![0u8; bytes.len()]).unwrap();
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        // the index is only loaded on first use
+        assert_eq!(*manager.index_state(), IndexState::Unloaded);
+        assert_eq!(manager.index().len(), 3);
+        assert_eq!(*manager.index_state(), IndexState::Loaded);
+        // a failed rebuild keeps the index it would have replaced
+        assert!(manager.rebuild_index().is_err());
+        assert_eq!(manager.index().len(), 3);
+        std::fs::write(&object, bytes).unwrap();
+        assert_eq!(manager.rebuild_index().unwrap(), 3);
+
+        // missing objects are dropped and unknown ones are read
+        std::fs::remove_file(&object).unwrap();
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert_eq!(manager.index().len(), 2);
+        assert_eq!(manager.index_drift().unwrap(), 1);
+        std::fs::write(&index_path, b"not an index").unwrap();
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert_eq!(manager.index().len(), 2);
+        assert_eq!(manager.meta_cache.lock().unwrap().len(), 2);
+        assert_eq!(manager.backup(&b).unwrap().version().get(), 1);
+        let loaded = index::load(&index_path, store.path()).unwrap();
+        assert_eq!(loaded.len(), 3);
+    }
+
+    #[test]
+    fn eviction_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let a = files.path().join("a.txt");
+        let b = files.path().join("b.txt");
+        std::fs::write(&b, "the only version of b").unwrap();
+
+        let mut manager = BackupManager::new(test_config(store.path())).unwrap();
+        manager.backup(&b).unwrap();
+        for i in 0..4 {
+            std::fs::write(&a, format!("version {i} of a")).unwrap();
+            manager.backup(&a).unwrap();
+        }
+        assert!(manager.take_evictions().evicted().is_empty());
+
+        // room for everything but the two oldest versions of `a`, no contents are shared
+        let cap = manager
+            .index()
+            .iter()
+            .filter(|info| info.meta.path() != &a || info.meta.version().get() > 2)
+            .map(|info| info.backup_size + info.meta.content().map_or(0, |c| c.stored()))
+            .sum();
+        manager.update_config(
+            test_config(store.path())
+                .extend_with(&storage_common::MaybeConfig::default().with_max_store_bytes(cap)),
+        );
+        let planned = manager.evict_to_cap(DryRun::On).unwrap();
+        assert_eq!(planned.plan().deleted_backups(), 2);
+        assert_eq!(manager.stats().total_backups(), 5);
+        let report = manager.evict_to_cap(DryRun::Off).unwrap();
+        assert_eq!(report, planned);
+        let evicted = report
+            .evicted()
+            .iter()
+            .map(|backup| (backup.path(), backup.version().get()))
+            .collect::<Vec<_>>();
+        assert_eq!(evicted, [(a.as_path(), 1), (a.as_path(), 2)]);
+        assert!(!report.is_over_cap());
+        assert_eq!(manager.stats().compressed_bytes(), report.store_bytes());
+
+        // a backup over the cap evicts on its own, but never the last version of a file
+        manager.update_config(
+            test_config(store.path())
+                .extend_with(&storage_common::MaybeConfig::default().with_max_store_bytes(1)),
+        );
+        std::fs::write(&a, "version 4 of a").unwrap();
+        manager.backup(&a).unwrap();
+        let report = manager.take_evictions();
+        assert_eq!(report.evicted().len(), 2);
+        assert!(report.is_over_cap());
+        assert!(manager.take_evictions().evicted().is_empty());
+        assert_eq!(manager.stats().total_backups(), 2);
+        assert_eq!(manager.next_version(&a).get(), 6);
+    }
+
+    #[test]
+    fn protected_backups_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let a = files.path().join("a.txt");
+        let mut manager = BackupManager::new(test_config(store.path())).unwrap();
+        for i in 0..4 {
+            std::fs::write(&a, format!("version {i} of a")).unwrap();
+            manager.backup(&a).unwrap();
+        }
+        manager
+            .annotate(&a, FileVersion::new(), |annotation| {
+                annotation.set_pinned(true)
+            })
+            .unwrap();
+        manager
+            .annotate(&a, FileVersion::new() + 1u32, |annotation| {
+                annotation.add_tag(PRE_RESTORE_TAG);
+            })
+            .unwrap();
+        let versions = |manager: &BackupManager| {
+            manager
+                .history(&a)
+                .iter()
+                .map(|meta| meta.version().get())
+                .collect::<Vec<_>>()
+        };
+
+        // the pinned and the pre-restore versions survive `keep=1`
+        let policy = "keep=1".parse().unwrap();
+        assert_eq!(manager.simulate_retention(&policy).removed_versions(), 1);
+        let report = manager.apply_retention(&policy, DryRun::Off).unwrap();
+        assert_eq!(report.removed_versions(), 1);
+        let mut kept = versions(&manager);
+        kept.sort_unstable();
+        assert_eq!(kept, [1, 2, 4]);
+
+        // and evictions
+        manager.update_config(
+            test_config(store.path())
+                .extend_with(&storage_common::MaybeConfig::default().with_max_store_bytes(1)),
+        );
+        assert!(manager
+            .evict_to_cap(DryRun::Off)
+            .unwrap()
+            .evicted()
+            .is_empty());
+        assert_eq!(versions(&manager).len(), 3);
+    }
+
+    #[test]
+    fn dedup_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let a = files.path().join("a.conf");
+        let b = files.path().join("b.conf");
+        let shared = b"the same config in two places";
+        std::fs::write(&a, shared).unwrap();
+        std::fs::write(&b, shared).unwrap();
+
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        let first = manager.backup(&a).unwrap().content().unwrap();
+        assert_eq!(manager.backup(&b).unwrap().content(), Some(first));
+        assert!(manager.backend().contains(&first.name()).unwrap());
+        // two objects and a single content blob
+        assert_eq!(manager.backend().list().unwrap().len(), 3);
+        assert_eq!(manager.stats().deduplicated_bytes(), shared.len() as u64);
+        assert_eq!(manager.stats().original_bytes(), 2 * shared.len() as u64);
+        assert_eq!(
+            manager
+                .read_version_head(&b, FileVersion::new(), 8)
+                .unwrap(),
+            &shared[..8]
+        );
+
+        // the blob outlives the backups of `a`, `b` still refers to it
+        std::fs::write(&a, "edited").unwrap();
+        manager.backup(&a).unwrap();
+        let policy = "keep=1".parse::<RetentionPolicy>().unwrap();
+        manager.apply_retention(&policy, DryRun::Off).unwrap();
+        assert!(manager.backend().contains(&first.name()).unwrap());
+        std::fs::remove_file(&b).unwrap();
+        manager.restore(&b, None, &RestoreOptions::new()).unwrap();
+        assert_eq!(std::fs::read(&b).unwrap(), shared);
+
+        std::fs::write(&b, "different").unwrap();
+        manager.backup(&b).unwrap();
+        manager.apply_retention(&policy, DryRun::Off).unwrap();
+        assert!(!manager.backend().contains(&first.name()).unwrap());
+        assert_eq!(manager.backend().list().unwrap().len(), 4);
+        assert_eq!(manager.stats().deduplicated_bytes(), 0);
+
+        // a reopened store still finds every backup, and only the backups
+        let reopened = BackupManager::new(test_config(store.path())).unwrap();
+        reopened.rebuild_index().unwrap();
+        assert_eq!(reopened.stats().total_backups(), 2);
+        assert_eq!(
+            reopened
+                .read_version(&a, FileVersion::new() + 1u32)
+                .unwrap(),
+            b"edited"
+        );
+    }
+
+    #[test]
+    fn needs_backup_and_retention_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "v1").unwrap();
+
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert!(manager.needs_backup(&path).unwrap());
+        manager.backup(&path).unwrap();
+        assert!(!manager.needs_backup(&path).unwrap());
+        for contents in ["v22", "v333"] {
+            std::fs::write(&path, contents).unwrap();
+            assert!(manager.needs_backup(&path).unwrap());
+            manager.backup(&path).unwrap();
+        }
+
+        let policy = RetentionPolicy::new().with_max_versions(1);
+        let planned = manager.apply_retention(&policy, DryRun::On).unwrap();
+        assert_eq!(planned.plan().deleted_backups(), 2);
+        assert_eq!(manager.stats().total_backups(), 3);
+        let report = manager.apply_retention(&policy, DryRun::Off).unwrap();
+        assert_eq!(report.plan(), planned.plan());
+        assert_eq!(report.removed_versions(), 2);
+        assert_eq!(manager.stats().total_backups(), 1);
+        let objects = std::fs::read_dir(store.path())
+            .unwrap()
+            .filter(|entry| {
+                entry.as_ref().unwrap().path().extension() == Some(OBJECT_EXTENSION.as_ref())
+            })
+            .count();
+        assert_eq!(objects, 1);
+        assert_eq!(manager.next_version(&path).get(), 4);
+    }
+
+    #[test]
+    fn pending_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let config = test_config(store.path()).extend_with(
+            &storage_common::MaybeConfig::default().with_app_dir(files.path().to_str().unwrap()),
+        );
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "queued").unwrap();
+
+        let manager = BackupManager::new(config.clone()).unwrap();
+        Schedule::pause(
+            &config,
+            Timestamp::new(Timestamp::now().as_secs() + 60 * 60),
+        )
+        .unwrap();
+        manager.queue_backup(&path);
+        manager.queue_backup(&path);
+        assert!(manager.run_pending().unwrap().is_empty());
+        assert_eq!(manager.pending(), [path.clone()]);
+
+        Schedule::resume(&config).unwrap();
+        let results = manager.run_pending().unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_ok());
+        assert!(manager.pending().is_empty());
+    }
+
+    #[test]
+    fn shutdown_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let config = test_config(store.path()).extend_with(
+            &storage_common::MaybeConfig::default().with_app_dir(files.path().to_str().unwrap()),
+        );
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "deferred").unwrap();
+
+        let mut manager = BackupManager::new(config.clone()).unwrap();
+        let shutdown = Shutdown::new();
+        manager.set_shutdown(shutdown.clone());
+        shutdown.request();
+        assert!(manager.shutdown().is_requested());
+
+        // no backup is started after the shutdown, the paths stay queued instead
+        manager.queue_backup(&path);
+        assert!(manager.run_pending().unwrap().is_empty());
+        assert_eq!(manager.pending(), [path.clone()]);
+        assert!(manager.backup_all([&path, &path]).is_empty());
+        assert_eq!(manager.pending(), [path.clone()]);
+
+        manager.flush().unwrap();
+        assert!(config.store_index_path().exists());
+        let manager = BackupManager::new(config).unwrap();
+        assert_eq!(manager.run_pending().unwrap().len(), 0);
+        assert_eq!(manager.backup_all([&path]).len(), 1);
+    }
+
+    #[test]
+    fn sync_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let touched = files.path().join("touched.txt");
+        let rewritten = files.path().join("rewritten.txt");
+        let dir = files.path().join("dir");
+        std::fs::create_dir(&dir).unwrap();
+        let new = dir.join("new.txt");
+        std::fs::write(&touched, "touched").unwrap();
+        std::fs::write(&rewritten, "abc").unwrap();
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        manager.backup_all([&touched, &rewritten]);
+
+        // same contents with a new modification time, and new contents with the old one
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(&touched, "touched").unwrap();
+        let modified = std::fs::metadata(&rewritten).unwrap().modified().unwrap();
+        std::fs::write(&rewritten, "xyz").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&rewritten)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        std::fs::write(&new, "new").unwrap();
+
+        let missing = files.path().join("missing.txt");
+        let paths = [&touched, &rewritten, &dir, &missing];
+        let report = manager.sync(paths, SyncMode::Quick);
+        assert_eq!(report.unchanged(), [touched.clone(), rewritten.clone()]);
+        assert_eq!(report.backed_up().len(), 1);
+        assert_eq!(report.backed_up()[0].0, new);
+        assert_eq!(report.missing(), [missing.clone()]);
+        assert!(report.failed().is_empty());
+
+        let report = manager.sync(paths, SyncMode::Thorough);
+        assert_eq!(report.unchanged(), [touched.clone(), new.clone()]);
+        assert_eq!(report.backed_up().len(), 1);
+        assert_eq!(report.backed_up()[0].0, rewritten);
+        assert_eq!(report.backed_up()[0].1.version().get(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let fifo = files.path().join("pipe");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .expect("failed to run mkfifo");
+        assert!(status.success());
+        let text = files.path().join("text.txt");
+        std::fs::write(&text, "text").unwrap();
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+
+        // nothing writes to the pipe, reading it would block forever
+        let report = manager.sync([files.path()], SyncMode::Quick);
+        assert_eq!(report.skipped(), [(fifo.clone(), FileKind::Fifo)]);
+        assert_eq!(report.backed_up().len(), 1);
+        assert_eq!(report.backed_up()[0].0, text);
+        assert!(report.failed().is_empty());
+
+        let err = manager.backup(&fifo).unwrap_err();
+        assert!(err.to_string().contains("is a named pipe"), "{err}");
+        assert!(manager.latest_backup(&fifo).is_none());
+    }
+
+    #[test]
+    fn update_metadata_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "contents").unwrap();
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert!(matches!(
+            manager.update_metadata(&path).unwrap(),
+            MetadataUpdate::NeedsBackup
+        ));
+        let backed_up = manager.backup(&path).unwrap();
+        assert!(matches!(
+            manager.update_metadata(&path).unwrap(),
+            MetadataUpdate::Unchanged
+        ));
+
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        let MetadataUpdate::Updated(meta) = manager.update_metadata(&path).unwrap() else {
+            panic!("the permissions changed");
+        };
+        assert_eq!(meta.version(), backed_up.version());
+        assert_eq!(meta.id(), backed_up.id());
+        assert_eq!(meta.meta_revision(), 1);
+        assert!(meta.fs_meta().permissions().unwrap().readonly());
+        assert!(!manager.needs_backup(&path).unwrap());
+
+        // the rewritten object is found again with its new metadata
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert_eq!(manager.rebuild_index().unwrap(), 1);
+        let meta = manager.backup_by_id(backed_up.id()).unwrap();
+        assert_eq!(meta.meta_revision(), 1);
+    }
+
+    #[cfg(feature = "xattr")]
+    #[test]
+    fn xattrs_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "contents").unwrap();
+        // not every file system supports user attributes
+        if xattr::set(&path, "user.tag", b"red").is_err() {
+            return;
+        }
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        let meta = manager.backup(&path).unwrap();
+        assert_eq!(meta.fs_meta().xattrs().unwrap()["user.tag"], b"red");
+
+        xattr::set(&path, "user.tag", b"blue").unwrap();
+        xattr::set(&path, "user.other", b"1").unwrap();
+        assert!(!manager.needs_backup(&path).unwrap());
+        let MetadataUpdate::Updated(meta) = manager.update_metadata(&path).unwrap() else {
+            panic!("the attributes changed");
+        };
+        assert_eq!(meta.fs_meta().xattrs().unwrap().len(), 2);
+
+        xattr::remove(&path, "user.other").unwrap();
+        manager.backup(&path).unwrap();
+        xattr::set(&path, "user.tag", b"green").unwrap();
+        xattr::set(&path, "user.other", b"2").unwrap();
+        manager
+            .restore(&path, None, &RestoreOptions::new())
+            .unwrap();
+        assert_eq!(xattr::get(&path, "user.tag").unwrap().unwrap(), b"blue");
+        assert_eq!(xattr::get(&path, "user.other").unwrap(), None);
+    }
+
+    #[test]
+    fn diff_test() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let text = files.path().join("notes.txt");
+        let binary = files.path().join("image.bin");
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        for contents in [
+            "one\ntwo\nthree\n",
+            "one\n2\nthree\nfour\n",
+            "one\n2\nthree\nfour\n",
+        ] {
+            std::fs::write(&text, contents).unwrap();
+            manager.backup(&text).unwrap();
+        }
+        std::fs::write(&binary, [0u8, 1, 2, 3]).unwrap();
+        manager.backup(&binary).unwrap();
+
+        // the latest version against the one before it
+        let diff = manager.diff(&text, None, None).unwrap();
+        assert_eq!((diff.from().get(), diff.to().get()), (2, 3));
+        assert!(diff.is_identical());
+
+        let mut first = FileVersion::new();
+        let diff = manager.diff(&text, Some(first), None).unwrap();
+        let crate::Changes::Text {
+            unified,
+            inserted,
+            deleted,
+        } = diff.changes()
+        else {
+            panic!("expected a text diff");
+        };
+        assert_eq!((*inserted, *deleted), (2, 1));
+        assert!(unified.contains(&format!("--- {} (version 1)", text.display())));
+        assert!(unified.contains("-two\n+2\n"));
+        assert!(unified.contains("+four\n"));
+
+        assert!(manager.diff(&binary, None, None).is_err());
+        std::fs::write(&binary, [0u8, 1, 9]).unwrap();
+        manager.backup(&binary).unwrap();
+        let diff = manager.diff(&binary, None, None).unwrap();
+        assert_eq!(
+            diff.changes(),
+            &crate::Changes::Binary {
+                from_size: 4,
+                to_size: 3,
+                changed_bytes: 2,
+                first_change: Some(2),
+            }
+        );
+
+        first.increment_n(5);
+        assert!(manager.diff(&text, Some(first), None).is_err());
+        assert!(manager
+            .diff(files.path().join("missing"), None, None)
+            .is_err());
+    }
+
+    #[test]
+    fn progress_test() {
+        let contents = vec![42u8; crate::BUFFER_SIZE * 3 + 10];
+        let mut file = create_named_temp_file();
+        file.write_all(&contents)
+            .expect("failed to write to temp file");
+
+        let mut updates: Vec<(u64, u64)> = Vec::new();
+        let backup =
+            BackupFile::create_new_with_progress(file.path(), &mut |p, t| updates.push((p, t)))
+                .unwrap();
+        assert_eq!(updates.len(), 5);
+        assert_eq!(
+            updates.last(),
+            Some(&(contents.len() as u64, contents.len() as u64))
+        );
+
+        let mut last = (0, 1);
+        let compressed = backup
+            .try_compress_with_progress(&mut |p, t| last = (p, t))
+            .unwrap();
+        assert_eq!(last.0, last.1);
+
+        let restored = create_named_temp_file();
+        let mut last = (0, 1);
+        compressed
+            .try_decompress()
+            .unwrap()
+            .restore_to_with_progress(restored.path(), &mut |p, t| last = (p, t))
+            .unwrap();
+        assert_eq!(last, (contents.len() as u64, contents.len() as u64));
+        xstd::assert_bytes_eq!(std::fs::read(restored.path()).unwrap(), contents);
+    }
+
+    #[test]
+    fn lazy_file_data() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let mut file = create_named_temp_file();
+        write!(file, "lazy contents").expect("failed to write to temp file");
+        let object = store.path().join("lazy.bak");
+
+        let mut backup = BackupFile::create_new(file.path()).unwrap();
+        assert!(backup.data().is_loaded());
+        assert!(!backup.unload());
+        backup
+            .clone()
+            .try_compress()
+            .unwrap()
+            .write_to_file(&object)
+            .unwrap();
+
+        let mut opened = BackupFile::open(&object).unwrap();
+        assert_eq!(opened.data(), &FileData::OnDisk(object.clone()));
+        assert_eq!(opened.file_bytes(), None);
+        assert_eq!(opened.meta().id(), backup.meta().id());
+
+        let restored = create_named_temp_file();
+        opened.restore_to(restored.path()).unwrap();
+        assert_eq!(std::fs::read(restored.path()).unwrap(), b"lazy contents");
+        assert!(!opened.data().is_loaded());
+
+        assert_eq!(opened.load().unwrap(), b"lazy contents");
+        assert_eq!(opened.file_bytes(), Some(&b"lazy contents"[..]));
+        assert!(opened.unload());
+        assert_eq!(opened.file_bytes(), None);
+
+        // a different backup written over the object is not mistaken for this one
+        backup.update_backup().unwrap();
+        backup
+            .try_compress()
+            .unwrap()
+            .write_to_file(&object)
+            .unwrap();
+        assert!(opened.load().is_err());
+    }
+
+    #[test]
+    fn detects_corrupted_objects() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let mut file = create_named_temp_file();
+        write!(file, "bits that will rot").expect("failed to write to temp file");
+        let object = store.path().join("rot.bak");
+        let compressed = BackupFile::create_new(file.path())
+            .unwrap()
+            .try_compress()
+            .unwrap();
+        compressed.write_to_file(&object).unwrap();
+        assert!(extract_header_and_meta(&object).is_ok());
+
+        let mut bytes = std::fs::read(&object).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0x01;
+        std::fs::write(&object, &bytes).unwrap();
+
+        let err = extract_header_and_meta(&object).unwrap_err();
+        assert!(
+            matches!(&err, crate::Error::Corrupted { path: Some(path), expected, actual }
+                if path == &object && expected != actual),
+            "{err}"
+        );
+        assert_eq!(err.category(), "corrupted");
+        assert!(matches!(
+            CompressedBackupFile::new(bytes).try_decompress(),
+            Err(crate::Error::Corrupted { path: None, .. })
+        ));
+    }
+
+    #[test]
+    fn rekey_test() {
+        let store = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "secret contents").unwrap();
+        let mut manager = BackupManager::new(test_config(store.path())).unwrap();
+        let meta = manager.backup(&path).unwrap();
+        let id = storage_format::object_name(meta.path(), *meta.version());
+        let key_path = test_config(store.path()).store_key_path();
+
+        // a plain store is encrypted
+        let blobs = manager.backend().list().unwrap().len();
+        let key = StoreKey::with_cost("one", 64, 1).unwrap();
+        assert_eq!(manager.rekey(key).unwrap(), blobs);
+        assert!(std::fs::read(store.path().join(&id))
+            .unwrap()
+            .starts_with(b"SENC"));
+        let version = *meta.version();
+        assert_eq!(
+            manager.read_version(meta.path(), version).unwrap(),
+            b"secret contents"
+        );
+        assert!(StoreKey::unlock(&key_path, "two").is_err());
+
+        // and its passphrase changed
+        manager
+            .rekey(StoreKey::with_cost("two", 64, 1).unwrap())
+            .unwrap();
+        drop(manager);
+        assert!(StoreKey::unlock(&key_path, "one").is_err());
+        let backend =
+            LocalBackend::new(store.path()).with_key(StoreKey::unlock(&key_path, "two").unwrap());
+        let manager =
+            BackupManager::with_backend(test_config(store.path()), backend.clone()).unwrap();
+        assert_eq!(
+            manager.read_version(meta.path(), version).unwrap(),
+            b"secret contents"
+        );
+
+        // a rekey interrupted after saving the key is finished, one interrupted before is undone
+        let contents = backend.get(&id).unwrap();
+        std::fs::write(store.path().join(&id), b"encrypted with the old key").unwrap();
+        backend.put(&crypto::rekeyed_id(&id), &contents).unwrap();
+        let other =
+            LocalBackend::new(store.path()).with_key(StoreKey::with_cost("three", 64, 1).unwrap());
+        other
+            .put(&crypto::rekeyed_id("other.bak"), b"never saved")
+            .unwrap();
+        assert_eq!(crypto::finish_rekey(&backend).unwrap(), 1);
+        assert_eq!(backend.get(&id).unwrap(), contents);
+        assert!(!store.path().join(crypto::rekeyed_id("other.bak")).exists());
+    }
+
+    #[test]
+    fn detects_truncated_objects() {
+        let store = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let path = files.path().join("file.txt");
+        std::fs::write(&path, "cut short").unwrap();
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        let meta = manager.backup(&path).unwrap();
+        let object = store
+            .path()
+            .join(storage_format::object_name(meta.path(), *meta.version()));
+        drop(manager);
+
+        // a new store has a format with checksums, so the scan notices the missing trailer
+        let bytes = std::fs::read(&object).unwrap();
+        std::fs::write(&object, &bytes[..bytes.len() - CHECKSUM_SIZE]).unwrap();
+        std::fs::remove_file(test_config(store.path()).store_index_path()).unwrap();
+        // the index is loaded on first use: queries find nothing, writes report the error
+        let manager = BackupManager::new(test_config(store.path())).unwrap();
+        assert!(manager.history(&path).is_empty());
+        let err = manager.backup(&path).unwrap_err();
+        assert!(err.to_string().contains("has no checksum"), "{err}");
+        assert!(manager.rebuild_index().is_err());
     }
 
     #[test]
@@ -314,7 +3936,7 @@ mod tests {
         );
         let backup = result.unwrap();
         {
-            let file_text = String::from_utf8(backup.file_bytes.clone())
+            let file_text = String::from_utf8(backup.file_bytes().unwrap().to_vec())
                 .expect("failed to create string from file bytes");
             assert_eq!(
                 file_text, FILE_TEXT,
@@ -337,7 +3959,7 @@ mod tests {
             result.unwrap_err()
         );
         let decompressed = result.unwrap();
-        let file_text = String::from_utf8(decompressed.file_bytes)
+        let file_text = String::from_utf8(decompressed.file_bytes().unwrap().to_vec())
             .expect("failed to create string from file bytes");
         assert_eq!(
             file_text, FILE_TEXT,
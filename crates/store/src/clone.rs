@@ -0,0 +1,258 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use xstd::{cast::CastFrom, hash::fnv1a};
+
+use crate::{
+    backup::BackupInfo, content, FileHeader, FileMeta, FileVersion, Result, StorageBackend,
+};
+
+/// The outcome of [`BackupManager::clone_history`](crate::BackupManager::clone_history)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct CloneReport {
+    copied: usize,
+    copied_bytes: u64,
+    skipped: usize,
+    renumbered: Vec<(PathBuf, FileVersion, FileVersion)>,
+}
+
+impl CloneReport {
+    /// Gets the number of objects that were copied into the destination
+    #[must_use]
+    pub fn copied(&self) -> usize {
+        self.copied
+    }
+
+    /// Gets the number of bytes that were copied into the destination
+    #[must_use]
+    pub fn copied_bytes(&self) -> u64 {
+        self.copied_bytes
+    }
+
+    /// Gets the number of objects the destination already had, including objects of a file whose
+    /// contents one of its backups in the destination already holds
+    #[must_use]
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Gets the `(path, source version, copied version)` of every object whose version was
+    /// already taken by a different backup in the destination, and which was copied as the next
+    /// free version of its file instead
+    #[must_use]
+    pub fn renumbered(&self) -> &[(PathBuf, FileVersion, FileVersion)] {
+        &self.renumbered
+    }
+}
+
+/// Copies the object described by `source` out of the `from` backend into the `to` backend of
+/// the store directory `store`, adding it to the `infos` of the destination. An object the
+/// destination already has is skipped, as is one whose contents a backup of the same file in the
+/// destination holds; an object whose version is taken by a different backup of the file is
+/// copied as its next free version instead, like an archive import does. The copy is verified
+/// like [`write_verified`] does. The content blob of the object is copied first, unless the
+/// destination has it already, after the dictionary it was compressed with, if any.
+pub(crate) fn copy_object(
+    source: &BackupInfo,
+    from: &dyn StorageBackend,
+    to: &dyn StorageBackend,
+    store: &Path,
+    infos: &mut Vec<BackupInfo>,
+    report: &mut CloneReport,
+) -> Result {
+    let id = source.object_id()?;
+    let mut target = id.to_string();
+    let mut bytes = from.get(id)?;
+    if to.contains(id)? {
+        if fnv1a(&to.get(id)?) == fnv1a(&bytes)
+            || has_content(to, infos, &source.meta, &content::read(from, source)?)?
+        {
+            report.skipped += 1;
+            return Ok(());
+        }
+        let (_, mut meta, data) = storage_format::decode(&bytes)?;
+        (bytes, target) = renumber(&mut meta, &data, infos)?;
+        report
+            .renumbered
+            .push((meta.path().clone(), *source.meta.version(), *meta.version()));
+    }
+
+    if let Some(content) = source.meta.content() {
+        let name = content.name();
+        if !to.contains(&name)? {
+            let blob = from.get(&name)?;
+            if let Some(id) = storage_format::content_dictionary(&blob) {
+                copy_dictionary(id, from, to)?;
+            }
+            to.put(&name, &blob)?;
+            if let Err(err) = verify(to, &name, fnv1a(&blob)) {
+                let _ = to.delete(&name);
+                return Err(err);
+            }
+        }
+    }
+
+    let info = write_verified(&bytes, to, &target, store)?;
+    report.copied += 1;
+    report.copied_bytes += info.backup_size;
+    infos.push(info);
+    Ok(())
+}
+
+/// Checks whether any of the `infos` of the file described by `meta` holds exactly `contents`,
+/// so copying the same objects twice does not renumber the conflicting ones again
+pub(crate) fn has_content(
+    backend: &dyn StorageBackend,
+    infos: &[BackupInfo],
+    meta: &FileMeta,
+    contents: &[u8],
+) -> Result<bool> {
+    let hash = fnv1a(contents);
+    for info in infos.iter().filter(|info| {
+        info.meta.path() == meta.path() && info.file_size() == u64::cast_from(contents.len())
+    }) {
+        if fnv1a(&content::read(backend, info)?) == hash {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Moves the object described by `meta` holding `data` to the version after the latest of its
+/// file in `infos`, returning the re-encoded object and its new name
+pub(crate) fn renumber(
+    meta: &mut FileMeta,
+    data: &[u8],
+    infos: &[BackupInfo],
+) -> Result<(Vec<u8>, String)> {
+    let version = infos
+        .iter()
+        .filter(|info| info.meta.path() == meta.path())
+        .map(|info| *info.meta.version())
+        .max()
+        .map_or_else(FileVersion::new, |version| version + 1u32);
+    meta.set_version(version);
+    let header = FileHeader::new(storage_format::encode_meta(meta)?.len(), data.len());
+    let bytes = storage_format::encode(&header, meta, data)?;
+    Ok((bytes, storage_format::object_name(meta.path(), version)))
+}
+
+/// Copies the dictionary `id` from `from` to `to`, unless the destination has it already
+fn copy_dictionary(id: u64, from: &dyn StorageBackend, to: &dyn StorageBackend) -> Result {
+    let name = storage_format::dictionary_name(id);
+    if to.contains(&name)? {
+        return Ok(());
+    }
+    let dictionary = content::load_dictionary(from, id)?;
+    to.put(&name, dictionary.bytes())?;
+    if let Err(err) = content::load_dictionary(to, id) {
+        let _ = to.delete(&name);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// The extension of the blob an object is written to before it replaces the object, never listed
+/// by a [`LocalBackend`](crate::LocalBackend)
+const STAGED_EXTENSION: &str = "staged";
+
+/// Writes the object `bytes` as the blob `id` of `backend`, recording it in the store directory
+/// `store`. The object is decoded before it is written, then written next to `id` and read back,
+/// and only replaces the blob `id` once it hashes to the same bytes. A blob that does not is
+/// deleted again, leaving any previous blob `id` untouched.
+pub(crate) fn write_verified(
+    bytes: &[u8],
+    backend: &dyn StorageBackend,
+    id: &str,
+    store: &Path,
+) -> Result<BackupInfo> {
+    let (header, meta, _) = storage_format::decode(bytes)?;
+    let staged = format!("{id}.{STAGED_EXTENSION}");
+    backend.put(&staged, bytes)?;
+    if let Err(err) = verify(backend, &staged, fnv1a(bytes)) {
+        // don't leave a corrupt object behind, the error being returned is the useful one
+        let _ = backend.delete(&staged);
+        return Err(err);
+    }
+    if let Err(err) = backend.rename(&staged, id) {
+        let _ = backend.delete(&staged);
+        return Err(err);
+    }
+
+    Ok(BackupInfo {
+        header,
+        meta,
+        backup_path: store.join(id),
+        backup_size: u64::cast_from(bytes.len()),
+    })
+}
+
+/// Checks that the blob `id` of `backend` hashes to `hash`
+fn verify(backend: &dyn StorageBackend, id: &str, hash: u64) -> Result {
+    if fnv1a(&backend.get(id)?) != hash {
+        return Err(format!("hash mismatch after writing '{id}'").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileHeader, FileMeta, FileVersion, FsMetadata, MemoryBackend, Timestamp};
+
+    /// A [`MemoryBackend`] that flips a byte of every staged blob it is asked to keep
+    #[derive(Debug, Default)]
+    struct Corrupting(MemoryBackend);
+
+    impl StorageBackend for Corrupting {
+        fn put(&self, id: &str, bytes: &[u8]) -> Result {
+            let mut bytes = bytes.to_vec();
+            if id.ends_with(STAGED_EXTENSION) {
+                bytes[0] ^= 0xff;
+            }
+            self.0.put(id, &bytes)
+        }
+
+        fn get(&self, id: &str) -> Result<Vec<u8>> {
+            self.0.get(id)
+        }
+
+        fn list(&self) -> Result<Vec<crate::BlobEntry>> {
+            self.0.list()
+        }
+
+        fn delete(&self, id: &str) -> Result {
+            self.0.delete(id)
+        }
+    }
+
+    #[test]
+    fn keeps_the_original_object() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let meta = FileMeta::new(
+            FileVersion::new(),
+            Timestamp::new(1),
+            temp.path().to_path_buf(),
+            FsMetadata::from_path(temp.path()).unwrap(),
+        );
+        let header = FileHeader::new(storage_format::encode_meta(&meta).unwrap().len(), 8);
+        let object = storage_format::encode(&header, &meta, b"contents").unwrap();
+        let id = "object.bak";
+
+        let backend = MemoryBackend::new();
+        write_verified(&object, &backend, id, Path::new("store")).unwrap();
+        assert_eq!(backend.get(id).unwrap(), object);
+        assert_eq!(backend.list().unwrap().len(), 1);
+
+        let corrupting = Corrupting(backend);
+        corrupting.put(id, b"the only copy").unwrap();
+        assert!(write_verified(&object, &corrupting, id, Path::new("store")).is_err());
+        assert_eq!(corrupting.get(id).unwrap(), b"the only copy");
+        assert_eq!(corrupting.list().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Content blobs, the contents of backups stored once per distinct content. The
+//! [`CompressStage`](crate::CompressStage) moves the contents of a backup into the blob named
+//! after their hash, and the object only keeps a [`ContentRef`] to it. Copies of the same file are
+//! stored once that way, however many paths or versions they appear under.
+//!
+//! Blobs are not counted anywhere but in the index: the backups referring to a blob are its
+//! references, and a blob is deleted once the last of them is removed.
+//!
+//! Small contents may be compressed with the shared [`Dictionary`] of the store, see
+//! [`BackupManager::train_dictionary`](crate::BackupManager::train_dictionary). A blob names the
+//! dictionary it needs, which is kept in the backend next to the blobs and never deleted, so
+//! older blobs stay readable after a new dictionary has been trained.
+
+use std::{collections::HashMap, io::ErrorKind};
+
+use xstd::cast::CastFrom;
+
+use crate::{
+    backup::BackupInfo, Config, ContentRef, Dictionary, FileHeader, FileMeta, Result,
+    StorageBackend,
+};
+
+/// Gets the contents of an object that was decoded into `meta` and `bytes`, reading them from
+/// the content blob in `backend` that `meta` refers to, if any
+///
+/// ## Errors
+/// - Returns an error if the content blob cannot be read or decoded
+/// - Returns an error if the content blob does not hold as many bytes as `meta` says
+pub(crate) fn resolve(
+    backend: &dyn StorageBackend,
+    meta: &FileMeta,
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let Some(content) = meta.content() else {
+        return Ok(bytes);
+    };
+    let name = content.name();
+    let contents = decode_blob(backend, &backend.get(&name)?)?;
+    if u64::cast_from(contents.len()) != content.size() {
+        return Err(format!(
+            "content blob '{name}' holds {} byte(s), expected {}",
+            contents.len(),
+            content.size()
+        )
+        .into());
+    }
+    Ok(contents)
+}
+
+/// Decodes the store object `object`, moving contents kept in a content blob of `backend` back
+/// into it, as if the object held them itself
+///
+/// ## Errors
+/// - Returns an error if the object or its content blob cannot be decoded
+/// - Returns an error if the content blob cannot be read
+pub(crate) fn decode(
+    backend: &dyn StorageBackend,
+    object: &[u8],
+) -> Result<(FileHeader, FileMeta, Vec<u8>)> {
+    let (header, meta, bytes) = storage_format::decode(object)?;
+    if meta.content().is_none() {
+        return Ok((header, meta, bytes));
+    }
+    let bytes = resolve(backend, &meta, bytes)?;
+    let (header, meta) = inline(meta, bytes.len())?;
+    Ok((header, meta, bytes))
+}
+
+/// Gets the header and metadata an object holding its `size` bytes of contents itself would have,
+/// for the metadata `meta` of an object keeping them in a content blob
+///
+/// ## Errors
+/// - Returns an error if the metadata cannot be serialized
+pub(crate) fn inline(mut meta: FileMeta, size: usize) -> Result<(FileHeader, FileMeta)> {
+    meta.take_content();
+    Ok((
+        FileHeader::new(storage_format::encode_meta(&meta)?.len(), size),
+        meta,
+    ))
+}
+
+/// Reads the contents of the backup `info` out of `backend`
+///
+/// ## Errors
+/// - Returns an error if the object or its content blob cannot be read or decoded
+pub(crate) fn read(backend: &dyn StorageBackend, info: &BackupInfo) -> Result<Vec<u8>> {
+    let (_, meta, bytes) = storage_format::decode(&backend.get(info.object_id()?)?)
+        .map_err(|e| e.with_path(&info.backup_path))?;
+    resolve(backend, &meta, bytes).map_err(|e| e.with_path(&info.backup_path))
+}
+
+/// Reads up to `max_bytes` of the contents of the backup `info` out of `backend`, only
+/// decompressing as much of the object or its content blob as needed
+///
+/// ## Errors
+/// - Returns an error if the object or its content blob cannot be read or decoded
+pub(crate) fn read_head(
+    backend: &dyn StorageBackend,
+    info: &BackupInfo,
+    max_bytes: usize,
+) -> Result<Vec<u8>> {
+    let object = backend.get(info.object_id()?)?;
+    let (_, meta, bytes) = storage_format::decode_head(&object, max_bytes)
+        .map_err(|e| e.with_path(&info.backup_path))?;
+    let Some(content) = meta.content() else {
+        return Ok(bytes);
+    };
+    let blob = backend.get(&content.name())?;
+    let dictionary = blob_dictionary(backend, &blob)?;
+    storage_format::decode_content_head_with(&blob, max_bytes, dictionary.as_ref())
+        .map_err(|e| e.with_path(&info.backup_path))
+}
+
+/// Decodes the content blob `blob`, with the dictionary out of `backend` it was compressed with
+///
+/// ## Errors
+/// - Returns an error if the blob or its dictionary cannot be read or decoded
+pub(crate) fn decode_blob(backend: &dyn StorageBackend, blob: &[u8]) -> Result<Vec<u8>> {
+    let dictionary = blob_dictionary(backend, blob)?;
+    storage_format::decode_content_with(blob, dictionary.as_ref())
+}
+
+/// Loads the dictionary the content blob `blob` was compressed with out of `backend`, `None` if
+/// it was compressed without one
+fn blob_dictionary(backend: &dyn StorageBackend, blob: &[u8]) -> Result<Option<Dictionary>> {
+    storage_format::content_dictionary(blob)
+        .map(|id| load_dictionary(backend, id))
+        .transpose()
+}
+
+/// Loads the shared dictionary of the store of `config` out of `backend`, `None` if no
+/// dictionary has been trained for it
+///
+/// ## Errors
+/// - Returns an error if the id of the dictionary cannot be read
+/// - Returns an error if the dictionary cannot be read or is corrupted
+pub(crate) fn current_dictionary(
+    config: &Config,
+    backend: &dyn StorageBackend,
+) -> Result<Option<Dictionary>> {
+    let path = config.store_dictionary_path();
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let id = u64::from_str_radix(contents.trim(), 16).map_err(|e| {
+        format!(
+            "invalid dictionary id '{}' in '{}' - {e}",
+            contents.trim(),
+            path.display()
+        )
+    })?;
+    load_dictionary(backend, id).map(Some)
+}
+
+/// Saves `dictionary` to `backend` and makes it the shared dictionary of the store of `config`
+///
+/// ## Errors
+/// - Returns an error if the dictionary or its id cannot be written
+pub(crate) fn set_current_dictionary(
+    config: &Config,
+    backend: &dyn StorageBackend,
+    dictionary: &Dictionary,
+) -> Result {
+    backend.put(
+        &storage_format::dictionary_name(dictionary.id()),
+        dictionary.bytes(),
+    )?;
+    Ok(std::fs::write(
+        config.store_dictionary_path(),
+        format!("{:016x}\n", dictionary.id()),
+    )?)
+}
+
+/// Loads the dictionary `id` out of `backend`
+///
+/// ## Errors
+/// - Returns an error if the dictionary cannot be read or is corrupted
+pub(crate) fn load_dictionary(backend: &dyn StorageBackend, id: u64) -> Result<Dictionary> {
+    let name = storage_format::dictionary_name(id);
+    Dictionary::load(id, backend.get(&name)?).map_err(|e| e.with_path(name))
+}
+
+/// Counts the backups in `infos` referring to each content blob, keyed by the name of the blob
+pub(crate) fn references<'a>(
+    infos: impl IntoIterator<Item = &'a BackupInfo>,
+) -> HashMap<String, (ContentRef, usize)> {
+    let mut references = HashMap::<String, (ContentRef, usize)>::new();
+    for content in infos.into_iter().filter_map(|info| info.meta.content()) {
+        references.entry(content.name()).or_insert((content, 0)).1 += 1;
+    }
+    references
+}
+
+/// Deletes the content blobs among `candidates` that none of the backups in `infos` refers to
+/// any more. Blobs that are already gone are skipped.
+///
+/// ## Errors
+/// - Returns an error if a content blob cannot be deleted
+pub(crate) fn remove_unreferenced(
+    backend: &dyn StorageBackend,
+    infos: &[BackupInfo],
+    candidates: impl IntoIterator<Item = ContentRef>,
+) -> Result {
+    let references = references(infos);
+    let mut removed = Vec::new();
+    for content in candidates {
+        let name = content.name();
+        if references.contains_key(&name) || removed.contains(&name) {
+            continue;
+        }
+        tracing::info!(blob = %name, "removing unreferenced content blob");
+        match backend.delete(&name) {
+            Ok(()) => {}
+            Err(e) if e.io_kind() == Some(ErrorKind::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        removed.push(name);
+    }
+    Ok(())
+}
+
+/// Checks whether `id` names a content blob rather than an object
+pub(crate) fn is_content(id: &str) -> bool {
+    std::path::Path::new(id)
+        .extension()
+        .is_some_and(|extension| extension == crate::CONTENT_EXTENSION)
+}
+
+/// Checks whether `id` names a dictionary rather than an object
+pub(crate) fn is_dictionary(id: &str) -> bool {
+    std::path::Path::new(id)
+        .extension()
+        .is_some_and(|extension| extension == crate::DICTIONARY_EXTENSION)
+}
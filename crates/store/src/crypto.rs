@@ -0,0 +1,351 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Encrypting the blobs of a store with a key derived from a passphrase, see [`StoreKey`] and
+//! [`BackupManager::rekey`](crate::BackupManager::rekey).
+//!
+//! The key of a store is derived from its passphrase and a random salt with `Argon2id`. The salt
+//! and the cost of the derivation are kept in [`Config::store_key_path`] together with a
+//! key-check value, so a wrong passphrase fails as soon as the store is opened instead of on the
+//! first object read. Every blob is encrypted with `XChaCha20-Poly1305` under a key of its own,
+//! derived from the store key and the random nonce in front of the blob.
+//!
+//! Only the blobs are encrypted: the index, the annotations and the snapshots of a store stay
+//! readable, and so do the paths of the backed up files they hold.
+
+use std::{
+    fmt,
+    fmt::Write,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lock::{StoreLock, DEFAULT_LOCK_TIMEOUT},
+    Config, Error, LocalBackend, Result, StorageBackend,
+};
+
+/// The environment variable holding the passphrase of an encrypted store
+pub const PASSPHRASE_ENV: &str = "STORAGE_PASSPHRASE";
+/// Marks an encrypted blob, followed by the nonce and the encrypted bytes
+const ENCRYPTED_MAGIC: &[u8; 4] = b"SENC";
+/// The size of the nonce of an encrypted blob
+const NONCE_SIZE: usize = 24;
+/// The size of the salt the key of a store is derived with
+const SALT_SIZE: usize = 16;
+/// The extension of the copies of the blobs re-encrypted by a
+/// [`BackupManager::rekey`](crate::BackupManager::rekey) that did not replace the blobs yet
+pub(crate) const REKEYED_EXTENSION: &str = "rekeyed";
+
+/// What is stored in [`Config::store_key_path`]: everything needed to derive the key of the
+/// store again, except for the passphrase
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct KeyFile {
+    /// The salt of the key derivation, hex encoded
+    salt: String,
+    /// The memory cost of the key derivation in KiB
+    memory_kib: u32,
+    /// The number of passes of the key derivation
+    iterations: u32,
+    /// The key-check value of the key, hex encoded
+    check: String,
+}
+
+/// The key the blobs of an encrypted store are encrypted with, derived from its passphrase
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct StoreKey {
+    key: [u8; 32],
+    file: KeyFile,
+}
+
+impl fmt::Debug for StoreKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoreKey")
+            .field("check", &self.file.check)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StoreKey {
+    /// Derives a new key from `passphrase` and a random salt, with the default cost of
+    /// `Argon2id` (19 MiB, 2 passes)
+    ///
+    /// ## Errors
+    /// - Returns an error if the key cannot be derived
+    pub fn new(passphrase: &str) -> Result<Self> {
+        Self::with_cost(passphrase, Params::DEFAULT_M_COST, Params::DEFAULT_T_COST)
+    }
+
+    /// Same as [`StoreKey::new`], deriving the key with `memory_kib` KiB of memory and
+    /// `iterations` passes
+    ///
+    /// ## Errors
+    /// - Returns an error if the cost is out of the range `Argon2id` allows
+    pub fn with_cost(passphrase: &str, memory_kib: u32, iterations: u32) -> Result<Self> {
+        let mut salt = [0; SALT_SIZE];
+        OsRng
+            .try_fill_bytes(&mut salt)
+            .map_err(|e| format!("unable to generate a salt - {e}"))?;
+        let file = KeyFile {
+            salt: to_hex(&salt),
+            memory_kib,
+            iterations,
+            check: String::new(),
+        };
+        let key = derive(passphrase, &file)?;
+        let check = to_hex(&key_check(&key));
+        Ok(Self {
+            key,
+            file: KeyFile { check, ..file },
+        })
+    }
+
+    /// Derives the key of the store from `passphrase` with the salt and cost saved at `path`
+    ///
+    /// ## Errors
+    /// - Returns an error if the key file cannot be read or is invalid
+    /// - Returns an error if `passphrase` is not the passphrase of the store
+    pub fn unlock(path: &Path, passphrase: &str) -> Result<Self> {
+        let file: KeyFile = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| Error::Serde(format!("invalid key file '{}' - {e}", path.display())))?;
+        let key = derive(passphrase, &file)?;
+        if to_hex(&key_check(&key)) != file.check {
+            return Err("wrong passphrase for the encrypted store".into());
+        }
+        Ok(Self { key, file })
+    }
+
+    /// Unlocks the key of the store of `config` with the passphrase in [`PASSPHRASE_ENV`],
+    /// `None` if the store is not encrypted
+    ///
+    /// ## Errors
+    /// - Returns an error if the store is encrypted, but [`PASSPHRASE_ENV`] is not set
+    /// - Returns an error if the key cannot be unlocked, see [`StoreKey::unlock`]
+    pub fn for_config(config: &Config) -> Result<Option<Self>> {
+        let path = config.store_key_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let passphrase = std::env::var(PASSPHRASE_ENV).map_err(|_| {
+            format!("the store is encrypted, set {PASSPHRASE_ENV} to its passphrase")
+        })?;
+        Self::unlock(&path, &passphrase).map(Some)
+    }
+
+    /// Saves what is needed to unlock this key again to `path`, replacing the key saved there
+    ///
+    /// ## Errors
+    /// - Returns an error if the key file cannot be written
+    pub fn save(&self, path: &Path) -> Result {
+        let partial = path.with_extension("partial");
+        let json = serde_json::to_vec_pretty(&self.file)
+            .map_err(|e| Error::Serde(format!("unable to serialize the store key - {e}")))?;
+        std::fs::write(&partial, json)?;
+        Ok(std::fs::rename(&partial, path)?)
+    }
+
+    /// Encrypts `bytes` under a key of their own, with a random nonce
+    ///
+    /// ## Errors
+    /// - Returns an error if the bytes cannot be encrypted
+    pub fn encrypt(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = self
+            .cipher(&nonce)
+            .encrypt(&nonce, bytes)
+            .map_err(|_| "unable to encrypt the blob")?;
+        let mut blob = Vec::with_capacity(ENCRYPTED_MAGIC.len() + NONCE_SIZE + encrypted.len());
+        blob.extend_from_slice(ENCRYPTED_MAGIC);
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&encrypted);
+        Ok(blob)
+    }
+
+    /// Decrypts `blob`, which was encrypted by [`StoreKey::encrypt`]
+    ///
+    /// ## Errors
+    /// - Returns an error if `blob` is not encrypted, or not with this key
+    /// - Returns an error if `blob` was changed after it was encrypted
+    pub fn decrypt(&self, blob: &[u8]) -> Result<Vec<u8>> {
+        let Some((nonce, encrypted)) = blob
+            .strip_prefix(ENCRYPTED_MAGIC)
+            .and_then(|rest| rest.split_at_checked(NONCE_SIZE))
+        else {
+            return Err("the blob is not encrypted".into());
+        };
+        let nonce = XNonce::from_slice(nonce);
+        self.cipher(nonce)
+            .decrypt(nonce, encrypted)
+            .map_err(|_| "unable to decrypt the blob, it is corrupted or has another key".into())
+    }
+
+    /// Gets the cipher of the blob with the nonce `nonce`
+    fn cipher(&self, nonce: &XNonce) -> XChaCha20Poly1305 {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        hasher.update(b"blob key");
+        hasher.update(nonce);
+        XChaCha20Poly1305::new(hasher.finalize().as_bytes().into())
+    }
+}
+
+/// Derives the key of `passphrase` with the salt and cost of `file`
+fn derive(passphrase: &str, file: &KeyFile) -> Result<[u8; 32]> {
+    let params = Params::new(file.memory_kib, file.iterations, 1, Some(32))
+        .map_err(|e| format!("invalid key derivation cost - {e}"))?;
+    let mut key = [0; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &from_hex(&file.salt)?, &mut key)
+        .map_err(|e| format!("unable to derive the store key - {e}"))?;
+    Ok(key)
+}
+
+/// Gets the value that tells whether a passphrase derived `key`, without giving the key away
+fn key_check(key: &[u8; 32]) -> [u8; 32] {
+    *blake3::keyed_hash(key, b"key check").as_bytes()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(format!("'{hex}' is not hex encoded").into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("'{hex}' is not hex encoded").into())
+        })
+        .collect()
+}
+
+/// Gets the id of the re-encrypted copy of the blob `id`
+pub(crate) fn rekeyed_id(id: &str) -> String {
+    format!("{id}.{REKEYED_EXTENSION}")
+}
+
+/// Same as [`finish_rekey`] for the store of `config`, taking the store lock if there is
+/// anything to finish
+///
+/// ## Errors
+/// - Returns an error if the store lock cannot be acquired
+/// - Returns an error if the rekey cannot be finished, see [`finish_rekey`]
+pub(crate) fn finish_interrupted_rekey(backend: &LocalBackend, config: &Config) -> Result {
+    if rekeyed_copies(backend)?.is_empty() {
+        return Ok(());
+    }
+    let _lock = StoreLock::acquire(&config.store_lock_path(), DEFAULT_LOCK_TIMEOUT)?;
+    finish_rekey(backend).map(drop)
+}
+
+/// Finishes or undoes an interrupted rekey of the blobs in `backend`, which must only be done
+/// while holding the store lock: the re-encrypted copies the current key reads replace their
+/// originals, since the new key was saved before, the others are removed, since it was not.
+/// Returns the number of blobs replaced.
+///
+/// ## Errors
+/// - Returns an error if the store directory cannot be read
+/// - Returns an error if a copy cannot be moved or removed
+pub(crate) fn finish_rekey(backend: &LocalBackend) -> Result<usize> {
+    let mut finished = 0;
+    for path in rekeyed_copies(backend)? {
+        let (Some(id), Some(original)) = (
+            path.file_name().and_then(|name| name.to_str()),
+            path.file_stem().and_then(|name| name.to_str()),
+        ) else {
+            continue;
+        };
+        if backend.key().is_some() && backend.get(id).is_ok() {
+            backend.rename(id, original)?;
+            finished += 1;
+        } else {
+            remove(&path)?;
+        }
+    }
+    if finished > 0 {
+        tracing::info!(blobs = finished, "finished an interrupted rekey");
+    }
+    Ok(finished)
+}
+
+/// Gets the paths of the re-encrypted copies an interrupted rekey left in `backend`
+fn rekeyed_copies(backend: &LocalBackend) -> Result<Vec<PathBuf>> {
+    let mut copies = Vec::new();
+    for entry in std::fs::read_dir(backend.dir())? {
+        let path = entry?.path();
+        if path.extension() == Some(REKEYED_EXTENSION.as_ref()) {
+            copies.push(path);
+        }
+    }
+    Ok(copies)
+}
+
+fn remove(path: &Path) -> Result {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A key that is cheap to derive, the default cost takes seconds without optimizations
+    fn test_key(passphrase: &str) -> StoreKey {
+        StoreKey::with_cost(passphrase, 64, 1).unwrap()
+    }
+
+    #[test]
+    fn encrypts_blobs() {
+        let key = test_key("correct horse");
+        let blob = key.encrypt(b"secret contents").unwrap();
+        assert!(!blob.windows(6).any(|window| window == b"secret"));
+        assert_eq!(key.decrypt(&blob).unwrap(), b"secret contents");
+        // every blob has a nonce of its own
+        assert_ne!(key.encrypt(b"secret contents").unwrap(), blob);
+
+        let mut tampered = blob.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.decrypt(&tampered).is_err());
+        assert!(test_key("correct horse").decrypt(&blob).is_err());
+        assert!(key.decrypt(b"plain").is_err());
+    }
+
+    #[test]
+    fn unlocks_saved_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.json");
+        let key = test_key("correct horse");
+        key.save(&path).unwrap();
+
+        let unlocked = StoreKey::unlock(&path, "correct horse").unwrap();
+        assert_eq!(unlocked, key);
+        let err = StoreKey::unlock(&path, "battery staple").unwrap_err();
+        assert!(err.to_string().contains("wrong passphrase"), "{err}");
+        assert!(!format!("{key:?}").contains(&to_hex(&key.key)));
+    }
+
+    #[test]
+    fn hex_roundtrip() {
+        assert_eq!(to_hex(&[0, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10").unwrap(), [0, 0xab, 0x10]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
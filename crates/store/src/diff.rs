@@ -0,0 +1,212 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Comparing two backed up versions of a file: a unified diff for text, a byte-level summary for
+//! anything else.
+
+use std::path::{Path, PathBuf};
+
+use similar::{ChangeTag, TextDiff};
+use xstd::cast::CastFrom;
+
+use crate::{backup::BackupInfo, content, FileVersion, Result, StorageBackend};
+
+/// The number of unchanged lines shown around each change of a unified diff
+pub const DIFF_CONTEXT_LINES: usize = 3;
+
+/// How many leading bytes are inspected to tell text from binary contents, like `git` does
+const TEXT_SNIFF_BYTES: usize = 8000;
+
+/// What changed between two versions of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Changes {
+    /// Both versions are text
+    Text {
+        /// The unified diff between the versions, empty if they are identical
+        unified: String,
+        /// The number of lines only in the newer version
+        inserted: usize,
+        /// The number of lines only in the older version
+        deleted: usize,
+    },
+    /// At least one of the versions is binary
+    Binary {
+        /// The size of the older version
+        from_size: u64,
+        /// The size of the newer version
+        to_size: u64,
+        /// The number of bytes that differ, bytes beyond the end of the shorter version count as
+        /// changed
+        changed_bytes: u64,
+        /// The offset of the first byte that differs, if any
+        first_change: Option<u64>,
+    },
+}
+
+/// The comparison of two versions of a file, see
+/// [`BackupManager::diff`](crate::BackupManager::diff)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    path: PathBuf,
+    from: FileVersion,
+    to: FileVersion,
+    changes: Changes,
+}
+
+impl FileDiff {
+    /// Gets the path of the compared file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the older version
+    #[must_use]
+    pub fn from(&self) -> FileVersion {
+        self.from
+    }
+
+    /// Gets the newer version
+    #[must_use]
+    pub fn to(&self) -> FileVersion {
+        self.to
+    }
+
+    /// Gets what changed between the versions
+    #[must_use]
+    pub fn changes(&self) -> &Changes {
+        &self.changes
+    }
+
+    /// Returns true if both versions have the same contents
+    #[must_use]
+    pub fn is_identical(&self) -> bool {
+        match &self.changes {
+            Changes::Text { unified, .. } => unified.is_empty(),
+            Changes::Binary { first_change, .. } => first_change.is_none(),
+        }
+    }
+}
+
+/// Checks whether `bytes` look like text: valid utf-8 without NUL bytes in the first
+/// [`TEXT_SNIFF_BYTES`] bytes
+#[must_use]
+pub fn is_text(bytes: &[u8]) -> bool {
+    as_text(bytes).is_some()
+}
+
+fn as_text(bytes: &[u8]) -> Option<&str> {
+    let sniffed = &bytes[..bytes.len().min(TEXT_SNIFF_BYTES)];
+    if sniffed.contains(&0) {
+        return None;
+    }
+    std::str::from_utf8(bytes).ok()
+}
+
+/// Decompresses the backups `from` and `to` out of `backend` and compares their contents
+pub(crate) fn diff(
+    backend: &dyn StorageBackend,
+    from: &BackupInfo,
+    to: &BackupInfo,
+) -> Result<FileDiff> {
+    let old = contents(backend, from)?;
+    let new = contents(backend, to)?;
+    let changes = match (as_text(&old), as_text(&new)) {
+        (Some(old_text), Some(new_text)) => {
+            text_changes(to.meta.path(), from, to, old_text, new_text)
+        }
+        _ => binary_changes(&old, &new),
+    };
+    Ok(FileDiff {
+        path: to.meta.path().clone(),
+        from: *from.meta.version(),
+        to: *to.meta.version(),
+        changes,
+    })
+}
+
+/// Decompresses the contents of the backup `info` out of `backend`
+pub(crate) fn contents(backend: &dyn StorageBackend, info: &BackupInfo) -> Result<Vec<u8>> {
+    content::read(backend, info)
+}
+
+fn text_changes(path: &Path, from: &BackupInfo, to: &BackupInfo, old: &str, new: &str) -> Changes {
+    let diff = TextDiff::from_lines(old, new);
+    let (mut inserted, mut deleted) = (0, 0);
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => inserted += 1,
+            ChangeTag::Delete => deleted += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    let unified = if inserted + deleted == 0 {
+        String::new()
+    } else {
+        let label =
+            |info: &BackupInfo| format!("{} (version {})", path.display(), info.meta.version());
+        diff.unified_diff()
+            .context_radius(DIFF_CONTEXT_LINES)
+            .header(&label(from), &label(to))
+            .to_string()
+    };
+    Changes::Text {
+        unified,
+        inserted,
+        deleted,
+    }
+}
+
+fn binary_changes(old: &[u8], new: &[u8]) -> Changes {
+    let common = old.len().min(new.len());
+    let differing = old.iter().zip(new).filter(|(a, b)| a != b).count();
+    let first_change = old
+        .iter()
+        .zip(new)
+        .position(|(a, b)| a != b)
+        .or((old.len() != new.len()).then_some(common));
+    Changes::Binary {
+        from_size: u64::cast_from(old.len()),
+        to_size: u64::cast_from(new.len()),
+        changed_bytes: u64::cast_from(differing + old.len().max(new.len()) - common),
+        first_change: first_change.map(u64::cast_from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_text() {
+        assert!(is_text(b"hello\nworld\n"));
+        assert!(is_text("grüße".as_bytes()));
+        assert!(is_text(b""));
+        assert!(!is_text(b"hello\0world"));
+        assert!(!is_text(&[0xff, 0xfe, b'a']));
+    }
+
+    #[test]
+    fn summarizes_binary_changes() {
+        assert_eq!(
+            binary_changes(&[1, 2, 3, 4], &[1, 9, 3]),
+            Changes::Binary {
+                from_size: 4,
+                to_size: 3,
+                changed_bytes: 2,
+                first_change: Some(1),
+            }
+        );
+        let Changes::Binary { first_change, .. } = binary_changes(&[1, 2], &[1, 2, 3]) else {
+            unreachable!()
+        };
+        assert_eq!(first_change, Some(2));
+        let Changes::Binary { first_change, .. } = binary_changes(&[1, 2], &[1, 2]) else {
+            unreachable!()
+        };
+        assert_eq!(first_change, None);
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Keeps a store under its size cap (see [`Config::max_store_bytes`](crate::Config)) by evicting
+//! the oldest versions first. The newest version of a file and its newest version with contents
+//! are never evicted, and neither are pinned backups or the ones kept before a restore (see
+//! [`Annotation::is_protected`](crate::Annotation::is_protected)), so a store may stay over its
+//! cap if every file is down to a single version. A content blob shared by several backups only
+//! frees its space once the last of them is evicted.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{backup::BackupInfo, content, FileVersion, Plan, PlannedChange, Timestamp, UniqueId};
+
+/// A backup that was evicted to keep the store under its size cap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvictedBackup {
+    path: PathBuf,
+    version: FileVersion,
+    id: UniqueId,
+    created: Timestamp,
+    size: u64,
+    backup_path: PathBuf,
+}
+
+impl EvictedBackup {
+    /// Gets the path of the original file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the version that was evicted
+    #[must_use]
+    pub fn version(&self) -> FileVersion {
+        self.version
+    }
+
+    /// Gets the id of the evicted backup
+    #[must_use]
+    pub fn id(&self) -> UniqueId {
+        self.id
+    }
+
+    /// Gets when the evicted backup was created
+    #[must_use]
+    pub fn created(&self) -> Timestamp {
+        self.created
+    }
+
+    /// Gets the (compressed) size of the evicted backup, including its content blob if no other
+    /// backup refers to it
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// The backups evicted to keep the store under its size cap, oldest first
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EvictionReport {
+    evicted: Vec<EvictedBackup>,
+    store_bytes: u64,
+    max_store_bytes: Option<u64>,
+}
+
+impl EvictionReport {
+    /// Gets the evicted backups, oldest first
+    #[must_use]
+    pub fn evicted(&self) -> &[EvictedBackup] {
+        &self.evicted
+    }
+
+    /// Gets the total number of (compressed) bytes evicted
+    #[must_use]
+    pub fn evicted_bytes(&self) -> u64 {
+        self.evicted.iter().map(|backup| backup.size).sum()
+    }
+
+    /// Gets the (compressed) size of the store afterwards
+    #[must_use]
+    pub fn store_bytes(&self) -> u64 {
+        self.store_bytes
+    }
+
+    /// Gets whether the store is still over its cap, because every file is down to its only
+    /// version
+    #[must_use]
+    pub fn is_over_cap(&self) -> bool {
+        self.max_store_bytes
+            .is_some_and(|max_store_bytes| self.store_bytes > max_store_bytes)
+    }
+
+    /// Gets the deletions of the eviction, see
+    /// [`BackupManager::evict_to_cap`](crate::BackupManager::evict_to_cap)
+    #[must_use]
+    pub fn plan(&self) -> Plan {
+        self.evicted
+            .iter()
+            .map(|backup| PlannedChange::DeleteBackup {
+                path: backup.path.clone(),
+                version: backup.version,
+                object: backup.backup_path.clone(),
+                size: backup.size,
+            })
+            .collect()
+    }
+
+    /// Gets the store paths of every evicted backup
+    pub(crate) fn removed_paths(&self) -> impl Iterator<Item = &Path> {
+        self.evicted
+            .iter()
+            .map(|backup| backup.backup_path.as_path())
+    }
+
+    /// Adds the evictions of a later `report`
+    pub(crate) fn merge(&mut self, report: EvictionReport) {
+        self.evicted.extend(report.evicted);
+        self.store_bytes = report.store_bytes;
+        self.max_store_bytes = report.max_store_bytes;
+    }
+}
+
+/// Picks the backups to evict from `infos` so they fit into `max_store_bytes`, oldest first and
+/// never the newest version of a file, its newest version with contents or a `protected` backup
+pub(crate) fn plan(
+    infos: &[BackupInfo],
+    protected: impl Fn(&BackupInfo) -> bool,
+    max_store_bytes: Option<u64>,
+) -> EvictionReport {
+    plan_room(infos, protected, max_store_bytes, 0)
+}
+
+/// Same as [`plan`], leaving room for `incoming` more bytes under `max_store_bytes`, e.g. for
+/// backups that are about to be written
+pub(crate) fn plan_room(
+    infos: &[BackupInfo],
+    protected: impl Fn(&BackupInfo) -> bool,
+    max_store_bytes: Option<u64>,
+    incoming: u64,
+) -> EvictionReport {
+    let mut references = content::references(infos);
+    let mut store_bytes = infos.iter().map(|info| info.backup_size).sum::<u64>()
+        + references
+            .values()
+            .map(|(content, _)| content.stored())
+            .sum::<u64>();
+    let mut evicted = Vec::new();
+    if let Some(max_store_bytes) = max_store_bytes {
+        let mut oldest_first = infos.iter().collect::<Vec<_>>();
+        oldest_first.sort_by_key(|info| (*info.meta.created(), *info.meta.version()));
+        // the newest version and the newest one with contents of every file, later ones win
+        let mut newest = HashMap::<&Path, (&Path, Option<&Path>)>::new();
+        for info in &oldest_first {
+            let entry = newest
+                .entry(info.meta.path())
+                .or_insert((&info.backup_path, None));
+            entry.0 = &info.backup_path;
+            if !info.meta.is_deleted() {
+                entry.1 = Some(&info.backup_path);
+            }
+        }
+
+        for info in oldest_first {
+            if store_bytes.saturating_add(incoming) <= max_store_bytes {
+                break;
+            }
+            let (newest, contents) = newest[info.meta.path().as_path()];
+            if info.backup_path == newest
+                || Some(info.backup_path.as_path()) == contents
+                || protected(info)
+            {
+                continue;
+            }
+            let mut size = info.backup_size;
+            if let Some((content, count)) = info
+                .meta
+                .content()
+                .and_then(|content| references.get_mut(&content.name()))
+            {
+                *count -= 1;
+                if *count == 0 {
+                    size += content.stored();
+                }
+            }
+            store_bytes -= size;
+            evicted.push(EvictedBackup {
+                path: info.meta.path().clone(),
+                version: *info.meta.version(),
+                id: info.id(),
+                created: *info.meta.created(),
+                size,
+                backup_path: info.backup_path.clone(),
+            });
+        }
+    }
+    EvictionReport {
+        evicted,
+        store_bytes,
+        max_store_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContentRef, FileHeader, FileMeta, FsMetadata};
+
+    fn info(fs_meta: &FsMetadata, path: &str, version: u32, created: u64, size: u64) -> BackupInfo {
+        BackupInfo {
+            header: FileHeader::default(),
+            meta: FileMeta::new(
+                FileVersion::new() + (version - 1),
+                Timestamp::new(created),
+                PathBuf::from(path),
+                fs_meta.clone(),
+            ),
+            backup_path: PathBuf::from(format!("{path}.{version}")),
+            backup_size: size,
+        }
+    }
+
+    #[test]
+    fn evicts_oldest_first() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let fs_meta = FsMetadata::from_path(temp.path()).unwrap();
+        let infos = vec![
+            info(&fs_meta, "a", 1, 10, 100),
+            info(&fs_meta, "b", 1, 5, 100),
+            info(&fs_meta, "a", 2, 20, 100),
+            info(&fs_meta, "c", 1, 1, 300),
+            info(&fs_meta, "b", 2, 30, 100),
+        ];
+        assert!(plan(&infos, |_| false, None).evicted().is_empty());
+        assert!(plan(&infos, |_| false, Some(700)).evicted().is_empty());
+
+        // `c` is the oldest but also the only version of its file
+        let report = plan(&infos, |_| false, Some(550));
+        let evicted = report
+            .evicted()
+            .iter()
+            .map(|backup| (backup.path().to_str().unwrap(), backup.version().get()))
+            .collect::<Vec<_>>();
+        assert_eq!(evicted, [("b", 1), ("a", 1)]);
+        assert_eq!(report.evicted_bytes(), 200);
+        assert_eq!(report.store_bytes(), 500);
+        assert!(!report.is_over_cap());
+
+        let report = plan(&infos, |_| false, Some(100));
+        assert_eq!(report.evicted().len(), 2);
+        assert_eq!(report.store_bytes(), 500);
+        assert!(report.is_over_cap());
+
+        // room for a backup that is about to be written is made up front
+        let report = plan_room(&infos, |_| false, Some(700), 50);
+        assert_eq!(report.evicted_bytes(), 100);
+        assert_eq!(report.store_bytes(), 600);
+        assert!(!report.is_over_cap());
+    }
+
+    #[test]
+    fn frees_shared_content_with_its_last_reference() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let fs_meta = FsMetadata::from_path(temp.path()).unwrap();
+        let shared = ContentRef::new(1, 500, 100);
+        let mut infos = vec![
+            info(&fs_meta, "a", 1, 1, 10),
+            info(&fs_meta, "a", 2, 2, 10),
+            info(&fs_meta, "b", 1, 3, 10),
+        ];
+        infos[0].meta.set_content(shared);
+        infos[2].meta.set_content(shared);
+
+        // `b` still refers to the blob of the evicted version
+        let report = plan(&infos, |_| false, Some(125));
+        assert_eq!(report.evicted_bytes(), 10);
+        assert_eq!(report.store_bytes(), 120);
+
+        infos[2].meta.take_content();
+        let report = plan(&infos, |_| false, Some(100));
+        assert_eq!(report.evicted_bytes(), 110);
+        assert_eq!(report.store_bytes(), 20);
+    }
+
+    #[test]
+    fn keeps_contents_behind_tombstones() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let fs_meta = FsMetadata::from_path(temp.path()).unwrap();
+        let mut infos = vec![
+            info(&fs_meta, "a", 1, 1, 100),
+            info(&fs_meta, "a", 2, 2, 100),
+            info(&fs_meta, "a", 3, 3, 10),
+        ];
+        infos[2].meta.mark_deleted();
+
+        // the tombstone is the newest version, but not the last contents
+        let report = plan(&infos, |_| false, Some(0));
+        let evicted = report
+            .evicted()
+            .iter()
+            .map(|backup| backup.version().get())
+            .collect::<Vec<_>>();
+        assert_eq!(evicted, [1]);
+        assert!(report.is_over_cap());
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Collects the content blobs no backup refers to any more, e.g. after a crash between writing a
+//! blob and the object referring to it, or after objects were deleted by hand. The index is
+//! rebuilt from the objects, so objects themselves are never orphaned, and the blobs of pinned
+//! backups are referenced like any other. Dictionaries are kept, blobs compressed with an older
+//! dictionary still need it to be read.
+
+use std::{collections::HashSet, time::Duration};
+
+use crate::{backup::BackupInfo, content, Result, StorageBackend, Timestamp};
+
+/// How old an unreferenced content blob has to be before [`BackupManager::gc`] removes it by
+/// default
+///
+/// [`BackupManager::gc`]: crate::BackupManager::gc
+pub const DEFAULT_GC_GRACE: Duration = Duration::from_hours(1);
+
+/// A content blob no backup refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanedBlob {
+    id: String,
+    size: u64,
+    modified: Option<Timestamp>,
+}
+
+impl OrphanedBlob {
+    /// Gets the id of the blob
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Gets the size of the blob in bytes
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Gets when the blob was last written, if the backend keeps track of it
+    #[must_use]
+    pub fn modified(&self) -> Option<Timestamp> {
+        self.modified
+    }
+}
+
+/// The outcome of [`BackupManager::gc`](crate::BackupManager::gc)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GcReport {
+    removed: Vec<OrphanedBlob>,
+    kept: Vec<OrphanedBlob>,
+}
+
+impl GcReport {
+    /// Gets the unreferenced blobs that were removed, or would be with a dry run
+    #[must_use]
+    pub fn removed(&self) -> &[OrphanedBlob] {
+        &self.removed
+    }
+
+    /// Gets the unreferenced blobs that were kept as they are younger than the grace period or
+    /// the backend does not know their age
+    #[must_use]
+    pub fn kept(&self) -> &[OrphanedBlob] {
+        &self.kept
+    }
+
+    /// Gets the number of bytes reclaimed by removing the unreferenced blobs
+    #[must_use]
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.removed.iter().map(OrphanedBlob::size).sum()
+    }
+}
+
+/// Finds the content blobs of `backend` that none of the backups in `infos` refers to. Blobs
+/// written less than `grace` before `now` are kept, and so are blobs of unknown age, as a backup
+/// may still be writing them. A zero `grace` removes every orphan regardless of its age.
+///
+/// ## Errors
+/// - Returns an error if the blobs cannot be listed or their age cannot be looked up
+pub(crate) fn plan(
+    backend: &dyn StorageBackend,
+    infos: &[BackupInfo],
+    grace: Duration,
+    now: Timestamp,
+) -> Result<GcReport> {
+    let referenced = infos
+        .iter()
+        .filter_map(|info| info.meta.content())
+        .map(|content| content.name())
+        .collect::<HashSet<_>>();
+    let mut report = GcReport::default();
+    for blob in backend.list()? {
+        if !content::is_content(blob.id()) || referenced.contains(blob.id()) {
+            continue;
+        }
+        let modified = backend.modified(blob.id())?;
+        let orphan = OrphanedBlob {
+            id: blob.id().to_string(),
+            size: blob.size(),
+            modified,
+        };
+        let aged = modified.is_some_and(|modified| {
+            now.as_duration().saturating_sub(modified.as_duration()) >= grace
+        });
+        if grace.is_zero() || aged {
+            report.removed.push(orphan);
+        } else {
+            report.kept.push(orphan);
+        }
+    }
+    report.removed.sort_by(|a, b| a.id.cmp(&b.id));
+    report.kept.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BackupManager, Config, DryRun, MemoryBackend};
+
+    #[test]
+    fn collects_orphaned_blobs() {
+        let store = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let kept = files.path().join("kept.txt");
+        let dropped = files.path().join("dropped.txt");
+        std::fs::write(&kept, "still backed up").unwrap();
+        std::fs::write(&dropped, "object deleted by hand").unwrap();
+        let config = Config::new().extend_with(
+            &storage_common::MaybeConfig::default().with_store_dir(store.path().to_str().unwrap()),
+        );
+        let manager = BackupManager::new(config).unwrap();
+        manager.backup(&kept).unwrap();
+        let meta = manager.backup(&dropped).unwrap();
+        let blob = meta.content().unwrap().name();
+        assert!(manager
+            .gc(Duration::ZERO, DryRun::Off)
+            .unwrap()
+            .removed()
+            .is_empty());
+
+        for entry in std::fs::read_dir(store.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|e| e == crate::OBJECT_EXTENSION)
+                && crate::extract_header_and_meta(&path).unwrap().1.path() == &dropped
+            {
+                std::fs::remove_file(path).unwrap();
+            }
+        }
+
+        // too young to be collected
+        let report = manager.gc(DEFAULT_GC_GRACE, DryRun::Off).unwrap();
+        assert!(report.removed().is_empty());
+        assert_eq!(report.kept()[0].id(), blob);
+
+        let report = manager.gc(Duration::ZERO, DryRun::On).unwrap();
+        assert_eq!(report.removed()[0].id(), blob);
+        assert!(report.reclaimed_bytes() > 0);
+        assert!(manager.backend().contains(&blob).unwrap());
+        let report = manager.gc(Duration::ZERO, DryRun::Off).unwrap();
+        assert_eq!(report.removed().len(), 1);
+        assert!(!manager.backend().contains(&blob).unwrap());
+        assert!(manager.read_version(&kept, *meta.version()).is_ok());
+        assert!(manager.history(&dropped).is_empty());
+    }
+
+    #[test]
+    fn collects_aged_blobs_in_memory() {
+        let store = tempfile::tempdir().unwrap();
+        let config = Config::new().extend_with(
+            &storage_common::MaybeConfig::default().with_store_dir(store.path().to_str().unwrap()),
+        );
+        let manager = BackupManager::with_backend(config, MemoryBackend::new()).unwrap();
+        let aged = format!("aged.{}", crate::CONTENT_EXTENSION);
+        let fresh = format!("fresh.{}", crate::CONTENT_EXTENSION);
+        manager.backend().put(&aged, b"left behind").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        manager
+            .backend()
+            .put(&fresh, b"still being written")
+            .unwrap();
+
+        let written = |id: &str| manager.backend().modified(id).unwrap().unwrap();
+        let now = written(&fresh);
+        let grace = now.as_duration() - written(&aged).as_duration();
+        let report = plan(manager.backend(), &[], grace, now).unwrap();
+        assert_eq!(report.removed()[0].id(), aged);
+        assert_eq!(report.kept()[0].id(), fresh);
+
+        let report = manager.gc(Duration::ZERO, DryRun::Off).unwrap();
+        assert_eq!(report.removed().len(), 2);
+        assert!(manager.backend().list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,383 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Fires the hooks of the config (see [`HookConfig`]) when something happens to the store. Hooks
+//! are queued on a worker thread of their own (see [`HookDispatcher`]), where they run one after
+//! the other and are stopped once they exceed their timeout. A failed hook is only logged, the
+//! operation that fired it already succeeded.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use storage_common::{HookAction, HookConfig, HookEvent};
+use xstd::thread::{ThreadPool, ThreadPoolBuilder};
+
+use crate::{FileVersion, Result, Timestamp};
+
+/// How often a running hook command is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+/// The most of a webhook response that is read, only the status line is of interest
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+/// The name of the thread running the hooks
+const HOOK_THREAD_NAME: &str = "storage-hooks";
+/// How many events may wait for their hooks, later ones are dropped until the hooks catch up
+const MAX_QUEUED_EVENTS: usize = 1024;
+
+/// A [`HookEvent`] together with the details of what happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HookPayload {
+    event: HookEvent,
+    store: PathBuf,
+    path: Option<PathBuf>,
+    version: Option<FileVersion>,
+    count: usize,
+    time: Timestamp,
+}
+
+impl HookPayload {
+    /// Creates a new [`HookPayload`] for `event` in the store at `store`, concerning one file
+    pub(crate) fn new(event: HookEvent, store: &Path) -> Self {
+        Self {
+            event,
+            store: store.to_path_buf(),
+            path: None,
+            version: None,
+            count: 1,
+            time: Timestamp::now(),
+        }
+    }
+
+    /// Sets the file and version the event is about
+    pub(crate) fn with_file(self, path: &Path, version: FileVersion) -> Self {
+        Self {
+            path: Some(path.to_path_buf()),
+            version: Some(version),
+            ..self
+        }
+    }
+
+    /// Sets how many backups the event concerns, e.g. the number a prune deleted
+    pub(crate) fn with_count(self, count: usize) -> Self {
+        Self { count, ..self }
+    }
+
+    /// Gets the environment variables describing the event to a hook command
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("STORAGE_HOOK_EVENT", self.event.to_string()),
+            ("STORAGE_HOOK_STORE", self.store.display().to_string()),
+            ("STORAGE_HOOK_COUNT", self.count.to_string()),
+            ("STORAGE_HOOK_TIME", self.time.as_secs().to_string()),
+        ];
+        if let Some(path) = &self.path {
+            vars.push(("STORAGE_HOOK_PATH", path.display().to_string()));
+        }
+        if let Some(version) = self.version {
+            vars.push(("STORAGE_HOOK_VERSION", version.to_string()));
+        }
+        vars
+    }
+
+    /// Gets the JSON body posted to a webhook
+    fn to_json(&self) -> String {
+        serde_json::json!({
+            "event": self.event.as_str(),
+            "store": self.store,
+            "path": self.path,
+            "version": self.version.map(u32::from),
+            "count": self.count,
+            "time": self.time.as_secs(),
+        })
+        .to_string()
+    }
+}
+
+/// Runs hooks on a worker thread of its own, so slow hooks never hold up the operations firing
+/// them. The worker is only spawned once a hook is fired, and dropping the dispatcher waits for
+/// the hooks already queued.
+#[derive(Debug, Default)]
+pub(crate) struct HookDispatcher {
+    pool: Mutex<Option<ThreadPool>>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl HookDispatcher {
+    /// Queues every hook in `hooks` that is fired on the event of `payload`
+    pub(crate) fn dispatch(&self, hooks: &[HookConfig], payload: HookPayload) {
+        let hooks = hooks
+            .iter()
+            .filter(|hook| hook.fires_on(payload.event))
+            .cloned()
+            .collect::<Vec<_>>();
+        if hooks.is_empty() {
+            return;
+        }
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= MAX_QUEUED_EVENTS {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            tracing::warn!(event = %payload.event, "too many hooks are queued, dropping the event");
+            return;
+        }
+
+        let mut pool = self.pool.lock().expect("mutex poisoned");
+        if pool.is_none() {
+            match ThreadPoolBuilder::new()
+                .with_size(1)
+                .with_name(HOOK_THREAD_NAME)
+                .build()
+            {
+                Ok(spawned) => *pool = Some(spawned),
+                Err(e) => {
+                    tracing::warn!(error = %e, "unable to spawn the hook thread, running in place");
+                    drop(pool);
+                    fire(&hooks, &payload);
+                    self.queued.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+            }
+        }
+        let queued = Arc::clone(&self.queued);
+        if let Some(pool) = pool.as_ref() {
+            pool.execute(move || {
+                fire(&hooks, &payload);
+                queued.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+}
+
+/// Runs every hook in `hooks` that is fired on the event of `payload`, logging the ones that fail
+pub(crate) fn fire(hooks: &[HookConfig], payload: &HookPayload) {
+    for hook in hooks.iter().filter(|hook| hook.fires_on(payload.event)) {
+        let started = Instant::now();
+        match run(hook, payload) {
+            Ok(()) => tracing::debug!(
+                hook = hook.name(),
+                event = %payload.event,
+                elapsed_ms = started.elapsed().as_millis(),
+                "ran hook"
+            ),
+            Err(e) => tracing::warn!(
+                hook = hook.name(),
+                event = %payload.event,
+                error = %e,
+                "hook failed"
+            ),
+        }
+    }
+}
+
+/// Runs `hook` for `payload`, failing if it does not succeed within its timeout
+fn run(hook: &HookConfig, payload: &HookPayload) -> Result {
+    match hook.action() {
+        HookAction::Command(command) => run_command(command, &payload.env(), hook.timeout()),
+        HookAction::Webhook(url) => post(url, &payload.to_json(), hook.timeout()),
+    }
+}
+
+/// Creates the command running `command` with the shell of the platform
+fn shell(command: &str) -> Command {
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Runs `command` with `env` set, killing it once it runs longer than `timeout`
+fn run_command(command: &str, env: &[(&str, String)], timeout: Duration) -> Result {
+    let mut child = shell(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("unable to run '{command}' - {e}"))?;
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if status.success() {
+                return Ok(());
+            }
+            return Err(format!("'{command}' exited with {status}").into());
+        }
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            // the command may have exited in the meantime, which is fine
+            let _ = child.kill();
+            child.wait()?;
+            return Err(format!("'{command}' timed out after {}s", timeout.as_secs_f32()).into());
+        }
+        std::thread::sleep(POLL_INTERVAL.min(remaining));
+    }
+}
+
+/// Posts `body` as JSON to the `http://` URL `url`, failing unless it responds with a 2xx status
+/// within `timeout`, which covers connecting, sending the request and reading the response
+fn post(url: &str, body: &str, timeout: Duration) -> Result {
+    let deadline = Instant::now() + timeout;
+    let remaining = || {
+        deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| format!("'{url}' timed out after {}s", timeout.as_secs_f32()))
+    };
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("unsupported url '{url}', expected an http:// url"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let address = if authority.ends_with(']') || !authority.contains(':') {
+        format!("{authority}:80")
+    } else {
+        authority.to_string()
+    };
+    let unreachable = |e: &dyn std::fmt::Display| format!("unable to post to '{url}' - {e}");
+
+    let mut last_error = None;
+    let mut stream = None;
+    for addr in address.to_socket_addrs().map_err(|e| unreachable(&e))? {
+        match TcpStream::connect_timeout(&addr, remaining()?) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let mut stream = match (stream, last_error) {
+        (Some(stream), _) => stream,
+        (None, Some(e)) => return Err(unreachable(&e).into()),
+        (None, None) => return Err(unreachable(&"the host has no address").into()),
+    };
+    stream.set_write_timeout(Some(remaining()?))?;
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: storage/{}\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    )
+    .map_err(|e| unreachable(&e))?;
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    while response.len() < MAX_RESPONSE_BYTES {
+        stream.set_read_timeout(Some(remaining()?))?;
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(unreachable(&e).into()),
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some(_) => Err(format!("'{url}' responded with '{status}'").into()),
+        None => Err(format!("'{url}' sent an invalid response").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn payload() -> HookPayload {
+        HookPayload::new(HookEvent::BackupCreated, Path::new("/store"))
+            .with_file(Path::new("/home/me/notes.txt"), 3.try_into().unwrap())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let hook = |command: String| {
+            HookConfig::new(
+                "test",
+                vec![HookEvent::BackupCreated],
+                HookAction::Command(command),
+            )
+            .with_timeout(Duration::from_millis(500))
+        };
+        let write = hook(format!(
+            "echo \"$STORAGE_HOOK_EVENT $STORAGE_HOOK_PATH $STORAGE_HOOK_VERSION\" > {}",
+            out.display()
+        ));
+        run(&write, &payload()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "backup-created /home/me/notes.txt 3\n"
+        );
+
+        assert!(run(&hook("exit 3".into()), &payload()).is_err());
+        let started = Instant::now();
+        let err = run(&hook("sleep 5".into()), &payload()).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // hooks for other events are not run
+        std::fs::remove_file(&out).unwrap();
+        fire(
+            &[write],
+            &HookPayload::new(HookEvent::PruneExecuted, Path::new("/store")),
+        );
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn posts_webhooks() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/storage", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["204 No Content", "500 Internal Server Error"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                // the request is complete once the JSON body is
+                while !request.ends_with(b"}") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let timeout = Duration::from_secs(5);
+        post(&url, &payload().to_json(), timeout).unwrap();
+        let err = post(&url, &payload().to_json(), timeout).unwrap_err();
+        assert!(err.to_string().contains("500"), "{err}");
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /hooks/storage HTTP/1.1\r\n"));
+        assert!(requests[0].contains(r#""event":"backup-created""#));
+        assert!(requests[0].contains(r#""version":3"#));
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A persisted index of the objects in a store, so opening a store does not have to read the
+//! metadata of every object. The index is only a cache: when a store is opened, objects the index
+//! does not know (or whose size changed) are read, and entries of missing objects are dropped.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use storage_common::ResultExt;
+
+use crate::{backup::BackupInfo, FileHeader, FileMeta, Result};
+
+/// The version of the index layout written by [`save`]
+const INDEX_VERSION: u32 = 1;
+
+/// A single object in the index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    /// The file name of the object, relative to the store so the store can be moved
+    object: String,
+    backup_size: u64,
+    header: FileHeader,
+    meta: FileMeta,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Index {
+    version: u32,
+    entries: Vec<IndexEntry>,
+}
+
+/// Loads the index at `path` for the objects in `store`. A missing index is empty, an index
+/// written by a newer version is ignored.
+///
+/// ## Errors
+/// - Returns an error if the index cannot be read or is corrupt
+pub(crate) fn load(path: &Path, store: &Path) -> Result<Vec<BackupInfo>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).context(format!("unable to read the index '{}'", path.display())),
+    };
+    let index: Index = rmp_serde::from_slice(&bytes)?;
+    if index.version > INDEX_VERSION {
+        tracing::warn!(
+            index = %path.display(),
+            version = index.version,
+            "ignoring store index written by a newer version"
+        );
+        return Ok(vec![]);
+    }
+    Ok(index
+        .entries
+        .into_iter()
+        .map(|entry| BackupInfo {
+            header: entry.header,
+            meta: entry.meta,
+            backup_path: store.join(entry.object),
+            backup_size: entry.backup_size,
+        })
+        .collect())
+}
+
+/// Writes the index of `infos` to `path`, replacing the previous index only once the new one is
+/// complete
+///
+/// ## Errors
+/// - Returns an error if the index cannot be serialized or written
+pub(crate) fn save(path: &Path, infos: &[BackupInfo]) -> Result {
+    let entries = infos
+        .iter()
+        .filter_map(|info| {
+            Some(IndexEntry {
+                object: info.backup_path.file_name()?.to_str()?.to_string(),
+                backup_size: info.backup_size,
+                header: info.header,
+                meta: info.meta.clone(),
+            })
+        })
+        .collect();
+    let bytes = rmp_serde::to_vec_named(&Index {
+        version: INDEX_VERSION,
+        entries,
+    })?;
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, bytes)
+        .and_then(|()| std::fs::rename(&partial, path))
+        .with_context(|| format!("unable to write the index '{}'", path.display()))
+}
@@ -40,16 +40,73 @@
     )
 )]
 
+mod annotations;
+mod archive;
+mod backend;
 mod backup;
-mod header;
-mod meta;
-mod version;
+mod clone;
+mod content;
+mod crypto;
+mod diff;
+mod eviction;
+mod gc;
+mod hooks;
+mod index;
+mod lock;
+mod manifest;
+mod migrate;
+mod pipeline;
+mod plan;
+mod queue;
+mod registry;
+mod restore;
+mod retention;
+mod snapshot;
+mod stats;
+mod symlink;
+mod sync;
+mod vfs;
 
-pub use backup::{extract_header_and_meta, BackupFile, BackupManager, CompressedBackupFile};
-pub use header::FileHeader;
-pub use meta::{FileKind, FileMeta, FsMetadata};
-pub use version::SaturatingFileVersion as FileVersion;
-pub use version::{SaturatingFileVersion, WrappingFileVersion};
+pub use annotations::{Annotation, AnnotationReport};
+pub use archive::ImportReport;
+pub use backend::{BlobEntry, LocalBackend, MemoryBackend, StorageBackend};
+pub use backup::{
+    extract_header_and_meta, BackupFile, BackupManager, CompressedBackupFile, FileData,
+    MetadataUpdate,
+};
+pub use clone::CloneReport;
+pub use crypto::{StoreKey, PASSPHRASE_ENV};
+pub use diff::{is_text, Changes, FileDiff, DIFF_CONTEXT_LINES};
+pub use eviction::{EvictedBackup, EvictionReport};
+pub use gc::{GcReport, OrphanedBlob, DEFAULT_GC_GRACE};
+pub use lock::DEFAULT_LOCK_TIMEOUT;
+pub use manifest::{Manifest, ManifestEntry};
+pub use migrate::{Migration, MigrationReport, MIGRATIONS};
+pub use pipeline::{
+    BackupPipeline, BackupStage, CompressStage, HashStage, PipelineItem, StageOutcome, WriteStage,
+    COMPRESS_STAGE, HASH_STAGE, READ_STAGE, WRITE_STAGE,
+};
+pub use plan::{DryRun, Plan, PlannedChange};
+pub use queue::QueuedBackup;
+pub use registry::{RegisteredStore, StoreRegistry};
+pub use restore::{RestoreOptions, RestoredFile, DEFAULT_RESTORE_RETRIES, PRE_RESTORE_TAG};
+pub use retention::{RetentionGroupReport, RetentionPolicy, RetentionReport};
+pub use snapshot::{Snapshot, SnapshotBuilder, SnapshotId, SnapshotMember};
+pub use stats::StoreStats;
+pub use storage_format::{
+    Compression, ContentRef, Dictionary, FileHeader, FileKind, FileMeta, FileVersion, FsMetadata,
+    Permissions, Saturating, SaturatingFileVersion, StreamOptions, UniqueId, VersionStrategy,
+    Wrapping, WrappingFileVersion,
+};
+pub use sync::{SyncMode, SyncReport};
+pub use vfs::{MemoryFs, RealFs, Vfs};
 
-pub(crate) use storage_common::{Config, Result, Timestamp};
-pub(crate) const BUFFER_SIZE: usize = 4096;
+pub use storage_common::{
+    CompressionConfig, ProgressReport, ProgressSink, QuietHours, Schedule, Shutdown, StageProgress,
+    SymlinkPolicy,
+};
+
+pub(crate) use storage_common::{Config, Error, Result, Timestamp};
+pub(crate) use storage_format::{
+    BUFFER_SIZE, CONTENT_EXTENSION, DICTIONARY_EXTENSION, OBJECT_EXTENSION,
+};
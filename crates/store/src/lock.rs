@@ -0,0 +1,175 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A lock file in the store directory that keeps several processes from writing to the same store
+//! at once. The file is locked with an advisory lock of the operating system, which is released
+//! when the holder closes it or exits, so a crashed process never leaves a stale lock behind. The
+//! file itself stays in place and records the process holding it, along with a generation that
+//! changes every time the lock is released, so a process can tell whether anyone wrote to the
+//! store since it last held the lock.
+
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result, Timestamp};
+
+/// How long [`BackupManager`](crate::BackupManager) waits for the store lock by default
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a held lock is checked again while waiting for it
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The contents of a lock file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct LockOwner {
+    pid: u32,
+    acquired: Timestamp,
+    #[serde(default)]
+    generation: u64,
+}
+
+impl LockOwner {
+    fn read(file: &mut File) -> Option<Self> {
+        let mut bytes = Vec::new();
+        file.rewind().ok()?;
+        file.read_to_end(&mut bytes).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    fn write(&self, file: &mut File) -> Result {
+        let bytes = serde_json::to_vec(self)
+            .map_err(|e| Error::Serde(format!("unable to serialize store lock - {e}")))?;
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// An acquired store lock, which is released when dropped
+#[derive(Debug)]
+pub(crate) struct StoreLock {
+    path: PathBuf,
+    file: File,
+    owner: LockOwner,
+}
+
+impl StoreLock {
+    /// Acquires the lock at `path`, waiting up to `timeout` for another holder to release it
+    ///
+    /// ## Errors
+    /// - Returns an error if the lock is still held after `timeout`
+    /// - Returns an error if the lock file cannot be created, locked or written
+    pub(crate) fn acquire(path: &Path, timeout: Duration) -> Result<Self> {
+        let started = Instant::now();
+        loop {
+            match Self::try_acquire(path)? {
+                Ok(lock) => return Ok(lock),
+                Err(owner) if started.elapsed() >= timeout => {
+                    return Err(match owner {
+                        Some(owner) => format!(
+                            "store is locked by process {} since {}",
+                            owner.pid, owner.acquired
+                        ),
+                        None => format!("store is locked by '{}'", path.display()),
+                    }
+                    .into());
+                }
+                Err(_) => std::thread::sleep(RETRY_INTERVAL),
+            }
+        }
+    }
+
+    /// Tries to acquire the lock once, returning the current owner (if it can be read) when the
+    /// lock is held by someone else
+    fn try_acquire(path: &Path) -> Result<Result<Self, Option<LockOwner>>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(Err(LockOwner::read(&mut file))),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        let generation = LockOwner::read(&mut file).map_or(0, |owner| owner.generation);
+        let owner = LockOwner {
+            pid: std::process::id(),
+            acquired: Timestamp::now(),
+            generation,
+        };
+        owner.write(&mut file)?;
+        Ok(Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            owner,
+        }))
+    }
+
+    /// Gets the generation of the store when the lock was acquired. Releasing the lock moves the
+    /// store to the next generation.
+    pub(crate) fn generation(&self) -> u64 {
+        self.owner.generation
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        let released = LockOwner {
+            generation: self.owner.generation.wrapping_add(1),
+            ..self.owner
+        };
+        // closing the file releases the lock, whether or not the generation could be written
+        if let Err(e) = released.write(&mut self.file) {
+            tracing::warn!(lock = %self.path.display(), error = %e, "unable to update store lock");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.lock");
+
+        let lock = StoreLock::acquire(&path, Duration::ZERO).unwrap();
+        assert!(path.exists());
+        assert_eq!(lock.generation(), 0);
+        let err = StoreLock::acquire(&path, Duration::from_millis(100)).unwrap_err();
+        if cfg!(unix) {
+            assert!(err.to_string().contains("locked by process"), "{err}");
+        }
+        drop(lock);
+        let lock = StoreLock::acquire(&path, Duration::ZERO).unwrap();
+        assert_eq!(lock.generation(), 1);
+        drop(lock);
+
+        // a lock file left behind by a process that no longer exists does not hold the lock
+        let dead = LockOwner {
+            pid: u32::MAX,
+            acquired: Timestamp::now(),
+            generation: 7,
+        };
+        std::fs::write(&path, serde_json::to_vec(&dead).unwrap()).unwrap();
+        let lock = StoreLock::acquire(&path, Duration::ZERO).unwrap();
+        assert_eq!(lock.generation(), 7);
+        let owner: LockOwner = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(owner.pid, std::process::id());
+        drop(lock);
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    annotations, backup::BackupInfo, Compression, FileVersion, Result, StorageBackend, Timestamp,
+    UniqueId,
+};
+
+/// An inventory of every backup in a store, ordered by path and version, e.g. to audit a store
+/// or to compare two stores with an external tool
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Collects the manifest of the given backups, reading the contents of those that are not
+    /// kept in a content blob to hash them
+    pub(crate) fn collect(backend: &dyn StorageBackend, infos: &[BackupInfo]) -> Result<Self> {
+        let mut entries = infos
+            .iter()
+            .map(|info| ManifestEntry::collect(backend, info))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by(|a, b| (&a.path, a.version).cmp(&(&b.path, b.version)));
+        Ok(Self { entries })
+    }
+
+    /// Gets every backup of the store, ordered by path and version
+    #[must_use]
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// Gets the number of backups in the manifest
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the store holds no backups
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A single backup of a [`Manifest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    path: PathBuf,
+    version: FileVersion,
+    id: UniqueId,
+    created: Timestamp,
+    modified: Option<Timestamp>,
+    hash: Option<u64>,
+    size: u64,
+    stored_size: u64,
+    compression: Compression,
+}
+
+impl ManifestEntry {
+    fn collect(backend: &dyn StorageBackend, info: &BackupInfo) -> Result<Self> {
+        let meta = &info.meta;
+        let hash = if meta.is_deleted() {
+            None
+        } else {
+            Some(annotations::content_hash(backend, info)?)
+        };
+        Ok(Self {
+            path: meta.path().clone(),
+            version: *meta.version(),
+            id: meta.id(),
+            created: *meta.created(),
+            modified: meta.fs_meta().modified(),
+            hash,
+            size: info.file_size(),
+            stored_size: info.backup_size,
+            compression: meta.compression(),
+        })
+    }
+
+    /// Gets the path of the backed up file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the version of the backup
+    #[must_use]
+    pub fn version(&self) -> FileVersion {
+        self.version
+    }
+
+    /// Gets the id of the backup
+    #[must_use]
+    pub fn id(&self) -> UniqueId {
+        self.id
+    }
+
+    /// Gets when the backup was created
+    #[must_use]
+    pub fn created(&self) -> Timestamp {
+        self.created
+    }
+
+    /// Gets when the file was last modified before it was backed up, if known
+    #[must_use]
+    pub fn modified(&self) -> Option<Timestamp> {
+        self.modified
+    }
+
+    /// Gets the FNV-1a hash of the original contents, `None` for a version recording the
+    /// deletion of the file
+    #[must_use]
+    pub fn hash(&self) -> Option<u64> {
+        self.hash
+    }
+
+    /// Gets the size of the original contents
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Gets the size of the backup in the store, without a content blob it shares with others
+    #[must_use]
+    pub fn stored_size(&self) -> u64 {
+        self.stored_size
+    }
+
+    /// Gets how the backup is compressed
+    #[must_use]
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns true if this version records the deletion of the file
+    #[must_use]
+    pub fn is_deleted(&self) -> bool {
+        self.hash.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BackupManager, Config, MemoryBackend, MemoryFs, Vfs};
+    use xstd::hash::fnv1a;
+
+    #[test]
+    fn lists_every_backup() {
+        let store = tempfile::tempdir().unwrap();
+        let config = Config::new().extend_with(
+            &storage_common::MaybeConfig::default()
+                .with_store_dir(store.path().to_str().unwrap())
+                .with_tracking_list(store.path().join("tracking.json").to_str().unwrap()),
+        );
+        let notes = Path::new("/home/me/notes.txt");
+        let todo = Path::new("/home/me/a/todo.txt");
+        let mut manager = BackupManager::with_backend(config, MemoryBackend::default()).unwrap();
+        manager.set_vfs(
+            MemoryFs::new()
+                .with_file(notes, "v1")
+                .with_file(todo, "todo"),
+        );
+        assert!(manager.manifest().unwrap().is_empty());
+
+        manager.backup(notes).unwrap();
+        manager.vfs().write(notes, b"version 2").unwrap();
+        manager.backup(notes).unwrap();
+        manager.backup(todo).unwrap();
+        manager.vfs().remove_file(notes).unwrap();
+        manager.record_deletion(notes).unwrap();
+
+        let manifest = manager.manifest().unwrap();
+        let entries = manifest
+            .entries()
+            .iter()
+            .map(|entry| {
+                (
+                    entry.path(),
+                    entry.version().get(),
+                    entry.hash(),
+                    entry.size(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                (todo, 1, Some(fnv1a(b"todo")), 4),
+                (notes, 1, Some(fnv1a(b"v1")), 2),
+                (notes, 2, Some(fnv1a(b"version 2")), 9),
+                (notes, 3, None, 0),
+            ]
+        );
+        assert!(manifest.entries()[3].is_deleted());
+        assert_eq!(manifest.len(), 4);
+    }
+}
@@ -0,0 +1,453 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Upgrading the objects of a store to the current [`FORMAT_VERSION`], see
+//! [`BackupManager::migrate`](crate::BackupManager::migrate). Old objects stay readable without a
+//! migration, but only a migrated object gains what newer formats added, e.g. a stored id or a
+//! checksum.
+//!
+//! The format of a store is recorded in [`Config::store_format_path`]. A store without the file
+//! predates it, its format is detected from its objects when it is opened and recorded, see
+//! [`detect_format`]. While a migration runs, the original of every object is copied into
+//! [`Config::store_migration_path`] before the object is rewritten, and every migrated object is
+//! journaled there, so an interrupted migration picks up where it stopped, or is rolled back with
+//! [`BackupManager::rollback_migration`](crate::BackupManager::rollback_migration). Both are
+//! removed once every object is migrated.
+
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use storage_format::{
+    FileHeader, FileMeta, CHECKSUM_SIZE, FORMAT_VERSION, MIN_READABLE_FORMAT_VERSION,
+};
+use xstd::fs::create_write_append;
+
+use crate::{backup::BackupInfo, Config, DryRun, Result, StorageBackend};
+
+/// The name of the journal of migrated objects inside the migration directory
+const JOURNAL_NAME: &str = "journal";
+
+/// The extension of an original that is still being copied into the migration directory
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// A single step upgrading the objects of a store to a newer format
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    to: u32,
+    description: &'static str,
+    apply: fn(&mut FileMeta),
+}
+
+impl Migration {
+    /// Gets the format this migration upgrades to
+    #[must_use]
+    pub fn to(&self) -> u32 {
+        self.to
+    }
+
+    /// Gets what this migration changes
+    #[must_use]
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+}
+
+/// Every migration, ordered by the format they upgrade to. Formats that only added something
+/// new backups may carry need no migration. Every object is written in the current format when it
+/// is migrated, which is all the format `6` migration needs.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 4,
+        description: "store the id of every backup",
+        apply: store_id,
+    },
+    Migration {
+        to: 5,
+        description: "record the codec of every object",
+        apply: record_compression,
+    },
+    Migration {
+        to: 6,
+        description: "append a checksum to every object",
+        apply: |_| {},
+    },
+];
+
+fn store_id(meta: &mut FileMeta) {
+    meta.set_id(meta.id());
+}
+
+fn record_compression(meta: &mut FileMeta) {
+    meta.set_compression(meta.compression());
+}
+
+/// Gets the migrations upgrading a store of format `from` to the current format
+pub(crate) fn pending(from: u32) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(move |migration| migration.to > from)
+}
+
+/// The outcome of [`BackupManager::migrate`](crate::BackupManager::migrate)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationReport {
+    from: u32,
+    migrated: usize,
+    resumed: usize,
+    dry_run: DryRun,
+}
+
+impl MigrationReport {
+    pub(crate) fn new(from: u32, dry_run: DryRun) -> Self {
+        Self {
+            from,
+            migrated: 0,
+            resumed: 0,
+            dry_run,
+        }
+    }
+
+    /// Gets the format of the store before the migration
+    #[must_use]
+    pub fn from(&self) -> u32 {
+        self.from
+    }
+
+    /// Gets the format of the store after the migration, [`FORMAT_VERSION`] unless it was a dry run
+    #[must_use]
+    pub fn to(&self) -> u32 {
+        if self.dry_run.is_on() {
+            self.from
+        } else {
+            FORMAT_VERSION.max(self.from)
+        }
+    }
+
+    /// Gets the migrations applied to every object, or that would be with a dry run
+    pub fn migrations(&self) -> impl Iterator<Item = &'static Migration> {
+        pending(self.from)
+    }
+
+    /// Gets the number of objects migrated, or that would be with a dry run
+    #[must_use]
+    pub fn migrated(&self) -> usize {
+        self.migrated
+    }
+
+    /// Gets the number of objects an interrupted migration had migrated already
+    #[must_use]
+    pub fn resumed(&self) -> usize {
+        self.resumed
+    }
+
+    /// Gets whether the store was up to date already
+    #[must_use]
+    pub fn is_up_to_date(&self) -> bool {
+        self.from >= FORMAT_VERSION
+    }
+
+    pub(crate) fn record_migrated(&mut self) {
+        self.migrated += 1;
+    }
+
+    pub(crate) fn record_resumed(&mut self) {
+        self.resumed += 1;
+    }
+}
+
+/// Reads the format of the store of `config`
+///
+/// ## Errors
+/// - Returns an error if the format file cannot be read or does not hold a number
+pub(crate) fn store_format(config: &Config) -> Result<u32> {
+    let path = config.store_format_path();
+    if !path.exists() {
+        return Ok(MIN_READABLE_FORMAT_VERSION);
+    }
+    let contents = std::fs::read_to_string(&path)?;
+    contents.trim().parse().map_err(|e| {
+        format!(
+            "invalid store format '{}' in '{}' - {e}",
+            contents.trim(),
+            path.display()
+        )
+        .into()
+    })
+}
+
+/// Records `format` as the format of the store of `config`
+pub(crate) fn set_store_format(config: &Config, format: u32) -> Result {
+    Ok(std::fs::write(
+        config.store_format_path(),
+        format!("{format}\n"),
+    )?)
+}
+
+/// Detects the format of a store without a format file from its `objects`: the oldest format
+/// that wrote what each of them carries. Only what a migration adds is told apart, which is all
+/// [`BackupManager::migrate`](crate::BackupManager::migrate) needs, so a store with a checksum at
+/// the end of every object is up to date. A new store, one without objects, is as well.
+///
+/// ## Errors
+/// - Returns an error if the end of any object cannot be read
+pub(crate) fn detect_format(backend: &dyn StorageBackend, objects: &[BackupInfo]) -> Result<u32> {
+    let mut format = FORMAT_VERSION;
+    for info in objects {
+        if format <= MIN_READABLE_FORMAT_VERSION {
+            break;
+        }
+        let tail = backend.tail(info.object_id()?, CHECKSUM_SIZE)?;
+        let object = if storage_format::has_checksum(&tail) {
+            FORMAT_VERSION
+        } else if info.meta.has_stored_id() {
+            // ids are stored since format `4`, the codec may be recorded as well, which a
+            // migration recording it again does not change
+            4
+        } else {
+            MIN_READABLE_FORMAT_VERSION
+        };
+        format = format.min(object);
+    }
+    Ok(format)
+}
+
+/// Checks that the store of `config` can be read by this version. The format of a store without
+/// a format file is detected with `detect`, see [`detect_format`], and recorded.
+///
+/// ## Errors
+/// - Returns an error if the store was written by a newer format
+/// - Returns an error if the format of the store cannot be read, detected or written
+pub(crate) fn check_format(config: &Config, detect: impl FnOnce() -> Result<u32>) -> Result {
+    if !config.store_format_path().exists() {
+        let format = detect()?;
+        if format < FORMAT_VERSION {
+            tracing::info!(format, "detected the format of the store");
+        }
+        return set_store_format(config, format);
+    }
+    let format = store_format(config)?;
+    if format > FORMAT_VERSION {
+        return Err(format!(
+            "the store '{}' has format {format}, this version only reads formats up to \
+             {FORMAT_VERSION}",
+            config.store_dir_path().display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Decodes `object`, applies the migrations from format `from` to its metadata and encodes it
+/// again in the current format
+pub(crate) fn upgrade(object: &[u8], from: u32) -> Result<Vec<u8>> {
+    let (_, mut meta, bytes) = storage_format::decode(object)?;
+    for migration in pending(from) {
+        (migration.apply)(&mut meta);
+    }
+    let header = FileHeader::new(storage_format::encode_meta(&meta)?.len(), bytes.len());
+    storage_format::encode(&header, &meta, &bytes)
+}
+
+/// The journal of a migration in progress, along with the originals of the objects it migrated
+#[derive(Debug)]
+pub(crate) struct Journal {
+    dir: PathBuf,
+    migrated: HashSet<String>,
+}
+
+impl Journal {
+    /// Opens the journal in `dir`, reading the objects an interrupted migration migrated already
+    pub(crate) fn open(dir: &Path) -> Result<Self> {
+        let migrated = match std::fs::read_to_string(dir.join(JOURNAL_NAME)) {
+            Ok(journal) => journal.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            migrated,
+        })
+    }
+
+    /// Returns true if the object `id` was migrated already
+    pub(crate) fn contains(&self, id: &str) -> bool {
+        self.migrated.contains(id)
+    }
+
+    /// Keeps a copy of the `original` bytes of the object `id` until the migration is finished,
+    /// returning the original to migrate. An interrupted migration may have rewritten the object
+    /// already, so a copy it kept is returned instead of being overwritten.
+    pub(crate) fn keep_original(&self, id: &str, original: Vec<u8>) -> Result<Vec<u8>> {
+        let path = self.dir.join(id);
+        match std::fs::read(&path) {
+            Ok(kept) => return Ok(kept),
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            Err(_) => {}
+        }
+        xstd::fs::ensure_dir(&self.dir)?;
+        // a copy cut short by a crash must not pass for the original
+        let partial = self.dir.join(format!("{id}.{PARTIAL_EXTENSION}"));
+        std::fs::write(&partial, &original)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(original)
+    }
+
+    /// Gets the ids of the objects whose originals are kept, along with the path of each copy
+    pub(crate) fn originals(&self) -> Result<Vec<(String, PathBuf)>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut originals = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let Some(id) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let partial = path.extension().is_some_and(|e| e == PARTIAL_EXTENSION);
+            if id != JOURNAL_NAME && !partial {
+                originals.push((id.to_string(), path.clone()));
+            }
+        }
+        originals.sort();
+        Ok(originals)
+    }
+
+    /// Records that the object `id` was migrated
+    pub(crate) fn record(&mut self, id: &str) -> Result {
+        let mut journal = create_write_append().open(self.dir.join(JOURNAL_NAME))?;
+        writeln!(journal, "{id}")?;
+        journal.sync_data()?;
+        self.migrated.insert(id.to_string());
+        Ok(())
+    }
+
+    /// Removes the journal and the originals once every object is migrated
+    pub(crate) fn finish(self) -> Result {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BackupManager, FileKind, FileVersion, FsMetadata, Timestamp};
+    use storage_common::MaybeConfig;
+
+    /// Writes `contents` as version 1 of `path` into `store` the way format `3` did: no id, no
+    /// codec and no checksum. Returns the object id.
+    fn legacy_object(store: &Path, path: &Path, contents: &[u8]) -> String {
+        let ts = Timestamp::new(1_680_000_000);
+        let fs_meta = FsMetadata::new(Some(ts), Some(ts), None, 2, FileKind::File);
+        let meta = FileMeta::new(FileVersion::new(), ts, path.to_path_buf(), fs_meta);
+        let header = FileHeader::new(storage_format::encode_meta(&meta).unwrap().len(), 2);
+        let mut object = storage_format::encode(&header, &meta, contents).unwrap();
+        object.truncate(object.len() - 8);
+        let id = storage_format::object_name(path, FileVersion::new());
+        std::fs::write(store.join(&id), object).unwrap();
+        id
+    }
+
+    #[test]
+    fn migrates_legacy_stores() {
+        let store = tempfile::tempdir().unwrap();
+        let config = Config::new()
+            .extend_with(&MaybeConfig::default().with_store_dir(store.path().to_str().unwrap()));
+        let a = legacy_object(store.path(), Path::new("/tmp/a.txt"), b"v1");
+        let b = legacy_object(store.path(), Path::new("/tmp/b.txt"), b"v2");
+        let (original_a, original_b) = (
+            std::fs::read(store.path().join(&a)).unwrap(),
+            std::fs::read(store.path().join(&b)).unwrap(),
+        );
+
+        let manager = BackupManager::new(config.clone()).unwrap();
+        assert_eq!(store_format(&config).unwrap(), MIN_READABLE_FORMAT_VERSION);
+        let report = manager.migrate(DryRun::On).unwrap();
+        assert_eq!((report.from(), report.to(), report.migrated()), (1, 1, 2));
+        assert_eq!(
+            report.migrations().map(Migration::to).collect::<Vec<_>>(),
+            [4, 5, 6]
+        );
+        assert_eq!(std::fs::read(store.path().join(&b)).unwrap(), original_b);
+
+        // an interrupted migration got as far as the first object
+        std::fs::create_dir(config.store_migration_path()).unwrap();
+        Journal::open(&config.store_migration_path())
+            .unwrap()
+            .record(&a)
+            .unwrap();
+        let report = manager.migrate(DryRun::Off).unwrap();
+        assert_eq!((report.resumed(), report.migrated()), (1, 1));
+        assert_eq!(report.to(), FORMAT_VERSION);
+        assert_eq!(std::fs::read(store.path().join(&a)).unwrap(), original_a);
+        let migrated = std::fs::read(store.path().join(&b)).unwrap();
+        assert_eq!(&migrated[migrated.len() - 8..][..4], b"SCRC");
+        let (_, meta, contents) = storage_format::decode(&migrated).unwrap();
+        assert!(meta.has_stored_id());
+        assert_eq!(contents, b"v2");
+        assert!(!config.store_migration_path().exists());
+        assert_eq!(store_format(&config).unwrap(), FORMAT_VERSION);
+        assert!(manager.migrate(DryRun::Off).unwrap().is_up_to_date());
+
+        // a lost format file is recreated from the objects, `a` was journaled without being
+        // migrated above and is still in the legacy format
+        std::fs::remove_file(config.store_format_path()).unwrap();
+        let manager = BackupManager::new(config.clone()).unwrap();
+        assert_eq!(store_format(&config).unwrap(), MIN_READABLE_FORMAT_VERSION);
+        assert_eq!(manager.migrate(DryRun::Off).unwrap().migrated(), 2);
+        std::fs::remove_file(config.store_format_path()).unwrap();
+        drop(BackupManager::new(config.clone()).unwrap());
+        assert_eq!(store_format(&config).unwrap(), FORMAT_VERSION);
+
+        set_store_format(&config, FORMAT_VERSION + 1).unwrap();
+        let err = BackupManager::new(config).unwrap_err();
+        assert!(
+            err.to_string().contains("only reads formats up to"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rolls_back_migrations() {
+        let store = tempfile::tempdir().unwrap();
+        let config = Config::new()
+            .extend_with(&MaybeConfig::default().with_store_dir(store.path().to_str().unwrap()));
+        let a = legacy_object(store.path(), Path::new("/tmp/a.txt"), b"v1");
+        let original = std::fs::read(store.path().join(&a)).unwrap();
+        let manager = BackupManager::new(config.clone()).unwrap();
+        assert!(manager.rollback_migration().is_err());
+
+        // a migration rewrote the object, but was interrupted before journaling it
+        let journal = Journal::open(&config.store_migration_path()).unwrap();
+        let kept = journal.keep_original(&a, original.clone()).unwrap();
+        let migrated = upgrade(&kept, MIN_READABLE_FORMAT_VERSION).unwrap();
+        std::fs::write(store.path().join(&a), &migrated).unwrap();
+        // resuming it keeps the original instead of the rewritten object
+        assert_eq!(journal.keep_original(&a, migrated).unwrap(), original);
+        assert_eq!(journal.originals().unwrap().len(), 1);
+
+        assert_eq!(manager.rollback_migration().unwrap(), 1);
+        assert_eq!(std::fs::read(store.path().join(&a)).unwrap(), original);
+        assert!(!config.store_migration_path().exists());
+        assert_eq!(store_format(&config).unwrap(), MIN_READABLE_FORMAT_VERSION);
+        let history = manager.history("/tmp/a.txt");
+        assert_eq!(history.len(), 1);
+        assert!(!history[0].has_stored_id());
+        assert_eq!(
+            manager
+                .read_version("/tmp/a.txt", FileVersion::new())
+                .unwrap(),
+            b"v1"
+        );
+    }
+}
@@ -0,0 +1,863 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The per-file backup pipeline. Every backup passes through an ordered chain of
+//! [`BackupStage`]s, by default `hash -> compress -> write`. Embedders can insert their own
+//! stages anywhere in the chain, e.g. a virus scanner before `compress` or an encryption stage
+//! between `compress` and `write` (with [deduplication](CompressStage::with_dedup) turned off,
+//! since content blobs are written as they are).
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use xstd::{cast::CastFrom, hash::fnv1a};
+
+use crate::{
+    backup::BackupInfo, content, BackupFile, Compression, CompressionConfig, ContentRef,
+    Dictionary, FileHeader, FileMeta, FileVersion, ProgressSink, RealFs, Result, StorageBackend,
+    StreamOptions, SymlinkPolicy, Vfs,
+};
+
+/// The name of the stage reported to a [`ProgressSink`] while the source file is read, before
+/// any [`BackupStage`] runs
+pub const READ_STAGE: &str = "read";
+/// The name of the built-in [`HashStage`]
+pub const HASH_STAGE: &str = "hash";
+/// The name of the built-in [`CompressStage`]
+pub const COMPRESS_STAGE: &str = "compress";
+/// The name of the built-in [`WriteStage`]
+pub const WRITE_STAGE: &str = "write";
+
+/// What the pipeline should do after a [`BackupStage`] has processed an item
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StageOutcome {
+    /// Pass the item on to the next stage
+    Continue,
+    /// Abort the backup of this file with the given reason. Nothing is written to the store.
+    Reject(String),
+}
+
+/// A single step of the [`BackupPipeline`]
+pub trait BackupStage: Send + Sync {
+    /// The name of this stage, used to position other stages relative to it
+    fn name(&self) -> &str;
+
+    /// Processes `item`, possibly modifying its data
+    ///
+    /// ## Errors
+    /// - Any error returned here aborts the backup of the file and is reported to the caller
+    fn process(&self, item: &mut PipelineItem) -> Result<StageOutcome>;
+
+    /// Same as [`BackupStage::process`], reporting the number of bytes processed so far to
+    /// `progress`. Stages that take a while on large files should override this, the default
+    /// reports nothing.
+    ///
+    /// ## Errors
+    /// - Any error returned here aborts the backup of the file and is reported to the caller
+    fn process_with_progress(
+        &self,
+        item: &mut PipelineItem,
+        _progress: &mut dyn ProgressSink,
+    ) -> Result<StageOutcome> {
+        self.process(item)
+    }
+}
+
+/// A file on its way through the [`BackupPipeline`]
+#[derive(Debug, Clone)]
+pub struct PipelineItem {
+    meta: FileMeta,
+    header: FileHeader,
+    data: Vec<u8>,
+    hash: Option<u64>,
+    /// The content blob to write before the object, see [`CompressStage`]
+    content: Option<(String, Vec<u8>)>,
+    encoded: bool,
+    backend: Arc<dyn StorageBackend>,
+    dictionary: Option<Arc<Dictionary>>,
+    object_id: String,
+    destination: PathBuf,
+    written: bool,
+}
+
+impl PipelineItem {
+    /// Gets the [`FileMeta`] of the file being backed up
+    #[must_use]
+    pub fn meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    /// Gets the path of the original file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        self.meta.path()
+    }
+
+    /// Gets the version this backup will be stored as
+    #[must_use]
+    pub fn version(&self) -> FileVersion {
+        *self.meta.version()
+    }
+
+    /// Gets the current data. Before [`COMPRESS_STAGE`] this is the original file content,
+    /// afterwards it is the encoded store object.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Replaces the current data, e.g. after transforming or encrypting it
+    pub fn set_data(&mut self, data: Vec<u8>) {
+        self.data = data;
+    }
+
+    /// Gets the content hash, if a [`HashStage`] has run
+    #[must_use]
+    pub fn hash(&self) -> Option<u64> {
+        self.hash
+    }
+
+    /// Checks whether the data has already been encoded into a store object
+    #[must_use]
+    pub fn is_encoded(&self) -> bool {
+        self.encoded
+    }
+
+    /// Gets the shared dictionary of the store that small contents are compressed with, if one
+    /// has been trained
+    #[must_use]
+    pub fn dictionary(&self) -> Option<&Dictionary> {
+        self.dictionary.as_deref()
+    }
+
+    /// Gets the id of the blob this item will be written to in the [`StorageBackend`] of the
+    /// store
+    #[must_use]
+    pub fn object_id(&self) -> &str {
+        &self.object_id
+    }
+
+    /// Gets the path in the store directory this item will be written to. With a backend other
+    /// than [`LocalBackend`](crate::LocalBackend) the object is not actually stored there, see
+    /// [`PipelineItem::object_id`].
+    #[must_use]
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    /// Checks whether the item has been written to the store
+    #[must_use]
+    pub fn is_written(&self) -> bool {
+        self.written
+    }
+}
+
+/// Computes the [`fnv1a`] hash of the original file content
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashStage;
+
+impl BackupStage for HashStage {
+    fn name(&self) -> &str {
+        HASH_STAGE
+    }
+
+    fn process(&self, item: &mut PipelineItem) -> Result<StageOutcome> {
+        if item.encoded {
+            return Err(format!("'{HASH_STAGE}' stage must run before '{COMPRESS_STAGE}'").into());
+        }
+        item.hash = Some(fnv1a(&item.data));
+        Ok(StageOutcome::Continue)
+    }
+}
+
+/// Encodes and compresses the data into a store object. The [`Compression`] is picked from the
+/// path and size of the file by a [`CompressionConfig`], and data that does not get any smaller
+/// is stored uncompressed instead. The choice is recorded in the [`FileMeta`] of the object,
+/// the buffer size and window of the config only tune how the stream is written.
+///
+/// Unless turned off with [`CompressStage::with_dedup`], the content is moved into the content
+/// blob named after its BLAKE3 digest, so identical contents are stored once however many files
+/// or versions share them, and the object only keeps a [`ContentRef`] to it (see
+/// [`FileMeta::content`]). A new blob is written by the [`WriteStage`], an existing one is reused
+/// once its contents were compared. Stages between `compress` and `write` only see the object,
+/// not the blob. New blobs smaller than [`CompressionConfig::dictionary_bytes`] are compressed
+/// with the [dictionary](PipelineItem::dictionary) of the store, if it has one.
+#[derive(Debug, Clone)]
+pub struct CompressStage {
+    config: CompressionConfig,
+    dedup: bool,
+}
+
+/// What [`CompressStage::existing_blob`] found under the name of a content blob
+enum ExistingBlob {
+    /// There is no such blob
+    Missing,
+    /// The blob holds exactly the contents, taking up this many bytes
+    Same(u64),
+    /// The blob holds other contents with the same digest
+    Different,
+}
+
+impl CompressStage {
+    /// Creates a [`CompressStage`] picking the [`Compression`] of every file with `config`
+    #[must_use]
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            config,
+            dedup: true,
+        }
+    }
+
+    /// Sets whether contents are moved into shared content blobs, which is the default
+    #[must_use]
+    pub fn with_dedup(self, dedup: bool) -> Self {
+        Self { dedup, ..self }
+    }
+
+    /// Gets the heuristics picking the [`Compression`] of every file
+    #[must_use]
+    pub fn config(&self) -> &CompressionConfig {
+        &self.config
+    }
+
+    /// Gets whether contents are moved into shared content blobs
+    #[must_use]
+    pub fn dedup(&self) -> bool {
+        self.dedup
+    }
+
+    /// Moves the data of `item` into its content blob, compressing a new blob with `compression`
+    /// unless that does not make it any smaller. Returns the compression picked for the contents,
+    /// an existing blob is not read to find out how it was compressed. If the blob exists but
+    /// holds something else the data is left in the object, the blob is never replaced.
+    fn share_content(
+        &self,
+        item: &mut PipelineItem,
+        compression: Compression,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<Compression> {
+        // earlier stages may have transformed the content, so it is hashed again
+        let hash = fnv1a(&item.data);
+        let digest = *blake3::hash(&item.data).as_bytes();
+        let name = storage_format::content_digest_name(&digest);
+        let size = u64::cast_from(item.data.len());
+        let (compression, stored) = match Self::existing_blob(item, &name)? {
+            ExistingBlob::Same(stored) => {
+                tracing::debug!(blob = name, path = %item.path().display(), "reusing content blob");
+                (compression, stored)
+            }
+            ExistingBlob::Different => {
+                tracing::warn!(
+                    blob = name,
+                    path = %item.path().display(),
+                    "content hash collision, keeping the contents in the object"
+                );
+                return Ok(compression);
+            }
+            ExistingBlob::Missing => {
+                let mut compression = compression;
+                let options = StreamOptions::from(&self.config);
+                let mut blob = match (compression, item.dictionary()) {
+                    (Compression::Brotli(quality), Some(dictionary))
+                        if size < self.config.dictionary_bytes() =>
+                    {
+                        storage_format::encode_content_with_dictionary(
+                            &item.data, quality, dictionary, options, progress,
+                        )?
+                    }
+                    _ => storage_format::encode_content_with_progress(
+                        &item.data,
+                        compression,
+                        options,
+                        progress,
+                    )?,
+                };
+                if compression != Compression::Store && blob.len() >= item.data.len() {
+                    compression = Compression::Store;
+                    blob = storage_format::encode_content_with_progress(
+                        &item.data,
+                        compression,
+                        options,
+                        &mut (),
+                    )?;
+                }
+                let stored = u64::cast_from(blob.len());
+                item.content = Some((name, blob));
+                (compression, stored)
+            }
+        };
+        item.meta
+            .set_content(ContentRef::new(hash, size, stored).with_digest(digest));
+        item.data.clear();
+        Ok(compression)
+    }
+
+    /// Looks up the existing content blob `name`, reading it to check that it holds exactly the
+    /// data of `item`
+    fn existing_blob(item: &PipelineItem, name: &str) -> Result<ExistingBlob> {
+        if !item.backend.contains(name)? {
+            return Ok(ExistingBlob::Missing);
+        }
+        let blob = item.backend.get(name)?;
+        if content::decode_blob(&*item.backend, &blob)? == item.data {
+            return Ok(ExistingBlob::Same(u64::cast_from(blob.len())));
+        }
+        Ok(ExistingBlob::Different)
+    }
+
+    /// Records `compression` in the metadata of `item` and encodes it
+    fn encode(
+        &self,
+        item: &PipelineItem,
+        compression: Compression,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<(FileMeta, FileHeader, Vec<u8>)> {
+        let meta = item.meta.clone().with_compression(compression);
+        let header = FileHeader::new(storage_format::encode_meta(&meta)?.len(), item.data.len());
+        let bytes = storage_format::encode_with_progress(
+            &header,
+            &meta,
+            &item.data,
+            StreamOptions::from(&self.config),
+            progress,
+        )?;
+        Ok((meta, header, bytes))
+    }
+}
+
+impl Default for CompressStage {
+    fn default() -> Self {
+        Self::new(CompressionConfig::default())
+    }
+}
+
+impl BackupStage for CompressStage {
+    fn name(&self) -> &str {
+        COMPRESS_STAGE
+    }
+
+    fn process(&self, item: &mut PipelineItem) -> Result<StageOutcome> {
+        self.process_with_progress(item, &mut ())
+    }
+
+    fn process_with_progress(
+        &self,
+        item: &mut PipelineItem,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<StageOutcome> {
+        if item.encoded {
+            return Err("backup data has already been compressed".into());
+        }
+        let compression = self
+            .config
+            .quality_for(item.meta.path(), u64::cast_from(item.data.len()))
+            .map_or(Compression::Store, Compression::Brotli);
+        if self.dedup && !item.data.is_empty() && item.meta.content().is_none() {
+            // the object records the compression picked for its contents, it is tiny either way
+            let compression = self.share_content(item, compression, progress)?;
+            (item.meta, item.header, item.data) = self.encode(item, compression, &mut ())?;
+            item.encoded = true;
+            return Ok(StageOutcome::Continue);
+        }
+        // earlier stages may have transformed the content, so the header is rebuilt here
+        let (mut meta, mut header, mut bytes) = self.encode(item, compression, progress)?;
+        let frame_size = std::mem::size_of::<FileHeader>() + header.meta_size + header.file_size;
+        if compression != Compression::Store && bytes.len() >= frame_size {
+            tracing::debug!(
+                path = %item.meta.path().display(),
+                %compression,
+                "compression did not reduce the size, storing the file uncompressed"
+            );
+            // storing is a plain copy, so its progress is not reported a second time
+            (meta, header, bytes) = self.encode(item, Compression::Store, &mut ())?;
+        }
+        item.meta = meta;
+        item.header = header;
+        item.data = bytes;
+        item.encoded = true;
+        Ok(StageOutcome::Continue)
+    }
+}
+
+/// Writes the data to its [blob](PipelineItem::object_id) in the [`StorageBackend`] of the store,
+/// after the new content blob of the [`CompressStage`], if any
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteStage;
+
+impl BackupStage for WriteStage {
+    fn name(&self) -> &str {
+        WRITE_STAGE
+    }
+
+    fn process(&self, item: &mut PipelineItem) -> Result<StageOutcome> {
+        self.process_with_progress(item, &mut ())
+    }
+
+    fn process_with_progress(
+        &self,
+        item: &mut PipelineItem,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<StageOutcome> {
+        if !item.encoded {
+            return Err(format!("'{COMPRESS_STAGE}' stage must run before '{WRITE_STAGE}'").into());
+        }
+        if let Some((name, blob)) = item.content.take() {
+            item.backend.put_with_progress(&name, &blob, progress)?;
+            // the object itself is tiny next to the contents, its progress is not reported
+            item.backend.put(&item.object_id, &item.data)?;
+        } else {
+            item.backend
+                .put_with_progress(&item.object_id, &item.data, progress)?;
+        }
+        item.written = true;
+        Ok(StageOutcome::Continue)
+    }
+}
+
+/// Where [`BackupPipeline::run`] reads a file from: the [`Vfs`] it lives in and how symbolic
+/// links are backed up, along with the dictionary of the store its contents may be compressed
+/// with
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Source<'a> {
+    pub(crate) vfs: &'a dyn Vfs,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) dictionary: Option<&'a Arc<Dictionary>>,
+}
+
+impl<'a> Source<'a> {
+    pub(crate) fn new(vfs: &'a dyn Vfs, symlinks: SymlinkPolicy) -> Self {
+        Self {
+            vfs,
+            symlinks,
+            dictionary: None,
+        }
+    }
+
+    pub(crate) fn with_dictionary(self, dictionary: Option<&'a Arc<Dictionary>>) -> Self {
+        Self { dictionary, ..self }
+    }
+}
+
+impl Default for Source<'_> {
+    /// The real file system, with the default [`SymlinkPolicy`]
+    fn default() -> Self {
+        Self::new(&RealFs, SymlinkPolicy::default())
+    }
+}
+
+/// An ordered chain of [`BackupStage`]s every backed up file passes through
+pub struct BackupPipeline {
+    stages: Vec<Box<dyn BackupStage>>,
+}
+
+impl BackupPipeline {
+    /// Creates a new [`BackupPipeline`] with the default `hash -> compress -> write` stages
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_compression(CompressionConfig::default())
+    }
+
+    /// Same as [`BackupPipeline::new`], the [`CompressStage`] picking the [`Compression`] of
+    /// every file with `compression`
+    #[must_use]
+    pub fn with_compression(compression: CompressionConfig) -> Self {
+        Self {
+            stages: vec![
+                Box::new(HashStage),
+                Box::new(CompressStage::new(compression)),
+                Box::new(WriteStage),
+            ],
+        }
+    }
+
+    /// Creates a [`BackupPipeline`] without any stages
+    #[must_use]
+    pub fn empty() -> Self {
+        Self { stages: Vec::new() }
+    }
+
+    /// Appends `stage` to the end of the pipeline
+    #[must_use]
+    pub fn with_stage(mut self, stage: impl BackupStage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Inserts `stage` directly before the stage named `before`
+    ///
+    /// ## Errors
+    /// - Function returns an error if there is no stage named `before`
+    pub fn insert_before(&mut self, before: &str, stage: impl BackupStage + 'static) -> Result {
+        let index = self.position(before)?;
+        self.stages.insert(index, Box::new(stage));
+        Ok(())
+    }
+
+    /// Inserts `stage` directly after the stage named `after`
+    ///
+    /// ## Errors
+    /// - Function returns an error if there is no stage named `after`
+    pub fn insert_after(&mut self, after: &str, stage: impl BackupStage + 'static) -> Result {
+        let index = self.position(after)?;
+        self.stages.insert(index + 1, Box::new(stage));
+        Ok(())
+    }
+
+    /// Removes the stage named `name`, returning it if it existed
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn BackupStage>> {
+        let index = self.position(name).ok()?;
+        Some(self.stages.remove(index))
+    }
+
+    /// Gets the names of all stages, in order
+    #[must_use]
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|s| s.name()).collect()
+    }
+
+    fn position(&self, name: &str) -> Result<usize> {
+        self.stages
+            .iter()
+            .position(|s| s.name() == name)
+            .ok_or_else(|| format!("no backup stage named '{name}'").into())
+    }
+
+    /// Runs the file at `path` in `source` through the pipeline, storing it as `version` in
+    /// `backend`. The store directory `store` is where the object is recorded in the index.
+    pub(crate) fn run(
+        &self,
+        backend: &Arc<dyn StorageBackend>,
+        store: &Path,
+        path: &Path,
+        version: FileVersion,
+        source: Source<'_>,
+    ) -> Result<BackupInfo> {
+        self.run_with_progress(backend, store, path, version, source, &mut ())
+    }
+
+    /// Same as [`BackupPipeline::run`], announcing every stage (starting with [`READ_STAGE`]) to
+    /// `progress` and reporting the number of bytes processed within it
+    pub(crate) fn run_with_progress(
+        &self,
+        backend: &Arc<dyn StorageBackend>,
+        store: &Path,
+        path: &Path,
+        version: FileVersion,
+        source: Source<'_>,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<BackupInfo> {
+        let _span = tracing::info_span!("backup", path = %path.display(), %version).entered();
+        let started = std::time::Instant::now();
+        progress.stage(READ_STAGE);
+        let (header, meta, data) =
+            BackupFile::create_in(source.vfs, path, version, source.symlinks, progress)?
+                .into_parts()?;
+        let object_id = storage_format::object_name(path, version);
+        let mut item = PipelineItem {
+            meta,
+            header,
+            data,
+            hash: None,
+            content: None,
+            encoded: false,
+            backend: Arc::clone(backend),
+            dictionary: source.dictionary.cloned(),
+            destination: store.join(&object_id),
+            object_id,
+            written: false,
+        };
+
+        for stage in &self.stages {
+            progress.stage(stage.name());
+            if let StageOutcome::Reject(reason) =
+                stage.process_with_progress(&mut item, progress)?
+            {
+                tracing::info!(stage = stage.name(), %reason, "backup rejected");
+                return Err(format!(
+                    "backup of '{}' rejected by '{}' stage - {reason}",
+                    path.display(),
+                    stage.name()
+                )
+                .into());
+            }
+        }
+
+        if !item.written {
+            return Err(format!(
+                "backup pipeline finished without writing '{}' to the store",
+                path.display()
+            )
+            .into());
+        }
+
+        let backup_size = CastFrom::cast_from(item.data.len());
+        tracing::info!(
+            size = item.meta.fs_meta().size(),
+            stored = backup_size,
+            compression = %item.meta.compression(),
+            elapsed_ms = started.elapsed().as_millis(),
+            "backup created"
+        );
+        Ok(BackupInfo {
+            header: item.header,
+            meta: item.meta,
+            backup_path: item.destination,
+            backup_size,
+        })
+    }
+}
+
+impl Default for BackupPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for BackupPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackupPipeline")
+            .field("stages", &self.stage_names())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content;
+    use crate::BUFFER_SIZE;
+
+    fn local_backend(store: &Path) -> Arc<dyn StorageBackend> {
+        Arc::new(crate::LocalBackend::new(store))
+    }
+
+    struct DenyList(&'static [u8]);
+
+    impl BackupStage for DenyList {
+        fn name(&self) -> &str {
+            "deny-list"
+        }
+
+        fn process(&self, item: &mut PipelineItem) -> Result<StageOutcome> {
+            if item.data().windows(self.0.len()).any(|w| w == self.0) {
+                return Ok(StageOutcome::Reject("found forbidden content".to_string()));
+            }
+            Ok(StageOutcome::Continue)
+        }
+    }
+
+    struct Uppercase;
+
+    impl BackupStage for Uppercase {
+        fn name(&self) -> &str {
+            "uppercase"
+        }
+
+        fn process(&self, item: &mut PipelineItem) -> Result<StageOutcome> {
+            let data = item.data().to_ascii_uppercase();
+            item.set_data(data);
+            Ok(StageOutcome::Continue)
+        }
+    }
+
+    #[test]
+    fn inserts_stages() {
+        let mut pipeline = BackupPipeline::new();
+        pipeline.insert_before(COMPRESS_STAGE, Uppercase).unwrap();
+        pipeline.insert_after(HASH_STAGE, DenyList(b"x")).unwrap();
+        assert_eq!(
+            pipeline.stage_names(),
+            ["hash", "deny-list", "uppercase", "compress", "write"]
+        );
+        assert!(pipeline.insert_before("encrypt", Uppercase).is_err());
+        assert!(pipeline.remove("uppercase").is_some());
+        assert!(pipeline.remove("uppercase").is_none());
+    }
+
+    #[test]
+    fn runs_custom_stages() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let backend = local_backend(store.path());
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let clean = files.path().join("clean.txt");
+        let infected = files.path().join("infected.txt");
+        std::fs::write(&clean, "hello").unwrap();
+        std::fs::write(&infected, "hello EICAR").unwrap();
+
+        let mut pipeline = BackupPipeline::new();
+        pipeline.insert_before(COMPRESS_STAGE, Uppercase).unwrap();
+        pipeline
+            .insert_before("uppercase", DenyList(b"EICAR"))
+            .unwrap();
+
+        let info = pipeline
+            .run(
+                &backend,
+                store.path(),
+                &clean,
+                FileVersion::new(),
+                Source::default(),
+            )
+            .unwrap();
+        let (_, meta, bytes) =
+            content::decode(&*backend, &std::fs::read(&info.backup_path).unwrap()).unwrap();
+        assert_eq!(meta.path(), &clean);
+        assert_eq!(bytes, b"HELLO");
+
+        let err = pipeline
+            .run(
+                &backend,
+                store.path(),
+                &infected,
+                FileVersion::new(),
+                Source::default(),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("deny-list"));
+        // the object of `clean` and its content blob
+        assert_eq!(std::fs::read_dir(store.path()).unwrap().count(), 2);
+
+        assert!(BackupPipeline::empty()
+            .run(
+                &backend,
+                store.path(),
+                &clean,
+                FileVersion::new(),
+                Source::default()
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn reports_stage_progress() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let file = tempfile::NamedTempFile::new().expect("failed to create file");
+        std::fs::write(file.path(), vec![7; 3 * BUFFER_SIZE]).unwrap();
+
+        let mut finished = Vec::new();
+        let mut progress = crate::StageProgress::new(|report: &crate::ProgressReport<'_>| {
+            if report.processed == report.total {
+                finished.push(report.stage.to_string());
+            }
+        });
+        BackupPipeline::new()
+            .run_with_progress(
+                &local_backend(store.path()),
+                store.path(),
+                file.path(),
+                FileVersion::new(),
+                Source::default(),
+                &mut progress,
+            )
+            .unwrap();
+        assert_eq!(finished, [READ_STAGE, COMPRESS_STAGE, WRITE_STAGE]);
+    }
+
+    #[test]
+    fn picks_compression() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let backend = local_backend(store.path());
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let text = files.path().join("notes.txt");
+        let photo = files.path().join("photo.png");
+        let noise = files.path().join("noise.bin");
+        std::fs::write(&text, "hello ".repeat(1000)).unwrap();
+        std::fs::write(&photo, "hello ".repeat(1000)).unwrap();
+        // a xorshift sequence, which brotli is unable to compress
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let random = (0..BUFFER_SIZE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        std::fs::write(&noise, &random).unwrap();
+
+        let pipeline =
+            BackupPipeline::with_compression(CompressionConfig::new().with_large_files(5000, 3));
+        let run = |path: &Path| {
+            let info = pipeline
+                .run(
+                    &backend,
+                    store.path(),
+                    path,
+                    FileVersion::new(),
+                    Source::default(),
+                )
+                .unwrap();
+            let object = std::fs::read(&info.backup_path).unwrap();
+            let (_, meta, bytes) = content::decode(&*backend, &object).unwrap();
+            xstd::assert_bytes_eq!(bytes, std::fs::read(path).unwrap());
+            assert_eq!(info.meta.compression(), meta.compression());
+            let blob = info.meta.content().unwrap().stored();
+            (meta.compression(), usize::try_from(blob).unwrap())
+        };
+
+        let (compression, size) = run(&text);
+        assert_eq!(compression, Compression::Brotli(3));
+        assert!(size < 500);
+        assert_eq!(run(&photo).0, Compression::Store);
+        let (compression, size) = run(&noise);
+        assert_eq!(compression, Compression::Store);
+        assert!(size < random.len() + 500);
+    }
+
+    #[test]
+    fn shares_identical_contents() {
+        let store = tempfile::tempdir().expect("failed to create store dir");
+        let backend = local_backend(store.path());
+        let files = tempfile::tempdir().expect("failed to create files dir");
+        let (a, b) = (files.path().join("a.txt"), files.path().join("b.txt"));
+        std::fs::write(&a, "hello ".repeat(100)).unwrap();
+        std::fs::write(&b, "hello ".repeat(100)).unwrap();
+
+        let run = |pipeline: &BackupPipeline, path: &Path| {
+            pipeline
+                .run(
+                    &backend,
+                    store.path(),
+                    path,
+                    FileVersion::new(),
+                    Source::default(),
+                )
+                .unwrap()
+        };
+        let pipeline = BackupPipeline::new();
+        let content = run(&pipeline, &a).meta.content().unwrap();
+        assert_eq!(run(&pipeline, &b).meta.content(), Some(content));
+        assert_eq!(content.size(), 600);
+        assert_eq!(content.hash(), fnv1a("hello ".repeat(100).as_bytes()));
+        assert_eq!(
+            content.digest(),
+            Some(blake3::hash("hello ".repeat(100).as_bytes()).as_bytes())
+        );
+        assert_eq!(std::fs::read_dir(store.path()).unwrap().count(), 3);
+
+        // a blob holding something else under the same name is neither reused nor replaced
+        let other = storage_format::encode_content(b"other", Compression::Store).unwrap();
+        backend.put(&content.name(), &other).unwrap();
+        let info = run(&pipeline, &b);
+        assert_eq!(info.meta.content(), None);
+        let (_, _, bytes) =
+            storage_format::decode(&std::fs::read(&info.backup_path).unwrap()).unwrap();
+        assert_eq!(bytes, "hello ".repeat(100).as_bytes());
+        assert_eq!(backend.get(&content.name()).unwrap(), other);
+
+        let mut pipeline = BackupPipeline::empty()
+            .with_stage(CompressStage::default().with_dedup(false))
+            .with_stage(WriteStage);
+        let info = run(&pipeline, &files.path().join("a.txt"));
+        assert_eq!(info.meta.content(), None);
+        let (_, _, bytes) =
+            storage_format::decode(&std::fs::read(&info.backup_path).unwrap()).unwrap();
+        assert_eq!(bytes, "hello ".repeat(100).as_bytes());
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Dry runs of the operations that delete backups or overwrite files. Each of them describes its
+//! changes as a [`Plan`], which is all that happens with [`DryRun::On`].
+
+use std::{fmt, path::PathBuf};
+
+use xstd::display::format_bytes;
+
+use crate::FileVersion;
+
+/// Whether a destructive operation is performed, or only planned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DryRun {
+    /// The operation is performed
+    #[default]
+    Off,
+    /// The operation only reports its [`Plan`], without deleting or writing anything
+    On,
+}
+
+impl DryRun {
+    /// Gets whether this is a dry run
+    #[must_use]
+    pub fn is_on(self) -> bool {
+        self == Self::On
+    }
+}
+
+impl From<bool> for DryRun {
+    fn from(dry_run: bool) -> Self {
+        if dry_run {
+            Self::On
+        } else {
+            Self::Off
+        }
+    }
+}
+
+/// A single change to the store or the file system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlannedChange {
+    /// A backup is deleted from the store
+    DeleteBackup {
+        /// The original path of the backed up file
+        path: PathBuf,
+        /// The deleted version
+        version: FileVersion,
+        /// The object in the store holding the backup
+        object: PathBuf,
+        /// The (compressed) size of the object
+        size: u64,
+    },
+    /// A file is restored where there is no file yet
+    CreateFile {
+        /// The path that is written
+        path: PathBuf,
+        /// The restored version
+        version: FileVersion,
+    },
+    /// An existing file is overwritten by a restore
+    OverwriteFile {
+        /// The path that is written
+        path: PathBuf,
+        /// The restored version
+        version: FileVersion,
+    },
+}
+
+impl PlannedChange {
+    /// Gets whether this change deletes or overwrites existing data
+    #[must_use]
+    pub fn is_destructive(&self) -> bool {
+        !matches!(self, Self::CreateFile { .. })
+    }
+}
+
+impl fmt::Display for PlannedChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeleteBackup {
+                path,
+                version,
+                size,
+                ..
+            } => write!(
+                f,
+                "delete {} version {version} ({})",
+                path.display(),
+                format_bytes(*size)
+            ),
+            Self::CreateFile { path, version } => {
+                write!(f, "create {} from version {version}", path.display())
+            }
+            Self::OverwriteFile { path, version } => {
+                write!(f, "overwrite {} with version {version}", path.display())
+            }
+        }
+    }
+}
+
+/// The changes of a destructive operation, in the order they are made
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Plan {
+    changes: Vec<PlannedChange>,
+}
+
+impl Plan {
+    /// Gets every change, in the order they are made
+    #[must_use]
+    pub fn changes(&self) -> &[PlannedChange] {
+        &self.changes
+    }
+
+    /// Gets whether nothing changes
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Gets the number of backups deleted from the store
+    #[must_use]
+    pub fn deleted_backups(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| matches!(change, PlannedChange::DeleteBackup { .. }))
+            .count()
+    }
+
+    /// Gets the number of existing files overwritten
+    #[must_use]
+    pub fn overwritten_files(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| matches!(change, PlannedChange::OverwriteFile { .. }))
+            .count()
+    }
+}
+
+impl FromIterator<PlannedChange> for Plan {
+    fn from_iter<T: IntoIterator<Item = PlannedChange>>(iter: T) -> Self {
+        Self {
+            changes: iter.into_iter().collect(),
+        }
+    }
+}
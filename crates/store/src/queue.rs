@@ -0,0 +1,328 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The queue of backups waiting for [`BackupManager::run_pending`], ordered so a burst of changes
+//! backs up the urgent and the small files first: by [`BackupPriority`], then by size. Files of
+//! different groups (e.g. entries of the tracking list) of the same priority take turns, so a
+//! directory with thousands of changed files does not hold up the others.
+//!
+//! [`BackupManager::run_pending`]: crate::BackupManager::run_pending
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use storage_common::BackupPriority;
+
+/// A backup waiting in the queue of a [`BackupManager`](crate::BackupManager), see
+/// [`BackupManager::queue`](crate::BackupManager::queue)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedBackup {
+    path: PathBuf,
+    priority: BackupPriority,
+    group: Option<PathBuf>,
+}
+
+impl QueuedBackup {
+    /// Creates a new [`QueuedBackup`] of the file at `path`, with the normal priority and in a
+    /// group of its own
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            priority: BackupPriority::default(),
+            group: None,
+        }
+    }
+
+    /// Sets the priority of the backup
+    #[must_use]
+    pub fn with_priority(self, priority: BackupPriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// Sets the group of the backup, e.g. the tracking list entry the file belongs to. Queued
+    /// files of different groups take turns.
+    #[must_use]
+    pub fn with_group(self, group: impl Into<PathBuf>) -> Self {
+        Self {
+            group: Some(group.into()),
+            ..self
+        }
+    }
+
+    /// Gets the file to back up
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the priority of the backup
+    #[must_use]
+    pub fn priority(&self) -> BackupPriority {
+        self.priority
+    }
+
+    /// Gets the group of the backup, the file itself unless one was set
+    #[must_use]
+    pub fn group(&self) -> &Path {
+        self.group.as_deref().unwrap_or(&self.path)
+    }
+}
+
+/// The place of a queued file within its group: smaller files first, then in order of arrival
+type FileKey = (u64, u64, PathBuf);
+
+/// A queued backup with the size of its file and the order it arrived in
+#[derive(Debug, Clone)]
+struct Entry {
+    job: QueuedBackup,
+    size: u64,
+    arrival: u64,
+}
+
+impl Entry {
+    fn key(&self) -> FileKey {
+        (self.size, self.arrival, self.job.path.clone())
+    }
+}
+
+/// The queued files of a group, and the round the group takes its next turn in
+#[derive(Debug, Clone)]
+struct Group {
+    files: BTreeSet<FileKey>,
+    round: u64,
+}
+
+/// The queued backups of one priority
+#[derive(Debug, Clone, Default)]
+struct Level {
+    groups: HashMap<PathBuf, Group>,
+    /// The next file of every group, by the round of the group, then by size
+    turns: BTreeSet<(u64, FileKey, PathBuf)>,
+    /// The round of the file taken last, which a new group joins in
+    round: u64,
+}
+
+impl Level {
+    fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Changes the files of `group` with `update`, keeping the turn of the group in order
+    fn update(&mut self, group: &Path, update: impl FnOnce(&mut BTreeSet<FileKey>)) {
+        let round = self.round;
+        let entry = self
+            .groups
+            .entry(group.to_path_buf())
+            .or_insert_with(|| Group {
+                files: BTreeSet::new(),
+                round,
+            });
+        if let Some(first) = entry.files.first() {
+            self.turns
+                .remove(&(entry.round, first.clone(), group.to_path_buf()));
+        }
+        update(&mut entry.files);
+        if let Some(first) = entry.files.first() {
+            self.turns
+                .insert((entry.round, first.clone(), group.to_path_buf()));
+        } else {
+            self.groups.remove(group);
+        }
+    }
+
+    /// Takes the smallest file of the group whose turn it is
+    fn pop(&mut self) -> Option<PathBuf> {
+        let (round, file, group) = self.turns.pop_first()?;
+        self.round = round;
+        if let Some(entry) = self.groups.get_mut(&group) {
+            entry.files.remove(&file);
+            entry.round = round + 1;
+            if let Some(first) = entry.files.first() {
+                self.turns.insert((entry.round, first.clone(), group));
+            } else {
+                self.groups.remove(&group);
+            }
+        }
+        Some(file.2)
+    }
+}
+
+/// The backups waiting to run, at most one per path. Queued files are ordered by priority, then
+/// in rounds taking the smallest file of each group, every round ordered by size.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BackupQueue {
+    entries: HashMap<PathBuf, Entry>,
+    levels: BTreeMap<Reverse<BackupPriority>, Level>,
+    arrivals: u64,
+}
+
+impl BackupQueue {
+    /// Queues `job` for a file of `size` bytes. A job already queued for the same path is
+    /// replaced, taking the higher of both priorities and the new size but keeping its place
+    /// among files of the same size, so a file that keeps changing is not pushed back.
+    pub(crate) fn push(&mut self, job: QueuedBackup, size: u64) {
+        let (job, arrival) = if let Some(queued) = self.entries.remove(&job.path) {
+            self.unlink(&queued);
+            let priority = queued.job.priority.max(job.priority);
+            (job.with_priority(priority), queued.arrival)
+        } else {
+            self.arrivals += 1;
+            (job, self.arrivals)
+        };
+        let entry = Entry { job, size, arrival };
+        let key = entry.key();
+        self.levels
+            .entry(Reverse(entry.job.priority))
+            .or_default()
+            .update(entry.job.group(), |files| {
+                files.insert(key);
+            });
+        self.entries.insert(entry.job.path.clone(), entry);
+    }
+
+    /// Returns true if no backup is queued
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Gets the queued paths in the order they are backed up
+    pub(crate) fn paths(&self) -> Vec<PathBuf> {
+        self.clone().pop(usize::MAX)
+    }
+
+    /// Takes the next `max` queued paths at most, in the order they are backed up
+    pub(crate) fn pop(&mut self, max: usize) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        while paths.len() < max {
+            let Some(mut level) = self.levels.first_entry() else {
+                break;
+            };
+            if let Some(path) = level.get_mut().pop() {
+                self.entries.remove(&path);
+                paths.push(path);
+            }
+            if level.get().is_empty() {
+                level.remove();
+            }
+        }
+        paths
+    }
+
+    /// Removes the queued `entry` from its group
+    fn unlink(&mut self, entry: &Entry) {
+        let priority = Reverse(entry.job.priority);
+        if let Some(level) = self.levels.get_mut(&priority) {
+            level.update(entry.job.group(), |files| {
+                files.remove(&entry.key());
+            });
+            if level.is_empty() {
+                self.levels.remove(&priority);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_backups() {
+        let mut queue = BackupQueue::default();
+        queue.push(QueuedBackup::new("/data/disk.img"), 4 << 30);
+        queue.push(QueuedBackup::new("/home/me/.bashrc"), 300);
+        queue.push(QueuedBackup::new("/home/me/notes.txt"), 2000);
+        // small files go first
+        assert_eq!(
+            queue.paths(),
+            [
+                Path::new("/home/me/.bashrc"),
+                Path::new("/home/me/notes.txt"),
+                Path::new("/data/disk.img"),
+            ]
+        );
+
+        // a duplicate takes over the higher priority of the two
+        queue.push(
+            QueuedBackup::new("/home/me/notes.txt").with_priority(BackupPriority::High),
+            2500,
+        );
+        queue.push(
+            QueuedBackup::new("/home/me/notes.txt").with_priority(BackupPriority::Low),
+            2600,
+        );
+        queue.push(
+            QueuedBackup::new("/data/disk.img").with_priority(BackupPriority::Low),
+            4 << 30,
+        );
+        assert_eq!(
+            queue.paths(),
+            [
+                Path::new("/home/me/notes.txt"),
+                Path::new("/home/me/.bashrc"),
+                Path::new("/data/disk.img"),
+            ]
+        );
+        assert_eq!(queue.pop(usize::MAX).len(), 3);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn groups_take_turns() {
+        let mut queue = BackupQueue::default();
+        for i in 0..3 {
+            queue.push(
+                QueuedBackup::new(format!("/src/file{i}")).with_group("/src"),
+                100 + i,
+            );
+        }
+        queue.push(QueuedBackup::new("/etc/app.conf").with_group("/etc"), 500);
+        queue.push(QueuedBackup::new("/etc/big.conf").with_group("/etc"), 900);
+        assert_eq!(
+            queue.pop(usize::MAX),
+            [
+                PathBuf::from("/src/file0"),
+                PathBuf::from("/etc/app.conf"),
+                PathBuf::from("/src/file1"),
+                PathBuf::from("/etc/big.conf"),
+                PathBuf::from("/src/file2"),
+            ]
+        );
+    }
+    #[test]
+    fn preempts_queued_backups() {
+        let mut queue = BackupQueue::default();
+        for i in 0..4 {
+            queue.push(QueuedBackup::new(format!("/data/file{i}")), 1000 + i);
+        }
+        assert_eq!(
+            queue.pop(2),
+            [Path::new("/data/file0"), Path::new("/data/file1")]
+        );
+
+        // files queued while a chunk is backed up go ahead of the ones of a lower priority
+        queue.push(
+            QueuedBackup::new("/etc/app.conf").with_priority(BackupPriority::High),
+            5000,
+        );
+        queue.push(
+            QueuedBackup::new("/data/file3").with_priority(BackupPriority::High),
+            1003,
+        );
+        assert_eq!(queue.paths().len(), 3);
+        assert_eq!(
+            queue.pop(2),
+            [Path::new("/data/file3"), Path::new("/etc/app.conf")]
+        );
+        assert_eq!(queue.pop(2), [Path::new("/data/file2")]);
+        assert!(queue.is_empty());
+        assert!(queue.pop(2).is_empty());
+    }
+}
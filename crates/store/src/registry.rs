@@ -0,0 +1,225 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use crate::{BackupManager, Config, FileMeta, Result, RetentionPolicy};
+
+/// A store of a [`StoreRegistry`]: the global store of the config or one of its named stores
+/// (see [`StoreConfig`](storage_common::StoreConfig))
+#[derive(Debug)]
+pub struct RegisteredStore {
+    name: Option<String>,
+    config: Config,
+    retention: Option<RetentionPolicy>,
+    manager: BackupManager,
+}
+
+impl RegisteredStore {
+    /// Gets the name of the store, `None` for the global store
+    #[must_use]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Gets the config of the store, see [`Config::for_store`]
+    #[must_use]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Gets the retention policy configured for the store, if any
+    #[must_use]
+    pub fn retention(&self) -> Option<&RetentionPolicy> {
+        self.retention.as_ref()
+    }
+
+    /// Gets the manager of the store
+    #[must_use]
+    pub fn manager(&self) -> &BackupManager {
+        &self.manager
+    }
+}
+
+/// The [`BackupManager`]s of the global store and every named store of a [`Config`], routing
+/// each path to the store with the longest root containing it (see [`Config::store_for`])
+#[derive(Debug)]
+pub struct StoreRegistry {
+    config: Config,
+    /// The global store followed by the named stores, in the order of the config
+    stores: Vec<RegisteredStore>,
+}
+
+impl StoreRegistry {
+    /// Opens the global store and every named store of `config`. The directories of the named
+    /// stores are created if they do not exist yet.
+    ///
+    /// ## Errors
+    /// - Returns an error if the retention policy of a named store is invalid
+    /// - Returns an error if a store cannot be created or opened (see [`BackupManager::new`])
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut stores = vec![RegisteredStore {
+            name: None,
+            config: config.clone(),
+            retention: None,
+            manager: BackupManager::new(config.clone())?,
+        }];
+        for store in config.stores() {
+            let retention =
+                store.retention().map(str::parse).transpose().map_err(|e| {
+                    format!("invalid retention of the store '{}' - {e}", store.name())
+                })?;
+            let store_config = config.for_store(store.name())?;
+            xstd::fs::ensure_dir(store_config.store_dir_path())?;
+            stores.push(RegisteredStore {
+                name: Some(store.name().to_string()),
+                manager: BackupManager::new(store_config.clone())?,
+                config: store_config,
+                retention,
+            });
+        }
+        Ok(Self {
+            config: config.clone(),
+            stores,
+        })
+    }
+
+    /// Gets the global store
+    #[must_use]
+    pub fn global(&self) -> &RegisteredStore {
+        &self.stores[0]
+    }
+
+    /// Gets the named store called `name`
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&RegisteredStore> {
+        self.stores.iter().find(|store| store.name() == Some(name))
+    }
+
+    /// Gets every store, the global one first
+    pub fn stores(&self) -> impl Iterator<Item = &RegisteredStore> + '_ {
+        self.stores.iter()
+    }
+
+    /// Gets the store `path` belongs to
+    #[must_use]
+    pub fn route(&self, path: &Path) -> &RegisteredStore {
+        self.config
+            .store_for(path)
+            .and_then(|store| self.get(store.name()))
+            .unwrap_or_else(|| self.global())
+    }
+
+    /// Groups `paths` by the store they belong to. The groups are ordered by the first path of
+    /// each, and keep the order of their paths.
+    pub fn group<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+    ) -> Vec<(&RegisteredStore, Vec<PathBuf>)> {
+        let mut groups: Vec<(&RegisteredStore, Vec<PathBuf>)> = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            let store = self.route(path);
+            match groups
+                .iter_mut()
+                .find(|(group, _)| std::ptr::eq(*group, store))
+            {
+                Some((_, group)) => group.push(path.to_path_buf()),
+                None => groups.push((store, vec![path.to_path_buf()])),
+            }
+        }
+        groups
+    }
+
+    /// Reads the tracked files of every store, see [`Config::read_tracked_files`]. A named store
+    /// without a tracking list tracks nothing.
+    ///
+    /// ## Errors
+    /// - Returns an error if the tracking list of the global store cannot be read
+    /// - Returns an error if an existing tracking list of a named store cannot be read
+    pub fn tracked_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for store in &self.stores {
+            if store.name.is_some() && !store.config.tracking_list_path().exists() {
+                continue;
+            }
+            files.extend(
+                store
+                    .config
+                    .read_tracked_files()?
+                    .into_iter()
+                    .map(PathBuf::from),
+            );
+        }
+        Ok(files)
+    }
+
+    /// Backs up the file at `path` into the store it belongs to, see [`BackupManager::backup`]
+    ///
+    /// ## Errors
+    /// - Returns an error if the backup fails
+    pub fn backup(&self, path: impl AsRef<Path>) -> Result<FileMeta> {
+        let path = path.as_ref();
+        self.route(path).manager.backup(path)
+    }
+
+    /// Backs up `paths`, each into the store it belongs to, see [`BackupManager::backup_all`].
+    /// The results are grouped by store like [`StoreRegistry::group`].
+    pub fn backup_all<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+    ) -> Vec<(PathBuf, Result<FileMeta>)> {
+        self.group(paths)
+            .into_iter()
+            .flat_map(|(store, paths)| store.manager.backup_all(paths))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage_common::{MaybeConfig, StoreConfig};
+
+    #[test]
+    fn routes_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        let (inside, outside) = (project.join("src/main.rs"), dir.path().join("notes.md"));
+        std::fs::write(&inside, "fn main() {}").unwrap();
+        std::fs::write(&outside, "notes").unwrap();
+        let global = dir.path().join("store");
+        std::fs::create_dir(&global).unwrap();
+        let config = Config::new().extend_with(
+            &MaybeConfig::default()
+                .with_app_dir(dir.path().to_str().unwrap())
+                .with_store_dir(global.to_str().unwrap())
+                .with_stores(vec![
+                    StoreConfig::new("project", &project).with_retention("keep=2")
+                ]),
+        );
+
+        let registry = StoreRegistry::new(&config).unwrap();
+        assert_eq!(registry.route(&inside).name(), Some("project"));
+        assert_eq!(registry.route(&outside).name(), None);
+        let groups = registry.group([&inside, &outside, &inside]);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].1, [inside.clone(), inside.clone()]);
+
+        let results = registry.backup_all([&outside, &inside]);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        let project = registry.get("project").unwrap();
+        assert_eq!(project.retention(), Some(&"keep=2".parse().unwrap()));
+        assert_eq!(project.manager().stats().total_backups(), 1);
+        assert!(project.config().store_dir_path().ends_with(".storage"));
+        assert_eq!(registry.global().manager().stats().total_backups(), 1);
+        assert!(
+            registry.tracked_files().is_err(),
+            "the global list is missing"
+        );
+    }
+}
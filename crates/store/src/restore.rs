@@ -0,0 +1,459 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::{Component, Path, PathBuf};
+
+use xstd::{hash::fnv1a, path::PathExt};
+
+use crate::{
+    backup::BackupInfo, content, vfs, BackupFile, DryRun, FileKind, FileMeta, FileVersion,
+    PlannedChange, Result, StorageBackend, UniqueId, Vfs,
+};
+
+/// The default number of times a restore is retried when its verification fails
+pub const DEFAULT_RESTORE_RETRIES: u32 = 2;
+
+/// The tag of the backup holding the contents of a file right before a restore overwrote it,
+/// see [`RestoreOptions::with_pre_restore_backup`]
+pub const PRE_RESTORE_TAG: &str = "pre-restore";
+
+/// Options for [`BackupManager::restore`](crate::BackupManager::restore)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreOptions {
+    destination: Option<PathBuf>,
+    /// The directory whose files are restored right into `destination`, see
+    /// [`BackupManager::restore_tree`](crate::BackupManager::restore_tree)
+    root: Option<PathBuf>,
+    verify: bool,
+    retries: u32,
+    dry_run: DryRun,
+    pre_restore_backup: bool,
+    force: bool,
+}
+
+impl RestoreOptions {
+    /// Creates new [`RestoreOptions`] that restore files to their original paths without
+    /// verification, backing up the files they overwrite first. Files that changed since their
+    /// latest backup are not overwritten, see [`RestoreOptions::with_force`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            destination: None,
+            root: None,
+            verify: false,
+            retries: DEFAULT_RESTORE_RETRIES,
+            dry_run: DryRun::Off,
+            pre_restore_backup: true,
+            force: false,
+        }
+    }
+
+    /// Restores into the directory `destination` instead of the original paths, recreating the
+    /// original directory structure below it
+    #[must_use]
+    pub fn with_destination(self, destination: impl Into<PathBuf>) -> Self {
+        Self {
+            destination: Some(destination.into()),
+            ..self
+        }
+    }
+
+    /// Re-reads every restored file and compares its hash with the backed up contents
+    #[must_use]
+    pub fn with_verification(self, verify: bool) -> Self {
+        Self { verify, ..self }
+    }
+
+    /// Sets how often a restore whose verification failed is written again before giving up
+    #[must_use]
+    pub fn with_retries(self, retries: u32) -> Self {
+        Self { retries, ..self }
+    }
+
+    /// Only works out which files would be created or overwritten, without writing anything
+    #[must_use]
+    pub fn with_dry_run(self, dry_run: DryRun) -> Self {
+        Self { dry_run, ..self }
+    }
+
+    /// Sets whether an existing file is backed up before it is overwritten, so the restore can
+    /// be undone. The backup is tagged with [`PRE_RESTORE_TAG`], an existing file that did not
+    /// change since its latest backup only has that backup tagged.
+    #[must_use]
+    pub fn with_pre_restore_backup(self, pre_restore_backup: bool) -> Self {
+        Self {
+            pre_restore_backup,
+            ..self
+        }
+    }
+
+    /// Sets whether a file that changed since its latest backup is overwritten. Without it such
+    /// a restore fails with [`Error::Conflict`](crate::Error::Conflict), since the changes are
+    /// not in the store. Only files restored to their original paths are checked.
+    #[must_use]
+    pub fn with_force(self, force: bool) -> Self {
+        Self { force, ..self }
+    }
+
+    /// Restores the files below `root` into the directory `target`, keeping their paths relative
+    /// to `root` only
+    pub(crate) fn with_tree(self, root: &Path, target: &Path) -> Self {
+        Self {
+            destination: Some(target.to_path_buf()),
+            root: Some(root.to_path_buf()),
+            ..self
+        }
+    }
+
+    /// Gets the directory files are restored into, if not their original paths
+    #[must_use]
+    pub fn destination(&self) -> Option<&Path> {
+        self.destination.as_deref()
+    }
+
+    /// Gets whether restored files are verified
+    #[must_use]
+    pub fn verify(&self) -> bool {
+        self.verify
+    }
+
+    /// Gets how often a restore whose verification failed is retried
+    #[must_use]
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Gets whether restores are only planned
+    #[must_use]
+    pub fn dry_run(&self) -> DryRun {
+        self.dry_run
+    }
+
+    /// Gets whether existing files are backed up before they are overwritten
+    #[must_use]
+    pub fn pre_restore_backup(&self) -> bool {
+        self.pre_restore_backup
+    }
+
+    /// Gets whether files that changed since their latest backup are overwritten
+    #[must_use]
+    pub fn force(&self) -> bool {
+        self.force
+    }
+
+    /// Gets the path the file originally at `path` is restored to
+    pub(crate) fn target(&self, path: &Path) -> PathBuf {
+        let Some(destination) = &self.destination else {
+            return path.to_path_buf();
+        };
+        match self.root.as_deref().and_then(|root| path.relative_to(root)) {
+            Some(relative) => destination.join(relative),
+            None => destination.join(
+                path.components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .collect::<PathBuf>(),
+            ),
+        }
+    }
+}
+
+impl Default for RestoreOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A file restored by [`BackupManager::restore`](crate::BackupManager::restore)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoredFile {
+    path: PathBuf,
+    destination: PathBuf,
+    version: FileVersion,
+    id: UniqueId,
+    verified: bool,
+    attempts: u32,
+    change: PlannedChange,
+    pre_restore: Option<FileVersion>,
+}
+
+impl RestoredFile {
+    /// Gets the original path of the file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the path the file was restored to
+    #[must_use]
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    /// Gets the restored version
+    #[must_use]
+    pub fn version(&self) -> FileVersion {
+        self.version
+    }
+
+    /// Gets the id of the restored backup
+    #[must_use]
+    pub fn id(&self) -> UniqueId {
+        self.id
+    }
+
+    /// Gets whether the restored file was verified against the backed up contents
+    #[must_use]
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Gets how often the file had to be written, more than once only if a verification failed
+    /// and `0` for a dry run
+    #[must_use]
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Gets whether the restore created the file or overwrote an existing one
+    #[must_use]
+    pub fn change(&self) -> &PlannedChange {
+        &self.change
+    }
+
+    /// Gets the version of the backup holding the overwritten contents of the destination,
+    /// which restores the file to how it was before. `None` if nothing was overwritten or
+    /// [`RestoreOptions::with_pre_restore_backup`] was off.
+    #[must_use]
+    pub fn pre_restore_version(&self) -> Option<FileVersion> {
+        self.pre_restore
+    }
+
+    pub(crate) fn with_pre_restore_version(self, version: FileVersion) -> Self {
+        Self {
+            pre_restore: Some(version),
+            ..self
+        }
+    }
+}
+
+/// Restores the backup described by `info` out of `backend` into `vfs` according to `options`.
+/// The backup is written to a temporary file next to the destination, which replaces the destination in a
+/// single step once it is complete (and verified), so a failed restore leaves the destination
+/// as it was. A restore that fails its verification is retried.
+pub(crate) fn restore(
+    backend: &dyn StorageBackend,
+    vfs: &dyn Vfs,
+    info: &BackupInfo,
+    options: &RestoreOptions,
+) -> Result<RestoredFile> {
+    let destination = options.target(info.meta.path());
+    let version = *info.meta.version();
+    let change = if vfs.symlink_metadata(&destination).is_ok() {
+        PlannedChange::OverwriteFile {
+            path: destination.clone(),
+            version,
+        }
+    } else {
+        PlannedChange::CreateFile {
+            path: destination.clone(),
+            version,
+        }
+    };
+    if options.dry_run.is_on() {
+        return Ok(RestoredFile {
+            path: info.meta.path().clone(),
+            destination,
+            version,
+            id: info.id(),
+            verified: false,
+            attempts: 0,
+            change,
+            pre_restore: None,
+        });
+    }
+
+    let (header, meta, bytes) = content::decode(backend, &backend.get(info.object_id()?)?)
+        .map_err(|e| e.with_path(&info.backup_path))?;
+    let mut backup = BackupFile::from_parts(header, meta, bytes);
+    if let Some(parent) = destination.parent() {
+        vfs.create_dir_all(parent)?;
+    }
+
+    let expected = if options.verify {
+        Some(ContentHash::recorded(&info.meta, backup.load()?))
+    } else {
+        None
+    };
+    let temp = temp_path(&destination, info.id());
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        // a temporary file left behind by an earlier attempt may be read-only
+        let _ = vfs.remove_file(&temp);
+        let Some((expected, actual)) = write_verified(vfs, &backup, &temp, expected)? else {
+            break;
+        };
+        tracing::warn!(
+            destination = %destination.display(),
+            attempts,
+            "restored file failed verification"
+        );
+        if attempts > options.retries {
+            return Err(format!(
+                "verification of '{}' failed after {attempts} attempt(s) - expected {expected}, \
+                 found {actual}",
+                destination.display()
+            )
+            .into());
+        }
+    }
+    if let Err(e) = vfs.rename(&temp, &destination) {
+        let _ = vfs.remove_file(&temp);
+        return Err(format!(
+            "unable to replace '{}' with the restored file - {e}",
+            destination.display()
+        )
+        .into());
+    }
+
+    tracing::info!(
+        path = %info.meta.path().display(),
+        version = %info.meta.version(),
+        destination = %destination.display(),
+        attempts,
+        "backup restored"
+    );
+    Ok(RestoredFile {
+        path: info.meta.path().clone(),
+        destination,
+        version,
+        id: info.id(),
+        verified: options.verify,
+        attempts,
+        change,
+        pre_restore: None,
+    })
+}
+
+/// A hash of the contents of a backup, which a restored file is verified against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentHash {
+    /// The BLAKE3 digest of the contents
+    Digest([u8; 32]),
+    /// The [`fnv1a`] hash of the contents
+    Fnv1a(u64),
+}
+
+impl ContentHash {
+    /// Gets the hash of the contents recorded in `meta` when they were backed up, that of their
+    /// content blob. An object holding the `contents` itself records no hash of them, they were
+    /// checked against the checksum of the object when they were read.
+    fn recorded(meta: &FileMeta, contents: &[u8]) -> Self {
+        match meta.content() {
+            Some(content) => content
+                .digest()
+                .map_or(Self::Fnv1a(content.hash()), |digest| Self::Digest(*digest)),
+            None => Self::Fnv1a(fnv1a(contents)),
+        }
+    }
+
+    /// Hashes `contents` the same way as `self`
+    fn of(self, contents: &[u8]) -> Self {
+        match self {
+            Self::Digest(_) => Self::Digest(*blake3::hash(contents).as_bytes()),
+            Self::Fnv1a(_) => Self::Fnv1a(fnv1a(contents)),
+        }
+    }
+}
+
+impl std::fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Digest(digest) => {
+                f.write_str("digest ")?;
+                digest.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+            Self::Fnv1a(hash) => write!(f, "hash {hash:016x}"),
+        }
+    }
+}
+
+/// Writes `backup` to `path` in `vfs`. With an `expected` hash the written file is read back,
+/// returning the expected and actual hashes if they differ. The file is removed again unless it
+/// was written and matches.
+fn write_verified(
+    vfs: &dyn Vfs,
+    backup: &BackupFile,
+    path: &Path,
+    expected: Option<ContentHash>,
+) -> Result<Option<(ContentHash, ContentHash)>> {
+    let verified = backup.restore_in(vfs, path, &mut ()).and_then(|()| {
+        let Some(expected) = expected else {
+            return Ok(None);
+        };
+        let actual = expected.of(
+            &if backup.meta().fs_meta().file_type() == FileKind::Symlink {
+                vfs::read_target(vfs, path)?
+            } else {
+                vfs.read(path)?
+            },
+        );
+        Ok((actual != expected).then_some((expected, actual)))
+    });
+    if !matches!(verified, Ok(None)) {
+        let _ = vfs.remove_file(path);
+    }
+    verified
+}
+
+/// Gets the temporary file the backup with the given `id` is written to before it replaces
+/// `destination`, hidden in the same directory so it can be renamed in a single step
+fn temp_path(destination: &Path, id: UniqueId) -> PathBuf {
+    let name = destination
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    destination.with_file_name(format!(".{name}.{id}.restoring"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryFs, SymlinkPolicy};
+
+    #[test]
+    fn removes_unverified_files() {
+        let vfs = MemoryFs::new().with_file("/src/file.txt", "contents");
+        let mut backup = BackupFile::create_in(
+            &vfs,
+            "/src/file.txt",
+            FileVersion::new(),
+            SymlinkPolicy::default(),
+            &mut (),
+        )
+        .unwrap();
+        let contents = backup.load().unwrap().to_vec();
+        let recorded = ContentHash::recorded(backup.meta(), &contents);
+        assert_eq!(recorded, ContentHash::Fnv1a(fnv1a(b"contents")));
+
+        let path = Path::new("/dst/file.txt");
+        assert_eq!(
+            write_verified(&vfs, &backup, path, Some(recorded)).unwrap(),
+            None
+        );
+        assert_eq!(vfs.read(path).unwrap(), b"contents");
+
+        let stored = ContentHash::Digest([0; 32]);
+        let (expected, actual) = write_verified(&vfs, &backup, path, Some(stored))
+            .unwrap()
+            .unwrap();
+        assert_eq!(expected, stored);
+        assert_eq!(
+            actual,
+            ContentHash::Digest(*blake3::hash(b"contents").as_bytes())
+        );
+        assert!(vfs.read(path).is_err());
+    }
+}
@@ -0,0 +1,375 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use storage_common::parse_duration;
+
+use crate::{backup::BackupInfo, Plan, PlannedChange, Timestamp};
+
+/// A policy describing which backup versions should be kept in the store.
+///
+/// The most recent version of every file is **always** kept, regardless of the policy, and so is
+/// the most recent version that still has contents. Tombstones (see
+/// [`FileMeta::is_deleted`](crate::FileMeta::is_deleted)) do not count towards the versions kept,
+/// a tombstone is kept as long as the next older version with contents is. Pinned backups and the
+/// ones kept before a restore (see [`Annotation::is_protected`](crate::Annotation::is_protected))
+/// are always kept, whether or not the policy would keep them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct RetentionPolicy {
+    /// The maximum number of versions to keep for each file
+    pub max_versions: Option<usize>,
+    /// The maximum age of a version before it is removed
+    pub max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Creates a new [`RetentionPolicy`] that keeps everything
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of versions to keep for each file
+    #[must_use]
+    pub fn with_max_versions(self, max_versions: usize) -> Self {
+        Self {
+            max_versions: Some(max_versions),
+            ..self
+        }
+    }
+
+    /// Sets the maximum age of a version before it is removed
+    #[must_use]
+    pub fn with_max_age(self, max_age: Duration) -> Self {
+        Self {
+            max_age: Some(max_age),
+            ..self
+        }
+    }
+
+    /// Checks whether the version at `index` (`0` being the newest) created at `created` should
+    /// be kept under this policy.
+    fn keeps(&self, index: usize, created: Timestamp, now: Timestamp) -> bool {
+        if index == 0 {
+            return true;
+        }
+        if let Some(max_versions) = self.max_versions {
+            if index >= max_versions {
+                return false;
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            let age = now.as_secs().saturating_sub(created.as_secs());
+            if age > max_age.as_secs() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Runs this policy against the given backups without touching the store, keeping the
+    /// `protected` ones.
+    pub(crate) fn simulate(
+        &self,
+        infos: &[BackupInfo],
+        protected: impl Fn(&BackupInfo) -> bool,
+        now: Timestamp,
+    ) -> RetentionReport {
+        let mut by_path: BTreeMap<&Path, Vec<&BackupInfo>> = BTreeMap::new();
+        for info in infos {
+            by_path.entry(info.meta.path()).or_default().push(info);
+        }
+
+        let groups = by_path
+            .into_iter()
+            .map(|(path, mut versions)| {
+                versions.sort_by(|a, b| {
+                    b.meta
+                        .created()
+                        .cmp(a.meta.created())
+                        .then_with(|| b.meta.version().cmp(a.meta.version()))
+                });
+
+                let mut group = RetentionGroupReport::new(path.to_path_buf());
+                let mut contents = 0;
+                for (index, info) in versions.into_iter().enumerate() {
+                    let created = *info.meta.created();
+                    let keep = if info.meta.is_deleted() {
+                        index == 0 || self.keeps(contents, created, now)
+                    } else {
+                        contents += 1;
+                        self.keeps(contents - 1, created, now)
+                    };
+                    if keep || protected(info) {
+                        group.kept_versions += 1;
+                        group.kept_bytes += info.backup_size;
+                        group.oldest_kept = Some(created);
+                    } else {
+                        group.removed_versions += 1;
+                        group.removed_bytes += info.backup_size;
+                        group.removed.push(PlannedChange::DeleteBackup {
+                            path: info.meta.path().clone(),
+                            version: *info.meta.version(),
+                            object: info.backup_path.clone(),
+                            size: info.backup_size,
+                        });
+                    }
+                }
+                group
+            })
+            .collect();
+
+        RetentionReport { groups }
+    }
+}
+
+impl FromStr for RetentionPolicy {
+    type Err = crate::Error;
+
+    /// Parses a policy of the form `keep=10,max-age=30d`. Both keys are optional, ages accept
+    /// the suffixes `s`, `m`, `h`, `d` and `w` (seconds when omitted).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid retention rule '{part}', expected 'key=value'"))?;
+            match key.trim() {
+                "keep" | "max-versions" => {
+                    let max_versions = value
+                        .trim()
+                        .parse()
+                        .map_err(|e| format!("invalid version count '{value}' - {e}"))?;
+                    policy = policy.with_max_versions(max_versions);
+                }
+                "max-age" => policy = policy.with_max_age(parse_duration(value.trim())?),
+                other => return Err(format!("unknown retention rule '{other}'").into()),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// The simulated outcome of a [`RetentionPolicy`] for a single original file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionGroupReport {
+    path: PathBuf,
+    kept_versions: usize,
+    kept_bytes: u64,
+    removed_versions: usize,
+    removed_bytes: u64,
+    oldest_kept: Option<Timestamp>,
+    removed: Vec<PlannedChange>,
+}
+
+impl RetentionGroupReport {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            kept_versions: 0,
+            kept_bytes: 0,
+            removed_versions: 0,
+            removed_bytes: 0,
+            oldest_kept: None,
+            removed: Vec::new(),
+        }
+    }
+
+    /// Gets the path of the original file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the number of versions that would be kept
+    #[must_use]
+    pub fn kept_versions(&self) -> usize {
+        self.kept_versions
+    }
+
+    /// Gets the number of (compressed) bytes that would be kept
+    #[must_use]
+    pub fn kept_bytes(&self) -> u64 {
+        self.kept_bytes
+    }
+
+    /// Gets the number of versions that would be removed
+    #[must_use]
+    pub fn removed_versions(&self) -> usize {
+        self.removed_versions
+    }
+
+    /// Gets the number of (compressed) bytes that would be removed
+    #[must_use]
+    pub fn removed_bytes(&self) -> u64 {
+        self.removed_bytes
+    }
+
+    /// Gets the creation time of the oldest version that would be kept
+    #[must_use]
+    pub fn oldest_kept(&self) -> Option<Timestamp> {
+        self.oldest_kept
+    }
+}
+
+/// The simulated outcome of a [`RetentionPolicy`] across the whole store
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RetentionReport {
+    groups: Vec<RetentionGroupReport>,
+}
+
+impl RetentionReport {
+    /// Gets the per-file results, ordered by path
+    #[must_use]
+    pub fn groups(&self) -> &[RetentionGroupReport] {
+        &self.groups
+    }
+
+    /// Gets the store paths of every backup that would be removed
+    pub(crate) fn removed_paths(&self) -> impl Iterator<Item = &Path> {
+        self.groups
+            .iter()
+            .flat_map(|g| &g.removed)
+            .filter_map(|change| match change {
+                PlannedChange::DeleteBackup { object, .. } => Some(object.as_path()),
+                _ => None,
+            })
+    }
+
+    /// Gets the deletions applying the policy makes, see
+    /// [`BackupManager::apply_retention`](crate::BackupManager::apply_retention)
+    #[must_use]
+    pub fn plan(&self) -> Plan {
+        self.groups
+            .iter()
+            .flat_map(|g| g.removed.iter().cloned())
+            .collect()
+    }
+
+    /// Gets the total number of versions that would be removed
+    #[must_use]
+    pub fn removed_versions(&self) -> usize {
+        self.groups.iter().map(|g| g.removed_versions).sum()
+    }
+
+    /// Gets the total number of (compressed) bytes that would be removed
+    #[must_use]
+    pub fn removed_bytes(&self) -> u64 {
+        self.groups.iter().map(|g| g.removed_bytes).sum()
+    }
+
+    /// Gets the creation time of the oldest version left in the store afterwards
+    #[must_use]
+    pub fn oldest_kept(&self) -> Option<Timestamp> {
+        self.groups.iter().filter_map(|g| g.oldest_kept).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileHeader, FileMeta, FileVersion, FsMetadata};
+
+    fn info(fs_meta: &FsMetadata, path: &str, version: u32, created: u64) -> BackupInfo {
+        BackupInfo {
+            header: FileHeader::default(),
+            meta: FileMeta::new(
+                FileVersion::new() + (version - 1),
+                Timestamp::new(created),
+                PathBuf::from(path),
+                fs_meta.clone(),
+            ),
+            backup_path: PathBuf::from(format!("{path}.{version}")),
+            backup_size: 10,
+        }
+    }
+
+    #[test]
+    fn parses_policy() {
+        let policy: RetentionPolicy = "keep=3, max-age=2d".parse().unwrap();
+        assert_eq!(policy.max_versions, Some(3));
+        assert_eq!(policy.max_age, Some(Duration::from_secs(2 * 24 * 60 * 60)));
+        assert!("keep=abc".parse::<RetentionPolicy>().is_err());
+        assert!("forever=1".parse::<RetentionPolicy>().is_err());
+        assert_eq!(
+            "".parse::<RetentionPolicy>().unwrap(),
+            RetentionPolicy::new()
+        );
+    }
+
+    #[test]
+    fn simulates_policy() {
+        let temp = tempfile::NamedTempFile::new().expect("failed to create temp file");
+        let fs_meta = FsMetadata::from_path(temp.path()).unwrap();
+        let infos = vec![
+            info(&fs_meta, "/a", 1, 100),
+            info(&fs_meta, "/a", 2, 200),
+            info(&fs_meta, "/a", 3, 300),
+            info(&fs_meta, "/b", 1, 50),
+        ];
+
+        let report = RetentionPolicy::new().with_max_versions(2).simulate(
+            &infos,
+            |_| false,
+            Timestamp::new(300),
+        );
+        assert_eq!(report.removed_versions(), 1);
+        assert_eq!(report.removed_bytes(), 10);
+        assert_eq!(report.groups()[0].oldest_kept(), Some(Timestamp::new(200)));
+        assert_eq!(report.oldest_kept(), Some(Timestamp::new(50)));
+
+        // the newest version of a file is always kept
+        let report = RetentionPolicy::new()
+            .with_max_age(Duration::from_secs(10))
+            .simulate(&infos, |_| false, Timestamp::new(300));
+        assert_eq!(report.removed_versions(), 2);
+        assert_eq!(report.groups()[1].kept_versions(), 1);
+    }
+
+    #[test]
+    fn keeps_contents_behind_tombstones() {
+        let temp = tempfile::NamedTempFile::new().expect("failed to create temp file");
+        let fs_meta = FsMetadata::from_path(temp.path()).unwrap();
+        let tombstone = |version, created| {
+            let mut info = info(&fs_meta, "/a", version, created);
+            info.meta.mark_deleted();
+            info
+        };
+        let infos = vec![
+            info(&fs_meta, "/a", 1, 100),
+            info(&fs_meta, "/a", 2, 200),
+            tombstone(3, 250),
+            info(&fs_meta, "/a", 4, 260),
+            tombstone(5, 300),
+        ];
+
+        // the tombstone does not take the place of the last contents
+        let report = RetentionPolicy::new().with_max_versions(1).simulate(
+            &infos,
+            |_| false,
+            Timestamp::new(300),
+        );
+        let removed = report.removed_paths().collect::<Vec<_>>();
+        assert_eq!(
+            removed,
+            [Path::new("/a.3"), Path::new("/a.2"), Path::new("/a.1")]
+        );
+        assert_eq!(report.groups()[0].kept_versions(), 2);
+
+        let report = RetentionPolicy::new().with_max_versions(2).simulate(
+            &infos,
+            |_| false,
+            Timestamp::new(300),
+        );
+        assert_eq!(report.groups()[0].kept_versions(), 4);
+    }
+}
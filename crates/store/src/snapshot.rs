@@ -0,0 +1,273 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Snapshots, groups of backups that were taken together and are restored together, see
+//! [`BackupManager::open_snapshot`](crate::BackupManager::open_snapshot).
+//!
+//! A snapshot is all-or-nothing: if any of its files cannot be backed up, the objects written
+//! for the others are deleted again and neither the index nor the list of snapshots changes. The
+//! list of snapshots is kept in [`Config::snapshots_path`], next to the objects of the store.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use xstd::id_gen::ParseUniqueIdError;
+
+use crate::{
+    backup::BackupManager, Error, FileMeta, FileVersion, Result, StorageBackend, Timestamp,
+    UniqueId,
+};
+
+/// The id of a [`Snapshot`], formatted like a [`UniqueId`]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct SnapshotId(UniqueId);
+
+impl SnapshotId {
+    /// Generates a new id for the current time
+    pub(crate) fn new() -> Self {
+        Self(UniqueId::new())
+    }
+}
+
+impl fmt::Display for SnapshotId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for SnapshotId {
+    type Err = ParseUniqueIdError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// A single backup that is part of a [`Snapshot`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMember {
+    path: PathBuf,
+    version: FileVersion,
+    id: UniqueId,
+}
+
+impl SnapshotMember {
+    /// Gets the path of the backed up file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the version of the backup
+    #[must_use]
+    pub fn version(&self) -> FileVersion {
+        self.version
+    }
+
+    /// Gets the id of the backup, see [`BackupManager::backup_by_id`]
+    #[must_use]
+    pub fn id(&self) -> UniqueId {
+        self.id
+    }
+}
+
+impl From<&FileMeta> for SnapshotMember {
+    fn from(meta: &FileMeta) -> Self {
+        Self {
+            path: meta.path().clone(),
+            version: *meta.version(),
+            id: meta.id(),
+        }
+    }
+}
+
+/// A group of backups that were taken together, see
+/// [`BackupManager::open_snapshot`](crate::BackupManager::open_snapshot)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    id: SnapshotId,
+    created: Timestamp,
+    members: Vec<SnapshotMember>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(members: Vec<SnapshotMember>) -> Self {
+        Self {
+            id: SnapshotId::new(),
+            created: Timestamp::now(),
+            members,
+        }
+    }
+
+    /// Gets the id of the snapshot
+    #[must_use]
+    pub fn id(&self) -> SnapshotId {
+        self.id
+    }
+
+    /// Gets when the snapshot was taken
+    #[must_use]
+    pub fn created(&self) -> Timestamp {
+        self.created
+    }
+
+    /// Gets the backups of the snapshot, in the order their files were added
+    #[must_use]
+    pub fn members(&self) -> &[SnapshotMember] {
+        &self.members
+    }
+}
+
+/// A snapshot being assembled, created by
+/// [`BackupManager::open_snapshot`](crate::BackupManager::open_snapshot). Nothing is backed up
+/// until it is committed.
+#[derive(Debug)]
+pub struct SnapshotBuilder<'a, B: StorageBackend> {
+    manager: &'a BackupManager<B>,
+    paths: Vec<PathBuf>,
+}
+
+impl<'a, B: StorageBackend> SnapshotBuilder<'a, B> {
+    pub(crate) fn new(manager: &'a BackupManager<B>) -> Self {
+        Self {
+            manager,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Adds the file at `path` to the snapshot. A file added twice is only backed up once.
+    pub fn add(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        let path = path.as_ref();
+        if !self.paths.iter().any(|p| p == path) {
+            self.paths.push(path.to_path_buf());
+        }
+        self
+    }
+
+    /// Gets the files added to the snapshot so far
+    #[must_use]
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Backs up every added file and records them as a single snapshot
+    ///
+    /// ## Errors
+    /// - Returns an error if no file was added
+    /// - Returns an error if any of the files cannot be backed up, in which case none of them is
+    /// - Returns an error if the store lock cannot be acquired or the snapshot cannot be recorded
+    pub fn commit(self) -> Result<Snapshot> {
+        self.manager.commit_snapshot(&self.paths)
+    }
+}
+
+/// The snapshots of a store, oldest first
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SnapshotIndex {
+    path: PathBuf,
+    snapshots: Vec<Snapshot>,
+}
+
+impl SnapshotIndex {
+    /// Loads the list at `path`, which is empty if the file does not exist yet
+    pub(crate) fn load(path: PathBuf) -> Result<Self> {
+        let snapshots = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| Error::Serde(format!("invalid snapshot list - {e}")))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, snapshots })
+    }
+
+    /// Writes the list to disk, replacing the previous one in a single step
+    pub(crate) fn save(&self) -> Result {
+        let json = serde_json::to_vec_pretty(&self.snapshots)
+            .map_err(|e| Error::Serde(format!("unable to serialize snapshot list - {e}")))?;
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)?;
+        Ok(std::fs::rename(tmp, &self.path)?)
+    }
+
+    /// Gets every snapshot, oldest first
+    pub(crate) fn all(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    /// Gets the snapshot with the given `id`
+    pub(crate) fn get(&self, id: SnapshotId) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|snapshot| snapshot.id == id)
+    }
+
+    /// Adds `snapshot` to the list
+    pub(crate) fn push(&mut self, snapshot: Snapshot) {
+        self.snapshots.push(snapshot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, RestoreOptions, OBJECT_EXTENSION};
+    use storage_common::MaybeConfig;
+
+    #[test]
+    fn snapshots_are_all_or_nothing() {
+        let store = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let config = Config::new()
+            .extend_with(&MaybeConfig::default().with_store_dir(store.path().to_str().unwrap()));
+        let manager = BackupManager::new(config.clone()).unwrap();
+        let a = files.path().join("a.txt");
+        let b = files.path().join("b.txt");
+        std::fs::write(&a, "a1").unwrap();
+        std::fs::write(&b, "b1").unwrap();
+
+        let mut builder = manager.open_snapshot();
+        builder.add(&a).add(&b).add(&a);
+        assert_eq!(builder.paths(), [a.clone(), b.clone()]);
+        let snapshot = builder.commit().unwrap();
+        assert_eq!(snapshot.members().len(), 2);
+        assert_eq!(manager.snapshots().unwrap(), [snapshot.clone()]);
+
+        // a missing file fails the whole snapshot without leaving backups of the others behind
+        std::fs::write(&a, "a2").unwrap();
+        let missing = files.path().join("missing.txt");
+        assert!(manager.snapshot([&a, &missing]).is_err());
+        assert_eq!(manager.stats().total_backups(), 2);
+        let objects = std::fs::read_dir(store.path())
+            .unwrap()
+            .filter(|entry| {
+                let path = entry.as_ref().unwrap().path();
+                path.extension().is_some_and(|ext| ext == OBJECT_EXTENSION)
+            })
+            .count();
+        assert_eq!(objects, 2);
+        assert_eq!(manager.snapshots().unwrap().len(), 1);
+
+        std::fs::write(&b, "b2").unwrap();
+        let restored = manager
+            .restore_snapshot(snapshot.id(), &RestoreOptions::new().with_force(true))
+            .unwrap();
+        assert_eq!(restored.len(), 2);
+        assert!(restored.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "a1");
+        assert_eq!(std::fs::read_to_string(&b).unwrap(), "b1");
+
+        let id = snapshot.id().to_string().parse().unwrap();
+        assert_eq!(manager.snapshot_by_id(id).unwrap(), Some(snapshot));
+        assert!(manager
+            .restore_snapshot(SnapshotId::new(), &RestoreOptions::new())
+            .is_err());
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use xstd::cast::CastFrom;
+
+use crate::{backup::BackupInfo, content, Timestamp};
+
+/// Aggregate statistics about the contents of the backup store
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StoreStats {
+    total_backups: usize,
+    compressed_bytes: u64,
+    original_bytes: u64,
+    deduplicated_bytes: u64,
+    versions: BTreeMap<PathBuf, usize>,
+    oldest: Option<Timestamp>,
+    newest: Option<Timestamp>,
+}
+
+impl StoreStats {
+    /// Collects the statistics for the given backups
+    pub(crate) fn collect(infos: &[BackupInfo]) -> Self {
+        let mut stats = Self::default();
+        for info in infos {
+            let created = *info.meta.created();
+            stats.total_backups += 1;
+            stats.compressed_bytes += info.backup_size;
+            stats.original_bytes += info.file_size();
+            *stats.versions.entry(info.meta.path().clone()).or_default() += 1;
+            stats.oldest = Some(stats.oldest.map_or(created, |oldest| oldest.min(created)));
+            stats.newest = Some(stats.newest.map_or(created, |newest| newest.max(created)));
+        }
+        // every content blob is stored once, however many backups share it
+        for (content, references) in content::references(infos).into_values() {
+            stats.compressed_bytes += content.stored();
+            stats.deduplicated_bytes += content.size() * u64::cast_from(references - 1);
+        }
+        stats
+    }
+
+    /// Gets the total number of backups (i.e. versions of all files) in the store
+    #[must_use]
+    pub fn total_backups(&self) -> usize {
+        self.total_backups
+    }
+
+    /// Gets the number of distinct files that have been backed up
+    #[must_use]
+    pub fn total_files(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// Gets the total size of all backups on disk
+    #[must_use]
+    pub fn compressed_bytes(&self) -> u64 {
+        self.compressed_bytes
+    }
+
+    /// Gets the total size of the original file contents of all backups
+    #[must_use]
+    pub fn original_bytes(&self) -> u64 {
+        self.original_bytes
+    }
+
+    /// Gets the size of the original contents that were not stored again, since an earlier
+    /// backup of the same contents shares its content blob with them
+    #[must_use]
+    pub fn deduplicated_bytes(&self) -> u64 {
+        self.deduplicated_bytes
+    }
+
+    /// Gets the ratio of the size on disk to the original size, e.g. `0.25` when the store
+    /// takes up a quarter of the space the original files would. `None` if the store is empty.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.original_bytes > 0).then(|| self.compressed_bytes as f64 / self.original_bytes as f64)
+    }
+
+    /// Gets the number of versions stored for each file, ordered by path
+    pub fn versions(&self) -> impl Iterator<Item = (&Path, usize)> + '_ {
+        self.versions
+            .iter()
+            .map(|(path, count)| (path.as_path(), *count))
+    }
+
+    /// Gets the creation time of the oldest backup in the store
+    #[must_use]
+    pub fn oldest(&self) -> Option<Timestamp> {
+        self.oldest
+    }
+
+    /// Gets the creation time of the newest backup in the store
+    #[must_use]
+    pub fn newest(&self) -> Option<Timestamp> {
+        self.newest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContentRef, FileHeader, FileKind, FileMeta, FileVersion, FsMetadata};
+
+    fn info(path: &str, created: u64, file_size: usize, backup_size: u64) -> BackupInfo {
+        BackupInfo {
+            header: FileHeader::new(0, file_size),
+            meta: FileMeta::new(
+                FileVersion::new(),
+                Timestamp::new(created),
+                PathBuf::from(path),
+                FsMetadata::new(None, None, None, 0, FileKind::File),
+            ),
+            backup_path: PathBuf::from(path),
+            backup_size,
+        }
+    }
+
+    #[test]
+    fn collects_stats() {
+        assert_eq!(StoreStats::collect(&[]).compression_ratio(), None);
+
+        let stats = StoreStats::collect(&[
+            info("/b", 200, 100, 20),
+            info("/a", 100, 100, 30),
+            info("/b", 300, 200, 50),
+        ]);
+        assert_eq!(stats.total_backups(), 3);
+        assert_eq!(stats.total_files(), 2);
+        assert_eq!(stats.compressed_bytes(), 100);
+        assert_eq!(stats.original_bytes(), 400);
+        assert_eq!(stats.compression_ratio(), Some(0.25));
+        assert_eq!(
+            stats.versions().collect::<Vec<_>>(),
+            [(Path::new("/a"), 1), (Path::new("/b"), 2)]
+        );
+        assert_eq!(stats.oldest(), Some(Timestamp::new(100)));
+        assert_eq!(stats.newest(), Some(Timestamp::new(300)));
+    }
+
+    #[test]
+    fn counts_shared_contents_once() {
+        let shared = |path, hash| {
+            let mut info = info(path, 100, 0, 10);
+            info.meta.set_content(ContentRef::new(hash, 100, 40));
+            info
+        };
+        let stats = StoreStats::collect(&[shared("/a", 1), shared("/b", 1), shared("/c", 2)]);
+        assert_eq!(stats.original_bytes(), 300);
+        // three objects and two blobs
+        assert_eq!(stats.compressed_bytes(), 110);
+        assert_eq!(stats.deduplicated_bytes(), 100);
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Backups of symbolic links. Unless the [`SymlinkPolicy`](crate::SymlinkPolicy) says to follow
+//! them, a link is backed up as its target path, which takes the place of the file contents in
+//! the object, and restoring it recreates the link instead of writing a file.
+
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+
+/// Encodes the target of a link as the bytes stored in place of the file contents
+pub(crate) fn target_to_bytes(target: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        target.as_os_str().as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        target.to_string_lossy().into_owned().into_bytes()
+    }
+}
+
+/// Decodes the target of a link from the bytes stored by [`target_to_bytes`]
+pub(crate) fn target_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Creates a link at `path` pointing to the target encoded in `target`, replacing any file or
+/// link that is already there. Directories are never replaced.
+pub(crate) fn create(path: &Path, target: &[u8]) -> Result {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => {
+            return Err(format!(
+                "unable to restore link '{}', a directory is in the way",
+                path.display()
+            )
+            .into());
+        }
+        Ok(_) => std::fs::remove_file(path)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let target = target_from_bytes(target);
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, path)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(target, path)?;
+    Ok(())
+}
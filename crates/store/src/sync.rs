@@ -0,0 +1,173 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Bringing the store up to date with files that changed while nothing was watching them, see
+//! [`BackupManager::sync`](crate::BackupManager::sync).
+
+use std::path::{Path, PathBuf};
+
+use xstd::hash::fnv1a;
+
+use crate::{
+    annotations, backup::BackupInfo, Error, FileKind, FileMeta, FsMetadata, Result, StorageBackend,
+    Vfs,
+};
+
+/// How thoroughly [`BackupManager::sync`](crate::BackupManager::sync) compares a file with its
+/// latest backup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SyncMode {
+    /// A file with the size and modification time of its latest backup is unchanged. If only
+    /// the modification time differs the contents are compared, so touching a file does not
+    /// create a new backup.
+    #[default]
+    Quick,
+    /// The contents of every file with the size of its latest backup are compared, e.g. to
+    /// catch tools that restore the modification time after writing
+    Thorough,
+}
+
+/// The outcome of [`BackupManager::sync`](crate::BackupManager::sync)
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    backed_up: Vec<(PathBuf, FileMeta)>,
+    unchanged: Vec<PathBuf>,
+    missing: Vec<PathBuf>,
+    skipped: Vec<(PathBuf, FileKind)>,
+    failed: Vec<(PathBuf, Error)>,
+}
+
+impl SyncReport {
+    /// Gets the files that changed since their latest backup (or had none) and were backed up
+    #[must_use]
+    pub fn backed_up(&self) -> &[(PathBuf, FileMeta)] {
+        &self.backed_up
+    }
+
+    /// Gets the files that did not change since their latest backup
+    #[must_use]
+    pub fn unchanged(&self) -> &[PathBuf] {
+        &self.unchanged
+    }
+
+    /// Gets the paths that do not exist (anymore), nothing is done for them
+    #[must_use]
+    pub fn missing(&self) -> &[PathBuf] {
+        &self.missing
+    }
+
+    /// Gets the sockets, named pipes and devices that were skipped, with their kind. Their
+    /// contents cannot be backed up, see [`FileKind::is_special`].
+    #[must_use]
+    pub fn skipped(&self) -> &[(PathBuf, FileKind)] {
+        &self.skipped
+    }
+
+    /// Gets the files that could not be compared or backed up, with the reason
+    #[must_use]
+    pub fn failed(&self) -> &[(PathBuf, Error)] {
+        &self.failed
+    }
+
+    pub(crate) fn push_unchanged(&mut self, path: PathBuf) {
+        self.unchanged.push(path);
+    }
+
+    pub(crate) fn push_missing(&mut self, path: PathBuf) {
+        self.missing.push(path);
+    }
+
+    pub(crate) fn push_result(&mut self, path: PathBuf, result: Result<FileMeta>) {
+        match result {
+            Ok(meta) => self.backed_up.push((path, meta)),
+            Err(e) => self.failed.push((path, e)),
+        }
+    }
+}
+
+/// Expands `paths` in `vfs` into the files to compare: directories stand for the files directly
+/// inside them, like the watcher watches them. Paths that do not exist are reported as missing, special
+/// files (see [`FileKind::is_special`]) as skipped without ever being opened.
+pub(crate) fn expand(
+    vfs: &dyn Vfs,
+    paths: impl IntoIterator<Item = PathBuf>,
+    report: &mut SyncReport,
+) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        let Ok(metadata) = vfs.symlink_metadata(&path) else {
+            report.push_missing(path);
+            continue;
+        };
+        if metadata.file_type() != FileKind::Dir {
+            push_file(path, metadata.file_type(), &mut files, report);
+            continue;
+        }
+        match vfs.read_dir(&path) {
+            Ok(entries) => {
+                let mut entries = entries
+                    .into_iter()
+                    .filter_map(|entry| {
+                        let kind = vfs.symlink_metadata(&entry).ok()?.file_type();
+                        Some((entry, kind))
+                    })
+                    // links to directories are left out as well
+                    .filter(|(entry, kind)| {
+                        *kind != FileKind::Dir
+                            && vfs
+                                .metadata(entry)
+                                .map_or(true, |meta| meta.file_type() != FileKind::Dir)
+                    })
+                    .collect::<Vec<_>>();
+                entries.sort();
+                for (entry, kind) in entries {
+                    push_file(entry, kind, &mut files, report);
+                }
+            }
+            Err(e) => report.failed.push((path, e)),
+        }
+    }
+    files
+}
+
+/// Adds the file at `path` to `files`, or to the skipped files of `report` if it is special
+fn push_file(path: PathBuf, kind: FileKind, files: &mut Vec<PathBuf>, report: &mut SyncReport) {
+    if kind.is_special() {
+        tracing::debug!(path = %path.display(), %kind, "skipping a special file");
+        report.skipped.push((path, kind));
+    } else {
+        files.push(path);
+    }
+}
+
+/// Checks whether the file at `path` in `vfs` (whose metadata is `current`) differs from its
+/// `latest` backup, which is read from `backend` if the contents need to be compared
+pub(crate) fn has_changed(
+    backend: &dyn StorageBackend,
+    vfs: &dyn Vfs,
+    path: &Path,
+    current: &FsMetadata,
+    latest: Option<&BackupInfo>,
+    mode: SyncMode,
+) -> Result<bool> {
+    // a file that was deleted since its latest backup is back
+    let Some(latest) = latest.filter(|latest| !latest.meta.is_deleted()) else {
+        return Ok(true);
+    };
+    let backed_up = latest.meta.fs_meta();
+    if current.size() != backed_up.size() || current.file_type() != backed_up.file_type() {
+        return Ok(true);
+    }
+    let same_mtime = current.modified() == backed_up.modified();
+    if mode == SyncMode::Quick && same_mtime {
+        return Ok(false);
+    }
+    // only the contents of regular files can be compared cheaply
+    if current.file_type() != FileKind::File {
+        return Ok(!same_mtime);
+    }
+    Ok(fnv1a(&vfs.read(path)?) != annotations::content_hash(backend, latest)?)
+}
@@ -0,0 +1,481 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The file system the backed up files are read from and restored to. A
+//! [`BackupManager`](crate::BackupManager) reaches the files it backs up only through its [`Vfs`],
+//! the real file system ([`RealFs`]) by default. A [`MemoryFs`] keeps the files in memory instead,
+//! e.g. for tests or where there is no file system. Like with the
+//! [`StorageBackend`](crate::StorageBackend), the index, the annotations and the lock of a store
+//! stay in its local store directory either way.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use xstd::{cast::CastFrom, fs::create_write_truncate};
+
+use crate::{symlink, FileKind, FsMetadata, Result, Timestamp};
+
+/// A file system the files that are backed up live in. Errors that stand for a missing file are
+/// [`ErrorKind::NotFound`] IO errors, see [`Error::io_kind`](crate::Error::io_kind).
+pub trait Vfs: fmt::Debug + Send + Sync {
+    /// Opens the file at `path` for reading
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no file at `path` or it cannot be opened
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>>;
+
+    /// Reads the whole file at `path`
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no file at `path` or it cannot be read
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.open(path)?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Creates the file at `path` for writing, truncating it if it exists. The written bytes are
+    /// in place once the writer is flushed or dropped.
+    ///
+    /// ## Errors
+    /// - Returns an error if the file cannot be created
+    fn create(&self, path: &Path) -> Result<Box<dyn Write + '_>>;
+
+    /// Replaces the contents of the file at `path` with `bytes`, creating it if needed
+    ///
+    /// ## Errors
+    /// - Returns an error if the file cannot be created or written
+    fn write(&self, path: &Path, bytes: &[u8]) -> Result {
+        let mut file = self.create(path)?;
+        file.write_all(bytes)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Gets the metadata of the file at `path`, following symbolic links
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no file at `path` or its metadata cannot be read
+    fn metadata(&self, path: &Path) -> Result<FsMetadata>;
+
+    /// Gets the metadata of the file at `path`, of the link itself if it is a symbolic link. The
+    /// default is [`Vfs::metadata`], for file systems without links.
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no file at `path` or its metadata cannot be read
+    fn symlink_metadata(&self, path: &Path) -> Result<FsMetadata> {
+        self.metadata(path)
+    }
+
+    /// Applies the permissions (and extended attributes) of `meta` to the file at `path`. The
+    /// default does nothing, for file systems without them.
+    ///
+    /// ## Errors
+    /// - Returns an error if the permissions cannot be changed
+    fn set_metadata(&self, path: &Path, meta: &FsMetadata) -> Result {
+        let _ = (path, meta);
+        Ok(())
+    }
+
+    /// Lists the entries of the directory at `path`, in no particular order
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no directory at `path` or it cannot be read
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+
+    /// Gets the target of the symbolic link at `path`. The default fails, for file systems
+    /// without links.
+    ///
+    /// ## Errors
+    /// - Returns an error if `path` is not a symbolic link
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        Err(format!("'{}' is not a symbolic link", path.display()).into())
+    }
+
+    /// Creates a symbolic link at `path` pointing to `target`, replacing any file or link that
+    /// is already there. The default fails, for file systems without links.
+    ///
+    /// ## Errors
+    /// - Returns an error if a directory is in the way or the link cannot be created
+    fn symlink(&self, path: &Path, target: &Path) -> Result {
+        let _ = target;
+        Err(format!(
+            "unable to create the link '{}', links are not supported",
+            path.display()
+        )
+        .into())
+    }
+
+    /// Creates the directory at `path` and all of its missing parents
+    ///
+    /// ## Errors
+    /// - Returns an error if a directory cannot be created
+    fn create_dir_all(&self, path: &Path) -> Result;
+
+    /// Removes the file at `path`
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no file at `path` or it cannot be removed
+    fn remove_file(&self, path: &Path) -> Result;
+
+    /// Moves the file at `from` to `to`, replacing any file there
+    ///
+    /// ## Errors
+    /// - Returns an error if there is no file at `from` or it cannot be moved
+    fn rename(&self, from: &Path, to: &Path) -> Result;
+}
+
+/// The real file system, through [`std::fs`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Vfs for RealFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        Ok(Box::new(xstd::fs::read_only().open(path)?))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        Ok(std::fs::read(path)?)
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn Write + '_>> {
+        Ok(Box::new(create_write_truncate().open(path)?))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+        FsMetadata::from_path(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<FsMetadata> {
+        let fs_meta = FsMetadata::from_metadata(&std::fs::symlink_metadata(path)?);
+        #[cfg(feature = "xattr")]
+        let fs_meta = fs_meta.with_xattrs_of(path);
+        Ok(fs_meta)
+    }
+
+    fn set_metadata(&self, path: &Path, meta: &FsMetadata) -> Result {
+        // before the permissions, which may make the file read-only
+        #[cfg(feature = "xattr")]
+        meta.apply_xattrs(path)?;
+        if let Some(permissions) = meta.permissions() {
+            permissions.apply(path)?;
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        std::fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect()
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        Ok(std::fs::read_link(path)?)
+    }
+
+    fn symlink(&self, path: &Path, target: &Path) -> Result {
+        symlink::create(path, &symlink::target_to_bytes(target))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result {
+        Ok(std::fs::create_dir_all(path)?)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result {
+        Ok(std::fs::remove_file(path)?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result {
+        Ok(std::fs::rename(from, to)?)
+    }
+}
+
+/// An entry of a [`MemoryFs`]
+#[derive(Debug, Clone)]
+enum Entry {
+    File { bytes: Vec<u8>, modified: Timestamp },
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// A file system kept in memory. Directories exist as soon as a file is written below them, and
+/// files have no permissions.
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    entries: Mutex<BTreeMap<PathBuf, Entry>>,
+}
+
+/// How many links [`MemoryFs::metadata`] follows before giving up, like the `ELOOP` of the OS
+const MAX_LINKS: usize = 40;
+
+impl MemoryFs {
+    /// Creates a new, empty [`MemoryFs`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the file at `path` with the given `contents`, e.g. to set up a test
+    #[must_use]
+    pub fn with_file(self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Self {
+        self.insert(path.as_ref(), contents.as_ref().to_vec());
+        self
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, Entry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn insert(&self, path: &Path, bytes: Vec<u8>) {
+        let modified = Timestamp::now();
+        self.entries()
+            .insert(path.to_path_buf(), Entry::File { bytes, modified });
+    }
+
+    /// Gets the entry at `path`, a directory if only entries below it exist
+    fn entry(&self, path: &Path) -> Result<Entry> {
+        let entries = self.entries();
+        if let Some(entry) = entries.get(path) {
+            return Ok(entry.clone());
+        }
+        if path.parent().is_none() || entries.keys().any(|entry| entry.starts_with(path)) {
+            return Ok(Entry::Dir);
+        }
+        Err(not_found(path))
+    }
+
+    /// Gets the entry `path` ends up at after following its links
+    fn resolve(&self, path: &Path) -> Result<(PathBuf, Entry)> {
+        let mut path = path.to_path_buf();
+        for _ in 0..MAX_LINKS {
+            match self.entry(&path)? {
+                Entry::Symlink(target) => {
+                    path = path.parent().unwrap_or(Path::new("")).join(target);
+                }
+                entry => return Ok((path, entry)),
+            }
+        }
+        Err(format!("too many levels of links at '{}'", path.display()).into())
+    }
+}
+
+fn not_found(path: &Path) -> crate::Error {
+    std::io::Error::new(
+        ErrorKind::NotFound,
+        format!("no such file '{}'", path.display()),
+    )
+    .into()
+}
+
+fn fs_meta(entry: &Entry) -> FsMetadata {
+    match entry {
+        Entry::File { bytes, modified } => FsMetadata::new(
+            None,
+            Some(*modified),
+            None,
+            u64::cast_from(bytes.len()),
+            FileKind::File,
+        ),
+        Entry::Dir => FsMetadata::new(None, None, None, 0, FileKind::Dir),
+        Entry::Symlink(target) => FsMetadata::new(
+            None,
+            None,
+            None,
+            u64::cast_from(symlink::target_to_bytes(target).len()),
+            FileKind::Symlink,
+        ),
+    }
+}
+
+/// A file of a [`MemoryFs`] being written, the bytes are stored when it is flushed or dropped
+struct MemoryFile<'a> {
+    fs: &'a MemoryFs,
+    path: PathBuf,
+    bytes: Vec<u8>,
+}
+
+impl Write for MemoryFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.fs.insert(&self.path, self.bytes.clone());
+        Ok(())
+    }
+}
+
+impl Drop for MemoryFile<'_> {
+    fn drop(&mut self) {
+        self.fs.insert(&self.path, std::mem::take(&mut self.bytes));
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        Ok(Box::new(std::io::Cursor::new(self.read(path)?)))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        match self.resolve(path)? {
+            (_, Entry::File { bytes, .. }) => Ok(bytes),
+            _ => Err(format!("'{}' is a directory", path.display()).into()),
+        }
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn Write + '_>> {
+        if matches!(self.entry(path), Ok(Entry::Dir)) {
+            return Err(format!("'{}' is a directory", path.display()).into());
+        }
+        // the file exists right away, like on a real file system
+        self.insert(path, Vec::new());
+        Ok(Box::new(MemoryFile {
+            fs: self,
+            path: path.to_path_buf(),
+            bytes: Vec::new(),
+        }))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+        Ok(fs_meta(&self.resolve(path)?.1))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<FsMetadata> {
+        Ok(fs_meta(&self.entry(path)?))
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let (path, entry) = self.resolve(path)?;
+        if !matches!(entry, Entry::Dir) {
+            return Err(format!("'{}' is not a directory", path.display()).into());
+        }
+        let mut children = self
+            .entries()
+            .keys()
+            .filter_map(|entry| {
+                let rest = entry.strip_prefix(&path).ok()?;
+                Some(path.join(rest.components().next()?))
+            })
+            .collect::<Vec<_>>();
+        children.dedup();
+        Ok(children)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        match self.entry(path)? {
+            Entry::Symlink(target) => Ok(target),
+            _ => Err(format!("'{}' is not a symbolic link", path.display()).into()),
+        }
+    }
+
+    fn symlink(&self, path: &Path, target: &Path) -> Result {
+        if matches!(self.entry(path), Ok(Entry::Dir)) {
+            return Err(format!(
+                "unable to restore link '{}', a directory is in the way",
+                path.display()
+            )
+            .into());
+        }
+        self.entries()
+            .insert(path.to_path_buf(), Entry::Symlink(target.to_path_buf()));
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result {
+        let mut entries = self.entries();
+        for dir in path.ancestors().filter(|dir| dir.parent().is_some()) {
+            match entries.get(dir) {
+                Some(Entry::Dir) => {}
+                Some(_) => {
+                    return Err(format!("'{}' is not a directory", dir.display()).into());
+                }
+                None => {
+                    entries.insert(dir.to_path_buf(), Entry::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result {
+        let mut entries = self.entries();
+        match entries.get(path) {
+            Some(Entry::File { .. } | Entry::Symlink(_)) => {
+                entries.remove(path);
+                Ok(())
+            }
+            Some(Entry::Dir) => Err(format!("'{}' is a directory", path.display()).into()),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result {
+        let mut entries = self.entries();
+        let Some(entry) = entries.remove(from) else {
+            return Err(not_found(from));
+        };
+        entries.insert(to.to_path_buf(), entry);
+        Ok(())
+    }
+}
+
+/// Reads the target of the link at `path` in `vfs`, encoded as by
+/// [`target_to_bytes`](symlink::target_to_bytes)
+pub(crate) fn read_target(vfs: &dyn Vfs, path: &Path) -> Result<Vec<u8>> {
+    Ok(symlink::target_to_bytes(&vfs.read_link(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_files() {
+        let fs = MemoryFs::new().with_file("/home/me/notes.txt", "notes");
+        assert_eq!(fs.read(Path::new("/home/me/notes.txt")).unwrap(), b"notes");
+        assert_eq!(
+            fs.metadata(Path::new("/home/me")).unwrap().file_type(),
+            FileKind::Dir
+        );
+        let missing = fs.read(Path::new("/home/me/missing")).unwrap_err();
+        assert_eq!(missing.io_kind(), Some(ErrorKind::NotFound));
+
+        fs.write(Path::new("/home/me/todo.txt"), b"todo").unwrap();
+        fs.symlink(Path::new("/home/me/link"), Path::new("todo.txt"))
+            .unwrap();
+        let mut entries = fs.read_dir(Path::new("/home")).unwrap();
+        assert_eq!(entries, [PathBuf::from("/home/me")]);
+        entries = fs.read_dir(Path::new("/home/me")).unwrap();
+        entries.sort();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(fs.read(Path::new("/home/me/link")).unwrap(), b"todo");
+        assert_eq!(
+            fs.symlink_metadata(Path::new("/home/me/link"))
+                .unwrap()
+                .file_type(),
+            FileKind::Symlink
+        );
+        assert_eq!(fs.metadata(Path::new("/home/me/link")).unwrap().size(), 4);
+
+        fs.rename(
+            Path::new("/home/me/todo.txt"),
+            Path::new("/home/me/done.txt"),
+        )
+        .unwrap();
+        assert!(fs.read(Path::new("/home/me/link")).is_err());
+        fs.remove_file(Path::new("/home/me/done.txt")).unwrap();
+        assert!(fs.remove_file(Path::new("/home/me/done.txt")).is_err());
+        assert!(fs.remove_file(Path::new("/home/me")).is_err());
+    }
+}
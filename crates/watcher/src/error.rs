@@ -0,0 +1,200 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! What went wrong while watching, sorted by what the caller can do about it. [`notify`] reports
+//! a missing path, a path that may not be read and an exhausted watch limit all as errors of the
+//! same type, which end up flattened into the common [`Error`]. A [`WatchError`] tells them apart
+//! again, so a caller can decide whether to retry, skip the path or give up.
+
+use std::{
+    fmt,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use storage_common::Error;
+
+/// What a caller should do about a [`WatchError`], see [`WatchError::action`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchErrorAction {
+    /// Try again later, the error is likely to go away on its own
+    Retry,
+    /// Give up on the path the error is about, the other paths are not affected
+    Skip,
+    /// Stop watching, no other path is going to fare better
+    Abort,
+}
+
+/// An error of a file watcher, classified by whether it is fatal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchError {
+    /// The process may not read a watched path
+    PermissionDenied {
+        /// The path that may not be read, if known
+        path: Option<PathBuf>,
+    },
+    /// A watched path does not exist (any more)
+    PathMissing {
+        /// The missing path, if known
+        path: Option<PathBuf>,
+    },
+    /// The OS limit on the number of watches is reached, e.g. inotify's `max_user_watches`
+    WatchLimitReached,
+    /// Any other error, which is assumed to be temporary
+    Transient(String),
+}
+
+impl WatchError {
+    /// Classifies an error reported by [`notify`]
+    #[must_use]
+    pub fn from_notify(err: &notify::Error) -> Self {
+        let path = err.paths.first().cloned();
+        match &err.kind {
+            notify::ErrorKind::MaxFilesWatch => Self::WatchLimitReached,
+            notify::ErrorKind::PathNotFound | notify::ErrorKind::WatchNotFound => {
+                Self::PathMissing { path }
+            }
+            notify::ErrorKind::Io(io) => match io.kind() {
+                ErrorKind::PermissionDenied => Self::PermissionDenied { path },
+                ErrorKind::NotFound => Self::PathMissing { path },
+                _ => Self::Transient(err.to_string()),
+            },
+            notify::ErrorKind::Generic(_) | notify::ErrorKind::InvalidConfig(_) => {
+                Self::Transient(err.to_string())
+            }
+        }
+    }
+
+    /// Classifies an error returned by a [`FileWatcher`](crate::FileWatcher), looking through
+    /// any context added to it
+    #[must_use]
+    pub fn classify(err: &Error) -> Self {
+        match err.root_cause() {
+            Error::Notify(notify) => Self::from_notify(notify),
+            Error::Io(io) if io.kind() == ErrorKind::PermissionDenied => {
+                Self::PermissionDenied { path: None }
+            }
+            Error::Io(io) if io.kind() == ErrorKind::NotFound => Self::PathMissing { path: None },
+            _ => Self::Transient(err.to_string()),
+        }
+    }
+
+    /// Gets the path the error is about, if known
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::PermissionDenied { path } | Self::PathMissing { path } => path.as_deref(),
+            Self::WatchLimitReached | Self::Transient(_) => None,
+        }
+    }
+
+    /// Gets what to do about the error
+    #[must_use]
+    pub fn action(&self) -> WatchErrorAction {
+        match self {
+            Self::PermissionDenied { .. } | Self::PathMissing { .. } => WatchErrorAction::Skip,
+            Self::WatchLimitReached => WatchErrorAction::Abort,
+            Self::Transient(_) => WatchErrorAction::Retry,
+        }
+    }
+
+    /// Returns true if watching cannot go on, see [`WatchErrorAction::Abort`]
+    #[must_use]
+    pub fn is_fatal(&self) -> bool {
+        self.action() == WatchErrorAction::Abort
+    }
+
+    /// Gets a hint on how to fix the cause of the error, meant for the user
+    #[must_use]
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            Self::PermissionDenied { .. } => {
+                "make the path readable by the user running the watcher, or stop tracking it"
+            }
+            Self::PathMissing { .. } => {
+                "the path is watched again once it is created, stop tracking it if it is gone for good"
+            }
+            Self::WatchLimitReached => {
+                "raise the limit, e.g. `sysctl fs.inotify.max_user_watches=524288`, or set \
+                 `watcher` to `poll` in the config"
+            }
+            Self::Transient(_) => "this is likely temporary, try again later",
+        }
+    }
+
+    /// Gets a short, stable name for the kind of this error, e.g. `path_missing`
+    #[must_use]
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::PermissionDenied { .. } => "permission_denied",
+            Self::PathMissing { .. } => "path_missing",
+            Self::WatchLimitReached => "watch_limit_reached",
+            Self::Transient(_) => "transient",
+        }
+    }
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PermissionDenied { path: Some(path) } => {
+                write!(f, "permission denied for '{}'", path.display())
+            }
+            Self::PermissionDenied { path: None } => f.write_str("permission denied"),
+            Self::PathMissing { path: Some(path) } => {
+                write!(f, "'{}' does not exist", path.display())
+            }
+            Self::PathMissing { path: None } => f.write_str("path does not exist"),
+            Self::WatchLimitReached => f.write_str("the limit on the number of watches is reached"),
+            Self::Transient(err) => f.write_str(err),
+        }
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+impl From<&notify::Error> for WatchError {
+    fn from(err: &notify::Error) -> Self {
+        Self::from_notify(err)
+    }
+}
+
+impl From<&Error> for WatchError {
+    fn from(err: &Error) -> Self {
+        Self::classify(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let limit = Error::from(notify::Error::new(notify::ErrorKind::MaxFilesWatch))
+            .context("unable to watch '/tmp'");
+        assert_eq!(WatchError::classify(&limit), WatchError::WatchLimitReached);
+        assert!(WatchError::classify(&limit).is_fatal());
+
+        let denied = notify::Error::io(std::io::Error::from(ErrorKind::PermissionDenied))
+            .add_path(PathBuf::from("/root"));
+        let denied = WatchError::from_notify(&denied);
+        assert_eq!(denied.path(), Some(Path::new("/root")));
+        assert_eq!(denied.action(), WatchErrorAction::Skip);
+
+        let missing = notify::Error::path_not_found().add_path(PathBuf::from("/gone"));
+        assert_eq!(
+            WatchError::from_notify(&missing),
+            WatchError::PathMissing {
+                path: Some(PathBuf::from("/gone"))
+            }
+        );
+
+        let other = WatchError::classify(&Error::from("inotify queue overflowed"));
+        assert_eq!(other.action(), WatchErrorAction::Retry);
+        assert_eq!(other.category(), "transient");
+    }
+}
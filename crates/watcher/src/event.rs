@@ -0,0 +1,165 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use storage_common::EventKinds;
+
+/// A change to a watched path, independent of the [`FileWatcher`](crate::FileWatcher)
+/// implementation that detected it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WatchEvent {
+    /// A file or directory was created
+    Created(PathBuf),
+    /// The contents of a file were modified
+    Modified(PathBuf),
+    /// A file or directory was removed
+    Removed(PathBuf),
+    /// A file or directory was renamed (or moved)
+    Renamed {
+        /// The previous path
+        from: PathBuf,
+        /// The new path
+        to: PathBuf,
+    },
+    /// The metadata (permissions, timestamps, ownership, ...) of a path changed
+    MetadataChanged(PathBuf),
+    /// A file was read or opened without being changed, only reported if
+    /// [`EventKinds::ACCESS`] is watched for
+    Accessed(PathBuf),
+}
+
+impl WatchEvent {
+    /// Gets the path(s) affected by this event. For [`WatchEvent::Renamed`] this is the previous
+    /// path followed by the new one.
+    #[must_use]
+    pub fn paths(&self) -> Vec<&Path> {
+        match self {
+            Self::Created(path)
+            | Self::Modified(path)
+            | Self::Removed(path)
+            | Self::MetadataChanged(path)
+            | Self::Accessed(path) => vec![path],
+            Self::Renamed { from, to } => vec![from, to],
+        }
+    }
+
+    /// Gets the kind of this event, as filtered by [`EventKinds`]
+    #[must_use]
+    pub fn kind(&self) -> EventKinds {
+        match self {
+            Self::Created(_) => EventKinds::CREATE,
+            Self::Modified(_) => EventKinds::MODIFY,
+            Self::Removed(_) => EventKinds::REMOVE,
+            Self::Renamed { .. } => EventKinds::RENAME,
+            Self::MetadataChanged(_) => EventKinds::METADATA,
+            Self::Accessed(_) => EventKinds::ACCESS,
+        }
+    }
+
+    /// Translates a [`notify::Event`] into zero or more [`WatchEvent`]s
+    pub(crate) fn from_notify(event: notify::Event) -> Vec<Self> {
+        let notify::Event { kind, paths, .. } = event;
+        match kind {
+            EventKind::Access(_) => paths.into_iter().map(Self::Accessed).collect(),
+            EventKind::Create(_) => paths.into_iter().map(Self::Created).collect(),
+            EventKind::Remove(_) => paths.into_iter().map(Self::Removed).collect(),
+            EventKind::Modify(ModifyKind::Metadata(_)) => {
+                paths.into_iter().map(Self::MetadataChanged).collect()
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+                let mut paths = paths.into_iter();
+                match (paths.next(), paths.next()) {
+                    (Some(from), Some(to)) => vec![Self::Renamed { from, to }],
+                    _ => unreachable!("paths has exactly two elements"),
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                paths.into_iter().map(Self::Removed).collect()
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                paths.into_iter().map(Self::Created).collect()
+            }
+            // the backend could not tell which side of the rename this is, so check the disk
+            EventKind::Modify(ModifyKind::Name(_)) => paths
+                .into_iter()
+                .map(|path| {
+                    if path.exists() {
+                        Self::Created(path)
+                    } else {
+                        Self::Removed(path)
+                    }
+                })
+                .collect(),
+            EventKind::Modify(_) | EventKind::Any | EventKind::Other => {
+                paths.into_iter().map(Self::Modified).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, MetadataKind};
+
+    fn event(kind: EventKind, paths: &[&str]) -> notify::Event {
+        paths.iter().fold(notify::Event::new(kind), |event, path| {
+            event.add_path(path.into())
+        })
+    }
+
+    #[test]
+    fn translates_notify_events() {
+        let a = PathBuf::from("/a");
+        let b = PathBuf::from("/b");
+        assert_eq!(
+            WatchEvent::from_notify(event(EventKind::Create(CreateKind::File), &["/a", "/b"])),
+            [
+                WatchEvent::Created(a.clone()),
+                WatchEvent::Created(b.clone())
+            ]
+        );
+        assert_eq!(
+            WatchEvent::from_notify(event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &["/a", "/b"]
+            )),
+            [WatchEvent::Renamed {
+                from: a.clone(),
+                to: b.clone()
+            }]
+        );
+        assert_eq!(
+            WatchEvent::from_notify(event(
+                EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+                &["/a"]
+            )),
+            [WatchEvent::MetadataChanged(a.clone())]
+        );
+        assert_eq!(
+            WatchEvent::from_notify(event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                &["/a"]
+            )),
+            [WatchEvent::Removed(a.clone())]
+        );
+        assert_eq!(
+            WatchEvent::from_notify(event(EventKind::Access(AccessKind::Read), &["/a"])),
+            [WatchEvent::Accessed(a.clone())]
+        );
+        assert_eq!(WatchEvent::Accessed(a.clone()).kind(), EventKinds::ACCESS);
+        assert_eq!(
+            WatchEvent::Renamed {
+                from: a.clone(),
+                to: b.clone()
+            }
+            .paths(),
+            [a.as_path(), b.as_path()]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Dropping the events of kinds nobody asked for, e.g. access-time churn, before a
+//! [`NotifyWatcher`](crate::NotifyWatcher) reports them. The kinds are set globally and can be
+//! replaced per watched path, see [`TrackedPath::events`](storage_common::TrackedPath::events).
+
+use std::path::{Path, PathBuf};
+
+use storage_common::{EventKinds, TrackedPath};
+
+use crate::WatchEvent;
+
+/// The kinds of events reported for the watched paths
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct EventFilter {
+    global: EventKinds,
+    paths: Vec<(PathBuf, EventKinds)>,
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        Self::new(EventKinds::DEFAULT)
+    }
+}
+
+impl EventFilter {
+    /// Creates a new [`EventFilter`] reporting the `global` kinds for every path
+    pub(crate) fn new(global: EventKinds) -> Self {
+        Self {
+            global,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Creates a new [`EventFilter`] reporting the `global` kinds, except for the entries of
+    /// `tracked` with kinds of their own
+    pub(crate) fn for_tracked(global: EventKinds, tracked: &[TrackedPath]) -> Self {
+        let mut filter = Self::new(global);
+        for entry in tracked {
+            filter.set_path(entry.path(), entry.events());
+        }
+        filter
+    }
+
+    /// Gets the kinds reported for paths without kinds of their own
+    pub(crate) fn global(&self) -> EventKinds {
+        self.global
+    }
+
+    /// Sets the kinds reported for paths without kinds of their own
+    pub(crate) fn set_global(&mut self, kinds: EventKinds) {
+        self.global = kinds;
+    }
+
+    /// Sets the kinds reported for `path` and the paths below it, `None` falls back to the
+    /// global kinds
+    pub(crate) fn set_path(&mut self, path: &Path, kinds: Option<EventKinds>) {
+        self.paths.retain(|(known, _)| known != path);
+        if let Some(kinds) = kinds {
+            self.paths.push((path.to_path_buf(), kinds));
+        }
+    }
+
+    /// Gets the kinds reported for `path`, those of the closest path set for it or one of its
+    /// ancestors, or else the global kinds
+    pub(crate) fn kinds_for(&self, path: &Path) -> EventKinds {
+        self.paths
+            .iter()
+            .filter(|(known, _)| path.starts_with(known))
+            .max_by_key(|(known, _)| known.components().count())
+            .map_or(self.global, |(_, kinds)| *kinds)
+    }
+
+    /// Returns true if `event` is reported, i.e. its kind is reported for any of its paths
+    pub(crate) fn accepts(&self, event: &WatchEvent) -> bool {
+        event
+            .paths()
+            .into_iter()
+            .any(|path| self.kinds_for(path).contains(event.kind()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_by_path() {
+        let notes = PathBuf::from("/home/me/notes");
+        let todo = notes.join("todo.md");
+        let other = PathBuf::from("/home/me/other.txt");
+        let mut filter = EventFilter::default();
+        assert!(filter.accepts(&WatchEvent::Modified(other.clone())));
+        assert!(!filter.accepts(&WatchEvent::Accessed(other.clone())));
+
+        filter.set_path(&notes, Some(EventKinds::MODIFY));
+        assert!(filter.accepts(&WatchEvent::Modified(todo.clone())));
+        assert!(!filter.accepts(&WatchEvent::MetadataChanged(todo.clone())));
+        assert!(filter.accepts(&WatchEvent::MetadataChanged(other.clone())));
+
+        // the closest path wins, renames pass if either side wants them
+        filter.set_path(&todo, Some(EventKinds::all()));
+        assert!(filter.accepts(&WatchEvent::Accessed(todo.clone())));
+        assert!(!filter.accepts(&WatchEvent::Renamed {
+            from: notes.join("a.md"),
+            to: notes.join("b.md"),
+        }));
+        assert!(filter.accepts(&WatchEvent::Renamed {
+            from: notes.join("a.md"),
+            to: other.clone(),
+        }));
+
+        filter.set_path(&notes, None);
+        filter.set_global(EventKinds::empty());
+        assert_eq!(filter.kinds_for(&notes.join("a.md")), EventKinds::empty());
+        assert_eq!(filter.kinds_for(&todo), EventKinds::all());
+    }
+}
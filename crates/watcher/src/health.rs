@@ -0,0 +1,274 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The health of the paths watched by a [`NotifyWatcher`](crate::NotifyWatcher), see
+//! [`NotifyWatcher::health`](crate::NotifyWatcher::health) and
+//! [`PollingWatcher::health`](crate::PollingWatcher::health). A watch the OS dropped does not
+//! produce an error, it just goes quiet, so the last event and error of every watched path are
+//! kept to tell a quiet path from a dead one.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use storage_common::Timestamp;
+use xstd::display::format_duration;
+
+/// Whether a watched path is registered with the OS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchState {
+    /// The path is registered and its changes are reported
+    Watching,
+    /// The path is registered but its changes are dropped until the watcher is resumed
+    Paused,
+    /// The watcher is not started, nothing is registered
+    Stopped,
+    /// The path was removed, it is registered again once it reappears
+    Lost,
+    /// The path was removed and re-registering it failed too often, it is no longer watched
+    Failed,
+    /// The path does not exist yet, it is registered once it is created
+    Pending,
+    /// The OS limit on watches was reached, the path is polled for changes instead
+    Polling,
+}
+
+impl WatchState {
+    /// Gets the state as a string, e.g. `watching`
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Watching => "watching",
+            Self::Paused => "paused",
+            Self::Stopped => "stopped",
+            Self::Lost => "lost",
+            Self::Failed => "failed",
+            Self::Pending => "pending",
+            Self::Polling => "polling",
+        }
+    }
+}
+
+impl fmt::Display for WatchState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The health of a single watched path, as reported by
+/// [`NotifyWatcher::health`](crate::NotifyWatcher::health)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchStatus {
+    path: PathBuf,
+    state: WatchState,
+    last_event: Option<Timestamp>,
+    last_error: Option<String>,
+}
+
+impl WatchStatus {
+    /// Creates the status of `path` in `state`, without events or errors
+    pub(crate) fn new(path: &Path, state: WatchState) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            state,
+            last_event: None,
+            last_error: None,
+        }
+    }
+
+    /// Gets the watched path
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets whether the path is registered with the OS
+    #[must_use]
+    pub fn state(&self) -> WatchState {
+        self.state
+    }
+
+    /// Gets when the latest event for the path was received, if any was since it was watched
+    #[must_use]
+    pub fn last_event(&self) -> Option<Timestamp> {
+        self.last_event
+    }
+
+    /// Gets the latest error reported for the path, if any
+    #[must_use]
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Returns true if changes of the path are reported, or will be once the watcher is
+    /// started or resumed, or the path is created
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        matches!(
+            self.state,
+            WatchState::Watching
+                | WatchState::Paused
+                | WatchState::Stopped
+                | WatchState::Pending
+                | WatchState::Polling
+        )
+    }
+}
+
+/// Formats the status for people, e.g. `/home/me/notes.md: watching (last event 2m 5s ago)`
+impl fmt::Display for WatchStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.state)?;
+        match self.last_event {
+            Some(last_event) => {
+                let ago = Timestamp::now()
+                    .as_duration()
+                    .saturating_sub(last_event.as_duration());
+                write!(f, " (last event {} ago)", format_duration(ago))?;
+            }
+            None => f.write_str(" (no events yet)")?,
+        }
+        if let Some(err) = &self.last_error {
+            write!(f, " - last error: {err}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What is known about a watched path beyond whether the watcher is running
+#[derive(Debug, Clone, Default)]
+struct PathHealth {
+    /// [`WatchState::Lost`], [`WatchState::Failed`], [`WatchState::Pending`] or
+    /// [`WatchState::Polling`], `None` while the path is registered
+    state: Option<WatchState>,
+    last_event: Option<Timestamp>,
+    last_error: Option<String>,
+}
+
+/// The health of every watched path, shared between a
+/// [`NotifyWatcher`](crate::NotifyWatcher), its event handler and its recovery loop
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HealthTracker(Arc<Mutex<HashMap<PathBuf, PathHealth>>>);
+
+impl HealthTracker {
+    fn update(&self, path: &Path, update: impl FnOnce(&mut PathHealth)) {
+        let mut paths = self.0.lock().expect("mutex poisoned");
+        update(paths.entry(path.to_path_buf()).or_default());
+    }
+
+    /// Records that an event was received for the watched `path`
+    pub(crate) fn event(&self, path: &Path) {
+        self.update(path, |health| health.last_event = Some(Timestamp::now()));
+    }
+
+    /// Records `err` as the latest error of the watched `path`
+    pub(crate) fn error(&self, path: &Path, err: &impl ToString) {
+        self.update(path, |health| health.last_error = Some(err.to_string()));
+    }
+
+    /// Records that the watched `path` was removed and is no longer registered
+    pub(crate) fn lost(&self, path: &Path) {
+        self.update(path, |health| health.state = Some(WatchState::Lost));
+    }
+
+    /// Records that the watched `path` does not exist yet and is registered once it is created
+    pub(crate) fn pending(&self, path: &Path) {
+        self.update(path, |health| health.state = Some(WatchState::Pending));
+    }
+
+    /// Records that the watched `path` is polled, as the OS limit on watches was reached
+    pub(crate) fn polled(&self, path: &Path) {
+        self.update(path, |health| health.state = Some(WatchState::Polling));
+    }
+
+    /// Records that the watched `path` is registered again
+    pub(crate) fn registered(&self, path: &Path) {
+        self.update(path, |health| health.state = None);
+    }
+
+    /// Records that the watched `path` will not be registered again, because of `err`
+    pub(crate) fn failed(&self, path: &Path, err: &impl ToString) {
+        self.update(path, |health| {
+            health.state = Some(WatchState::Failed);
+            health.last_error = Some(err.to_string());
+        });
+    }
+
+    /// Forgets everything about `path` once it is no longer watched
+    pub(crate) fn forget(&self, path: &Path) {
+        self.0.lock().expect("mutex poisoned").remove(path);
+    }
+
+    /// Forgets every path that is not in `watched`
+    pub(crate) fn retain(&self, watched: &[String]) {
+        self.0
+            .lock()
+            .expect("mutex poisoned")
+            .retain(|path, _| watched.iter().any(|file| Path::new(file) == path));
+    }
+
+    /// Gets the status of the watched `path`, in `state` unless it was lost or given up on
+    pub(crate) fn status(&self, path: &Path, state: WatchState) -> WatchStatus {
+        let health = self
+            .0
+            .lock()
+            .expect("mutex poisoned")
+            .get(path)
+            .cloned()
+            .unwrap_or_default();
+        WatchStatus {
+            path: path.to_path_buf(),
+            state: health.state.unwrap_or(state),
+            last_event: health.last_event,
+            last_error: health.last_error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_status() {
+        let tracker = HealthTracker::default();
+        let path = Path::new("/tmp/notes.md");
+        assert_eq!(
+            tracker.status(path, WatchState::Watching).to_string(),
+            "/tmp/notes.md: watching (no events yet)"
+        );
+
+        tracker.event(path);
+        let status = tracker.status(path, WatchState::Watching);
+        assert!(status.is_healthy());
+        assert!(
+            status
+                .to_string()
+                .starts_with("/tmp/notes.md: watching (last event "),
+            "{status}"
+        );
+
+        tracker.lost(path);
+        tracker.failed(path, &"gave up");
+        let status = tracker.status(path, WatchState::Watching);
+        assert_eq!(status.state(), WatchState::Failed);
+        assert!(!status.is_healthy());
+        assert!(status.to_string().ends_with(" ago) - last error: gave up"));
+
+        tracker.registered(path);
+        assert_eq!(
+            tracker.status(path, WatchState::Paused).state(),
+            WatchState::Paused
+        );
+        tracker.retain(&[]);
+        assert_eq!(tracker.status(path, WatchState::Stopped).last_event(), None);
+    }
+}
@@ -37,24 +37,40 @@
         rust_2021_compatibility
     )
 )]
-#![feature(associated_type_defaults)]
-
+mod error;
+mod event;
+mod filter;
+mod health;
+mod limits;
+mod polling;
 mod watcher;
 
-pub use watcher::{NotifyEvent, NotifyWatcher};
+pub use crossbeam_channel::Receiver;
+pub use error::{WatchError, WatchErrorAction};
+pub use event::WatchEvent;
+pub use health::{WatchState, WatchStatus};
+pub use limits::WatchLimit;
+/// An event of the underlying [`notify`] watcher, see [`NotifyWatcher::subscribe_raw`]
+pub use notify::Event as RawEvent;
+pub use polling::PollingWatcher;
+pub use watcher::{NotifyWatcher, RecoveryErrorCallback, RecoveryOptions};
 
-pub(crate) use storage_common::{Config, Result};
+use std::path::Path;
 
-/// A trait describing the behavior and available functions for a file watcher
-pub trait FileWatcher: Send {
-    /// The type of the inner configuration used by the file watcher, if any
-    type InnerConfig = ();
+pub(crate) use storage_common::{Config, Result, WatcherKind};
 
+/// A trait describing the behavior and available functions for a file watcher. Watchers with
+/// settings of their own beyond the [application config](Config) also implement
+/// [`ConfigurableFileWatcher`].
+pub trait FileWatcher: Send {
     /// Returns a list of all files currently being watched
     ///
     /// ## Errors
     /// - Errors only if the currently watched files cannot be read (e.g. mutex is poisoned). Very unlikely.
     fn currently_watched(&self) -> Result<Vec<String>>;
+    /// Returns a receiver for the [`WatchEvent`]s produced by this watcher. All receivers
+    /// returned from this function share the same queue, so each event is delivered once.
+    fn events(&self) -> Receiver<WatchEvent>;
     /// Applies the [application config](Config) to the file watcher
     ///
     /// ## Errors
@@ -70,22 +86,34 @@ pub trait FileWatcher: Send {
     /// ## Errors
     /// - Any errors returned while attempting to stop the file watcher
     fn stop(&mut self) -> Result;
-
-    /// Applies both the [application config](storage_common::Config) as well as the [inner config](FileWatcher::InnerConfig)
-    /// and starts the file watcher.
-    /// Default implementation simply calls [`FileWatcher::apply_inner_config`], [`FileWatcher::apply_app_config`] and then [`FileWatcher::start`].
+    /// Adds a single path to the watched files without restarting the watcher. If the watcher is
+    /// running, changes to `path` are reported from now on. Watching a path twice has no effect.
     ///
     /// ## Errors
-    /// - Any errors that occur during configuration or start-up will be propagated
-    fn start_with_config(
-        &mut self,
-        app_config: &Config,
-        impl_config: &Self::InnerConfig,
-    ) -> Result {
-        self.apply_app_config(app_config)?;
-        self.apply_inner_config(impl_config)?;
-        self.start()
-    }
+    /// - Errors if `path` is not valid utf-8
+    /// - Errors if the watcher is running and `path` cannot be watched (e.g. it does not exist)
+    fn watch_path(&mut self, path: &Path) -> Result;
+    /// Removes a single path from the watched files without restarting the watcher. Unwatching
+    /// a path that is not watched has no effect.
+    ///
+    /// ## Errors
+    /// - Errors if the watcher is running and fails to stop watching `path`
+    fn unwatch_path(&mut self, path: &Path) -> Result;
+    /// Pauses the file watcher. The watched paths stay registered, but changes made while
+    /// paused are not reported, not even after [`FileWatcher::resume`].
+    ///
+    /// ## Errors
+    /// - Errors if the watcher fails to pause
+    fn pause(&mut self) -> Result;
+    /// Resumes a watcher paused with [`FileWatcher::pause`], resuming an unpaused watcher has no
+    /// effect.
+    ///
+    /// ## Errors
+    /// - Errors if the watcher fails to resume
+    fn resume(&mut self) -> Result;
+    /// Returns true if the file watcher is paused
+    fn is_paused(&self) -> bool;
+
     /// Applies the [application config](storage_common::Config) and starts the file watcher.
     /// Default implementation simple calls [`FileWatcher::apply_app_config`] and then [`FileWatcher::start`].
     ///
@@ -95,13 +123,34 @@ pub trait FileWatcher: Send {
         self.apply_app_config(config)?;
         self.start()
     }
-    /// Applies the given [inner config](FileWatcher::InnerConfig).
-    /// **Default implementation simply returns Ok(()) so if you are writing a custom implementation be sure to override.**
+}
+
+/// A [`FileWatcher`] with a typed configuration of its own, applied on top of the
+/// [application config](Config)
+pub trait ConfigurableFileWatcher: FileWatcher {
+    /// The type of the inner configuration used by the file watcher
+    type InnerConfig;
+
+    /// Applies the given [inner config](ConfigurableFileWatcher::InnerConfig).
+    ///
+    /// ## Errors
+    /// - Any errors that occur during configuration will be propagated
+    fn apply_inner_config(&mut self, config: &Self::InnerConfig) -> Result;
+
+    /// Applies both the [application config](storage_common::Config) as well as the [inner config](ConfigurableFileWatcher::InnerConfig)
+    /// and starts the file watcher.
+    /// Default implementation simply calls [`FileWatcher::apply_app_config`], [`ConfigurableFileWatcher::apply_inner_config`] and then [`FileWatcher::start`].
     ///
     /// ## Errors
     /// - Any errors that occur during configuration or start-up will be propagated
-    fn apply_inner_config(&mut self, _config: &Self::InnerConfig) -> Result {
-        Ok(())
+    fn start_with_config(
+        &mut self,
+        app_config: &Config,
+        impl_config: &Self::InnerConfig,
+    ) -> Result {
+        self.apply_app_config(app_config)?;
+        self.apply_inner_config(impl_config)?;
+        self.start()
     }
 }
 
@@ -112,3 +161,123 @@ pub trait FileWatcher: Send {
 pub fn create_file_watcher() -> Result<impl FileWatcher> {
     watcher::NotifyWatcher::new()
 }
+
+/// Creates the file watcher selected by [`Config::watcher`], configured from `config`
+///
+/// ## Errors
+/// - Errors if the file watcher cannot be created
+/// - Errors if the tracked files cannot be read, or the watcher rejects the configuration
+pub fn create_file_watcher_for(config: &Config) -> Result<ConfiguredWatcher> {
+    let mut watcher = match config.watcher() {
+        WatcherKind::Notify => ConfiguredWatcher::Notify(NotifyWatcher::new()?),
+        WatcherKind::Poll => ConfiguredWatcher::Poll(PollingWatcher::new(
+            std::time::Duration::from_millis(config.delay()),
+        )),
+    };
+    watcher.apply_app_config(config)?;
+    Ok(watcher)
+}
+
+/// A file watcher of the kind selected at runtime through [`Config::watcher`]
+#[derive(Debug)]
+pub enum ConfiguredWatcher {
+    /// See [`NotifyWatcher`]
+    Notify(NotifyWatcher),
+    /// See [`PollingWatcher`]
+    Poll(PollingWatcher),
+}
+
+impl ConfiguredWatcher {
+    /// Gets the health of every path on the watch list, see [`NotifyWatcher::health`] and
+    /// [`PollingWatcher::health`]
+    #[must_use]
+    pub fn health(&self) -> Vec<WatchStatus> {
+        match self {
+            Self::Notify(watcher) => watcher.health(),
+            Self::Poll(watcher) => watcher.health(),
+        }
+    }
+}
+
+impl FileWatcher for ConfiguredWatcher {
+    fn currently_watched(&self) -> Result<Vec<String>> {
+        match self {
+            Self::Notify(watcher) => watcher.currently_watched(),
+            Self::Poll(watcher) => watcher.currently_watched(),
+        }
+    }
+
+    fn events(&self) -> Receiver<WatchEvent> {
+        match self {
+            Self::Notify(watcher) => watcher.events(),
+            Self::Poll(watcher) => watcher.events(),
+        }
+    }
+
+    fn apply_app_config(&mut self, config: &Config) -> Result {
+        match self {
+            Self::Notify(watcher) => watcher.apply_app_config(config),
+            Self::Poll(watcher) => watcher.apply_app_config(config),
+        }
+    }
+
+    fn start(&mut self) -> Result {
+        match self {
+            Self::Notify(watcher) => watcher.start(),
+            Self::Poll(watcher) => watcher.start(),
+        }
+    }
+
+    fn stop(&mut self) -> Result {
+        match self {
+            Self::Notify(watcher) => watcher.stop(),
+            Self::Poll(watcher) => watcher.stop(),
+        }
+    }
+
+    fn watch_path(&mut self, path: &Path) -> Result {
+        match self {
+            Self::Notify(watcher) => FileWatcher::watch_path(watcher, path),
+            Self::Poll(watcher) => FileWatcher::watch_path(watcher, path),
+        }
+    }
+
+    fn unwatch_path(&mut self, path: &Path) -> Result {
+        match self {
+            Self::Notify(watcher) => FileWatcher::unwatch_path(watcher, path),
+            Self::Poll(watcher) => FileWatcher::unwatch_path(watcher, path),
+        }
+    }
+
+    fn pause(&mut self) -> Result {
+        match self {
+            Self::Notify(watcher) => FileWatcher::pause(watcher),
+            Self::Poll(watcher) => FileWatcher::pause(watcher),
+        }
+    }
+
+    fn resume(&mut self) -> Result {
+        match self {
+            Self::Notify(watcher) => FileWatcher::resume(watcher),
+            Self::Poll(watcher) => FileWatcher::resume(watcher),
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        match self {
+            Self::Notify(watcher) => FileWatcher::is_paused(watcher),
+            Self::Poll(watcher) => FileWatcher::is_paused(watcher),
+        }
+    }
+}
+
+/// Converts `path` into the form it is kept in on the watch list
+fn watch_list_entry(path: &Path) -> Result<String> {
+    path.to_str().map(ToString::to_string).ok_or_else(|| {
+        format!(
+            "unable to watch '{}', the path is not valid utf-8",
+            path.display()
+        )
+        .into()
+    })
+}
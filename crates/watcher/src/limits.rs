@@ -0,0 +1,119 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The OS limit on the number of watches. On Linux every watched path takes up one inotify
+//! watch out of `fs.inotify.max_user_watches`, shared by every process of the user. Once they
+//! are used up a [`NotifyWatcher`](crate::NotifyWatcher) polls the paths it cannot watch.
+
+/// Where Linux exposes the limit on inotify watches per user
+#[cfg(target_os = "linux")]
+const MAX_USER_WATCHES: &str = "/proc/sys/fs/inotify/max_user_watches";
+
+/// The OS limit on the number of watches and how many of them are in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchLimit {
+    limit: u64,
+    used: Option<u64>,
+}
+
+impl WatchLimit {
+    /// Reads the current limit and usage of inotify watches. Only the watches of processes that
+    /// can be inspected are counted, which are those of the current user unless running as
+    /// root. Returns `None` if the OS has no such limit (anything but Linux) or it cannot be
+    /// read.
+    #[must_use]
+    pub fn current() -> Option<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            let limit = std::fs::read_to_string(MAX_USER_WATCHES).ok()?;
+            Some(Self {
+                limit: limit.trim().parse().ok()?,
+                used: inotify_watches_in_use(),
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+
+    /// Gets the largest number of watches
+    #[must_use]
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Gets the number of watches in use, if they could be counted
+    #[must_use]
+    pub fn used(&self) -> Option<u64> {
+        self.used
+    }
+
+    /// Gets the number of watches that are still available, if the used ones could be counted
+    #[must_use]
+    pub fn available(&self) -> Option<u64> {
+        self.used.map(|used| self.limit.saturating_sub(used))
+    }
+}
+
+/// Counts the inotify watches of every process whose file descriptors can be read
+#[cfg(target_os = "linux")]
+fn inotify_watches_in_use() -> Option<u64> {
+    let mut used = 0;
+    for process in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(fds) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let is_inotify = std::fs::read_link(fd.path())
+                .is_ok_and(|target| target.as_os_str() == "anon_inode:inotify");
+            if !is_inotify {
+                continue;
+            }
+            let info = process.path().join("fdinfo").join(fd.file_name());
+            if let Ok(info) = std::fs::read_to_string(info) {
+                used += count_watches(&info);
+            }
+        }
+    }
+    Some(used)
+}
+
+/// Counts the watches listed in the `fdinfo` of an inotify file descriptor, one line each
+#[cfg(target_os = "linux")]
+fn count_watches(fdinfo: &str) -> u64 {
+    use xstd::cast::CastFrom;
+
+    let watches = fdinfo
+        .lines()
+        .filter(|line| line.starts_with("inotify wd:"))
+        .count();
+    u64::cast_from(watches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn counts_watches() {
+        let fdinfo = "pos:\t0\nflags:\t02004000\nmnt_id:\t15\nino:\t1057\n\
+                      inotify wd:2 ino:1a sdev:800001 mask:fce ignored_mask:0\n\
+                      inotify wd:1 ino:2 sdev:800001 mask:fce ignored_mask:0\n";
+        assert_eq!(count_watches(fdinfo), 2);
+        assert_eq!(count_watches("pos:\t0\n"), 0);
+
+        let mut watcher = crate::NotifyWatcher::new().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        watcher.watch_path(dir.path()).unwrap();
+        crate::FileWatcher::start(&mut watcher).unwrap();
+        let limit = WatchLimit::current().unwrap();
+        assert!(limit.limit() > 0);
+        assert!(limit.used().unwrap() >= 1);
+        assert!(limit.available().unwrap() < limit.limit());
+    }
+}
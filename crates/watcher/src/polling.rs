@@ -0,0 +1,536 @@
+// Copyright (c) 2023 Tony Barbitta
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+
+use crate::{Config, Result, WatchEvent, WatchState, WatchStatus};
+
+/// The state of a single path as seen by the last poll
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    is_dir: bool,
+    len: u64,
+    modified: Option<SystemTime>,
+    permissions: std::fs::Permissions,
+    hash: Option<u64>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of `path`, re-using the content hash of `previous` if the file does
+    /// not look like it changed
+    fn take(path: &Path, previous: Option<&Snapshot>) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let mut snapshot = Self {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            permissions: metadata.permissions(),
+            hash: None,
+        };
+        if !snapshot.is_dir {
+            snapshot.hash = match previous {
+                Some(prev) if prev.len == snapshot.len && prev.modified == snapshot.modified => {
+                    prev.hash
+                }
+                _ => std::fs::read(path)
+                    .ok()
+                    .map(|bytes| xstd::hash::fnv1a(&bytes)),
+            };
+        }
+        Some(snapshot)
+    }
+}
+
+/// Snapshots of a watched path and, if it is a directory, its immediate children
+type Tree = BTreeMap<PathBuf, Snapshot>;
+
+fn scan(root: &Path, previous: &Tree) -> Tree {
+    let mut tree = Tree::new();
+    let Some(snapshot) = Snapshot::take(root, previous.get(root)) else {
+        return tree;
+    };
+    if snapshot.is_dir {
+        if let Ok(entries) = std::fs::read_dir(root) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if let Some(child) = Snapshot::take(&path, previous.get(&path)) {
+                    tree.insert(path, child);
+                }
+            }
+        }
+    }
+    tree.insert(root.to_path_buf(), snapshot);
+    tree
+}
+
+/// Compares two scans of the same root, pairing up removed and created files with identical
+/// contents as renames
+fn diff(before: &Tree, after: &Tree) -> Vec<WatchEvent> {
+    let mut events = Vec::new();
+    let mut removed: Vec<&PathBuf> = before.keys().filter(|p| !after.contains_key(*p)).collect();
+
+    for (path, now) in after {
+        let Some(then) = before.get(path) else {
+            let renamed_from = removed.iter().position(|old| {
+                let old = &before[*old];
+                !now.is_dir && old.hash.is_some() && old.hash == now.hash && old.len == now.len
+            });
+            match renamed_from {
+                Some(index) => events.push(WatchEvent::Renamed {
+                    from: removed.remove(index).clone(),
+                    to: path.clone(),
+                }),
+                None => events.push(WatchEvent::Created(path.clone())),
+            }
+            continue;
+        };
+        if then.hash != now.hash || then.len != now.len || then.is_dir != now.is_dir {
+            events.push(WatchEvent::Modified(path.clone()));
+        } else if then.modified != now.modified || then.permissions != now.permissions {
+            // directories change their modification time whenever a child is added or removed,
+            // which is already reported through the child itself
+            if !now.is_dir {
+                events.push(WatchEvent::MetadataChanged(path.clone()));
+            }
+        }
+    }
+    events.extend(
+        removed
+            .into_iter()
+            .map(|path| WatchEvent::Removed(path.clone())),
+    );
+    events
+}
+
+/// A running poll loop
+#[derive(Debug)]
+struct PollLoop {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+/// A [`FileWatcher`](crate::FileWatcher) that periodically stats (and hashes) the watched paths,
+/// for file systems that do not support native change notifications such as network shares.
+///
+/// Like [`NotifyWatcher`](crate::NotifyWatcher), directories are watched non-recursively.
+#[derive(Debug)]
+pub struct PollingWatcher {
+    events: Receiver<WatchEvent>,
+    sender: Sender<WatchEvent>,
+    interval: Duration,
+    watched_files: Arc<Mutex<Vec<String>>>,
+    is_paused: Arc<AtomicBool>,
+    poll_loop: Option<PollLoop>,
+}
+
+impl PollingWatcher {
+    /// Creates a new **inactive** [`PollingWatcher`] instance with no watched files, polling
+    /// every `interval` once started
+    #[must_use]
+    pub fn new(interval: Duration) -> Self {
+        let (sender, events) = unbounded();
+        Self::with_channel(interval, sender, events)
+    }
+
+    /// Creates a new **inactive** [`PollingWatcher`] sending its events to `sender`, whose
+    /// receiving end is `events`. Used by a [`NotifyWatcher`](crate::NotifyWatcher) to report the
+    /// events of the paths it polls through its own channel.
+    pub(crate) fn with_channel(
+        interval: Duration,
+        sender: Sender<WatchEvent>,
+        events: Receiver<WatchEvent>,
+    ) -> Self {
+        Self {
+            events,
+            sender,
+            interval,
+            watched_files: Arc::new(Mutex::new(Vec::new())),
+            is_paused: Arc::new(AtomicBool::new(false)),
+            poll_loop: None,
+        }
+    }
+
+    /// Gets a list of the files that are currently on the watch list of this [`PollingWatcher`]
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
+    #[must_use]
+    pub fn watched_files(&self) -> Vec<String> {
+        self.watched_files.lock().expect("mutex poisoned").clone()
+    }
+
+    /// Replaces the list of watched files. Takes effect with the next poll.
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
+    pub fn update_watched_files(&mut self, files: Vec<String>) {
+        *self.watched_files.lock().expect("mutex poisoned") = files;
+    }
+
+    /// Adds `path` to the watch list. Changes to it are reported from the poll after next on,
+    /// the next poll only takes its first snapshot. Watching a path twice has no effect.
+    ///
+    /// ## Errors
+    /// - Returns an error if `path` is not valid utf-8
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
+    pub fn watch_path(&mut self, path: &Path) -> Result {
+        let entry = crate::watch_list_entry(path)?;
+        let mut files = self.watched_files.lock().expect("mutex poisoned");
+        if !files.contains(&entry) {
+            tracing::info!(path = %path.display(), "path added to watch list");
+            files.push(entry);
+        }
+        Ok(())
+    }
+
+    /// Removes `path` from the watch list, takes effect with the next poll
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
+    pub fn unwatch_path(&mut self, path: &Path) {
+        let mut files = self.watched_files.lock().expect("mutex poisoned");
+        let before = files.len();
+        files.retain(|file| Path::new(file) != path);
+        if files.len() != before {
+            tracing::info!(path = %path.display(), "path removed from watch list");
+        }
+    }
+
+    /// Skips polling until [`PollingWatcher::resume`] is called. Changes made in the meantime
+    /// are not reported.
+    pub fn pause(&mut self) {
+        tracing::info!("polling paused");
+        self.is_paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resumes polling after [`PollingWatcher::pause`]
+    pub fn resume(&mut self) {
+        tracing::info!("polling resumed");
+        self.is_paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns true if this [`PollingWatcher`] is paused
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.is_paused.load(Ordering::SeqCst)
+    }
+
+    /// Gets the interval between two polls
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Sets the interval between two polls, restarting the poll loop if it is running
+    ///
+    /// ## Errors
+    /// - Returns an error if the poll loop has to be restarted and its thread cannot be spawned
+    pub fn set_interval(&mut self, interval: Duration) -> Result {
+        self.interval = interval;
+        if self.poll_loop.is_some() {
+            self.stop_polling();
+            self.start_polling()?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if this [`PollingWatcher`] is currently polling
+    #[must_use]
+    pub fn is_watching(&self) -> bool {
+        self.poll_loop.is_some()
+    }
+
+    /// Gets the health of every path on the watch list. Every path is polled, one that does not
+    /// exist is reported as [`WatchState::Pending`], as its changes are reported once it is
+    /// created.
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
+    #[must_use]
+    pub fn health(&self) -> Vec<WatchStatus> {
+        let state = if !self.is_watching() {
+            WatchState::Stopped
+        } else if self.is_paused() {
+            WatchState::Paused
+        } else {
+            WatchState::Polling
+        };
+        self.watched_files()
+            .iter()
+            .map(|file| {
+                let path = Path::new(file);
+                if state == WatchState::Polling && !path.exists() {
+                    WatchStatus::new(path, WatchState::Pending)
+                } else {
+                    WatchStatus::new(path, state)
+                }
+            })
+            .collect()
+    }
+
+    /// Gets the receiver for events that are generated from the watched files
+    #[must_use]
+    pub fn event_stream(&self) -> &Receiver<WatchEvent> {
+        &self.events
+    }
+
+    pub(crate) fn start_polling(&mut self) -> Result {
+        if self.poll_loop.is_some() {
+            return Ok(());
+        }
+        let (stop, stopped) = bounded(1);
+        let watched_files = Arc::clone(&self.watched_files);
+        let is_paused = Arc::clone(&self.is_paused);
+        let sender = self.sender.clone();
+        let interval = self.interval;
+
+        // the initial scan happens here so that changes made right after `start` are noticed
+        let mut trees: BTreeMap<PathBuf, Tree> = watched_files
+            .lock()
+            .expect("mutex poisoned")
+            .iter()
+            .map(|file| (PathBuf::from(file), scan(Path::new(file), &Tree::new())))
+            .collect();
+
+        let handle = std::thread::Builder::new()
+            .name("storage-mon-poll".into())
+            .spawn(move || loop {
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+                if is_paused.load(Ordering::SeqCst) {
+                    // the snapshots are retaken after resuming, so changes made while paused
+                    // are not reported
+                    trees.clear();
+                    continue;
+                }
+                let roots = watched_files.lock().expect("mutex poisoned").clone();
+                let mut next = BTreeMap::new();
+                for root in roots.iter().map(PathBuf::from) {
+                    // roots added at runtime (or after a pause) only take their first snapshot
+                    let Some(before) = trees.remove(&root) else {
+                        let snapshot = scan(&root, &Tree::new());
+                        next.insert(root, snapshot);
+                        continue;
+                    };
+                    let after = scan(&root, &before);
+                    for event in diff(&before, &after) {
+                        tracing::debug!(?event, "event detected");
+                        if sender.send(event).is_err() {
+                            return;
+                        }
+                    }
+                    next.insert(root, after);
+                }
+                trees = next;
+            })?;
+
+        tracing::info!(interval_ms = interval.as_millis(), "polling started");
+        self.poll_loop = Some(PollLoop { stop, handle });
+        Ok(())
+    }
+
+    pub(crate) fn stop_polling(&mut self) {
+        if let Some(poll_loop) = self.poll_loop.take() {
+            poll_loop.stop.send(()).ok();
+            poll_loop.handle.join().ok();
+            tracing::info!("polling stopped");
+        }
+    }
+}
+
+impl Drop for PollingWatcher {
+    fn drop(&mut self) {
+        self.stop_polling();
+    }
+}
+
+impl super::FileWatcher for PollingWatcher {
+    fn currently_watched(&self) -> Result<Vec<String>> {
+        Ok(self.watched_files())
+    }
+
+    fn events(&self) -> Receiver<WatchEvent> {
+        self.events.clone()
+    }
+
+    fn apply_app_config(&mut self, config: &Config) -> Result {
+        self.update_watched_files(config.read_tracked_files()?);
+        self.set_interval(Duration::from_millis(config.delay()))
+    }
+
+    fn start(&mut self) -> Result {
+        self.start_polling()
+    }
+
+    fn stop(&mut self) -> Result {
+        self.stop_polling();
+        Ok(())
+    }
+
+    fn watch_path(&mut self, path: &Path) -> Result {
+        PollingWatcher::watch_path(self, path)
+    }
+
+    fn unwatch_path(&mut self, path: &Path) -> Result {
+        PollingWatcher::unwatch_path(self, path);
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result {
+        PollingWatcher::pause(self);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result {
+        PollingWatcher::resume(self);
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        PollingWatcher::is_paused(self)
+    }
+}
+
+impl super::ConfigurableFileWatcher for PollingWatcher {
+    /// The interval between two polls
+    type InnerConfig = Duration;
+
+    fn apply_inner_config(&mut self, interval: &Self::InnerConfig) -> Result {
+        self.set_interval(*interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ConfigurableFileWatcher, FileWatcher};
+    use super::*;
+
+    fn collect(watcher: &PollingWatcher) -> Vec<WatchEvent> {
+        let mut events = Vec::new();
+        while let Ok(event) = watcher.events().recv_timeout(Duration::from_millis(200)) {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn detects_changes() {
+        let temp = tempfile::tempdir().expect("failed to create temp dir");
+        let file1 = temp.path().join("file1.txt");
+        let file2 = temp.path().join("file2.txt");
+        let moved = temp.path().join("moved.txt");
+        std::fs::write(&file1, "test").unwrap();
+        std::fs::write(&file2, "other").unwrap();
+
+        let mut watcher = PollingWatcher::new(Duration::from_millis(20));
+        watcher.update_watched_files(vec![temp.path().to_str().unwrap().to_string()]);
+        watcher.start().unwrap();
+
+        std::fs::write(&file1, "changed").unwrap();
+        std::fs::rename(&file2, &moved).unwrap();
+        let created = temp.path().join("file3.txt");
+        std::fs::write(&created, "new").unwrap();
+        let events = collect(&watcher);
+        assert!(
+            events.contains(&WatchEvent::Modified(file1.clone())),
+            "{events:?}"
+        );
+        assert!(events.contains(&WatchEvent::Renamed {
+            from: file2,
+            to: moved.clone()
+        }));
+        assert!(events.contains(&WatchEvent::Created(created)));
+
+        std::fs::remove_file(&moved).unwrap();
+        assert_eq!(collect(&watcher), [WatchEvent::Removed(moved)]);
+
+        // permissions do not change the contents, so they are reported apart
+        let mut permissions = std::fs::metadata(&file1).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&file1, permissions).unwrap();
+        assert_eq!(
+            collect(&watcher),
+            [WatchEvent::MetadataChanged(file1.clone())]
+        );
+
+        watcher.stop().unwrap();
+        std::fs::write(&file1, "unseen").unwrap();
+        assert!(collect(&watcher).is_empty());
+    }
+
+    #[test]
+    fn watches_paths_at_runtime() {
+        let temp = tempfile::tempdir().expect("failed to create temp dir");
+        let file1 = temp.path().join("file1.txt");
+        let file2 = temp.path().join("file2.txt");
+        std::fs::write(&file1, "test").unwrap();
+        std::fs::write(&file2, "other").unwrap();
+
+        let mut watcher = PollingWatcher::new(Duration::from_millis(20));
+        watcher.watch_path(&file1).unwrap();
+        watcher.start().unwrap();
+        watcher.watch_path(&file2).unwrap();
+        watcher.watch_path(&file2).unwrap();
+        assert_eq!(watcher.currently_watched().unwrap().len(), 2);
+        // the added path is not reported as created
+        assert!(collect(&watcher).is_empty());
+
+        std::fs::write(&file2, "changed").unwrap();
+        assert_eq!(collect(&watcher), [WatchEvent::Modified(file2.clone())]);
+
+        watcher.pause();
+        std::fs::write(&file1, "unseen").unwrap();
+        assert!(collect(&watcher).is_empty());
+        watcher.resume();
+        assert!(collect(&watcher).is_empty());
+
+        watcher.unwatch_path(&file1);
+        std::fs::write(&file1, "also unseen").unwrap();
+        std::fs::write(&file2, "seen").unwrap();
+        assert_eq!(collect(&watcher), [WatchEvent::Modified(file2)]);
+    }
+
+    #[test]
+    fn adds_directories_at_runtime() {
+        let temp = tempfile::tempdir().expect("failed to create temp dir");
+        std::fs::write(temp.path().join("file1.txt"), "test").unwrap();
+        std::fs::write(temp.path().join("file2.txt"), "other").unwrap();
+
+        let mut watcher = PollingWatcher::new(Duration::from_millis(20));
+        watcher.start().unwrap();
+        watcher.watch_path(temp.path()).unwrap();
+        // the children the directory already has are not reported as created
+        assert!(collect(&watcher).is_empty());
+
+        let created = temp.path().join("file3.txt");
+        std::fs::write(&created, "new").unwrap();
+        assert_eq!(collect(&watcher), [WatchEvent::Created(created)]);
+    }
+
+    #[test]
+    fn applies_inner_config() {
+        let mut watcher = PollingWatcher::new(Duration::from_millis(20));
+        watcher
+            .apply_inner_config(&Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(watcher.interval(), Duration::from_millis(50));
+    }
+}
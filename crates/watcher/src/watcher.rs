@@ -6,43 +6,215 @@
 use super::{Config, Result};
 
 use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, Weak,
+    },
+    time::{Duration, Instant},
 };
 
-use crossbeam_channel::{unbounded, Receiver};
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use notify::{event::ModifyKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use storage_common::{Error, EventKinds};
 
-/// Typedef for a result that produces either a [`notify::Event`] or a [`notify::Error`]
-pub type NotifyEvent = Result<notify::Event, notify::Error>;
+use crate::{
+    filter::EventFilter,
+    health::{HealthTracker, WatchState},
+    PollingWatcher, WatchError, WatchEvent, WatchStatus,
+};
+
+/// The number of errors reported by [`notify`] that are kept until they are received
+const ERROR_CAPACITY: usize = 64;
+
+/// Callback invoked when a removed path could not be re-registered with the watcher
+pub type RecoveryErrorCallback = Arc<dyn Fn(&Path, &Error) + Send + Sync>;
+
+/// Options controlling how a [`NotifyWatcher`] re-registers watched paths that have been
+/// removed (e.g. by an editor performing an atomic save) once they reappear.
+#[derive(Clone)]
+pub struct RecoveryOptions {
+    /// The delay before the first attempt to re-register a removed path
+    pub initial_backoff: Duration,
+    /// The upper bound for the delay between attempts, the delay doubles after each attempt
+    pub max_backoff: Duration,
+    /// The number of attempts before giving up on a path, `None` to retry forever
+    pub max_attempts: Option<u32>,
+    on_error: Option<RecoveryErrorCallback>,
+}
+
+impl RecoveryOptions {
+    /// Sets the callback that is invoked whenever re-registering a path fails, or the watcher
+    /// gives up on a path after [`RecoveryOptions::max_attempts`]
+    #[must_use]
+    pub fn with_error_callback(
+        self,
+        on_error: impl Fn(&Path, &Error) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            on_error: Some(Arc::new(on_error)),
+            ..self
+        }
+    }
+
+    fn report(&self, path: &Path, err: &Error) {
+        if let Some(on_error) = &self.on_error {
+            on_error(path, err);
+        }
+    }
+}
+
+impl Default for RecoveryOptions {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_attempts: None,
+            on_error: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for RecoveryOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecoveryOptions")
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("max_attempts", &self.max_attempts)
+            .field("on_error", &self.on_error.is_some())
+            .finish()
+    }
+}
 
 /// A [`FileWatcher`](super::FileWatcher) implementation using the [`notify`] crate
 #[derive(Debug)]
 pub struct NotifyWatcher {
-    events: Receiver<NotifyEvent>,
+    events: Receiver<WatchEvent>,
+    errors: Receiver<WatchError>,
     notify_config: notify::Config,
-    is_watching: bool,
-    watcher: RecommendedWatcher,
+    is_watching: Arc<AtomicBool>,
+    is_paused: Arc<AtomicBool>,
+    watcher: Arc<Mutex<RecommendedWatcher>>,
     watched_files: Arc<Mutex<Vec<String>>>,
+    missing: Arc<Mutex<MissingPaths>>,
+    recovery: Arc<Mutex<RecoveryOptions>>,
+    health: HealthTracker,
+    filter: Arc<Mutex<EventFilter>>,
+    raw: Arc<Mutex<Option<Sender<notify::Event>>>>,
+    /// Polls the paths that could not be watched as the OS limit on watches was reached
+    fallback: PollingWatcher,
 }
 
 impl NotifyWatcher {
-    /// Creates a new **inactive** [`NotifyWatcher`] instance with no watched files
+    /// Creates a new **inactive** [`NotifyWatcher`] instance with no watched files. Watched paths
+    /// that do not exist yet are waited for by watching their nearest existing ancestor, and
+    /// registered once they are created. Events of the kinds in [`EventKinds::DEFAULT`] are
+    /// reported, see [`NotifyWatcher::set_event_kinds`]. Paths that cannot be watched because
+    /// the OS limit on watches (e.g. inotify's `max_user_watches`) is reached are polled
+    /// instead, see [`NotifyWatcher::polled_paths`].
     ///
     /// ## Errors
     /// - Returns an error if the underlying [`notify::RecommendedWatcher`] cannot be created
+    /// - Returns an error if the background thread that recovers removed paths cannot be spawned
+    ///
+    /// ## Panics
+    /// The event handler panics if the watched files mutex is poisoned
     pub fn new() -> Result<Self> {
         let (tx, rx) = unbounded();
+        let (err_tx, err_rx) = bounded(ERROR_CAPACITY);
+        let (signal_tx, signal_rx) = unbounded();
         let config = notify::Config::default().with_poll_interval(Duration::from_secs(5));
-        let watcher = notify::RecommendedWatcher::new(tx, config)?;
         let watched_files = Arc::new(Mutex::new(Vec::new()));
+        let missing = MissingPaths::default();
+        let ancestors = Arc::clone(&missing.ancestors);
+        let is_watching = Arc::new(AtomicBool::new(false));
+        let is_paused = Arc::new(AtomicBool::new(false));
+        let recovery = Arc::new(Mutex::new(RecoveryOptions::default()));
+        let health = HealthTracker::default();
+        let filter = Arc::new(Mutex::new(EventFilter::default()));
+        let raw = Arc::new(Mutex::new(None::<Sender<notify::Event>>));
+        let fallback = PollingWatcher::with_channel(Duration::from_secs(5), tx.clone(), rx.clone());
+
+        let handler = {
+            let watched_files = Arc::clone(&watched_files);
+            let is_paused = Arc::clone(&is_paused);
+            let health = health.clone();
+            let filter = Arc::clone(&filter);
+            let raw = Arc::clone(&raw);
+            let backlog = err_rx.clone();
+            move |event: notify::Result<notify::Event>| match event {
+                Ok(event) => {
+                    if let Some(raw) = &*raw.lock().expect("mutex poisoned") {
+                        raw.send(event.clone()).ok();
+                    }
+                    {
+                        let watched = watched_files.lock().expect("mutex poisoned");
+                        for path in &event.paths {
+                            for root in watched_roots(&watched, path) {
+                                health.event(root);
+                            }
+                        }
+                        if event.kind.is_remove() {
+                            for path in &event.paths {
+                                if watched.iter().any(|file| Path::new(file) == path) {
+                                    health.lost(path);
+                                    signal_tx.send(Signal::Lost(path.clone())).ok();
+                                }
+                            }
+                        }
+
+                        let ancestors = ancestors.lock().expect("mutex poisoned");
+                        if !ancestors.is_empty() && changes_entries(&event.kind) {
+                            signal_tx.send(Signal::Changed).ok();
+                        }
+                        if is_ancestor_event(&watched, &ancestors, &event.paths) {
+                            tracing::trace!(?event, "event of a missing path's ancestor dropped");
+                            return;
+                        }
+                    }
+                    // removed paths are still recovered while paused, only the events are dropped
+                    if is_paused.load(Ordering::SeqCst) {
+                        tracing::trace!(?event, "event dropped while paused");
+                        return;
+                    }
+                    forward(&filter, &tx, event);
+                }
+                Err(err) => report_error(&watched_files, &health, (&err_tx, &backlog), &err),
+            }
+        };
+        let watcher = Arc::new(Mutex::new(notify::RecommendedWatcher::new(
+            handler, config,
+        )?));
+
+        let missing = Arc::new(Mutex::new(missing));
+        let recovery_loop = RecoveryLoop {
+            signals: signal_rx,
+            watcher: Arc::downgrade(&watcher),
+            watched_files: Arc::clone(&watched_files),
+            missing: Arc::clone(&missing),
+            is_watching: Arc::clone(&is_watching),
+            options: Arc::clone(&recovery),
+            health: health.clone(),
+            pending: Vec::new(),
+        };
+        std::thread::Builder::new()
+            .name("storage-mon-recovery".into())
+            .spawn(move || recovery_loop.run())?;
 
         let file_watcher = Self {
             events: rx,
-            is_watching: false,
+            errors: err_rx,
+            is_watching,
+            is_paused,
             notify_config: config,
             watcher,
             watched_files,
+            missing,
+            recovery,
+            health,
+            filter,
+            raw,
+            fallback,
         };
 
         Ok(file_watcher)
@@ -65,17 +237,20 @@ impl NotifyWatcher {
     ///
     /// ## Errors
     /// - If this `NotifyWatcher` is currently active, this method will stop the watcher
-    /// and restart ([`NotifyWatcher::start`] and [`NotifyWatcher::stop`]) so any errors will be
-    /// propogated.
+    ///   and restart ([`NotifyWatcher::start`] and [`NotifyWatcher::stop`]) so any errors will be
+    ///   propogated.
     /// - If this `NotifyWatcher` is not currently active, this method cannot fail.
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
     pub fn update_watched_files(&mut self, files: Vec<String>) -> Result<()> {
-        let currently_watching = self.is_watching;
+        let currently_watching = self.is_watching.load(Ordering::SeqCst);
         if currently_watching {
             self.stop_watch()?;
         }
 
-        self.watched_files.lock().expect("mutex poisoned").clear();
-        self.watched_files = Arc::new(Mutex::new(files));
+        self.health.retain(&files);
+        *self.watched_files.lock().expect("mutex poisoned") = files;
         if currently_watching {
             self.start_watch()?;
         }
@@ -84,6 +259,9 @@ impl NotifyWatcher {
     }
 
     /// Returns true if this `NotifyWatcher` is currently active (actively monitoring files)
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
     #[must_use]
     pub fn is_watching(&self) -> bool {
         let is_empty = self
@@ -91,7 +269,142 @@ impl NotifyWatcher {
             .lock()
             .expect("mutex poisoned")
             .is_empty();
-        !is_empty && self.is_watching
+        !is_empty && self.is_watching.load(Ordering::SeqCst)
+    }
+
+    /// Adds `path` to the watch list, registering it right away if this `NotifyWatcher` is
+    /// active. A path that does not exist yet is registered once it is created. Watching a path
+    /// that is already watched has no effect.
+    ///
+    /// ## Errors
+    /// - Returns an error if `path` is not valid utf-8
+    /// - Returns an error if this `NotifyWatcher` is active and `path`, or the nearest existing
+    ///   ancestor of a missing `path`, cannot be watched
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
+    pub fn watch_path(&mut self, path: &Path) -> Result<()> {
+        let entry = crate::watch_list_entry(path)?;
+        if self
+            .watched_files
+            .lock()
+            .expect("mutex poisoned")
+            .contains(&entry)
+        {
+            return Ok(());
+        }
+        // registered first so a path that cannot be watched does not end up on the list
+        if self.is_watching.load(Ordering::SeqCst) {
+            register(
+                &mut self.watcher.lock().expect("mutex poisoned"),
+                &mut self.missing.lock().expect("mutex poisoned"),
+                &mut self.fallback,
+                &self.health,
+                path,
+            )?;
+        }
+        tracing::info!(path = %path.display(), "path added to watch list");
+        self.watched_files
+            .lock()
+            .expect("mutex poisoned")
+            .push(entry);
+        Ok(())
+    }
+
+    /// Removes `path` from the watch list, unregistering it right away if this `NotifyWatcher`
+    /// is active. Unwatching a path that is not watched has no effect.
+    ///
+    /// ## Errors
+    /// - Returns an error if this `NotifyWatcher` is active and `path` cannot be unwatched
+    ///
+    /// ## Panics
+    /// Panics if the watched files mutex is poisoned
+    pub fn unwatch_path(&mut self, path: &Path) -> Result<()> {
+        let removed = {
+            let mut files = self.watched_files.lock().expect("mutex poisoned");
+            let before = files.len();
+            files.retain(|file| Path::new(file) != path);
+            files.len() != before
+        };
+        let polled = self
+            .polled_paths()
+            .iter()
+            .any(|file| Path::new(file) == path);
+        if removed && polled {
+            self.fallback.unwatch_path(path);
+        } else if removed && self.is_watching.load(Ordering::SeqCst) {
+            let watched = self.watched_files();
+            let mut watcher = self.inner_watcher();
+            let mut missing = self.missing.lock().expect("mutex poisoned");
+            // a missing path is not registered, and another one may still wait through it
+            if !missing.unwatch(&mut watcher, path, &watched) && !missing.is_ancestor(path) {
+                unwatch(&mut watcher, path)?;
+            }
+        }
+        if removed {
+            self.health.forget(path);
+            tracing::info!(path = %path.display(), "path removed from watch list");
+        }
+        Ok(())
+    }
+
+    /// Stops reporting events until [`NotifyWatcher::resume`] is called. The watched paths stay
+    /// registered, changes made in the meantime are dropped.
+    pub fn pause(&mut self) {
+        tracing::info!("watch paused");
+        self.is_paused.store(true, Ordering::SeqCst);
+        self.fallback.pause();
+    }
+
+    /// Resumes reporting events after [`NotifyWatcher::pause`]
+    pub fn resume(&mut self) {
+        tracing::info!("watch resumed");
+        self.is_paused.store(false, Ordering::SeqCst);
+        self.fallback.resume();
+    }
+
+    /// Returns true if this `NotifyWatcher` is paused
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.is_paused.load(Ordering::SeqCst)
+    }
+
+    /// Gets the paths of the watch list that are polled for changes instead of being registered
+    /// with the OS, as the OS limit on watches was reached when they were watched. They are
+    /// registered again the next time the watcher is started.
+    #[must_use]
+    pub fn polled_paths(&self) -> Vec<String> {
+        self.fallback.watched_files()
+    }
+
+    /// Gets the health of every path on the watch list: whether it is registered with the OS,
+    /// when its latest event was received and the latest error reported for it. A path that
+    /// vanished without the OS reporting its removal is reported as [`WatchState::Lost`], one
+    /// that does not exist yet as [`WatchState::Pending`].
+    ///
+    /// ## Panics
+    /// Panics if the health mutex is poisoned
+    #[must_use]
+    pub fn health(&self) -> Vec<WatchStatus> {
+        let state = if !self.is_watching.load(Ordering::SeqCst) {
+            WatchState::Stopped
+        } else if self.is_paused() {
+            WatchState::Paused
+        } else {
+            WatchState::Watching
+        };
+        self.watched_files()
+            .iter()
+            .map(|file| {
+                let path = Path::new(file);
+                let state = if state != WatchState::Stopped && !path.exists() {
+                    WatchState::Lost
+                } else {
+                    state
+                };
+                self.health.status(path, state)
+            })
+            .collect()
     }
 
     /// Sets the polling interval for the internal [`notify::RecommendedWatcher`] instance
@@ -102,7 +415,7 @@ impl NotifyWatcher {
         self.notify_config = self
             .notify_config
             .with_poll_interval(Duration::from_millis(millis));
-        self.watcher.configure(self.notify_config)?;
+        self.inner_watcher().configure(self.notify_config)?;
         Ok(())
     }
 
@@ -113,62 +426,595 @@ impl NotifyWatcher {
     /// - Returns an error if the call to [`notify::RecommendedWatcher::configure`] fails
     pub fn set_compare_contents(&mut self, compare: bool) -> Result<(), notify::Error> {
         self.notify_config = self.notify_config.with_compare_contents(compare);
-        self.watcher.configure(self.notify_config)?;
+        self.inner_watcher().configure(self.notify_config)?;
         Ok(())
     }
 
+    /// Sets the [`RecoveryOptions`] used when a watched path is removed and later reappears
+    ///
+    /// ## Panics
+    /// Panics if the recovery options mutex is poisoned
+    pub fn set_recovery_options(&mut self, options: RecoveryOptions) {
+        *self.recovery.lock().expect("mutex poisoned") = options;
+    }
+
+    /// Gets the kinds of events reported for paths without kinds of their own
+    ///
+    /// ## Panics
+    /// Panics if the event filter mutex is poisoned
+    #[must_use]
+    pub fn event_kinds(&self) -> EventKinds {
+        self.filter.lock().expect("mutex poisoned").global()
+    }
+
+    /// Sets the kinds of events reported for paths without kinds of their own, the events of
+    /// other kinds are dropped. Applying the [application config](Config) replaces them with
+    /// [`Config::watch_events`].
+    ///
+    /// ## Panics
+    /// Panics if the event filter mutex is poisoned
+    pub fn set_event_kinds(&mut self, kinds: EventKinds) {
+        self.filter
+            .lock()
+            .expect("mutex poisoned")
+            .set_global(kinds);
+    }
+
+    /// Sets the kinds of events reported for `path` and the paths below it, `None` reports
+    /// those of [`NotifyWatcher::event_kinds`]. Applying the [application config](Config)
+    /// replaces them with the kinds of the tracking list entries.
+    ///
+    /// ## Panics
+    /// Panics if the event filter mutex is poisoned
+    pub fn set_path_event_kinds(&mut self, path: &Path, kinds: Option<EventKinds>) {
+        self.filter
+            .lock()
+            .expect("mutex poisoned")
+            .set_path(path, kinds);
+    }
+
     /// Gets the receiver for events that are generated from the watched files
     #[must_use]
-    pub fn event_stream(&self) -> &Receiver<NotifyEvent> {
+    pub fn event_stream(&self) -> &Receiver<WatchEvent> {
         &self.events
     }
 
-    /// Gets a reference to the inner [`notify::RecommendedWatcher`] instance
-    #[allow(dead_code)]
-    pub(crate) fn inner_watcher(&self) -> &RecommendedWatcher {
-        &self.watcher
+    /// Subscribes to the events of the underlying [`notify`] watcher as they arrive, before they
+    /// are translated into [`WatchEvent`]s and whether or not they are reported, e.g. to find out
+    /// why a change is not. Only the latest subscriber receives the events.
+    ///
+    /// ## Panics
+    /// Panics if the raw events mutex is poisoned
+    #[must_use]
+    pub fn subscribe_raw(&self) -> Receiver<notify::Event> {
+        let (tx, rx) = unbounded();
+        *self.raw.lock().expect("mutex poisoned") = Some(tx);
+        rx
+    }
+
+    /// Gets the receiver for errors reported by the underlying [`notify`] watcher, classified by
+    /// whether they are fatal. Only the most recent errors are kept if they are not received.
+    #[must_use]
+    pub fn error_stream(&self) -> &Receiver<WatchError> {
+        &self.errors
+    }
+
+    /// Gets a lock on the inner [`notify::RecommendedWatcher`] instance
+    pub(crate) fn inner_watcher(&self) -> MutexGuard<'_, RecommendedWatcher> {
+        self.watcher.lock().expect("mutex poisoned")
     }
 
     fn start_watch(&mut self) -> Result<()> {
-        if self.is_watching {
+        if self.is_watching.load(Ordering::SeqCst) {
             return Ok(());
         }
-        for file in self.watched_files.lock().expect("mutex poisoned").iter() {
-            self.watcher
-                .watch(std::path::Path::new(file), RecursiveMode::NonRecursive)?;
+        // the list is cloned so the event handler is never blocked while notify is busy
+        let files = self.watched_files();
+        let mut watcher = self.watcher.lock().expect("mutex poisoned");
+        let mut missing = self.missing.lock().expect("mutex poisoned");
+        for file in &files {
+            register(
+                &mut watcher,
+                &mut missing,
+                &mut self.fallback,
+                &self.health,
+                Path::new(file),
+            )?;
         }
 
-        self.is_watching = true;
+        tracing::info!(paths = files.len(), "watch started");
+        self.is_watching.store(true, Ordering::SeqCst);
         Ok(())
     }
 
     fn stop_watch(&mut self) -> Result<()> {
-        if !self.is_watching {
+        if !self.is_watching.load(Ordering::SeqCst) {
             return Ok(());
         }
-        for file in self.watched_files.lock().expect("mutex poisoned").iter() {
-            self.watcher.unwatch(std::path::Path::new(file))?;
+        let files = self.watched_files();
+        let mut watcher = self.inner_watcher();
+        for file in &files {
+            unwatch(&mut watcher, Path::new(file))?;
+        }
+        self.missing
+            .lock()
+            .expect("mutex poisoned")
+            .clear(&mut watcher);
+        drop(watcher);
+        self.fallback.stop_polling();
+        for file in self.fallback.watched_files() {
+            self.health.registered(Path::new(&file));
         }
-        self.is_watching = false;
+        self.fallback.update_watched_files(Vec::new());
+        tracing::info!(paths = files.len(), "watch stopped");
+        self.is_watching.store(false, Ordering::SeqCst);
         Ok(())
     }
 }
 
-impl super::FileWatcher for NotifyWatcher {
-    type InnerConfig = notify::Config;
+/// Registers `path` with `watcher`, or its nearest existing ancestor through `missing` if it
+/// does not exist yet. Once the OS limit on watches is reached `path` is handed to `fallback`
+/// to be polled instead.
+fn register(
+    watcher: &mut RecommendedWatcher,
+    missing: &mut MissingPaths,
+    fallback: &mut PollingWatcher,
+    health: &HealthTracker,
+    path: &Path,
+) -> Result<()> {
+    match missing.watch(watcher, path) {
+        Ok(true) => health.registered(path),
+        Ok(false) => health.pending(path),
+        Err(err) if WatchError::classify(&err).is_fatal() => {
+            tracing::warn!(
+                path = %path.display(),
+                %err,
+                guidance = WatchError::WatchLimitReached.guidance(),
+                "watch limit reached, polling the path instead"
+            );
+            fallback.watch_path(path)?;
+            fallback.start_polling()?;
+            health.polled(path);
+        }
+        Err(err) => return Err(err),
+    }
+    Ok(())
+}
+
+/// Unregisters `path` from `watcher`
+fn unwatch(watcher: &mut RecommendedWatcher, path: &Path) -> Result<()> {
+    match watcher.unwatch(path) {
+        // paths that were removed (and not yet recovered) are no longer registered
+        Err(notify::Error {
+            kind: notify::ErrorKind::WatchNotFound,
+            ..
+        })
+        | Ok(()) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Sends the [`WatchEvent`]s of `event` to `events`, dropping those `filter` does not accept
+///
+/// ## Panics
+/// Panics if the event filter mutex is poisoned
+fn forward(filter: &Mutex<EventFilter>, events: &Sender<WatchEvent>, event: notify::Event) {
+    let filter = filter.lock().expect("mutex poisoned");
+    for event in WatchEvent::from_notify(event) {
+        if !filter.accepts(&event) {
+            tracing::trace!(?event, "event of an unwatched kind dropped");
+            continue;
+        }
+        tracing::debug!(?event, "event received");
+        events.send(event).ok();
+    }
+}
+
+/// Records `err` in the health of the watched paths it is about and sends it through the bounded
+/// `errors` channel. Errors nobody is receiving do not pile up: once the channel is full its
+/// oldest error is dropped to make room, so the most recent ones are kept.
+///
+/// ## Panics
+/// Panics if the watched files mutex is poisoned
+fn report_error(
+    watched_files: &Mutex<Vec<String>>,
+    health: &HealthTracker,
+    (errors, backlog): (&Sender<WatchError>, &Receiver<WatchError>),
+    err: &notify::Error,
+) {
+    tracing::warn!(%err, "watcher error");
+    let watched = watched_files.lock().expect("mutex poisoned");
+    // an error without paths is about the watcher as a whole
+    if err.paths.is_empty() {
+        for file in watched.iter() {
+            health.error(Path::new(file), err);
+        }
+    }
+    for path in &err.paths {
+        for root in watched_roots(&watched, path) {
+            health.error(root, err);
+        }
+    }
+    let mut error = WatchError::from_notify(err);
+    while let Err(TrySendError::Full(rejected)) = errors.try_send(error) {
+        backlog.try_recv().ok();
+        error = rejected;
+    }
+}
+
+/// Gets the paths of `watched` an event or error for `path` is about: `path` itself, or the
+/// directory containing it
+fn watched_roots<'a>(watched: &'a [String], path: &'a Path) -> impl Iterator<Item = &'a Path> {
+    watched
+        .iter()
+        .map(Path::new)
+        .filter(move |root| *root == path || path.parent() == Some(*root))
+}
+
+/// Returns true if an event about `paths` only concerns `ancestors` of missing paths, which are
+/// watched to notice their creation rather than for their own changes
+fn is_ancestor_event(watched: &[String], ancestors: &[PathBuf], paths: &[PathBuf]) -> bool {
+    !paths.is_empty()
+        && paths.iter().all(|path| {
+            watched_roots(watched, path).next().is_none()
+                && ancestors
+                    .iter()
+                    .any(|dir| dir == path || path.parent() == Some(dir))
+        })
+}
+
+/// Returns true if events of `kind` may create or remove the entries of a directory
+fn changes_entries(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+    )
+}
+
+/// Gets the nearest ancestor of `path` that is an existing directory
+fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// A watched path that does not exist yet, waiting for its creation through a watch on its
+/// nearest existing ancestor
+#[derive(Debug)]
+struct MissingPath {
+    path: PathBuf,
+    ancestor: PathBuf,
+}
+
+/// The watched paths that do not exist yet. Every change to the entries of a directory is
+/// checked against them by the recovery loop, which moves their watches closer as their parents
+/// are created and registers them once they exist.
+#[derive(Debug, Default)]
+struct MissingPaths {
+    paths: Vec<MissingPath>,
+    /// The watched ancestors, shared with the event handler which drops their events. Never
+    /// locked while notify is busy, so the event handler is never blocked by it.
+    ancestors: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl MissingPaths {
+    /// Registers `path` with `watcher`, or its nearest existing ancestor if `path` does not
+    /// exist. Returns true if `path` itself was registered.
+    fn watch(&mut self, watcher: &mut RecommendedWatcher, path: &Path) -> Result<bool> {
+        if path.exists() {
+            watcher.watch(path, RecursiveMode::NonRecursive)?;
+            return Ok(true);
+        }
+        let ancestor = existing_ancestor(path).ok_or_else(|| {
+            format!(
+                "unable to watch '{}', neither the path nor any of its parents exist",
+                path.display()
+            )
+        })?;
+        watcher.watch(&ancestor, RecursiveMode::NonRecursive)?;
+        tracing::info!(
+            path = %path.display(),
+            ancestor = %ancestor.display(),
+            "watched path does not exist yet, waiting for its creation"
+        );
+        self.paths.push(MissingPath {
+            path: path.to_path_buf(),
+            ancestor,
+        });
+        self.publish();
+        Ok(false)
+    }
+
+    /// Stops waiting for `path`. Returns false if `path` is not missing.
+    fn unwatch(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        path: &Path,
+        watched: &[String],
+    ) -> bool {
+        let Some(index) = self.paths.iter().position(|missing| missing.path == path) else {
+            return false;
+        };
+        let missing = self.paths.remove(index);
+        self.release(watcher, &missing.ancestor, watched);
+        self.publish();
+        true
+    }
+
+    /// Returns true if `dir` is watched for a missing path
+    fn is_ancestor(&self, dir: &Path) -> bool {
+        self.paths.iter().any(|missing| missing.ancestor == dir)
+    }
+
+    /// Stops waiting for any path, once the watcher is stopped
+    fn clear(&mut self, watcher: &mut RecommendedWatcher) {
+        for missing in std::mem::take(&mut self.paths) {
+            // ancestors shared by several paths are only registered once
+            if let Err(err) = unwatch(watcher, &missing.ancestor) {
+                tracing::warn!(ancestor = %missing.ancestor.display(), %err, "unable to unwatch");
+            }
+        }
+        self.publish();
+    }
+
+    /// Registers the missing paths that exist by now and moves the watches of the others to
+    /// their nearest existing ancestor. Gets the paths that were (or failed to be) registered.
+    fn settle(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        watched: &[String],
+    ) -> Vec<(PathBuf, Result)> {
+        let mut settled = Vec::new();
+        let mut index = 0;
+        while index < self.paths.len() {
+            let path = self.paths[index].path.clone();
+            match self.settle_at(watcher, index, watched) {
+                // the path is no longer missing, the next one moved to `index`
+                Ok(true) => settled.push((path, Ok(()))),
+                Ok(false) => index += 1,
+                Err(err) => {
+                    settled.push((path, Err(err)));
+                    index += 1;
+                }
+            }
+        }
+        self.publish();
+        settled
+    }
+
+    /// Settles the missing path at `index`, returns true if it was registered
+    fn settle_at(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        index: usize,
+        watched: &[String],
+    ) -> Result<bool> {
+        // a parent may be created right before its watch is moved to it, so this repeats until
+        // nothing changed in the meantime
+        loop {
+            let missing = &self.paths[index];
+            if missing.path.exists() {
+                watcher.watch(&missing.path, RecursiveMode::NonRecursive)?;
+                let missing = self.paths.remove(index);
+                self.release(watcher, &missing.ancestor, watched);
+                return Ok(true);
+            }
+            let Some(ancestor) = existing_ancestor(&missing.path) else {
+                return Ok(false);
+            };
+            if ancestor == missing.ancestor {
+                return Ok(false);
+            }
+            watcher.watch(&ancestor, RecursiveMode::NonRecursive)?;
+            let previous = std::mem::replace(&mut self.paths[index].ancestor, ancestor);
+            self.release(watcher, &previous, watched);
+        }
+    }
+
+    /// Unregisters the ancestor `dir` unless it is still needed, as the ancestor of another
+    /// missing path or as a watched path itself
+    fn release(&self, watcher: &mut RecommendedWatcher, dir: &Path, watched: &[String]) {
+        if self.is_ancestor(dir) || watched.iter().any(|file| Path::new(file) == dir) {
+            return;
+        }
+        if let Err(err) = unwatch(watcher, dir) {
+            tracing::warn!(ancestor = %dir.display(), %err, "unable to unwatch");
+        }
+    }
+
+    /// Shares the current ancestors with the event handler
+    fn publish(&self) {
+        *self.ancestors.lock().expect("mutex poisoned") = self
+            .paths
+            .iter()
+            .map(|missing| missing.ancestor.clone())
+            .collect();
+    }
+}
+
+/// What the event handler tells the recovery loop
+#[derive(Debug)]
+enum Signal {
+    /// A watched path was removed
+    Lost(PathBuf),
+    /// The entries of a watched directory changed, a missing path may have been created
+    Changed,
+}
+
+/// A removed path that is waiting to be re-registered
+#[derive(Debug)]
+struct PendingPath {
+    path: PathBuf,
+    attempts: u32,
+    backoff: Duration,
+    next_attempt: Instant,
+}
+
+/// Background loop that re-registers removed paths once they reappear, and missing paths once
+/// they are created
+struct RecoveryLoop {
+    signals: Receiver<Signal>,
+    watcher: Weak<Mutex<RecommendedWatcher>>,
+    watched_files: Arc<Mutex<Vec<String>>>,
+    missing: Arc<Mutex<MissingPaths>>,
+    is_watching: Arc<AtomicBool>,
+    options: Arc<Mutex<RecoveryOptions>>,
+    health: HealthTracker,
+    pending: Vec<PendingPath>,
+}
+
+impl RecoveryLoop {
+    fn run(mut self) {
+        loop {
+            let next = self.pending.iter().map(|p| p.next_attempt).min();
+            let received = match next {
+                Some(next) => self
+                    .signals
+                    .recv_timeout(next.saturating_duration_since(Instant::now())),
+                None => self
+                    .signals
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let signal = match received {
+                Ok(signal) => Some(signal),
+                Err(RecvTimeoutError::Timeout) => None,
+                // the watcher (and with it the event handler) has been dropped
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+
+            let Some(watcher) = self.watcher.upgrade() else {
+                return;
+            };
+            match signal {
+                Some(Signal::Lost(path)) => self.track(path),
+                Some(Signal::Changed) => self.settle(&watcher),
+                None => {}
+            }
+            self.attempt_due(&watcher);
+        }
+    }
+
+    fn track(&mut self, path: PathBuf) {
+        if self.pending.iter().any(|p| p.path == path) {
+            return;
+        }
+        let backoff = self.options.lock().expect("mutex poisoned").initial_backoff;
+        tracing::debug!(path = %path.display(), "watched path removed, waiting for it to reappear");
+        self.pending.push(PendingPath {
+            path,
+            attempts: 0,
+            backoff,
+            next_attempt: Instant::now() + backoff,
+        });
+    }
+
+    /// Registers the missing paths that were created
+    fn settle(&self, watcher: &Mutex<RecommendedWatcher>) {
+        let watched_files = self.watched_files.lock().expect("mutex poisoned").clone();
+        let settled = {
+            let mut watcher = watcher.lock().expect("mutex poisoned");
+            self.missing
+                .lock()
+                .expect("mutex poisoned")
+                .settle(&mut watcher, &watched_files)
+        };
+        for (path, result) in settled {
+            match result {
+                Ok(()) => {
+                    tracing::info!(path = %path.display(), "missing path created, watching it");
+                    self.health.registered(&path);
+                }
+                Err(err) => {
+                    tracing::warn!(path = %path.display(), %err, "unable to watch created path");
+                    self.health.error(&path, &err);
+                    self.options
+                        .lock()
+                        .expect("mutex poisoned")
+                        .report(&path, &err);
+                }
+            }
+        }
+    }
+
+    fn attempt_due(&mut self, watcher: &Mutex<RecommendedWatcher>) {
+        let options = self.options.lock().expect("mutex poisoned").clone();
+        let watched_files = self.watched_files.lock().expect("mutex poisoned").clone();
+        let is_watching = self.is_watching.load(Ordering::SeqCst);
+        let now = Instant::now();
+
+        self.pending.retain_mut(|pending| {
+            if pending.next_attempt > now {
+                return true;
+            }
+            // the path was removed from the watch list, or the watcher was stopped, in the meantime
+            if !is_watching || !watched_files.iter().any(|f| Path::new(f) == pending.path) {
+                return false;
+            }
 
+            pending.attempts += 1;
+            if pending.path.exists() {
+                let result = watcher
+                    .lock()
+                    .expect("mutex poisoned")
+                    .watch(&pending.path, RecursiveMode::NonRecursive);
+                match result {
+                    Ok(()) => {
+                        tracing::debug!(path = %pending.path.display(), "removed path watched again");
+                        self.health.registered(&pending.path);
+                        return false;
+                    }
+                    Err(err) => {
+                        let err: Error = err.into();
+                        self.health.error(&pending.path, &err);
+                        options.report(&pending.path, &err);
+                    }
+                }
+            }
+
+            if options
+                .max_attempts
+                .is_some_and(|max| pending.attempts >= max)
+            {
+                let err = Error::Other(format!(
+                    "giving up on re-watching '{}' after {} attempts",
+                    pending.path.display(),
+                    pending.attempts
+                ));
+                tracing::warn!(%err);
+                self.health.failed(&pending.path, &err);
+                options.report(&pending.path, &err);
+                return false;
+            }
+
+            pending.backoff = (pending.backoff * 2).min(options.max_backoff);
+            pending.next_attempt = now + pending.backoff;
+            true
+        });
+    }
+}
+
+impl super::FileWatcher for NotifyWatcher {
     fn currently_watched(&self) -> Result<Vec<String>> {
         Ok(self.watched_files())
     }
 
+    fn events(&self) -> Receiver<WatchEvent> {
+        self.events.clone()
+    }
+
     fn apply_app_config(&mut self, config: &Config) -> Result {
         let file_list = config.read_tracked_files()?;
+        let filter = EventFilter::for_tracked(config.watch_events(), &config.read_tracking_list()?);
+        *self.filter.lock().expect("mutex poisoned") = filter;
         self.update_watched_files(file_list)?;
-        self.watcher.configure(
+        self.inner_watcher().configure(
             notify::Config::default()
                 .with_poll_interval(std::time::Duration::from_millis(config.delay())),
         )?;
-        Ok(())
+        self.fallback
+            .set_interval(Duration::from_millis(config.delay()))
     }
 
     fn start(&mut self) -> Result {
@@ -179,23 +1025,39 @@ impl super::FileWatcher for NotifyWatcher {
         self.stop_watch()
     }
 
-    fn start_with_config(
-        &mut self,
-        app_config: &Config,
-        impl_config: &Self::InnerConfig,
-    ) -> Result {
-        self.apply_app_config(app_config)?;
-        self.apply_inner_config(impl_config)?;
-        self.start()
+    fn watch_path(&mut self, path: &Path) -> Result {
+        NotifyWatcher::watch_path(self, path)
+    }
+
+    fn unwatch_path(&mut self, path: &Path) -> Result {
+        NotifyWatcher::unwatch_path(self, path)
+    }
+
+    fn pause(&mut self) -> Result {
+        NotifyWatcher::pause(self);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result {
+        NotifyWatcher::resume(self);
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        NotifyWatcher::is_paused(self)
     }
 
     fn start_with_app_config(&mut self, config: &Config) -> Result {
         self.apply_app_config(config)?;
         self.start()
     }
+}
+
+impl super::ConfigurableFileWatcher for NotifyWatcher {
+    type InnerConfig = notify::Config;
 
     fn apply_inner_config(&mut self, config: &Self::InnerConfig) -> Result {
-        self.watcher.configure(*config)?;
+        self.inner_watcher().configure(*config)?;
         Ok(())
     }
 }
@@ -256,22 +1118,14 @@ mod tests {
             let mut counter = 0usize;
 
             for _ in 0..25 {
-                let event = match watcher.event_stream().try_recv() {
-                    Ok(event) => event,
-                    Err(err) => {
-                        println!("channel error - {err:?}");
-                        break;
-                    }
-                };
-
-                match event {
+                match watcher.events().try_recv() {
                     Ok(event) => {
                         println!("event - {event:?}");
                         counter += 1;
                     }
                     Err(err) => {
-                        println!("event error - {err:?}");
-                        continue;
+                        println!("channel error - {err:?}");
+                        break;
                     }
                 }
             }
@@ -286,4 +1140,211 @@ mod tests {
         println!("event count: {event_count}");
         assert_ne!(event_count, 0, "at least one event should be received");
     }
+
+    #[test]
+    fn recovers_removed_path() {
+        let temp = setup_test_directory();
+        let file1 = temp.path().join("file1.txt");
+
+        let mut watcher = NotifyWatcher::new().expect("failed to create watcher");
+        watcher.set_recovery_options(RecoveryOptions {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+            ..RecoveryOptions::default()
+        });
+        watcher
+            .update_watched_files(vec![file1.to_str().unwrap().to_string()])
+            .expect("unable to update watched files");
+        watcher.start().expect("unable to start watcher");
+
+        std::fs::remove_file(&file1).expect("unable to remove file1");
+        std::thread::sleep(Duration::from_millis(50));
+        std::fs::write(&file1, "recreated").expect("unable to recreate file1");
+        std::thread::sleep(Duration::from_millis(200));
+        while watcher.event_stream().try_recv().is_ok() {}
+
+        std::fs::write(&file1, "modified").expect("unable to modify file1");
+        let event = watcher
+            .event_stream()
+            .recv_timeout(Duration::from_secs(2))
+            .expect("no event received after the path was recreated");
+        assert!(event.paths().contains(&file1.as_path()));
+    }
+
+    #[test]
+    fn watches_paths_at_runtime() {
+        let temp = setup_test_directory();
+        let file1 = temp.path().join("file1.txt");
+        let file2 = temp.path().join("file2.txt");
+        let drain = |watcher: &NotifyWatcher| {
+            std::thread::sleep(Duration::from_millis(100));
+            while watcher.event_stream().try_recv().is_ok() {}
+        };
+
+        let mut watcher = NotifyWatcher::new().expect("failed to create watcher");
+        watcher.start().expect("unable to start watcher");
+        watcher.watch_path(&file1).expect("unable to watch file1");
+        watcher.watch_path(&file1).expect("unable to watch file1");
+        assert_eq!(watcher.watched_files().len(), 1);
+        let missing = temp.path().join("missing");
+        watcher
+            .watch_path(&missing)
+            .expect("unable to watch a missing path");
+        assert_eq!(watcher.watched_files().len(), 2);
+        watcher
+            .unwatch_path(&missing)
+            .expect("unable to unwatch a missing path");
+        assert_eq!(watcher.watched_files().len(), 1);
+
+        std::fs::write(&file1, "modified").expect("unable to modify file1");
+        let event = watcher
+            .event_stream()
+            .recv_timeout(Duration::from_secs(2))
+            .expect("no event received for the added path");
+        assert!(event.paths().contains(&file1.as_path()));
+
+        drain(&watcher);
+        let raw = watcher.subscribe_raw();
+        watcher.pause();
+        std::fs::write(&file1, "paused").expect("unable to modify file1");
+        let event = raw
+            .recv_timeout(Duration::from_secs(2))
+            .expect("no raw event received while paused");
+        assert!(event.paths.contains(&file1));
+        drain(&watcher);
+        watcher.resume();
+        watcher
+            .unwatch_path(&file1)
+            .expect("unable to unwatch file1");
+        watcher.watch_path(&file2).expect("unable to watch file2");
+        std::fs::write(&file1, "unwatched").expect("unable to modify file1");
+        std::fs::write(&file2, "watched").expect("unable to modify file2");
+        let event = watcher
+            .event_stream()
+            .recv_timeout(Duration::from_secs(2))
+            .expect("no event received after resuming");
+        assert_eq!(event.paths(), [file2.as_path()]);
+    }
+
+    #[test]
+    fn watches_missing_path() {
+        let temp = setup_test_directory();
+        let dir = temp.path().join("sub").join("dir");
+        let file = dir.join("notes.txt");
+
+        let mut watcher = NotifyWatcher::new().expect("failed to create watcher");
+        watcher
+            .update_watched_files(vec![file.to_str().unwrap().to_string()])
+            .expect("unable to update watched files");
+        watcher.start().expect("unable to start watcher");
+        assert_eq!(watcher.health()[0].state(), WatchState::Pending);
+        assert!(watcher.health()[0].is_healthy());
+
+        // the changes to the ancestors are not reported
+        std::fs::write(temp.path().join("file1.txt"), "modified").expect("unable to modify");
+        std::fs::create_dir_all(&dir).expect("unable to create the parents");
+        std::thread::sleep(Duration::from_millis(100));
+        std::fs::write(dir.join("other.txt"), "unwatched").expect("unable to write other.txt");
+        std::fs::write(&file, "created").expect("unable to create the file");
+        let event = watcher
+            .event_stream()
+            .recv_timeout(Duration::from_secs(2))
+            .expect("no event received for the created path");
+        assert_eq!(event.paths(), [file.as_path()]);
+
+        let start = Instant::now();
+        while watcher.health()[0].state() != WatchState::Watching {
+            assert!(
+                start.elapsed() < Duration::from_secs(2),
+                "path was not registered"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        while watcher.event_stream().try_recv().is_ok() {}
+        std::fs::write(dir.join("other.txt"), "still unwatched").expect("unable to modify");
+        std::fs::write(&file, "modified").expect("unable to modify the file");
+        let event = watcher
+            .event_stream()
+            .recv_timeout(Duration::from_secs(2))
+            .expect("no event received after the path was registered");
+        assert_eq!(event.paths(), [file.as_path()]);
+    }
+
+    #[test]
+    fn reports_health() {
+        let temp = setup_test_directory();
+        let file1 = temp.path().join("file1.txt");
+
+        let mut watcher = NotifyWatcher::new().expect("failed to create watcher");
+        watcher.set_recovery_options(RecoveryOptions {
+            max_attempts: Some(1),
+            ..RecoveryOptions::default()
+        });
+        watcher.watch_path(&file1).expect("unable to watch file1");
+        let health = watcher.health();
+        assert_eq!(health.len(), 1);
+        assert_eq!(health[0].state(), WatchState::Stopped);
+
+        watcher.start().expect("unable to start watcher");
+        assert_eq!(watcher.health()[0].state(), WatchState::Watching);
+        assert_eq!(watcher.health()[0].last_event(), None);
+        std::fs::write(&file1, "modified").expect("unable to modify file1");
+        watcher
+            .event_stream()
+            .recv_timeout(Duration::from_secs(2))
+            .expect("no event received");
+        assert!(watcher.health()[0].last_event().is_some());
+
+        std::fs::remove_file(&file1).expect("unable to remove file1");
+        assert_eq!(watcher.health()[0].state(), WatchState::Lost);
+        std::thread::sleep(Duration::from_millis(300));
+        let health = watcher.health();
+        assert_eq!(health[0].state(), WatchState::Failed);
+        assert!(health[0].last_error().unwrap().contains("giving up"));
+    }
+
+    #[test]
+    fn keeps_the_latest_errors() {
+        let watched = Mutex::new(Vec::new());
+        let (tx, rx) = bounded(2);
+        for name in ["a", "b", "c"] {
+            let err = notify::Error::path_not_found().add_path(PathBuf::from(name));
+            report_error(&watched, &HealthTracker::default(), (&tx, &rx), &err);
+        }
+        let paths = rx
+            .try_iter()
+            .map(|err| err.path().unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [PathBuf::from("b"), PathBuf::from("c")]);
+    }
+
+    #[test]
+    fn reports_unrecoverable_path() {
+        let temp = setup_test_directory();
+        let file1 = temp.path().join("file1.txt");
+        let (err_tx, err_rx) = unbounded();
+
+        let mut watcher = NotifyWatcher::new().expect("failed to create watcher");
+        watcher.set_recovery_options(
+            RecoveryOptions {
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+                max_attempts: Some(3),
+                ..RecoveryOptions::default()
+            }
+            .with_error_callback(move |path, _| {
+                err_tx.send(path.to_path_buf()).ok();
+            }),
+        );
+        watcher
+            .update_watched_files(vec![file1.to_str().unwrap().to_string()])
+            .expect("unable to update watched files");
+        watcher.start().expect("unable to start watcher");
+
+        std::fs::remove_file(&file1).expect("unable to remove file1");
+        let path = err_rx
+            .recv_timeout(Duration::from_secs(2))
+            .expect("error callback was not invoked");
+        assert_eq!(path, file1);
+    }
 }
@@ -24,8 +24,20 @@
 //!   * [`soft_panic_or_log`](crate::soft_panic_or_log)
 //!
 //! Due to limitations in Rust, these macros are exported at the crate root.
+//!
+//! # Test assertions
+//!
+//! Assertions for tests whose failure messages tell more than [`assert!`] or [`assert_eq!`]:
+//!
+//!   * [`assert_contains`](crate::assert_contains)
+//!   * [`assert_ok`](crate::assert_ok)
+//!   * [`assert_err_matches`](crate::assert_err_matches)
+//!   * [`assert_between`](crate::assert_between)
+//!   * [`assert_approx_eq`](crate::assert_approx_eq)
+//!   * [`assert_bytes_eq`](crate::assert_bytes_eq), which prints a hex dump of where two byte
+//!     slices differ
 
-use std::sync::atomic::AtomicBool;
+use std::{fmt::Write, sync::atomic::AtomicBool};
 
 use crate::env;
 
@@ -164,6 +176,241 @@ macro_rules! assert_contains {
     }};
 }
 
+/// Asserts that a [`Result`] is `Ok` and evaluates to its value.
+///
+/// On failure the error is printed with its [`Debug`](std::fmt::Debug) representation, along
+/// with the optional message.
+///
+/// # Examples
+///
+/// ```
+/// use xstd::assert_ok;
+/// let value = assert_ok!("42".parse::<u32>());
+/// assert_eq!(value, 42);
+/// ```
+///
+/// ```should_panic
+/// use xstd::assert_ok;
+/// assert_ok!("forty-two".parse::<u32>(), "parsing the answer");
+/// ```
+#[macro_export]
+macro_rules! assert_ok {
+    ($result:expr $(,)?) => {
+        match $result {
+            Ok(value) => value,
+            Err(err) => panic!("assertion failed: `{}` is `Err`:\n error: `{:?}`", stringify!($result), err),
+        }
+    };
+    ($result:expr, $($arg:tt)+) => {
+        match $result {
+            Ok(value) => value,
+            Err(err) => panic!(
+                "assertion failed: `{}` is `Err`: {}\n error: `{:?}`",
+                stringify!($result),
+                format_args!($($arg)+),
+                err
+            ),
+        }
+    };
+}
+
+/// Asserts that a [`Result`] is an `Err` matching the given pattern, with an optional guard.
+///
+/// On failure the `Ok` value or the error that did not match is printed with its
+/// [`Debug`](std::fmt::Debug) representation.
+///
+/// # Examples
+///
+/// ```
+/// use std::num::IntErrorKind;
+/// use xstd::assert_err_matches;
+/// assert_err_matches!("".parse::<u32>(), err if *err.kind() == IntErrorKind::Empty);
+/// assert_err_matches!(std::fs::read("/does/not/exist"), _);
+/// ```
+///
+/// ```should_panic
+/// use xstd::assert_err_matches;
+/// assert_err_matches!("42".parse::<u32>(), _);
+/// ```
+#[macro_export]
+macro_rules! assert_err_matches {
+    ($result:expr, $($pattern:pat_param)|+ $(if $guard:expr)? $(,)?) => {
+        match $result {
+            Err($($pattern)|+) $(if $guard)? => {}
+            Err(err) => panic!(
+                "assertion failed: `{}` does not match `Err({})`:\n error: `{:?}`",
+                stringify!($result),
+                stringify!($($pattern)|+ $(if $guard)?),
+                err
+            ),
+            Ok(value) => panic!(
+                "assertion failed: `{}` is `Ok`, expected `Err({})`:\n value: `{:?}`",
+                stringify!($result),
+                stringify!($($pattern)|+ $(if $guard)?),
+                value
+            ),
+        }
+    };
+}
+
+/// Asserts that a value lies within an inclusive range, `low <= value <= high`.
+///
+/// # Examples
+///
+/// ```
+/// use xstd::assert_between;
+/// assert_between!(5, 1, 10);
+/// assert_between!(1.5, 1.5, 2.0);
+/// ```
+///
+/// ```should_panic
+/// use xstd::assert_between;
+/// assert_between!(11, 1, 10);
+/// ```
+#[macro_export]
+macro_rules! assert_between {
+    ($value:expr, $low:expr, $high:expr $(,)?) => {{
+        let (value, low, high) = (&$value, &$low, &$high);
+        if !(*low <= *value && *value <= *high) {
+            panic!(
+                "assertion failed: `low <= value <= high`:\n value: `{:?}`\n   low: `{:?}`\n  high: `{:?}`",
+                value, low, high
+            );
+        }
+    }};
+}
+
+/// Asserts that two numbers differ by at most `epsilon`, e.g. floating point numbers that went
+/// through different calculations. A `NaN` is never close to anything.
+///
+/// # Examples
+///
+/// ```
+/// use xstd::assert_approx_eq;
+/// assert_approx_eq!(0.1 + 0.2, 0.3, 1e-9);
+/// ```
+///
+/// ```should_panic
+/// use xstd::assert_approx_eq;
+/// assert_approx_eq!(1.0, 1.1, 0.01);
+/// ```
+#[macro_export]
+macro_rules! assert_approx_eq {
+    ($left:expr, $right:expr, $epsilon:expr $(,)?) => {{
+        let (left, right, epsilon) = ($left, $right, $epsilon);
+        let difference = if left > right { left - right } else { right - left };
+        if !(difference <= epsilon) {
+            panic!(
+                "assertion failed: `|left - right| <= epsilon`:\n    left: `{:?}`\n   right: `{:?}`\n epsilon: `{:?}`",
+                left, right, epsilon
+            );
+        }
+    }};
+}
+
+/// Asserts that two byte slices are equal, printing a hex dump of both around the first
+/// difference when they are not (see [`bytes_diff`]), instead of two long lists of numbers.
+///
+/// Accepts anything that is [`AsRef<[u8]>`](AsRef), e.g. a `Vec<u8>`, a `&[u8]` or a byte
+/// string literal.
+///
+/// # Examples
+///
+/// ```
+/// use xstd::assert_bytes_eq;
+/// assert_bytes_eq!(b"hello".to_vec(), b"hello");
+/// ```
+///
+/// ```should_panic
+/// use xstd::assert_bytes_eq;
+/// assert_bytes_eq!(b"hello world", b"hello_world");
+/// ```
+#[macro_export]
+macro_rules! assert_bytes_eq {
+    ($left:expr, $right:expr $(,)?) => {{
+        if let Some(diff) = $crate::assert::bytes_diff(
+            ::std::convert::AsRef::<[u8]>::as_ref(&$left),
+            ::std::convert::AsRef::<[u8]>::as_ref(&$right),
+        ) {
+            panic!("assertion failed: `left == right`\n{}", diff);
+        }
+    }};
+    ($left:expr, $right:expr, $($arg:tt)+) => {{
+        if let Some(diff) = $crate::assert::bytes_diff(
+            ::std::convert::AsRef::<[u8]>::as_ref(&$left),
+            ::std::convert::AsRef::<[u8]>::as_ref(&$right),
+        ) {
+            panic!("assertion failed: `left == right`: {}\n{}", format_args!($($arg)+), diff);
+        }
+    }};
+}
+
+/// The number of bytes in a row of the hex dumps of [`bytes_diff`]
+const DUMP_WIDTH: usize = 16;
+/// The number of rows [`bytes_diff`] dumps, starting a row before the first difference
+const DUMP_ROWS: usize = 4;
+
+/// Describes how two byte slices differ, `None` if they are equal: their lengths, the offset of
+/// the first difference, and a hex dump of both around it with the differing bytes marked.
+///
+/// # Examples
+///
+/// ```
+/// use xstd::assert::bytes_diff;
+/// assert!(bytes_diff(b"same", b"same").is_none());
+/// let diff = bytes_diff(b"hello world", b"hello_world").unwrap();
+/// assert!(diff.contains("first difference at offset 0x5"));
+/// ```
+#[must_use]
+pub fn bytes_diff(left: &[u8], right: &[u8]) -> Option<String> {
+    let first = left
+        .iter()
+        .zip(right)
+        .position(|(l, r)| l != r)
+        .or_else(|| (left.len() != right.len()).then(|| left.len().min(right.len())))?;
+    let start = (first / DUMP_WIDTH).saturating_sub(1) * DUMP_WIDTH;
+    let end = start + DUMP_WIDTH * DUMP_ROWS;
+
+    let mut out = String::new();
+    let _ = writeln!(out, "  left: {} byte(s)", left.len());
+    let _ = writeln!(out, " right: {} byte(s)", right.len());
+    let _ = writeln!(out, "first difference at offset {first:#x}");
+    for (name, bytes, other) in [("left", left, right), ("right", right, left)] {
+        let _ = writeln!(out, "{name}:");
+        for row in (start..end.min(bytes.len())).step_by(DUMP_WIDTH) {
+            dump_row(&mut out, bytes, other, row);
+        }
+    }
+    Some(out)
+}
+
+/// Writes the row of `bytes` at `offset` as hex and ascii, marking the bytes that differ from
+/// `other` with a `*`
+fn dump_row(out: &mut String, bytes: &[u8], other: &[u8], offset: usize) {
+    let row = &bytes[offset..bytes.len().min(offset + DUMP_WIDTH)];
+    let _ = write!(out, "  {offset:08x} ");
+    for (i, byte) in row.iter().enumerate() {
+        let marker = if other.get(offset + i) == Some(byte) {
+            ' '
+        } else {
+            '*'
+        };
+        let _ = write!(out, "{marker}{byte:02x}");
+    }
+    for _ in row.len()..DUMP_WIDTH {
+        out.push_str("   ");
+    }
+    out.push_str("  |");
+    out.extend(row.iter().map(|&byte| {
+        if byte.is_ascii_graphic() || byte == b' ' {
+            char::from(byte)
+        } else {
+            '.'
+        }
+    }));
+    out.push_str("|\n");
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -183,4 +430,51 @@ mod tests {
     fn test_assert_contains_fail() {
         assert_contains!("hello", "yellow");
     }
+
+    #[test]
+    fn test_assert_results() {
+        assert_eq!(assert_ok!("7".parse::<u8>()), 7);
+        assert_err_matches!("300".parse::<u8>(), err if err.to_string().contains("too large"));
+        let result = std::panic::catch_unwind(|| assert_err_matches!("7".parse::<u8>(), _));
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert_contains!(&message, "is `Ok`");
+        assert_contains!(&message, "value: `7`");
+    }
+
+    #[test]
+    fn test_assert_ranges() {
+        assert_between!(3, 3, 4);
+        assert_between!("b", "a", "c");
+        assert_approx_eq!(1.0_f32 / 3.0, 0.333, 0.001);
+        let result = std::panic::catch_unwind(|| assert_between!(0.5, 1.0, 2.0));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_bytes_diff() {
+        assert!(super::bytes_diff(b"", b"").is_none());
+        let left = (0..=255).collect::<Vec<u8>>();
+        let mut right = left.clone();
+        right[0x42] = 0;
+        right.truncate(0x60);
+        let diff = super::bytes_diff(&left, &right).unwrap();
+        assert_contains!(&diff, "  left: 256 byte(s)\n right: 96 byte(s)\n");
+        assert_contains!(&diff, "first difference at offset 0x42");
+        // the dump starts a row before the difference, which is marked on both sides
+        assert_contains!(&diff, "  00000030  30 31");
+        assert_contains!(&diff, "*42 43");
+        assert_contains!(&diff, "*00 43");
+        assert!(!diff.contains("00000020"));
+
+        // a shorter slice differs where it ends
+        let diff = super::bytes_diff(b"abc", b"ab").unwrap();
+        assert_contains!(&diff, "first difference at offset 0x2");
+        assert_contains!(&diff, "  00000000  61 62*63");
+    }
+
+    #[test]
+    #[should_panic(expected = "first difference at offset 0x1")]
+    fn test_assert_bytes_eq_fail() {
+        assert_bytes_eq!(vec![1, 2, 3], [1, 3, 3]);
+    }
 }
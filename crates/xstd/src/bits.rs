@@ -10,3 +10,281 @@ pub const fn align_up<const N: usize>(p: usize) -> usize {
         p + (N - (p % N))
     }
 }
+
+/// Like [`align_up`], but returns `None` instead of overflowing.
+#[must_use]
+pub const fn checked_align_up<const N: usize>(p: usize) -> Option<usize> {
+    if p.is_multiple_of(N) {
+        Some(p)
+    } else {
+        p.checked_add(N - (p % N))
+    }
+}
+
+/// Decreases `p` as little as possible (including possibly 0)
+/// such that it becomes a multiple of `N`.
+#[must_use]
+pub const fn align_down<const N: usize>(p: usize) -> usize {
+    p - (p % N)
+}
+
+/// Checks whether `p` is a multiple of `N` with a mask instead of a
+/// division, which requires `N` to be a power of two.
+///
+/// ## Panics
+/// Panics if `N` is not a power of two
+#[must_use]
+pub const fn is_power_of_two_align<const N: usize>(p: usize) -> bool {
+    assert!(N.is_power_of_two(), "the alignment must be a power of two");
+    p & (N - 1) == 0
+}
+
+/// Gets the smallest power of two that is at least `p` and at least
+/// `minimum`, e.g. to size a buffer. Returns `None` if it does not fit a
+/// `usize`.
+#[must_use]
+pub const fn next_power_of_two_at_least(p: usize, minimum: usize) -> Option<usize> {
+    let p = if p < minimum { minimum } else { p };
+    p.checked_next_power_of_two()
+}
+
+/// Declares a set of bit flags backed by an unsigned integer, similar to
+/// the `bitflags` crate but without the dependency.
+///
+/// The struct gets a constant per flag, the usual set operations (`|`,
+/// `&`, `-`, `!` and their assigning forms) and a [`Debug`] implementation
+/// listing the names of the set flags. Flags may span several bits, e.g. a
+/// field selecting one of a few codecs.
+///
+/// ```
+/// xstd::bitflags! {
+///     /// The options of a stored file
+///     pub struct Flags: u8 {
+///         /// The contents are encrypted
+///         const ENCRYPTED = 1 << 0;
+///         /// The contents are a delta against the previous version
+///         const DELTA = 1 << 1;
+///     }
+/// }
+///
+/// let mut flags = Flags::ENCRYPTED | Flags::DELTA;
+/// assert!(flags.contains(Flags::DELTA));
+/// flags.remove(Flags::ENCRYPTED);
+/// assert_eq!(flags.bits(), 0b10);
+/// assert_eq!(Flags::from_bits(0b100), None);
+/// assert_eq!(format!("{flags:?}"), "Flags(DELTA)");
+/// ```
+#[macro_export]
+macro_rules! bitflags {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident: $repr:ty {
+            $(
+                $(#[$flag_meta:meta])*
+                const $flag:ident = $value:expr;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+        $vis struct $name {
+            bits: $repr,
+        }
+
+        impl $name {
+            $(
+                $(#[$flag_meta])*
+                pub const $flag: Self = Self { bits: $value };
+            )*
+
+            /// Every flag with its name, in declaration order
+            pub const FLAGS: &'static [(&'static str, Self)] = &[$((stringify!($flag), Self::$flag)),*];
+
+            /// Gets the set without any flags
+            #[must_use]
+            pub const fn empty() -> Self {
+                Self { bits: 0 }
+            }
+
+            /// Gets the set of every declared flag
+            #[must_use]
+            pub const fn all() -> Self {
+                Self { bits: 0 $(| $value)* }
+            }
+
+            /// Gets the raw bits of the set
+            #[must_use]
+            pub const fn bits(&self) -> $repr {
+                self.bits
+            }
+
+            /// Creates a set from raw bits, or `None` if any bit does not
+            /// belong to a declared flag
+            #[must_use]
+            pub const fn from_bits(bits: $repr) -> Option<Self> {
+                if bits & !Self::all().bits == 0 {
+                    Some(Self { bits })
+                } else {
+                    None
+                }
+            }
+
+            /// Creates a set from raw bits, dropping the bits that do not
+            /// belong to a declared flag
+            #[must_use]
+            pub const fn from_bits_truncate(bits: $repr) -> Self {
+                Self { bits: bits & Self::all().bits }
+            }
+
+            /// Returns true if no flag is set
+            #[must_use]
+            pub const fn is_empty(&self) -> bool {
+                self.bits == 0
+            }
+
+            /// Returns true if every flag of `other` is set
+            #[must_use]
+            pub const fn contains(&self, other: Self) -> bool {
+                self.bits & other.bits == other.bits
+            }
+
+            /// Returns true if any flag of `other` is set
+            #[must_use]
+            pub const fn intersects(&self, other: Self) -> bool {
+                self.bits & other.bits != 0
+            }
+
+            /// Sets the flags of `other`
+            pub fn insert(&mut self, other: Self) {
+                self.bits |= other.bits;
+            }
+
+            /// Clears the flags of `other`
+            pub fn remove(&mut self, other: Self) {
+                self.bits &= !other.bits;
+            }
+
+            /// Sets or clears the flags of `other`
+            pub fn set(&mut self, other: Self, value: bool) {
+                if value {
+                    self.insert(other);
+                } else {
+                    self.remove(other);
+                }
+            }
+        }
+
+        impl ::std::ops::BitOr for $name {
+            type Output = Self;
+
+            fn bitor(self, other: Self) -> Self {
+                Self { bits: self.bits | other.bits }
+            }
+        }
+
+        impl ::std::ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, other: Self) {
+                self.bits |= other.bits;
+            }
+        }
+
+        impl ::std::ops::BitAnd for $name {
+            type Output = Self;
+
+            fn bitand(self, other: Self) -> Self {
+                Self { bits: self.bits & other.bits }
+            }
+        }
+
+        impl ::std::ops::BitAndAssign for $name {
+            fn bitand_assign(&mut self, other: Self) {
+                self.bits &= other.bits;
+            }
+        }
+
+        impl ::std::ops::Sub for $name {
+            type Output = Self;
+
+            fn sub(self, other: Self) -> Self {
+                Self { bits: self.bits & !other.bits }
+            }
+        }
+
+        impl ::std::ops::SubAssign for $name {
+            fn sub_assign(&mut self, other: Self) {
+                self.bits &= !other.bits;
+            }
+        }
+
+        impl ::std::ops::Not for $name {
+            type Output = Self;
+
+            fn not(self) -> Self {
+                Self::from_bits_truncate(!self.bits)
+            }
+        }
+
+        impl ::std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                write!(f, "{}(", stringify!($name))?;
+                let mut rest = self.bits;
+                let mut separator = "";
+                for (name, flag) in Self::FLAGS {
+                    if flag.bits != 0 && self.contains(*flag) {
+                        write!(f, "{separator}{name}")?;
+                        separator = " | ";
+                        rest &= !flag.bits;
+                    }
+                }
+                if rest != 0 {
+                    write!(f, "{separator}{rest:#x}")?;
+                }
+                f.write_str(")")
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::bitflags! {
+        struct Flags: u8 {
+            const A = 1 << 0;
+            const B = 1 << 1;
+            const CODEC = 0b1100;
+        }
+    }
+
+    #[test]
+    fn aligns() {
+        assert_eq!(align_up::<8>(9), 16);
+        assert_eq!(align_down::<8>(15), 8);
+        assert_eq!(align_down::<8>(16), 16);
+        assert_eq!(checked_align_up::<8>(16), Some(16));
+        assert_eq!(checked_align_up::<8>(usize::MAX), None);
+        assert!(is_power_of_two_align::<4>(12));
+        assert!(!is_power_of_two_align::<4>(13));
+        assert_eq!(next_power_of_two_at_least(5, 2), Some(8));
+        assert_eq!(next_power_of_two_at_least(5, 64), Some(64));
+        assert_eq!(next_power_of_two_at_least(usize::MAX, 1), None);
+    }
+
+    #[test]
+    fn combines_flags() {
+        let mut flags = Flags::A | Flags::CODEC;
+        assert!(flags.contains(Flags::A));
+        assert!(!flags.contains(Flags::B));
+        assert!(flags.intersects(Flags::from_bits_truncate(0b0100)));
+        flags.set(Flags::B, true);
+        flags -= Flags::A;
+        assert_eq!(flags.bits(), 0b1110);
+        assert_eq!(!flags, Flags::A);
+        assert_eq!(Flags::all().bits(), 0b1111);
+        assert_eq!(Flags::from_bits(0b1_0000), None);
+        assert_eq!(Flags::from_bits_truncate(0b1_0001), Flags::A);
+        assert_eq!(format!("{flags:?}"), "Flags(B | CODEC)");
+        assert_eq!(format!("{:?}", Flags::empty()), "Flags()");
+    }
+}
@@ -6,8 +6,12 @@ use std::collections::BTreeMap;
 use std::fmt::{Debug, Display};
 
 mod hash;
+mod lru;
+mod multimap;
 
 pub use self::hash::{HashMap, HashSet};
+pub use self::lru::LruCache;
+pub use self::multimap::MultiMap;
 
 /// Extension methods for collections.
 pub trait CollectionExt<T>: Sized
@@ -0,0 +1,325 @@
+//! A bounded cache evicting the least recently used entry.
+
+use std::borrow::Borrow;
+use std::hash::Hash;
+
+use super::HashMap;
+
+/// Marks the end of the recency list
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// The next more recently used entry
+    prev: usize,
+    /// The next less recently used entry
+    next: usize,
+}
+
+/// A cache holding up to a fixed number of entries. Inserting into a full cache evicts the
+/// entry that was used least recently, where an entry is used by inserting it or by looking it
+/// up with [`LruCache::get`] or [`LruCache::get_mut`].
+///
+/// Every operation takes constant time, the entries are kept in a single allocation.
+///
+/// ```
+/// use xstd::collections::LruCache;
+///
+/// let mut cache = LruCache::new(2);
+/// cache.insert("a", 1);
+/// cache.insert("b", 2);
+/// assert_eq!(cache.get("a"), Some(&1));
+/// // "b" is the least recently used entry now
+/// assert_eq!(cache.insert("c", 3), Some(("b", 2)));
+/// assert!(!cache.contains_key("b"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LruCache<K, V> {
+    map: HashMap<K, usize>,
+    nodes: Vec<Node<K, V>>,
+    /// The most recently used entry
+    head: usize,
+    /// The least recently used entry
+    tail: usize,
+    capacity: usize,
+}
+
+impl<K, V> LruCache<K, V> {
+    /// Creates an empty cache holding up to `capacity` entries
+    ///
+    /// ## Panics
+    /// Panics if `capacity` is zero
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "an LruCache must be able to hold an entry");
+        Self {
+            map: HashMap::new(),
+            nodes: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            capacity,
+        }
+    }
+
+    /// Gets the number of entries the cache holds at most
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Gets the number of entries in the cache
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns true if the cache holds no entries
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Removes every entry
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.nodes.clear();
+        self.head = NIL;
+        self.tail = NIL;
+    }
+
+    /// Iterates over the entries from the most to the least recently used one, without marking
+    /// any of them as used
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        let mut index = self.head;
+        std::iter::from_fn(move || {
+            let node = self.nodes.get(index)?;
+            index = node.next;
+            Some((&node.key, &node.value))
+        })
+    }
+
+    /// Unlinks the entry at `index` from the recency list
+    fn detach(&mut self, index: usize) {
+        let Node { prev, next, .. } = self.nodes[index];
+        match self.nodes.get_mut(prev) {
+            Some(node) => node.next = next,
+            None => self.head = next,
+        }
+        match self.nodes.get_mut(next) {
+            Some(node) => node.prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    /// Links the (detached) entry at `index` in as the most recently used one
+    fn attach_front(&mut self, index: usize) {
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = self.head;
+        match self.nodes.get_mut(self.head) {
+            Some(node) => node.prev = index,
+            None => self.tail = index,
+        }
+        self.head = index;
+    }
+
+    fn touch(&mut self, index: usize) {
+        if self.head != index {
+            self.detach(index);
+            self.attach_front(index);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// Gets the value of `key` and marks it as the most recently used entry
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        self.touch(index);
+        Some(&self.nodes[index].value)
+    }
+
+    /// Gets the value of `key` for modification and marks it as the most recently used entry
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        self.touch(index);
+        Some(&mut self.nodes[index].value)
+    }
+
+    /// Gets the value of `key` without marking it as used
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key).map(|index| &self.nodes[*index].value)
+    }
+
+    /// Returns true if the cache holds `key`, without marking it as used
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Inserts `value` for `key` as the most recently used entry. Returns the entry that was
+    /// pushed out: the previous value of `key`, or the least recently used entry if the cache
+    /// was full.
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(&index) = self.map.get(&key) {
+            self.touch(index);
+            let old = std::mem::replace(&mut self.nodes[index].value, value);
+            return Some((key, old));
+        }
+
+        let node = Node {
+            key: key.clone(),
+            value,
+            prev: NIL,
+            next: NIL,
+        };
+        if self.nodes.len() < self.capacity {
+            self.nodes.push(node);
+            let index = self.nodes.len() - 1;
+            self.map.insert(key, index);
+            self.attach_front(index);
+            return None;
+        }
+
+        // the slot of the least recently used entry is reused
+        let index = self.tail;
+        self.detach(index);
+        let evicted = std::mem::replace(&mut self.nodes[index], node);
+        self.map.remove(&evicted.key);
+        self.map.insert(key, index);
+        self.attach_front(index);
+        Some((evicted.key, evicted.value))
+    }
+
+    /// Gets the value of `key` like [`LruCache::get`], inserting the value produced by `f` first
+    /// if the cache does not hold it. Nothing is inserted if `f` fails.
+    ///
+    /// ## Errors
+    /// - Returns the error of `f`
+    pub fn get_or_try_insert_with<E>(
+        &mut self,
+        key: K,
+        f: impl FnOnce() -> Result<V, E>,
+    ) -> Result<&V, E> {
+        if let Some(&index) = self.map.get(&key) {
+            self.touch(index);
+            return Ok(&self.nodes[index].value);
+        }
+        self.insert(key, f()?);
+        Ok(&self.nodes[self.head].value)
+    }
+
+    /// Removes `key` from the cache, returning its value
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.map.remove(key)?;
+        self.detach(index);
+        let last = self.nodes.len() - 1;
+        let removed = self.nodes.swap_remove(index);
+        if index != last {
+            // the last entry moved into the freed slot, so its links have to follow it
+            let Node { prev, next, .. } = self.nodes[index];
+            match self.nodes.get_mut(prev) {
+                Some(node) => node.next = index,
+                None => self.head = index,
+            }
+            match self.nodes.get_mut(next) {
+                Some(node) => node.prev = index,
+                None => self.tail = index,
+            }
+            if let Some(moved) = self.map.get_mut::<K>(&self.nodes[index].key) {
+                *moved = index;
+            }
+        }
+        Some(removed.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(cache: &LruCache<u32, u32>) -> Vec<u32> {
+        cache.iter().map(|(key, _)| *key).collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(3);
+        for key in 1..=3 {
+            assert_eq!(cache.insert(key, key * 10), None);
+        }
+        assert_eq!(order(&cache), [3, 2, 1]);
+
+        assert_eq!(cache.get(&1), Some(&10));
+        assert_eq!(cache.peek(&2), Some(&20));
+        assert_eq!(order(&cache), [1, 3, 2]);
+        assert_eq!(cache.insert(4, 40), Some((2, 20)));
+        assert_eq!(order(&cache), [4, 1, 3]);
+
+        // replacing a value uses the entry without evicting another one
+        assert_eq!(cache.insert(3, 31), Some((3, 30)));
+        *cache.get_mut(&1).unwrap() += 1;
+        assert_eq!(order(&cache), [1, 3, 4]);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.peek(&1), Some(&11));
+    }
+
+    #[test]
+    fn removes_entries() {
+        let mut cache = LruCache::new(4);
+        for key in 1..=4 {
+            cache.insert(key, key);
+        }
+        assert_eq!(cache.remove(&1), Some(1));
+        assert_eq!(cache.remove(&1), None);
+        assert_eq!(order(&cache), [4, 3, 2]);
+        assert_eq!(cache.remove(&4), Some(4));
+        assert_eq!(order(&cache), [3, 2]);
+        cache.insert(5, 5);
+        assert_eq!(order(&cache), [5, 3, 2]);
+        assert_eq!(cache.get(&2), Some(&2));
+        assert_eq!(order(&cache), [2, 5, 3]);
+
+        cache.clear();
+        assert!(cache.is_empty());
+        cache.insert(6, 6);
+        assert_eq!(order(&cache), [6]);
+    }
+
+    #[test]
+    fn inserts_on_demand() {
+        let mut cache = LruCache::new(1);
+        let value: Result<&u32, ()> = cache.get_or_try_insert_with(1, || Ok(10));
+        assert_eq!(value, Ok(&10));
+        assert_eq!(
+            cache.get_or_try_insert_with(1, || Err("not called")),
+            Ok(&10)
+        );
+        assert_eq!(
+            cache.get_or_try_insert_with(2, || Err("failed")),
+            Err("failed")
+        );
+        assert_eq!(cache.get_or_try_insert_with(2, || Ok::<_, ()>(20)), Ok(&20));
+        assert!(!cache.contains_key(&1));
+    }
+}
@@ -0,0 +1,211 @@
+//! A map from keys to any number of values, keeping the order of insertion.
+
+use std::borrow::Borrow;
+
+/// A map holding any number of values per key. Both the keys and the values of each key keep the
+/// order they were first inserted in, which makes iteration deterministic.
+///
+/// The entries are kept in a [`Vec`] and looked up by comparing keys, so this is meant for small
+/// maps (up to a few hundred keys). Use a [`BTreeMap`](std::collections::BTreeMap) of vectors for
+/// larger ones.
+///
+/// ```
+/// use xstd::collections::MultiMap;
+///
+/// let mut map = MultiMap::new();
+/// map.insert("b", 1);
+/// map.insert("a", 2);
+/// map.insert("b", 3);
+/// assert_eq!(map.get("b"), [1, 3]);
+/// assert_eq!(map.keys().collect::<Vec<_>>(), [&"b", &"a"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiMap<K, V> {
+    entries: Vec<(K, Vec<V>)>,
+}
+
+impl<K, V> Default for MultiMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<K, V> MultiMap<K, V> {
+    /// Creates an empty [`MultiMap`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the number of keys
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Gets the number of values of all keys together
+    #[must_use]
+    pub fn values_len(&self) -> usize {
+        self.entries.iter().map(|(_, values)| values.len()).sum()
+    }
+
+    /// Returns true if the map holds no keys
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes every key and value
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Iterates over the keys, in the order they were first inserted
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    /// Iterates over each key with all of its values, in the order the keys were first inserted
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &[V])> + '_ {
+        self.entries
+            .iter()
+            .map(|(key, values)| (key, values.as_slice()))
+    }
+
+    /// Iterates over every value together with its key, grouped by key
+    pub fn flat_iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.entries
+            .iter()
+            .flat_map(|(key, values)| values.iter().map(move |value| (key, value)))
+    }
+}
+
+impl<K: Eq, V> MultiMap<K, V> {
+    /// Appends `value` to the values of `key`. A new key is placed after all existing ones.
+    pub fn insert(&mut self, key: K, value: V) {
+        match self.position(&key) {
+            Some(index) => self.entries[index].1.push(value),
+            None => self.entries.push((key, vec![value])),
+        }
+    }
+
+    /// Gets the values of `key`, which are empty if the key is not in the map
+    pub fn get<Q>(&self, key: &Q) -> &[V]
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.position(key)
+            .map_or(&[], |index| self.entries[index].1.as_slice())
+    }
+
+    /// Gets the values of `key` for modification, if the key is in the map
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut Vec<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let index = self.position(key)?;
+        Some(&mut self.entries[index].1)
+    }
+
+    /// Returns true if the map holds at least one value for `key`
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.position(key).is_some()
+    }
+
+    /// Removes `key` together with its values, which are returned in insertion order. The order
+    /// of the remaining keys is kept.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Vec<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let index = self.position(key)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    /// Keeps only the values for which `f` returns true, keys left without values are removed
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) {
+        for (key, values) in &mut self.entries {
+            values.retain(|value| f(key, value));
+        }
+        self.entries.retain(|(_, values)| !values.is_empty());
+    }
+
+    fn position<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.entries.iter().position(|(k, _)| k.borrow() == key)
+    }
+}
+
+impl<K: Eq, V> Extend<(K, V)> for MultiMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Eq, V> FromIterator<(K, V)> for MultiMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V> IntoIterator for MultiMap<K, V> {
+    type Item = (K, Vec<V>);
+    type IntoIter = std::vec::IntoIter<(K, Vec<V>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_insertion_order() {
+        let mut map: MultiMap<String, u32> = [("b", 1), ("a", 2), ("b", 3), ("c", 4)]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.values_len(), 4);
+        assert_eq!(map.get("b"), [1, 3]);
+        assert!(map.get("d").is_empty());
+        assert_eq!(
+            map.flat_iter()
+                .map(|(key, value)| format!("{key}{value}"))
+                .collect::<Vec<_>>(),
+            ["b1", "b3", "a2", "c4"]
+        );
+
+        map.get_mut("a").unwrap().push(5);
+        assert_eq!(map.remove("b"), Some(vec![1, 3]));
+        assert_eq!(map.remove("b"), None);
+        map.insert("b".to_string(), 6);
+        assert_eq!(map.keys().collect::<Vec<_>>(), ["a", "c", "b"]);
+
+        map.retain(|key, value| key != "a" || *value != 5);
+        map.retain(|_, value| *value != 4);
+        assert!(!map.contains_key("c"));
+        assert_eq!(
+            map.into_iter().collect::<Vec<_>>(),
+            [("a".to_string(), vec![2]), ("b".to_string(), vec![6])]
+        );
+    }
+}
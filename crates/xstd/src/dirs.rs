@@ -0,0 +1,305 @@
+//! Platform-aware user directories.
+//!
+//! The directories follow the conventions of each platform: the XDG base directories on unix,
+//! `~/Library` on macOS and the known folders (`%APPDATA%`, `%LOCALAPPDATA%`) on Windows. The
+//! free functions resolve them for the current platform from the process environment, the
+//! methods of [`Platform`] resolve them for any platform from any environment.
+
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+
+/// A family of operating systems sharing the same directory conventions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// Linux, the BSDs and every other unix except macOS, following the XDG base directories
+    Unix,
+    /// macOS, keeping application files below `~/Library`
+    MacOs,
+    /// Windows, using the known folders of the user profile
+    Windows,
+}
+
+impl Platform {
+    /// Gets the platform this program was compiled for.
+    #[must_use]
+    pub fn current() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else if cfg!(target_os = "macos") {
+            Self::MacOs
+        } else {
+            Self::Unix
+        }
+    }
+
+    /// Gets the separator between path components, `\` on Windows and `/` everywhere else.
+    #[must_use]
+    pub fn separator(self) -> char {
+        match self {
+            Self::Windows => '\\',
+            Self::Unix | Self::MacOs => '/',
+        }
+    }
+
+    /// Reports whether `path` is absolute on this platform. On Windows this means it starts with
+    /// a drive (`C:\`) or is a UNC path (`\\server\share`), either separator is accepted.
+    ///
+    /// ```
+    /// use xstd::dirs::Platform;
+    ///
+    /// assert!(Platform::Windows.is_absolute(r"C:\Users\tony".as_ref()));
+    /// assert!(!Platform::Windows.is_absolute("/home/tony".as_ref()));
+    /// assert!(Platform::Unix.is_absolute("/home/tony".as_ref()));
+    /// ```
+    #[must_use]
+    pub fn is_absolute(self, path: &OsStr) -> bool {
+        let path = path.to_string_lossy();
+        match self {
+            Self::Windows => {
+                let bytes = path.as_bytes();
+                let is_separator = |b: u8| b == b'\\' || b == b'/';
+                match bytes {
+                    [drive, b':', separator, ..] => {
+                        drive.is_ascii_alphabetic() && is_separator(*separator)
+                    }
+                    [first, second, ..] => is_separator(*first) && is_separator(*second),
+                    _ => false,
+                }
+            }
+            Self::Unix | Self::MacOs => path.starts_with('/'),
+        }
+    }
+
+    /// Appends the relative `components` to `base` using the separator of this platform.
+    ///
+    /// ```
+    /// use xstd::dirs::Platform;
+    ///
+    /// let path = Platform::Windows.join(r"C:\Users\tony\".as_ref(), &["AppData", "Roaming"]);
+    /// assert_eq!(path.to_str(), Some(r"C:\Users\tony\AppData\Roaming"));
+    /// ```
+    #[must_use]
+    pub fn join(self, base: &OsStr, components: &[&str]) -> PathBuf {
+        let mut path = base.to_os_string();
+        for component in components {
+            let last = path.to_string_lossy().chars().last();
+            let has_separator = match self {
+                Self::Windows => matches!(last, Some('\\' | '/')),
+                Self::Unix | Self::MacOs => last == Some('/'),
+            };
+            if !has_separator {
+                path.push(self.separator().to_string());
+            }
+            path.push(component);
+        }
+        PathBuf::from(path)
+    }
+
+    /// Gets the home directory of the user from `env`, i.e. `$HOME` or `%USERPROFILE%`.
+    pub fn home_dir(self, env: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+        self.absolute_var(&env, self.home_key()).map(PathBuf::from)
+    }
+
+    /// Gets the directory for application data from `env`, e.g. `~/.local/share`,
+    /// `~/Library/Application Support` or `%APPDATA%`.
+    pub fn data_dir(self, env: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+        match self {
+            Self::Unix => self.dir(&env, Some("XDG_DATA_HOME"), &[".local", "share"]),
+            Self::MacOs => self.dir(&env, None, &["Library", "Application Support"]),
+            Self::Windows => self.dir(&env, Some("APPDATA"), &["AppData", "Roaming"]),
+        }
+    }
+
+    /// Gets the directory for configuration files from `env`, e.g. `~/.config`,
+    /// `~/Library/Application Support` or `%APPDATA%`.
+    pub fn config_dir(self, env: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+        match self {
+            Self::Unix => self.dir(&env, Some("XDG_CONFIG_HOME"), &[".config"]),
+            Self::MacOs => self.dir(&env, None, &["Library", "Application Support"]),
+            Self::Windows => self.dir(&env, Some("APPDATA"), &["AppData", "Roaming"]),
+        }
+    }
+
+    /// Gets the directory for cached files that may be deleted at any time from `env`, e.g.
+    /// `~/.cache`, `~/Library/Caches` or `%LOCALAPPDATA%`.
+    pub fn cache_dir(self, env: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+        match self {
+            Self::Unix => self.dir(&env, Some("XDG_CACHE_HOME"), &[".cache"]),
+            Self::MacOs => self.dir(&env, None, &["Library", "Caches"]),
+            Self::Windows => self.dir(&env, Some("LOCALAPPDATA"), &["AppData", "Local"]),
+        }
+    }
+
+    /// Gets the directory in the variable `key`, or `fallback` below the home directory if it is
+    /// not set. Like the XDG specification demands, relative paths are ignored.
+    fn dir(
+        self,
+        env: &impl Fn(&str) -> Option<OsString>,
+        key: Option<&str>,
+        fallback: &[&str],
+    ) -> Option<PathBuf> {
+        if let Some(dir) = key.and_then(|key| self.absolute_var(env, key)) {
+            return Some(PathBuf::from(dir));
+        }
+        let home = self.absolute_var(env, self.home_key())?;
+        Some(self.join(&home, fallback))
+    }
+
+    fn home_key(self) -> &'static str {
+        match self {
+            Self::Windows => "USERPROFILE",
+            Self::Unix | Self::MacOs => "HOME",
+        }
+    }
+
+    fn absolute_var(self, env: &impl Fn(&str) -> Option<OsString>, key: &str) -> Option<OsString> {
+        env(key).filter(|value| self.is_absolute(value))
+    }
+}
+
+/// Gets the home directory of the current user, see [`Platform::home_dir`].
+#[must_use]
+pub fn home_dir() -> Option<PathBuf> {
+    Platform::current().home_dir(|key| std::env::var_os(key))
+}
+
+/// Gets the directory for application data of the current user, see [`Platform::data_dir`].
+#[must_use]
+pub fn data_dir() -> Option<PathBuf> {
+    Platform::current().data_dir(|key| std::env::var_os(key))
+}
+
+/// Gets the directory for configuration files of the current user, see
+/// [`Platform::config_dir`].
+#[must_use]
+pub fn config_dir() -> Option<PathBuf> {
+    Platform::current().config_dir(|key| std::env::var_os(key))
+}
+
+/// Gets the directory for cached files of the current user, see [`Platform::cache_dir`].
+#[must_use]
+pub fn cache_dir() -> Option<PathBuf> {
+    Platform::current().cache_dir(|key| std::env::var_os(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        move |key| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| OsString::from(value))
+        }
+    }
+
+    fn path(path: Option<PathBuf>) -> Option<String> {
+        path.map(|path| path.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn unix_dirs() {
+        let unix = Platform::Unix;
+        let vars = [("HOME", "/home/tony"), ("XDG_CACHE_HOME", "/tmp/cache")];
+        assert_eq!(
+            path(unix.home_dir(env(&vars))).as_deref(),
+            Some("/home/tony")
+        );
+        assert_eq!(
+            path(unix.data_dir(env(&vars))).as_deref(),
+            Some("/home/tony/.local/share")
+        );
+        assert_eq!(
+            path(unix.config_dir(env(&vars))).as_deref(),
+            Some("/home/tony/.config")
+        );
+        assert_eq!(
+            path(unix.cache_dir(env(&vars))).as_deref(),
+            Some("/tmp/cache")
+        );
+
+        // relative directories are ignored
+        let vars = [("HOME", "/home/tony/"), ("XDG_CONFIG_HOME", "config")];
+        assert_eq!(
+            path(unix.config_dir(env(&vars))).as_deref(),
+            Some("/home/tony/.config")
+        );
+        assert_eq!(unix.data_dir(env(&[("HOME", "tony")])), None);
+        assert_eq!(unix.data_dir(env(&[])), None);
+    }
+
+    #[test]
+    fn macos_dirs() {
+        let vars = [("HOME", "/Users/tony"), ("XDG_DATA_HOME", "/tmp/data")];
+        assert_eq!(
+            path(Platform::MacOs.data_dir(env(&vars))).as_deref(),
+            Some("/Users/tony/Library/Application Support")
+        );
+        assert_eq!(
+            path(Platform::MacOs.cache_dir(env(&vars))).as_deref(),
+            Some("/Users/tony/Library/Caches")
+        );
+    }
+
+    #[test]
+    fn windows_dirs() {
+        let windows = Platform::Windows;
+        let vars = [
+            ("USERPROFILE", r"C:\Users\tony"),
+            ("APPDATA", r"D:\Profiles\tony\Roaming"),
+            ("HOME", "/home/tony"),
+            ("XDG_DATA_HOME", "/tmp/data"),
+        ];
+        assert_eq!(
+            path(windows.home_dir(env(&vars))).as_deref(),
+            Some(r"C:\Users\tony")
+        );
+        assert_eq!(
+            path(windows.data_dir(env(&vars))).as_deref(),
+            Some(r"D:\Profiles\tony\Roaming")
+        );
+        assert_eq!(
+            path(windows.config_dir(env(&vars))).as_deref(),
+            Some(r"D:\Profiles\tony\Roaming")
+        );
+        assert_eq!(
+            path(windows.cache_dir(env(&vars))).as_deref(),
+            Some(r"C:\Users\tony\AppData\Local")
+        );
+
+        // unix paths and drive-relative paths are not absolute on windows
+        let vars = [("USERPROFILE", "/home/tony"), ("LOCALAPPDATA", r"C:Local")];
+        assert_eq!(windows.cache_dir(env(&vars)), None);
+        let vars = [("USERPROFILE", r"\\server\users\tony")];
+        assert_eq!(
+            path(windows.data_dir(env(&vars))).as_deref(),
+            Some(r"\\server\users\tony\AppData\Roaming")
+        );
+    }
+
+    #[test]
+    fn windows_path_semantics() {
+        let windows = Platform::Windows;
+        assert!(windows.is_absolute(r"c:\".as_ref()));
+        assert!(windows.is_absolute("C:/Users".as_ref()));
+        assert!(windows.is_absolute(r"\\?\C:\Users".as_ref()));
+        assert!(!windows.is_absolute("C:".as_ref()));
+        assert!(!windows.is_absolute(r"\Users".as_ref()));
+        assert!(!windows.is_absolute("1:/Users".as_ref()));
+        assert_eq!(
+            windows.join("C:/Users/".as_ref(), &["tony"]).to_str(),
+            Some("C:/Users/tony")
+        );
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn current_windows_dirs() {
+        assert_eq!(Platform::current(), Platform::Windows);
+        if let Some(data_dir) = data_dir() {
+            assert!(data_dir.is_absolute());
+            assert!(data_dir.join("storage").to_string_lossy().contains('\\'));
+        }
+    }
+}
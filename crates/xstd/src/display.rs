@@ -1,6 +1,6 @@
 //! Display utilities.
 
-use std::fmt::Display;
+use std::{fmt::Display, time::Duration};
 
 /// Extension methods for [`std::fmt::Display`].
 pub trait DisplayExt {
@@ -14,6 +14,53 @@ impl<T: Display> DisplayExt for T {
     }
 }
 
+/// Formats a number of bytes using binary units, e.g. `512 B`, `1.5 KiB` or `2.0 GiB`.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Formats a duration for humans, e.g. `350ms`, `2.5s`, `4m 10s` or `3d 2h`. Durations of a
+/// minute or longer show the two most significant units.
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    const UNITS: [(u64, &str); 4] = [(60 * 60 * 24, "d"), (60 * 60, "h"), (60, "m"), (1, "s")];
+
+    let secs = duration.as_secs();
+    if secs == 0 {
+        return format!("{}ms", duration.subsec_millis());
+    }
+    if secs < 60 {
+        return format!("{secs}.{}s", duration.subsec_millis() / 100);
+    }
+
+    let mut parts = Vec::with_capacity(2);
+    let mut remaining = secs;
+    for (size, unit) in UNITS {
+        if parts.len() == 2 {
+            break;
+        }
+        let amount = remaining / size;
+        remaining %= size;
+        if amount > 0 || !parts.is_empty() {
+            parts.push(format!("{amount}{unit}"));
+        }
+    }
+    parts.join(" ")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -33,4 +80,26 @@ mod test {
 
         assert_eq!(Foo.to_string_alt(), "success");
     }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_bytes(u64::MAX), "16.0 EiB");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::ZERO), "0ms");
+        assert_eq!(format_duration(Duration::from_millis(350)), "350ms");
+        assert_eq!(format_duration(Duration::from_millis(2_550)), "2.5s");
+        assert_eq!(format_duration(Duration::from_secs(250)), "4m 10s");
+        assert_eq!(format_duration(Duration::from_secs(60 * 60)), "1h 0m");
+        assert_eq!(
+            format_duration(Duration::from_secs(3 * 86_400 + 7_300)),
+            "3d 2h"
+        );
+    }
 }
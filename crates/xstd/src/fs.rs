@@ -3,6 +3,25 @@
 pub use walkdir;
 pub use walkdir::{DirEntry as WalkDirEntry, Result as WalkDirResult, WalkDir};
 
+use std::{
+    collections::BTreeMap,
+    fs::{File, TryLockError},
+    hash::Hasher,
+    io::{self, Read},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{hash::Fnv1aHasher, thread::CancellationToken};
+
+/// The size of the buffer files are read with when [`scan_dir_parallel`] hashes them
+const SCAN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How often a locked file is tried again while [`lock_exclusive`] or [`lock_shared`] wait for it
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
 /// A simple implementation of `% touch path` (ignores existing files)
 ///
 /// ## Errors
@@ -18,6 +37,93 @@ pub fn touch(path: &std::path::Path) -> std::io::Result<()> {
     }
 }
 
+/// Like [`touch`], but creates the missing parent directories first like [`ensure_dir`]
+/// (`% mkdir -p $(dirname path) && touch path`)
+///
+/// ## Errors
+/// - Returns an error if a parent directory or the file at `path` cannot be created
+pub fn touch_all(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        ensure_dir(parent)?;
+    }
+    touch(path)
+}
+
+/// Creates the directory at `path` and its missing parents (`% mkdir -p path`). A directory
+/// that already exists, or is created by someone else at the same time, is not an error.
+///
+/// ## Errors
+/// - Returns an error if a directory cannot be created, or a file is in the way
+pub fn ensure_dir(path: &Path) -> io::Result<()> {
+    match std::fs::create_dir_all(path) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists && path.is_dir() => Ok(()),
+        result => result,
+    }
+}
+
+/// Gets the number of bytes available to the current user on the volume holding `path`
+/// (`% df path`), which can be less than the free space of the volume when some of it is
+/// reserved
+///
+/// ## Errors
+/// - Returns an error if `path` does not exist or the volume cannot be queried
+/// - Returns an error of kind [`io::ErrorKind::Unsupported`] on platforms other than unix and
+///   windows
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: `path` is a valid nul terminated string and `stat` is only read after
+        // `statvfs` reported that it filled it in
+        let stat = unsafe {
+            if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            stat.assume_init()
+        };
+        // the field types differ between platforms, u64 on some of them
+        #[allow(clippy::useless_conversion)]
+        Ok(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+
+        let path = path
+            .as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect::<Vec<_>>();
+        let mut available = 0;
+        // SAFETY: `path` is a valid nul terminated wide string and the sizes that are not
+        // wanted are allowed to be null
+        let ok = unsafe {
+            windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW(
+                path.as_ptr(),
+                &mut available,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(available)
+    }
+    #[cfg(not(any(unix, windows)))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "cannot query the free space of '{}' on this platform",
+            path.display()
+        ),
+    ))
+}
+
 /// Walks the directory at `path` using the `walkdir` crate
 pub fn walk_dir(path: &std::path::Path) -> impl Iterator<Item = WalkDirResult<WalkDirEntry>> {
     WalkDir::new(path).into_iter()
@@ -248,3 +354,898 @@ pub fn create_write_append() -> std::fs::OpenOptions {
         .truncate(false)
         .clone()
 }
+
+/// What [`copy_dir_recursive`] does with a file that already exists at the destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum OverwritePolicy {
+    /// Fails the copy with an [`AlreadyExists`](io::ErrorKind::AlreadyExists) error
+    #[default]
+    Error,
+    /// Keeps the existing file
+    Skip,
+    /// Replaces the existing file
+    Overwrite,
+    /// Replaces the existing file if the source was modified more recently
+    IfNewer,
+}
+
+/// Options for [`copy_dir_recursive`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CopyOptions {
+    /// What to do with files that already exist at the destination
+    pub overwrite: OverwritePolicy,
+    /// Copy the permissions of files and directories
+    pub preserve_permissions: bool,
+    /// Copy the access and modification times of files (and of directories on unix)
+    pub preserve_timestamps: bool,
+    /// Copy what symbolic links point to instead of the links themselves
+    pub follow_links: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            overwrite: OverwritePolicy::default(),
+            preserve_permissions: true,
+            preserve_timestamps: true,
+            follow_links: false,
+        }
+    }
+}
+
+impl CopyOptions {
+    /// Sets the `overwrite` option
+    #[must_use]
+    pub fn with_overwrite(self, overwrite: OverwritePolicy) -> Self {
+        Self { overwrite, ..self }
+    }
+
+    /// Sets the `preserve_permissions` option
+    #[must_use]
+    pub fn with_preserve_permissions(self, preserve_permissions: bool) -> Self {
+        Self {
+            preserve_permissions,
+            ..self
+        }
+    }
+
+    /// Sets the `preserve_timestamps` option
+    #[must_use]
+    pub fn with_preserve_timestamps(self, preserve_timestamps: bool) -> Self {
+        Self {
+            preserve_timestamps,
+            ..self
+        }
+    }
+
+    /// Sets the `follow_links` option
+    #[must_use]
+    pub fn with_follow_links(self, follow_links: bool) -> Self {
+        Self {
+            follow_links,
+            ..self
+        }
+    }
+}
+
+/// How far [`copy_dir_recursive`] or [`remove_dir_contents`] got, passed to their progress
+/// callback after every entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsProgress<'a> {
+    /// The (source) path of the entry that was just handled
+    pub path: &'a Path,
+    /// The number of entries handled so far, including `path`
+    pub done: usize,
+    /// The number of entries to handle
+    pub total: usize,
+    /// The number of file bytes handled so far
+    pub bytes_done: u64,
+    /// The number of file bytes to handle
+    pub bytes_total: u64,
+}
+
+/// What [`copy_dir_recursive`] did
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct CopySummary {
+    /// The number of directories created or reused
+    pub dirs: usize,
+    /// The number of files copied
+    pub files: usize,
+    /// The number of symbolic links recreated
+    pub links: usize,
+    /// The number of files and links left alone because of the [`OverwritePolicy`]
+    pub skipped: usize,
+    /// The number of bytes copied
+    pub bytes: u64,
+}
+
+/// Copies the directory `src` with everything in it to `dst`, which is created if needed.
+///
+/// The tree is walked before anything is copied, so `progress` knows the totals and copying a
+/// directory into itself does not recurse. Directory permissions and timestamps are applied
+/// after their contents are copied, so read-only directories can be copied as well.
+///
+/// ## Errors
+/// - Returns an error if `src` is not a directory or cannot be walked
+/// - Returns an error if an entry cannot be copied, entries copied before stay in place
+/// - Returns an [`AlreadyExists`](io::ErrorKind::AlreadyExists) error for an existing file when
+///   the policy is [`OverwritePolicy::Error`]
+pub fn copy_dir_recursive(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+    mut progress: impl FnMut(&FsProgress<'_>),
+) -> io::Result<CopySummary> {
+    if !std::fs::metadata(src)?.is_dir() {
+        return Err(not_a_directory(src));
+    }
+    let entries = WalkDir::new(src)
+        .follow_links(options.follow_links)
+        .sort_by_file_name()
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    let bytes_total = file_bytes(&entries)?;
+
+    let mut summary = CopySummary::default();
+    let mut bytes_done = 0;
+    let mut dirs = Vec::new();
+    for (done, entry) in entries.iter().enumerate() {
+        let relative = entry.path().strip_prefix(src).map_err(io::Error::other)?;
+        let target = dst.join(relative);
+        let metadata = entry.metadata()?;
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+            summary.dirs += 1;
+            dirs.push((target, metadata));
+        } else if entry.file_type().is_symlink() {
+            if prepare_target(&target, &metadata, options.overwrite, true)? {
+                copy_link(entry.path(), &target)?;
+                summary.links += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        } else {
+            if prepare_target(&target, &metadata, options.overwrite, false)? {
+                copy_file(entry.path(), &target, &metadata, *options)?;
+                summary.files += 1;
+                summary.bytes += metadata.len();
+            } else {
+                summary.skipped += 1;
+            }
+            bytes_done += metadata.len();
+        }
+        progress(&FsProgress {
+            path: entry.path(),
+            done: done + 1,
+            total: entries.len(),
+            bytes_done,
+            bytes_total,
+        });
+    }
+
+    // deepest first, a read-only parent would keep its children from being changed
+    for (target, metadata) in dirs.iter().rev() {
+        #[cfg(unix)]
+        if options.preserve_timestamps {
+            File::open(target)?.set_times(file_times(metadata))?;
+        }
+        if options.preserve_permissions {
+            std::fs::set_permissions(target, metadata.permissions())?;
+        }
+    }
+    Ok(summary)
+}
+
+/// Removes everything inside the directory at `path`, but not the directory itself.
+///
+/// Symbolic links are removed, never followed, so nothing outside of `path` is touched.
+/// Returns the number of removed entries.
+///
+/// ## Errors
+/// - Returns an [`InvalidInput`](io::ErrorKind::InvalidInput) error if `path` is not a
+///   directory, including a symbolic link to a directory
+/// - Returns an error if an entry cannot be removed, entries removed before stay removed
+pub fn remove_dir_contents(
+    path: &Path,
+    mut progress: impl FnMut(&FsProgress<'_>),
+) -> io::Result<usize> {
+    if !std::fs::symlink_metadata(path)?.is_dir() {
+        return Err(not_a_directory(path));
+    }
+    let entries = WalkDir::new(path)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    let bytes_total = file_bytes(&entries)?;
+
+    let mut bytes_done = 0;
+    for (done, entry) in entries.iter().enumerate() {
+        if entry.file_type().is_dir() {
+            std::fs::remove_dir(entry.path())?;
+        } else {
+            if entry.file_type().is_file() {
+                bytes_done += entry.metadata()?.len();
+            }
+            std::fs::remove_file(entry.path())?;
+        }
+        progress(&FsProgress {
+            path: entry.path(),
+            done: done + 1,
+            total: entries.len(),
+            bytes_done,
+            bytes_total,
+        });
+    }
+    Ok(entries.len())
+}
+
+/// Options for [`scan_dir_parallel`]
+#[derive(Clone, Debug, Default)]
+pub struct ScanOptions {
+    /// The number of threads walking the tree, one per available core if `None`
+    pub threads: Option<usize>,
+    /// Hash the contents of every file, see [`DirSummary::hashes`]
+    pub hash_files: bool,
+    /// Stops the scan once cancelled
+    pub cancel: Option<CancellationToken>,
+}
+
+impl ScanOptions {
+    /// Sets the `threads` option
+    #[must_use]
+    pub fn with_threads(self, threads: usize) -> Self {
+        Self {
+            threads: Some(threads),
+            ..self
+        }
+    }
+
+    /// Sets the `hash_files` option
+    #[must_use]
+    pub fn with_hash_files(self, hash_files: bool) -> Self {
+        Self { hash_files, ..self }
+    }
+
+    /// Sets the `cancel` option
+    #[must_use]
+    pub fn with_cancel(self, cancel: CancellationToken) -> Self {
+        Self {
+            cancel: Some(cancel),
+            ..self
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+/// What [`scan_dir_parallel`] found
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct DirSummary {
+    /// The number of directories, including the scanned one
+    pub dirs: usize,
+    /// The number of regular files
+    pub files: usize,
+    /// The number of symbolic links
+    pub links: usize,
+    /// The total size of the regular files in bytes
+    pub bytes: u64,
+    /// The [FNV-1a](crate::hash::fnv1a) hash of the contents of every regular file, only
+    /// filled in with [`ScanOptions::hash_files`]
+    pub hashes: BTreeMap<PathBuf, u64>,
+}
+
+impl DirSummary {
+    fn merge(&mut self, other: Self) {
+        self.dirs += other.dirs;
+        self.files += other.files;
+        self.links += other.links;
+        self.bytes += other.bytes;
+        self.hashes.extend(other.hashes);
+    }
+}
+
+/// Walks the directory at `path` on several threads, counting its directories, files and links
+/// and summing up the sizes of the files, see [`ScanOptions`].
+///
+/// Every thread takes the next directory nobody has read yet, so deep and wide trees keep all
+/// of them busy. Symbolic links are counted but never followed, entries removed while the tree
+/// is walked are skipped.
+///
+/// ## Errors
+/// - Returns an error if `path` is not a directory
+/// - Returns an error if a directory cannot be read or a file cannot be hashed, the other
+///   threads stop as soon as they finished their current directory
+/// - Returns an [`Interrupted`](io::ErrorKind::Interrupted) error if the scan was cancelled
+///   through [`ScanOptions::cancel`]
+pub fn scan_dir_parallel(path: &Path, options: &ScanOptions) -> io::Result<DirSummary> {
+    if !std::fs::metadata(path)?.is_dir() {
+        return Err(not_a_directory(path));
+    }
+    let threads = options
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, NonZeroUsize::get))
+        .max(1);
+    let queue = ScanQueue {
+        state: Mutex::new(ScanState {
+            dirs: vec![path.to_path_buf()],
+            busy: 0,
+            error: None,
+        }),
+        changed: Condvar::new(),
+    };
+
+    let summaries = std::thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| scope.spawn(|| scan_worker(&queue, options)))
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect::<Vec<_>>()
+    });
+    let state = queue
+        .state
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(e) = state.error {
+        return Err(e);
+    }
+    let mut summary = DirSummary::default();
+    for worker in summaries {
+        summary.merge(worker);
+    }
+    Ok(summary)
+}
+
+/// The directories [`scan_dir_parallel`] has yet to read, shared by its threads
+struct ScanQueue {
+    state: Mutex<ScanState>,
+    changed: Condvar,
+}
+
+struct ScanState {
+    dirs: Vec<PathBuf>,
+    /// The number of directories being read, each of them can add more
+    busy: usize,
+    /// The first error, which stops every thread
+    error: Option<io::Error>,
+}
+
+impl ScanQueue {
+    /// Waits for the next directory to read, `None` once every directory was read or the scan
+    /// failed
+    fn next(&self, options: &ScanOptions) -> Option<PathBuf> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if state.error.is_none() && options.is_cancelled() {
+                state.error = Some(cancelled());
+                self.changed.notify_all();
+            }
+            if state.error.is_some() {
+                return None;
+            }
+            if let Some(dir) = state.dirs.pop() {
+                state.busy += 1;
+                return Some(dir);
+            }
+            if state.busy == 0 {
+                return None;
+            }
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Queues the directories found in a directory that was read, or records why reading it
+    /// failed
+    fn done(&self, result: io::Result<Vec<PathBuf>>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.busy -= 1;
+        match result {
+            Ok(dirs) => state.dirs.extend(dirs),
+            Err(e) => {
+                state.error.get_or_insert(e);
+            }
+        }
+        self.changed.notify_all();
+    }
+}
+
+fn scan_worker(queue: &ScanQueue, options: &ScanOptions) -> DirSummary {
+    let mut summary = DirSummary::default();
+    while let Some(dir) = queue.next(options) {
+        queue.done(scan_entries(&dir, options, &mut summary));
+    }
+    summary
+}
+
+/// Adds the entries of `dir` to `summary`, returning the directories in it
+fn scan_entries(
+    dir: &Path,
+    options: &ScanOptions,
+    summary: &mut DirSummary,
+) -> io::Result<Vec<PathBuf>> {
+    let Some(entries) = skip_removed(std::fs::read_dir(dir))? else {
+        return Ok(Vec::new());
+    };
+    summary.dirs += 1;
+    let mut dirs = Vec::new();
+    for entry in entries {
+        if options.is_cancelled() {
+            return Err(cancelled());
+        }
+        let entry = entry?;
+        // the metadata of the entry itself, links are not followed
+        let Some(metadata) = skip_removed(entry.metadata())? else {
+            continue;
+        };
+        if metadata.is_dir() {
+            dirs.push(entry.path());
+        } else if metadata.is_symlink() {
+            summary.links += 1;
+        } else if metadata.is_file() {
+            if options.hash_files {
+                let path = entry.path();
+                let Some(hash) = skip_removed(hash_file(&path, options))? else {
+                    continue;
+                };
+                summary.hashes.insert(path, hash);
+            }
+            summary.files += 1;
+            summary.bytes += metadata.len();
+        }
+    }
+    Ok(dirs)
+}
+
+fn hash_file(path: &Path, options: &ScanOptions) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = Fnv1aHasher::default();
+    let mut buffer = vec![0; SCAN_BUFFER_SIZE];
+    loop {
+        if options.is_cancelled() {
+            return Err(cancelled());
+        }
+        match file.read(&mut buffer)? {
+            0 => return Ok(hasher.finish()),
+            read => hasher.write(&buffer[..read]),
+        }
+    }
+}
+
+/// How a [`FileLock`] shares its file with other locks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockMode {
+    /// Any number of shared locks may be held at once, but no exclusive one, e.g. for readers
+    Shared,
+    /// No other lock may be held at the same time, e.g. for a writer
+    Exclusive,
+}
+
+/// An advisory lock on a file, which is released when dropped. Only other locks respect it, the
+/// file itself can still be read and written by anyone.
+///
+/// The lock belongs to the open file rather than to the process (`flock` on unix, `LockFileEx`
+/// on Windows), so two locks on the same path conflict even within a single process. The OS
+/// releases the lock of a process that exits without dropping it.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    mode: LockMode,
+}
+
+impl FileLock {
+    /// Gets the locked file, e.g. to read what the holder of the lock wrote into it
+    #[must_use]
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Gets the locked file mutably, e.g. to record the process holding the lock in it
+    pub fn file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Gets whether the lock is shared or exclusive
+    #[must_use]
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Releases the lock, unlike dropping it reporting a failure to do so
+    ///
+    /// ## Errors
+    /// - Returns an error if the OS fails to release the lock
+    pub fn unlock(self) -> io::Result<()> {
+        self.file.unlock()
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // closing the file releases the lock anyway
+        let _ = self.file.unlock();
+    }
+}
+
+/// Locks the file at `path` exclusively, creating it if it does not exist. Waits up to `timeout`
+/// for other locks to be released, or as long as it takes if `timeout` is `None`.
+///
+/// ## Errors
+/// - Returns an error if the file cannot be opened or locked
+/// - Returns a [`TimedOut`](io::ErrorKind::TimedOut) error if the file is still locked after
+///   `timeout`
+pub fn lock_exclusive(path: &Path, timeout: Option<Duration>) -> io::Result<FileLock> {
+    lock_with_timeout(path, LockMode::Exclusive, timeout)
+}
+
+/// Same as [`lock_exclusive`], taking a shared lock which only waits for exclusive locks
+///
+/// ## Errors
+/// - Returns an error if the file cannot be opened or locked
+/// - Returns a [`TimedOut`](io::ErrorKind::TimedOut) error if the file is still locked
+///   exclusively after `timeout`
+pub fn lock_shared(path: &Path, timeout: Option<Duration>) -> io::Result<FileLock> {
+    lock_with_timeout(path, LockMode::Shared, timeout)
+}
+
+/// Tries to lock the file at `path` in `mode` without waiting, creating it if it does not exist.
+/// Returns `None` if a conflicting lock is held.
+///
+/// ## Errors
+/// - Returns an error if the file cannot be opened or locked
+pub fn try_lock(path: &Path, mode: LockMode) -> io::Result<Option<FileLock>> {
+    let file = open_lock_file(path)?;
+    let locked = match mode {
+        LockMode::Shared => file.try_lock_shared(),
+        LockMode::Exclusive => file.try_lock(),
+    };
+    match locked {
+        Ok(()) => Ok(Some(FileLock { file, mode })),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+fn lock_with_timeout(
+    path: &Path,
+    mode: LockMode,
+    timeout: Option<Duration>,
+) -> io::Result<FileLock> {
+    let Some(timeout) = timeout else {
+        let file = open_lock_file(path)?;
+        match mode {
+            LockMode::Shared => file.lock_shared()?,
+            LockMode::Exclusive => file.lock()?,
+        }
+        return Ok(FileLock { file, mode });
+    };
+    let started = Instant::now();
+    loop {
+        if let Some(lock) = try_lock(path, mode)? {
+            return Ok(lock);
+        }
+        if started.elapsed() >= timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("'{}' is still locked after {timeout:?}", path.display()),
+            ));
+        }
+        std::thread::sleep(LOCK_RETRY_INTERVAL.min(timeout));
+    }
+}
+
+fn open_lock_file(path: &Path) -> io::Result<File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+/// Turns the [`NotFound`](io::ErrorKind::NotFound) error of an entry that was removed while a
+/// tree is walked into `None`
+fn skip_removed<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "the scan was cancelled")
+}
+
+fn not_a_directory(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("'{}' is not a directory", path.display()),
+    )
+}
+
+/// Sums the sizes of the regular files in `entries`
+fn file_bytes(entries: &[WalkDirEntry]) -> io::Result<u64> {
+    let mut bytes = 0;
+    for entry in entries.iter().filter(|entry| entry.file_type().is_file()) {
+        bytes += entry.metadata()?.len();
+    }
+    Ok(bytes)
+}
+
+/// Decides whether the entry described by `metadata` is written to `target`. An existing link at
+/// `target` (or any existing file when `is_link` is set) is removed, so the copy never writes
+/// through a link.
+fn prepare_target(
+    target: &Path,
+    metadata: &std::fs::Metadata,
+    policy: OverwritePolicy,
+    is_link: bool,
+) -> io::Result<bool> {
+    let existing = match std::fs::symlink_metadata(target) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    let replace = match policy {
+        OverwritePolicy::Error => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' already exists", target.display()),
+            ))
+        }
+        OverwritePolicy::Skip => false,
+        OverwritePolicy::Overwrite => true,
+        OverwritePolicy::IfNewer => metadata.modified()? > existing.modified()?,
+    };
+    if replace && (is_link || existing.file_type().is_symlink()) {
+        std::fs::remove_file(target)?;
+    }
+    Ok(replace)
+}
+
+fn copy_file(
+    src: &Path,
+    dst: &Path,
+    metadata: &std::fs::Metadata,
+    options: CopyOptions,
+) -> io::Result<()> {
+    let mut from = File::open(src)?;
+    let mut to = File::create(dst)?;
+    io::copy(&mut from, &mut to)?;
+    if options.preserve_timestamps {
+        to.set_times(file_times(metadata))?;
+    }
+    if options.preserve_permissions {
+        to.set_permissions(metadata.permissions())?;
+    }
+    Ok(())
+}
+
+fn file_times(metadata: &std::fs::Metadata) -> std::fs::FileTimes {
+    let mut times = std::fs::FileTimes::new();
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    times
+}
+
+/// Creates a link at `dst` pointing where the link at `src` points
+fn copy_link(src: &Path, dst: &Path) -> io::Result<()> {
+    let points_to = std::fs::read_link(src)?;
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(points_to, dst);
+    #[cfg(windows)]
+    return if std::fs::metadata(src).is_ok_and(|metadata| metadata.is_dir()) {
+        std::os::windows::fs::symlink_dir(points_to, dst)
+    } else {
+        std::os::windows::fs::symlink_file(points_to, dst)
+    };
+    #[cfg(not(any(unix, windows)))]
+    return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot copy the link '{}' on this platform", src.display()),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn queries_free_space() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(free_space(dir.path()).unwrap() > 0);
+        let missing = free_space(&dir.path().join("missing")).unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn creates_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a/b/c.txt");
+        touch_all(&file).unwrap();
+        touch_all(&file).unwrap();
+        assert!(file.is_file());
+
+        let nested = dir.path().join("x/y/z");
+        let threads = (0..4)
+            .map(|_| {
+                let nested = nested.clone();
+                std::thread::spawn(move || ensure_dir(&nested))
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+        assert!(nested.is_dir());
+        assert!(ensure_dir(&file).is_err());
+    }
+
+    #[test]
+    fn copies_recursively() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("out/dst"));
+        write(&src.join("a.txt"), "a");
+        write(&src.join("sub/b.txt"), "bb");
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(src.join("a.txt"))
+            .unwrap()
+            .set_times(std::fs::FileTimes::new().set_modified(old))
+            .unwrap();
+        let mut read_only = std::fs::metadata(src.join("sub/b.txt"))
+            .unwrap()
+            .permissions();
+        read_only.set_readonly(true);
+        std::fs::set_permissions(src.join("sub/b.txt"), read_only).unwrap();
+
+        let mut updates = Vec::new();
+        let summary = copy_dir_recursive(&src, &dst, &CopyOptions::default(), |progress| {
+            updates.push((progress.done, progress.total, progress.bytes_done));
+        })
+        .unwrap();
+        assert_eq!(
+            summary,
+            CopySummary {
+                dirs: 2,
+                files: 2,
+                bytes: 3,
+                ..CopySummary::default()
+            }
+        );
+        assert_eq!(updates, [(1, 4, 0), (2, 4, 1), (3, 4, 1), (4, 4, 3)]);
+        assert_eq!(
+            std::fs::read_to_string(dst.join("sub/b.txt")).unwrap(),
+            "bb"
+        );
+        let copied = std::fs::metadata(dst.join("a.txt")).unwrap();
+        assert_eq!(copied.modified().unwrap(), old);
+        assert!(std::fs::metadata(dst.join("sub/b.txt"))
+            .unwrap()
+            .permissions()
+            .readonly());
+
+        let error = copy_dir_recursive(&src, &dst, &CopyOptions::default(), |_| {}).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        // only the file that is newer than its copy is replaced
+        write(&dst.join("a.txt"), "changed");
+        let options = CopyOptions::default().with_overwrite(OverwritePolicy::IfNewer);
+        let summary = copy_dir_recursive(&src, &dst, &options, |_| {}).unwrap();
+        assert_eq!((summary.files, summary.skipped), (0, 2));
+        assert_eq!(
+            std::fs::read_to_string(dst.join("a.txt")).unwrap(),
+            "changed"
+        );
+    }
+
+    #[test]
+    fn scans_in_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            write(&dir.path().join(format!("{}/{i}/file.txt", i % 3)), "abc");
+        }
+        write(&dir.path().join("top.txt"), "hello");
+
+        let summary =
+            scan_dir_parallel(dir.path(), &ScanOptions::default().with_threads(4)).unwrap();
+        assert_eq!(
+            (summary.dirs, summary.files, summary.links, summary.bytes),
+            (24, 21, 0, 65)
+        );
+        assert!(summary.hashes.is_empty());
+        let single =
+            scan_dir_parallel(dir.path(), &ScanOptions::default().with_threads(1)).unwrap();
+        assert_eq!(single, summary);
+
+        let summary =
+            scan_dir_parallel(dir.path(), &ScanOptions::default().with_hash_files(true)).unwrap();
+        assert_eq!(summary.hashes.len(), 21);
+        assert_eq!(
+            summary.hashes[&dir.path().join("top.txt")],
+            crate::hash::fnv1a(b"hello")
+        );
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let error =
+            scan_dir_parallel(dir.path(), &ScanOptions::default().with_cancel(cancel)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+        let error =
+            scan_dir_parallel(&dir.path().join("top.txt"), &ScanOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn locks_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.lock");
+
+        let mut exclusive = lock_exclusive(&path, None).unwrap();
+        assert!(path.exists());
+        assert_eq!(exclusive.mode(), LockMode::Exclusive);
+        std::io::Write::write_all(exclusive.file_mut(), b"42").unwrap();
+        assert!(try_lock(&path, LockMode::Shared).unwrap().is_none());
+        let error = lock_exclusive(&path, Some(Duration::from_millis(30))).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        exclusive.unlock().unwrap();
+
+        let first = lock_shared(&path, Some(Duration::ZERO)).unwrap();
+        let second = try_lock(&path, LockMode::Shared).unwrap().unwrap();
+        assert!(try_lock(&path, LockMode::Exclusive).unwrap().is_none());
+        drop((first, second));
+        assert!(try_lock(&path, LockMode::Exclusive).unwrap().is_some());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "42");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removes_contents_without_following_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, outside) = (dir.path().join("target"), dir.path().join("outside"));
+        write(&outside.join("keep.txt"), "keep");
+        write(&target.join("sub/file.txt"), "file");
+        std::os::unix::fs::symlink(&outside, target.join("link")).unwrap();
+
+        // links are copied as links
+        let copy = dir.path().join("copy");
+        let summary = copy_dir_recursive(&target, &copy, &CopyOptions::default(), |_| {}).unwrap();
+        assert_eq!(summary.links, 1);
+        assert_eq!(std::fs::read_link(copy.join("link")).unwrap(), outside);
+
+        let mut last = None;
+        let removed = remove_dir_contents(&target, |progress| {
+            last = Some((progress.done, progress.total, progress.bytes_done));
+        })
+        .unwrap();
+        assert_eq!((removed, last), (3, Some((3, 3, 4))));
+        assert_eq!(std::fs::read_dir(&target).unwrap().count(), 0);
+        assert!(outside.join("keep.txt").exists());
+
+        let error = remove_dir_contents(&copy.join("link"), |_| {}).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
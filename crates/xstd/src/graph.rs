@@ -1,6 +1,15 @@
 //! Graph utilities.
+//!
+//! Generic depth-first traversals over any graph representation, a
+//! [`DiGraph`] with topological sorting, cycle detection, strongly connected
+//! components and reachability queries, and a [`TaskDag`] running tasks on a
+//! [`ThreadPool`] in the order of their dependencies.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+
+use crate::thread::ThreadPool;
 
 /// A non-recursive implementation of a fallible depth-first traversal
 /// starting from `root`.
@@ -235,3 +244,673 @@ where
     }
     None
 }
+
+/// A directed graph with nodes of type `N`, e.g. paths whose backups depend on
+/// each other.
+///
+/// Nodes are identified by their value and kept in insertion order, which is
+/// also the order every query visits them in, so results are deterministic.
+#[derive(Debug, Clone)]
+pub struct DiGraph<N> {
+    nodes: Vec<N>,
+    indices: BTreeMap<N, usize>,
+    successors: Vec<BTreeSet<usize>>,
+}
+
+/// The error returned by [`DiGraph::toposort`] for a graph with a cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleError<N> {
+    cycle: Vec<N>,
+}
+
+impl<N> CycleError<N> {
+    /// Returns the nodes of one cycle in the graph, starting and ending with
+    /// the same node.
+    #[must_use]
+    pub fn cycle(&self) -> &[N] {
+        &self.cycle
+    }
+}
+
+impl<N: std::fmt::Display> std::fmt::Display for CycleError<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cycle detected: ")?;
+        for (i, node) in self.cycle.iter().enumerate() {
+            if i > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{node}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<N: std::fmt::Debug + std::fmt::Display> std::error::Error for CycleError<N> {}
+
+impl<N: Ord + Clone> DiGraph<N> {
+    /// Creates an empty graph.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            indices: BTreeMap::new(),
+            successors: Vec::new(),
+        }
+    }
+
+    /// Adds `node` to the graph.
+    ///
+    /// Returns whether the node was new.
+    pub fn add_node(&mut self, node: N) -> bool {
+        if self.indices.contains_key(&node) {
+            return false;
+        }
+        self.index_of_or_insert(node);
+        true
+    }
+
+    /// Adds an edge from `from` to `to`, adding either node if it is not part
+    /// of the graph yet. For [`DiGraph::toposort`], `from` comes before `to`.
+    ///
+    /// Returns whether the edge was new.
+    pub fn add_edge(&mut self, from: N, to: N) -> bool {
+        let from = self.index_of_or_insert(from);
+        let to = self.index_of_or_insert(to);
+        self.successors[from].insert(to)
+    }
+
+    /// Returns whether `node` is part of the graph.
+    #[must_use]
+    pub fn contains_node(&self, node: &N) -> bool {
+        self.indices.contains_key(node)
+    }
+
+    /// Returns whether there is an edge from `from` to `to`.
+    #[must_use]
+    pub fn contains_edge(&self, from: &N, to: &N) -> bool {
+        match (self.indices.get(from), self.indices.get(to)) {
+            (Some(from), Some(to)) => self.successors[*from].contains(to),
+            _ => false,
+        }
+    }
+
+    /// Returns the number of nodes.
+    #[must_use]
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the number of edges.
+    #[must_use]
+    pub fn edge_count(&self) -> usize {
+        self.successors.iter().map(BTreeSet::len).sum()
+    }
+
+    /// Returns the nodes in insertion order.
+    pub fn nodes(&self) -> impl Iterator<Item = &N> + '_ {
+        self.nodes.iter()
+    }
+
+    /// Returns the nodes `node` has an edge to, or nothing if `node` is not
+    /// part of the graph.
+    pub fn successors(&self, node: &N) -> impl Iterator<Item = &N> + '_ {
+        self.indices
+            .get(node)
+            .into_iter()
+            .flat_map(|index| self.successors[*index].iter())
+            .map(|index| &self.nodes[*index])
+    }
+
+    /// Orders the nodes so that every node comes before all nodes it has an
+    /// edge to. Nodes without an order between them keep their insertion
+    /// order.
+    ///
+    /// ## Errors
+    /// - Returns a [`CycleError`] describing one cycle if the graph has any.
+    pub fn toposort(&self) -> Result<Vec<&N>, CycleError<N>> {
+        let mut in_degree = vec![0usize; self.nodes.len()];
+        for successors in &self.successors {
+            for successor in successors {
+                in_degree[*successor] += 1;
+            }
+        }
+
+        // a min-heap on the index keeps unrelated nodes in insertion order
+        let mut ready = (0..self.nodes.len())
+            .filter(|index| in_degree[*index] == 0)
+            .map(std::cmp::Reverse)
+            .collect::<BinaryHeap<_>>();
+        let mut sorted = Vec::with_capacity(self.nodes.len());
+        while let Some(std::cmp::Reverse(index)) = ready.pop() {
+            sorted.push(&self.nodes[index]);
+            for successor in &self.successors[index] {
+                in_degree[*successor] -= 1;
+                if in_degree[*successor] == 0 {
+                    ready.push(std::cmp::Reverse(*successor));
+                }
+            }
+        }
+
+        if sorted.len() == self.nodes.len() {
+            Ok(sorted)
+        } else {
+            Err(CycleError {
+                cycle: self.find_cycle(),
+            })
+        }
+    }
+
+    /// Returns the strongly connected components of the graph, i.e. the
+    /// largest groups of nodes that can all reach each other. Every node is
+    /// part of exactly one component, and the components are returned in
+    /// reverse topological order.
+    #[must_use]
+    pub fn strongly_connected_components(&self) -> Vec<Vec<&N>> {
+        self.components()
+            .into_iter()
+            .map(|component| component.into_iter().map(|i| &self.nodes[i]).collect())
+            .collect()
+    }
+
+    /// Returns every node reachable from `node` by following one or more
+    /// edges. `node` itself is only included if it is part of a cycle.
+    #[must_use]
+    pub fn reachable_from(&self, node: &N) -> BTreeSet<&N> {
+        let Some(start) = self.indices.get(node) else {
+            return BTreeSet::new();
+        };
+        let mut reached = BTreeSet::new();
+        let mut stack = self.successors[*start].iter().copied().collect::<Vec<_>>();
+        while let Some(index) = stack.pop() {
+            if reached.insert(index) {
+                stack.extend(self.successors[index].iter().copied());
+            }
+        }
+        reached
+            .into_iter()
+            .map(|index| &self.nodes[index])
+            .collect()
+    }
+
+    /// Returns whether `to` can be reached from `from` by following one or
+    /// more edges.
+    #[must_use]
+    pub fn is_reachable(&self, from: &N, to: &N) -> bool {
+        self.reachable_from(from).contains(to)
+    }
+
+    fn index_of_or_insert(&mut self, node: N) -> usize {
+        if let Some(index) = self.indices.get(&node) {
+            return *index;
+        }
+        let index = self.nodes.len();
+        self.indices.insert(node.clone(), index);
+        self.nodes.push(node);
+        self.successors.push(BTreeSet::new());
+        index
+    }
+
+    /// Tarjan's algorithm, without recursion so deep graphs cannot overflow
+    /// the stack.
+    fn components(&self) -> Vec<Vec<usize>> {
+        const UNVISITED: usize = usize::MAX;
+
+        let count = self.nodes.len();
+        let mut order = vec![UNVISITED; count];
+        let mut low_link = vec![0; count];
+        let mut on_stack = vec![false; count];
+        let mut stack = Vec::new();
+        let mut next_order = 0;
+        let mut components = Vec::new();
+
+        for root in 0..count {
+            if order[root] != UNVISITED {
+                continue;
+            }
+            // each frame is a node and the successors it still has to visit
+            let mut frames = vec![(root, self.successors[root].iter())];
+            order[root] = next_order;
+            low_link[root] = next_order;
+            next_order += 1;
+            stack.push(root);
+            on_stack[root] = true;
+
+            while let Some((node, successors)) = frames.last_mut() {
+                let node = *node;
+                if let Some(&successor) = successors.next() {
+                    if order[successor] == UNVISITED {
+                        order[successor] = next_order;
+                        low_link[successor] = next_order;
+                        next_order += 1;
+                        stack.push(successor);
+                        on_stack[successor] = true;
+                        frames.push((successor, self.successors[successor].iter()));
+                    } else if on_stack[successor] {
+                        low_link[node] = low_link[node].min(order[successor]);
+                    }
+                    continue;
+                }
+
+                frames.pop();
+                if let Some((parent, _)) = frames.last() {
+                    low_link[*parent] = low_link[*parent].min(low_link[node]);
+                }
+                if low_link[node] == order[node] {
+                    let mut component = Vec::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    component.reverse();
+                    components.push(component);
+                }
+            }
+        }
+        components
+    }
+
+    /// Finds one cycle, starting and ending with the same node. Must only be
+    /// called on a graph that has a cycle.
+    fn find_cycle(&self) -> Vec<N> {
+        let Some(component) = self.components().into_iter().find(|component| {
+            component.len() > 1 || self.successors[component[0]].contains(&component[0])
+        }) else {
+            return Vec::new();
+        };
+
+        // a breadth-first search within the component from its first node back
+        // to itself yields a shortest cycle through that node
+        let start = component[0];
+        let members = component.into_iter().collect::<BTreeSet<_>>();
+        let mut parents = BTreeMap::new();
+        let mut queue = std::collections::VecDeque::from([start]);
+        while let Some(index) = queue.pop_front() {
+            for successor in &self.successors[index] {
+                if !members.contains(successor) || parents.contains_key(successor) {
+                    continue;
+                }
+                parents.insert(*successor, index);
+                if *successor == start {
+                    queue.clear();
+                    break;
+                }
+                queue.push_back(*successor);
+            }
+        }
+
+        let mut cycle = vec![self.nodes[start].clone()];
+        let mut index = parents[&start];
+        while index != start {
+            cycle.push(self.nodes[index].clone());
+            index = parents[&index];
+        }
+        cycle.push(self.nodes[start].clone());
+        cycle.reverse();
+        cycle
+    }
+}
+
+impl<N: Ord + Clone> Default for DiGraph<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Task<T, E> = Box<dyn FnOnce() -> Result<T, E> + Send + 'static>;
+
+/// What became of a task run by [`TaskDag::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskOutcome<T, E> {
+    /// The task ran and succeeded.
+    Done(T),
+    /// The task ran and returned an error.
+    Failed(E),
+    /// The task panicked.
+    Panicked,
+    /// The task did not run because one of its dependencies did not succeed.
+    Skipped,
+}
+
+impl<T, E> TaskOutcome<T, E> {
+    /// Returns true if the task ran and succeeded.
+    #[must_use]
+    pub fn is_done(&self) -> bool {
+        matches!(self, Self::Done(_))
+    }
+}
+
+/// The error returned by [`TaskDag::run`] for tasks that cannot be ordered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskDagError<N> {
+    /// The dependencies between the tasks form a cycle.
+    Cycle(CycleError<N>),
+    /// `task` depends on `dependency`, which was never added.
+    UnknownDependency {
+        /// The task with the unknown dependency
+        task: N,
+        /// The dependency that is not a task
+        dependency: N,
+    },
+}
+
+impl<N: std::fmt::Display> std::fmt::Display for TaskDagError<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle(err) => err.fmt(f),
+            Self::UnknownDependency { task, dependency } => {
+                write!(f, "task {task} depends on unknown task {dependency}")
+            }
+        }
+    }
+}
+
+impl<N: std::fmt::Debug + std::fmt::Display> std::error::Error for TaskDagError<N> {}
+
+/// A set of tasks identified by values of type `N`, with dependencies between
+/// them, e.g. the steps of a startup sequence.
+///
+/// [`TaskDag::run`] runs the tasks on a [`ThreadPool`], every task once all of
+/// its dependencies succeeded, so tasks without an order between them run in
+/// parallel. A task that fails (or panics) does not stop the others, only the
+/// tasks depending on it are skipped.
+pub struct TaskDag<N, T, E> {
+    graph: DiGraph<N>,
+    tasks: BTreeMap<N, Task<T, E>>,
+}
+
+impl<N, T, E> TaskDag<N, T, E>
+where
+    N: Ord + Clone + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    /// Creates an empty task graph.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            graph: DiGraph::new(),
+            tasks: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the task `name`, which only runs once every task in
+    /// `dependencies` succeeded. Dependencies may be added after the tasks
+    /// depending on them.
+    ///
+    /// Returns whether the task was new, an existing task is left as it is.
+    pub fn add_task(
+        &mut self,
+        name: N,
+        dependencies: impl IntoIterator<Item = N>,
+        task: impl FnOnce() -> Result<T, E> + Send + 'static,
+    ) -> bool {
+        if self.tasks.contains_key(&name) {
+            return false;
+        }
+        self.graph.add_node(name.clone());
+        for dependency in dependencies {
+            self.graph.add_edge(dependency, name.clone());
+        }
+        self.tasks.insert(name, Box::new(task));
+        true
+    }
+
+    /// Returns the number of tasks.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns true if no task was added.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Runs every task on `pool`, blocking until all of them are done, and
+    /// returns the outcome of each. Tasks that are ready at the same time
+    /// are started in the order they were added.
+    ///
+    /// ## Errors
+    /// - Returns [`TaskDagError::UnknownDependency`] if a task depends on a
+    ///   task that was never added, nothing is run
+    /// - Returns [`TaskDagError::Cycle`] if the dependencies form a cycle,
+    ///   nothing is run
+    ///
+    /// ## Panics
+    /// Panics if a task is lost by the pool without reporting its outcome.
+    pub fn run(
+        mut self,
+        pool: &ThreadPool,
+    ) -> Result<BTreeMap<N, TaskOutcome<T, E>>, TaskDagError<N>> {
+        if let Some(dependency) = self
+            .graph
+            .nodes()
+            .find(|node| !self.tasks.contains_key(*node))
+        {
+            let task = self
+                .graph
+                .successors(dependency)
+                .next()
+                .expect("only dependencies are added without a task")
+                .clone();
+            return Err(TaskDagError::UnknownDependency {
+                task,
+                dependency: dependency.clone(),
+            });
+        }
+        let order = self.graph.toposort().map_err(TaskDagError::Cycle)?;
+
+        let mut waiting_on = order
+            .iter()
+            .map(|node| ((*node).clone(), 0usize))
+            .collect::<BTreeMap<_, _>>();
+        for node in &order {
+            for successor in self.graph.successors(node) {
+                *waiting_on
+                    .get_mut(successor)
+                    .expect("every node has a count") += 1;
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut outcomes = BTreeMap::new();
+        let mut running = 0usize;
+        let start = |name: N, tasks: &mut BTreeMap<N, Task<T, E>>| {
+            let task = tasks.remove(&name).expect("every task is started once");
+            let tx = tx.clone();
+            pool.execute(move || {
+                let outcome = match panic::catch_unwind(AssertUnwindSafe(task)) {
+                    Ok(Ok(value)) => TaskOutcome::Done(value),
+                    Ok(Err(err)) => TaskOutcome::Failed(err),
+                    Err(_) => TaskOutcome::Panicked,
+                };
+                tx.send((name, outcome)).ok();
+            });
+        };
+        for node in order.iter().filter(|node| waiting_on[**node] == 0) {
+            start((*node).clone(), &mut self.tasks);
+            running += 1;
+        }
+
+        while running > 0 {
+            let (name, outcome) = rx.recv().expect("a task was lost by the pool");
+            running -= 1;
+            if outcome.is_done() {
+                // dependents are visited in insertion order
+                let mut ready = Vec::new();
+                for successor in self.graph.successors(&name) {
+                    let count = waiting_on
+                        .get_mut(successor)
+                        .expect("every node has a count");
+                    *count -= 1;
+                    if *count == 0 && !outcomes.contains_key(successor) {
+                        ready.push(successor.clone());
+                    }
+                }
+                for node in ready {
+                    start(node, &mut self.tasks);
+                    running += 1;
+                }
+            } else {
+                for skipped in self.graph.reachable_from(&name) {
+                    outcomes
+                        .entry(skipped.clone())
+                        .or_insert(TaskOutcome::Skipped);
+                }
+            }
+            outcomes.insert(name, outcome);
+        }
+        Ok(outcomes)
+    }
+}
+
+impl<N, T, E> Default for TaskDag<N, T, E>
+where
+    N: Ord + Clone + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N: std::fmt::Debug, T, E> std::fmt::Debug for TaskDag<N, T, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskDag")
+            .field("graph", &self.graph)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn graph(edges: &[(&'static str, &'static str)]) -> DiGraph<&'static str> {
+        let mut graph = DiGraph::new();
+        for (from, to) in edges {
+            graph.add_edge(*from, *to);
+        }
+        graph
+    }
+
+    #[test]
+    fn sorts_topologically() {
+        let mut graph = graph(&[("/a", "/a/b"), ("/a/b", "/a/b/c"), ("/x", "/a/b")]);
+        graph.add_node("/y");
+        assert_eq!(graph.node_count(), 5);
+        assert_eq!(graph.edge_count(), 3);
+        assert_eq!(
+            graph.toposort().unwrap(),
+            [&"/a", &"/x", &"/a/b", &"/a/b/c", &"/y"]
+        );
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let cyclic = graph(&[("a", "b"), ("b", "c"), ("c", "a"), ("c", "d")]);
+        let err = cyclic.toposort().unwrap_err();
+        assert_eq!(err.cycle(), ["a", "b", "c", "a"]);
+        assert_eq!(err.to_string(), "cycle detected: a -> b -> c -> a");
+
+        let self_loop = graph(&[("a", "a")]);
+        assert_eq!(self_loop.toposort().unwrap_err().cycle(), ["a", "a"]);
+    }
+
+    #[test]
+    fn finds_components_and_reachability() {
+        let graph = graph(&[("a", "b"), ("b", "a"), ("b", "c"), ("c", "d"), ("d", "c")]);
+        assert_eq!(
+            graph.strongly_connected_components(),
+            [vec![&"c", &"d"], vec![&"a", &"b"]]
+        );
+        assert_eq!(
+            graph.reachable_from(&"a").into_iter().collect::<Vec<_>>(),
+            [&"a", &"b", &"c", &"d"]
+        );
+        assert!(graph.is_reachable(&"a", &"d"));
+        assert!(!graph.is_reachable(&"c", &"a"));
+        assert!(!graph.is_reachable(&"missing", &"a"));
+    }
+
+    #[test]
+    fn runs_tasks_in_dependency_order() {
+        use std::sync::{Arc, Barrier, Mutex};
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        // both stores can only pass the barrier if they run at the same time
+        let barrier = Arc::new(Barrier::new(2));
+        let mut dag = TaskDag::<&str, usize, String>::new();
+        let step = |name: &'static str| {
+            let log = Arc::clone(&log);
+            move || {
+                log.lock().unwrap().push(name);
+                Ok(name.len())
+            }
+        };
+        dag.add_task("watch", ["reconcile"], step("watch"));
+        dag.add_task("reconcile", ["store", "index"], step("reconcile"));
+        for name in ["store", "index"] {
+            let barrier = Arc::clone(&barrier);
+            let step = step(name);
+            dag.add_task(name, ["config"], move || {
+                barrier.wait();
+                step()
+            });
+        }
+        dag.add_task("config", [], step("config"));
+        assert!(!dag.add_task("config", [], || Err("added twice".into())));
+        assert_eq!(dag.len(), 5);
+
+        let outcomes = dag.run(&ThreadPool::new(2)).unwrap();
+        assert!(outcomes.values().all(TaskOutcome::is_done));
+        assert_eq!(outcomes["reconcile"], TaskOutcome::Done(9));
+        let log = log.lock().unwrap();
+        assert_eq!(log[0], "config");
+        assert_eq!(log[3..], ["reconcile", "watch"]);
+    }
+
+    #[test]
+    fn skips_dependents_of_failed_tasks() {
+        let mut dag = TaskDag::<&str, (), &str>::new();
+        dag.add_task("a", [], || Err("failed"));
+        dag.add_task("b", ["a"], || Ok(()));
+        dag.add_task("c", ["b"], || Ok(()));
+        dag.add_task("d", [], || panic!("task panicked"));
+        dag.add_task("e", ["d"], || Ok(()));
+        dag.add_task("f", [], || Ok(()));
+
+        let outcomes = dag.run(&ThreadPool::new(2)).unwrap();
+        assert_eq!(outcomes["a"], TaskOutcome::Failed("failed"));
+        assert_eq!(outcomes["b"], TaskOutcome::Skipped);
+        assert_eq!(outcomes["c"], TaskOutcome::Skipped);
+        assert_eq!(outcomes["d"], TaskOutcome::Panicked);
+        assert_eq!(outcomes["e"], TaskOutcome::Skipped);
+        assert_eq!(outcomes["f"], TaskOutcome::Done(()));
+    }
+
+    #[test]
+    fn rejects_unorderable_tasks() {
+        let pool = ThreadPool::new(1);
+        let mut dag = TaskDag::<&str, (), ()>::new();
+        dag.add_task("a", ["b"], || Ok(()));
+        dag.add_task("b", ["a"], || Ok(()));
+        let err = dag.run(&pool).unwrap_err();
+        assert_eq!(err.to_string(), "cycle detected: a -> b -> a");
+
+        let mut dag = TaskDag::<&str, (), ()>::new();
+        dag.add_task("a", ["missing"], || Ok(()));
+        assert_eq!(
+            dag.run(&pool).unwrap_err(),
+            TaskDagError::UnknownDependency {
+                task: "a",
+                dependency: "missing"
+            }
+        );
+    }
+}
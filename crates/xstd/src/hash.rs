@@ -9,3 +9,40 @@ pub fn hash<T: Hash>(t: &T) -> u64 {
     t.hash(&mut hasher);
     hasher.finish()
 }
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Computes the 64-bit [FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/) hash of `bytes`.
+///
+/// Unlike [`hash`], the result is stable across program executions and compiler versions,
+/// so it is suitable for use in file names and other persisted data.
+#[must_use]
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1aHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// A streaming version of [`fnv1a`], for data that does not fit in memory at once. Writing the
+/// bytes in several chunks gives the same hash as [`fnv1a`] of all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fnv1aHasher(u64);
+
+impl Default for Fnv1aHasher {
+    fn default() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for Fnv1aHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        });
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -1,9 +1,15 @@
 //! ID generation utilities.
 
-use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
 use std::marker::PhantomData;
-use std::ops::AddAssign;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cast::CastFrom;
 
 /// Manages the allocation of unique IDs.
 #[derive(Debug, Default, Clone)]
@@ -19,66 +25,460 @@ impl<Id: From<u64> + Default> Gen<Id> {
         self.id += 1;
         id.into()
     }
+
+    /// Like [`Gen::allocate_id`], but returns an error instead of wrapping
+    /// around once every `u64` has been handed out.
+    ///
+    /// ## Errors
+    /// - [`IdOverflowError`] if the generator is exhausted
+    pub fn checked_allocate(&mut self) -> Result<Id, IdOverflowError> {
+        let id = self.id;
+        self.id = id.checked_add(1).ok_or(IdOverflowError)?;
+        Ok(id.into())
+    }
 }
 
 /// A generator of u64-bit IDs.
 pub type IdGen = Gen<u64>;
 
+/// Like [`Gen`], but allocates through a shared reference using an
+/// [`AtomicU64`], so it can be shared between threads without a mutex.
+#[derive(Debug, Default)]
+pub struct AtomicGen<Id: From<u64>> {
+    id: AtomicU64,
+    phantom: PhantomData<fn() -> Id>,
+}
+
+impl<Id: From<u64>> AtomicGen<Id> {
+    /// Creates a new generator whose first identifier is `first`.
+    #[must_use]
+    pub const fn new(first: u64) -> Self {
+        Self {
+            id: AtomicU64::new(first),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Allocates a new identifier of type `Id` and advances the generator.
+    ///
+    /// ## Panics
+    /// Panics if the generator is exhausted, see [`AtomicGen::checked_allocate`]
+    pub fn allocate_id(&self) -> Id {
+        self.checked_allocate().expect("id generator exhausted")
+    }
+
+    /// Allocates a new identifier of type `Id` and advances the generator,
+    /// returning an error instead of wrapping around once every `u64` has
+    /// been handed out.
+    ///
+    /// ## Errors
+    /// - [`IdOverflowError`] if the generator is exhausted
+    pub fn checked_allocate(&self) -> Result<Id, IdOverflowError> {
+        self.id
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| id.checked_add(1))
+            .map(Id::from)
+            .map_err(|_| IdOverflowError)
+    }
+}
+
+/// The error returned when a [`Gen`] or [`AtomicGen`] has no identifiers left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdOverflowError;
+
+impl fmt::Display for IdOverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the id generator is exhausted")
+    }
+}
+
+impl std::error::Error for IdOverflowError {}
+
 /// Manages allocation of numeric IDs.
 ///
-/// Note that the current implementation wastes memory. It would be far more
-/// efficient to use a compressed bitmap, like <https://roaringbitmap.org> or
-/// the hibitset crate, but neither presently supports a fast "find first zero"
-/// operation.
+/// The allocated IDs are kept in a hierarchical bitset: one bit per ID, plus
+/// a level above with one bit per 64-bit word that is full, and so on until a
+/// single word is left. Finding the lowest free ID walks down one word per
+/// level, and the bitset only grows up to the highest ID handed out so far.
 #[derive(Debug)]
 pub struct IdAllocator<T>(Mutex<IdAllocatorInner<T>>);
 
 #[derive(Debug)]
 struct IdAllocatorInner<T> {
-    next: T,
+    min: T,
     max: T,
-    free: VecDeque<T>,
+    used: BitTree,
 }
 
 impl<T> IdAllocator<T>
 where
-    T: From<u8> + AddAssign + PartialOrd + Copy,
+    T: Into<u64> + TryFrom<u64> + PartialOrd + Copy,
 {
     /// Creates a new `IdAllocator` that will assign IDs between `min` and
     /// `max`, both inclusive.
     pub fn new(min: T, max: T) -> IdAllocator<T> {
         IdAllocator(Mutex::new(IdAllocatorInner {
-            next: min,
+            min,
             max,
-            free: VecDeque::new(),
+            used: BitTree::default(),
         }))
     }
 
-    /// Allocates a new ID.
+    /// Allocates the lowest free ID.
     ///
     /// Returns `None` if the allocator is exhausted.
+    ///
+    /// ## Panics
+    /// Panics if the mutex guarding the allocator is poisoned
     pub fn alloc(&self) -> Option<T> {
         let mut inner = self.0.lock().expect("lock poisoned");
-        if let Some(id) = inner.free.pop_front() {
-            Some(id)
+        let offset = inner.used.first_zero();
+        let id = inner.min.into().checked_add(u64::cast_from(offset))?;
+        if inner.max < inner.min || id > inner.max.into() {
+            return None;
+        }
+        let id = T::try_from(id).ok()?;
+        inner.used.set(offset);
+        Some(id)
+    }
+
+    /// Releases an allocated ID back to the pool, it is handed out again by
+    /// the next [`IdAllocator::alloc`] unless a lower ID is free.
+    ///
+    /// ## Errors
+    /// - [`FreeIdError::OutOfRange`] if `id` is outside of the range of this
+    ///   allocator
+    /// - [`FreeIdError::NotAllocated`] if `id` is not currently allocated,
+    ///   e.g. because it was already freed
+    ///
+    /// ## Panics
+    /// Panics if the mutex guarding the allocator is poisoned
+    pub fn free(&self, id: T) -> Result<(), FreeIdError<T>> {
+        let mut inner = self.0.lock().expect("lock poisoned");
+        if id < inner.min || id > inner.max {
+            return Err(FreeIdError::OutOfRange(id));
+        }
+        let offset = usize::try_from(id.into() - inner.min.into());
+        if offset.is_ok_and(|offset| inner.used.clear(offset)) {
+            Ok(())
         } else {
-            let id = inner.next;
-            if id > inner.max {
-                None
-            } else {
-                inner.next += 1.into();
-                Some(id)
+            Err(FreeIdError::NotAllocated(id))
+        }
+    }
+}
+
+/// The error returned by [`IdAllocator::free`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeIdError<T> {
+    /// The ID is outside of the range of the allocator
+    OutOfRange(T),
+    /// The ID is not allocated, it was freed already or never handed out
+    NotAllocated(T),
+}
+
+impl<T: fmt::Display> fmt::Display for FreeIdError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange(id) => write!(f, "id {id} is outside of the allocator's range"),
+            Self::NotAllocated(id) => write!(f, "id {id} is not allocated"),
+        }
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> std::error::Error for FreeIdError<T> {}
+
+/// The number of bits in a word of a [`BitTree`]
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A growable hierarchical bitset. `levels[0]` holds the bits themselves, a
+/// bit of every level above is set when the word below it is full. The top
+/// level is a single word.
+#[derive(Debug, Default)]
+struct BitTree {
+    levels: Vec<Vec<u64>>,
+}
+
+impl BitTree {
+    /// Gets the lowest bit that is not set, which may be past the words
+    /// allocated so far
+    fn first_zero(&self) -> usize {
+        let Some(top) = self.levels.last() else {
+            return 0;
+        };
+        if top[0] == u64::MAX {
+            return self.levels[0].len() * WORD_BITS;
+        }
+        let mut index = 0;
+        for words in self.levels.iter().rev() {
+            let word = words.get(index).copied().unwrap_or(0);
+            index = index * WORD_BITS + usize::cast_from((!word).trailing_zeros());
+        }
+        index
+    }
+
+    /// Sets `bit`, growing the tree to hold it
+    fn set(&mut self, bit: usize) {
+        self.grow(bit);
+        let mut index = bit;
+        for words in &mut self.levels {
+            let word = &mut words[index / WORD_BITS];
+            *word |= 1 << (index % WORD_BITS);
+            if *word != u64::MAX {
+                break;
+            }
+            index /= WORD_BITS;
+        }
+    }
+
+    /// Clears `bit`, returning whether it was set
+    fn clear(&mut self, bit: usize) -> bool {
+        let mut index = bit;
+        for (level, words) in self.levels.iter_mut().enumerate() {
+            let Some(word) = words.get_mut(index / WORD_BITS) else {
+                return false;
+            };
+            let mask = 1 << (index % WORD_BITS);
+            if level == 0 && *word & mask == 0 {
+                return false;
+            }
+            let was_full = *word == u64::MAX;
+            *word &= !mask;
+            // the bit above is only set for full words
+            if !was_full {
+                break;
             }
+            index /= WORD_BITS;
         }
+        true
     }
 
-    /// Releases a new ID back to the pool.
+    /// Adds the words needed to hold `bit` on every level, and levels on top
+    /// until the top one is a single word again
+    fn grow(&mut self, bit: usize) {
+        let needed = bit / WORD_BITS + 1;
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        if self.levels[0].len() < needed {
+            self.levels[0].resize(needed, 0);
+        }
+        let mut level = 0;
+        while self.levels[level].len() > 1 {
+            let needed = self.levels[level].len().div_ceil(WORD_BITS);
+            if level + 1 == self.levels.len() {
+                // a new top level, whose bits are set for the words that are full already
+                let mut above = vec![0; needed];
+                for (i, _) in self.levels[level]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, word)| **word == u64::MAX)
+                {
+                    above[i / WORD_BITS] |= 1 << (i % WORD_BITS);
+                }
+                self.levels.push(above);
+            } else if self.levels[level + 1].len() < needed {
+                self.levels[level + 1].resize(needed, 0);
+            }
+            level += 1;
+        }
+    }
+}
+
+/// The Crockford base32 alphabet used to format a [`UniqueId`]
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// The number of characters in a formatted [`UniqueId`]
+const UNIQUE_ID_LEN: usize = 26;
+/// The number of random bits in a [`UniqueId`]
+const RANDOM_BITS: u32 = 80;
+const RANDOM_MASK: u128 = (1 << RANDOM_BITS) - 1;
+
+/// The last id handed out by [`UniqueId::new`], used to keep ids from the same millisecond
+/// ordered
+static LAST_UNIQUE_ID: Mutex<u128> = Mutex::new(0);
+
+/// A 128-bit, time-ordered unique identifier in the style of a ULID: 48 bits of milliseconds
+/// since the unix epoch followed by 80 random bits.
+///
+/// Ids are formatted as 26 characters of Crockford base32 (e.g. `01H2XCEJQF2W8Y6VXN5D3T0GKR`),
+/// so their string form sorts the same way as the ids themselves. Ids created by
+/// [`UniqueId::new`] within the same process are strictly increasing, even within the same
+/// millisecond.
+///
+/// The random bits come from the randomly seeded [`RandomState`] of the standard library, which
+/// is fine to tell ids apart but **not** suitable where ids must be unguessable.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct UniqueId(u128);
+
+impl UniqueId {
+    /// The largest timestamp (in milliseconds) that fits in an id
+    pub const MAX_TIMESTAMP: u64 = (1 << 48) - 1;
+
+    /// Generates a new id for the current time
     ///
-    /// It is undefined behavior to free an ID twice, or to free an ID that was
-    /// not allocated by this allocator.
-    pub fn free(&self, id: T) {
-        let mut inner = self.0.lock().expect("lock poisoned");
-        inner.free.push_back(id);
+    /// ## Panics
+    /// Panics if the mutex guarding the last generated id is poisoned
+    #[must_use]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        let candidate = Self::from_parts(millis, random_bits());
+
+        let mut last = LAST_UNIQUE_ID.lock().expect("mutex poisoned");
+        // within the same millisecond (or if the clock went backwards) the previous id is
+        // incremented instead, which carries into the timestamp once the random bits run out
+        let id = if candidate.0 > *last {
+            candidate.0
+        } else {
+            last.wrapping_add(1)
+        };
+        *last = id;
+        Self(id)
+    }
+
+    /// Creates an id from a timestamp (in milliseconds since the unix epoch) and random bits.
+    /// Only the lowest 48 bits of `millis` and 80 bits of `random` are used.
+    #[must_use]
+    pub const fn from_parts(millis: u64, random: u128) -> Self {
+        Self(((millis as u128) << RANDOM_BITS) | (random & RANDOM_MASK))
+    }
+
+    /// Creates an id from its numeric value
+    #[must_use]
+    pub const fn from_u128(value: u128) -> Self {
+        Self(value)
+    }
+
+    /// Gets the numeric value of this id
+    #[must_use]
+    pub const fn as_u128(self) -> u128 {
+        self.0
+    }
+
+    /// Gets the timestamp of this id, in milliseconds since the unix epoch
+    #[must_use]
+    pub const fn timestamp_millis(self) -> u64 {
+        #[allow(clippy::cast_possible_truncation)]
+        let millis = (self.0 >> RANDOM_BITS) as u64;
+        millis
+    }
+
+    /// Gets the random bits of this id
+    #[must_use]
+    pub const fn random(self) -> u128 {
+        self.0 & RANDOM_MASK
+    }
+}
+
+/// Produces 80 bits that differ between calls and processes
+fn random_bits() -> u128 {
+    let state = RandomState::new();
+    let mut high = state.build_hasher();
+    high.write_u8(0);
+    let mut low = state.build_hasher();
+    low.write_u8(1);
+    (u128::from(high.finish()) << 64 | u128::from(low.finish())) & RANDOM_MASK
+}
+
+impl fmt::Display for UniqueId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = [0u8; UNIQUE_ID_LEN];
+        let mut value = self.0;
+        for c in out.iter_mut().rev() {
+            *c = CROCKFORD[(value & 0x1f) as usize];
+            value >>= 5;
+        }
+        // the alphabet is ascii, so this never fails
+        f.write_str(std::str::from_utf8(&out).map_err(|_| fmt::Error)?)
+    }
+}
+
+impl fmt::Debug for UniqueId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UniqueId({self})")
+    }
+}
+
+/// The error returned when a string is not a valid [`UniqueId`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseUniqueIdError {
+    /// The string does not have 26 characters
+    InvalidLength(usize),
+    /// The string contains a character outside of the Crockford base32 alphabet
+    InvalidCharacter(char),
+    /// The value does not fit in 128 bits, i.e. the first character is larger than `7`
+    Overflow,
+}
+
+impl fmt::Display for ParseUniqueIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLength(len) => {
+                write!(f, "expected {UNIQUE_ID_LEN} characters, found {len}")
+            }
+            Self::InvalidCharacter(c) => write!(f, "invalid character '{c}'"),
+            Self::Overflow => f.write_str("value does not fit in 128 bits"),
+        }
+    }
+}
+
+impl std::error::Error for ParseUniqueIdError {}
+
+impl FromStr for UniqueId {
+    type Err = ParseUniqueIdError;
+
+    /// Parses an id case-insensitively, also accepting `I`/`L` for `1` and `O` for `0` as
+    /// Crockford base32 does
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let len = s.chars().count();
+        if len != UNIQUE_ID_LEN {
+            return Err(ParseUniqueIdError::InvalidLength(len));
+        }
+        let mut value = 0u128;
+        for (i, c) in s.chars().enumerate() {
+            let digit = match c.to_ascii_uppercase() {
+                'I' | 'L' => 1,
+                'O' => 0,
+                upper => CROCKFORD
+                    .iter()
+                    .position(|&a| char::from(a) == upper)
+                    .ok_or(ParseUniqueIdError::InvalidCharacter(c))?,
+            };
+            // 26 characters hold 130 bits, the first one may only use the lowest 3
+            if i == 0 && digit > 7 {
+                return Err(ParseUniqueIdError::Overflow);
+            }
+            value = value << 5 | digit as u128;
+        }
+        Ok(Self(value))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for UniqueId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for UniqueId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = UniqueId;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a 26 character unique id")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
     }
 }
 
@@ -88,18 +488,107 @@ mod tests {
 
     #[test]
     fn test_id_alloc() {
-        let ida = IdAllocator::new(3, 5);
+        let ida = IdAllocator::new(3u32, 5);
         assert_eq!(ida.alloc().unwrap(), 3);
         assert_eq!(ida.alloc().unwrap(), 4);
         assert_eq!(ida.alloc().unwrap(), 5);
-        ida.free(4);
+        ida.free(4).unwrap();
         assert_eq!(ida.alloc().unwrap(), 4);
-        ida.free(5);
-        ida.free(3);
-        assert_eq!(ida.alloc().unwrap(), 5);
+        ida.free(5).unwrap();
+        ida.free(3).unwrap();
         assert_eq!(ida.alloc().unwrap(), 3);
+        assert_eq!(ida.alloc().unwrap(), 5);
         if let Some(id) = ida.alloc() {
             panic!("id allocator returned {id}, not expected id exhaustion error")
         }
+        ida.free(4).unwrap();
+        assert_eq!(ida.free(4), Err(FreeIdError::NotAllocated(4)));
+        assert_eq!(ida.free(6), Err(FreeIdError::OutOfRange(6)));
+        assert_eq!(ida.free(2), Err(FreeIdError::OutOfRange(2)));
+    }
+
+    #[test]
+    fn finds_first_free_id() {
+        let ida = IdAllocator::new(0u64, u64::MAX);
+        for expected in 0..10_000 {
+            assert_eq!(ida.alloc(), Some(expected));
+        }
+        for id in [9_999, 4_096, 4_095, 63, 0] {
+            ida.free(id).unwrap();
+        }
+        for expected in [0, 63, 4_095, 4_096, 9_999, 10_000] {
+            assert_eq!(ida.alloc(), Some(expected));
+        }
+        assert_eq!(ida.free(20_000), Err(FreeIdError::NotAllocated(20_000)));
+
+        let small = IdAllocator::new(250u8, 255);
+        assert_eq!((0..8).filter_map(|_| small.alloc()).count(), 6);
+        small.free(255).unwrap();
+        assert_eq!(small.alloc(), Some(255));
+        assert_eq!(small.alloc(), None);
+    }
+
+    #[test]
+    fn atomic_ids() {
+        let gen = std::sync::Arc::new(AtomicGen::<u64>::default());
+        let threads = (0..4)
+            .map(|_| {
+                let gen = gen.clone();
+                std::thread::spawn(move || (0..1000).map(|_| gen.allocate_id()).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        let mut ids = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..4000).collect::<Vec<_>>());
+
+        let gen = AtomicGen::<u64>::new(u64::MAX - 1);
+        assert_eq!(gen.checked_allocate(), Ok(u64::MAX - 1));
+        assert_eq!(gen.checked_allocate(), Err(IdOverflowError));
+        let mut gen = IdGen::default();
+        gen.id = u64::MAX - 1;
+        assert_eq!(gen.checked_allocate(), Ok(u64::MAX - 1));
+        assert_eq!(gen.checked_allocate(), Err(IdOverflowError));
+    }
+
+    #[test]
+    fn unique_ids() {
+        let ids = (0..1000).map(|_| UniqueId::new()).collect::<Vec<_>>();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        let strings = ids.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert!(strings.windows(2).all(|w| w[0] < w[1]));
+        for (id, s) in ids.iter().zip(&strings) {
+            assert_eq!(s.len(), 26);
+            assert_eq!(s.parse::<UniqueId>().unwrap(), *id);
+            assert_eq!(s.to_lowercase().parse::<UniqueId>().unwrap(), *id);
+        }
+
+        let id = UniqueId::from_parts(1_469_918_176_385, 0xABCD);
+        assert_eq!(id.timestamp_millis(), 1_469_918_176_385);
+        assert_eq!(id.random(), 0xABCD);
+        assert_eq!(id.to_string(), "01ARYZ6S410000000000001AYD");
+        assert_eq!(
+            "01ARYZ6S41OOOOOOOOOOOO1AYD".parse::<UniqueId>().unwrap(),
+            id
+        );
+        assert_eq!(
+            UniqueId::from_u128(u128::MAX).to_string(),
+            "7ZZZZZZZZZZZZZZZZZZZZZZZZZ"
+        );
+
+        assert_eq!(
+            "01ARYZ6S41".parse::<UniqueId>(),
+            Err(ParseUniqueIdError::InvalidLength(10))
+        );
+        assert_eq!(
+            "01ARYZ6S41000000000000U000".parse::<UniqueId>(),
+            Err(ParseUniqueIdError::InvalidCharacter('U'))
+        );
+        assert_eq!(
+            "8ZZZZZZZZZZZZZZZZZZZZZZZZZ".parse::<UniqueId>(),
+            Err(ParseUniqueIdError::Overflow)
+        );
     }
 }
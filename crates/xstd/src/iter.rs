@@ -26,10 +26,135 @@ where
             Some(v1) => self.all(|v2| v1 == v2),
         }
     }
+
+    /// Groups the elements of the iterator into `Vec`s of `n` elements, the last one holding
+    /// the remainder.
+    ///
+    /// ```
+    /// use xstd::iter::IteratorExt;
+    ///
+    /// let chunks = (1..=5).chunks_of(2).collect::<Vec<_>>();
+    /// assert_eq!(chunks, [vec![1, 2], vec![3, 4], vec![5]]);
+    /// ```
+    ///
+    /// ## Panics
+    /// - Panics if `n` is zero
+    fn chunks_of(self, n: usize) -> ChunksOf<Self> {
+        assert!(n > 0, "chunk size must be greater than zero");
+        ChunksOf { iter: self, n }
+    }
+
+    /// Skips elements that are equal to the element before them, like [`Vec::dedup`].
+    ///
+    /// ```
+    /// use xstd::iter::IteratorExt;
+    ///
+    /// let deduped = [1, 1, 2, 3, 3, 3, 1].into_iter().dedup_consecutive().collect::<Vec<_>>();
+    /// assert_eq!(deduped, [1, 2, 3, 1]);
+    /// ```
+    fn dedup_consecutive(self) -> DedupConsecutive<Self>
+    where
+        Self::Item: PartialEq + Clone,
+    {
+        DedupConsecutive {
+            iter: self,
+            last: None,
+        }
+    }
+
+    /// Collects the `Ok` values of an iterator of `Result`s into a `Vec`, stopping at the
+    /// first `Err`.
+    ///
+    /// Equivalent to `self.collect::<Result<Vec<_>, _>>()`.
+    ///
+    /// ## Errors
+    /// - Returns the first `Err` of the iterator
+    fn try_collect_vec<T, E>(self) -> Result<Vec<T>, E>
+    where
+        Self: Iterator<Item = Result<T, E>>,
+    {
+        self.collect()
+    }
+
+    /// Splits an iterator of `Result`s into its `Ok` and its `Err` values, both in the order
+    /// they came in.
+    ///
+    /// ```
+    /// use xstd::iter::IteratorExt;
+    ///
+    /// let (numbers, errors) = ["1", "x", "3"].into_iter().map(str::parse::<u8>).partition_result();
+    /// assert_eq!(numbers, [1, 3]);
+    /// assert_eq!(errors.len(), 1);
+    /// ```
+    fn partition_result<T, E>(self) -> (Vec<T>, Vec<E>)
+    where
+        Self: Iterator<Item = Result<T, E>>,
+    {
+        let mut oks = Vec::new();
+        let mut errs = Vec::new();
+        for item in self {
+            match item {
+                Ok(ok) => oks.push(ok),
+                Err(err) => errs.push(err),
+            }
+        }
+        (oks, errs)
+    }
 }
 
 impl<I> IteratorExt for I where I: Iterator {}
 
+/// An iterator over `Vec`s of the elements of another iterator, created by
+/// [`IteratorExt::chunks_of`].
+#[derive(Debug, Clone)]
+pub struct ChunksOf<I> {
+    iter: I,
+    n: usize,
+}
+
+impl<I: Iterator> Iterator for ChunksOf<I> {
+    type Item = Vec<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.iter.by_ref().take(self.n).collect::<Vec<_>>();
+        (!chunk.is_empty()).then_some(chunk)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        (
+            lower.div_ceil(self.n),
+            upper.map(|upper| upper.div_ceil(self.n)),
+        )
+    }
+}
+
+/// An iterator that skips repeated elements of another iterator, created by
+/// [`IteratorExt::dedup_consecutive`].
+#[derive(Debug, Clone)]
+pub struct DedupConsecutive<I: Iterator> {
+    iter: I,
+    last: Option<I::Item>,
+}
+
+impl<I> Iterator for DedupConsecutive<I>
+where
+    I: Iterator,
+    I::Item: PartialEq + Clone,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for item in self.iter.by_ref() {
+            if self.last.as_ref() != Some(&item) {
+                self.last = Some(item.clone());
+                return Some(item);
+            }
+        }
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::iter::IteratorExt;
@@ -42,4 +167,31 @@ mod tests {
         assert!([1, 1].iter().all_equal());
         assert!(![1, 2].iter().all_equal());
     }
+
+    #[test]
+    fn test_chunks_and_dedup() {
+        let chunks = (0..6).chunks_of(3);
+        assert_eq!(chunks.size_hint(), (2, Some(2)));
+        assert_eq!(chunks.collect::<Vec<_>>(), [vec![0, 1, 2], vec![3, 4, 5]]);
+        assert_eq!((0..0).chunks_of(3).count(), 0);
+
+        let empty: [u8; 0] = [];
+        assert_eq!(empty.into_iter().dedup_consecutive().count(), 0);
+        let deduped = "aabbba".chars().dedup_consecutive().collect::<String>();
+        assert_eq!(deduped, "aba");
+    }
+
+    #[test]
+    fn test_results() {
+        let all_ok = [Ok(1), Ok(2)].into_iter().try_collect_vec::<i32, ()>();
+        assert_eq!(all_ok, Ok(vec![1, 2]));
+        let first_err = [Ok(1), Err("a"), Err("b")].into_iter().try_collect_vec();
+        assert_eq!(first_err, Err("a"));
+
+        let (oks, errs) = [Ok(1), Err("a"), Ok(2), Err("b")]
+            .into_iter()
+            .partition_result();
+        assert_eq!(oks, [1, 2]);
+        assert_eq!(errs, ["a", "b"]);
+    }
 }
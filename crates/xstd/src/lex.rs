@@ -1,4 +1,8 @@
 //! Lexing utilities.
+//!
+//! [`LexBuf`] is a low-level cursor over a string, [`Lexer`] builds on it to
+//! split a string into [`Token`]s with [`Span`]s according to configurable
+//! [`LexerRules`].
 
 /// A cursor over a string with a variety of lexing convenience methods.
 #[derive(Debug)]
@@ -149,3 +153,484 @@ impl<'a> Iterator for LexBuf<'a> {
         c
     }
 }
+
+/// A region of the input a [`Token`] was read from.
+///
+/// `start` and `end` are byte offsets, `line` and `column` are 1-based and
+/// refer to the first character of the token, counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The byte offset of the first character.
+    pub start: usize,
+    /// The byte offset just past the last character.
+    pub end: usize,
+    /// The line of the first character.
+    pub line: usize,
+    /// The column of the first character.
+    pub column: usize,
+}
+
+/// The kind of a [`Token`] produced by a [`Lexer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// A word made up of identifier characters, see
+    /// [`LexerRules::with_identifiers`].
+    Ident,
+    /// A decimal number, optionally with a fractional part.
+    Number,
+    /// A quoted string. The token text includes the quotes, see
+    /// [`Token::unquoted`].
+    String,
+    /// A line or block comment, including its delimiters.
+    Comment,
+    /// A run of whitespace.
+    Whitespace,
+    /// Any other single character.
+    Punct,
+}
+
+/// A token produced by a [`Lexer`], borrowing its text from the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    /// What kind of token this is.
+    pub kind: TokenKind,
+    /// The text of the token, exactly as it appears in the input.
+    pub text: &'a str,
+    /// Where the token appears in the input.
+    pub span: Span,
+}
+
+impl<'a> Token<'a> {
+    /// Returns the contents of a [`TokenKind::String`] token without its
+    /// quotes, with escaped characters replaced by the characters themselves.
+    ///
+    /// Returns the text unchanged for every other kind of token.
+    #[must_use]
+    pub fn unquoted(&self, escape: Option<char>) -> std::borrow::Cow<'a, str> {
+        if self.kind != TokenKind::String || self.text.len() < 2 {
+            return self.text.into();
+        }
+        let quote_len = self.text.chars().next().map_or(0, char::len_utf8);
+        let inner = &self.text[quote_len..self.text.len() - quote_len];
+        match escape {
+            Some(escape) if inner.contains(escape) => {
+                let mut unquoted = String::with_capacity(inner.len());
+                let mut chars = inner.chars();
+                while let Some(ch) = chars.next() {
+                    if ch == escape {
+                        unquoted.extend(chars.next());
+                    } else {
+                        unquoted.push(ch);
+                    }
+                }
+                unquoted.into()
+            }
+            _ => inner.into(),
+        }
+    }
+}
+
+/// The error produced by a [`Lexer`] for malformed input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LexError {
+    /// A quoted string is missing its closing quote.
+    UnterminatedString(Span),
+    /// A block comment is missing its closing delimiter.
+    UnterminatedComment(Span),
+}
+
+impl LexError {
+    /// Returns the span from the start of the offending token to the end of
+    /// the input.
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnterminatedString(span) | Self::UnterminatedComment(span) => *span,
+        }
+    }
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (what, span) = match self {
+            Self::UnterminatedString(span) => ("string", span),
+            Self::UnterminatedComment(span) => ("comment", span),
+        };
+        write!(
+            f,
+            "unterminated {what} starting at line {}, column {}",
+            span.line, span.column
+        )
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// The rules a [`Lexer`] uses to split its input into [`Token`]s.
+///
+/// The defaults recognize C-like identifiers, decimal numbers and strings in
+/// double quotes with `\` as the escape character. Comments are disabled and
+/// whitespace is skipped.
+#[derive(Debug, Clone)]
+pub struct LexerRules {
+    ident_start: fn(char) -> bool,
+    ident_continue: fn(char) -> bool,
+    numbers: bool,
+    quotes: Vec<char>,
+    escape: Option<char>,
+    line_comment: Option<String>,
+    block_comment: Option<(String, String)>,
+    skip_whitespace: bool,
+    skip_comments: bool,
+}
+
+impl LexerRules {
+    /// Creates the default rules.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            ident_start: |c| c.is_alphabetic() || c == '_',
+            ident_continue: |c| c.is_alphanumeric() || c == '_',
+            numbers: true,
+            quotes: vec!['"'],
+            escape: Some('\\'),
+            line_comment: None,
+            block_comment: None,
+            skip_whitespace: true,
+            skip_comments: false,
+        }
+    }
+
+    /// Sets the characters that may start and continue an identifier, e.g. to
+    /// allow `*`, `/` and `.` in glob patterns.
+    #[must_use]
+    pub fn with_identifiers(self, start: fn(char) -> bool, cont: fn(char) -> bool) -> Self {
+        Self {
+            ident_start: start,
+            ident_continue: cont,
+            ..self
+        }
+    }
+
+    /// Sets whether runs of digits are read as [`TokenKind::Number`]s. Without
+    /// numbers, digits are read as identifier characters (if allowed) or
+    /// punctuation.
+    #[must_use]
+    pub fn with_numbers(self, numbers: bool) -> Self {
+        Self { numbers, ..self }
+    }
+
+    /// Sets the characters that open and close a quoted string.
+    #[must_use]
+    pub fn with_quotes(self, quotes: &[char]) -> Self {
+        Self {
+            quotes: quotes.to_vec(),
+            ..self
+        }
+    }
+
+    /// Sets the character that escapes the next character inside of a quoted
+    /// string, `None` disables escaping.
+    #[must_use]
+    pub fn with_escape(self, escape: Option<char>) -> Self {
+        Self { escape, ..self }
+    }
+
+    /// Enables comments running from `prefix` to the end of the line.
+    #[must_use]
+    pub fn with_line_comment(self, prefix: impl Into<String>) -> Self {
+        Self {
+            line_comment: Some(prefix.into()),
+            ..self
+        }
+    }
+
+    /// Enables comments running from `open` to `close`, which may span lines.
+    #[must_use]
+    pub fn with_block_comment(self, open: impl Into<String>, close: impl Into<String>) -> Self {
+        Self {
+            block_comment: Some((open.into(), close.into())),
+            ..self
+        }
+    }
+
+    /// Sets whether whitespace is skipped instead of being produced as
+    /// [`TokenKind::Whitespace`] tokens.
+    #[must_use]
+    pub fn with_skip_whitespace(self, skip_whitespace: bool) -> Self {
+        Self {
+            skip_whitespace,
+            ..self
+        }
+    }
+
+    /// Sets whether comments are skipped instead of being produced as
+    /// [`TokenKind::Comment`] tokens.
+    #[must_use]
+    pub fn with_skip_comments(self, skip_comments: bool) -> Self {
+        Self {
+            skip_comments,
+            ..self
+        }
+    }
+
+    /// Returns the escape character of quoted strings, if any.
+    #[must_use]
+    pub fn escape(&self) -> Option<char> {
+        self.escape
+    }
+}
+
+impl Default for LexerRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A tokenizer splitting a string into [`Token`]s according to
+/// [`LexerRules`].
+///
+/// The lexer is an iterator over the tokens of its input. Malformed input
+/// produces a single [`LexError`], after which the iterator is exhausted.
+///
+/// ```
+/// use xstd::lex::{Lexer, LexerRules, TokenKind};
+///
+/// let rules = LexerRules::new().with_line_comment("#");
+/// let tokens = Lexer::new("size > 10 # in MiB", &rules)
+///     .map(|token| token.map(|t| (t.kind, t.text)))
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(tokens[1], (TokenKind::Punct, ">"));
+/// assert_eq!(tokens[3], (TokenKind::Comment, "# in MiB"));
+/// ```
+#[derive(Debug)]
+pub struct Lexer<'a, 'r> {
+    buf: LexBuf<'a>,
+    rules: &'r LexerRules,
+    line: usize,
+    column: usize,
+    failed: bool,
+}
+
+impl<'a, 'r> Lexer<'a, 'r> {
+    /// Creates a new lexer over `input`.
+    #[must_use]
+    pub fn new(input: &'a str, rules: &'r LexerRules) -> Self {
+        Self {
+            buf: LexBuf::new(input),
+            rules,
+            line: 1,
+            column: 1,
+            failed: false,
+        }
+    }
+
+    /// Reads the next token, including the ones the rules say to skip.
+    fn read(&mut self) -> Option<Result<Token<'a>, LexError>> {
+        let start = self.buf.pos();
+        let (line, column) = (self.line, self.column);
+        let first = self.buf.peek()?;
+        let rules = self.rules;
+
+        let kind = if first.is_whitespace() {
+            LexBuf::take_while(&mut self.buf, char::is_whitespace);
+            TokenKind::Whitespace
+        } else if rules
+            .line_comment
+            .as_deref()
+            .is_some_and(|p| !p.is_empty() && self.buf.consume_str(p))
+        {
+            LexBuf::take_while(&mut self.buf, |c| c != '\n');
+            TokenKind::Comment
+        } else if let Some((_, close)) = rules
+            .block_comment
+            .as_ref()
+            .filter(|(open, _)| !open.is_empty() && self.buf.consume_str(open))
+        {
+            if self.buf.take_to_delimiter(close).is_none() {
+                return Some(Err(self.fail(start, line, column, true)));
+            }
+            TokenKind::Comment
+        } else if rules.quotes.contains(&first) {
+            self.buf.next();
+            let mut terminated = false;
+            while let Some(ch) = self.buf.next() {
+                if Some(ch) == rules.escape {
+                    self.buf.next();
+                } else if ch == first {
+                    terminated = true;
+                    break;
+                }
+            }
+            if !terminated {
+                return Some(Err(self.fail(start, line, column, false)));
+            }
+            TokenKind::String
+        } else if rules.numbers && first.is_ascii_digit() {
+            LexBuf::take_while(&mut self.buf, |c| c.is_ascii_digit());
+            if self.buf.consume('.') {
+                if self.buf.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    LexBuf::take_while(&mut self.buf, |c| c.is_ascii_digit());
+                } else {
+                    self.buf.prev();
+                }
+            }
+            TokenKind::Number
+        } else if (rules.ident_start)(first) {
+            self.buf.next();
+            LexBuf::take_while(&mut self.buf, rules.ident_continue);
+            TokenKind::Ident
+        } else {
+            self.buf.next();
+            TokenKind::Punct
+        };
+
+        let text = &self.buf.inner()[start..self.buf.pos()];
+        self.advance(text);
+        Some(Ok(Token {
+            kind,
+            text,
+            span: Span {
+                start,
+                end: self.buf.pos(),
+                line,
+                column,
+            },
+        }))
+    }
+
+    /// Moves the line and column past `text`.
+    fn advance(&mut self, text: &str) {
+        for ch in text.chars() {
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+    }
+
+    fn fail(&mut self, start: usize, line: usize, column: usize, comment: bool) -> LexError {
+        self.failed = true;
+        let span = Span {
+            start,
+            end: self.buf.inner().len(),
+            line,
+            column,
+        };
+        if comment {
+            LexError::UnterminatedComment(span)
+        } else {
+            LexError::UnterminatedString(span)
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a, '_> {
+    type Item = Result<Token<'a>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            let token = self.read()?;
+            let skip = match &token {
+                Ok(t) => {
+                    (t.kind == TokenKind::Whitespace && self.rules.skip_whitespace)
+                        || (t.kind == TokenKind::Comment && self.rules.skip_comments)
+                }
+                Err(_) => false,
+            };
+            if !skip {
+                return Some(token);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds<'a>(input: &'a str, rules: &LexerRules) -> Vec<(TokenKind, &'a str)> {
+        Lexer::new(input, rules)
+            .map(|token| token.map(|t| (t.kind, t.text)))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn tokenizes_with_spans() {
+        let rules = LexerRules::new()
+            .with_line_comment("//")
+            .with_block_comment("/*", "*/");
+        let input = "name = \"a \\\"b\\\"\" // note\n  /* x\ny */ 1.5.";
+        assert_eq!(
+            kinds(input, &rules),
+            [
+                (TokenKind::Ident, "name"),
+                (TokenKind::Punct, "="),
+                (TokenKind::String, "\"a \\\"b\\\"\""),
+                (TokenKind::Comment, "// note"),
+                (TokenKind::Comment, "/* x\ny */"),
+                (TokenKind::Number, "1.5"),
+                (TokenKind::Punct, "."),
+            ]
+        );
+
+        let tokens = Lexer::new(input, &rules)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tokens[2].unquoted(rules.escape()), "a \"b\"");
+        assert_eq!(
+            tokens[5].span,
+            Span {
+                start: 37,
+                end: 40,
+                line: 3,
+                column: 6
+            }
+        );
+    }
+
+    #[test]
+    fn supports_custom_rules() {
+        let rules = LexerRules::new()
+            .with_identifiers(
+                |c| c.is_alphanumeric() || "*?/._".contains(c),
+                |c| c.is_alphanumeric() || "*?/._-".contains(c),
+            )
+            .with_numbers(false)
+            .with_quotes(&['\''])
+            .with_skip_whitespace(false);
+        assert_eq!(
+            kinds("src/**/*.rs !'my dir'", &rules),
+            [
+                (TokenKind::Ident, "src/**/*.rs"),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Punct, "!"),
+                (TokenKind::String, "'my dir'"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unterminated_input() {
+        let rules = LexerRules::new().with_block_comment("/*", "*/");
+        let mut lexer = Lexer::new("a\n \"open", &rules);
+        assert!(lexer.next().unwrap().is_ok());
+        let err = lexer.next().unwrap().unwrap_err();
+        assert_eq!(err.span().line, 2);
+        assert_eq!(
+            err.to_string(),
+            "unterminated string starting at line 2, column 2"
+        );
+        assert!(lexer.next().is_none());
+
+        let err = Lexer::new("/* open", &rules).next().unwrap().unwrap_err();
+        assert!(matches!(err, LexError::UnterminatedComment(_)));
+    }
+}
@@ -45,6 +45,7 @@ pub mod assert;
 pub mod bits;
 pub mod cast;
 pub mod collections;
+pub mod dirs;
 pub mod display;
 pub mod env;
 pub mod fs;
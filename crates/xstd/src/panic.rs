@@ -1,8 +1,10 @@
 //! Panic utilities.
 
 use std::any::Any;
+use std::backtrace::Backtrace;
 use std::cell::RefCell;
-use std::panic::{self, UnwindSafe};
+use std::fmt;
+use std::panic::{self, Location, PanicHookInfo, UnwindSafe};
 use std::process;
 
 #[cfg(feature = "async")]
@@ -51,6 +53,89 @@ pub fn set_abort_on_panic() {
     }));
 }
 
+/// A panic as seen by the hook installed with [`install_panic_hook`].
+#[derive(Debug)]
+pub struct PanicReport<'a> {
+    thread: Option<&'a str>,
+    message: &'a str,
+    location: Option<&'a Location<'a>>,
+    backtrace: &'a Backtrace,
+}
+
+impl<'a> PanicReport<'a> {
+    /// The name of the panicking thread, if it has one.
+    #[must_use]
+    pub fn thread(&self) -> Option<&'a str> {
+        self.thread
+    }
+
+    /// The panic message, or `Box<dyn Any>` if the payload is not a string.
+    #[must_use]
+    pub fn message(&self) -> &'a str {
+        self.message
+    }
+
+    /// Where the panic happened, if known.
+    #[must_use]
+    pub fn location(&self) -> Option<&'a Location<'a>> {
+        self.location
+    }
+
+    /// The backtrace of the panicking thread, captured whatever `RUST_BACKTRACE` says.
+    pub fn backtrace(&self) -> &'a Backtrace {
+        self.backtrace
+    }
+}
+
+impl fmt::Display for PanicReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread '{}' panicked",
+            self.thread.unwrap_or("<unnamed>")
+        )?;
+        if let Some(location) = self.location {
+            write!(f, " at {location}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Replaces the panic hook with one that hands every panic to `logger`, along with a backtrace.
+///
+/// The default hook only prints to stderr, which is easily lost for a background thread of a
+/// long running process: the thread is gone and nothing else notices. With this hook the panic
+/// ends up wherever `logger` sends it, e.g. `tracing`, and `logger` can also ask the rest of the
+/// program to shut down.
+///
+/// Call [`set_abort_on_panic`] afterwards to abort the process once the panic has been logged.
+pub fn install_panic_hook<F>(logger: F)
+where
+    F: Fn(&PanicReport<'_>) + Send + Sync + 'static,
+{
+    panic::set_hook(Box::new(move |panic_info| {
+        let thread = std::thread::current();
+        let backtrace = Backtrace::force_capture();
+        logger(&PanicReport {
+            thread: thread.name(),
+            message: panic_message(panic_info),
+            location: panic_info.location(),
+            backtrace: &backtrace,
+        });
+    }));
+}
+
+/// Gets the message of a panic, which is a `&str` or `String` unless it was raised with
+/// [`std::panic::panic_any`].
+fn panic_message<'a>(panic_info: &'a PanicHookInfo<'_>) -> &'a str {
+    let payload = panic_info.payload();
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
 /// Like [`std::panic::catch_unwind`], but can unwind panics even if
 /// [`set_abort_on_panic`] has been called.
 ///
@@ -68,3 +153,34 @@ where
         res
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn hook_reports_panics() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let logged = Arc::clone(&reports);
+        install_panic_hook(move |report| {
+            // other tests may panic while the hook is installed
+            if report.thread() == Some("hook-reports-panics") {
+                logged.lock().unwrap().push(report.to_string());
+            }
+        });
+        let result = std::thread::Builder::new()
+            .name("hook-reports-panics".to_string())
+            .spawn(|| panic!("oh no {}", 42))
+            .unwrap()
+            .join();
+        drop(panic::take_hook());
+
+        assert!(result.is_err());
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].starts_with("thread 'hook-reports-panics' panicked at "));
+        assert!(reports[0].contains("panic.rs:"));
+        assert!(reports[0].ends_with(": oh no 42"));
+    }
+}
@@ -26,6 +26,22 @@ pub trait PathExt {
     /// [`path.Clean`]: https://pkg.go.dev/path#Clean
     /// [`MAIN_SEPARATOR`]: std::path::MAIN_SEPARATOR
     fn clean(&self) -> PathBuf;
+
+    /// Checks whether the path is hidden: its file name starts with a `.`, or on Windows the
+    /// file has the hidden attribute. Only the latter touches the file system, a file that
+    /// cannot be queried is not hidden.
+    fn is_hidden(&self) -> bool;
+
+    /// Gets the longest path both this path and `other` start with, comparing the
+    /// [cleaned](PathExt::clean) paths component by component. The path is empty if they have
+    /// nothing in common, e.g. an absolute and a relative path.
+    fn common_prefix(&self, other: &Path) -> PathBuf;
+
+    /// Gets the path that leads from `base` to this path, e.g. `../c` from `/a/b` to `/a/c`,
+    /// computed lexically on the [cleaned](PathExt::clean) paths. Returns `None` if there is no
+    /// such path without knowing the working directory, i.e. one path is absolute and the other
+    /// is not, or `base` climbs out of their common prefix with `..`.
+    fn relative_to(&self, base: &Path) -> Option<PathBuf>;
 }
 
 impl PathExt for Path {
@@ -61,6 +77,65 @@ impl PathExt for Path {
         }
         buf
     }
+
+    fn is_hidden(&self) -> bool {
+        if self
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            return true;
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::MetadataExt;
+
+            use windows_sys::Win32::Storage::FileSystem::FILE_ATTRIBUTE_HIDDEN;
+
+            if let Ok(metadata) = std::fs::symlink_metadata(self) {
+                return metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+            }
+        }
+        false
+    }
+
+    fn common_prefix(&self, other: &Path) -> PathBuf {
+        let (this, other) = (self.clean(), other.clean());
+        this.components()
+            .zip(other.components())
+            .take_while(|(a, b)| a == b)
+            .map(|(component, _)| component)
+            .collect()
+    }
+
+    fn relative_to(&self, base: &Path) -> Option<PathBuf> {
+        let (this, base) = (self.clean(), base.clean());
+        if this.has_root() != base.has_root() {
+            return None;
+        }
+        let mut this = this.components().peekable();
+        let mut base = base.components().peekable();
+        while let (Some(a), Some(b)) = (this.peek(), base.peek()) {
+            if a != b {
+                break;
+            }
+            this.next();
+            base.next();
+        }
+        let mut relative = PathBuf::new();
+        for component in base {
+            match component {
+                Component::Normal(_) => relative.push(Component::ParentDir),
+                Component::CurDir => (),
+                // the other path has a different root or prefix, or `base` climbs further up
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+            }
+        }
+        relative.extend(this.filter(|component| *component != Component::CurDir));
+        if relative.as_os_str().is_empty() {
+            relative.push(".");
+        }
+        Some(relative)
+    }
 }
 
 #[cfg(test)]
@@ -120,4 +195,52 @@ mod tests {
             assert_eq!(Path::new(input).clean(), Path::new(output));
         }
     }
+
+    #[test]
+    fn test_is_hidden() {
+        assert!(Path::new(".bashrc").is_hidden());
+        assert!(Path::new("/home/user/.config/").is_hidden());
+        assert!(!Path::new("/home/user/.config/nvim").is_hidden());
+        assert!(!Path::new("notes.txt").is_hidden());
+        assert!(!Path::new("..").is_hidden());
+        assert!(!Path::new("/").is_hidden());
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        for (a, b, prefix) in [
+            ("/a/b/c", "/a/b/d", "/a/b"),
+            ("/a/b", "/a/b/c", "/a/b"),
+            ("/a/./b/../c", "/a/c/d", "/a/c"),
+            ("/ab", "/ac", "/"),
+            ("a/b", "a/c", "a"),
+            ("a/b", "c/d", ""),
+            ("/a", "a", ""),
+        ] {
+            assert_eq!(Path::new(a).common_prefix(Path::new(b)), Path::new(prefix));
+        }
+    }
+
+    #[test]
+    fn test_relative_to() {
+        for (path, base, relative) in [
+            ("/a/b/c", "/a/b", Some("c")),
+            ("/a/c", "/a/b", Some("../c")),
+            ("/a/b", "/a/b", Some(".")),
+            ("/a", "/a/b/c", Some("../..")),
+            ("/x/y", "/a/b", Some("../../x/y")),
+            ("/a/b/../c/", "/a/./d", Some("../c")),
+            ("a/b", "a", Some("b")),
+            ("../a", "b", Some("../../a")),
+            ("a", "..", None),
+            ("/a", "a", None),
+            ("a", "/a", None),
+        ] {
+            assert_eq!(
+                Path::new(path).relative_to(Path::new(base)).as_deref(),
+                relative.map(Path::new),
+                "{path} relative to {base}"
+            );
+        }
+    }
 }
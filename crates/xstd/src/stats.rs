@@ -1,4 +1,11 @@
 //! Statistics utilities.
+//!
+//! Buckets for histograms of timings and sizes, and [`StreamingStats`]
+//! summarizing a stream of samples without keeping them.
+
+use std::collections::BTreeMap;
+
+use crate::cast::{CastLossy, TryCastFrom};
 
 /// A standard range of buckets for timing data, measured in seconds.
 /// Individual histograms may only need a subset of this range, in which case,
@@ -36,3 +43,248 @@ pub const HISTOGRAM_BYTE_BUCKETS: [f64; 7] = [
     67_108_864.0,
     1_073_741_824.0,
 ];
+
+/// The relative error of the percentiles estimated by [`StreamingStats`]: an
+/// estimate is within 1% of a sample that has the requested rank.
+pub const STREAMING_STATS_ACCURACY: f64 = 0.01;
+
+/// Descriptive statistics of a stream of samples, updated one sample at a
+/// time without storing the samples.
+///
+/// The count, mean, minimum and maximum are exact, the variance is computed
+/// with Welford's online algorithm, which stays accurate for long streams.
+/// Percentiles are estimated from logarithmic buckets, each of which covers
+/// values within [`STREAMING_STATS_ACCURACY`] of each other, so the memory
+/// used only grows with the range of the samples, not their number.
+///
+/// Percentiles are meant for non-negative samples like durations and sizes,
+/// negative samples are counted as zero for them. Samples that are not finite
+/// are ignored.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StreamingStats {
+    count: u64,
+    mean: f64,
+    /// The sum of the squared differences from the mean
+    m2: f64,
+    min: f64,
+    max: f64,
+    /// The number of samples that are zero or negative
+    zeros: u64,
+    /// The number of positive samples per logarithmic bucket
+    buckets: BTreeMap<i32, u64>,
+}
+
+impl StreamingStats {
+    /// Creates new [`StreamingStats`] that have not seen any samples
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `sample` to the statistics. Samples that are not finite are
+    /// ignored.
+    pub fn push(&mut self, sample: f64) {
+        if !sample.is_finite() {
+            return;
+        }
+        if self.count == 0 {
+            self.min = sample;
+            self.max = sample;
+        } else {
+            self.min = self.min.min(sample);
+            self.max = self.max.max(sample);
+        }
+        self.count += 1;
+        let delta = sample - self.mean;
+        self.mean += delta / f64::cast_lossy(self.count);
+        self.m2 += delta * (sample - self.mean);
+
+        if sample > 0.0 {
+            *self.buckets.entry(bucket_of(sample)).or_default() += 1;
+        } else {
+            self.zeros += 1;
+        }
+    }
+
+    /// Adds the samples seen by `other` to these statistics, as if they had
+    /// been pushed here
+    pub fn merge(&mut self, other: &Self) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let (this, that, total) = (
+            f64::cast_lossy(self.count),
+            f64::cast_lossy(other.count),
+            f64::cast_lossy(count),
+        );
+        self.mean += delta * that / total;
+        self.m2 += other.m2 + delta * delta * this * that / total;
+        self.count = count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.zeros += other.zeros;
+        for (bucket, count) in &other.buckets {
+            *self.buckets.entry(*bucket).or_default() += count;
+        }
+    }
+
+    /// Gets the number of samples seen
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns true if no samples have been seen
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Gets the mean of the samples, `None` if there are none
+    #[must_use]
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    /// Gets the smallest sample, `None` if there are none
+    #[must_use]
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    /// Gets the largest sample, `None` if there are none
+    #[must_use]
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Gets the sample variance, `None` if there are fewer than two samples
+    #[must_use]
+    pub fn variance(&self) -> Option<f64> {
+        (self.count > 1).then(|| self.m2 / f64::cast_lossy(self.count - 1))
+    }
+
+    /// Gets the sample standard deviation, `None` if there are fewer than two
+    /// samples
+    #[must_use]
+    pub fn std_dev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    /// Estimates the `percentile`th percentile (from 0 to 100) of the
+    /// samples, within [`STREAMING_STATS_ACCURACY`] of the sample of that
+    /// rank. Returns `None` if there are no samples.
+    ///
+    /// ## Panics
+    /// - Panics if `percentile` is not between 0 and 100
+    #[must_use]
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        assert!(
+            (0.0..=100.0).contains(&percentile),
+            "percentile {percentile} is not between 0 and 100"
+        );
+        if self.count == 0 {
+            return None;
+        }
+        // the nearest rank, starting at 1
+        let rank = (percentile / 100.0 * f64::cast_lossy(self.count)).ceil();
+        let rank = u64::try_cast_from(rank).unwrap_or(0).max(1);
+        if rank <= self.zeros {
+            return Some(0.0_f64.max(self.min));
+        }
+        let mut seen = self.zeros;
+        for (bucket, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return Some(value_of(*bucket).clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+}
+
+impl Extend<f64> for StreamingStats {
+    fn extend<I: IntoIterator<Item = f64>>(&mut self, samples: I) {
+        for sample in samples {
+            self.push(sample);
+        }
+    }
+}
+
+impl FromIterator<f64> for StreamingStats {
+    fn from_iter<I: IntoIterator<Item = f64>>(samples: I) -> Self {
+        let mut stats = Self::new();
+        stats.extend(samples);
+        stats
+    }
+}
+
+/// The ratio between the bounds of a bucket of [`StreamingStats`]
+fn gamma() -> f64 {
+    (1.0 + STREAMING_STATS_ACCURACY) / (1.0 - STREAMING_STATS_ACCURACY)
+}
+
+/// Gets the bucket of the positive `sample`, the one covering
+/// `(gamma^(i-1), gamma^i]`
+#[allow(clippy::as_conversions, clippy::cast_possible_truncation)]
+fn bucket_of(sample: f64) -> i32 {
+    // saturates for samples far outside the range of any real measurement
+    (sample.ln() / gamma().ln()).ceil() as i32
+}
+
+/// Gets the value representing the samples of `bucket`, which is within
+/// [`STREAMING_STATS_ACCURACY`] of all of them
+fn value_of(bucket: i32) -> f64 {
+    2.0 * gamma().powi(bucket) / (gamma() + 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaming_stats() {
+        let mut stats = StreamingStats::new();
+        assert_eq!(stats.mean(), None);
+        assert_eq!(stats.percentile(50.0), None);
+        stats.extend([2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0, f64::NAN]);
+        assert_eq!(stats.count(), 8);
+        assert_eq!(stats.mean(), Some(5.0));
+        assert_eq!(stats.min(), Some(2.0));
+        assert_eq!(stats.max(), Some(9.0));
+        assert!((stats.variance().unwrap() - 32.0 / 7.0).abs() < 1e-9);
+
+        let samples = (1..=10_000).map(f64::from).collect::<Vec<_>>();
+        let stats = samples.iter().copied().collect::<StreamingStats>();
+        for (percentile, exact) in [
+            (0.0, 1.0),
+            (50.0, 5000.0),
+            (99.0, 9900.0),
+            (100.0, 10_000.0),
+        ] {
+            let estimate = stats.percentile(percentile).unwrap();
+            assert!(
+                (estimate - exact).abs() <= exact * STREAMING_STATS_ACCURACY,
+                "p{percentile} was {estimate}, expected {exact}"
+            );
+        }
+        assert!(stats.buckets.len() < 1000);
+
+        let (first, second) = samples.split_at(1234);
+        let mut merged = first.iter().copied().collect::<StreamingStats>();
+        merged.merge(&second.iter().copied().collect());
+        assert_eq!(merged.count(), stats.count());
+        assert_eq!(merged.buckets, stats.buckets);
+        assert!((merged.mean().unwrap() - stats.mean().unwrap()).abs() < 1e-9);
+        assert!((merged.variance().unwrap() / stats.variance().unwrap() - 1.0).abs() < 1e-9);
+
+        let zeros = [0.0, 0.0, 3.0].into_iter().collect::<StreamingStats>();
+        assert_eq!(zeros.percentile(50.0), Some(0.0));
+    }
+}
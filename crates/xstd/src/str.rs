@@ -1,5 +1,6 @@
 //! String utilities.
 
+use std::borrow::Cow;
 use std::fmt::{self, Write};
 use std::ops::Deref;
 
@@ -152,6 +153,121 @@ where
     }
 }
 
+/// Splits `s` into words for the case converters: at every character that is not alphanumeric,
+/// before an uppercase letter following a lowercase letter or digit, and before the last letter
+/// of an uppercase run that is followed by a lowercase letter (`HTTPServer` is `HTTP`, `Server`).
+fn words(s: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    for part in s.split(|c: char| !c.is_alphanumeric()) {
+        let chars = part.char_indices().collect::<Vec<_>>();
+        let mut start = 0;
+        for i in 1..chars.len() {
+            let (index, c) = chars[i];
+            let prev = chars[i - 1].1;
+            let next_is_lower = chars.get(i + 1).is_some_and(|(_, c)| c.is_lowercase());
+            if c.is_uppercase()
+                && (prev.is_lowercase()
+                    || prev.is_numeric()
+                    || (prev.is_uppercase() && next_is_lower))
+            {
+                words.push(&part[start..index]);
+                start = index;
+            }
+        }
+        if start < part.len() {
+            words.push(&part[start..]);
+        }
+    }
+    words
+}
+
+fn join_lowercase(s: &str, separator: &str) -> String {
+    words(s)
+        .into_iter()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Converts `s` to `snake_case`.
+///
+/// ```
+/// assert_eq!(xstd::str::snake_case("HTTPServer error"), "http_server_error");
+/// ```
+#[must_use]
+pub fn snake_case(s: &str) -> String {
+    join_lowercase(s, "_")
+}
+
+/// Converts `s` to `kebab-case`.
+///
+/// ```
+/// assert_eq!(xstd::str::kebab_case("backupNow"), "backup-now");
+/// ```
+#[must_use]
+pub fn kebab_case(s: &str) -> String {
+    join_lowercase(s, "-")
+}
+
+/// Converts `s` to `CamelCase` (upper camel case).
+///
+/// ```
+/// assert_eq!(xstd::str::camel_case("store_size-cap"), "StoreSizeCap");
+/// ```
+#[must_use]
+pub fn camel_case(s: &str) -> String {
+    let mut camel = String::with_capacity(s.len());
+    for word in words(s) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.extend(chars.flat_map(char::to_lowercase));
+        }
+    }
+    camel
+}
+
+/// Shortens `s` to at most `max_width` characters, replacing the end with `…` if it had to be
+/// cut. Strings that already fit are returned as they are. The width is counted in `char`s, so
+/// the string is never cut inside a character.
+///
+/// ```
+/// assert_eq!(xstd::str::truncate_ellipsis("größenbeschränkt", 6), "größe…");
+/// assert_eq!(xstd::str::truncate_ellipsis("short", 6), "short");
+/// ```
+#[must_use]
+pub fn truncate_ellipsis(s: &str, max_width: usize) -> Cow<'_, str> {
+    match s.char_indices().nth(max_width) {
+        None => Cow::Borrowed(s),
+        Some(_) if max_width == 0 => Cow::Borrowed(""),
+        Some(_) => {
+            let end = s
+                .char_indices()
+                .nth(max_width - 1)
+                .map_or(s.len(), |(index, _)| index);
+            Cow::Owned(format!("{}…", &s[..end]))
+        }
+    }
+}
+
+/// Indents every non-empty line of `s` by `n` spaces, keeping its line endings.
+///
+/// ```
+/// assert_eq!(xstd::str::indent("a\n\nb", 2), "  a\n\n  b");
+/// ```
+#[must_use]
+pub fn indent(s: &str, n: usize) -> String {
+    let prefix = " ".repeat(n);
+    let mut indented = String::with_capacity(s.len());
+    for line in s.split_inclusive('\n') {
+        if !line.trim_end_matches(['\r', '\n']).is_empty() {
+            indented.push_str(&prefix);
+        }
+        indented.push_str(line);
+    }
+    indented
+}
+
 /// A helper struct to keep track of indentation levels.
 ///
 /// This will be most often used as part of the rendering context
@@ -301,4 +417,31 @@ mod tests {
         indent += 1;
         assert_eq!(indent.to_string(), "~~~".to_string());
     }
+
+    #[test]
+    fn test_case_conversion() {
+        assert_eq!(snake_case("fooBar2Baz"), "foo_bar2_baz");
+        assert_eq!(
+            snake_case("  leading--and trailing  "),
+            "leading_and_trailing"
+        );
+        assert_eq!(kebab_case("IOError"), "io-error");
+        assert_eq!(kebab_case("already-kebab"), "already-kebab");
+        assert_eq!(camel_case("max_store_bytes"), "MaxStoreBytes");
+        assert_eq!(camel_case("HTTPServer"), "HttpServer");
+        assert_eq!(camel_case(""), "");
+    }
+
+    #[test]
+    fn test_truncate_and_indent() {
+        assert_eq!(truncate_ellipsis("abcdef", 6), "abcdef");
+        assert_eq!(truncate_ellipsis("abcdefg", 6), "abcde…");
+        assert_eq!(truncate_ellipsis("日本語テキスト", 3), "日本…");
+        assert_eq!(truncate_ellipsis("abc", 0), "");
+        assert_eq!(truncate_ellipsis("", 0), "");
+        assert!(matches!(truncate_ellipsis("abc", 3), Cow::Borrowed(_)));
+
+        assert_eq!(indent("a\r\nb\n", 1), " a\r\n b\n");
+        assert_eq!(indent("", 4), "");
+    }
 }
@@ -1,6 +1,11 @@
 //! Thread utilities.
 
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 /// Wraps a [`JoinHandle`] so that the child thread is joined when the handle is
 /// dropped, rather than detached. If the child thread panics,
@@ -47,3 +52,375 @@ impl<T> JoinHandleExt<T> for JoinHandle<T> {
         UnparkOnDropHandle(self)
     }
 }
+
+/// A flag asking long running work to stop early. Every clone shares the same flag, so one can
+/// be handed to the work and another kept to cancel it. Once cancelled, a token stays cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a new token that is not cancelled.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the work holding a clone of this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true once [`CancellationToken::cancel`] was called on any clone of this token.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+type Panic = Box<dyn Any + Send + 'static>;
+
+/// How often [`ThreadPool::join_all`] checks whether the workers are done
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The sending half of the job queue of a [`ThreadPool`]
+#[derive(Debug)]
+enum JobSender {
+    Unbounded(mpsc::Sender<Job>),
+    Bounded(mpsc::SyncSender<Job>),
+}
+
+impl JobSender {
+    fn send(&self, job: Job) -> Result<(), mpsc::SendError<Job>> {
+        match self {
+            Self::Unbounded(sender) => sender.send(job),
+            Self::Bounded(sender) => sender.send(job),
+        }
+    }
+}
+
+/// Configures and spawns a [`ThreadPool`].
+///
+/// By default the pool has one worker per available core, named `xstd-pool-<n>`, and an
+/// unbounded queue.
+#[derive(Debug, Clone)]
+pub struct ThreadPoolBuilder {
+    size: usize,
+    name: String,
+    queue_bound: Option<usize>,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        Self {
+            size: std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+            name: String::from("xstd-pool"),
+            queue_bound: None,
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// Creates a new builder with the default settings.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of worker threads (at least one).
+    #[must_use]
+    pub fn with_size(self, size: usize) -> Self {
+        Self {
+            size: size.max(1),
+            ..self
+        }
+    }
+
+    /// Sets the prefix of the worker thread names, the workers are named `<name>-<n>`.
+    #[must_use]
+    pub fn with_name(self, name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..self
+        }
+    }
+
+    /// Sets how many jobs may wait for a worker, [`ThreadPool::execute`] blocks once that many
+    /// are queued. A bound of zero hands every job straight to an idle worker.
+    #[must_use]
+    pub fn with_queue_bound(self, queue_bound: usize) -> Self {
+        Self {
+            queue_bound: Some(queue_bound),
+            ..self
+        }
+    }
+
+    /// Spawns the workers of the pool.
+    ///
+    /// ## Errors
+    /// - Returns an error if a worker thread cannot be spawned, the workers spawned before it
+    ///   are shut down again
+    pub fn build(self) -> std::io::Result<ThreadPool> {
+        let (sender, receiver) = if let Some(bound) = self.queue_bound {
+            let (sender, receiver) = mpsc::sync_channel::<Job>(bound);
+            (JobSender::Bounded(sender), receiver)
+        } else {
+            let (sender, receiver) = mpsc::channel::<Job>();
+            (JobSender::Unbounded(sender), receiver)
+        };
+        let receiver = Arc::new(Mutex::new(receiver));
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let mut pool = ThreadPool {
+            name: self.name,
+            workers: Vec::with_capacity(self.size),
+            sender: Some(sender),
+            panics,
+        };
+        for i in 0..self.size {
+            let receiver = Arc::clone(&receiver);
+            let panics = Arc::clone(&pool.panics);
+            let worker = std::thread::Builder::new()
+                .name(format!("{}-{i}", pool.name))
+                .spawn(move || loop {
+                    let job = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => return,
+                    };
+                    match job {
+                        Ok(job) => {
+                            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                                if let Ok(mut panics) = panics.lock() {
+                                    panics.push(panic);
+                                }
+                            }
+                        }
+                        // the pool has been dropped
+                        Err(_) => return,
+                    }
+                })?;
+            pool.workers.push(worker);
+        }
+        Ok(pool)
+    }
+}
+
+/// A fixed-size pool of named worker threads that execute `'static` jobs.
+///
+/// Jobs are distributed to the first idle worker. A panicking job does not take its
+/// worker down with it, the panic is handed to the owner of the pool instead, see
+/// [`ThreadPool::join_all`] and [`ThreadPool::map`]. Dropping the pool waits for all queued
+/// jobs to finish.
+#[derive(Debug)]
+pub struct ThreadPool {
+    name: String,
+    workers: Vec<JoinHandle<()>>,
+    sender: Option<JobSender>,
+    panics: Arc<Mutex<Vec<Panic>>>,
+}
+
+impl ThreadPool {
+    /// Creates a new pool with `size` worker threads (at least one), see [`ThreadPoolBuilder`]
+    /// for more settings.
+    ///
+    /// ## Panics
+    /// Panics if a worker thread cannot be spawned.
+    #[must_use]
+    pub fn new(size: usize) -> Self {
+        ThreadPoolBuilder::new()
+            .with_size(size)
+            .build()
+            .expect("failed to spawn pool worker")
+    }
+
+    /// Creates a new pool with one worker per available core.
+    ///
+    /// ## Panics
+    /// Panics if a worker thread cannot be spawned.
+    #[must_use]
+    pub fn with_available_parallelism() -> Self {
+        ThreadPoolBuilder::new()
+            .build()
+            .expect("failed to spawn pool worker")
+    }
+
+    /// The prefix of the names of the worker threads.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The number of worker threads in this pool.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Queues `job` to be run on the next idle worker. With a bounded queue (see
+    /// [`ThreadPoolBuilder::with_queue_bound`]) this blocks until there is room for the job.
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(sender) = &self.sender {
+            // workers only hang up once the sender is gone, so this cannot fail
+            sender.send(Box::new(job)).ok();
+        }
+    }
+
+    /// Runs `f` for every item on the pool, blocking until all are done. Results are returned
+    /// in the same order as `items`.
+    ///
+    /// ## Panics
+    /// If `f` panics for any of the items, the panic of the first of them is resumed once
+    /// every item is done.
+    pub fn map<T, R, F>(&self, items: impl IntoIterator<Item = T>, f: F) -> Vec<R>
+    where
+        T: Send + 'static,
+        R: Send + 'static,
+        F: Fn(T) -> R + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let (tx, rx) = mpsc::channel();
+        let mut count = 0;
+        for (index, item) in items.into_iter().enumerate() {
+            let f = Arc::clone(&f);
+            let tx = tx.clone();
+            self.execute(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| f(item)));
+                tx.send((index, result)).ok();
+            });
+            count += 1;
+        }
+        drop(tx);
+
+        let mut results: Vec<Option<R>> = std::iter::repeat_with(|| None).take(count).collect();
+        let mut panics = Vec::new();
+        for (index, result) in rx {
+            match result {
+                Ok(result) => results[index] = Some(result),
+                Err(panic) => panics.push((index, panic)),
+            }
+        }
+        if let Some((_, panic)) = panics.into_iter().min_by_key(|(index, _)| *index) {
+            panic::resume_unwind(panic);
+        }
+        results
+            .into_iter()
+            .map(|r| r.expect("thread pool job was lost"))
+            .collect()
+    }
+
+    /// Shuts the pool down gracefully: no more jobs are accepted, and the jobs already queued
+    /// are run. Waits up to `timeout` for the workers to finish them.
+    ///
+    /// Returns true if every worker finished in time, the workers that did not are detached and
+    /// finish the remaining jobs in the background.
+    ///
+    /// ## Panics
+    /// Resumes the panic of the first job run with [`ThreadPool::execute`] that panicked.
+    pub fn join_all(mut self, timeout: Duration) -> bool {
+        drop(self.sender.take());
+        let started = Instant::now();
+        while self.workers.iter().any(|worker| !worker.is_finished()) && started.elapsed() < timeout
+        {
+            std::thread::sleep(JOIN_POLL_INTERVAL);
+        }
+        let (finished, running): (Vec<_>, Vec<_>) =
+            self.workers.drain(..).partition(JoinHandle::is_finished);
+        for worker in finished {
+            worker.join().ok();
+        }
+        let panic = self
+            .panics
+            .lock()
+            .ok()
+            .and_then(|mut panics| (!panics.is_empty()).then(|| panics.remove(0)));
+        if let Some(panic) = panic {
+            panic::resume_unwind(panic);
+        }
+        running.is_empty()
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_map_preserves_order() {
+        let pool = ThreadPool::new(3);
+        assert_eq!(pool.size(), 3);
+        let squares = pool.map(0..20u64, |n| {
+            std::thread::sleep(std::time::Duration::from_millis(20 - n));
+            n * n
+        });
+        assert_eq!(squares, (0..20u64).map(|n| n * n).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pool_survives_panicking_job() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job panicked"));
+        assert_eq!(pool.map(vec![1, 2], |n| n + 1), vec![2, 3]);
+    }
+
+    #[test]
+    fn pool_names_workers() {
+        let pool = ThreadPoolBuilder::new()
+            .with_size(2)
+            .with_name("backup")
+            .with_queue_bound(1)
+            .build()
+            .unwrap();
+        assert_eq!(pool.name(), "backup");
+        let names = pool.map(0..10, |_| {
+            std::thread::current().name().map(ToString::to_string)
+        });
+        assert!(names
+            .iter()
+            .all(|name| matches!(name.as_deref(), Some("backup-0" | "backup-1"))));
+    }
+
+    #[test]
+    fn pool_propagates_panics() {
+        let pool = ThreadPool::new(2);
+        let panic = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.map(0..4, |n| {
+                assert!(n % 2 == 0, "odd {n}");
+                n
+            })
+        }))
+        .unwrap_err();
+        assert_eq!(panic.downcast_ref::<String>().unwrap(), "odd 1");
+
+        pool.execute(|| panic!("job panicked"));
+        let panic = panic::catch_unwind(AssertUnwindSafe(|| pool.join_all(Duration::from_secs(5))))
+            .unwrap_err();
+        assert_eq!(*panic.downcast_ref::<&str>().unwrap(), "job panicked");
+    }
+
+    #[test]
+    fn pool_joins_with_timeout() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel::<()>();
+        pool.execute(move || {
+            rx.recv().ok();
+        });
+        assert!(!pool.join_all(Duration::from_millis(20)));
+        // the detached worker still finishes its job
+        tx.send(()).unwrap();
+
+        let pool = ThreadPool::new(2);
+        let done = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&done);
+        pool.execute(move || flag.store(true, Ordering::SeqCst));
+        assert!(pool.join_all(Duration::from_secs(5)));
+        assert!(done.load(Ordering::SeqCst));
+    }
+}
@@ -122,6 +122,40 @@ pub trait VecExt<T> {
     fn drain_filter_swapping<F>(&mut self, filter: F) -> DrainFilterSwapping<'_, T, F>
     where
         F: FnMut(&mut T) -> bool;
+
+    /// Removes the first element for which `predicate` returns true and returns it, replacing it
+    /// with the last element like [`Vec::swap_remove`]. Returns `None` if no element matches.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xstd::vec::VecExt;
+    ///
+    /// let mut v = vec![1, 2, 3, 4];
+    /// assert_eq!(v.swap_remove_if(|x| *x % 2 == 0), Some(2));
+    /// assert_eq!(v, [1, 4, 3]);
+    /// assert_eq!(v.swap_remove_if(|x| *x > 10), None);
+    /// ```
+    fn swap_remove_if<F>(&mut self, predicate: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool;
+
+    /// Removes all but the **last** of consecutive elements that resolve to the same key, unlike
+    /// [`Vec::dedup_by_key`] which keeps the first. The order of the remaining elements is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xstd::vec::VecExt;
+    ///
+    /// let mut v = vec![("a", 1), ("a", 2), ("b", 3), ("a", 4)];
+    /// v.dedup_by_key_keep_last(|(key, _)| *key);
+    /// assert_eq!(v, [("a", 2), ("b", 3), ("a", 4)]);
+    /// ```
+    fn dedup_by_key_keep_last<K, F>(&mut self, key: F)
+    where
+        K: PartialEq,
+        F: FnMut(&T) -> K;
 }
 
 impl<T> VecExt<T> for Vec<T> {
@@ -135,6 +169,30 @@ impl<T> VecExt<T> for Vec<T> {
             pred: filter,
         }
     }
+
+    fn swap_remove_if<F>(&mut self, predicate: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let idx = self.iter().position(predicate)?;
+        Some(self.swap_remove(idx))
+    }
+
+    fn dedup_by_key_keep_last<K, F>(&mut self, mut key: F)
+    where
+        K: PartialEq,
+        F: FnMut(&T) -> K,
+    {
+        // `dedup_by` passes the later element first and removes it if they match, so moving the
+        // later element into the place of the earlier one keeps the last of every run
+        self.dedup_by(|later, earlier| {
+            let same = key(later) == key(earlier);
+            if same {
+                std::mem::swap(later, earlier);
+            }
+            same
+        });
+    }
 }
 
 /// An iterator which uses a closure to determine if an element should be removed.
@@ -195,6 +253,58 @@ pub fn swap_remove_multiple<T>(v: &mut Vec<T>, mut indexes: Vec<usize>) -> Vec<T
     result
 }
 
+/// Inserts `item` into the sorted vector `v`, after any elements equal to it, so that `v` stays
+/// sorted. Returns the index `item` was inserted at.
+///
+/// # Examples
+///
+/// ```
+/// let mut v = vec![1, 3, 3, 7];
+/// assert_eq!(xstd::vec::binary_search_insert(&mut v, 3), 3);
+/// assert_eq!(xstd::vec::binary_search_insert(&mut v, 0), 0);
+/// assert_eq!(v, [0, 1, 3, 3, 3, 7]);
+/// ```
+pub fn binary_search_insert<T: Ord>(v: &mut Vec<T>, item: T) -> usize {
+    let idx = v.partition_point(|x| x <= &item);
+    v.insert(idx, item);
+    idx
+}
+
+/// Splits `v` into consecutive chunks of at most `max_bytes`, as measured by `size_fn`, e.g. to
+/// batch work by the size of the files involved. The order of the elements is kept. An element
+/// larger than `max_bytes` gets a chunk of its own.
+///
+/// # Examples
+///
+/// ```
+/// let sizes = vec![4_u64, 3, 2, 9, 1];
+/// let chunks = xstd::vec::chunked_by_size(sizes, 6, |size| *size);
+/// assert_eq!(chunks, [vec![4], vec![3, 2], vec![9], vec![1]]);
+/// ```
+pub fn chunked_by_size<T, F>(v: Vec<T>, max_bytes: u64, mut size_fn: F) -> Vec<Vec<T>>
+where
+    F: FnMut(&T) -> u64,
+{
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_bytes = 0_u64;
+    for item in v {
+        let size = size_fn(&item);
+        let total = chunk_bytes.checked_add(size);
+        if !chunk.is_empty() && total.is_none_or(|total| total > max_bytes) {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_bytes = size;
+        } else {
+            chunk_bytes = total.unwrap_or(u64::MAX);
+        }
+        chunk.push(item);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -242,6 +352,40 @@ mod test {
         assert_eq!(other[0].s, "hmm2");
     }
 
+    #[test]
+    fn keeps_last_of_runs() {
+        let mut v = vec![(1, 'a'), (1, 'b'), (1, 'c'), (2, 'd'), (3, 'e'), (3, 'f')];
+        v.dedup_by_key_keep_last(|(key, _)| *key);
+        assert_eq!(v, [(1, 'c'), (2, 'd'), (3, 'f')]);
+
+        let mut empty: Vec<i32> = Vec::new();
+        empty.dedup_by_key_keep_last(|x| *x);
+        assert!(empty.is_empty());
+        assert_eq!(empty.swap_remove_if(|_| true), None);
+    }
+
+    #[test]
+    fn inserts_sorted() {
+        let mut v = Vec::new();
+        for item in [5, 1, 4, 1, 3] {
+            binary_search_insert(&mut v, item);
+        }
+        assert_eq!(v, [1, 1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn chunks_by_size() {
+        assert!(chunked_by_size(Vec::<u64>::new(), 10, |x| *x).is_empty());
+        assert_eq!(
+            chunked_by_size(vec![5_u64, 5, 5, 0, 11], 10, |x| *x),
+            [vec![5, 5], vec![5, 0], vec![11]]
+        );
+        assert_eq!(
+            chunked_by_size(vec![u64::MAX, 1], u64::MAX, |x| *x),
+            [vec![u64::MAX], vec![1]]
+        );
+    }
+
     #[test]
     #[should_panic(expected = "same size")]
     fn miri_test_wrong_size() {